@@ -5,8 +5,8 @@ pub mod state;
 pub mod update;
 
 use crypto_shared::{
-    derive_epsilon, derive_key, kdf::check_ec_signature, near_public_key_to_affine_point,
-    types::SignatureResponse, ScalarExt as _,
+    derive_key, derive_public_key, hashing::MAX_MESSAGE_LEN, kdf::check_ec_signature,
+    near_public_key_to_affine_point, types::SignatureResponse, ScalarExt as _,
 };
 use errors::{
     ConversionError, InitError, InvalidParameters, InvalidState, JoinError, PublicKeyError,
@@ -22,8 +22,9 @@ use near_sdk::{
     PromiseError, PublicKey,
 };
 use primitives::{
-    CandidateInfo, Candidates, ContractSignatureRequest, Participants, PkVotes, SignRequest,
-    SignaturePromiseError, SignatureRequest, SignatureResult, StorageKey, Votes, YieldIndex,
+    CandidateInfo, Candidates, ContractSignatureRequest, Participants, PkVotes, SignMessageRequest,
+    SignRequest, SignaturePromiseError, SignatureRequest, SignatureResult, StorageKey, Votes,
+    YieldIndex,
 };
 use std::collections::{BTreeMap, HashSet};
 
@@ -130,6 +131,46 @@ impl VersionedMpcContract {
             InvalidParameters::MalformedPayload
                 .message("Payload hash cannot be convereted to Scalar"),
         )?;
+        self.sign_payload(payload, path, key_version)
+    }
+
+    /// Same as `sign`, but the payload is the digest of `message` computed with the `hashing`
+    /// scheme, which the nodes compute the same way. This lets EVM transactions be signed by
+    /// handing over their encoding rather than hashing them beforehand.
+    #[handle_result]
+    #[payable]
+    pub fn sign_message(
+        &mut self,
+        request: SignMessageRequest,
+    ) -> Result<near_sdk::Promise, Error> {
+        let SignMessageRequest {
+            message,
+            hashing,
+            path,
+            key_version,
+        } = request;
+        if message.is_empty() {
+            return Err(InvalidParameters::MalformedPayload.message("Message is empty"));
+        }
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(InvalidParameters::MalformedPayload.message(format!(
+                "Message of {} bytes is longer than {MAX_MESSAGE_LEN} bytes",
+                message.len()
+            )));
+        }
+        let payload = hashing.payload(&message).ok_or(
+            InvalidParameters::MalformedPayload
+                .message("Message digest cannot be converted to Scalar"),
+        )?;
+        self.sign_payload(payload, path, key_version)
+    }
+
+    fn sign_payload(
+        &mut self,
+        payload: Scalar,
+        path: String,
+        key_version: u32,
+    ) -> Result<near_sdk::Promise, Error> {
         if key_version > self.latest_key_version() {
             return Err(SignError::UnsupportedKeyVersion.into());
         }
@@ -197,9 +238,11 @@ impl VersionedMpcContract {
         predecessor: Option<AccountId>,
     ) -> Result<PublicKey, Error> {
         let predecessor = predecessor.unwrap_or_else(env::predecessor_account_id);
-        let epsilon = derive_epsilon(&predecessor, &path);
-        let derived_public_key =
-            derive_key(near_public_key_to_affine_point(self.public_key()?), epsilon);
+        let derived_public_key = derive_public_key(
+            near_public_key_to_affine_point(self.public_key()?),
+            &predecessor,
+            &path,
+        );
         let encoded_point = derived_public_key.to_encoded_point(false);
         let slice: &[u8] = &encoded_point.as_bytes()[1..65];
         let mut data: Vec<u8> = vec![near_sdk::CurveType::SECP256K1 as u8];
@@ -1,4 +1,4 @@
-use crypto_shared::{derive_epsilon, SerializableScalar};
+use crypto_shared::{derive_epsilon, HashScheme, SerializableScalar};
 use k256::Scalar;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::serde::{Deserialize, Serialize};
@@ -271,6 +271,16 @@ pub struct SignRequest {
     pub key_version: u32,
 }
 
+/// A request to sign the digest of `message`, computed with `hashing` by the contract and the
+/// nodes rather than by the requester.
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug)]
+pub struct SignMessageRequest {
+    pub message: Vec<u8>,
+    pub hashing: HashScheme,
+    pub path: String,
+    pub key_version: u32,
+}
+
 #[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Clone, Debug)]
 pub enum SignatureResult<T, E> {
     Ok(T),
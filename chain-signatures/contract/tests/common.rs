@@ -3,8 +3,8 @@ use std::str::FromStr;
 
 use crypto_shared::kdf::{check_ec_signature, derive_secret_key};
 use crypto_shared::{
-    derive_epsilon, derive_key, ScalarExt as _, SerializableAffinePoint, SerializableScalar,
-    SignatureResponse,
+    derive_epsilon, derive_key, HashScheme, ScalarExt as _, SerializableAffinePoint,
+    SerializableScalar, SignatureResponse,
 };
 use digest::{Digest, FixedOutput};
 use ecdsa::signature::Verifier;
@@ -13,12 +13,13 @@ use k256::elliptic_curve::point::DecompressPoint as _;
 use k256::elliptic_curve::sec1::ToEncodedPoint;
 use k256::{AffinePoint, FieldBytes, Scalar, Secp256k1};
 use mpc_contract::primitives::{
-    CandidateInfo, ParticipantInfo, Participants, SignRequest, SignatureRequest,
+    CandidateInfo, ParticipantInfo, Participants, SignMessageRequest, SignRequest, SignatureRequest,
 };
 use mpc_contract::update::UpdateId;
 use near_workspaces::network::Sandbox;
 use near_workspaces::types::{AccountId, NearToken};
 use near_workspaces::{Account, Contract, Worker};
+use signature::hazmat::PrehashSigner;
 use signature::DigestSigner;
 
 pub const CONTRACT_FILE_PATH: &str =
@@ -172,43 +173,100 @@ pub async fn create_response(
         signing_key.try_sign_digest(digest).unwrap();
     verifying_key.verify(msg.as_bytes(), &signature).unwrap();
 
-    let s = signature.s();
-    let (r_bytes, _s_bytes) = signature.split_bytes();
     let payload_hash_s = Scalar::from_bytes(payload_hash).unwrap();
     let respond_req = SignatureRequest::new(payload_hash_s, predecessor_id, path);
+    let respond_resp = signature_response(&derived_pk, &signature, scalar_hash);
+
+    (payload_hash, respond_req, respond_resp)
+}
+
+/// Same as [`create_response`], but for a `sign_message` request of `message` hashed with
+/// `hashing`.
+pub async fn create_message_response(
+    predecessor_id: &AccountId,
+    message: &[u8],
+    hashing: HashScheme,
+    path: &str,
+    sk: &k256::SecretKey,
+) -> (SignatureRequest, SignatureResponse) {
+    let payload = hashing.payload(message).unwrap();
+    let epsilon = derive_epsilon(predecessor_id, path);
+    let derived_sk = derive_secret_key(sk, epsilon);
+    let derived_pk = derive_key(sk.public_key().into(), epsilon);
+    let signing_key = k256::ecdsa::SigningKey::from(&derived_sk);
+    let signature: ecdsa::Signature<Secp256k1> =
+        signing_key.sign_prehash(&hashing.digest(message)).unwrap();
+
+    let respond_req = SignatureRequest::new(payload, predecessor_id, path);
+    let respond_resp = signature_response(&derived_pk, &signature, payload);
+    (respond_req, respond_resp)
+}
+
+fn signature_response(
+    derived_pk: &AffinePoint,
+    signature: &ecdsa::Signature<Secp256k1>,
+    scalar_hash: Scalar,
+) -> SignatureResponse {
+    let s = signature.s();
+    let (r_bytes, _s_bytes) = signature.split_bytes();
     let big_r =
         AffinePoint::decompress(&r_bytes, k256::elliptic_curve::subtle::Choice::from(0)).unwrap();
     let s: k256::Scalar = *s.as_ref();
 
-    let recovery_id = if check_ec_signature(&derived_pk, &big_r, &s, scalar_hash, 0).is_ok() {
+    let recovery_id = if check_ec_signature(derived_pk, &big_r, &s, scalar_hash, 0).is_ok() {
         0
-    } else if check_ec_signature(&derived_pk, &big_r, &s, scalar_hash, 1).is_ok() {
+    } else if check_ec_signature(derived_pk, &big_r, &s, scalar_hash, 1).is_ok() {
         1
     } else {
         panic!("unable to use recovery id of 0 or 1");
     };
 
-    let respond_resp = SignatureResponse {
+    SignatureResponse {
         big_r: SerializableAffinePoint {
             affine_point: big_r,
         },
         s: SerializableScalar { scalar: s },
         recovery_id,
-    };
-
-    (payload_hash, respond_req, respond_resp)
+    }
 }
 
 pub async fn sign_and_validate(
     request: &SignRequest,
     respond: Option<(&SignatureRequest, &SignatureResponse)>,
     contract: &Contract,
+) -> anyhow::Result<()> {
+    call_and_validate(
+        "sign",
+        serde_json::json!({ "request": request }),
+        respond,
+        contract,
+    )
+    .await
+}
+
+pub async fn sign_message_and_validate(
+    request: &SignMessageRequest,
+    respond: Option<(&SignatureRequest, &SignatureResponse)>,
+    contract: &Contract,
+) -> anyhow::Result<()> {
+    call_and_validate(
+        "sign_message",
+        serde_json::json!({ "request": request }),
+        respond,
+        contract,
+    )
+    .await
+}
+
+async fn call_and_validate(
+    method: &str,
+    args: serde_json::Value,
+    respond: Option<(&SignatureRequest, &SignatureResponse)>,
+    contract: &Contract,
 ) -> anyhow::Result<()> {
     let status = contract
-        .call("sign")
-        .args_json(serde_json::json!({
-            "request": request,
-        }))
+        .call(method)
+        .args_json(args)
         .deposit(NearToken::from_yoctonear(1))
         .max_gas()
         .transact_async()
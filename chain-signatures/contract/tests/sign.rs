@@ -1,11 +1,15 @@
 pub mod common;
-use common::{candidates, create_response, init, init_env, sign_and_validate};
+use common::{
+    candidates, create_message_response, create_response, init, init_env, sign_and_validate,
+    sign_message_and_validate,
+};
 
 use mpc_contract::errors;
-use mpc_contract::primitives::{CandidateInfo, SignRequest};
+use mpc_contract::primitives::{CandidateInfo, SignMessageRequest, SignRequest};
 use near_workspaces::types::{AccountId, NearToken};
 
-use crypto_shared::SignatureResponse;
+use crypto_shared::hashing::MAX_MESSAGE_LEN;
+use crypto_shared::{HashScheme, SignatureResponse};
 use std::collections::HashMap;
 
 #[tokio::test]
@@ -58,6 +62,64 @@ async fn test_contract_sign_request() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_contract_sign_message_request() -> anyhow::Result<()> {
+    let (_, contract, _, sk) = init_env().await;
+    let predecessor_id = contract.id();
+    let path = "test";
+
+    for (hashing, message) in [
+        (HashScheme::Sha256, "hello world"),
+        (HashScheme::Keccak256, "hello world!"),
+        (HashScheme::EthPersonalMessage, "hello world!!"),
+    ] {
+        println!("submitting: {message} hashed with {hashing:?}");
+        let message = message.as_bytes();
+        let (respond_req, respond_resp) =
+            create_message_response(predecessor_id, message, hashing, path, &sk).await;
+        let request = SignMessageRequest {
+            message: message.to_vec(),
+            hashing,
+            path: path.into(),
+            key_version: 0,
+        };
+        sign_message_and_validate(&request, Some((&respond_req, &respond_resp)), &contract).await?;
+
+        // Signing the digest directly queues the same request, and gets the same signature.
+        let request = SignRequest {
+            payload: hashing.digest(message),
+            path: path.into(),
+            key_version: 0,
+        };
+        sign_and_validate(&request, Some((&respond_req, &respond_resp)), &contract).await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_contract_sign_message_malformed() -> anyhow::Result<()> {
+    let (_, contract, _, _) = init_env().await;
+
+    // Empty and oversized messages are rejected before anything gets queued.
+    for message in [Vec::new(), vec![0; MAX_MESSAGE_LEN + 1]] {
+        let request = SignMessageRequest {
+            message,
+            hashing: HashScheme::Keccak256,
+            path: "test".into(),
+            key_version: 0,
+        };
+        let err = sign_message_and_validate(&request, None, &contract)
+            .await
+            .expect_err("should have failed with a malformed payload");
+        assert!(err
+            .to_string()
+            .contains(&errors::InvalidParameters::MalformedPayload.to_string()));
+    }
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_contract_sign_success_refund() -> anyhow::Result<()> {
     let (worker, contract, _, sk) = init_env().await;
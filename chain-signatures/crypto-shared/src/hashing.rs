@@ -0,0 +1,81 @@
+//! Digests of the messages signed in message mode, where the requester hands over the message
+//! along with how to hash it instead of its digest. The contract and the nodes both compute the
+//! digest with these, and sign it the same as a digest handed over directly.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use k256::Scalar;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+use crate::types::ScalarExt;
+
+/// Largest message that can be signed in message mode, in bytes.
+pub const MAX_MESSAGE_LEN: usize = 8 * 1024;
+
+/// Prefix of the messages hashed with [`HashScheme::EthPersonalMessage`], see EIP-191.
+const ETH_PERSONAL_MESSAGE_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n";
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum HashScheme {
+    /// SHA-256 of the message, as signed by Bitcoin and most non-EVM chains.
+    Sha256,
+    /// Keccak-256 of the message, as signed by EVM chains for transactions, where the message
+    /// is the RLP encoding of the unsigned transaction.
+    Keccak256,
+    /// Keccak-256 of the message prefixed as per EIP-191, as done by `personal_sign`. The prefix
+    /// separates the messages from the transactions, such that a signed message can never be
+    /// submitted as a transaction.
+    EthPersonalMessage,
+}
+
+impl HashScheme {
+    pub fn digest(&self, message: &[u8]) -> [u8; 32] {
+        match self {
+            Self::Sha256 => Sha256::digest(message).into(),
+            Self::Keccak256 => Keccak256::digest(message).into(),
+            Self::EthPersonalMessage => Keccak256::new()
+                .chain_update(ETH_PERSONAL_MESSAGE_PREFIX)
+                .chain_update(message.len().to_string())
+                .chain_update(message)
+                .finalize()
+                .into(),
+        }
+    }
+
+    /// The digest of the message as the payload to sign. Returns nothing if the digest is not a
+    /// canonical scalar, which is as unlikely as finding a collision.
+    pub fn payload(&self, message: &[u8]) -> Option<Scalar> {
+        Scalar::from_bytes(self.digest(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_schemes() {
+        assert_eq!(
+            HashScheme::Sha256.digest(b""),
+            hex::decode("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+                .unwrap()
+        );
+        assert_eq!(
+            HashScheme::Keccak256.digest(b""),
+            hex::decode("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")
+                .unwrap()
+        );
+        assert_eq!(
+            HashScheme::EthPersonalMessage.digest(b"hello"),
+            HashScheme::Keccak256.digest(b"\x19Ethereum Signed Message:\n5hello")
+        );
+        assert_ne!(
+            HashScheme::EthPersonalMessage.digest(b"hello"),
+            HashScheme::Keccak256.digest(b"hello")
+        );
+    }
+}
@@ -32,6 +32,17 @@ pub fn derive_key(public_key: PublicKey, epsilon: Scalar) -> PublicKey {
     (<Secp256k1 as CurveArithmetic>::ProjectivePoint::GENERATOR * epsilon + public_key).to_affine()
 }
 
+/// Derives the public key that signatures requested by `predecessor_id` with the given
+/// derivation `path` are produced for. This only needs the root public key of the network, so
+/// clients can compute the address they control offline.
+pub fn derive_public_key(
+    root_public_key: PublicKey,
+    predecessor_id: &AccountId,
+    path: &str,
+) -> PublicKey {
+    derive_key(root_public_key, derive_epsilon(predecessor_id, path))
+}
+
 pub fn derive_secret_key(secret_key: &SecretKey, epsilon: Scalar) -> SecretKey {
     SecretKey::new((epsilon + secret_key.to_nonzero_scalar().as_ref()).into())
 }
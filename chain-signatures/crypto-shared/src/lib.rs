@@ -1,9 +1,13 @@
+pub mod hashing;
 pub mod kdf;
+pub mod signature;
 pub mod types;
 
+pub use hashing::HashScheme;
 use k256::elliptic_curve::sec1::FromEncodedPoint;
 use k256::EncodedPoint;
-pub use kdf::{derive_epsilon, derive_key, x_coordinate};
+pub use kdf::{derive_epsilon, derive_key, derive_public_key, x_coordinate};
+pub use signature::{FullSignature, RecoverableSignature};
 pub use types::{
     PublicKey, ScalarExt, SerializableAffinePoint, SerializableScalar, SignatureResponse,
 };
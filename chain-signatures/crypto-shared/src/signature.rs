@@ -0,0 +1,162 @@
+//! Encodings of the signatures produced by the network for the consumers of other chains.
+
+use anyhow::Context;
+use k256::ecdsa::{RecoveryId, Signature};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::{AffinePoint, Scalar};
+use serde::{Deserialize, Serialize};
+
+use crate::kdf::{recover, x_coordinate};
+use crate::types::{PublicKey, SignatureResponse};
+
+/// Signature produced by the signing protocol, normalized to a low `s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FullSignature {
+    pub big_r: AffinePoint,
+    pub s: Scalar,
+}
+
+/// Signature along with the recovery id of the public key it was produced for, as expected by
+/// `ecrecover`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoverableSignature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    /// Recovery id, 0 or 1. Ethereum transactions expect `v` to be offset by 27, or by the chain
+    /// id as per EIP-155, which is up to the consumer.
+    pub v: u8,
+}
+
+impl FullSignature {
+    /// Negates both `big_r` and `s` if `s` is high, which yields an equivalent signature with the
+    /// same `r` that is accepted by the chains rejecting malleable signatures.
+    pub fn new(big_r: AffinePoint, s: Scalar) -> Self {
+        if bool::from(s.is_high()) {
+            Self {
+                big_r: -big_r,
+                s: -s,
+            }
+        } else {
+            Self { big_r, s }
+        }
+    }
+
+    /// The x coordinate of `big_r`.
+    pub fn r(&self) -> Scalar {
+        x_coordinate(&self.big_r)
+    }
+
+    pub fn to_ecdsa(&self) -> anyhow::Result<Signature> {
+        Signature::from_scalars(self.r(), self.s).context("signature has a zero scalar")
+    }
+
+    /// ASN.1 DER encoding, as used by Bitcoin.
+    pub fn to_der(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(self.to_ecdsa()?.to_der().as_bytes().to_vec())
+    }
+
+    /// `r` followed by `s`, both as 32 bytes big endian.
+    pub fn to_compact(&self) -> anyhow::Result<[u8; 64]> {
+        let mut compact = [0u8; 64];
+        compact.copy_from_slice(&self.to_ecdsa()?.to_bytes());
+        Ok(compact)
+    }
+
+    /// Finds the recovery id with which the public key recovered from the signature of
+    /// `msg_hash` is `public_key`, the key derived for the request.
+    pub fn recovery_id(&self, public_key: &PublicKey, msg_hash: Scalar) -> anyhow::Result<u8> {
+        let signature = self.to_ecdsa()?;
+        let expected = public_key.to_encoded_point(false);
+        for recovery_id in 0..=1 {
+            let recovered = recover(
+                &msg_hash.to_bytes(),
+                &signature,
+                RecoveryId::try_from(recovery_id).context("invalid recovery id")?,
+            );
+            if recovered.is_ok_and(|key| key.to_encoded_point(false) == expected) {
+                return Ok(recovery_id);
+            }
+        }
+        anyhow::bail!("cannot use either recovery id (0 or 1) to recover public key")
+    }
+
+    pub fn to_recoverable(
+        &self,
+        public_key: &PublicKey,
+        msg_hash: Scalar,
+    ) -> anyhow::Result<RecoverableSignature> {
+        Ok(RecoverableSignature {
+            r: self.r().to_bytes().into(),
+            s: self.s.to_bytes().into(),
+            v: self.recovery_id(public_key, msg_hash)?,
+        })
+    }
+
+    /// The response to the sign request for `msg_hash`, as submitted to the contract.
+    pub fn to_response(
+        &self,
+        public_key: &PublicKey,
+        msg_hash: Scalar,
+    ) -> anyhow::Result<SignatureResponse> {
+        let recovery_id = self.recovery_id(public_key, msg_hash)?;
+        Ok(SignatureResponse::new(self.big_r, self.s, recovery_id))
+    }
+}
+
+impl From<&SignatureResponse> for FullSignature {
+    fn from(response: &SignatureResponse) -> Self {
+        Self::new(response.big_r.affine_point, response.s.scalar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::{SigningKey, VerifyingKey};
+    use k256::elliptic_curve::point::DecompressPoint;
+    use k256::elliptic_curve::subtle::Choice;
+
+    use super::*;
+    use crate::ScalarExt;
+
+    #[test]
+    fn test_signature_encodings() {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let public_key = *signing_key.verifying_key().as_affine();
+        let msg_hash = Scalar::from_bytes([42u8; 32]).unwrap();
+        let (expected, expected_id): (Signature, RecoveryId) = signing_key
+            .sign_prehash_recoverable(&msg_hash.to_bytes())
+            .unwrap();
+
+        // Rebuild the R point the signature was produced with from its recovery id.
+        let big_r = AffinePoint::decompress(
+            &expected.r().to_bytes(),
+            Choice::from(expected_id.is_y_odd() as u8),
+        )
+        .unwrap();
+        let s = *expected.s().as_ref();
+        let signature = FullSignature::new(big_r, s);
+
+        assert_eq!(
+            signature.to_compact().unwrap().to_vec(),
+            expected.to_bytes().to_vec()
+        );
+        assert_eq!(
+            signature.to_der().unwrap(),
+            expected.to_der().as_bytes().to_vec()
+        );
+        let recoverable = signature.to_recoverable(&public_key, msg_hash).unwrap();
+        assert_eq!(recoverable.v, expected_id.to_byte());
+        assert_eq!(recoverable.r, <[u8; 32]>::from(expected.r().to_bytes()));
+
+        // A high s is normalized along with R, keeping the signature valid.
+        let high = FullSignature::new(-big_r, -s);
+        assert_eq!(high, signature);
+        let recovered = VerifyingKey::recover_from_prehash(
+            &msg_hash.to_bytes(),
+            &high.to_ecdsa().unwrap(),
+            RecoveryId::from_byte(recoverable.v).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(*recovered.as_affine(), public_key);
+    }
+}
@@ -0,0 +1,164 @@
+//! Benchmarks of the hot paths of the protocol loop: poking the managers, taking triples out of a
+//! shared manager, encoding messages and a whole triple to presignature round in the in-process
+//! harness. Run with `cargo bench -p mpc-node --features testing`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cait_sith::protocol::Participant;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use mpc_node::harness::{Harness, NetworkOptions};
+use mpc_node::keyspace::KeyspaceId;
+use mpc_node::protocol::codec::{self, SCHEMA_BINARY, SCHEMA_JSON};
+use mpc_node::protocol::message::TripleMessage;
+use mpc_node::protocol::MpcMessage;
+use rand::RngCore;
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+
+const NODES: u32 = 3;
+const THRESHOLD: usize = 2;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Harness whose first node has `triples` triples of its own, generated up front.
+fn harness_with_triples(rt: &Runtime, triples: usize) -> Harness {
+    let mut harness = Harness::new(NODES, THRESHOLD, NetworkOptions::default());
+    harness.generate_triples(0, triples);
+    rt.block_on(harness.run_until_quiet(10_000));
+    harness
+}
+
+/// First poke of a triple manager with hundreds of freshly started generators, which is when a
+/// poke produces the most messages.
+fn bench_poke(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("triple_poke");
+    group.sample_size(10);
+    for generators in [100, 300] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(generators),
+            &generators,
+            |b, &generators| {
+                b.iter_batched(
+                    || {
+                        let mut harness = Harness::new(NODES, THRESHOLD, NetworkOptions::default());
+                        harness.cfg.max_concurrent_generation = generators as u32;
+                        harness.generate_triples(0, generators);
+                        harness
+                    },
+                    |mut harness| {
+                        let cfg = harness.cfg.clone();
+                        rt.block_on(harness.nodes[0].triple_manager.poke(&cfg))
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Tasks taking pairs of triples out of the same manager and putting them back, as the
+/// presignature stockpiling and the message handling do concurrently.
+fn bench_take_two(c: &mut Criterion) {
+    let rt = runtime();
+    let mut harness = harness_with_triples(&rt, 16);
+    let node = harness.nodes.swap_remove(0);
+    let triple_manager = Arc::new(RwLock::new(node.triple_manager));
+
+    let mut group = c.benchmark_group("triple_take_two");
+    for tasks in [1, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+            b.to_async(&rt).iter(|| {
+                let triple_manager = triple_manager.clone();
+                async move {
+                    let handles = (0..tasks)
+                        .map(|_| {
+                            let triple_manager = triple_manager.clone();
+                            tokio::spawn(async move {
+                                let mut triple_manager = triple_manager.write().await;
+                                if let Some(pairs) = triple_manager.take_two_mine_batch(1).await {
+                                    for (triple0, triple1) in pairs {
+                                        triple_manager.insert_mine(triple0).await;
+                                        triple_manager.insert_mine(triple1).await;
+                                    }
+                                }
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    for handle in handles {
+                        handle.await.unwrap();
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Encoding and decoding of a triple message of a typical size, in both wire formats.
+fn bench_codec(c: &mut Criterion) {
+    let mut data = vec![0; 4096];
+    rand::thread_rng().fill_bytes(&mut data);
+    let msg = MpcMessage::Triple(TripleMessage {
+        id: 1,
+        keyspace: KeyspaceId::root(),
+        epoch: 0,
+        from: Participant::from(0u32),
+        data,
+        timestamp: 0,
+    });
+
+    let mut group = c.benchmark_group("codec");
+    for (name, schema) in [("json", SCHEMA_JSON), ("binary", SCHEMA_BINARY)] {
+        let encoded = codec::encode(&msg, schema).unwrap();
+        group.bench_function(BenchmarkId::new("encode", name), |b| {
+            b.iter(|| codec::encode(&msg, schema).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("decode", name), |b| {
+            b.iter(|| codec::decode(&encoded, schema).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Latency of generating a presignature from scratch: its two triples, then the presignature.
+fn bench_triple_to_presignature(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("triple_to_presignature");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(60));
+    group.bench_function("latency", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let mut harness = Harness::new(NODES, THRESHOLD, NetworkOptions::default());
+                let start = Instant::now();
+                rt.block_on(async {
+                    harness.generate_triples(0, 2);
+                    harness.run_until_quiet(10_000).await;
+                    assert_eq!(harness.generate_presignatures(0, 1).await, 1);
+                    harness.run_until_quiet(10_000).await;
+                });
+                total += start.elapsed();
+            }
+            total
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_poke,
+    bench_take_two,
+    bench_codec,
+    bench_triple_to_presignature
+);
+criterion_main!(benches);
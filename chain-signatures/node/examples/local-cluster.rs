@@ -0,0 +1,105 @@
+//! Happy path of a cluster of nodes, end to end and in-process: the nodes generate a key among
+//! themselves, stockpile a presignature and sign a payload with it, which is then verified against
+//! the derived public key. No NEAR chain, datastore or indexer is involved; see `mpc-node cluster
+//! gen-config` to run the same nodes as containers instead.
+//!
+//! Run with `cargo run -p mpc-node --features testing --example local-cluster -- --nodes 3`.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use crypto_shared::ScalarExt;
+use k256::Scalar;
+use mpc_node::cluster::Cluster;
+use mpc_node::harness::{Harness, NetworkOptions};
+use mpc_node::indexer::ContractSignRequest;
+use mpc_node::kdf;
+use near_account_id::AccountId;
+use sha2::{Digest, Sha256};
+
+const MAX_TICKS: u64 = 10_000;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of nodes in the cluster.
+    #[arg(long, default_value("3"))]
+    nodes: u32,
+    /// Number of nodes required to sign.
+    #[arg(long, default_value("2"))]
+    threshold: usize,
+    /// Seed of the simulated network.
+    #[arg(long, default_value("0"))]
+    seed: u64,
+    /// Payload to sign, hashed with SHA-256.
+    #[arg(long, default_value("hello from the local cluster"))]
+    message: String,
+    /// Also write the configuration of a containerized cluster of the same size to this directory.
+    #[arg(long)]
+    gen_config: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    if let Some(dir) = &args.gen_config {
+        let contract: AccountId = "signer.test.near".parse()?;
+        Cluster::generate(args.nodes as usize, args.threshold, &contract)?.write(
+            dir,
+            "near/mpc-node:latest",
+            3000,
+        )?;
+        println!("wrote the cluster configuration to {}", dir.display());
+    }
+
+    println!(
+        "generating a key among {} nodes with threshold {}",
+        args.nodes, args.threshold
+    );
+    let mut harness = Harness::new(
+        args.nodes,
+        args.threshold,
+        NetworkOptions {
+            seed: args.seed,
+            ..Default::default()
+        },
+    );
+    println!("public key: {:?}", harness.public_key);
+
+    // A presignature consumes two triples.
+    harness.generate_triples(0, 2);
+    let ticks = harness.run_until_quiet(MAX_TICKS).await;
+    println!("generated triples in {ticks} ticks");
+    if harness.generate_presignatures(0, 1).await == 0 {
+        anyhow::bail!("node 0 has no triples to generate a presignature from");
+    }
+    let ticks = harness.run_until_quiet(MAX_TICKS).await;
+    println!("generated a presignature in {ticks} ticks");
+
+    let payload = Scalar::from_non_biased(Sha256::digest(args.message.as_bytes()).into());
+    let requester: AccountId = "requester.test.near".parse()?;
+    let path = "local-cluster".to_string();
+    let epsilon = kdf::cached_epsilon(&requester, &path);
+    let request = ContractSignRequest {
+        payload,
+        path,
+        key_version: 0,
+    };
+    let Some(receipt_id) = harness.sign(0, request, epsilon).await else {
+        anyhow::bail!("node 0 has no presignature to sign with");
+    };
+    let ticks = harness.run_until_quiet(MAX_TICKS).await;
+    println!("signed receipt {receipt_id} in {ticks} ticks");
+
+    let signature_manager = &harness.nodes[0].signature_manager;
+    let Some((big_r, s)) = signature_manager.cached_signature(&payload, &epsilon) else {
+        anyhow::bail!("signature of receipt {receipt_id} did not complete");
+    };
+    let derived_key = kdf::cached_key(harness.public_key, epsilon);
+    let signature = kdf::into_eth_sig(&derived_key, &big_r, &s, payload)?;
+    println!(
+        "verified signature of {:?} by {requester} on path {:?}: recovery id {}",
+        args.message, "local-cluster", signature.recovery_id
+    );
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! Feeds arbitrary bytes to the decoders of the messages received from other participants: the
+//! batches of encrypted messages, the signed envelopes and the protocol messages themselves, in
+//! every supported schema. None of them may panic, and a decoded message has to survive being
+//! encoded and decoded again unchanged. Run with `cargo +nightly fuzz run decode` from
+//! `chain-signatures/node`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpc_node::mesh::transport::{self, MESSAGE_SCHEMAS};
+use mpc_node::protocol::codec;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = transport::decode_batch(data, false);
+    let _ = transport::decode_batch(data, true);
+
+    for &schema in MESSAGE_SCHEMAS {
+        let _ = codec::decode_signed(data, schema);
+        let Ok(message) = codec::decode(data, schema) else {
+            continue;
+        };
+        for &schema in MESSAGE_SCHEMAS {
+            let encoded = codec::encode(&message, schema).expect("decoded message fails to encode");
+            let decoded = codec::decode(&encoded, schema).expect("encoded message fails to decode");
+            assert_eq!(decoded, message, "message changed through schema {schema}");
+        }
+    }
+});
@@ -0,0 +1,158 @@
+//! Feeds arbitrarily ordered sequences of messages to the router and to the protocol managers of
+//! the nodes of the in-process harness, interleaved with the nodes making progress. Messages are
+//! either replayed from the ones on the simulated network, to any node and on behalf of any
+//! sender, or decoded from arbitrary bytes. Neither the router nor the managers may panic on any
+//! of them. Run with `cargo +nightly fuzz run router` from `chain-signatures/node`.
+
+#![no_main]
+
+use std::time::Duration;
+
+use arbitrary::Arbitrary;
+use cait_sith::protocol::Participant;
+use k256::Scalar;
+use libfuzzer_sys::fuzz_target;
+use mpc_node::harness::{Harness, NetworkOptions};
+use mpc_node::indexer::ContractSignRequest;
+use mpc_node::protocol::codec::{self, SCHEMA_BINARY, SCHEMA_JSON};
+use mpc_node::protocol::router::{MessageRouter, Received};
+use mpc_node::protocol::MpcMessage;
+
+const NODES: u32 = 3;
+const THRESHOLD: usize = 2;
+
+/// Upper bound of the triples and presignatures generated at once, which are costly.
+const MAX_BATCH: u8 = 2;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    /// Pokes every node and delivers the messages that are due.
+    Tick,
+    GenerateTriples {
+        node: u8,
+        count: u8,
+    },
+    GeneratePresignatures {
+        node: u8,
+        count: u8,
+    },
+    Sign {
+        node: u8,
+        payload: u64,
+    },
+    /// Delivers a message that is still on the network once more.
+    Replay {
+        index: u16,
+        from: u8,
+        to: u8,
+    },
+    /// Delivers a message decoded from arbitrary bytes.
+    Raw {
+        from: u8,
+        to: u8,
+        binary: bool,
+        bytes: Vec<u8>,
+    },
+    /// Routes the received messages relative to the given epoch.
+    Route {
+        epoch: u8,
+    },
+    DropSentBefore {
+        timestamp: u64,
+    },
+}
+
+fn participant(index: u8) -> Participant {
+    Participant::from(index as u32 % NODES)
+}
+
+fn node(index: u8) -> usize {
+    (index as u32 % NODES) as usize
+}
+
+async fn run(ops: Vec<Op>) {
+    let mut harness = Harness::new(NODES, THRESHOLD, NetworkOptions::default());
+    let mut router = MessageRouter::new(Duration::from_secs(60));
+    for op in ops {
+        let (from, to, message) = match op {
+            Op::Tick => {
+                harness.tick().await;
+                continue;
+            }
+            Op::GenerateTriples { node: index, count } => {
+                harness.generate_triples(node(index), (count % MAX_BATCH + 1) as usize);
+                continue;
+            }
+            Op::GeneratePresignatures { node: index, count } => {
+                harness
+                    .generate_presignatures(node(index), (count % MAX_BATCH + 1) as usize)
+                    .await;
+                continue;
+            }
+            Op::Sign {
+                node: index,
+                payload,
+            } => {
+                let request = ContractSignRequest {
+                    payload: Scalar::from(payload),
+                    path: "fuzz".to_string(),
+                    key_version: 0,
+                };
+                harness
+                    .sign(node(index), request, Scalar::from(payload))
+                    .await;
+                continue;
+            }
+            Op::Replay { index, from, to } => {
+                let in_flight = harness.in_flight().count();
+                if in_flight == 0 {
+                    continue;
+                }
+                let (_, _, message) = harness.in_flight().nth(index as usize % in_flight).unwrap();
+                (participant(from), participant(to), message.clone())
+            }
+            Op::Raw {
+                from,
+                to,
+                binary,
+                bytes,
+            } => {
+                let schema = if binary { SCHEMA_BINARY } else { SCHEMA_JSON };
+                let Ok(message) = codec::decode(&bytes, schema) else {
+                    continue;
+                };
+                (participant(from), participant(to), message)
+            }
+            Op::Route { epoch } => {
+                router.route(Some(epoch as u64));
+                continue;
+            }
+            Op::DropSentBefore { timestamp } => {
+                router.drop_sent_before(timestamp);
+                continue;
+            }
+        };
+        deliver(&mut harness, &mut router, from, to, message).await;
+    }
+    harness.run_until_quiet(1_000).await;
+}
+
+async fn deliver(
+    harness: &mut Harness,
+    router: &mut MessageRouter,
+    from: Participant,
+    to: Participant,
+    message: MpcMessage,
+) {
+    if router.receive(message.clone()) == Received::Duplicate {
+        return;
+    }
+    harness.inject(from, to, message).await;
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(run(ops));
+});
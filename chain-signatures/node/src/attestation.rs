@@ -0,0 +1,370 @@
+//! Remote attestation of nodes running inside a TEE, either Intel TDX or AMD SEV-SNP.
+//!
+//! A node in a TEE produces a quote whose report data binds its transport keys, i.e. the sign and
+//! cipher keys it registered in the contract, and a commitment to its key share. The quote is
+//! served in the [`Handshake`](crate::mesh::transport::Handshake), where the other participants
+//! check that it binds the keys the contract has for the node and that the measurement of the
+//! node is an allowed one. With attestation enabled, participants without a valid quote are never
+//! active, so no protocol is ever run with them.
+//!
+//! Quotes are obtained through the configfs-tsm interface of the guest kernel, which both TDX and
+//! SEV-SNP guests expose. The signature chain of a quote up to the root of trust of the vendor is
+//! not checked here, as it requires the collateral of the vendor: a quote is only checked for the
+//! keys it binds and the measurement it reports.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use k256::elliptic_curve::group::GroupEncoding;
+use mpc_keys::hpke;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::protocol::contract::primitives::ParticipantInfo;
+use crate::types::SecretKeyShare;
+
+/// Directory of the configfs-tsm reports of the guest kernel.
+const TSM_REPORT_PATH: &str = "/sys/kernel/config/tsm/report";
+
+/// Domain separator of the report data of a quote.
+const REPORT_DATA_DOMAIN: &[u8] = b"mpc-node-attestation";
+
+const REPORT_DATA_LEN: usize = 64;
+const MEASUREMENT_LEN: usize = 48;
+
+static ATTESTOR: OnceCell<Attestor> = OnceCell::new();
+
+#[derive(Debug, Clone, Default, PartialEq, clap::Parser)]
+#[group(id = "attestation_options")]
+pub struct Options {
+    /// Serve an attestation quote of the TEE this node runs in, and only run protocols with
+    /// participants serving a valid one.
+    #[arg(long, env("MPC_ATTESTATION"))]
+    pub attestation: bool,
+    /// Hex encoded measurements allowed for the participants: the MRTD for TDX and the launch
+    /// measurement for SEV-SNP. Any measurement is allowed if not set.
+    #[arg(long, env("MPC_ATTESTATION_MEASUREMENTS"), value_delimiter = ',')]
+    pub attestation_measurements: Vec<String>,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut opts = Vec::new();
+        if self.attestation {
+            opts.push("--attestation".to_string());
+        }
+        if !self.attestation_measurements.is_empty() {
+            opts.extend(vec![
+                "--attestation-measurements".to_string(),
+                self.attestation_measurements.join(","),
+            ]);
+        }
+        opts
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttestationError {
+    #[error("attestation io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unknown TEE provider {0:?}")]
+    UnknownProvider(String),
+    #[error("invalid measurement {0:?}")]
+    InvalidMeasurement(String),
+    #[error("participant did not present an attestation")]
+    Missing,
+    #[error("quote is malformed")]
+    Malformed,
+    #[error("quote does not bind the registered keys of the participant")]
+    KeyMismatch,
+    #[error("measurement {0} is not allowed")]
+    MeasurementNotAllowed(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    Tdx,
+    SevSnp,
+}
+
+impl Platform {
+    fn from_provider(provider: &str) -> Result<Self, AttestationError> {
+        match provider.trim() {
+            "tdx_guest" => Ok(Self::Tdx),
+            "sev_guest" => Ok(Self::SevSnp),
+            provider => Err(AttestationError::UnknownProvider(provider.to_string())),
+        }
+    }
+
+    /// Checks the header of a quote of the platform, returning the offsets of the report data
+    /// and of the measurement in the quote.
+    fn layout(self, quote: &[u8]) -> Result<(usize, usize), AttestationError> {
+        match self {
+            // Quote v4: a 48 bytes header with the version and the TEE type, followed by the TD
+            // report body holding the MRTD at offset 136 and the report data at offset 520.
+            Self::Tdx => {
+                if quote.get(0..2) != Some(&[4, 0][..])
+                    || quote.get(4..8) != Some(&[0x81, 0, 0, 0][..])
+                {
+                    return Err(AttestationError::Malformed);
+                }
+                Ok((48 + 520, 48 + 136))
+            }
+            // Attestation report of version 2 or later.
+            Self::SevSnp => {
+                let version: [u8; 4] = quote
+                    .get(0..4)
+                    .and_then(|version| version.try_into().ok())
+                    .ok_or(AttestationError::Malformed)?;
+                if u32::from_le_bytes(version) < 2 {
+                    return Err(AttestationError::Malformed);
+                }
+                Ok((0x50, 0x90))
+            }
+        }
+    }
+}
+
+/// Attestation a node serves in its handshake.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub platform: Platform,
+    /// Commitment to the key share of the node, zero if it has none yet.
+    pub share_commitment: [u8; 32],
+    /// Hex encoded quote, or attestation report for SEV-SNP.
+    pub quote: String,
+}
+
+struct Attestor {
+    sign_pk: near_crypto::PublicKey,
+    cipher_pk: hpke::PublicKey,
+    measurements: Vec<Vec<u8>>,
+    /// Latest attestation, regenerated when the key share changes.
+    latest: Mutex<Option<Attestation>>,
+}
+
+/// Enables attestation for this node. Fails if the node does not run inside a supported TEE, so
+/// that a node configured for attestation never starts without it.
+pub fn init(
+    options: &Options,
+    sign_pk: near_crypto::PublicKey,
+    cipher_pk: hpke::PublicKey,
+) -> Result<(), AttestationError> {
+    let measurements = options
+        .attestation_measurements
+        .iter()
+        .map(|measurement| {
+            hex::decode(measurement)
+                .ok()
+                .filter(|bytes| bytes.len() == MEASUREMENT_LEN)
+                .ok_or_else(|| AttestationError::InvalidMeasurement(measurement.clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let attestor = Attestor {
+        sign_pk,
+        cipher_pk,
+        measurements,
+        latest: Mutex::new(None),
+    };
+    attestor.attest([0; 32])?;
+    let _ = ATTESTOR.set(attestor);
+    Ok(())
+}
+
+/// Whether participants are required to present a valid attestation.
+pub fn enabled() -> bool {
+    ATTESTOR.get().is_some()
+}
+
+/// Commitment to a key share: the hash of the public share.
+pub fn share_commitment(share: &SecretKeyShare) -> [u8; 32] {
+    let public_share = k256::ProjectivePoint::GENERATOR * share;
+    Sha256::digest(public_share.to_affine().to_bytes()).into()
+}
+
+/// The attestation of this node for its current key share, if attestation is enabled.
+pub fn attest(share: Option<&SecretKeyShare>) -> Option<Attestation> {
+    let attestor = ATTESTOR.get()?;
+    let share_commitment = share.map(share_commitment).unwrap_or_default();
+    match attestor.attest(share_commitment) {
+        Ok(attestation) => Some(attestation),
+        Err(err) => {
+            tracing::error!(?err, "failed to produce attestation quote");
+            None
+        }
+    }
+}
+
+/// Verifies the attestation presented by a participant against the keys the contract has for
+/// it. Always succeeds if attestation is not enabled.
+pub fn verify(
+    attestation: Option<&Attestation>,
+    info: &ParticipantInfo,
+) -> Result<(), AttestationError> {
+    let Some(attestor) = ATTESTOR.get() else {
+        return Ok(());
+    };
+    let attestation = attestation.ok_or(AttestationError::Missing)?;
+    verify_quote(attestation, info, &attestor.measurements)
+}
+
+fn verify_quote(
+    attestation: &Attestation,
+    info: &ParticipantInfo,
+    measurements: &[Vec<u8>],
+) -> Result<(), AttestationError> {
+    let quote = hex::decode(&attestation.quote).map_err(|_| AttestationError::Malformed)?;
+    let (data_offset, measurement_offset) = attestation.platform.layout(&quote)?;
+    let field = |offset: usize, len: usize| {
+        quote
+            .get(offset..offset + len)
+            .ok_or(AttestationError::Malformed)
+    };
+    let expected = report_data(
+        &info.sign_pk,
+        &info.cipher_pk,
+        &attestation.share_commitment,
+    );
+    if field(data_offset, REPORT_DATA_LEN)? != expected {
+        return Err(AttestationError::KeyMismatch);
+    }
+    let measurement = field(measurement_offset, MEASUREMENT_LEN)?;
+    if !measurements.is_empty() && !measurements.iter().any(|allowed| allowed == measurement) {
+        return Err(AttestationError::MeasurementNotAllowed(hex::encode(
+            measurement,
+        )));
+    }
+    Ok(())
+}
+
+/// Report data of a quote, binding the transport keys of the node and its key share.
+fn report_data(
+    sign_pk: &near_crypto::PublicKey,
+    cipher_pk: &hpke::PublicKey,
+    share_commitment: &[u8; 32],
+) -> [u8; REPORT_DATA_LEN] {
+    let digest = Sha512::new()
+        .chain_update(REPORT_DATA_DOMAIN)
+        .chain_update(sign_pk.key_data())
+        .chain_update(cipher_pk.to_bytes())
+        .chain_update(share_commitment)
+        .finalize();
+    let mut report_data = [0; REPORT_DATA_LEN];
+    report_data.copy_from_slice(&digest);
+    report_data
+}
+
+impl Attestor {
+    fn attest(&self, share_commitment: [u8; 32]) -> Result<Attestation, AttestationError> {
+        let mut latest = self.latest.lock().unwrap();
+        if let Some(attestation) = latest.as_ref() {
+            if attestation.share_commitment == share_commitment {
+                return Ok(attestation.clone());
+            }
+        }
+        let report_data = report_data(&self.sign_pk, &self.cipher_pk, &share_commitment);
+        let (platform, quote) = generate_quote(Path::new(TSM_REPORT_PATH), &report_data)?;
+        tracing::info!(?platform, "produced attestation quote");
+        let attestation = Attestation {
+            platform,
+            share_commitment,
+            quote: hex::encode(quote),
+        };
+        *latest = Some(attestation.clone());
+        Ok(attestation)
+    }
+}
+
+/// Requests a quote for the given report data from the configfs-tsm interface.
+fn generate_quote(
+    tsm_path: &Path,
+    report_data: &[u8; REPORT_DATA_LEN],
+) -> Result<(Platform, Vec<u8>), AttestationError> {
+    let report = tsm_path.join(format!("mpc-node-{}", std::process::id()));
+    std::fs::create_dir(&report)?;
+    let quote = (|| {
+        std::fs::write(report.join("inblob"), report_data)?;
+        let platform = Platform::from_provider(&std::fs::read_to_string(report.join("provider"))?)?;
+        let quote = std::fs::read(report.join("outblob"))?;
+        Ok((platform, quote))
+    })();
+    if let Err(err) = std::fs::remove_dir(&report) {
+        tracing::warn!(?err, "failed to remove configfs-tsm report");
+    }
+    quote
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_quote() {
+        let (_, cipher_pk) = hpke::generate();
+        let sign_pk =
+            near_crypto::SecretKey::from_random(near_crypto::KeyType::ED25519).public_key();
+        let mut info = ParticipantInfo::new(0);
+        info.sign_pk = sign_pk.clone();
+        info.cipher_pk = cipher_pk.clone();
+
+        let share_commitment = [7; 32];
+        let measurement = [9; MEASUREMENT_LEN];
+        let mut quote = vec![0; 1184];
+        quote[0] = 2;
+        quote[0x50..0x50 + REPORT_DATA_LEN].copy_from_slice(&report_data(
+            &sign_pk,
+            &cipher_pk,
+            &share_commitment,
+        ));
+        quote[0x90..0x90 + MEASUREMENT_LEN].copy_from_slice(&measurement);
+        let attestation = Attestation {
+            platform: Platform::SevSnp,
+            share_commitment,
+            quote: hex::encode(&quote),
+        };
+
+        verify_quote(&attestation, &info, &[]).unwrap();
+        verify_quote(&attestation, &info, &[measurement.to_vec()]).unwrap();
+        assert!(matches!(
+            verify_quote(&attestation, &info, &[vec![0; MEASUREMENT_LEN]]),
+            Err(AttestationError::MeasurementNotAllowed(_))
+        ));
+
+        // The quote only holds for the keys and the share it was produced for.
+        let (_, other_pk) = hpke::generate();
+        let mut other = info.clone();
+        other.cipher_pk = other_pk;
+        assert!(matches!(
+            verify_quote(&attestation, &other, &[]),
+            Err(AttestationError::KeyMismatch)
+        ));
+        let forged = Attestation {
+            share_commitment: [8; 32],
+            ..attestation.clone()
+        };
+        assert!(matches!(
+            verify_quote(&forged, &info, &[]),
+            Err(AttestationError::KeyMismatch)
+        ));
+
+        // A quote of the wrong platform or cut short is rejected.
+        let tdx = Attestation {
+            platform: Platform::Tdx,
+            ..attestation.clone()
+        };
+        assert!(matches!(
+            verify_quote(&tdx, &info, &[]),
+            Err(AttestationError::Malformed)
+        ));
+        let truncated = Attestation {
+            quote: hex::encode(&quote[..0x60]),
+            ..attestation
+        };
+        assert!(matches!(
+            verify_quote(&truncated, &info, &[]),
+            Err(AttestationError::Malformed)
+        ));
+    }
+}
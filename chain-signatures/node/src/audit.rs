@@ -0,0 +1,235 @@
+//! Append-only audit trail of the signatures published by this node, written as JSON lines. Every
+//! record ties a signature to the presignature and the triples it was generated from, such that
+//! any signature can later be traced back to its precomputation. The trail can be queried through
+//! the admin server.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use cait_sith::protocol::Participant;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::presignature::{Presignature, PresignatureId};
+use crate::protocol::triple::TripleId;
+
+static TRAIL: OnceCell<Trail> = OnceCell::new();
+
+struct Trail {
+    path: PathBuf,
+    writer: Mutex<LineWriter<File>>,
+}
+
+/// The presignature a signature was generated with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Precomputation {
+    pub id: PresignatureId,
+    /// Epoch of the key shares the presignature was generated with.
+    pub epoch: u64,
+    pub participants: Vec<Participant>,
+    /// Triples the presignature was generated from. Unknown for the presignatures generated
+    /// before they were recorded.
+    pub triples: Option<(TripleId, TripleId)>,
+}
+
+impl Precomputation {
+    pub fn of(presignature: &Presignature) -> Self {
+        Self {
+            id: presignature.id,
+            epoch: presignature.epoch,
+            participants: presignature.participants.clone(),
+            triples: presignature.triples,
+        }
+    }
+}
+
+/// How a signature came to be: the signing protocol and the presignature it consumed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lineage {
+    /// Epoch the signature was generated in.
+    pub epoch: u64,
+    pub proposer: Participant,
+    pub participants: Vec<Participant>,
+    pub presignature: Precomputation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Unix timestamp in milliseconds of when the signature was published.
+    pub timestamp: i64,
+    pub receipt_id: String,
+    /// Receipts of the requests merged into this one, answered by the same signature.
+    #[serde(default)]
+    pub merged: Vec<String>,
+    /// Hash of the signed payload, hex encoded.
+    pub request_hash: String,
+    pub big_r: String,
+    #[serde(flatten)]
+    pub lineage: Lineage,
+}
+
+/// Opens the audit trail at `path` in append mode. Signatures are only recorded once this is
+/// called.
+pub fn init(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let trail = Trail {
+        path: path.to_path_buf(),
+        writer: Mutex::new(LineWriter::new(file)),
+    };
+    if TRAIL.set(trail).is_err() {
+        tracing::warn!(?path, "audit trail is already initialized");
+    }
+    Ok(())
+}
+
+/// Appends a record to the audit trail. Does nothing if the trail is not enabled.
+pub fn record(record: &Record) {
+    let Some(trail) = TRAIL.get() else {
+        return;
+    };
+    let mut line = match serde_json::to_vec(record) {
+        Ok(line) => line,
+        Err(err) => {
+            tracing::warn!(?err, "failed to serialize audit record");
+            return;
+        }
+    };
+    line.push(b'\n');
+    let mut writer = trail
+        .writer
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Err(err) = writer.write_all(&line) {
+        tracing::error!(
+            ?err,
+            receipt_id = record.receipt_id,
+            "failed to write to audit trail"
+        );
+    }
+}
+
+/// Selects the records returned by [`query`]. Every field that is set must match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Filter {
+    /// Records of the signature answering this receipt, including merged ones.
+    pub receipt_id: Option<String>,
+    /// Records of the signature generated with this presignature.
+    pub presignature_id: Option<PresignatureId>,
+    /// Records of the signature whose presignature was generated from this triple.
+    pub triple_id: Option<TripleId>,
+    /// Records of signatures generated in this epoch.
+    pub epoch: Option<u64>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        let presignature = &record.lineage.presignature;
+        self.receipt_id.as_ref().map_or(true, |receipt_id| {
+            &record.receipt_id == receipt_id || record.merged.contains(receipt_id)
+        }) && self
+            .presignature_id
+            .map_or(true, |id| presignature.id == id)
+            && self.triple_id.map_or(true, |id| {
+                presignature
+                    .triples
+                    .is_some_and(|(triple0, triple1)| triple0 == id || triple1 == id)
+            })
+            && self
+                .epoch
+                .map_or(true, |epoch| record.lineage.epoch == epoch)
+    }
+}
+
+/// Reads the records of the audit trail matching `filter`, oldest first. Returns `None` if the
+/// trail is not enabled.
+pub fn query(filter: &Filter) -> Option<std::io::Result<Vec<Record>>> {
+    let trail = TRAIL.get()?;
+    Some(read(&trail.path, filter))
+}
+
+/// Reads the records of the audit trail at `path` matching `filter`. Lines that cannot be parsed,
+/// e.g. one cut short by a crash, are skipped.
+pub fn read(path: &Path, filter: &Filter) -> std::io::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::warn!(line = number + 1, ?err, "skipping malformed audit record");
+                continue;
+            }
+        };
+        if filter.matches(&record) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(receipt_id: &str, presignature_id: PresignatureId, triples: (u64, u64)) -> Record {
+        Record {
+            timestamp: 0,
+            receipt_id: receipt_id.to_string(),
+            merged: vec![format!("{receipt_id}-merged")],
+            request_hash: "00".to_string(),
+            big_r: "r".to_string(),
+            lineage: Lineage {
+                epoch: 1,
+                proposer: Participant::from(0u32),
+                participants: vec![Participant::from(0u32), Participant::from(1u32)],
+                presignature: Precomputation {
+                    id: presignature_id,
+                    epoch: 1,
+                    participants: vec![Participant::from(0u32), Participant::from(1u32)],
+                    triples: Some(triples),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_read_filters_records() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for record in [record("a", 1, (10, 11)), record("b", 2, (12, 13))] {
+            writeln!(file, "{}", serde_json::to_string(&record).unwrap()).unwrap();
+        }
+        // A record cut short by a crash.
+        write!(file, "{{\"timestamp\":").unwrap();
+        drop(file);
+
+        let all = read(&path, &Filter::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].lineage, record("a", 1, (10, 11)).lineage);
+
+        let by_triple = Filter {
+            triple_id: Some(13),
+            ..Default::default()
+        };
+        let records = read(&path, &by_triple).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].receipt_id, "b");
+
+        let by_merged = Filter {
+            receipt_id: Some("a-merged".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(read(&path, &by_merged).unwrap()[0].receipt_id, "a");
+
+        let none = Filter {
+            presignature_id: Some(1),
+            epoch: Some(2),
+            ..Default::default()
+        };
+        assert!(read(&path, &none).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,556 @@
+//! Backup of the key share of the node, for operators to recover a node whose secret storage got
+//! lost. The share is split with Shamir's secret sharing over GF(256) into one piece per recovery
+//! key, such that any `threshold` of the operators holding those keys can restore it, and every
+//! piece is encrypted to its recovery key. With a threshold of one, every operator can restore the
+//! share on their own.
+//!
+//! The restored share is written to the secret storage of the node, which then rejoins the mesh at
+//! the epoch of the share on its next start. A share of an epoch the contract has since moved past
+//! can no longer sign, and the node has to rejoin as a new participant instead.
+
+use std::path::{Path, PathBuf};
+
+use crypto_shared::PublicKey;
+use mpc_keys::hpke::{self, Ciphered};
+use near_account_id::AccountId;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::gcp::GcpService;
+use crate::protocol::state::PersistentNodeData;
+use crate::protocol::ProtocolState;
+use crate::{rpc_client, storage};
+
+/// Version of the backup format.
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("encryption error: {0}")]
+    Encryption(String),
+    #[error("unsupported backup version {0}")]
+    UnsupportedVersion(u32),
+    #[error("backup was taken by {0}")]
+    AccountMismatch(AccountId),
+    #[error("threshold must be between 1 and the {0} recovery keys")]
+    BadThreshold(usize),
+    #[error("{0} of the {1} shares required to recover the key share could be decrypted")]
+    NotEnoughShares(usize, u8),
+    #[error("recovered key share does not match the backup")]
+    Corrupted,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Command {
+    /// Exports the key share of the node, encrypted to the given recovery keys.
+    Export {
+        #[arg(long, env("MPC_ACCOUNT_ID"))]
+        account_id: AccountId,
+        /// The cipher secret key of the node, used to read a key share stored on disk.
+        #[arg(long, env("MPC_CIPHER_SK"))]
+        cipher_sk: String,
+        #[clap(flatten)]
+        storage_options: storage::Options,
+        /// Hex encoded public key of an operator the key share is exported to. Repeat it for
+        /// every operator.
+        #[arg(long = "recovery-key", required = true)]
+        recovery_keys: Vec<String>,
+        /// Number of operators required to restore the key share.
+        #[arg(long, default_value("1"))]
+        threshold: u8,
+        /// Path the backup is written to.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Restores the key share of the node from a backup, into its secret storage.
+    Restore {
+        #[arg(long, env("MPC_ACCOUNT_ID"))]
+        account_id: AccountId,
+        /// The cipher secret key of the node, used to write a key share stored on disk.
+        #[arg(long, env("MPC_CIPHER_SK"))]
+        cipher_sk: String,
+        #[clap(flatten)]
+        storage_options: storage::Options,
+        /// Hex encoded secret keys of the operators restoring the key share.
+        #[arg(long, env("MPC_RECOVERY_SKS"), value_delimiter(','), required = true)]
+        recovery_sks: Vec<String>,
+        /// Path of the backup to restore.
+        #[arg(long)]
+        input: PathBuf,
+        /// NEAR RPC address, to check the backup against the state of the contract.
+        #[arg(long, env("MPC_NEAR_RPC"), requires = "mpc_contract_id")]
+        near_rpc: Option<String>,
+        #[arg(long, env("MPC_CONTRACT_ID"))]
+        mpc_contract_id: Option<AccountId>,
+        /// Restore the key share even though the contract moved past its epoch.
+        #[arg(long)]
+        allow_stale: bool,
+        /// Overwrite the key share that is already in the secret storage.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl Command {
+    pub fn into_str_args(self) -> Vec<String> {
+        match self {
+            Command::Export {
+                account_id,
+                cipher_sk,
+                storage_options,
+                recovery_keys,
+                threshold,
+                output,
+            } => {
+                let mut args = vec![
+                    "export".to_string(),
+                    "--account-id".to_string(),
+                    account_id.to_string(),
+                    "--cipher-sk".to_string(),
+                    cipher_sk,
+                    "--threshold".to_string(),
+                    threshold.to_string(),
+                    "--output".to_string(),
+                    output.display().to_string(),
+                ];
+                for recovery_key in recovery_keys {
+                    args.extend(["--recovery-key".to_string(), recovery_key]);
+                }
+                args.extend(storage_options.into_str_args());
+                args
+            }
+            Command::Restore {
+                account_id,
+                cipher_sk,
+                storage_options,
+                recovery_sks,
+                input,
+                near_rpc,
+                mpc_contract_id,
+                allow_stale,
+                force,
+            } => {
+                let mut args = vec![
+                    "restore".to_string(),
+                    "--account-id".to_string(),
+                    account_id.to_string(),
+                    "--cipher-sk".to_string(),
+                    cipher_sk,
+                    "--recovery-sks".to_string(),
+                    recovery_sks.join(","),
+                    "--input".to_string(),
+                    input.display().to_string(),
+                ];
+                if let Some(near_rpc) = near_rpc {
+                    args.extend(["--near-rpc".to_string(), near_rpc]);
+                }
+                if let Some(mpc_contract_id) = mpc_contract_id {
+                    args.extend(["--mpc-contract-id".to_string(), mpc_contract_id.to_string()]);
+                }
+                if allow_stale {
+                    args.push("--allow-stale".to_string());
+                }
+                if force {
+                    args.push("--force".to_string());
+                }
+                args.extend(storage_options.into_str_args());
+                args
+            }
+        }
+    }
+}
+
+/// A piece of the key share, encrypted to the recovery key of one operator.
+#[derive(Serialize, Deserialize)]
+pub struct RecoveryShare {
+    pub recovery_key: hpke::PublicKey,
+    /// Point the piece was evaluated at, never zero.
+    pub index: u8,
+    pub ciphered: Ciphered,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub account_id: AccountId,
+    pub epoch: u64,
+    pub public_key: PublicKey,
+    /// Number of shares required to recover the key share.
+    pub threshold: u8,
+    pub shares: Vec<RecoveryShare>,
+}
+
+/// Binds every piece to the node, the epoch and the position of the piece.
+fn associated_data(account_id: &AccountId, epoch: u64, index: u8) -> Vec<u8> {
+    format!("mpc-node-key-share-backup:{account_id}:{epoch}:{index}").into_bytes()
+}
+
+impl Backup {
+    /// Splits the key share into one piece per recovery key, `threshold` of which are required to
+    /// recover it.
+    pub fn seal(
+        account_id: &AccountId,
+        data: &PersistentNodeData,
+        recovery_keys: &[hpke::PublicKey],
+        threshold: u8,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<Self, BackupError> {
+        if threshold == 0 || threshold as usize > recovery_keys.len() || recovery_keys.len() > 255 {
+            return Err(BackupError::BadThreshold(recovery_keys.len()));
+        }
+        let secret = Zeroizing::new(serde_json::to_vec(data)?);
+        let pieces = shamir::split(&secret, threshold, recovery_keys.len() as u8, rng);
+        let shares = recovery_keys
+            .iter()
+            .zip(pieces.iter())
+            .map(|(recovery_key, (index, piece))| {
+                let ciphered = recovery_key
+                    .encrypt(piece, &associated_data(account_id, data.epoch, *index))
+                    .map_err(|err| BackupError::Encryption(err.to_string()))?;
+                Ok(RecoveryShare {
+                    recovery_key: recovery_key.clone(),
+                    index: *index,
+                    ciphered,
+                })
+            })
+            .collect::<Result<_, BackupError>>()?;
+        Ok(Self {
+            version: BACKUP_VERSION,
+            account_id: account_id.clone(),
+            epoch: data.epoch,
+            public_key: data.public_key,
+            threshold,
+            shares,
+        })
+    }
+
+    /// Recovers the key share with the secret keys of enough operators.
+    pub fn open(
+        &self,
+        account_id: &AccountId,
+        recovery_sks: &[hpke::SecretKey],
+    ) -> Result<PersistentNodeData, BackupError> {
+        if self.version > BACKUP_VERSION {
+            return Err(BackupError::UnsupportedVersion(self.version));
+        }
+        if &self.account_id != account_id {
+            return Err(BackupError::AccountMismatch(self.account_id.clone()));
+        }
+        let mut pieces = Vec::new();
+        for share in &self.shares {
+            let Some(sk) = recovery_sks
+                .iter()
+                .find(|sk| sk.public_key() == share.recovery_key)
+            else {
+                continue;
+            };
+            let piece = sk
+                .decrypt(
+                    &share.ciphered,
+                    &associated_data(&self.account_id, self.epoch, share.index),
+                )
+                .map_err(|err| BackupError::Encryption(err.to_string()))?;
+            pieces.push((share.index, Zeroizing::new(piece)));
+            if pieces.len() == self.threshold as usize {
+                break;
+            }
+        }
+        if pieces.len() < self.threshold as usize {
+            return Err(BackupError::NotEnoughShares(pieces.len(), self.threshold));
+        }
+        let secret = shamir::combine(&pieces);
+        let data: PersistentNodeData =
+            serde_json::from_slice(&secret).map_err(|_| BackupError::Corrupted)?;
+        if data.epoch != self.epoch || data.public_key != self.public_key {
+            return Err(BackupError::Corrupted);
+        }
+        Ok(data)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), BackupError> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, BackupError> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+pub fn run(command: Command) -> anyhow::Result<()> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async {
+        match command {
+            Command::Export {
+                account_id,
+                cipher_sk,
+                storage_options,
+                recovery_keys,
+                threshold,
+                output,
+            } => {
+                let recovery_keys = recovery_keys
+                    .iter()
+                    .map(|key| Ok(hpke::PublicKey::try_from_bytes(&hex::decode(key)?)?))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(cipher_sk)?)?;
+                let gcp_service = GcpService::init(&account_id, &storage_options).await?;
+                let key_storage = storage::secret_storage::init(
+                    Some(&gcp_service),
+                    &storage_options,
+                    &account_id,
+                    &cipher_sk,
+                )
+                .await;
+                let Some(data) = key_storage.load().await? else {
+                    anyhow::bail!("there is no key share to back up");
+                };
+                let backup = Backup::seal(
+                    &account_id,
+                    &data,
+                    &recovery_keys,
+                    threshold,
+                    &mut rand::thread_rng(),
+                )?;
+                backup.write(&output)?;
+                tracing::info!(
+                    target: "audit",
+                    %account_id,
+                    epoch = data.epoch,
+                    recovery_keys = recovery_keys.len(),
+                    threshold,
+                    ?output,
+                    "exported key share backup"
+                );
+            }
+            Command::Restore {
+                account_id,
+                cipher_sk,
+                storage_options,
+                recovery_sks,
+                input,
+                near_rpc,
+                mpc_contract_id,
+                allow_stale,
+                force,
+            } => {
+                let recovery_sks = recovery_sks
+                    .iter()
+                    .map(|sk| Ok(hpke::SecretKey::try_from_bytes(&hex::decode(sk)?)?))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let data = Backup::read(&input)?.open(&account_id, &recovery_sks)?;
+
+                if let (Some(near_rpc), Some(mpc_contract_id)) = (near_rpc, mpc_contract_id) {
+                    let rpc_client = near_fetch::Client::new(&near_rpc);
+                    let contract_state =
+                        rpc_client::fetch_mpc_contract_state(&rpc_client, &mpc_contract_id)
+                            .await?;
+                    check_against_contract(&data, &contract_state, allow_stale)?;
+                }
+
+                let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(cipher_sk)?)?;
+                let gcp_service = GcpService::init(&account_id, &storage_options).await?;
+                let mut key_storage = storage::secret_storage::init(
+                    Some(&gcp_service),
+                    &storage_options,
+                    &account_id,
+                    &cipher_sk,
+                )
+                .await;
+                if let Some(existing) = key_storage.load().await? {
+                    if !force {
+                        anyhow::bail!(
+                            "a key share of epoch {} is already stored, pass --force to overwrite it",
+                            existing.epoch
+                        );
+                    }
+                }
+                key_storage.store(&data).await?;
+                tracing::info!(
+                    target: "audit",
+                    %account_id,
+                    epoch = data.epoch,
+                    ?input,
+                    "restored key share from backup"
+                );
+            }
+        }
+        anyhow::Ok(())
+    })
+}
+
+/// Makes sure the node can rejoin with the restored key share: it has to be a share of the key
+/// of the contract, and of the epoch the contract is in unless stale shares are allowed.
+fn check_against_contract(
+    data: &PersistentNodeData,
+    contract_state: &ProtocolState,
+    allow_stale: bool,
+) -> anyhow::Result<()> {
+    let epoch = match contract_state {
+        ProtocolState::Initializing(_) => anyhow::bail!("contract is still initializing"),
+        ProtocolState::Running(state) => state.epoch,
+        ProtocolState::Resharing(state) => state.old_epoch,
+    };
+    if contract_state.public_key() != Some(&data.public_key) {
+        anyhow::bail!("key share is not a share of the key of the contract");
+    }
+    if data.epoch > epoch {
+        anyhow::bail!(
+            "key share is of epoch {} ahead of the contract's {epoch}",
+            data.epoch
+        );
+    }
+    if data.epoch < epoch {
+        if !allow_stale {
+            anyhow::bail!(
+                "key share is of epoch {} while the contract is in {epoch}, the node would have to rejoin as a new participant; pass --allow-stale to restore it anyway",
+                data.epoch
+            );
+        }
+        tracing::warn!(
+            share_epoch = data.epoch,
+            epoch,
+            "restoring a stale key share, the node will rejoin as a new participant"
+        );
+    }
+    Ok(())
+}
+
+/// Shamir's secret sharing over GF(256), applied to every byte of the secret independently.
+mod shamir {
+    use rand::{CryptoRng, RngCore};
+    use zeroize::Zeroizing;
+
+    fn mul(mut a: u8, mut b: u8) -> u8 {
+        let mut product = 0;
+        while b != 0 {
+            if b & 1 != 0 {
+                product ^= a;
+            }
+            let carry = a & 0x80;
+            a <<= 1;
+            if carry != 0 {
+                a ^= 0x1b;
+            }
+            b >>= 1;
+        }
+        product
+    }
+
+    /// The inverse of a non-zero element, `a^254`.
+    fn inv(a: u8) -> u8 {
+        let mut result = 1;
+        let mut base = a;
+        let mut exp = 254;
+        while exp > 0 {
+            if exp & 1 != 0 {
+                result = mul(result, base);
+            }
+            base = mul(base, base);
+            exp >>= 1;
+        }
+        result
+    }
+
+    /// Splits the secret into `n` pieces evaluated at `1..=n`, any `threshold` of which recover
+    /// it.
+    pub(super) fn split(
+        secret: &[u8],
+        threshold: u8,
+        n: u8,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Vec<(u8, Zeroizing<Vec<u8>>)> {
+        let mut pieces: Vec<_> = (1..=n)
+            .map(|x| (x, Zeroizing::new(Vec::with_capacity(secret.len()))))
+            .collect();
+        let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize]);
+        for byte in secret {
+            coefficients[0] = *byte;
+            rng.fill_bytes(&mut coefficients[1..]);
+            for (x, piece) in pieces.iter_mut() {
+                // Horner's method, from the highest coefficient down.
+                let y = coefficients
+                    .iter()
+                    .rev()
+                    .fold(0, |acc, coefficient| mul(acc, *x) ^ coefficient);
+                piece.push(y);
+            }
+        }
+        pieces
+    }
+
+    /// Recovers the secret from pieces evaluated at distinct non-zero points, by Lagrange
+    /// interpolation at zero.
+    pub(super) fn combine(pieces: &[(u8, Zeroizing<Vec<u8>>)]) -> Zeroizing<Vec<u8>> {
+        let len = pieces.first().map_or(0, |(_, piece)| piece.len());
+        let mut secret = Zeroizing::new(vec![0u8; len]);
+        for (i, (xi, piece)) in pieces.iter().enumerate() {
+            let basis = pieces
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .fold(1, |acc, (_, (xj, _))| mul(acc, mul(*xj, inv(xj ^ xi))));
+            for (byte, y) in secret.iter_mut().zip(piece.iter()) {
+                *byte ^= mul(*y, basis);
+            }
+        }
+        secret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> PersistentNodeData {
+        PersistentNodeData {
+            epoch: 3,
+            private_share: Zeroizing::new(k256::Scalar::from(42u64)),
+            public_key: k256::AffinePoint::GENERATOR,
+        }
+    }
+
+    #[test]
+    fn test_shamir_any_threshold_recovers() {
+        let secret = b"not so secret".to_vec();
+        let pieces = shamir::split(&secret, 3, 5, &mut rand::thread_rng());
+        assert_eq!(*shamir::combine(&pieces[..3]), secret);
+        assert_eq!(*shamir::combine(&pieces[2..]), secret);
+        assert_ne!(*shamir::combine(&pieces[..2]), secret);
+    }
+
+    #[test]
+    fn test_backup_roundtrip() {
+        let account_id: AccountId = "p-0.testnet".parse().unwrap();
+        let keys: Vec<_> = (0..3).map(|_| hpke::generate()).collect();
+        let pks: Vec<_> = keys.iter().map(|(_, pk)| pk.clone()).collect();
+        let backup = Backup::seal(&account_id, &data(), &pks, 2, &mut rand::thread_rng()).unwrap();
+
+        let restored = backup
+            .open(&account_id, &[keys[0].0.clone(), keys[2].0.clone()])
+            .unwrap();
+        assert_eq!(*restored.private_share, *data().private_share);
+        assert_eq!(restored.epoch, 3);
+
+        assert!(matches!(
+            backup.open(&account_id, &[keys[1].0.clone()]),
+            Err(BackupError::NotEnoughShares(1, 2))
+        ));
+        let other: AccountId = "p-1.testnet".parse().unwrap();
+        assert!(matches!(
+            backup.open(&other, &[keys[0].0.clone(), keys[1].0.clone()]),
+            Err(BackupError::AccountMismatch(_))
+        ));
+        assert!(matches!(
+            Backup::seal(&account_id, &data(), &pks, 4, &mut rand::thread_rng()),
+            Err(BackupError::BadThreshold(3))
+        ));
+    }
+}
@@ -1,16 +1,31 @@
-use crate::config::{Config, LocalConfig, NetworkConfig, OverrideConfig};
+use crate::attestation;
+use crate::backup;
+use crate::cluster;
+use crate::config::{self, Config, ConfigWatcher, LocalConfig, NetworkConfig, OverrideConfig};
 use crate::gcp::GcpService;
+use crate::mesh::quic;
+use crate::protocol::reputation::Reputation;
+use crate::protocol::{reconciler, scheduler, sweeper, validation};
 use crate::protocol::{MpcSignProtocol, SignQueue};
+use crate::snapshot::Snapshot;
+use crate::storage::presignature_storage::LockPresignatureNodeStorageBox;
+use crate::storage::sign_request_storage::{self, LockSignRequestNodeStorageBox};
 use crate::storage::triple_storage::LockTripleNodeStorageBox;
-use crate::{indexer, storage, web};
+use crate::web::health;
+use crate::{
+    audit, import, indexer, inspect, journal, redact, shutdown, slo, storage, telemetry, web,
+};
 use clap::Parser;
 use local_ip_address::local_ip;
 use near_account_id::AccountId;
 use near_crypto::{InMemorySigner, SecretKey};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, RwLock};
 use tracing_stackdriver::layer as stackdriver_layer;
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
 use url::Url;
 
 use mpc_keys::hpke;
@@ -37,6 +52,17 @@ pub enum Cli {
         /// The web port for this server
         #[arg(long, env("MPC_WEB_PORT"))]
         web_port: u16,
+        /// The address the admin introspection server listens on. Only reachable from this host
+        /// by default, as its read endpoints are not authenticated.
+        #[arg(long, env("MPC_ADMIN_HOST"), default_value = "127.0.0.1")]
+        admin_host: IpAddr,
+        /// The port for the admin introspection server. The server is disabled if not set.
+        #[arg(long, env("MPC_ADMIN_PORT"))]
+        admin_port: Option<u16>,
+        /// Comma-separated `operator:token` pairs authorizing the control endpoints of the admin
+        /// server, e.g. `alice:s3cr3t,bob:t0k3n`. The control endpoints are disabled if not set.
+        #[arg(long, env("MPC_ADMIN_TOKENS"))]
+        admin_tokens: Option<String>,
         // TODO: need to add in CipherPK type for parsing.
         /// The cipher public key used to encrypt messages between nodes.
         #[arg(long, env("MPC_CIPHER_PK"))]
@@ -47,6 +73,21 @@ pub enum Cli {
         /// The secret key used to sign messages to be sent between nodes.
         #[arg(long, env("MPC_SIGN_SK"))]
         sign_sk: Option<SecretKey>,
+        /// Window in milliseconds in which duplicate incoming messages are dropped.
+        #[arg(long, env("MPC_MESSAGE_DEDUP_WINDOW_MS"), default_value("300000"))]
+        message_dedup_window_ms: u64,
+        /// Number of bytes per second that can be sent to a single participant. Unlimited if not
+        /// set.
+        #[arg(long, env("MPC_PEER_SEND_RATE_LIMIT"))]
+        peer_send_rate_limit: Option<u64>,
+        /// Number of messages that can wait to be delivered to a single participant before the
+        /// oldest ones get dropped. Unlimited if not set.
+        #[arg(long, env("MPC_PEER_OUTBOX_LIMIT"))]
+        peer_outbox_limit: Option<usize>,
+        /// Age in seconds after which unspent presignatures are discarded and regenerated. Zero
+        /// keeps them until they are used.
+        #[arg(long, env("MPC_PRESIGNATURE_MAX_AGE_SECS"), default_value("86400"))]
+        presignature_max_age_secs: u64,
         /// NEAR Lake Indexer options
         #[clap(flatten)]
         indexer_options: indexer::Options,
@@ -56,12 +97,95 @@ pub enum Cli {
         /// Storage options
         #[clap(flatten)]
         storage_options: storage::Options,
+        /// Poke scheduler options
+        #[clap(flatten)]
+        scheduler_options: scheduler::Options,
+        /// TEE attestation options
+        #[clap(flatten)]
+        attestation_options: attestation::Options,
+        /// OpenTelemetry export options
+        #[clap(flatten)]
+        telemetry_options: telemetry::Options,
+        /// Log redaction options
+        #[clap(flatten)]
+        redact_options: redact::Options,
+        /// Signature latency SLO options
+        #[clap(flatten)]
+        slo_options: slo::Options,
+        /// Sign request validation options
+        #[clap(flatten)]
+        validation_options: validation::Options,
+        /// Peer transport options
+        #[clap(flatten)]
+        transport_options: quic::Options,
+        /// Readiness probe options
+        #[clap(flatten)]
+        readiness_options: health::Options,
         /// The set of configurations that we will use to override contract configurations.
         #[arg(long, env("MPC_OVERRIDE_CONFIG"), value_parser = clap::value_parser!(OverrideConfig))]
         override_config: Option<OverrideConfig>,
+        /// Path of a JSON file of configurations overriding contract configurations, such as the
+        /// protocol timeouts. Entries of `--override-config` take precedence over the file.
+        #[arg(long, env("MPC_OVERRIDE_CONFIG_FILE"))]
+        override_config_file: Option<PathBuf>,
+        /// Path of a YAML file of settings that can be changed while the node runs, such as the
+        /// stockpile sizes, the pacing of triple generation and the log level. The file is
+        /// watched and its changes are applied without restarting the node.
+        #[arg(long, env("MPC_CONFIG_FILE"))]
+        config_file: Option<PathBuf>,
         /// referer header for mainnet whitelist
         #[arg(long, env("MPC_CLIENT_HEADER_REFERER"), default_value(None))]
         client_header_referer: Option<String>,
+        /// Path of the append-only journal of protocol events. The journal is disabled if not set.
+        #[arg(long, env("MPC_JOURNAL_PATH"))]
+        journal_path: Option<PathBuf>,
+        /// Path of the append-only audit trail tying every published signature to the
+        /// presignature and triples it was generated from. The trail is disabled if not set.
+        #[arg(long, env("MPC_AUDIT_PATH"))]
+        audit_path: Option<PathBuf>,
+        /// Path of the snapshot of the pools, the outbox and the sign queue written on SIGTERM
+        /// and restored on startup. Snapshots are disabled if not set.
+        #[arg(long, env("MPC_SNAPSHOT_PATH"))]
+        snapshot_path: Option<PathBuf>,
+        /// Seconds the in-flight signature protocols are given to complete on SIGTERM before the
+        /// node exits. No new protocol is started in the meantime.
+        #[arg(long, env("MPC_SHUTDOWN_GRACE_PERIOD_SECS"), default_value("30"))]
+        shutdown_grace_period_secs: u64,
+    },
+    /// Prints the entries of a protocol journal, optionally filtered.
+    Journal {
+        /// Path of the journal to read.
+        #[arg(long)]
+        path: PathBuf,
+        #[clap(flatten)]
+        filter: journal::Filter,
+    },
+    /// Inspects the state of a running node through its admin server.
+    State {
+        /// Address of the admin server of the node.
+        #[arg(long, env("MPC_ADMIN_URL"))]
+        admin_url: Url,
+        /// Print JSON instead of tables.
+        #[arg(long)]
+        json: bool,
+        #[command(subcommand)]
+        command: inspect::Command,
+    },
+    /// Exports or restores the key share of the node, see [`backup`].
+    Backup {
+        #[command(subcommand)]
+        command: backup::Command,
+    },
+    /// Imports a key share generated outside of this network into the secret storage of the
+    /// node, see [`import`].
+    Import {
+        #[clap(flatten)]
+        options: import::Options,
+    },
+    /// Generates the configuration of a local cluster of nodes, see [`cluster`].
+    Cluster {
+        #[command(subcommand)]
+        command: cluster::Command,
     },
 }
 
@@ -74,14 +198,35 @@ impl Cli {
                 mpc_contract_id,
                 account_sk,
                 web_port,
+                admin_host,
+                admin_port,
+                admin_tokens,
                 cipher_pk,
                 cipher_sk,
                 sign_sk,
+                message_dedup_window_ms,
+                peer_send_rate_limit,
+                peer_outbox_limit,
+                presignature_max_age_secs,
                 indexer_options,
                 my_address,
                 storage_options,
+                scheduler_options,
+                attestation_options,
+                telemetry_options,
+                redact_options,
+                slo_options,
+                validation_options,
+                transport_options,
+                readiness_options,
                 override_config,
+                override_config_file,
+                config_file,
                 client_header_referer,
+                journal_path,
+                audit_path,
+                snapshot_path,
+                shutdown_grace_period_secs,
             } => {
                 let mut args = vec![
                     "start".to_string(),
@@ -99,10 +244,39 @@ impl Cli {
                     cipher_pk,
                     "--cipher-sk".to_string(),
                     cipher_sk,
+                    "--message-dedup-window-ms".to_string(),
+                    message_dedup_window_ms.to_string(),
+                    "--presignature-max-age-secs".to_string(),
+                    presignature_max_age_secs.to_string(),
+                    "--shutdown-grace-period-secs".to_string(),
+                    shutdown_grace_period_secs.to_string(),
                 ];
+                if let Some(admin_port) = admin_port {
+                    args.extend([
+                        "--admin-host".to_string(),
+                        admin_host.to_string(),
+                        "--admin-port".to_string(),
+                        admin_port.to_string(),
+                    ]);
+                }
+                if let Some(admin_tokens) = admin_tokens {
+                    args.extend(["--admin-tokens".to_string(), admin_tokens]);
+                }
                 if let Some(sign_sk) = sign_sk {
                     args.extend(["--sign-sk".to_string(), sign_sk.to_string()]);
                 }
+                if let Some(peer_send_rate_limit) = peer_send_rate_limit {
+                    args.extend([
+                        "--peer-send-rate-limit".to_string(),
+                        peer_send_rate_limit.to_string(),
+                    ]);
+                }
+                if let Some(peer_outbox_limit) = peer_outbox_limit {
+                    args.extend([
+                        "--peer-outbox-limit".to_string(),
+                        peer_outbox_limit.to_string(),
+                    ]);
+                }
                 if let Some(my_address) = my_address {
                     args.extend(["--my-address".to_string(), my_address.to_string()]);
                 }
@@ -112,13 +286,88 @@ impl Cli {
                         serde_json::to_string(&override_config).unwrap(),
                     ]);
                 }
+                if let Some(override_config_file) = override_config_file {
+                    args.extend([
+                        "--override-config-file".to_string(),
+                        override_config_file.display().to_string(),
+                    ]);
+                }
+                if let Some(config_file) = config_file {
+                    args.extend([
+                        "--config-file".to_string(),
+                        config_file.display().to_string(),
+                    ]);
+                }
 
                 if let Some(client_header_referer) = client_header_referer {
                     args.extend(["--client-header-referer".to_string(), client_header_referer]);
                 }
+                if let Some(journal_path) = journal_path {
+                    args.extend([
+                        "--journal-path".to_string(),
+                        journal_path.display().to_string(),
+                    ]);
+                }
+                if let Some(audit_path) = audit_path {
+                    args.extend(["--audit-path".to_string(), audit_path.display().to_string()]);
+                }
+                if let Some(snapshot_path) = snapshot_path {
+                    args.extend([
+                        "--snapshot-path".to_string(),
+                        snapshot_path.display().to_string(),
+                    ]);
+                }
 
                 args.extend(indexer_options.into_str_args());
                 args.extend(storage_options.into_str_args());
+                args.extend(scheduler_options.into_str_args());
+                args.extend(attestation_options.into_str_args());
+                args.extend(telemetry_options.into_str_args());
+                args.extend(redact_options.into_str_args());
+                args.extend(slo_options.into_str_args());
+                args.extend(validation_options.into_str_args());
+                args.extend(transport_options.into_str_args());
+                args.extend(readiness_options.into_str_args());
+                args
+            }
+            Cli::Journal { path, filter } => {
+                let mut args = vec![
+                    "journal".to_string(),
+                    "--path".to_string(),
+                    path.display().to_string(),
+                ];
+                args.extend(filter.into_str_args());
+                args
+            }
+            Cli::State {
+                admin_url,
+                json,
+                command,
+            } => {
+                let mut args = vec![
+                    "state".to_string(),
+                    "--admin-url".to_string(),
+                    admin_url.to_string(),
+                ];
+                if json {
+                    args.push("--json".to_string());
+                }
+                args.extend(command.into_str_args());
+                args
+            }
+            Cli::Backup { command } => {
+                let mut args = vec!["backup".to_string()];
+                args.extend(command.into_str_args());
+                args
+            }
+            Cli::Import { options } => {
+                let mut args = vec!["import".to_string()];
+                args.extend(options.into_str_args());
+                args
+            }
+            Cli::Cluster { command } => {
+                let mut args = vec!["cluster".to_string()];
+                args.extend(command.into_str_args());
                 args
             }
         }
@@ -145,7 +394,9 @@ fn is_running_on_gcp() -> bool {
 
 pub fn run(cmd: Cli) -> anyhow::Result<()> {
     // Install global collector configured based on RUST_LOG env var.
-    let base_subscriber = Registry::default().with(EnvFilter::from_default_env());
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
+    config::install_log_filter(filter_handle);
+    let base_subscriber = Registry::default().with(filter).with(telemetry::layer());
 
     let subscriber = if is_running_on_gcp() {
         let stackdriver = stackdriver_layer().with_writer(std::io::stderr);
@@ -163,40 +414,146 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
         Cli::Start {
             near_rpc,
             web_port,
+            admin_host,
+            admin_port,
+            admin_tokens,
             mpc_contract_id,
             account_id,
             account_sk,
             cipher_pk,
             cipher_sk,
             sign_sk,
+            message_dedup_window_ms,
+            peer_send_rate_limit,
+            peer_outbox_limit,
+            presignature_max_age_secs,
             indexer_options,
             my_address,
             storage_options,
+            scheduler_options,
+            attestation_options,
+            telemetry_options,
+            redact_options,
+            slo_options,
+            validation_options,
+            transport_options,
+            readiness_options,
             override_config,
+            override_config_file,
+            config_file,
             client_header_referer,
+            journal_path,
+            audit_path,
+            snapshot_path,
+            shutdown_grace_period_secs,
         } => {
+            redact::init(&redact_options);
+            if let Some(journal_path) = &journal_path {
+                journal::init(journal_path)?;
+                tracing::info!(?journal_path, "protocol journal enabled");
+            }
+            if let Some(audit_path) = &audit_path {
+                audit::init(audit_path)?;
+                tracing::info!(?audit_path, "audit trail enabled");
+            }
+            #[cfg(feature = "fault-injection")]
+            if let Ok(rules) = std::env::var("MPC_FAULT_INJECTION") {
+                let rules: crate::fault::Rules = rules.parse()?;
+                tracing::warn!(?rules, "fault injection enabled");
+                crate::fault::install(Box::new(rules));
+            }
+            #[cfg(feature = "test-utils")]
+            if let Ok(seed) = std::env::var("MPC_RNG_SEED") {
+                let seed: u64 = seed.parse()?;
+                tracing::warn!(seed, "deterministic rng enabled");
+                crate::rng::install_seed(seed);
+            }
+            let override_config = match (override_config_file, override_config) {
+                (Some(path), over) => {
+                    let file = OverrideConfig::from_file(&path)?;
+                    tracing::info!(?path, "loaded override config file");
+                    match over {
+                        Some(over) => file.merged(&over),
+                        None => file,
+                    }
+                }
+                (None, over) => over.unwrap_or_default(),
+            };
             let sign_queue = Arc::new(RwLock::new(SignQueue::new()));
+            let reputation = Arc::new(RwLock::new(Reputation::new(&account_id)));
             let rt = tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
                 .build()?;
+            rt.block_on(async { telemetry::init(&telemetry_options, account_id.as_str()) })?;
+            rt.block_on(async { quic::init(&transport_options) })?;
+            slo::init(&slo_options, account_id.as_str(), reqwest::Client::new());
             let gcp_service =
                 rt.block_on(async { GcpService::init(&account_id, &storage_options).await })?;
+            let sign_request_storage: LockSignRequestNodeStorageBox = Arc::new(RwLock::new(
+                sign_request_storage::init(Some(&gcp_service), &account_id),
+            ));
+            // Queue the requests left unanswered by a previous run before indexing new ones.
+            match rt.block_on(sign_request_storage::recover(
+                &sign_request_storage,
+                &sign_queue,
+            )) {
+                Ok(recovered) => tracing::info!(recovered, "recovered sign requests"),
+                Err(err) => tracing::warn!(?err, "failed to recover sign requests"),
+            }
             let (indexer_handle, indexer) = indexer::run(
                 &indexer_options,
                 &mpc_contract_id,
                 &account_id,
                 &sign_queue,
+                &sign_request_storage,
                 &gcp_service,
                 &rt,
             )?;
 
-            let key_storage =
-                storage::secret_storage::init(Some(&gcp_service), &storage_options, &account_id);
+            let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(cipher_sk)?)?;
+            let key_storage = rt.block_on(storage::secret_storage::init(
+                Some(&gcp_service),
+                &storage_options,
+                &account_id,
+                &cipher_sk,
+            ));
             let triple_storage: LockTripleNodeStorageBox = Arc::new(RwLock::new(
                 storage::triple_storage::init(Some(&gcp_service), &account_id),
             ));
+            let presignature_storage: LockPresignatureNodeStorageBox = Arc::new(RwLock::new(
+                storage::presignature_storage::init(Some(&gcp_service), &account_id),
+            ));
+
+            let restored = snapshot_path.as_deref().and_then(|path| {
+                let restored = rt.block_on(async {
+                    let Some(snapshot) = Snapshot::read(path, &cipher_sk, &account_id)? else {
+                        return Ok(None);
+                    };
+                    snapshot
+                        .restore(
+                            &key_storage,
+                            &triple_storage,
+                            &presignature_storage,
+                            &sign_queue,
+                        )
+                        .await
+                });
+                restored.unwrap_or_else(|err| {
+                    tracing::warn!(?path, ?err, "failed to restore snapshot");
+                    None
+                })
+            });
 
             let sign_sk = sign_sk.unwrap_or_else(|| account_sk.clone());
+            let operators = web::admin::Operators::parse(admin_tokens.as_deref())?;
+            if attestation_options.attestation {
+                attestation::init(
+                    &attestation_options,
+                    sign_sk.public_key(),
+                    cipher_sk.public_key(),
+                )?;
+                tracing::info!("TEE attestation enabled");
+            }
             let my_address = my_address
                 .map(|mut addr| {
                     addr.set_port(Some(web_port)).unwrap();
@@ -218,7 +575,35 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
 
             tracing::info!(rpc_addr = rpc_client.rpc_addr(), "rpc client initialized");
             let signer = InMemorySigner::from_secret_key(account_id.clone(), account_sk);
-            let (protocol, protocol_state) = MpcSignProtocol::init(
+            let local_config = LocalConfig {
+                over: override_config,
+                scheduler: scheduler_options,
+                validation: validation_options,
+                presignature_max_age: (presignature_max_age_secs > 0)
+                    .then(|| Duration::from_secs(presignature_max_age_secs)),
+                network: NetworkConfig {
+                    cipher_pk: hpke::PublicKey::try_from_bytes(&hex::decode(cipher_pk)?)?,
+                    sign_sk: sign_sk.clone(),
+                    message_dedup_window: Duration::from_millis(message_dedup_window_ms),
+                    peer_send_rate_limit,
+                    peer_outbox_limit,
+                },
+            };
+            let (config_watcher, local_config) = match config_file {
+                Some(path) => {
+                    tracing::info!(?path, "loading config file");
+                    let (watcher, local_config) = ConfigWatcher::new(path, local_config)?;
+                    (Some(watcher), local_config)
+                }
+                None => (None, local_config),
+            };
+            let snapshot_task = snapshot_path.map(|path| shutdown::SnapshotTask {
+                path,
+                cipher_pk: cipher_sk.public_key(),
+                account_id: account_id.clone(),
+                sign_queue: sign_queue.clone(),
+            });
+            let (mut protocol, protocol_state) = MpcSignProtocol::init(
                 my_address,
                 mpc_contract_id,
                 account_id,
@@ -226,24 +611,73 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
                 signer,
                 receiver,
                 sign_queue,
+                indexer.clone(),
+                reputation.clone(),
                 key_storage,
                 triple_storage,
-                Config::new(LocalConfig {
-                    over: override_config.unwrap_or_else(Default::default),
-                    network: NetworkConfig {
-                        cipher_pk: hpke::PublicKey::try_from_bytes(&hex::decode(cipher_pk)?)?,
-                        sign_sk,
-                    },
-                }),
+                presignature_storage,
+                sign_request_storage.clone(),
+                Config::new(local_config),
+                config_watcher,
             );
+            if let Some((epoch, outbox)) = restored {
+                protocol.restore_outbox(epoch, outbox);
+            }
+            let controller = protocol.controller();
 
             rt.block_on(async {
                 tracing::info!("protocol initialized");
                 let protocol_handle = tokio::spawn(async move { protocol.run().await });
                 tracing::info!("protocol thread spawned");
-                let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(cipher_sk)?)?;
+                tokio::spawn(sweeper::run(protocol_state.clone()));
+                tokio::spawn(sign_request_storage::run_pruner(
+                    sign_request_storage.clone(),
+                ));
+                tokio::spawn(reconciler::run(protocol_state.clone()));
+                let shutdown_state = protocol_state.clone();
+                tokio::spawn(async move {
+                    shutdown::on_terminate(
+                        Duration::from_secs(shutdown_grace_period_secs),
+                        shutdown_state,
+                        snapshot_task,
+                    )
+                    .await
+                });
+                tracing::info!(
+                    shutdown_grace_period_secs,
+                    "graceful shutdown on SIGTERM enabled"
+                );
+                if let Some(admin_port) = admin_port {
+                    let protocol_state = protocol_state.clone();
+                    tokio::spawn(async move {
+                        web::admin::run(
+                            SocketAddr::new(admin_host, admin_port),
+                            protocol_state,
+                            reputation,
+                            sign_sk,
+                            controller,
+                            operators,
+                        )
+                        .await
+                    });
+                    tracing::info!("admin http server spawned");
+                }
+                tokio::spawn(quic::serve(
+                    sender.clone(),
+                    cipher_sk.clone(),
+                    protocol_state.clone(),
+                ));
                 let web_handle = tokio::spawn(async move {
-                    web::run(web_port, sender, cipher_sk, protocol_state, indexer).await
+                    web::run(
+                        web_port,
+                        sender,
+                        cipher_sk,
+                        protocol_state,
+                        indexer,
+                        sign_request_storage,
+                        readiness_options,
+                    )
+                    .await
                 });
                 tracing::info!("protocol http server spawned");
 
@@ -255,6 +689,25 @@ pub fn run(cmd: Cli) -> anyhow::Result<()> {
                 anyhow::Ok(())
             })?;
         }
+        Cli::Journal { path, filter } => {
+            journal::replay(&path, &filter, std::io::stdout().lock())?;
+        }
+        Cli::State {
+            admin_url,
+            json,
+            command,
+        } => {
+            inspect::run(&admin_url, command, json, std::io::stdout().lock())?;
+        }
+        Cli::Backup { command } => {
+            backup::run(command)?;
+        }
+        Cli::Import { options } => {
+            import::run(options)?;
+        }
+        Cli::Cluster { command } => {
+            cluster::run(command)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,80 @@
+//! Source of the time the protocol timeouts, the aging of generators and stockpiles, and the
+//! pacing of proposals are measured against. Production nodes use the monotonic clock of the
+//! operating system, which does not jump with the wall clock. Tests can install a [`MockClock`]
+//! into the managers and move time forward by hand instead of sleeping.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A source of monotonic time that can be shared between managers and their generators.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Time elapsed since `earlier`, zero if `earlier` is in the future of this clock.
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The monotonic clock of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The clock of the node, the monotonic clock of the operating system.
+pub fn monotonic() -> SharedClock {
+    Arc::new(MonotonicClock)
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub use mock::MockClock;
+
+#[cfg(any(test, feature = "test-utils"))]
+mod mock {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{Clock, SharedClock};
+
+    /// A clock that only moves when told to. Clones share the same time.
+    #[derive(Clone, Debug)]
+    pub struct MockClock {
+        now: Arc<Mutex<Instant>>,
+    }
+
+    impl Default for MockClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl MockClock {
+        /// A clock frozen at the current time.
+        pub fn new() -> Self {
+            Self {
+                now: Arc::new(Mutex::new(Instant::now())),
+            }
+        }
+
+        pub fn advance(&self, by: Duration) {
+            *self.now.lock().unwrap() += by;
+        }
+
+        pub fn shared(&self) -> SharedClock {
+            Arc::new(self.clone())
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Configuration of a local cluster of nodes, for integrators to try the node without going
+//! through the deployment scripts. `cluster gen-config` writes to a directory:
+//! - `node-<i>.env`: the fresh account, cipher and signing keys of every node along with the
+//!   ports and dependencies it is wired to,
+//! - `docker-compose.yml`: the nodes next to the datastore emulator, LocalStack and a NEAR Lake
+//!   Indexer running a localnet,
+//! - `init.json`: the arguments to initialize the contract with, listing the nodes as candidates,
+//! - `README.md`: the steps to bring the cluster up.
+//!
+//! Once the contract is initialized, the nodes run key generation among themselves and start
+//! stockpiling. The `local-cluster` example goes through key generation and a signature with
+//! in-process nodes instead, without any dependency.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use mpc_contract::primitives::CandidateInfo;
+use mpc_keys::hpke;
+use near_account_id::AccountId;
+use near_crypto::{KeyType, SecretKey};
+use serde_json::json;
+
+/// Port the nodes listen on inside their containers.
+const CONTAINER_WEB_PORT: u16 = 3000;
+const CONTAINER_ADMIN_PORT: u16 = 3001;
+
+const DATASTORE_IMAGE: &str = "gcr.io/google.com/cloudsdktool/google-cloud-cli:464.0.0-emulators";
+const LOCALSTACK_IMAGE: &str = "localstack/localstack:3.5.0";
+const LAKE_INDEXER_IMAGE: &str = "ghcr.io/near/near-lake-indexer:node-2.3.0";
+const LAKE_INDEXER_RPC_PORT: u16 = 3030;
+const S3_BUCKET: &str = "near-lake-custom";
+const S3_REGION: &str = "us-east-1";
+const GCP_PROJECT_ID: &str = "mpc-local";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClusterError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("threshold must be between 2 and the {0} nodes")]
+    BadThreshold(usize),
+    #[error("invalid account id: {0}")]
+    BadAccountId(String),
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Command {
+    /// Writes the configuration of a local cluster of nodes to a directory.
+    GenConfig {
+        /// Number of nodes in the cluster.
+        #[arg(long, default_value("3"))]
+        nodes: usize,
+        /// Number of nodes required to sign.
+        #[arg(long, default_value("2"))]
+        threshold: usize,
+        /// Directory the configuration is written to.
+        #[arg(long)]
+        output: PathBuf,
+        /// Image the nodes are run from.
+        #[arg(long, default_value("near/mpc-node:latest"))]
+        image: String,
+        /// Account the contract is deployed to. The node accounts are created under its parent.
+        #[arg(long, default_value("signer.test.near"))]
+        mpc_contract_id: AccountId,
+        /// Port of the first node on the host. The web and admin ports of node `i` are
+        /// `base_port + 2i` and `base_port + 2i + 1`.
+        #[arg(long, default_value("3000"))]
+        base_port: u16,
+    },
+}
+
+impl Command {
+    pub fn into_str_args(self) -> Vec<String> {
+        match self {
+            Command::GenConfig {
+                nodes,
+                threshold,
+                output,
+                image,
+                mpc_contract_id,
+                base_port,
+            } => vec![
+                "gen-config".to_string(),
+                "--nodes".to_string(),
+                nodes.to_string(),
+                "--threshold".to_string(),
+                threshold.to_string(),
+                "--output".to_string(),
+                output.display().to_string(),
+                "--image".to_string(),
+                image,
+                "--mpc-contract-id".to_string(),
+                mpc_contract_id.to_string(),
+                "--base-port".to_string(),
+                base_port.to_string(),
+            ],
+        }
+    }
+}
+
+pub fn run(command: Command) -> Result<(), ClusterError> {
+    match command {
+        Command::GenConfig {
+            nodes,
+            threshold,
+            output,
+            image,
+            mpc_contract_id,
+            base_port,
+        } => {
+            let cluster = Cluster::generate(nodes, threshold, &mpc_contract_id)?;
+            cluster.write(&output, &image, base_port)?;
+            println!(
+                "wrote the configuration of {nodes} nodes with threshold {threshold} to {}",
+                output.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Keys and identity of a node of the cluster.
+#[derive(Clone)]
+pub struct ClusterNode {
+    pub account_id: AccountId,
+    pub account_sk: SecretKey,
+    pub cipher_sk: hpke::SecretKey,
+    pub cipher_pk: hpke::PublicKey,
+    pub sign_sk: SecretKey,
+    /// Address the other nodes reach this node at, within the docker network.
+    pub url: String,
+}
+
+pub struct Cluster {
+    pub threshold: usize,
+    pub mpc_contract_id: AccountId,
+    pub nodes: Vec<ClusterNode>,
+}
+
+impl Cluster {
+    /// Generates fresh keys for `n` nodes, named `node-<i>` under the parent of the contract.
+    pub fn generate(
+        n: usize,
+        threshold: usize,
+        mpc_contract_id: &AccountId,
+    ) -> Result<Self, ClusterError> {
+        if threshold < 2 || threshold > n {
+            return Err(ClusterError::BadThreshold(n));
+        }
+        let parent = mpc_contract_id
+            .get_parent_account_id()
+            .map_or_else(|| mpc_contract_id.to_string(), |parent| parent.to_string());
+        let nodes = (0..n)
+            .map(|i| {
+                let account_id = format!("node-{i}.{parent}");
+                let account_id = account_id
+                    .parse()
+                    .map_err(|_| ClusterError::BadAccountId(account_id))?;
+                let (cipher_sk, cipher_pk) = hpke::generate();
+                Ok(ClusterNode {
+                    account_id,
+                    account_sk: SecretKey::from_random(KeyType::ED25519),
+                    cipher_sk,
+                    cipher_pk,
+                    sign_sk: SecretKey::from_random(KeyType::ED25519),
+                    url: format!("http://node-{i}:{CONTAINER_WEB_PORT}"),
+                })
+            })
+            .collect::<Result<_, ClusterError>>()?;
+        Ok(Self {
+            threshold,
+            mpc_contract_id: mpc_contract_id.clone(),
+            nodes,
+        })
+    }
+
+    /// Arguments of the `init` call of the contract, with every node as a candidate.
+    pub fn init_args(&self) -> Result<serde_json::Value, ClusterError> {
+        let mut candidates = BTreeMap::new();
+        for node in &self.nodes {
+            let account_id: near_sdk::AccountId = node
+                .account_id
+                .as_str()
+                .parse()
+                .map_err(|_| ClusterError::BadAccountId(node.account_id.to_string()))?;
+            let sign_pk = serde_json::from_value(json!(node.sign_sk.public_key().to_string()))?;
+            candidates.insert(
+                account_id.clone(),
+                CandidateInfo {
+                    account_id,
+                    url: node.url.clone(),
+                    cipher_pk: node.cipher_pk.to_bytes(),
+                    sign_pk,
+                },
+            );
+        }
+        Ok(json!({
+            "threshold": self.threshold,
+            "candidates": candidates,
+        }))
+    }
+
+    /// Environment of a node, read by the `start` command.
+    pub fn env(&self, node: &ClusterNode) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "MPC_NEAR_RPC",
+                format!("http://lake-indexer:{LAKE_INDEXER_RPC_PORT}"),
+            ),
+            ("MPC_CONTRACT_ID", self.mpc_contract_id.to_string()),
+            ("MPC_ACCOUNT_ID", node.account_id.to_string()),
+            ("MPC_ACCOUNT_SK", node.account_sk.to_string()),
+            ("MPC_WEB_PORT", CONTAINER_WEB_PORT.to_string()),
+            ("MPC_ADMIN_PORT", CONTAINER_ADMIN_PORT.to_string()),
+            ("MPC_CIPHER_PK", hex::encode(node.cipher_pk.to_bytes())),
+            ("MPC_CIPHER_SK", hex::encode(node.cipher_sk.to_bytes())),
+            ("MPC_SIGN_SK", node.sign_sk.to_string()),
+            ("MPC_LOCAL_ADDRESS", node.url.clone()),
+            ("MPC_INDEXER_S3_BUCKET", S3_BUCKET.to_string()),
+            ("MPC_INDEXER_S3_REGION", S3_REGION.to_string()),
+            ("MPC_INDEXER_S3_URL", "http://localstack:4566".to_string()),
+            ("MPC_INDEXER_START_BLOCK_HEIGHT", "0".to_string()),
+            ("MPC_ENV", "local".to_string()),
+            ("MPC_GCP_PROJECT_ID", GCP_PROJECT_ID.to_string()),
+            (
+                "MPC_GCP_DATASTORE_URL",
+                "http://datastore:3000/".to_string(),
+            ),
+            ("MPC_SK_SHARE_LOCAL_PATH", "/data/sk-share".to_string()),
+            ("AWS_ACCESS_KEY_ID", "FAKE_LOCALSTACK_KEY_ID".to_string()),
+            (
+                "AWS_SECRET_ACCESS_KEY",
+                "FAKE_LOCALSTACK_ACCESS_KEY".to_string(),
+            ),
+            ("RUST_LOG", "mpc_node=INFO".to_string()),
+        ]
+    }
+
+    /// Topology of the cluster as a docker-compose file.
+    pub fn compose(&self, image: &str, base_port: u16) -> serde_json::Value {
+        let mut services = serde_json::Map::new();
+        services.insert(
+            "datastore".to_string(),
+            json!({
+                "image": DATASTORE_IMAGE,
+                "entrypoint": "gcloud",
+                "command": [
+                    "beta", "emulators", "datastore", "start",
+                    format!("--project={GCP_PROJECT_ID}"),
+                    "--host-port", "0.0.0.0:3000",
+                    "--no-store-on-disk", "--consistency=1.0",
+                ],
+            }),
+        );
+        services.insert(
+            "localstack".to_string(),
+            json!({
+                "image": LOCALSTACK_IMAGE,
+                "volumes": ["./localstack-init.sh:/etc/localstack/init/ready.d/init.sh"],
+            }),
+        );
+        services.insert(
+            "lake-indexer".to_string(),
+            json!({
+                "image": LAKE_INDEXER_IMAGE,
+                "environment": {
+                    "AWS_ACCESS_KEY_ID": "FAKE_LOCALSTACK_KEY_ID",
+                    "AWS_SECRET_ACCESS_KEY": "FAKE_LOCALSTACK_ACCESS_KEY",
+                },
+                "command": [
+                    "--endpoint", "http://localstack:4566",
+                    "--bucket", S3_BUCKET,
+                    "--region", S3_REGION,
+                    "--stream-while-syncing", "sync-from-latest",
+                ],
+                "ports": [format!("{LAKE_INDEXER_RPC_PORT}:{LAKE_INDEXER_RPC_PORT}")],
+                "depends_on": ["localstack"],
+            }),
+        );
+        for (i, _) in self.nodes.iter().enumerate() {
+            let web_port = base_port + 2 * i as u16;
+            services.insert(
+                format!("node-{i}"),
+                json!({
+                    "image": image,
+                    "command": ["start"],
+                    "env_file": [format!("node-{i}.env")],
+                    "ports": [
+                        format!("{web_port}:{CONTAINER_WEB_PORT}"),
+                        format!("{}:{CONTAINER_ADMIN_PORT}", web_port + 1),
+                    ],
+                    "depends_on": ["datastore", "localstack", "lake-indexer"],
+                }),
+            );
+        }
+        json!({ "services": services })
+    }
+
+    /// Writes the configuration of the cluster to `dir`, see the module documentation.
+    pub fn write(&self, dir: &Path, image: &str, base_port: u16) -> Result<(), ClusterError> {
+        std::fs::create_dir_all(dir)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let env = self
+                .env(node)
+                .into_iter()
+                .map(|(key, value)| format!("{key}={value}\n"))
+                .collect::<String>();
+            std::fs::write(dir.join(format!("node-{i}.env")), env)?;
+        }
+        std::fs::write(
+            dir.join("docker-compose.yml"),
+            serde_yaml::to_string(&self.compose(image, base_port))?,
+        )?;
+        std::fs::write(
+            dir.join("localstack-init.sh"),
+            format!("#!/bin/sh\nawslocal s3 mb s3://{S3_BUCKET}\n"),
+        )?;
+        std::fs::write(
+            dir.join("init.json"),
+            serde_json::to_string_pretty(&self.init_args()?)?,
+        )?;
+        std::fs::write(dir.join("README.md"), self.readme(base_port))?;
+        Ok(())
+    }
+
+    fn readme(&self, base_port: u16) -> String {
+        let mut accounts = String::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let web_port = base_port + 2 * i as u16;
+            accounts.push_str(&format!(
+                "| `{}` | `{}` | {web_port} | {} |\n",
+                node.account_id,
+                node.account_sk.public_key(),
+                web_port + 1,
+            ));
+        }
+        format!(
+            "# Local cluster\n\n\
+             {nodes} nodes signing with a threshold of {threshold}, for the contract \
+             `{contract}`.\n\n\
+             | Account | Access key | Web port | Admin port |\n\
+             | --- | --- | --- | --- |\n\
+             {accounts}\n\
+             1. Start the localnet and its dependencies: \
+             `docker compose up -d datastore localstack lake-indexer`.\n\
+             2. Create the accounts above with their access keys on the localnet, along with \
+             `{contract}`, and deploy the contract to it.\n\
+             3. Initialize the contract: \
+             `near call {contract} init --argsFile init.json --accountId {contract}`.\n\
+             4. Start the nodes: `docker compose up -d`. They run key generation once all of \
+             them are up, which can be followed on the admin servers.\n",
+            nodes = self.nodes.len(),
+            threshold = self.threshold,
+            contract = self.mpc_contract_id,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_cluster() {
+        let contract: AccountId = "signer.test.near".parse().unwrap();
+        assert!(Cluster::generate(3, 1, &contract).is_err());
+        assert!(Cluster::generate(3, 4, &contract).is_err());
+
+        let cluster = Cluster::generate(3, 2, &contract).unwrap();
+        assert_eq!(cluster.nodes[2].account_id.as_str(), "node-2.test.near");
+        let init = cluster.init_args().unwrap();
+        assert_eq!(init["threshold"], 2);
+        assert_eq!(init["candidates"].as_object().unwrap().len(), 3);
+
+        let compose = cluster.compose("near/mpc-node:latest", 4000);
+        assert_eq!(compose["services"]["node-1"]["ports"][0], "4002:3000");
+        assert_eq!(compose["services"]["node-1"]["env_file"][0], "node-1.env");
+        let env = cluster.env(&cluster.nodes[1]);
+        assert!(env.contains(&("MPC_ACCOUNT_ID", "node-1.test.near".to_string())));
+    }
+}
@@ -1,11 +1,17 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 use mpc_contract::config::ProtocolConfig;
 use mpc_keys::hpke;
 use near_account_id::AccountId;
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::protocol::{scheduler, validation};
 
 /// The contract's config is a dynamic representation of all configurations possible.
 pub type ContractConfig = HashMap<String, Value>;
@@ -67,12 +73,22 @@ impl Config {
 pub struct LocalConfig {
     pub network: NetworkConfig,
     pub over: OverrideConfig,
+    pub scheduler: scheduler::Options,
+    pub validation: validation::Options,
+    /// Age after which unspent presignatures are discarded and regenerated, if any.
+    pub presignature_max_age: Option<Duration>,
 }
 
 #[derive(Clone, Debug)]
 pub struct NetworkConfig {
     pub sign_sk: near_crypto::SecretKey,
     pub cipher_pk: hpke::PublicKey,
+    /// Window in which a message identical to one already received is dropped as a duplicate.
+    pub message_dedup_window: Duration,
+    /// Number of bytes per second that can be sent to a single participant, if limited.
+    pub peer_send_rate_limit: Option<u64>,
+    /// Number of messages that can wait to be delivered to a single participant, if limited.
+    pub peer_outbox_limit: Option<usize>,
 }
 
 impl Default for NetworkConfig {
@@ -83,6 +99,9 @@ impl Default for NetworkConfig {
                 "test-entropy",
             ),
             cipher_pk: hpke::PublicKey::from_bytes(&[0; 32]),
+            message_dedup_window: Duration::from_secs(5 * 60),
+            peer_send_rate_limit: None,
+            peer_outbox_limit: None,
         }
     }
 }
@@ -112,6 +131,18 @@ impl OverrideConfig {
     pub fn new(entries: Value) -> Self {
         Self { entries }
     }
+
+    /// Reads the overrides from a JSON file.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(contents.parse()?)
+    }
+
+    /// Overrides the entries of this config with the ones of `other`.
+    pub fn merged(mut self, other: &OverrideConfig) -> Self {
+        merge(&mut self.entries, &other.entries);
+        self
+    }
 }
 
 impl FromStr for OverrideConfig {
@@ -122,6 +153,186 @@ impl FromStr for OverrideConfig {
     }
 }
 
+/// Settings of the file given with `--config-file`, written in YAML (or JSON). The file is watched
+/// while the node runs, and changes to it are applied without restarting the node. Only settings
+/// that are safe to change at runtime can be set here, everything else stays on the command line.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    /// Overrides of the contract configurations, such as the stockpile sizes and the protocol
+    /// timeouts. Entries of `--override-config` take precedence over these.
+    pub protocol: Option<Value>,
+    pub scheduler: SchedulerOverrides,
+    /// Age in seconds after which unspent presignatures are discarded. Zero keeps them.
+    pub presignature_max_age_secs: Option<u64>,
+    /// Prefixes of the derivation paths refused signatures, replacing the ones given with
+    /// `--deny-derivation-path`.
+    pub deny_derivation_paths: Option<Vec<String>>,
+    /// Filter directives for the logs, in the format of `RUST_LOG`.
+    pub log_level: Option<String>,
+}
+
+/// Overrides of the [`scheduler::Options`] given on the command line.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerOverrides {
+    pub poke_message_budget: Option<usize>,
+    pub poke_weight_signature: Option<usize>,
+    pub poke_weight_presignature: Option<usize>,
+    pub poke_weight_triple: Option<usize>,
+    pub triple_start_rate: Option<f64>,
+    pub triple_start_burst: Option<usize>,
+    pub max_triples_in_flight: Option<usize>,
+    pub liveness_margin: Option<usize>,
+    pub triple_mine_ratio: Option<f64>,
+    pub min_triples_to_join: Option<usize>,
+    pub max_generators_to_join: Option<usize>,
+    pub shed_max_rss_mb: Option<u64>,
+    pub shed_max_pool_size: Option<usize>,
+    pub max_presignature_generators: Option<usize>,
+    pub max_presignatures_held: Option<usize>,
+}
+
+impl SchedulerOverrides {
+    fn apply(&self, base: &scheduler::Options) -> scheduler::Options {
+        scheduler::Options {
+            poke_message_budget: self.poke_message_budget.unwrap_or(base.poke_message_budget),
+            poke_weight_signature: self
+                .poke_weight_signature
+                .unwrap_or(base.poke_weight_signature),
+            poke_weight_presignature: self
+                .poke_weight_presignature
+                .unwrap_or(base.poke_weight_presignature),
+            poke_weight_triple: self.poke_weight_triple.unwrap_or(base.poke_weight_triple),
+            triple_start_rate: self.triple_start_rate.unwrap_or(base.triple_start_rate),
+            triple_start_burst: self.triple_start_burst.unwrap_or(base.triple_start_burst),
+            max_triples_in_flight: self.max_triples_in_flight.or(base.max_triples_in_flight),
+            liveness_margin: self.liveness_margin.unwrap_or(base.liveness_margin),
+            triple_mine_ratio: self.triple_mine_ratio.or(base.triple_mine_ratio),
+            min_triples_to_join: self.min_triples_to_join.or(base.min_triples_to_join),
+            max_generators_to_join: self.max_generators_to_join.or(base.max_generators_to_join),
+            shed_max_rss_mb: self.shed_max_rss_mb.or(base.shed_max_rss_mb),
+            shed_max_pool_size: self.shed_max_pool_size.or(base.shed_max_pool_size),
+            max_presignature_generators: self
+                .max_presignature_generators
+                .or(base.max_presignature_generators),
+            max_presignatures_held: self.max_presignatures_held.or(base.max_presignatures_held),
+        }
+    }
+}
+
+impl FileConfig {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&contents)?)
+    }
+
+    /// Applies the settings of the file on top of the local config the node was started with.
+    pub fn apply(&self, base: &LocalConfig) -> LocalConfig {
+        let mut local = base.clone();
+        if let Some(protocol) = &self.protocol {
+            local.over = OverrideConfig::new(protocol.clone()).merged(&base.over);
+        }
+        local.scheduler = self.scheduler.apply(&base.scheduler);
+        if let Some(secs) = self.presignature_max_age_secs {
+            local.presignature_max_age = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(prefixes) = &self.deny_derivation_paths {
+            local.validation.deny_derivation_path = prefixes.clone();
+        }
+        local
+    }
+}
+
+/// Keeps the local config in line with the file given with `--config-file`.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    /// Local config set on the command line, which the settings of the file are applied to.
+    base: LocalConfig,
+    modified: Option<SystemTime>,
+    current: FileConfig,
+}
+
+impl ConfigWatcher {
+    /// Reads the file for the first time, returning the watcher along with the local config to
+    /// start the node with. Unlike later changes, an invalid file is an error here.
+    pub fn new(path: PathBuf, base: LocalConfig) -> anyhow::Result<(Self, LocalConfig)> {
+        let modified = std::fs::metadata(&path)?.modified().ok();
+        let current = FileConfig::from_file(&path)?;
+        if let Some(log_level) = &current.log_level {
+            set_log_level(Some(log_level))?;
+        }
+        let local = current.apply(&base);
+        let watcher = Self {
+            path,
+            base,
+            modified,
+            current,
+        };
+        Ok((watcher, local))
+    }
+
+    /// Rereads the file if it was modified since it was last read, and returns the local config
+    /// to use from now on if the settings in it changed. A file that cannot be read or applied is
+    /// reported and ignored, keeping the settings that were in use.
+    pub fn poll(&mut self) -> Option<LocalConfig> {
+        let modified = match std::fs::metadata(&self.path).and_then(|meta| meta.modified()) {
+            Ok(modified) => Some(modified),
+            Err(err) => {
+                tracing::warn!(path = ?self.path, ?err, "unable to read the config file");
+                return None;
+            }
+        };
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let file = match FileConfig::from_file(&self.path) {
+            Ok(file) => file,
+            Err(err) => {
+                tracing::warn!(path = ?self.path, ?err, "invalid config file, keeping the current settings");
+                return None;
+            }
+        };
+        if file == self.current {
+            return None;
+        }
+        if file.log_level != self.current.log_level {
+            if let Err(err) = set_log_level(file.log_level.as_deref()) {
+                tracing::warn!(?err, "invalid log level, keeping the current settings");
+                return None;
+            }
+        }
+        tracing::info!(path = ?self.path, config = ?file, "config file changed");
+        let local = file.apply(&self.base);
+        self.current = file;
+        Some(local)
+    }
+}
+
+static LOG_FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Registers the handle through which the log filter installed at startup can be replaced.
+pub fn install_log_filter(handle: reload::Handle<EnvFilter, Registry>) {
+    if LOG_FILTER.set(handle).is_err() {
+        tracing::warn!("log filter is already installed");
+    }
+}
+
+/// Replaces the log filter with the given directives, or with the ones of `RUST_LOG` if none.
+fn set_log_level(directives: Option<&str>) -> anyhow::Result<()> {
+    let filter = match directives {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => EnvFilter::from_default_env(),
+    };
+    if let Some(handle) = LOG_FILTER.get() {
+        handle.reload(filter)?;
+    }
+    Ok(())
+}
+
 pub fn merge(base: &mut Value, new: &Value) {
     match (base, new) {
         (base @ &mut Value::Object(_), Value::Object(new)) => {
@@ -137,9 +348,11 @@ pub fn merge(base: &mut Value, new: &Value) {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use serde::Deserialize;
 
-    use super::merge;
+    use super::{merge, FileConfig, LocalConfig, OverrideConfig};
 
     #[test]
     fn test_merge() {
@@ -177,4 +390,47 @@ mod tests {
         let base: Base = serde_json::from_value(base).unwrap();
         dbg!(base);
     }
+
+    #[test]
+    fn test_file_config() {
+        let file: FileConfig = serde_yaml::from_str(
+            r#"
+protocol:
+  presignature:
+    min_presignatures: 20
+  triple:
+    max_triples: 100
+scheduler:
+  triple_start_rate: 2.5
+  liveness_margin: 1
+presignature_max_age_secs: 0
+log_level: mpc_node=debug
+"#,
+        )
+        .unwrap();
+
+        let base = LocalConfig {
+            over: OverrideConfig::new(serde_json::json!({ "triple": { "max_triples": 50 } })),
+            presignature_max_age: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let local = file.apply(&base);
+        // Overrides given on the command line win over the ones of the file.
+        assert_eq!(
+            local.over.entries,
+            serde_json::json!({
+                "presignature": { "min_presignatures": 20 },
+                "triple": { "max_triples": 50 },
+            })
+        );
+        assert_eq!(local.scheduler.triple_start_rate, 2.5);
+        assert_eq!(local.scheduler.liveness_margin, 1);
+        assert_eq!(
+            local.scheduler.triple_start_burst,
+            base.scheduler.triple_start_burst
+        );
+        assert_eq!(local.presignature_max_age, None);
+
+        assert!(serde_yaml::from_str::<FileConfig>("web_port: 3000").is_err());
+    }
 }
@@ -0,0 +1,151 @@
+//! Stream of structured node events, such as completed presignatures, spent triples and published
+//! signatures. The events are served over the `/events` websocket of the admin server, so that
+//! dashboards and alerting can follow the node in real time without scraping logs.
+
+use cait_sith::protocol::Participant;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::protocol::presignature::PresignatureId;
+use crate::protocol::signature::ReceiptId;
+use crate::protocol::triple::TripleId;
+use crate::protocol::validation::Rejection;
+
+/// Number of events a subscriber can fall behind before it starts missing events.
+const CAPACITY: usize = 1024;
+
+static EVENTS: Lazy<broadcast::Sender<Entry>> = Lazy::new(|| broadcast::channel(CAPACITY).0);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+    TripleCompleted {
+        epoch: u64,
+        id: TripleId,
+        mine: bool,
+    },
+    TripleSpent {
+        epoch: u64,
+        id: TripleId,
+    },
+    /// An unspent triple was dropped to relieve memory pressure, see [`crate::shedding`].
+    TripleEvicted {
+        epoch: u64,
+        id: TripleId,
+    },
+    PresignatureCompleted {
+        epoch: u64,
+        id: PresignatureId,
+        mine: bool,
+    },
+    PresignatureSpent {
+        epoch: u64,
+        id: PresignatureId,
+    },
+    SignaturePublished {
+        receipt_id: ReceiptId,
+        latency_ms: u64,
+    },
+    /// A sign request was refused by the validators, see [`crate::protocol::validation`].
+    SignRequestRejected {
+        receipt_id: ReceiptId,
+        rejection: Rejection,
+    },
+    PeerUnreachable {
+        participant: Participant,
+    },
+    PeerRecovered {
+        participant: Participant,
+    },
+    SheddingStarted {
+        rss_bytes: Option<u64>,
+        pool_size: usize,
+    },
+    SheddingStopped {
+        rss_bytes: Option<u64>,
+        pool_size: usize,
+    },
+    /// The key share was refreshed in the given round of the schedule, see
+    /// [`crate::protocol::refresh`].
+    ShareRefreshed {
+        epoch: u64,
+        round: u64,
+    },
+    /// The refresh of the key share in the given round was given up on, the share is unchanged.
+    ShareRefreshFailed {
+        epoch: u64,
+        round: u64,
+        reason: String,
+    },
+    /// The p95 latency of signatures went over its objective, see [`crate::slo`].
+    LatencySloBreached {
+        p95_ms: u64,
+        slo_ms: u64,
+    },
+    /// The p95 latency of signatures is back under its objective.
+    LatencySloRecovered {
+        p95_ms: u64,
+        slo_ms: u64,
+    },
+    /// The subscriber fell behind and missed events.
+    Lagged {
+        skipped: u64,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub event: NodeEvent,
+}
+
+impl Entry {
+    pub fn new(event: NodeEvent) -> Self {
+        Self {
+            timestamp: Utc::now().timestamp_millis(),
+            event,
+        }
+    }
+}
+
+/// Publishes an event to the current subscribers. Does nothing if there are none.
+pub fn emit(event: NodeEvent) {
+    if EVENTS.receiver_count() > 0 {
+        let _ = EVENTS.send(Entry::new(event));
+    }
+}
+
+/// Subscribes to the events emitted from now on.
+pub fn subscribe() -> broadcast::Receiver<Entry> {
+    EVENTS.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit, subscribe, NodeEvent};
+
+    #[tokio::test]
+    async fn test_events() {
+        // Events emitted before subscribing are not seen. Other tests emit events concurrently,
+        // so only the events of an epoch no other test uses are looked at.
+        let epoch = u64::MAX;
+        emit(NodeEvent::TripleSpent { epoch, id: 1 });
+        let mut events = subscribe();
+        emit(NodeEvent::TripleSpent { epoch, id: 2 });
+        let entry = loop {
+            let entry = events.recv().await.unwrap();
+            if matches!(entry.event, NodeEvent::TripleSpent { epoch: e, .. } if e == epoch) {
+                break entry;
+            }
+        };
+        assert_eq!(entry.event, NodeEvent::TripleSpent { epoch, id: 2 });
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["event"], "triple_spent");
+        assert_eq!(json["id"], 2);
+    }
+}
@@ -0,0 +1,200 @@
+//! Fault injection for exercising the timeout, garbage collection and retry machinery of the
+//! protocols. An installed [`FaultInjector`] is consulted for every message the node sends or
+//! receives, and can drop, delay, duplicate or corrupt it. Only available with the
+//! `fault-injection` feature, and does nothing until an injector is installed.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use cait_sith::protocol::Participant;
+use once_cell::sync::OnceCell;
+use rand::Rng;
+
+use crate::protocol::MpcMessage;
+
+static INJECTOR: OnceCell<Box<dyn FaultInjector>> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    Deliver,
+    Drop,
+    Delay(Duration),
+    Duplicate,
+    Corrupt,
+}
+
+pub trait FaultInjector: Send + Sync {
+    /// Decides what happens to `msg` on its way to or from `peer`.
+    fn inject(&self, direction: Direction, peer: Participant, msg: &MpcMessage) -> Fault;
+}
+
+/// Installs the injector consulted for every message from now on. Only one injector can be
+/// installed for the lifetime of the process.
+pub fn install(injector: Box<dyn FaultInjector>) {
+    if INJECTOR.set(injector).is_err() {
+        tracing::warn!("fault injector is already installed");
+    }
+}
+
+/// Asks the installed injector what to do with the message. Messages are delivered as is when
+/// there is no injector.
+pub(crate) fn inject(direction: Direction, peer: Participant, msg: &MpcMessage) -> Fault {
+    let Some(injector) = INJECTOR.get() else {
+        return Fault::Deliver;
+    };
+    let fault = injector.inject(direction, peer, msg);
+    if fault != Fault::Deliver {
+        tracing::debug!(
+            ?direction,
+            ?peer,
+            typename = msg.typename(),
+            ?fault,
+            "injecting fault"
+        );
+    }
+    fault
+}
+
+/// Flips the bits of the protocol data carried by the message, so that it still gets routed to
+/// its protocol but fails to be processed there.
+pub(crate) fn corrupt(msg: &mut MpcMessage) {
+    let data = match msg {
+        MpcMessage::Generating(msg) => &mut msg.data,
+        MpcMessage::Resharing(msg) => &mut msg.data,
+        MpcMessage::Triple(msg) => &mut msg.data,
+        MpcMessage::Presignature(msg) => &mut msg.data,
+        MpcMessage::Signature(msg) => &mut msg.data,
+        MpcMessage::Abort(msg) => {
+            msg.reason = msg.reason.chars().rev().collect();
+            return;
+        }
+        MpcMessage::Reconcile(msg) => {
+            for id in msg.presignatures.iter_mut() {
+                *id = !*id;
+            }
+            return;
+        }
+        MpcMessage::UnknownTriple(msg) => {
+            msg.triple_id = !msg.triple_id;
+            return;
+        }
+        MpcMessage::Commitment(msg) => &mut msg.commitment[..],
+        MpcMessage::Announce(msg) => {
+            msg.started_at = !msg.started_at;
+            return;
+        }
+        MpcMessage::Refresh(msg) => &mut msg.data,
+    };
+    for byte in data.iter_mut() {
+        *byte = !*byte;
+    }
+}
+
+/// A single rule of [`Rules`], applying a fault to a share of the matching messages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub direction: Direction,
+    /// Type name of the messages the rule applies to, or `None` for all of them.
+    pub typename: Option<String>,
+    pub fault: Fault,
+    pub probability: f64,
+}
+
+/// Injector applying randomly chosen faults, configured with a comma separated list of rules of
+/// the form `<send|receive>:<message type|*>:<drop|duplicate|corrupt|delay=ms>:<probability>`,
+/// e.g. `send:presignature:drop:0.1,receive:*:delay=500:0.05`. The first matching rule that
+/// triggers decides the fault.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules(pub Vec<Rule>);
+
+impl FaultInjector for Rules {
+    fn inject(&self, direction: Direction, _peer: Participant, msg: &MpcMessage) -> Fault {
+        let mut rng = rand::thread_rng();
+        self.0
+            .iter()
+            .filter(|rule| rule.direction == direction)
+            .filter(|rule| {
+                rule.typename.as_ref().map_or(true, |typename| {
+                    typename.eq_ignore_ascii_case(msg.typename())
+                })
+            })
+            .find(|rule| rng.gen_bool(rule.probability))
+            .map_or(Fault::Deliver, |rule| rule.fault)
+    }
+}
+
+impl FromStr for Rules {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let [direction, typename, fault, probability] = rule.split(':').collect::<Vec<_>>()[..]
+            else {
+                anyhow::bail!("fault rule `{rule}` does not have four `:` separated parts");
+            };
+            let direction = match direction {
+                "send" => Direction::Send,
+                "receive" => Direction::Receive,
+                _ => anyhow::bail!("unknown direction `{direction}` in fault rule `{rule}`"),
+            };
+            let typename = (typename != "*").then(|| typename.to_string());
+            let fault = match fault.split_once('=') {
+                None if fault == "drop" => Fault::Drop,
+                None if fault == "duplicate" => Fault::Duplicate,
+                None if fault == "corrupt" => Fault::Corrupt,
+                Some(("delay", ms)) => Fault::Delay(Duration::from_millis(ms.parse()?)),
+                _ => anyhow::bail!("unknown fault `{fault}` in fault rule `{rule}`"),
+            };
+            let probability: f64 = probability.parse()?;
+            if !(0.0..=1.0).contains(&probability) {
+                anyhow::bail!("probability of fault rule `{rule}` is not between 0 and 1");
+            }
+            rules.push(Rule {
+                direction,
+                typename,
+                fault,
+                probability,
+            });
+        }
+        Ok(Self(rules))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let rules: Rules = "send:presignature:drop:0.1, receive:*:delay=500:1"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rules.0,
+            vec![
+                Rule {
+                    direction: Direction::Send,
+                    typename: Some("presignature".to_string()),
+                    fault: Fault::Drop,
+                    probability: 0.1,
+                },
+                Rule {
+                    direction: Direction::Receive,
+                    typename: None,
+                    fault: Fault::Delay(Duration::from_millis(500)),
+                    probability: 1.0,
+                },
+            ]
+        );
+        assert!("send:triple:explode:0.5".parse::<Rules>().is_err());
+        assert!("send:triple:drop:2".parse::<Rules>().is_err());
+        assert!("send:triple:drop".parse::<Rules>().is_err());
+    }
+}
@@ -18,6 +18,12 @@ pub enum SecretStorageError {
     IoError(#[from] std::io::Error),
     #[error("(de)serialization error: {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("encryption error: {0}")]
+    Encryption(String),
+    #[error("AWS error: {0}")]
+    AwsError(String),
+    #[error("Vault error: {0}")]
+    VaultError(String),
 }
 
 #[derive(thiserror::Error, Debug)]
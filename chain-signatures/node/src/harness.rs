@@ -0,0 +1,772 @@
+//! In-process harness running the protocol managers of several nodes against each other over a
+//! simulated network, so that triple, presignature and signature flows can be tested end to end
+//! without docker. Time on the network is counted in ticks, and every fault it injects is drawn
+//! from a seeded rng, so a given seed always produces the same delivery schedule. The ids of the
+//! triples introduced by the nodes are drawn from the same seed. The timeouts of the managers are
+//! measured against a shared [`MockClock`], which only moves on [`Harness::advance`].
+//!
+//! The messages sent over the network can be recorded into a [`Transcript`], to be replayed by
+//! later versions of the node, see [`crate::transcript`].
+
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use cait_sith::protocol::{Action, Participant, Protocol};
+use cait_sith::KeygenOutput;
+use crypto_shared::PublicKey;
+use k256::{Scalar, Secp256k1};
+use mpc_contract::config::ProtocolConfig;
+use near_primitives::hash::CryptoHash;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use tokio::sync::RwLock;
+
+use crate::clock::{Clock, MockClock};
+use crate::indexer::ContractSignRequest;
+use crate::protocol::codec::{self, SCHEMA_BINARY};
+use crate::protocol::contract::primitives::Participants;
+use crate::protocol::presignature::{GenerationError, PresignatureManager};
+use crate::protocol::signature::{ReceiptId, SignatureManager};
+use crate::protocol::triple::TripleManager;
+use crate::protocol::{MpcMessage, ParticipantInfo};
+use crate::rng;
+use crate::storage;
+use crate::transcript::{Recorded, Transcript};
+use crate::types::SecretKeyShare;
+
+const EPOCH: u64 = 0;
+
+/// Faults injected by the simulated network on every message.
+#[derive(Debug, Clone)]
+pub struct NetworkOptions {
+    /// Number of ticks a message takes to be delivered, drawn uniformly from this range.
+    pub latency: RangeInclusive<u64>,
+    /// Probability of a message being dropped.
+    pub drop_rate: f64,
+    /// Whether messages between the same two nodes may be delivered out of order.
+    pub reorder: bool,
+    pub seed: u64,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            latency: 0..=0,
+            drop_rate: 0.0,
+            reorder: false,
+            seed: 0,
+        }
+    }
+}
+
+struct Envelope {
+    deliver_at: u64,
+    from: Participant,
+    to: Participant,
+    message: MpcMessage,
+}
+
+struct Network {
+    options: NetworkOptions,
+    rng: StdRng,
+    in_flight: Vec<Envelope>,
+    tick: u64,
+    dropped: usize,
+}
+
+impl Network {
+    fn new(options: NetworkOptions) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(options.seed),
+            options,
+            in_flight: Vec::new(),
+            tick: 0,
+            dropped: 0,
+        }
+    }
+
+    fn send(&mut self, from: Participant, to: Participant, message: MpcMessage) {
+        if self.rng.gen_bool(self.options.drop_rate) {
+            self.dropped += 1;
+            return;
+        }
+        let mut deliver_at = self.tick + self.rng.gen_range(self.options.latency.clone());
+        if !self.options.reorder {
+            // Never deliver before a message sent earlier on the same link.
+            if let Some(last) = self
+                .in_flight
+                .iter()
+                .filter(|envelope| envelope.from == from && envelope.to == to)
+                .map(|envelope| envelope.deliver_at)
+                .max()
+            {
+                deliver_at = deliver_at.max(last);
+            }
+        }
+        self.in_flight.push(Envelope {
+            deliver_at,
+            from,
+            to,
+            message,
+        });
+    }
+
+    /// Removes the messages due this tick, in the order they get delivered.
+    fn due(&mut self) -> Vec<Envelope> {
+        let tick = self.tick;
+        let (mut due, in_flight): (Vec<_>, Vec<_>) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|envelope| envelope.deliver_at <= tick);
+        self.in_flight = in_flight;
+        if self.options.reorder {
+            due.shuffle(&mut self.rng);
+        } else {
+            due.sort_by_key(|envelope| envelope.deliver_at);
+        }
+        due
+    }
+}
+
+/// What came of handing a message to a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    Handled,
+    /// The node cannot handle the message yet, e.g. because a triple is still generating on its
+    /// side.
+    Postponed,
+    /// The node does not have the triple or presignature the message depends on.
+    Missing(String),
+    /// The node refused the message.
+    Rejected(String),
+}
+
+/// The protocol managers of a single simulated node.
+pub struct Node {
+    pub me: Participant,
+    pub triple_manager: TripleManager,
+    pub presignature_manager: PresignatureManager,
+    pub signature_manager: SignatureManager,
+    pub private_share: SecretKeyShare,
+}
+
+pub struct Harness {
+    pub nodes: Vec<Node>,
+    pub participants: Participants,
+    pub public_key: PublicKey,
+    pub cfg: ProtocolConfig,
+    /// Clock shared by the managers of every node.
+    pub clock: MockClock,
+    network: Network,
+    receipts: u64,
+    threshold: usize,
+    seed: u64,
+    /// Messages sent so far, if recording.
+    recorded: Option<Vec<Recorded>>,
+}
+
+impl Harness {
+    /// Creates `n` nodes sharing a key generated in-process with the given threshold. Key
+    /// generation runs over a perfect network; the faults only apply to what comes after.
+    pub fn new(n: u32, threshold: usize, options: NetworkOptions) -> Self {
+        let mut participants = Participants::default();
+        for p in (0..n).map(Participant::from) {
+            participants.insert(&p, ParticipantInfo::new(p.into()));
+        }
+        let keygens = participants
+            .keys()
+            .map(|p| {
+                let protocol: Box<dyn Protocol<Output = KeygenOutput<Secp256k1>>> =
+                    Box::new(cait_sith::keygen(&participants.keys_vec(), *p, threshold).unwrap());
+                (*p, protocol)
+            })
+            .collect();
+        let keys = run_protocols(keygens);
+        let public_key = keys[0].1.public_key;
+        let shares = keys.into_iter().map(|(_, key)| key.private_share).collect();
+        Self::from_keys(threshold, public_key, shares, options)
+    }
+
+    /// Creates one node per share of the given key, the share of the node `i` being the `i`th.
+    pub fn from_keys(
+        threshold: usize,
+        public_key: PublicKey,
+        shares: Vec<SecretKeyShare>,
+        options: NetworkOptions,
+    ) -> Self {
+        let mut participants = Participants::default();
+        for p in (0..shares.len() as u32).map(Participant::from) {
+            participants.insert(&p, ParticipantInfo::new(p.into()));
+        }
+        let clock = MockClock::new();
+
+        let nodes = shares
+            .into_iter()
+            .enumerate()
+            .map(|(i, private_share)| {
+                let me = Participant::from(i as u32);
+                let account_id = format!("p-{}.testnet", u32::from(me)).parse().unwrap();
+                let triple_storage = Arc::new(RwLock::new(storage::triple_storage::init(
+                    None,
+                    &account_id,
+                )));
+                let presignature_storage = Arc::new(RwLock::new(
+                    storage::presignature_storage::init(None, &account_id),
+                ));
+                let mut triple_manager =
+                    TripleManager::new(me, threshold, EPOCH, vec![], triple_storage, &account_id);
+                triple_manager.rng = rng::seeded_for(options.seed, account_id.as_str().as_bytes());
+                triple_manager.clock = clock.shared();
+                Node {
+                    me,
+                    triple_manager,
+                    presignature_manager: PresignatureManager::new(
+                        me,
+                        threshold,
+                        EPOCH,
+                        &public_key,
+                        vec![],
+                        presignature_storage,
+                        &account_id,
+                    )
+                    .with_clock(clock.shared()),
+                    signature_manager: SignatureManager::new(me, public_key, EPOCH, &account_id)
+                        .with_clock(clock.shared()),
+                    private_share,
+                }
+            })
+            .collect();
+
+        Self {
+            nodes,
+            participants,
+            public_key,
+            cfg: ProtocolConfig::default(),
+            clock,
+            seed: options.seed,
+            network: Network::new(options),
+            receipts: 0,
+            threshold,
+            recorded: None,
+        }
+    }
+
+    /// Creates the nodes of a recorded transcript, with the keys and seed they were recorded with.
+    pub fn from_transcript(transcript: &Transcript) -> Self {
+        Self::from_keys(
+            transcript.threshold,
+            transcript.public_key,
+            transcript.shares.clone(),
+            NetworkOptions {
+                seed: transcript.seed,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Records the messages sent by the nodes from now on, see [`Harness::transcript`].
+    pub fn record(&mut self) {
+        self.recorded.get_or_insert_with(Vec::new);
+    }
+
+    /// The messages recorded so far along with what it takes to replay them.
+    pub fn transcript(&self) -> Transcript {
+        Transcript {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            schema: SCHEMA_BINARY,
+            threshold: self.threshold,
+            public_key: self.public_key,
+            shares: self.nodes.iter().map(|node| node.private_share).collect(),
+            seed: self.seed,
+            messages: self.recorded.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Hands every message of the transcript to its receiving node in the order they were sent,
+    /// decoded as this version of the node would. Returns what came of each message, or why it
+    /// could not be decoded.
+    pub async fn replay(
+        &mut self,
+        transcript: &Transcript,
+    ) -> Vec<Result<Delivery, codec::CodecError>> {
+        let mut deliveries = Vec::new();
+        for recorded in &transcript.messages {
+            let delivery = match recorded.decode(transcript.schema) {
+                Ok(message) => {
+                    let envelope = Envelope {
+                        deliver_at: self.network.tick,
+                        from: recorded.from,
+                        to: recorded.to,
+                        message,
+                    };
+                    Ok(self.deliver(&envelope).await)
+                }
+                Err(err) => Err(err),
+            };
+            deliveries.push(delivery);
+        }
+        deliveries
+    }
+
+    /// Moves the clock of every node forward, e.g. past the timeout of the ongoing protocols.
+    pub fn advance(&mut self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// Number of messages dropped by the network so far.
+    pub fn dropped(&self) -> usize {
+        self.network.dropped
+    }
+
+    /// Starts generating `count` triples owned by `node`.
+    pub fn generate_triples(&mut self, node: usize, count: usize) {
+        let timeout = self.cfg.triple.generation_timeout;
+        for _ in 0..count {
+            self.nodes[node]
+                .triple_manager
+                .generate(&self.participants, timeout)
+                .unwrap();
+        }
+    }
+
+    /// Starts generating up to `count` presignatures owned by `node` from its own triples.
+    /// Returns the number of presignatures started.
+    pub async fn generate_presignatures(&mut self, node: usize, count: usize) -> usize {
+        let Node {
+            triple_manager,
+            presignature_manager,
+            private_share,
+            ..
+        } = &mut self.nodes[node];
+        presignature_manager
+            .generate_batch(
+                count,
+                triple_manager,
+                &self.participants,
+                &self.public_key,
+                private_share,
+                self.cfg.presignature.generation_timeout,
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Starts signing `request` with one of the presignatures owned by `node`. Returns `None` if
+    /// the node has no presignature.
+    pub async fn sign(
+        &mut self,
+        node: usize,
+        request: ContractSignRequest,
+        epsilon: Scalar,
+    ) -> Option<ReceiptId> {
+        self.receipts += 1;
+        let receipt_id = CryptoHash::hash_bytes(&self.receipts.to_le_bytes());
+        let entropy = self.network.rng.gen();
+        let node = &mut self.nodes[node];
+        let presignature = node.presignature_manager.take_mine().await?;
+        node.signature_manager
+            .generate(
+                &self.participants,
+                receipt_id,
+                presignature,
+                request,
+                epsilon,
+                entropy,
+                self.clock.now(),
+                &self.cfg,
+            )
+            .ok()?;
+        Some(receipt_id)
+    }
+
+    /// Pokes every node once, sends what they produced and delivers the messages that are due.
+    /// Returns whether anything happened at all.
+    pub async fn tick(&mut self) -> bool {
+        let mut active = false;
+        for node in &mut self.nodes {
+            let me = node.me;
+            let mut outgoing = Vec::new();
+            for (to, msg) in node.triple_manager.poke(&self.cfg).await.messages {
+                outgoing.push((to, MpcMessage::Triple(msg)));
+            }
+            for (to, msg) in node.presignature_manager.poke().await.messages {
+                outgoing.push((to, MpcMessage::Presignature(msg)));
+            }
+            for (to, msg) in node.presignature_manager.take_aborts() {
+                outgoing.push((to, MpcMessage::Abort(msg)));
+            }
+            for (to, msg) in node.presignature_manager.take_commitments() {
+                outgoing.push((to, MpcMessage::Commitment(msg)));
+            }
+            for (to, msg) in node.signature_manager.poke().messages {
+                outgoing.push((to, MpcMessage::Signature(msg)));
+            }
+            for (to, msg) in outgoing {
+                active = true;
+                if let Some(recorded) = &mut self.recorded {
+                    recorded.push(Recorded::new(
+                        self.network.tick,
+                        me,
+                        to,
+                        &msg,
+                        SCHEMA_BINARY,
+                    ));
+                }
+                self.network.send(me, to, msg);
+            }
+        }
+
+        let mut retry = VecDeque::new();
+        for envelope in self.network.due() {
+            active = true;
+            if self.deliver(&envelope).await == Delivery::Postponed {
+                retry.push_back(envelope);
+            }
+        }
+        // Messages that could not be handled yet, e.g. because a triple is still generating on
+        // the receiving node, are retried on the next tick.
+        for mut envelope in retry {
+            envelope.deliver_at = self.network.tick + 1;
+            self.network.in_flight.push(envelope);
+        }
+
+        self.network.tick += 1;
+        active || !self.network.in_flight.is_empty()
+    }
+
+    /// Ticks until nothing happens anymore, or until `max_ticks`. Returns the number of ticks.
+    pub async fn run_until_quiet(&mut self, max_ticks: u64) -> u64 {
+        for ticks in 0..max_ticks {
+            if !self.tick().await {
+                return ticks;
+            }
+        }
+        max_ticks
+    }
+
+    /// Messages sent by the nodes that are yet to be delivered, as `(from, to, message)`.
+    pub fn in_flight(&self) -> impl Iterator<Item = (Participant, Participant, &MpcMessage)> {
+        self.network
+            .in_flight
+            .iter()
+            .map(|envelope| (envelope.from, envelope.to, &envelope.message))
+    }
+
+    /// Hands `message` to the node `to` as if sent by `from`, bypassing the network. Returns
+    /// false if the node cannot handle it yet.
+    pub async fn inject(
+        &mut self,
+        from: Participant,
+        to: Participant,
+        message: MpcMessage,
+    ) -> bool {
+        let envelope = Envelope {
+            deliver_at: self.network.tick,
+            from,
+            to,
+            message,
+        };
+        self.deliver(&envelope).await != Delivery::Postponed
+    }
+
+    /// Hands the message to the receiving node.
+    async fn deliver(&mut self, envelope: &Envelope) -> Delivery {
+        tracing::trace!(
+            from = ?envelope.from,
+            to = ?envelope.to,
+            message = ?envelope.message,
+            "harness: delivering message"
+        );
+        let node = &mut self.nodes[u32::from(envelope.to) as usize];
+        match &envelope.message {
+            MpcMessage::Triple(msg) => {
+                match node
+                    .triple_manager
+                    .get_or_generate(msg.id, &self.participants, &self.cfg)
+                {
+                    Ok(Some(protocol)) => protocol.message(envelope.from, msg.data.clone()),
+                    Ok(None) => {}
+                    Err(err) => {
+                        tracing::warn!(?err, "harness: failed to join triple");
+                        return Delivery::Rejected(format!("{err:?}"));
+                    }
+                }
+                Delivery::Handled
+            }
+            MpcMessage::Presignature(msg) => {
+                let result = node
+                    .presignature_manager
+                    .get_or_generate(
+                        &self.participants,
+                        msg.id,
+                        msg.triple0,
+                        msg.triple1,
+                        msg.proposer,
+                        &mut node.triple_manager,
+                        &self.public_key,
+                        &node.private_share,
+                        &self.cfg,
+                    )
+                    .await;
+                match result {
+                    Ok(protocol) => protocol.message(envelope.from, msg.data.clone()),
+                    Err(
+                        GenerationError::TripleIsGenerating(_)
+                        | GenerationError::TooManyForeignGenerators { .. }
+                        | GenerationError::JoinDeclined(_),
+                    ) => return Delivery::Postponed,
+                    Err(err @ GenerationError::TripleIsMissing(_)) => {
+                        tracing::warn!(?err, "harness: failed to join presignature");
+                        return Delivery::Missing(err.to_string());
+                    }
+                    Err(err) => {
+                        tracing::warn!(?err, "harness: failed to join presignature");
+                        return Delivery::Rejected(err.to_string());
+                    }
+                }
+                Delivery::Handled
+            }
+            MpcMessage::Signature(msg) => {
+                let result = node
+                    .signature_manager
+                    .get_or_generate(
+                        &self.participants,
+                        msg.receipt_id,
+                        msg.proposer,
+                        msg.presignature_id,
+                        &msg.request,
+                        msg.epsilon,
+                        msg.entropy,
+                        &mut node.presignature_manager,
+                        &self.cfg,
+                    )
+                    .await;
+                match result {
+                    Ok(protocol) => protocol.message(envelope.from, msg.data.clone()),
+                    Err(GenerationError::PresignatureIsGenerating(_)) => {
+                        return Delivery::Postponed
+                    }
+                    Err(err @ GenerationError::PresignatureIsMissing(_)) => {
+                        tracing::warn!(?err, "harness: failed to join signature");
+                        return Delivery::Missing(err.to_string());
+                    }
+                    Err(err) => {
+                        tracing::warn!(?err, "harness: failed to join signature");
+                        return Delivery::Rejected(err.to_string());
+                    }
+                }
+                Delivery::Handled
+            }
+            MpcMessage::Abort(msg) => {
+                node.presignature_manager
+                    .on_abort(msg.id, msg.from, &msg.reason);
+                Delivery::Handled
+            }
+            MpcMessage::Reconcile(msg) => {
+                node.presignature_manager
+                    .on_inventory(msg.from, msg.presignatures.clone());
+                Delivery::Handled
+            }
+            MpcMessage::UnknownTriple(msg) => {
+                node.presignature_manager
+                    .on_unknown_triple(msg.id, msg.from, msg.triple_id);
+                Delivery::Handled
+            }
+            MpcMessage::Commitment(msg) => {
+                node.presignature_manager
+                    .on_commitment(msg.id, msg.from, msg.commitment)
+                    .await;
+                Delivery::Handled
+            }
+            _ => Delivery::Handled,
+        }
+    }
+}
+
+/// Runs the protocols of all participants to completion over a perfect network.
+fn run_protocols<O>(
+    mut protocols: Vec<(Participant, Box<dyn Protocol<Output = O>>)>,
+) -> Vec<(Participant, O)> {
+    let mut outputs = Vec::new();
+    while outputs.len() < protocols.len() {
+        for i in 0..protocols.len() {
+            let from = protocols[i].0;
+            loop {
+                match protocols[i].1.poke().unwrap() {
+                    Action::Wait => break,
+                    Action::SendMany(data) => {
+                        for (to, protocol) in protocols.iter_mut() {
+                            if *to != from {
+                                protocol.message(from, data.clone());
+                            }
+                        }
+                    }
+                    Action::SendPrivate(to, data) => {
+                        let (_, protocol) = protocols.iter_mut().find(|(p, _)| *p == to).unwrap();
+                        protocol.message(from, data);
+                    }
+                    Action::Return(output) => {
+                        outputs.push((from, output));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    outputs.sort_by_key(|(p, _)| u32::from(*p));
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sign_over_unreliable_network() {
+        let mut harness = Harness::new(
+            3,
+            2,
+            NetworkOptions {
+                latency: 0..=3,
+                reorder: true,
+                ..Default::default()
+            },
+        );
+        harness.generate_triples(0, 2);
+        harness.run_until_quiet(10_000).await;
+        assert_eq!(harness.generate_presignatures(0, 1).await, 1);
+        harness.run_until_quiet(10_000).await;
+
+        let request = ContractSignRequest {
+            payload: Scalar::from(42u64),
+            path: "test".to_string(),
+            key_version: 0,
+        };
+        let receipt_id = harness.sign(0, request, Scalar::ONE).await.unwrap();
+        harness.run_until_quiet(10_000).await;
+        for node in &harness.nodes {
+            assert!(node.signature_manager.is_completed(&receipt_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_triples_time_out_on_the_clock() {
+        let mut harness = Harness::new(
+            3,
+            2,
+            NetworkOptions {
+                drop_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        harness.generate_triples(0, 2);
+        harness.run_until_quiet(100).await;
+        assert!(harness.nodes[0].triple_manager.sweep().is_empty());
+
+        let timeout = harness.cfg.triple.generation_timeout;
+        harness.advance(Duration::from_millis(timeout + 1));
+        assert_eq!(harness.nodes[0].triple_manager.sweep().len(), 2);
+    }
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn encodings(scalar: &Scalar) -> Vec<String> {
+        let bytes = scalar.to_bytes();
+        vec![
+            format!("{scalar:?}"),
+            hex::encode(bytes),
+            hex::encode_upper(bytes),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_logs_leak_no_secrets() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut harness = Harness::new(3, 2, NetworkOptions::default());
+        let mut secrets: Vec<Scalar> = harness.nodes.iter().map(|n| n.private_share).collect();
+        harness.generate_triples(0, 2);
+        harness.run_until_quiet(10_000).await;
+        for node in &harness.nodes {
+            for triple in node.triple_manager.triples.values() {
+                tracing::trace!(?triple, "harness: generated triple");
+                secrets.extend([triple.share.a, triple.share.b, triple.share.c]);
+            }
+        }
+        assert_eq!(harness.generate_presignatures(0, 1).await, 1);
+        harness.run_until_quiet(10_000).await;
+        for node in &harness.nodes {
+            for (presignature, _) in node.presignature_manager.unspent() {
+                tracing::trace!(?presignature, "harness: generated presignature");
+                secrets.extend([presignature.output.k, presignature.output.sigma]);
+            }
+        }
+
+        let payload = Scalar::from(0x5eed_cafe_f00d_u64);
+        let path = "m/harness-secret-derivation-path";
+        let request = ContractSignRequest {
+            payload,
+            path: path.to_string(),
+            key_version: 0,
+        };
+        tracing::trace!(?request, "harness: signing");
+        let receipt_id = harness.sign(0, request, Scalar::ONE).await.unwrap();
+        harness.run_until_quiet(10_000).await;
+        assert!(harness.nodes[1].signature_manager.is_completed(&receipt_id));
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("harness: delivering message"));
+        assert!(!logs.contains(path));
+        for secret in secrets.iter().chain([&payload]) {
+            for encoding in encodings(secret) {
+                assert!(!logs.contains(&encoding), "{encoding} leaked into the logs");
+            }
+        }
+    }
+
+    #[test]
+    fn test_triple_ids_are_reproducible() {
+        let ids = |seed| {
+            let mut harness = Harness::new(
+                3,
+                2,
+                NetworkOptions {
+                    seed,
+                    ..Default::default()
+                },
+            );
+            harness.generate_triples(0, 4);
+            let mut ids = harness.nodes[0]
+                .triple_manager
+                .generators
+                .keys()
+                .copied()
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(1), ids(1));
+        assert_ne!(ids(1), ids(2));
+    }
+}
@@ -1,3 +1,6 @@
+use crate::mesh::bandwidth::{self, Throttle};
+use crate::mesh::quic;
+use crate::mesh::transport::{self, PeerTransport, WireFormat};
 use crate::protocol::contract::primitives::{ParticipantInfo, Participants};
 use crate::protocol::message::SignedMessage;
 use crate::protocol::MpcMessage;
@@ -29,56 +32,166 @@ pub enum SendError {
     Timeout(String),
     #[error("participant is not alive: {0}")]
     ParticipantNotAlive(String),
+    #[error("quic error: {0}")]
+    Quic(String),
 }
 
-async fn send_encrypted<U: IntoUrl>(
+/// Sends the batches of messages in the body of `/msg` requests, over TLS if the url of the
+/// participant is an `https` one.
+pub struct HttpTransport {
+    client: Client,
+}
+
+impl HttpTransport {
+    pub fn new(client: &Client) -> Self {
+        Self {
+            client: client.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PeerTransport for HttpTransport {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn send(
+        &self,
+        from: Participant,
+        to: &ParticipantInfo,
+        batch: &[Ciphered],
+        format: WireFormat,
+    ) -> Result<(), SendError> {
+        let _span = tracing::info_span!("message_request");
+        let mut url = to.url.as_str().into_url()?;
+        url.set_path("msg");
+        tracing::debug!(?from, to = %url, "making http request: sending encrypted message");
+        let (body, compressed) = transport::encode_batch(batch, format)?;
+        let action = || async {
+            let mut request = self
+                .client
+                .post(url.clone())
+                .header("content-type", "application/json")
+                .header(transport::SCHEMA_HEADER, format.schema)
+                .body(body.clone());
+            if compressed {
+                request = request.header("content-encoding", transport::ZSTD_ENCODING);
+            }
+            let response = request
+                .send()
+                .await
+                .map_err(SendError::ReqwestClientError)?;
+            let status = response.status();
+            let response_bytes = response
+                .bytes()
+                .await
+                .map_err(SendError::ReqwestBodyError)?;
+            let response_str =
+                std::str::from_utf8(&response_bytes).map_err(SendError::MalformedResponse)?;
+            if status.is_success() {
+                Ok(())
+            } else {
+                tracing::warn!(
+                    "failed to send a message to {} with code {}: {}",
+                    url,
+                    status,
+                    response_str
+                );
+                Err(SendError::Unsuccessful(response_str.into()))
+            }
+        };
+
+        let retry_strategy = ExponentialBackoff::from_millis(10).map(jitter).take(3);
+        Retry::spawn(retry_strategy, action).await
+    }
+}
+
+/// Sends the batch over the transport negotiated with the participant, falling back to HTTP if
+/// it cannot be delivered over QUIC.
+async fn send_batch(
+    http: &HttpTransport,
     from: Participant,
-    client: &Client,
-    url: U,
-    message: Vec<Ciphered>,
+    to: &ParticipantInfo,
+    batch: &[Ciphered],
+    format: WireFormat,
 ) -> Result<(), SendError> {
-    let _span = tracing::info_span!("message_request");
-    let mut url = url.into_url()?;
-    url.set_path("msg");
-    tracing::debug!(?from, to = %url, "making http request: sending encrypted message");
-    let action = || async {
-        let response = client
-            .post(url.clone())
-            .header("content-type", "application/json")
-            .json(&message)
-            .send()
-            .await
-            .map_err(SendError::ReqwestClientError)?;
-        let status = response.status();
-        let response_bytes = response
-            .bytes()
-            .await
-            .map_err(SendError::ReqwestBodyError)?;
-        let response_str =
-            std::str::from_utf8(&response_bytes).map_err(SendError::MalformedResponse)?;
-        if status.is_success() {
-            Ok(())
-        } else {
-            tracing::warn!(
-                "failed to send a message to {} with code {}: {}",
-                url,
-                status,
-                response_str
-            );
-            Err(SendError::Unsuccessful(response_str.into()))
+    if let Some(quic) = quic::transport().filter(|_| format.quic_port.is_some()) {
+        match send_over(quic, from, to, batch, format).await {
+            Ok(()) => return Ok(()),
+            Err(err) => tracing::debug!(
+                %err,
+                to = ?to.account_id,
+                "failed to send over quic, falling back to http"
+            ),
         }
-    };
+    }
+    send_over(http, from, to, batch, format).await
+}
+
+async fn send_over(
+    transport: &dyn PeerTransport,
+    from: Participant,
+    to: &ParticipantInfo,
+    batch: &[Ciphered],
+    format: WireFormat,
+) -> Result<(), SendError> {
+    let result = transport.send(from, to, batch, format).await;
+    crate::metrics::NUM_BATCHES_SENT_BY_TRANSPORT
+        .with_label_values(&[
+            to.account_id.as_str(),
+            transport.name(),
+            if result.is_ok() { "ok" } else { "error" },
+        ])
+        .inc();
+    result
+}
+
+/// Delay before retrying the delivery of a message for the first time. It doubles with every
+/// failed attempt, up to [`OUTBOX_MAX_BACKOFF`].
+const OUTBOX_BASE_BACKOFF: Duration = Duration::from_millis(100);
+const OUTBOX_MAX_BACKOFF: Duration = Duration::from_secs(5);
 
-    let retry_strategy = ExponentialBackoff::from_millis(10).map(jitter).take(3);
-    Retry::spawn(retry_strategy, action).await
+/// A message in the outbox waiting to be delivered to a participant.
+struct Outgoing {
+    info: ParticipantInfo,
+    msg: MpcMessage,
+    /// When the message was queued, used to time it out together with its protocol.
+    queued_at: Instant,
+    /// Number of failed attempts to deliver this message.
+    attempts: u32,
+    /// The message is held back until then after a failed attempt.
+    retry_at: Instant,
 }
 
-// TODO: add in retry logic either in struct or at call site.
-// TODO: add check for participant list to see if the messages to be sent are still valid.
+impl Outgoing {
+    fn backoff(&mut self) {
+        self.attempts += 1;
+        self.retry_at = Instant::now() + backoff(self.attempts);
+    }
+}
+
+fn backoff(attempts: u32) -> Duration {
+    OUTBOX_BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(OUTBOX_MAX_BACKOFF)
+}
+
+/// Outbox of the messages produced by the protocols. Messages that could not be delivered are
+/// kept and retried with an exponential backoff until they time out, or until the protocol they
+/// belong to is done and they get pruned.
 #[derive(Default)]
 pub struct MessageQueue {
-    deque: VecDeque<(ParticipantInfo, MpcMessage, Instant)>,
+    deque: VecDeque<Outgoing>,
     seen_counts: HashSet<String>,
+    /// Last time each participant acknowledged receiving our messages.
+    acks: HashMap<Participant, Instant>,
+    /// Wire format of the messages sent to each participant.
+    formats: HashMap<Participant, WireFormat>,
+    /// Limits the rate at which bytes are sent to each participant.
+    throttle: Throttle,
+    /// Maximum number of messages waiting to be delivered to a single participant, if limited.
+    outbox_limit: Option<usize>,
 }
 
 impl MessageQueue {
@@ -90,8 +203,76 @@ impl MessageQueue {
         self.deque.is_empty()
     }
 
+    /// Messages waiting to be delivered, with the participant they are addressed to.
+    pub fn outgoing(&self) -> impl Iterator<Item = (&ParticipantInfo, &MpcMessage)> {
+        self.deque
+            .iter()
+            .map(|outgoing| (&outgoing.info, &outgoing.msg))
+    }
+
     pub fn push(&mut self, info: ParticipantInfo, msg: MpcMessage) {
-        self.deque.push_back((info, msg, Instant::now()));
+        let now = Instant::now();
+        #[allow(unused_mut)]
+        let mut retry_at = now;
+        #[cfg(feature = "fault-injection")]
+        let msg = {
+            use crate::fault::{self, Direction, Fault};
+
+            let mut msg = msg;
+            match fault::inject(Direction::Send, Participant::from(info.id), &msg) {
+                Fault::Deliver => {}
+                Fault::Drop => return,
+                Fault::Delay(delay) => retry_at = now + delay,
+                Fault::Duplicate => self.deque.push_back(Outgoing {
+                    info: info.clone(),
+                    msg: msg.clone(),
+                    queued_at: now,
+                    attempts: 0,
+                    retry_at,
+                }),
+                Fault::Corrupt => fault::corrupt(&mut msg),
+            }
+            msg
+        };
+        self.deque.push_back(Outgoing {
+            info,
+            msg,
+            queued_at: now,
+            attempts: 0,
+            retry_at,
+        });
+    }
+
+    /// Number of messages waiting to be delivered to the participant.
+    pub fn pending(&self, participant: &Participant) -> usize {
+        self.deque
+            .iter()
+            .filter(|out| Participant::from(out.info.id) == *participant)
+            .count()
+    }
+
+    /// Last time the participant acknowledged receiving our messages, if ever.
+    pub fn last_ack(&self, participant: &Participant) -> Option<Instant> {
+        self.acks.get(participant).copied()
+    }
+
+    /// Sets the wire format of the messages sent to each participant, as negotiated by the mesh.
+    pub fn set_formats(&mut self, formats: &HashMap<Participant, WireFormat>) {
+        self.formats = formats.clone();
+    }
+
+    /// Sets the number of bytes per second that can be sent to a single participant, and the
+    /// number of messages that can wait to be delivered to it before the oldest ones get dropped.
+    pub fn set_limits(&mut self, send_rate: Option<u64>, outbox_limit: Option<usize>) {
+        self.throttle.set_rate(send_rate);
+        self.outbox_limit = outbox_limit;
+    }
+
+    /// Drops the messages for which `is_live` returns false, returning how many were dropped.
+    pub fn prune(&mut self, mut is_live: impl FnMut(&MpcMessage) -> bool) -> usize {
+        let before = self.deque.len();
+        self.deque.retain(|out| is_live(&out.msg));
+        before - self.deque.len()
     }
 
     pub async fn send_encrypted(
@@ -109,45 +290,85 @@ impl MessageQueue {
         let outer = Instant::now();
         let uncompacted = self.deque.len();
         let mut encrypted = HashMap::new();
-        while let Some((info, msg, instant)) = self.deque.pop_front() {
-            if instant.elapsed() > timeout(&msg, cfg) {
+        while let Some(out) = self.deque.pop_front() {
+            if out.queued_at.elapsed() > timeout(&out.msg, cfg) {
                 errors.push(SendError::Timeout(format!(
-                    "{} message has timed out: {info:?}",
-                    msg.typename(),
+                    "{} message has timed out after {} attempts: {:?}",
+                    out.msg.typename(),
+                    out.attempts,
+                    out.info,
                 )));
                 continue;
             }
+            if out.retry_at > outer {
+                failed.push_back(out);
+                continue;
+            }
 
-            if !participants.contains_key(&Participant::from(info.id)) {
-                let counter = participant_counter.entry(info.id).or_insert(0);
+            if !participants.contains_key(&Participant::from(out.info.id)) {
+                let counter = participant_counter.entry(out.info.id).or_insert(0);
                 *counter += 1;
-                failed.push_back((info, msg, instant));
+                failed.push_back(out);
                 continue;
             }
-            let encrypted_msg = match SignedMessage::encrypt(&msg, from, sign_sk, &info.cipher_pk) {
+            let schema = self
+                .formats
+                .get(&Participant::from(out.info.id))
+                .copied()
+                .unwrap_or_default()
+                .schema;
+            let encrypted_msg = match SignedMessage::encrypt(
+                &out.msg,
+                from,
+                sign_sk,
+                &out.info.cipher_pk,
+                schema,
+            ) {
                 Ok(encrypted) => encrypted,
                 Err(err) => {
                     errors.push(SendError::EncryptionError(err.to_string()));
                     continue;
                 }
             };
-            let encrypted = encrypted.entry(info.id).or_insert_with(Vec::new);
-            encrypted.push((encrypted_msg, (info, msg, instant)));
+            let encrypted = encrypted.entry(out.info.id).or_insert_with(Vec::new);
+            encrypted.push((encrypted_msg, out));
         }
 
+        let http = HttpTransport::new(client);
         let mut compacted = 0;
         for (id, encrypted) in encrypted {
             for partition in partition_ciphered_256kb(encrypted) {
-                let (encrypted_partition, msgs): (Vec<_>, Vec<_>) = partition.into_iter().unzip();
                 // guaranteed to unwrap due to our previous loop check:
                 let info = participants.get(&Participant::from(id)).unwrap();
                 let account_id = &info.account_id;
 
+                let sizes: Vec<_> = partition
+                    .iter()
+                    .map(|(ciphered, out)| (ciphered.text.len(), out.msg.typename()))
+                    .collect();
+                let bytes = sizes.iter().map(|(bytes, _)| bytes).sum();
+                if !self
+                    .throttle
+                    .try_take(Participant::from(id), bytes, Instant::now())
+                {
+                    crate::metrics::NUM_SEND_THROTTLED
+                        .with_label_values(&[account_id.as_str()])
+                        .inc();
+                    failed.extend(partition.into_iter().map(|(_, out)| out));
+                    continue;
+                }
+                let (encrypted_partition, msgs): (Vec<_>, Vec<_>) = partition.into_iter().unzip();
+
                 let start = Instant::now();
                 crate::metrics::NUM_SEND_ENCRYPTED_TOTAL
                     .with_label_values(&[account_id.as_str()])
                     .inc();
-                if let Err(err) = send_encrypted(from, client, &info.url, encrypted_partition).await
+                let format = self
+                    .formats
+                    .get(&Participant::from(id))
+                    .copied()
+                    .unwrap_or_default();
+                if let Err(err) = send_batch(&http, from, info, &encrypted_partition, format).await
                 {
                     crate::metrics::NUM_SEND_ENCRYPTED_FAILURE
                         .with_label_values(&[account_id.as_str()])
@@ -156,11 +377,23 @@ impl MessageQueue {
                         .with_label_values(&[account_id.as_str()])
                         .observe(start.elapsed().as_millis() as f64);
 
-                    // since we failed, put back all the messages related to this
-                    failed.extend(msgs);
+                    // since we failed, put back all the messages related to this and hold them
+                    // back for a while before trying again.
+                    failed.extend(msgs.into_iter().map(|mut out| {
+                        out.backoff();
+                        out
+                    }));
                     errors.push(err);
                 } else {
+                    // A successful response means the participant has accepted the messages.
+                    self.acks.insert(Participant::from(id), Instant::now());
                     compacted += msgs.len();
+                    for (bytes, typename) in sizes {
+                        bandwidth::record_sent(Participant::from(id), typename, bytes);
+                    }
+                    crate::metrics::NUM_MESSAGES_SENT
+                        .with_label_values(&[account_id.as_str()])
+                        .inc_by(msgs.len() as f64);
                     crate::metrics::SEND_ENCRYPTED_LATENCY
                         .with_label_values(&[account_id.as_str()])
                         .observe(start.elapsed().as_millis() as f64);
@@ -184,8 +417,34 @@ impl MessageQueue {
             )));
         }
 
-        // Add back the failed attempts for next time.
+        // Add back the failed attempts for next time, dropping the oldest messages of the
+        // participants that cannot keep up with them.
+        if let Some(limit) = self.outbox_limit {
+            let mut pending = HashMap::new();
+            let mut kept: Vec<_> = failed
+                .into_iter()
+                .rev()
+                .filter(|out| {
+                    let count = pending.entry(out.info.id).or_insert(0);
+                    *count += 1;
+                    if *count > limit {
+                        crate::metrics::NUM_OUTBOX_MESSAGES_DROPPED
+                            .with_label_values(&[out.info.account_id.as_str()])
+                            .inc();
+                        return false;
+                    }
+                    true
+                })
+                .collect();
+            kept.reverse();
+            failed = kept.into();
+        }
         self.deque = failed;
+        for (participant, info) in participants.iter() {
+            crate::metrics::MESSAGE_OUTBOX_PENDING
+                .with_label_values(&[info.account_id.as_str()])
+                .set(self.pending(participant) as i64);
+        }
         if !errors.is_empty() {
             tracing::warn!("got errors when sending encrypted messages: {errors:?}");
         }
@@ -196,7 +455,7 @@ impl MessageQueue {
 /// Encrypted message with a reference to the old message. Only the ciphered portion of this
 /// type will be sent over the wire, while the original message is kept just in case things
 /// go wrong somewhere and the message needs to be requeued to be sent later.
-type EncryptedMessage = (Ciphered, (ParticipantInfo, MpcMessage, Instant));
+type EncryptedMessage = (Ciphered, Outgoing);
 
 fn partition_ciphered_256kb(encrypted: Vec<EncryptedMessage>) -> Vec<Vec<EncryptedMessage>> {
     let mut result = Vec::new();
@@ -230,6 +489,12 @@ fn timeout(msg: &MpcMessage, cfg: &ProtocolConfig) -> Duration {
         MpcMessage::Triple(_) => Duration::from_millis(cfg.triple.generation_timeout),
         MpcMessage::Presignature(_) => Duration::from_millis(cfg.presignature.generation_timeout),
         MpcMessage::Signature(_) => Duration::from_millis(cfg.signature.generation_timeout),
+        MpcMessage::Abort(_) => Duration::from_millis(cfg.message_timeout),
+        MpcMessage::Reconcile(_) => Duration::from_millis(cfg.message_timeout),
+        MpcMessage::UnknownTriple(_) => Duration::from_millis(cfg.message_timeout),
+        MpcMessage::Commitment(_) => Duration::from_millis(cfg.message_timeout),
+        MpcMessage::Announce(_) => Duration::from_millis(cfg.message_timeout),
+        MpcMessage::Refresh(_) => Duration::from_millis(cfg.message_timeout),
     }
 }
 
@@ -238,6 +503,25 @@ mod tests {
     use crate::protocol::message::GeneratingMessage;
     use crate::protocol::MpcMessage;
 
+    #[test]
+    fn test_outbox_backoff() {
+        assert_eq!(super::backoff(1), super::OUTBOX_BASE_BACKOFF);
+        assert_eq!(super::backoff(2), super::OUTBOX_BASE_BACKOFF * 2);
+        assert_eq!(super::backoff(3), super::OUTBOX_BASE_BACKOFF * 4);
+        assert_eq!(super::backoff(100), super::OUTBOX_MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_compressed_messages_roundtrip() {
+        let body = serde_json::to_vec(&vec![0u8; 4096]).unwrap();
+        let compressed = crate::mesh::transport::compress(&body).unwrap();
+        assert!(compressed.len() < body.len());
+        assert_eq!(
+            crate::mesh::transport::decompress(&compressed).unwrap(),
+            body
+        );
+    }
+
     #[test]
     fn test_sending_encrypted_message() {
         let associated_data = b"";
@@ -0,0 +1,364 @@
+//! Import of a key share generated outside of this network, e.g. when migrating from another MPC
+//! implementation, instead of generating one with the other participants. The share is written to
+//! the secret storage of the node, which then starts at the epoch given for it on its next start.
+//!
+//! Before the share is written, it is checked against the contract, whose public key it has to be a
+//! share of, and against the shares of the other participants: every running node advertises the
+//! commitment to its share in its handshake, see [`crate::mesh::transport::Handshake`], and the
+//! commitments of at least `threshold` participants, ours included, have to interpolate to the
+//! public key. When the participants all import their shares at once, none of them runs yet, and
+//! the check against the other participants can be skipped with `--allow-unverified`.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use cait_sith::protocol::Participant;
+use crypto_shared::{PublicKey, ScalarExt};
+use k256::elliptic_curve::sec1::FromEncodedPoint;
+use k256::{EncodedPoint, ProjectivePoint, Scalar};
+use mpc_keys::hpke;
+use near_account_id::AccountId;
+use zeroize::Zeroizing;
+
+use crate::gcp::GcpService;
+use crate::mesh::transport::Handshake;
+use crate::protocol::contract::primitives::Participants;
+use crate::protocol::registry;
+use crate::protocol::state::PersistentNodeData;
+use crate::protocol::ProtocolState;
+use crate::util::NearPublicKeyExt;
+use crate::{rpc_client, storage};
+
+/// Time given to a participant to respond with its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ImportError {
+    #[error("key share is not a valid scalar")]
+    InvalidShare,
+    #[error("public key is not a valid point")]
+    InvalidPublicKey,
+    #[error("{0} is not a participant of the contract")]
+    NotAParticipant(AccountId),
+    #[error("key share is not a share of the key of the contract")]
+    PublicKeyMismatch,
+    #[error("key share is for epoch {epoch} while the contract is in {expected}")]
+    EpochMismatch { epoch: u64, expected: u64 },
+    #[error(
+        "{have} share commitments are available, {threshold} are required to check the key share"
+    )]
+    NotEnoughCommitments { have: usize, threshold: usize },
+    #[error("key share is inconsistent with the shares of the participants {0:?}")]
+    Inconsistent(Vec<Participant>),
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+#[group(id = "import_options")]
+pub struct Options {
+    #[arg(long, env("MPC_ACCOUNT_ID"))]
+    pub account_id: AccountId,
+    /// The cipher secret key of the node, used to write a key share stored on disk.
+    #[arg(long, env("MPC_CIPHER_SK"))]
+    pub cipher_sk: String,
+    #[clap(flatten)]
+    pub storage_options: storage::Options,
+    /// Hex encoded key share to import, as a big-endian scalar.
+    #[arg(long, env("MPC_IMPORT_SHARE"))]
+    pub share: String,
+    /// Public key the share is a share of, hex encoded in SEC1 or in the NEAR `secp256k1:` format.
+    #[arg(long, env("MPC_IMPORT_PUBLIC_KEY"))]
+    pub public_key: String,
+    /// Epoch the node starts at with the imported share, which has to be the one of the contract.
+    #[arg(long, env("MPC_IMPORT_EPOCH"))]
+    pub epoch: u64,
+    /// NEAR RPC address, to check the share against the state of the contract.
+    #[arg(long, env("MPC_NEAR_RPC"))]
+    pub near_rpc: String,
+    #[arg(long, env("MPC_CONTRACT_ID"))]
+    pub mpc_contract_id: AccountId,
+    /// Import the share even though too few participants advertise the commitment to their share
+    /// to check it against.
+    #[arg(long)]
+    pub allow_unverified: bool,
+    /// Overwrite the key share that is already in the secret storage.
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut args = vec![
+            "--account-id".to_string(),
+            self.account_id.to_string(),
+            "--cipher-sk".to_string(),
+            self.cipher_sk,
+            "--share".to_string(),
+            self.share,
+            "--public-key".to_string(),
+            self.public_key,
+            "--epoch".to_string(),
+            self.epoch.to_string(),
+            "--near-rpc".to_string(),
+            self.near_rpc,
+            "--mpc-contract-id".to_string(),
+            self.mpc_contract_id.to_string(),
+        ];
+        if self.allow_unverified {
+            args.push("--allow-unverified".to_string());
+        }
+        if self.force {
+            args.push("--force".to_string());
+        }
+        args.extend(self.storage_options.into_str_args());
+        args
+    }
+
+    /// The node data made of the imported share.
+    pub fn node_data(&self) -> Result<PersistentNodeData, ImportError> {
+        Ok(PersistentNodeData {
+            epoch: self.epoch,
+            private_share: Zeroizing::new(parse_share(&self.share)?),
+            public_key: parse_public_key(&self.public_key)?,
+        })
+    }
+}
+
+pub fn parse_share(share: &str) -> Result<Scalar, ImportError> {
+    let bytes = Zeroizing::new(hex::decode(share).map_err(|_| ImportError::InvalidShare)?);
+    let bytes: [u8; 32] = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| ImportError::InvalidShare)?;
+    let share = Scalar::from_bytes(bytes).ok_or(ImportError::InvalidShare)?;
+    if share == Scalar::ZERO {
+        return Err(ImportError::InvalidShare);
+    }
+    Ok(share)
+}
+
+pub fn parse_public_key(public_key: &str) -> Result<PublicKey, ImportError> {
+    if public_key.starts_with("secp256k1:") {
+        let public_key = near_crypto::PublicKey::from_str(public_key)
+            .map_err(|_| ImportError::InvalidPublicKey)?;
+        return Ok(public_key.into_affine_point());
+    }
+    let bytes = hex::decode(public_key).map_err(|_| ImportError::InvalidPublicKey)?;
+    let point = EncodedPoint::from_bytes(bytes).map_err(|_| ImportError::InvalidPublicKey)?;
+    PublicKey::from_encoded_point(&point)
+        .into_option()
+        .ok_or(ImportError::InvalidPublicKey)
+}
+
+/// Interpolates the commitments to the shares of the participants at zero, in the exponent. The
+/// share of a participant is the evaluation of the polynomial at its id plus one, as in cait-sith.
+fn interpolate(commitments: &BTreeMap<Participant, PublicKey>) -> ProjectivePoint {
+    let xs: Vec<Scalar> = commitments
+        .keys()
+        .map(|p| Scalar::from(u32::from(*p) as u64 + 1))
+        .collect();
+    commitments
+        .values()
+        .enumerate()
+        .map(|(i, commitment)| {
+            let lambda =
+                xs.iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .fold(Scalar::ONE, |acc, (_, xj)| {
+                        // The ids of the participants are distinct, so the difference is never zero.
+                        acc * xj * (*xj - xs[i]).invert().unwrap()
+                    });
+            ProjectivePoint::from(*commitment) * lambda
+        })
+        .sum()
+}
+
+/// Checks that the commitments to the shares of the participants, ours included, are shares of
+/// the public key. Every commitment takes part, so a single inconsistent share is caught as long
+/// as `threshold` commitments are given.
+pub fn verify_commitments(
+    public_key: &PublicKey,
+    threshold: usize,
+    commitments: &BTreeMap<Participant, PublicKey>,
+) -> Result<(), ImportError> {
+    if commitments.len() < threshold {
+        return Err(ImportError::NotEnoughCommitments {
+            have: commitments.len(),
+            threshold,
+        });
+    }
+    if interpolate(commitments) != ProjectivePoint::from(*public_key) {
+        return Err(ImportError::Inconsistent(
+            commitments.keys().copied().collect(),
+        ));
+    }
+    Ok(())
+}
+
+/// Fetches the commitments advertised by the participants other than `me`. The ones that do not
+/// respond, or do not run yet, are left out.
+async fn fetch_commitments(
+    participants: &Participants,
+    me: Participant,
+) -> BTreeMap<Participant, PublicKey> {
+    let client = reqwest::Client::new();
+    let mut commitments = BTreeMap::new();
+    for (participant, info) in participants.iter() {
+        if *participant == me {
+            continue;
+        }
+        let url = format!("{}/handshake", info.url.trim_end_matches('/'));
+        let handshake = client
+            .get(&url)
+            .timeout(HANDSHAKE_TIMEOUT)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        let handshake = match handshake {
+            Ok(resp) => resp.json::<Handshake>().await,
+            Err(err) => Err(err),
+        };
+        match handshake {
+            Ok(Handshake {
+                share_commitment: Some(commitment),
+                ..
+            }) => {
+                commitments.insert(*participant, commitment);
+            }
+            Ok(_) => {
+                tracing::warn!(
+                    ?participant,
+                    "participant does not advertise a share commitment"
+                )
+            }
+            Err(err) => tracing::warn!(?participant, ?err, "failed to fetch handshake"),
+        }
+    }
+    commitments
+}
+
+pub fn run(options: Options) -> anyhow::Result<()> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async {
+        let data = options.node_data()?;
+        let rpc_client = near_fetch::Client::new(&options.near_rpc);
+        let contract_state =
+            rpc_client::fetch_mpc_contract_state(&rpc_client, &options.mpc_contract_id).await?;
+        let ProtocolState::Running(contract_state) = &contract_state else {
+            anyhow::bail!("contract is not running, import the key share once it is");
+        };
+        if contract_state.public_key != data.public_key {
+            return Err(ImportError::PublicKeyMismatch.into());
+        }
+        if contract_state.epoch != data.epoch {
+            return Err(ImportError::EpochMismatch {
+                epoch: data.epoch,
+                expected: contract_state.epoch,
+            }
+            .into());
+        }
+        let me = contract_state
+            .participants
+            .find_participant(&options.account_id)
+            .ok_or_else(|| ImportError::NotAParticipant(options.account_id.clone()))?;
+        let mut commitments = fetch_commitments(&contract_state.participants, me).await;
+        commitments.insert(me, registry::share_commitment(&data.private_share));
+        match verify_commitments(&data.public_key, contract_state.threshold, &commitments) {
+            Ok(()) => {}
+            Err(err @ ImportError::NotEnoughCommitments { .. }) if options.allow_unverified => {
+                tracing::warn!(%err, "importing a key share that could not be checked");
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let cipher_sk = hpke::SecretKey::try_from_bytes(&hex::decode(&options.cipher_sk)?)?;
+        let gcp_service = GcpService::init(&options.account_id, &options.storage_options).await?;
+        let mut key_storage = storage::secret_storage::init(
+            Some(&gcp_service),
+            &options.storage_options,
+            &options.account_id,
+            &cipher_sk,
+        )
+        .await;
+        if let Some(existing) = key_storage.load().await? {
+            if !options.force {
+                anyhow::bail!(
+                    "a key share of epoch {} is already stored, pass --force to overwrite it",
+                    existing.epoch
+                );
+            }
+        }
+        key_storage.store(&data).await?;
+        tracing::info!(
+            target: "audit",
+            account_id = %options.account_id,
+            epoch = data.epoch,
+            verified_with = commitments.len(),
+            "imported key share"
+        );
+        anyhow::Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_commitments() {
+        // Shares of the secret 7 with a threshold of two, on the polynomial 7 + 5x.
+        let secret = Scalar::from(7u64);
+        let public_key = (ProjectivePoint::GENERATOR * secret).to_affine();
+        let mut commitments: BTreeMap<_, _> = (0u32..3)
+            .map(|id| {
+                let x = Scalar::from(id as u64 + 1);
+                let share = secret + Scalar::from(5u64) * x;
+                (Participant::from(id), registry::share_commitment(&share))
+            })
+            .collect();
+        assert_eq!(verify_commitments(&public_key, 2, &commitments), Ok(()));
+
+        let single: BTreeMap<_, _> = commitments.clone().into_iter().take(1).collect();
+        assert_eq!(
+            verify_commitments(&public_key, 2, &single),
+            Err(ImportError::NotEnoughCommitments {
+                have: 1,
+                threshold: 2
+            })
+        );
+
+        commitments.insert(
+            Participant::from(1u32),
+            registry::share_commitment(&Scalar::from(42u64)),
+        );
+        assert!(matches!(
+            verify_commitments(&public_key, 2, &commitments),
+            Err(ImportError::Inconsistent(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_share_and_public_key() {
+        let share = parse_share(&format!("{:064x}", 42)).unwrap();
+        assert_eq!(share, Scalar::from(42u64));
+        assert_eq!(parse_share("00"), Err(ImportError::InvalidShare));
+        assert_eq!(
+            parse_share(&"ff".repeat(32)),
+            Err(ImportError::InvalidShare)
+        );
+
+        let public_key = registry::share_commitment(&share);
+        let sec1 = k256::elliptic_curve::sec1::ToEncodedPoint::to_encoded_point(&public_key, true);
+        assert_eq!(
+            parse_public_key(&hex::encode(sec1.as_bytes())),
+            Ok(public_key)
+        );
+        assert_eq!(
+            parse_public_key("secp256k1:nope"),
+            Err(ImportError::InvalidPublicKey)
+        );
+    }
+}
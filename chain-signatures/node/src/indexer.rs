@@ -1,8 +1,13 @@
 use crate::gcp::error::DatastoreStorageError;
 use crate::gcp::GcpService;
+use crate::kdf;
+use crate::protocol::signature::{SignPriority, SIGN_REQUEST_DEADLINE};
 use crate::protocol::{SignQueue, SignRequest};
+use crate::redact::{UserData, UserScalar};
+use crate::storage::sign_request_storage::{LockSignRequestNodeStorageBox, SignRequestRecord};
 use crate::types::LatestBlockHeight;
-use crypto_shared::{derive_epsilon, ScalarExt};
+use crypto_shared::hashing::{HashScheme, MAX_MESSAGE_LEN};
+use crypto_shared::ScalarExt;
 use k256::Scalar;
 use near_account_id::AccountId;
 use near_lake_framework::{LakeBuilder, LakeContext};
@@ -10,7 +15,9 @@ use near_lake_primitives::actions::ActionMetaDataExt;
 use near_lake_primitives::receipts::ExecutionStatus;
 
 use near_primitives::types::BlockHeight;
+use near_sdk::NearToken;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::ops::Mul;
 use std::sync::Arc;
 use std::thread::JoinHandle;
@@ -91,18 +98,70 @@ struct UnvalidatedContractSignRequest {
     pub key_version: u32,
 }
 
-/// A validated version of the sign request
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct SignMessageArguments {
+    request: UnvalidatedSignMessageRequest,
+}
+
+/// What is recieved when sign_message is called
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct UnvalidatedSignMessageRequest {
+    pub message: Vec<u8>,
+    pub hashing: HashScheme,
+    pub path: String,
+    pub key_version: u32,
+}
+
+impl UnvalidatedSignMessageRequest {
+    /// The request for the digest of the message, computed the same way as by the contract.
+    fn into_sign_request(self) -> Option<UnvalidatedContractSignRequest> {
+        if self.message.is_empty() || self.message.len() > MAX_MESSAGE_LEN {
+            return None;
+        }
+        Some(UnvalidatedContractSignRequest {
+            payload: self.hashing.digest(&self.message),
+            path: self.path,
+            key_version: self.key_version,
+        })
+    }
+}
+
+/// A validated version of the sign request
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct ContractSignRequest {
     pub payload: Scalar,
     pub path: String,
     pub key_version: u32,
 }
 
+impl fmt::Debug for ContractSignRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContractSignRequest")
+            .field("payload", &UserScalar(&self.payload))
+            .field("path", &UserData::str(&self.path))
+            .field("key_version", &self.key_version)
+            .finish()
+    }
+}
+
+/// Methods of the contract that change the participant set, the key or the config when they
+/// succeed.
+const STATE_CHANGING_METHODS: &[&str] = &[
+    "join",
+    "vote_join",
+    "vote_leave",
+    "vote_pk",
+    "vote_reshared",
+    "vote_update",
+];
+
 #[derive(Debug, Clone)]
 pub struct Indexer {
+    /// The last block that has been fully processed. Indexing resumes from the block after it.
     latest_block_height: Arc<RwLock<LatestBlockHeight>>,
     last_updated_timestamp: Arc<RwLock<Instant>>,
+    /// When the indexer last saw a successful call changing the state of the contract.
+    last_contract_change: Arc<RwLock<Option<Instant>>>,
     running_threshold: Duration,
     behind_threshold: Duration,
 }
@@ -116,6 +175,7 @@ impl Indexer {
         Self {
             latest_block_height: Arc::new(RwLock::new(latest_block_height)),
             last_updated_timestamp: Arc::new(RwLock::new(Instant::now())),
+            last_contract_change: Arc::new(RwLock::new(None)),
             running_threshold: Duration::from_secs(options.running_threshold),
             behind_threshold: Duration::from_secs(options.behind_threshold),
         }
@@ -141,6 +201,15 @@ impl Indexer {
         self.last_updated_timestamp.read().await.elapsed() > self.behind_threshold
     }
 
+    /// Check whether the state of the contract changed on chain since `since`, in which case the
+    /// state known to the node is outdated.
+    pub async fn contract_changed_since(&self, since: Instant) -> bool {
+        self.last_contract_change
+            .read()
+            .await
+            .map_or(false, |changed| changed > since)
+    }
+
     async fn update_block_height(
         &self,
         block_height: BlockHeight,
@@ -163,6 +232,7 @@ struct Context {
     node_account_id: AccountId,
     gcp_service: GcpService,
     queue: Arc<RwLock<SignQueue>>,
+    sign_request_storage: LockSignRequestNodeStorageBox,
     indexer: Indexer,
 }
 
@@ -171,7 +241,19 @@ async fn handle_block(
     ctx: &Context,
 ) -> anyhow::Result<()> {
     tracing::debug!(block_height = block.block_height(), "handle_block");
+    let cursor = ctx.indexer.latest_block_height().await;
+    if block.block_height() <= cursor {
+        // The lake can hand us blocks again after a restart. Their requests are already queued.
+        tracing::debug!(
+            block_height = block.block_height(),
+            cursor,
+            "skipping already indexed block"
+        );
+        return Ok(());
+    }
+
     let mut pending_requests = Vec::new();
+    let mut contract_changed = false;
     for action in block.actions().cloned().collect::<Vec<_>>() {
         if action.receiver_id() == ctx.mpc_contract_id {
             tracing::debug!("got action targeting {}", ctx.mpc_contract_id);
@@ -183,32 +265,58 @@ async fn handle_block(
                 tracing::warn!("{err}");
                 anyhow::bail!(err);
             };
-            let ExecutionStatus::SuccessReceiptId(receipt_id) = receipt.status() else {
+            let Some(function_call) = action.as_function_call() else {
                 continue;
             };
-            let Some(function_call) = action.as_function_call() else {
+            if STATE_CHANGING_METHODS.contains(&function_call.method_name())
+                && matches!(
+                    receipt.status(),
+                    ExecutionStatus::SuccessValue(_) | ExecutionStatus::SuccessReceiptId(_)
+                )
+            {
+                tracing::info!(
+                    method = function_call.method_name(),
+                    caller_id = receipt.predecessor_id().to_string(),
+                    "indexed contract state change"
+                );
+                contract_changed = true;
+                continue;
+            }
+            let ExecutionStatus::SuccessReceiptId(receipt_id) = receipt.status() else {
                 continue;
             };
-            if function_call.method_name() == "sign" {
-                tracing::debug!("found `sign` function call");
-                let arguments =
-                    match serde_json::from_slice::<'_, SignArguments>(function_call.args()) {
-                        Ok(arguments) => arguments,
-                        Err(err) => {
-                            tracing::warn!(%err, "failed to parse `sign` arguments");
-                            continue;
-                        }
-                    };
+            let method = function_call.method_name();
+            if method == "sign" || method == "sign_message" {
+                tracing::debug!("found `{method}` function call");
+                let request = if method == "sign" {
+                    serde_json::from_slice::<'_, SignArguments>(function_call.args())
+                        .map(|arguments| Some(arguments.request))
+                } else {
+                    // The message is hashed here the same as the contract did before accepting it.
+                    serde_json::from_slice::<'_, SignMessageArguments>(function_call.args())
+                        .map(|arguments| arguments.request.into_sign_request())
+                };
+                let request = match request {
+                    Ok(Some(request)) => request,
+                    Ok(None) => {
+                        tracing::warn!("`{method}` message is too long");
+                        continue;
+                    }
+                    Err(err) => {
+                        tracing::warn!(%err, "failed to parse `{method}` arguments");
+                        continue;
+                    }
+                };
 
                 if receipt.logs().is_empty() {
-                    tracing::warn!("`sign` did not produce entropy");
+                    tracing::warn!("`{method}` did not produce entropy");
                     continue;
                 }
 
-                let Some(payload) = Scalar::from_bytes(arguments.request.payload) else {
+                let Some(payload) = Scalar::from_bytes(request.payload) else {
                     tracing::warn!(
-                        "`sign` did not produce payload correctly: {:?}",
-                        arguments.request.payload,
+                        "`{method}` did not produce payload correctly: {:?}",
+                        request.payload,
                     );
                     continue;
                 };
@@ -218,56 +326,89 @@ async fn handle_block(
                     serde_json::from_str::<'_, [u8; 32]>(&receipt.logs()[entropy_log_index])
                 else {
                     tracing::warn!(
-                        "`sign` did not produce entropy correctly: {:?}",
+                        "`{method}` did not produce entropy correctly: {:?}",
                         receipt.logs()[entropy_log_index]
                     );
                     continue;
                 };
-                let epsilon = derive_epsilon(&action.predecessor_id(), &arguments.request.path);
+                let epsilon = kdf::cached_epsilon(&action.predecessor_id(), &request.path);
                 tracing::info!(
                     receipt_id = %receipt_id,
                     caller_id = receipt.predecessor_id().to_string(),
                     our_account = ctx.node_account_id.to_string(),
-                    payload = hex::encode(arguments.request.payload),
-                    key_version = arguments.request.key_version,
+                    payload = hex::encode(request.payload),
+                    key_version = request.key_version,
                     entropy = hex::encode(entropy),
-                    "indexed new `sign` function call"
+                    "indexed new `{method}` function call"
                 );
                 let request = ContractSignRequest {
                     payload,
-                    path: arguments.request.path,
-                    key_version: arguments.request.key_version,
+                    path: request.path,
+                    key_version: request.key_version,
                 };
+                // TODO: use indexer timestamp instead.
+                let time_added = Instant::now();
                 pending_requests.push(SignRequest {
                     receipt_id,
                     request,
                     epsilon,
                     entropy,
-                    // TODO: use indexer timestamp instead.
-                    time_added: Instant::now(),
+                    time_added,
+                    priority: SignPriority::from_deposit(NearToken::from_yoctonear(
+                        function_call.deposit(),
+                    )),
+                    deadline: time_added + SIGN_REQUEST_DEADLINE,
+                    merged: Vec::new(),
                 });
             }
         }
     }
 
-    ctx.indexer
-        .update_block_height(block.block_height(), &ctx.gcp_service)
-        .await?;
-
-    crate::metrics::LATEST_BLOCK_HEIGHT
-        .with_label_values(&[ctx.gcp_service.account_id.as_str()])
-        .set(block.block_height() as i64);
-
     // Add the requests after going through the whole block to avoid partial processing if indexer fails somewhere.
     // This way we can revisit the same block if we failed while not having added the requests partially.
+    // Requests are persisted before being queued, such that they get queued again after a crash,
+    // and the ones already known are not queued again when their block is revisited.
+    let mut storage = ctx.sign_request_storage.write().await;
     let mut queue = ctx.queue.write().await;
     for request in pending_requests {
+        match storage.insert(SignRequestRecord::indexed(&request)).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!(
+                    receipt_id = %request.receipt_id,
+                    "skipping sign request that was already indexed"
+                );
+                continue;
+            }
+            Err(err) => {
+                tracing::warn!(receipt_id = %request.receipt_id, ?err, "failed to persist sign request");
+            }
+        }
         queue.add(request);
         crate::metrics::NUM_SIGN_REQUESTS
             .with_label_values(&[ctx.gcp_service.account_id.as_str()])
             .inc();
     }
     drop(queue);
+    drop(storage);
+    if contract_changed {
+        *ctx.indexer.last_contract_change.write().await = Some(Instant::now());
+    }
+
+    // Only move the cursor once the block has been fully processed. Failing to persist it does not
+    // undo the processing, so the block is not revisited while running; only a restart of the
+    // node would index it again, and its requests are known to the sign request storage by then.
+    if let Err(err) = ctx
+        .indexer
+        .update_block_height(block.block_height(), &ctx.gcp_service)
+        .await
+    {
+        tracing::warn!(%err, block_height = block.block_height(), "failed to persist indexer cursor");
+    }
+
+    crate::metrics::LATEST_BLOCK_HEIGHT
+        .with_label_values(&[ctx.gcp_service.account_id.as_str()])
+        .set(block.block_height() as i64);
 
     let log_indexing_interval = 1000;
     if block.block_height() % log_indexing_interval == 0 {
@@ -286,6 +427,7 @@ pub fn run(
     mpc_contract_id: &AccountId,
     node_account_id: &AccountId,
     queue: &Arc<RwLock<SignQueue>>,
+    sign_request_storage: &LockSignRequestNodeStorageBox,
     gcp_service: &crate::gcp::GcpService,
     rt: &tokio::runtime::Runtime,
 ) -> anyhow::Result<(JoinHandle<anyhow::Result<()>>, Indexer)> {
@@ -303,9 +445,10 @@ pub fn run(
             Ok(latest) => latest,
             Err(err) => {
                 tracing::warn!(%err, "failed to fetch latest block height; using start_block_height={} instead", options.start_block_height);
+                // Nothing has been processed yet, so the start block itself gets indexed.
                 LatestBlockHeight {
                     account_id: node_account_id.clone(),
-                    block_height: options.start_block_height,
+                    block_height: options.start_block_height.saturating_sub(1),
                 }
             }
         }
@@ -317,6 +460,7 @@ pub fn run(
         node_account_id: node_account_id.clone(),
         gcp_service: gcp_service.clone(),
         queue: queue.clone(),
+        sign_request_storage: sign_request_storage.clone(),
         indexer: indexer.clone(),
     };
 
@@ -342,7 +486,7 @@ pub fn run(
                 let mut lake_builder = LakeBuilder::default()
                     .s3_bucket_name(&options.s3_bucket)
                     .s3_region_name(&options.s3_region)
-                    .start_block_height(latest);
+                    .start_block_height(latest + 1);
 
                 if let Some(s3_url) = &options.s3_url {
                     let aws_config = aws_config::from_env().load().await;
@@ -416,3 +560,38 @@ fn backoff(i: u32, multiplier: u32, max: u64) {
     let delay: u64 = std::cmp::min(2u64.pow(i).mul(multiplier as u64), max);
     std::thread::sleep(std::time::Duration::from_secs(delay));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_message_into_sign_request() {
+        let arguments: SignMessageArguments = serde_json::from_value(serde_json::json!({
+            "request": {
+                "message": b"hello".to_vec(),
+                "hashing": "eth_personal_message",
+                "path": "test",
+                "key_version": 0,
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            arguments.request.clone().into_sign_request(),
+            Some(UnvalidatedContractSignRequest {
+                payload: HashScheme::EthPersonalMessage.digest(b"hello"),
+                path: "test".to_string(),
+                key_version: 0,
+            })
+        );
+
+        // Rejected the same way as by the contract.
+        for message in [Vec::new(), vec![0; MAX_MESSAGE_LEN + 1]] {
+            let request = UnvalidatedSignMessageRequest {
+                message,
+                ..arguments.request.clone()
+            };
+            assert_eq!(request.into_sign_request(), None);
+        }
+    }
+}
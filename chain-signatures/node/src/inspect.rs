@@ -0,0 +1,149 @@
+//! Client side of the admin server, used by the `state` subcommand to let operators inspect the
+//! state of a running node from the command line.
+
+use std::io::Write;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use url::Url;
+
+use crate::mesh::bandwidth::Traffic;
+use crate::web::admin::{
+    GeneratorsView, PeersView, PresignaturesView, SignaturesView, TriplesView,
+};
+
+#[derive(Debug, Clone, Copy, clap::Subcommand)]
+pub enum Command {
+    /// Prints everything the admin server exposes as a single JSON document.
+    Dump,
+    /// Prints the triples owned by the node.
+    Triples,
+    /// Prints the presignatures owned by the node.
+    Presignatures,
+    /// Prints the participants of the current epoch as seen by the node.
+    Peers,
+}
+
+impl Command {
+    pub fn into_str_args(self) -> Vec<String> {
+        let command = match self {
+            Command::Dump => "dump",
+            Command::Triples => "triples",
+            Command::Presignatures => "presignatures",
+            Command::Peers => "peers",
+        };
+        vec![command.to_string()]
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Dump {
+    triples: TriplesView,
+    presignatures: PresignaturesView,
+    generators: GeneratorsView,
+    signatures: SignaturesView,
+    peers: PeersView,
+}
+
+fn fetch<T: DeserializeOwned>(admin_url: &Url, path: &str) -> anyhow::Result<T> {
+    let url = admin_url.join(path)?;
+    let response = reqwest::blocking::get(url.clone())?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("{url} responded with {status}: {}", response.text()?);
+    }
+    Ok(response.json()?)
+}
+
+fn print_json(value: &impl Serialize, mut out: impl Write) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(&mut out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Queries the admin server at `admin_url` and writes the result to `out`, either as JSON or as
+/// a table meant to be read by a person.
+pub fn run(
+    admin_url: &Url,
+    command: Command,
+    json: bool,
+    mut out: impl Write,
+) -> anyhow::Result<()> {
+    match command {
+        Command::Dump => {
+            let dump = Dump {
+                triples: fetch(admin_url, "state/triples")?,
+                presignatures: fetch(admin_url, "state/presignatures")?,
+                generators: fetch(admin_url, "state/generators")?,
+                signatures: fetch(admin_url, "state/signatures")?,
+                peers: fetch(admin_url, "state/peers")?,
+            };
+            print_json(&dump, out)?;
+        }
+        Command::Triples => {
+            let view: TriplesView = fetch(admin_url, "state/triples")?;
+            if json {
+                return print_json(&view, out);
+            }
+            writeln!(out, "triples: {} (mine: {})", view.count, view.mine_count)?;
+            writeln!(out, "{:<22} MINE", "ID")?;
+            for triple in view.triples {
+                writeln!(out, "{:<22} {}", triple.id, triple.mine)?;
+            }
+        }
+        Command::Presignatures => {
+            let view: PresignaturesView = fetch(admin_url, "state/presignatures")?;
+            if json {
+                return print_json(&view, out);
+            }
+            writeln!(
+                out,
+                "presignatures: {} (mine: {}, reserved: {})",
+                view.count,
+                view.mine_count,
+                view.reserved.len()
+            )?;
+            writeln!(out, "{:<22} MINE", "ID")?;
+            for presignature in view.presignatures {
+                writeln!(out, "{:<22} {}", presignature.id, presignature.mine)?;
+            }
+            for id in view.reserved {
+                writeln!(out, "{id:<22} reserved")?;
+            }
+        }
+        Command::Peers => {
+            let view: PeersView = fetch(admin_url, "state/peers")?;
+            if json {
+                return print_json(&view, out);
+            }
+            writeln!(out, "epoch: {}, threshold: {}", view.epoch, view.threshold)?;
+            writeln!(
+                out,
+                "{:<4} {:<40} {:<8} {:<12} {:<12} {:<14} {:<14} URL",
+                "ID", "ACCOUNT", "PENDING", "LAST ACK MS", "MISBEHAVED", "SENT BYTES", "RECV BYTES"
+            )?;
+            for peer in view.peers {
+                let last_ack = peer
+                    .last_ack_ms
+                    .map_or_else(|| "-".to_string(), |ms| ms.to_string());
+                let mut traffic = Traffic::default();
+                for protocol in peer.traffic.values() {
+                    traffic.add(protocol);
+                }
+                writeln!(
+                    out,
+                    "{:<4} {:<40} {:<8} {:<12} {:<12} {:<14} {:<14} {}",
+                    u32::from(peer.participant),
+                    peer.account_id,
+                    peer.pending_messages,
+                    last_ack,
+                    peer.misbehaviors,
+                    traffic.sent_bytes,
+                    traffic.received_bytes,
+                    peer.url
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Append-only journal of protocol events, written as JSON lines. Tracing logs are too lossy to
+//! reconstruct why a specific triple, presignature or signature went missing on a node, so the
+//! journal records the whole lifecycle of every protocol keyed by epoch and protocol id. The
+//! journal can be filtered with the `journal` subcommand.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use cait_sith::protocol::Participant;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static JOURNAL: OnceCell<Mutex<LineWriter<File>>> = OnceCell::new();
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolKind {
+    Triple,
+    Presignature,
+    Signature,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started { participants: Vec<Participant> },
+    Sent { to: Participant },
+    Received { from: Participant },
+    Completed,
+    TimedOut,
+    Failed { reason: String },
+    Aborted { reason: String },
+    Taken,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    pub epoch: u64,
+    pub protocol: ProtocolKind,
+    pub id: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Opens the journal at `path` in append mode. Events are only recorded once this is called.
+pub fn init(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    if JOURNAL.set(Mutex::new(LineWriter::new(file))).is_err() {
+        tracing::warn!(?path, "protocol journal is already initialized");
+    }
+    Ok(())
+}
+
+/// Appends an event to the journal. Does nothing if the journal is not enabled.
+pub fn record(epoch: u64, protocol: ProtocolKind, id: impl ToString, event: Event) {
+    let Some(journal) = JOURNAL.get() else {
+        return;
+    };
+    let entry = Entry {
+        timestamp: Utc::now().timestamp_millis(),
+        epoch,
+        protocol,
+        id: id.to_string(),
+        event,
+    };
+    let mut line = match serde_json::to_vec(&entry) {
+        Ok(line) => line,
+        Err(err) => {
+            tracing::warn!(?err, "failed to serialize protocol journal entry");
+            return;
+        }
+    };
+    line.push(b'\n');
+    let mut journal = journal
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Err(err) = journal.write_all(&line) {
+        tracing::warn!(?err, "failed to write to protocol journal");
+    }
+}
+
+/// Filters the entries printed by the `journal` subcommand.
+#[derive(Debug, Clone, clap::Parser)]
+#[group(id = "journal_filter")]
+pub struct Filter {
+    /// Only print entries of this epoch.
+    #[arg(long)]
+    pub epoch: Option<u64>,
+    /// Only print entries of this kind of protocol.
+    #[arg(long, value_enum)]
+    pub protocol: Option<ProtocolKind>,
+    /// Only print entries of the protocol with this id.
+    #[arg(long)]
+    pub id: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.epoch.map_or(true, |epoch| entry.epoch == epoch)
+            && self
+                .protocol
+                .map_or(true, |protocol| entry.protocol == protocol)
+            && self.id.as_ref().map_or(true, |id| &entry.id == id)
+    }
+
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(epoch) = self.epoch {
+            args.extend(["--epoch".to_string(), epoch.to_string()]);
+        }
+        if let Some(protocol) = self.protocol {
+            let protocol = clap::ValueEnum::to_possible_value(&protocol).unwrap();
+            args.extend(["--protocol".to_string(), protocol.get_name().to_string()]);
+        }
+        if let Some(id) = self.id {
+            args.extend(["--id".to_string(), id]);
+        }
+        args
+    }
+}
+
+/// Writes the entries of the journal at `path` matching `filter` to `out`, returning the number
+/// of entries written. Lines that cannot be parsed, e.g. one cut short by a crash, are skipped.
+pub fn replay(path: &Path, filter: &Filter, mut out: impl Write) -> anyhow::Result<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut written = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let entry: Entry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::warn!(line = number + 1, ?err, "skipping malformed journal entry");
+                continue;
+            }
+        };
+        if filter.matches(&entry) {
+            writeln!(out, "{line}")?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
@@ -1,9 +1,75 @@
-use anyhow::Context;
-use crypto_shared::{kdf::recover, x_coordinate, ScalarExt, SignatureResponse};
+use crypto_shared::{
+    derive_epsilon, derive_key, FullSignature, PublicKey, ScalarExt, SignatureResponse,
+};
 use hkdf::Hkdf;
-use k256::{ecdsa::RecoveryId, elliptic_curve::sec1::ToEncodedPoint, AffinePoint, Scalar};
+use k256::{elliptic_curve::sec1::ToEncodedPoint, AffinePoint, Scalar};
+use lru::LruCache;
+use near_account_id::AccountId;
 use near_primitives::hash::CryptoHash;
+use once_cell::sync::Lazy;
 use sha3::Sha3_256;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// Maximum number of derivation paths, and of derived public keys, whose tweaks are cached.
+const TWEAK_CACHE_CAPACITY: usize = 4096;
+
+/// Tweaks derived for recent sign requests. The epsilon of a request is derived from its
+/// predecessor and path, and the public key it is signed for from that epsilon, both of which get
+/// repeated for every request of the same account and path, and at several stages of signing.
+struct Tweaks {
+    /// Root public key the derived public keys are cached for.
+    root: Option<PublicKey>,
+    epsilons: LruCache<(AccountId, String), Scalar>,
+    keys: LruCache<[u8; 32], PublicKey>,
+}
+
+static TWEAKS: Lazy<Mutex<Tweaks>> = Lazy::new(|| {
+    let capacity = NonZeroUsize::new(TWEAK_CACHE_CAPACITY).unwrap();
+    Mutex::new(Tweaks {
+        root: None,
+        epsilons: LruCache::new(capacity),
+        keys: LruCache::new(capacity),
+    })
+});
+
+fn record_lookup(kind: &str, hit: bool) {
+    crate::metrics::KDF_CACHE_LOOKUPS
+        .with_label_values(&[kind, if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+/// Same as [`derive_epsilon`], cached per predecessor and path.
+pub fn cached_epsilon(predecessor_id: &AccountId, path: &str) -> Scalar {
+    let key = (predecessor_id.clone(), path.to_string());
+    let mut tweaks = TWEAKS.lock().unwrap();
+    if let Some(epsilon) = tweaks.epsilons.get(&key) {
+        record_lookup("epsilon", true);
+        return *epsilon;
+    }
+    record_lookup("epsilon", false);
+    let epsilon = derive_epsilon(predecessor_id, path);
+    tweaks.epsilons.put(key, epsilon);
+    epsilon
+}
+
+/// Same as [`derive_key`], cached per epsilon for the root `public_key`.
+pub fn cached_key(public_key: PublicKey, epsilon: Scalar) -> PublicKey {
+    let key: [u8; 32] = epsilon.to_bytes().into();
+    let mut tweaks = TWEAKS.lock().unwrap();
+    if tweaks.root != Some(public_key) {
+        tweaks.keys.clear();
+        tweaks.root = Some(public_key);
+    }
+    if let Some(derived) = tweaks.keys.get(&key) {
+        record_lookup("public_key", true);
+        return *derived;
+    }
+    record_lookup("public_key", false);
+    let derived = derive_key(public_key, epsilon);
+    tweaks.keys.put(key, derived);
+    derived
+}
 
 // In case there are multiple requests in the same block (hence same entropy), we need to ensure
 // that we generate different random scalars as delta tweaks.
@@ -29,37 +95,34 @@ pub fn derive_delta(
 // near-mpc-recovery with key derivation protocol vX.Y.Z.
 const DELTA_DERIVATION_PREFIX: &str = "near-mpc-recovery v0.1.0 delta derivation:";
 
-// try to get the correct recovery id for this signature by brute force.
+/// The response to a sign request for `msg_hash`, with the recovery id of `public_key`, the key
+/// derived for the request. See [`FullSignature`] for the other encodings of the signature.
 pub fn into_eth_sig(
     public_key: &k256::AffinePoint,
     big_r: &k256::AffinePoint,
     s: &k256::Scalar,
     msg_hash: Scalar,
 ) -> anyhow::Result<SignatureResponse> {
-    let public_key = public_key.to_encoded_point(false);
-    let signature = k256::ecdsa::Signature::from_scalars(x_coordinate(big_r), s)
-        .context("cannot create signature from cait_sith signature")?;
-    let pk0 = recover(
-        &msg_hash.to_bytes()[..],
-        &signature,
-        RecoveryId::try_from(0).context("cannot create recovery_id=0")?,
-    )
-    .context("unable to use 0 as recovery_id to recover public key")?
-    .to_encoded_point(false);
-    if public_key == pk0 {
-        return Ok(SignatureResponse::new(*big_r, *s, 0));
-    }
+    FullSignature::new(*big_r, *s).to_response(public_key, msg_hash)
+}
 
-    let pk1 = recover(
-        &msg_hash.to_bytes()[..],
-        &signature,
-        RecoveryId::try_from(1).context("cannot create recovery_id=1")?,
-    )
-    .context("unable to use 1 as recovery_id to recover public key")?
-    .to_encoded_point(false);
-    if public_key == pk1 {
-        return Ok(SignatureResponse::new(*big_r, *s, 1));
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    anyhow::bail!("cannot use either recovery id (0 or 1) to recover pubic key")
+    #[test]
+    fn test_cached_tweaks_match_derived() {
+        let predecessor: AccountId = "alice.testnet".parse().unwrap();
+        let epsilon = derive_epsilon(&predecessor, "eth/0");
+        assert_eq!(cached_epsilon(&predecessor, "eth/0"), epsilon);
+        assert_eq!(cached_epsilon(&predecessor, "eth/0"), epsilon);
+        assert_ne!(cached_epsilon(&predecessor, "eth/1"), epsilon);
+
+        let root = k256::AffinePoint::GENERATOR;
+        assert_eq!(cached_key(root, epsilon), derive_key(root, epsilon));
+        assert_eq!(cached_key(root, epsilon), derive_key(root, epsilon));
+        // Keys cached for another root are not reused.
+        let other = derive_key(root, Scalar::ONE);
+        assert_eq!(cached_key(other, epsilon), derive_key(other, epsilon));
+    }
 }
@@ -0,0 +1,46 @@
+//! Keyspaces separate the signatures produced under different root keys by the same set of
+//! nodes, e.g. to keep the keys of mainnet and testnet domains apart. Every protocol message of
+//! the running state names the keyspace it belongs to, and the managers only take the messages of
+//! the keyspace they serve.
+//!
+//! The contract holds a single root key and verifies responses against it, so the root keyspace
+//! is the only one served for now. Messages of nodes that predate keyspaces belong to it.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies a keyspace. The root keyspace has an empty id, such that messages of the root
+/// keyspace do not carry it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeyspaceId(String);
+
+impl KeyspaceId {
+    /// The keyspace of the root key of the contract.
+    pub const fn root() -> Self {
+        Self(String::new())
+    }
+
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for KeyspaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            f.write_str("root")
+        } else {
+            f.write_str(&self.0)
+        }
+    }
+}
@@ -1,15 +1,39 @@
+pub mod attestation;
+pub mod audit;
+pub mod backup;
 pub mod cli;
+pub mod clock;
+pub mod cluster;
 pub mod config;
+pub mod events;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
 pub mod gcp;
+#[cfg(any(test, feature = "testing"))]
+pub mod harness;
 pub mod http_client;
+pub mod import;
 pub mod indexer;
+pub mod inspect;
+pub mod journal;
 pub mod kdf;
+pub mod keyspace;
 pub mod mesh;
 pub mod metrics;
+pub mod pause;
 pub mod protocol;
+pub mod redact;
+pub mod rng;
 pub mod rpc_client;
+pub mod shedding;
+pub mod shutdown;
+pub mod slo;
+pub mod snapshot;
 pub mod storage;
+pub mod telemetry;
 pub mod test_utils;
+#[cfg(any(test, feature = "testing"))]
+pub mod transcript;
 pub mod types;
 pub mod util;
 pub mod web;
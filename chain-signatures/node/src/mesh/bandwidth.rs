@@ -0,0 +1,144 @@
+//! Accounting of the bytes exchanged with every participant, per type of protocol message, and
+//! throttling of the bytes sent to them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use cait_sith::protocol::Participant;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::scheduler::TokenBucket;
+
+/// Size of the largest batch of messages sent at once, see `partition_ciphered_256kb`.
+const MAX_BATCH_BYTES: u64 = 256 * 1024;
+
+static LEDGER: Lazy<Mutex<HashMap<Participant, BTreeMap<&'static str, Traffic>>>> =
+    Lazy::new(Default::default);
+
+/// Traffic exchanged with a participant for one type of message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Traffic {
+    pub sent_bytes: u64,
+    pub sent_messages: u64,
+    pub received_bytes: u64,
+    pub received_messages: u64,
+}
+
+impl Traffic {
+    pub fn add(&mut self, other: &Traffic) {
+        self.sent_bytes += other.sent_bytes;
+        self.sent_messages += other.sent_messages;
+        self.received_bytes += other.received_bytes;
+        self.received_messages += other.received_messages;
+    }
+}
+
+/// Records a message of `bytes` encrypted bytes delivered to the participant.
+pub fn record_sent(participant: Participant, typename: &'static str, bytes: usize) {
+    let mut ledger = LEDGER.lock().unwrap();
+    let traffic = ledger
+        .entry(participant)
+        .or_default()
+        .entry(typename)
+        .or_default();
+    traffic.sent_bytes += bytes as u64;
+    traffic.sent_messages += 1;
+    crate::metrics::MESSAGE_BYTES_SENT
+        .with_label_values(&[&u32::from(participant).to_string(), typename])
+        .inc_by(bytes as f64);
+}
+
+/// Records a message of `bytes` encrypted bytes received from the participant.
+pub fn record_received(participant: Participant, typename: &'static str, bytes: usize) {
+    let mut ledger = LEDGER.lock().unwrap();
+    let traffic = ledger
+        .entry(participant)
+        .or_default()
+        .entry(typename)
+        .or_default();
+    traffic.received_bytes += bytes as u64;
+    traffic.received_messages += 1;
+    crate::metrics::MESSAGE_BYTES_RECEIVED
+        .with_label_values(&[&u32::from(participant).to_string(), typename])
+        .inc_by(bytes as f64);
+}
+
+/// Traffic exchanged with the participant since the node started, per type of message.
+pub fn traffic(participant: &Participant) -> BTreeMap<String, Traffic> {
+    LEDGER
+        .lock()
+        .unwrap()
+        .get(participant)
+        .map(|traffic| {
+            traffic
+                .iter()
+                .map(|(typename, traffic)| (typename.to_string(), *traffic))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Limits the rate at which bytes are sent to each participant, so that a burst of messages for
+/// one participant does not hold up the others.
+#[derive(Default)]
+pub struct Throttle {
+    /// Bytes per second that can be sent to a single participant, if limited.
+    rate: Option<u64>,
+    buckets: HashMap<Participant, TokenBucket>,
+}
+
+impl Throttle {
+    /// Changes the rate, starting the participants over with a full bucket if it changed.
+    pub fn set_rate(&mut self, rate: Option<u64>) {
+        if self.rate != rate {
+            self.rate = rate;
+            self.buckets.clear();
+        }
+    }
+
+    /// Takes `bytes` out of the budget of the participant, returning false without taking
+    /// anything if the budget is not large enough right now.
+    pub fn try_take(&mut self, participant: Participant, bytes: usize, now: Instant) -> bool {
+        let Some(rate) = self.rate else {
+            return true;
+        };
+        // A batch has to fit in the bucket, or it would never be sent.
+        let burst = rate.max(MAX_BATCH_BYTES) as usize;
+        let bucket = self
+            .buckets
+            .entry(participant)
+            .or_insert_with(|| TokenBucket::new(rate as f64, burst));
+        if bucket.available(now) < bytes.min(burst) {
+            return false;
+        }
+        bucket.take(bytes);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use cait_sith::protocol::Participant;
+
+    use super::{Throttle, MAX_BATCH_BYTES};
+
+    #[test]
+    fn test_throttle() {
+        let (a, b) = (Participant::from(0u32), Participant::from(1u32));
+        let mut throttle = Throttle::default();
+        assert!(throttle.try_take(a, usize::MAX, Instant::now()));
+
+        // The bucket holds at least a full batch, even when the rate is lower.
+        throttle.set_rate(Some(1024));
+        let now = Instant::now();
+        assert!(throttle.try_take(a, MAX_BATCH_BYTES as usize, now));
+        assert!(!throttle.try_take(a, 2048, now));
+        // Other participants have a budget of their own.
+        assert!(throttle.try_take(b, 2048, now));
+        assert!(throttle.try_take(a, 2048, now + Duration::from_secs(2)));
+    }
+}
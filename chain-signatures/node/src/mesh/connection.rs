@@ -5,12 +5,26 @@ use cait_sith::protocol::Participant;
 use tokio::sync::RwLock;
 use url::Url;
 
-use crate::protocol::contract::primitives::Participants;
+use crate::attestation;
+use crate::mesh::transport::{Handshake, HandshakeError, WireFormat, ZSTD_ENCODING};
+use crate::protocol::contract::primitives::{ParticipantInfo, Participants};
 use crate::protocol::ProtocolState;
 use crate::web::StateView;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
 
+/// How long to wait before handshaking again with a participant that is not compatible.
+const HANDSHAKE_RETRY: Duration = Duration::from_secs(30);
+
+/// Outcome of the handshake with a participant.
+struct Negotiated {
+    /// Software version of the participant, unknown if it predates the handshake.
+    version: Option<String>,
+    /// Format of the messages sent to the participant, or why none is compatible.
+    format: Result<WireFormat, HandshakeError>,
+    at: Instant,
+}
+
 // TODO: this is a basic connection pool and does not do most of the work yet. This is
 //       mostly here just to facilitate offline node handling for now.
 // TODO/NOTE: we can use libp2p to facilitate most the of low level TCP connection work.
@@ -20,6 +34,9 @@ pub struct Pool {
     connections: RwLock<Participants>,
     potential_connections: RwLock<Participants>,
     status: RwLock<HashMap<Participant, StateView>>,
+    /// Outcome of the handshake with each participant. Forgotten when a participant stops
+    /// responding, so that the handshake is done again with whatever version comes back.
+    negotiated: RwLock<HashMap<Participant, Negotiated>>,
 
     /// The currently active participants for this epoch.
     current_active: RwLock<Option<(Participants, Instant)>>,
@@ -55,8 +72,13 @@ impl Pool {
                     participant,
                     url
                 );
+                self.forget(participant).await;
                 continue;
             };
+            if !self.handshake(participant, info, &url).await {
+                continue;
+            }
+            self.record_compression(participant, &resp).await;
 
             let Ok(state): Result<StateView, _> = resp.json().await else {
                 tracing::warn!(
@@ -93,9 +115,14 @@ impl Pool {
                 continue;
             };
 
-            let Ok(resp) = self.http.get(url).send().await else {
+            let Ok(resp) = self.http.get(url.clone()).send().await else {
+                self.forget(participant).await;
                 continue;
             };
+            if !self.handshake(participant, info, &url).await {
+                continue;
+            }
+            self.record_compression(participant, &resp).await;
 
             let Ok(state): Result<StateView, _> = resp.json().await else {
                 continue;
@@ -149,6 +176,135 @@ impl Pool {
         self.potential_connections.read().await.clone()
     }
 
+    /// Participants of the current epoch that are not part of the given active set.
+    pub async fn unreachable(&self, active: &Participants) -> Vec<Participant> {
+        self.connections
+            .read()
+            .await
+            .keys()
+            .filter(|participant| !active.contains_key(participant))
+            .copied()
+            .collect()
+    }
+
+    /// Handshakes with the participant unless done already, returning whether messages can be
+    /// exchanged with it. `url` is any endpoint of the participant. With attestation enabled,
+    /// the participant also has to present a valid attestation for its registered keys.
+    async fn handshake(
+        &self,
+        participant: &Participant,
+        info: &ParticipantInfo,
+        url: &Url,
+    ) -> bool {
+        if let Some(negotiated) = self.negotiated.read().await.get(participant) {
+            if negotiated.format.is_ok() || negotiated.at.elapsed() < HANDSHAKE_RETRY {
+                return negotiated.format.is_ok();
+            }
+        }
+
+        let Ok(url) = url.join("/handshake") else {
+            return false;
+        };
+        let Ok(resp) = self.http.get(url).send().await else {
+            return false;
+        };
+        let negotiated = if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            // The participant predates the handshake. Compression is negotiated from the
+            // headers of its `/state` responses instead.
+            tracing::info!(?participant, "participant does not support the handshake");
+            let format = if attestation::enabled() {
+                tracing::warn!(?participant, "participant cannot present an attestation");
+                Err(HandshakeError::Unattested(
+                    "participant does not support the handshake".to_string(),
+                ))
+            } else {
+                Ok(WireFormat::default())
+            };
+            Negotiated {
+                version: None,
+                format,
+                at: Instant::now(),
+            }
+        } else {
+            let Ok(theirs): Result<Handshake, _> = resp.json().await else {
+                tracing::warn!(?participant, "Pool.handshake malformed handshake");
+                return false;
+            };
+            let format = Handshake::ours().negotiate(&theirs).and_then(|format| {
+                attestation::verify(theirs.attestation.as_ref(), info)
+                    .map_err(|err| HandshakeError::Unattested(err.to_string()))?;
+                Ok(format)
+            });
+            match &format {
+                Ok(format) => tracing::info!(
+                    ?participant,
+                    version = theirs.version,
+                    ?format,
+                    "negotiated wire format with participant"
+                ),
+                Err(err) => {
+                    tracing::warn!(
+                        ?participant,
+                        version = theirs.version,
+                        %err,
+                        "participant is not compatible with this node"
+                    );
+                    crate::metrics::NUM_INCOMPATIBLE_PEER_HANDSHAKES
+                        .with_label_values(&[&u32::from(*participant).to_string()])
+                        .inc();
+                }
+            }
+            Negotiated {
+                version: Some(theirs.version),
+                format,
+                at: Instant::now(),
+            }
+        };
+        let compatible = negotiated.format.is_ok();
+        self.negotiated
+            .write()
+            .await
+            .insert(*participant, negotiated);
+        compatible
+    }
+
+    async fn forget(&self, participant: &Participant) {
+        self.negotiated.write().await.remove(participant);
+    }
+
+    /// Participants that predate the handshake advertise accepting compressed messages with the
+    /// headers of their `/state` responses.
+    async fn record_compression(&self, participant: &Participant, resp: &reqwest::Response) {
+        let mut negotiated = self.negotiated.write().await;
+        let Some(Negotiated {
+            version: None,
+            format: Ok(format),
+            ..
+        }) = negotiated.get_mut(participant)
+        else {
+            return;
+        };
+        format.compressed = resp
+            .headers()
+            .get_all(reqwest::header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|encoding| encoding.trim() == ZSTD_ENCODING);
+    }
+
+    /// Wire format of the messages sent to each participant of the given active set.
+    pub async fn formats(&self, active: &Participants) -> HashMap<Participant, WireFormat> {
+        let negotiated = self.negotiated.read().await;
+        active
+            .keys()
+            .filter_map(|participant| {
+                let format = negotiated.get(participant)?.format.as_ref().ok()?;
+                Some((*participant, *format))
+            })
+            .collect()
+    }
+
     pub async fn is_participant_stable(&self, participant: &Participant) -> bool {
         self.status
             .read()
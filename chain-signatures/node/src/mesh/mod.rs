@@ -1,7 +1,31 @@
+use std::collections::HashMap;
+
+use cait_sith::protocol::Participant;
+
+use crate::events::{self, NodeEvent};
+use crate::mesh::transport::WireFormat;
 use crate::protocol::contract::primitives::Participants;
+use crate::protocol::registry::{self, SharedRegistry};
 use crate::protocol::ProtocolState;
 
+pub mod bandwidth;
 pub mod connection;
+pub mod quic;
+pub mod transport;
+
+/// Health of the mesh as observed by the latest round of pings.
+#[derive(Clone, Debug, Default)]
+pub struct MeshState {
+    /// Participants that responded to the latest ping.
+    pub active: Participants,
+    /// Active participants that are also stable, i.e. caught up with the latest block height.
+    pub stable: Participants,
+    /// Participants of the current epoch that did not respond to the latest ping.
+    pub unreachable: Vec<Participant>,
+    /// Wire format of the messages sent to each active participant, as negotiated in the
+    /// handshake. Participants that are not compatible with this node are never active.
+    pub formats: HashMap<Participant, WireFormat>,
+}
 
 #[derive(Default)]
 pub struct Mesh {
@@ -14,6 +38,12 @@ pub struct Mesh {
     /// Potential participants that are active at the beginning of each protocol loop. This
     /// includes participants belonging to the next epoch.
     pub active_potential_participants: Participants,
+
+    /// Health of the mesh at the beginning of each protocol loop.
+    pub state: MeshState,
+
+    /// Participants of the contract and their liveness, shared with the managers.
+    pub registry: SharedRegistry,
 }
 
 impl Mesh {
@@ -28,6 +58,12 @@ impl Mesh {
         &self.active_potential_participants
     }
 
+    /// Health of the mesh at the beginning of each protocol loop. New protocols should only be
+    /// started with participants that are part of this state to avoid guaranteed timeouts.
+    pub fn state(&self) -> &MeshState {
+        &self.state
+    }
+
     /// Get all pontential participants, but they may not necessarily be active.
     pub async fn potential_participants(&self) -> Participants {
         self.connections.potential_participants().await
@@ -64,6 +100,7 @@ impl Mesh {
         self.connections
             .establish_participants(contract_state)
             .await;
+        registry::write(&self.registry).update(contract_state);
         self.ping().await;
 
         tracing::debug!(
@@ -77,5 +114,41 @@ impl Mesh {
     pub async fn ping(&mut self) {
         self.active_participants = self.connections.ping().await;
         self.active_potential_participants = self.connections.ping_potential().await;
+
+        let state = MeshState {
+            active: self.active_participants.clone(),
+            stable: self.stable_participants().await,
+            unreachable: self
+                .connections
+                .unreachable(&self.active_participants)
+                .await,
+            formats: self.connections.formats(&self.active_participants).await,
+        };
+        let lost: Vec<_> = state
+            .unreachable
+            .iter()
+            .filter(|p| !self.state.unreachable.contains(p))
+            .collect();
+        let recovered: Vec<_> = self
+            .state
+            .unreachable
+            .iter()
+            .filter(|p| !state.unreachable.contains(p))
+            .collect();
+        if !lost.is_empty() || !recovered.is_empty() {
+            tracing::info!(?lost, ?recovered, "mesh liveness changed");
+        }
+        for participant in lost {
+            events::emit(NodeEvent::PeerUnreachable {
+                participant: *participant,
+            });
+        }
+        for participant in recovered {
+            events::emit(NodeEvent::PeerRecovered {
+                participant: *participant,
+            });
+        }
+        registry::write(&self.registry).set_active(&state.active);
+        self.state = state;
     }
 }
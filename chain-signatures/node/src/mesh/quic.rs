@@ -0,0 +1,339 @@
+//! QUIC transport of protocol messages. The rounds of cait-sith exchange many small messages,
+//! which over HTTP queue up behind each other on the few connections to a participant. Over QUIC,
+//! every batch of messages is sent on its own stream of a single connection per participant, such
+//! that a lost packet only holds back the batch it belongs to.
+//!
+//! Nodes advertise the UDP port they accept QUIC connections on in their handshake, and messages
+//! are sent over QUIC to the participants advertising one when this node has QUIC enabled too.
+//! Like with HTTP, the channel is not what authenticates the messages: each of them is signed by
+//! its sender and encrypted to its receiver, see [`super::transport`]. The certificate of a node
+//! is hence self-signed and not verified by its peers.
+//!
+//! A batch is sent on a bidirectional stream as a 4 bytes little endian schema version, a byte
+//! telling whether the batch is compressed, and the batch itself. The receiver answers with a
+//! status byte, followed by the reason when the batch got rejected.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use cait_sith::protocol::Participant;
+use mpc_keys::hpke::{self, Ciphered};
+use once_cell::sync::OnceCell;
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{Mutex, RwLock};
+use url::Url;
+
+use crate::http_client::SendError;
+use crate::mesh::bandwidth;
+use crate::mesh::transport::{self, PeerTransport, WireFormat};
+use crate::protocol::contract::primitives::ParticipantInfo;
+use crate::protocol::{MpcMessage, NodeState};
+
+/// Name the self-signed certificate of every node is issued for.
+const SERVER_NAME: &str = "mpc-node";
+
+/// Batches are partitioned to at most 256kb of ciphertext before being serialized, this leaves
+/// plenty of room for their encoding.
+const MAX_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+const MAX_REPLY_BYTES: usize = 64 * 1024;
+
+/// Maximum number of connections accepted at once. Participants use a single connection each, so
+/// this only bounds what peers that are not participants can open before being authenticated.
+const MAX_CONNECTIONS: u32 = 256;
+
+/// Maximum number of batches in flight on a connection at once, each of which is read into memory
+/// in full before being handled.
+const MAX_STREAMS_PER_CONNECTION: u32 = 32;
+
+const STATUS_OK: u8 = 0;
+const STATUS_REJECTED: u8 = 1;
+
+static QUIC: OnceCell<QuicTransport> = OnceCell::new();
+
+#[derive(Debug, Clone, Default, clap::Parser)]
+#[group(id = "transport_options")]
+pub struct Options {
+    /// UDP port to accept protocol messages on over QUIC. Messages are then sent over QUIC to the
+    /// participants supporting it as well, and over HTTP to the others. QUIC is disabled if not
+    /// set.
+    #[arg(long, env("MPC_QUIC_PORT"))]
+    pub quic_port: Option<u16>,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut opts = Vec::new();
+        if let Some(quic_port) = self.quic_port {
+            opts.extend(vec!["--quic-port".to_string(), quic_port.to_string()]);
+        }
+        opts
+    }
+}
+
+/// Sends batches of messages over QUIC, reusing one connection per participant.
+pub struct QuicTransport {
+    endpoint: Endpoint,
+    port: u16,
+    connections: Mutex<HashMap<SocketAddr, ConnectionSlot>>,
+}
+
+/// The connection to an address, locked while it is being established such that concurrent
+/// batches to the same address share it rather than each opening their own.
+type ConnectionSlot = Arc<Mutex<Option<Connection>>>;
+
+/// Binds the QUIC endpoint of `options`, if any. Has to be called within the tokio runtime.
+pub fn init(options: &Options) -> anyhow::Result<()> {
+    let Some(port) = options.quic_port else {
+        return Ok(());
+    };
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])?;
+    let mut transport_config = quinn::TransportConfig::default();
+    transport_config
+        .max_concurrent_bidi_streams(MAX_STREAMS_PER_CONNECTION.into())
+        .max_concurrent_uni_streams(0u32.into());
+    let mut server_config = quinn::ServerConfig::with_single_cert(
+        vec![rustls::Certificate(cert.serialize_der()?)],
+        rustls::PrivateKey(cert.serialize_private_key_der()),
+    )?;
+    server_config
+        .transport_config(Arc::new(transport_config))
+        .concurrent_connections(MAX_CONNECTIONS);
+    let client_crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(UnverifiedServer))
+        .with_no_client_auth();
+    let mut endpoint = Endpoint::server(server_config, SocketAddr::from(([0, 0, 0, 0], port)))?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(client_crypto)));
+    let quic = QuicTransport {
+        endpoint,
+        port,
+        connections: Mutex::new(HashMap::new()),
+    };
+    if QUIC.set(quic).is_err() {
+        tracing::warn!("quic endpoint is already bound");
+    }
+    tracing::info!(port, "accepting messages over quic");
+    Ok(())
+}
+
+/// The QUIC transport of this node, if enabled.
+pub fn transport() -> Option<&'static QuicTransport> {
+    QUIC.get()
+}
+
+/// The UDP port this node accepts messages on over QUIC, if enabled.
+pub fn port() -> Option<u16> {
+    QUIC.get().map(|quic| quic.port)
+}
+
+impl QuicTransport {
+    /// The open connection to `addr`, connecting to it first if there is none. Only the slot of
+    /// `addr` stays locked while connecting, such that a slow or unreachable participant does not
+    /// hold back the batches sent to the others.
+    async fn connection(&self, addr: SocketAddr) -> Result<Connection, SendError> {
+        let slot = self
+            .connections
+            .lock()
+            .await
+            .entry(addr)
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(connection) = slot.as_ref() {
+            if connection.close_reason().is_none() {
+                return Ok(connection.clone());
+            }
+        }
+        let connection = self
+            .endpoint
+            .connect(addr, SERVER_NAME)
+            .map_err(quic_err)?
+            .await
+            .map_err(quic_err)?;
+        *slot = Some(connection.clone());
+        Ok(connection)
+    }
+
+    async fn send_on_stream(
+        &self,
+        addr: SocketAddr,
+        body: &[u8],
+        format: WireFormat,
+        compressed: bool,
+    ) -> Result<Vec<u8>, SendError> {
+        let connection = self.connection(addr).await?;
+        let (mut send, mut recv) = connection.open_bi().await.map_err(quic_err)?;
+        let mut header = format.schema.to_le_bytes().to_vec();
+        header.push(compressed as u8);
+        send.write_all(&header).await.map_err(quic_err)?;
+        send.write_all(body).await.map_err(quic_err)?;
+        send.finish().await.map_err(quic_err)?;
+        recv.read_to_end(MAX_REPLY_BYTES).await.map_err(quic_err)
+    }
+}
+
+#[async_trait::async_trait]
+impl PeerTransport for QuicTransport {
+    fn name(&self) -> &'static str {
+        "quic"
+    }
+
+    async fn send(
+        &self,
+        from: Participant,
+        to: &ParticipantInfo,
+        batch: &[Ciphered],
+        format: WireFormat,
+    ) -> Result<(), SendError> {
+        let Some(port) = format.quic_port else {
+            return Err(SendError::Quic(format!(
+                "{} does not accept messages over quic",
+                to.account_id
+            )));
+        };
+        let addr = resolve(&to.url, port).await?;
+        tracing::debug!(?from, to = %addr, "sending encrypted message over quic");
+        let (body, compressed) = transport::encode_batch(batch, format)?;
+        let reply = match self.send_on_stream(addr, &body, format, compressed).await {
+            Ok(reply) => reply,
+            Err(err) => {
+                // The connection is likely broken, so the next batch opens a new one.
+                self.connections.lock().await.remove(&addr);
+                return Err(err);
+            }
+        };
+        match reply.split_first() {
+            Some((&STATUS_OK, _)) => Ok(()),
+            Some((_, reason)) => Err(SendError::Unsuccessful(
+                String::from_utf8_lossy(reason).into_owned(),
+            )),
+            None => Err(SendError::Quic("empty reply".to_string())),
+        }
+    }
+}
+
+fn quic_err(err: impl std::fmt::Display) -> SendError {
+    SendError::Quic(err.to_string())
+}
+
+/// Address of the QUIC endpoint of the participant, on the host of its registered url.
+async fn resolve(url: &str, port: u16) -> Result<SocketAddr, SendError> {
+    let parsed = Url::parse(url).map_err(|err| SendError::Quic(format!("{url}: {err}")))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| SendError::Quic(format!("{url} has no host")))?;
+    tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| SendError::Quic(format!("{host}: {err}")))?
+        .next()
+        .ok_or_else(|| SendError::Quic(format!("{host} does not resolve")))
+}
+
+/// Where the messages received over QUIC are handed over to.
+struct Inbox {
+    sender: Sender<MpcMessage>,
+    protocol_state: Arc<RwLock<NodeState>>,
+    cipher_sk: hpke::SecretKey,
+}
+
+/// Accepts the batches of messages sent over QUIC and hands them to the protocol, the same way
+/// the `/msg` endpoint does. Returns right away if QUIC is disabled.
+pub async fn serve(
+    sender: Sender<MpcMessage>,
+    cipher_sk: hpke::SecretKey,
+    protocol_state: Arc<RwLock<NodeState>>,
+) {
+    let Some(quic) = transport() else {
+        return;
+    };
+    let inbox = Arc::new(Inbox {
+        sender,
+        protocol_state,
+        cipher_sk,
+    });
+    while let Some(connecting) = quic.endpoint.accept().await {
+        let inbox = inbox.clone();
+        tokio::spawn(async move {
+            let connection = match connecting.await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    tracing::debug!(%err, "failed to accept quic connection");
+                    return;
+                }
+            };
+            while let Ok((send, recv)) = connection.accept_bi().await {
+                let inbox = inbox.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle(&inbox, send, recv).await {
+                        tracing::debug!(%err, "failed to handle quic stream");
+                    }
+                });
+            }
+        });
+    }
+}
+
+async fn handle(inbox: &Inbox, mut send: SendStream, mut recv: RecvStream) -> anyhow::Result<()> {
+    let request = recv.read_to_end(MAX_BATCH_BYTES).await?;
+    let reply = match deliver(inbox, &request).await {
+        Ok(()) => vec![STATUS_OK],
+        Err(reason) => {
+            tracing::warn!(reason, "rejected a batch of messages received over quic");
+            let mut reply = vec![STATUS_REJECTED];
+            reply.extend(reason.into_bytes());
+            reply
+        }
+    };
+    send.write_all(&reply).await?;
+    send.finish().await?;
+    Ok(())
+}
+
+async fn deliver(inbox: &Inbox, request: &[u8]) -> Result<(), String> {
+    if request.len() < 5 {
+        return Err("batch is missing its header".to_string());
+    }
+    let (header, body) = request.split_at(5);
+    let schema = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    if !transport::MESSAGE_SCHEMAS.contains(&schema) {
+        return Err(format!(
+            "unsupported message schema: {schema}, supported: {:?}",
+            transport::MESSAGE_SCHEMAS
+        ));
+    }
+    let encrypted = transport::decode_batch(body, header[4] != 0)?;
+    for encrypted in encrypted {
+        let bytes = encrypted.text.len();
+        let message =
+            transport::receive(&inbox.cipher_sk, &inbox.protocol_state, encrypted, schema)
+                .await
+                .map_err(|err| err.to_string())?;
+        bandwidth::record_received(message.sender(), message.typename(), bytes);
+        inbox
+            .sender
+            .send(message)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Accepts any certificate: the messages are authenticated on their own, see the module docs.
+struct UnverifiedServer;
+
+impl rustls::client::ServerCertVerifier for UnverifiedServer {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,378 @@
+//! Authenticated transport of protocol messages between participants.
+//!
+//! Every message is encrypted to the registered cipher key of the receiving participant and
+//! signed with the registered sign key of the sending participant, which establishes the identity
+//! of the sender of the channel. The protocol messages themselves also claim a sender, which has
+//! to match the identity of the channel or the message gets rejected.
+//!
+//! Participants exchange a [`Handshake`] when they connect, from which the [`WireFormat`] of the
+//! messages sent to each of them is negotiated. This keeps clusters running mixed versions during
+//! an upgrade from silently failing to deserialize each other's messages. The handshake also
+//! selects the [`PeerTransport`] the messages are sent with: QUIC when both participants support
+//! it, see [`super::quic`], and HTTP otherwise.
+
+use crate::attestation::Attestation;
+use crate::http_client::SendError;
+use crate::mesh::quic;
+use crate::protocol::codec;
+use crate::protocol::contract::primitives::ParticipantInfo;
+use crate::protocol::message::SignedMessage;
+use crate::protocol::{CryptographicError, MpcMessage, NodeState};
+
+use cait_sith::protocol::Participant;
+use mpc_keys::hpke::{self, Ciphered};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Header carrying the schema version of a batch of messages.
+pub const SCHEMA_HEADER: &str = "x-mpc-message-schema";
+
+/// Versions of the message schema this node can read and write, oldest first. Senders that
+/// predate the handshake do not set [`SCHEMA_HEADER`] and use the first version.
+pub const MESSAGE_SCHEMAS: &[u32] = &[codec::SCHEMA_JSON, codec::SCHEMA_BINARY];
+
+/// Content encoding of a compressed batch of messages. Participants advertise that they accept it
+/// in their handshake, and with the `accept-encoding` header of their `/state` response for the
+/// participants that predate the handshake.
+pub const ZSTD_ENCODING: &str = "zstd";
+
+/// Batches smaller than this are not worth compressing.
+pub const COMPRESSION_MIN_BYTES: usize = 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Curve {
+    Secp256k1,
+}
+
+/// Capabilities a node advertises on its `/handshake` endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    /// Software version of the node.
+    pub version: String,
+    /// Supported message schema versions.
+    pub schemas: Vec<u32>,
+    pub curves: Vec<Curve>,
+    /// Content encodings accepted for batches of messages.
+    pub encodings: Vec<String>,
+    /// Attestation of the TEE the node runs in, if it runs with attestation enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+    /// UDP port the node accepts messages on over QUIC, if it supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quic_port: Option<u16>,
+    /// Commitment to the key share of the node, see [`crate::protocol::registry::share_commitment`],
+    /// if it is running. Lets operators check an imported share against the ones of the
+    /// participants, see [`crate::import`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_commitment: Option<crypto_shared::PublicKey>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("no common message schema, ours: {ours:?}, theirs: {theirs:?}")]
+    NoCommonSchema { ours: Vec<u32>, theirs: Vec<u32> },
+    #[error("no common curve, ours: {ours:?}, theirs: {theirs:?}")]
+    NoCommonCurve {
+        ours: Vec<Curve>,
+        theirs: Vec<Curve>,
+    },
+    #[error("participant is not attested: {0}")]
+    Unattested(String),
+}
+
+impl Handshake {
+    /// The capabilities of this node.
+    pub fn ours() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            schemas: MESSAGE_SCHEMAS.to_vec(),
+            curves: vec![Curve::Secp256k1],
+            encodings: vec![ZSTD_ENCODING.to_string()],
+            attestation: None,
+            quic_port: quic::port(),
+            share_commitment: None,
+        }
+    }
+
+    /// Selects the format of the messages sent to the node that advertised `theirs`: the newest
+    /// schema both nodes support, compressed if they both accept it, over QUIC if they both
+    /// support it.
+    pub fn negotiate(&self, theirs: &Handshake) -> Result<WireFormat, HandshakeError> {
+        if !self
+            .curves
+            .iter()
+            .any(|curve| theirs.curves.contains(curve))
+        {
+            return Err(HandshakeError::NoCommonCurve {
+                ours: self.curves.clone(),
+                theirs: theirs.curves.clone(),
+            });
+        }
+        let schema = self
+            .schemas
+            .iter()
+            .filter(|schema| theirs.schemas.contains(schema))
+            .max()
+            .ok_or_else(|| HandshakeError::NoCommonSchema {
+                ours: self.schemas.clone(),
+                theirs: theirs.schemas.clone(),
+            })?;
+        let compressed = [&self.encodings, &theirs.encodings]
+            .iter()
+            .all(|encodings| encodings.iter().any(|encoding| encoding == ZSTD_ENCODING));
+        Ok(WireFormat {
+            schema: *schema,
+            compressed,
+            quic_port: self.quic_port.and(theirs.quic_port),
+        })
+    }
+}
+
+/// Format of the messages sent to a participant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WireFormat {
+    pub schema: u32,
+    pub compressed: bool,
+    /// Port the messages are sent to over QUIC, or sent over HTTP if none.
+    pub quic_port: Option<u16>,
+}
+
+impl Default for WireFormat {
+    /// The format understood by participants that predate the handshake.
+    fn default() -> Self {
+        Self {
+            schema: MESSAGE_SCHEMAS[0],
+            compressed: false,
+            quic_port: None,
+        }
+    }
+}
+
+/// Compresses a serialized batch of encrypted messages before it is sent.
+pub fn compress(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::encode_all(body, ZSTD_LEVEL)
+}
+
+/// Decompresses a batch of encrypted messages received with the zstd content encoding.
+pub fn decompress(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::decode_all(body)
+}
+
+/// Serializes a batch of encrypted messages in the given format, returning whether the body got
+/// compressed. Small batches are not compressed.
+pub fn encode_batch(batch: &[Ciphered], format: WireFormat) -> Result<(Vec<u8>, bool), SendError> {
+    let body = serde_json::to_vec(batch).map_err(SendError::DataConversionError)?;
+    if format.compressed && body.len() >= COMPRESSION_MIN_BYTES {
+        match compress(&body) {
+            Ok(compressed) => return Ok((compressed, true)),
+            Err(err) => tracing::warn!(?err, "failed to compress messages, sending uncompressed"),
+        }
+    }
+    Ok((body, false))
+}
+
+/// Deserializes a batch of encrypted messages sent with [`encode_batch`].
+pub fn decode_batch(body: &[u8], compressed: bool) -> Result<Vec<Ciphered>, String> {
+    if compressed {
+        let body = decompress(body).map_err(|err| format!("invalid zstd body: {err}"))?;
+        serde_json::from_slice(&body)
+    } else {
+        serde_json::from_slice(body)
+    }
+    .map_err(|err| err.to_string())
+}
+
+/// Means of delivering batches of encrypted messages to a participant.
+#[async_trait::async_trait]
+pub trait PeerTransport: Send + Sync {
+    /// Name of the transport in logs and metrics.
+    fn name(&self) -> &'static str;
+
+    /// Sends the batch to the participant, returning once the participant has accepted it.
+    async fn send(
+        &self,
+        from: Participant,
+        to: &ParticipantInfo,
+        batch: &[Ciphered],
+        format: WireFormat,
+    ) -> Result<(), SendError>;
+}
+
+/// Decrypts an incoming message, verifies that it was signed by the participant it was sent by,
+/// and that the protocol message it contains claims to be sent by that same participant.
+pub async fn receive(
+    cipher_sk: &hpke::SecretKey,
+    protocol_state: &Arc<RwLock<NodeState>>,
+    encrypted: Ciphered,
+    schema: u32,
+) -> Result<MpcMessage, CryptographicError> {
+    let (from, message) = SignedMessage::<MpcMessage>::decrypt_with_sender(
+        cipher_sk,
+        protocol_state,
+        encrypted,
+        schema,
+    )
+    .await?;
+    let claimed = message.sender();
+    if claimed != from {
+        tracing::error!(
+            ?from,
+            ?claimed,
+            typename = message.typename(),
+            "rejecting message claiming to be sent by another participant"
+        );
+        crate::metrics::NUM_SENDER_MISMATCHES
+            .with_label_values(&[message.typename()])
+            .inc();
+        return Err(CryptographicError::SenderMismatch {
+            claimed,
+            authenticated: from,
+        });
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Curve, Handshake, HandshakeError, WireFormat};
+    use crate::keyspace::KeyspaceId;
+    use crate::protocol::codec;
+    use crate::protocol::contract::primitives::{ParticipantInfo, Participants};
+    use crate::protocol::message::{AbortMessage, SignedMessage};
+    use crate::protocol::state::WaitingForConsensusState;
+    use crate::protocol::{CryptographicError, MpcMessage, NodeState};
+
+    use cait_sith::protocol::Participant;
+    use mpc_keys::hpke;
+    use near_crypto::{KeyType, SecretKey};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use zeroize::Zeroizing;
+
+    #[test]
+    fn test_handshake_negotiation() {
+        let ours = Handshake::ours();
+        let newer = Handshake {
+            version: "99.0.0".to_string(),
+            schemas: vec![1, 2, 3],
+            curves: vec![Curve::Secp256k1],
+            encodings: vec![],
+            attestation: None,
+            quic_port: Some(4433),
+            share_commitment: None,
+        };
+        assert_eq!(
+            ours.negotiate(&newer),
+            Ok(WireFormat {
+                schema: 2,
+                compressed: false,
+                quic_port: None,
+            })
+        );
+        assert_eq!(
+            ours.negotiate(&ours),
+            Ok(WireFormat {
+                schema: 2,
+                compressed: true,
+                quic_port: None,
+            })
+        );
+
+        let incompatible = Handshake {
+            schemas: vec![3],
+            ..newer.clone()
+        };
+        assert!(matches!(
+            ours.negotiate(&incompatible),
+            Err(HandshakeError::NoCommonSchema { .. })
+        ));
+        let no_curve = Handshake {
+            curves: vec![],
+            ..newer.clone()
+        };
+        assert!(matches!(
+            ours.negotiate(&no_curve),
+            Err(HandshakeError::NoCommonCurve { .. })
+        ));
+
+        // QUIC is only used when both nodes support it.
+        let quic = Handshake {
+            quic_port: Some(4434),
+            ..ours.clone()
+        };
+        assert_eq!(quic.negotiate(&newer).unwrap().quic_port, Some(4433));
+        assert_eq!(quic.negotiate(&ours).unwrap().quic_port, None);
+    }
+
+    #[tokio::test]
+    async fn test_receive_rejects_sender_mismatch() {
+        let (cipher_sk, cipher_pk) = hpke::generate();
+        let sign_sk = SecretKey::from_random(KeyType::ED25519);
+        let mut participants = Participants::default();
+        participants.insert(&Participant::from(0u32), ParticipantInfo::new(0));
+        participants.insert(
+            &Participant::from(1u32),
+            ParticipantInfo {
+                sign_pk: sign_sk.public_key(),
+                ..ParticipantInfo::new(1)
+            },
+        );
+        let state = Arc::new(RwLock::new(NodeState::WaitingForConsensus(
+            WaitingForConsensusState {
+                epoch: 0,
+                participants,
+                threshold: 2,
+                private_share: Zeroizing::new(k256::Scalar::ONE),
+                public_key: k256::AffinePoint::GENERATOR,
+                messages: Default::default(),
+            },
+        )));
+        let abort = |from: u32| {
+            MpcMessage::Abort(AbortMessage {
+                id: 1,
+                keyspace: KeyspaceId::root(),
+                epoch: 0,
+                from: Participant::from(from),
+                reason: String::new(),
+                timestamp: 0,
+            })
+        };
+
+        // Participant 1 sending its own message gets it through.
+        let schema = codec::SCHEMA_BINARY;
+        let encrypted = SignedMessage::encrypt(
+            &abort(1),
+            Participant::from(1u32),
+            &sign_sk,
+            &cipher_pk,
+            schema,
+        )
+        .unwrap();
+        let message = super::receive(&cipher_sk, &state, encrypted, schema)
+            .await
+            .unwrap();
+        assert_eq!(message, abort(1));
+
+        // Participant 1 sending a message claiming to be from participant 0 gets it rejected.
+        let encrypted = SignedMessage::encrypt(
+            &abort(0),
+            Participant::from(1u32),
+            &sign_sk,
+            &cipher_pk,
+            schema,
+        )
+        .unwrap();
+        let err = super::receive(&cipher_sk, &state, encrypted, schema)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CryptographicError::SenderMismatch { claimed, authenticated }
+                if claimed == Participant::from(0u32) && authenticated == Participant::from(1u32)
+        ));
+    }
+}
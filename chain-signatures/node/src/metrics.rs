@@ -80,6 +80,45 @@ pub(crate) static PRESIGNATURE_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub(crate) static PROTOCOL_ROUND_WAIT_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "multichain_protocol_round_wait_sec",
+        "Time a protocol waited between two rounds for the messages of the other participants.",
+        &["node_account_id", "protocol"],
+        Some(exponential_buckets(0.01, 2.0, 16).unwrap()),
+    )
+    .unwrap()
+});
+
+pub(crate) static PROTOCOL_POKE_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "multichain_protocol_poke_sec",
+        "Total time spent poking a completed protocol, excluding the waits between rounds.",
+        &["node_account_id", "protocol"],
+        Some(exponential_buckets(0.001, 2.0, 20).unwrap()),
+    )
+    .unwrap()
+});
+
+pub(crate) static PROTOCOL_TOTAL_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "multichain_protocol_total_sec",
+        "Wall-clock time of a completed protocol, from its first poke until it completed.",
+        &["node_account_id", "protocol"],
+        Some(exponential_buckets(0.01, 1.5, 30).unwrap()),
+    )
+    .unwrap()
+});
+
+pub(crate) static KDF_CACHE_LOOKUPS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_kdf_cache_lookups",
+        "number of lookups of the derived epsilons and public keys in the tweak cache, by result",
+        &["kind", "result"],
+    )
+    .unwrap()
+});
+
 pub(crate) static SIGN_QUEUE_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
     try_create_int_gauge_vec(
         "multichain_sign_queue_size",
@@ -316,6 +355,15 @@ pub(crate) static NUM_SEND_ENCRYPTED_FAILURE: Lazy<CounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub(crate) static NUM_BATCHES_SENT_BY_TRANSPORT: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_batches_sent_by_transport",
+        "number of batches of messages sent to a participant over each transport, by result",
+        &["node_account_id", "transport", "result"],
+    )
+    .unwrap()
+});
+
 pub(crate) static NUM_SEND_ENCRYPTED_TOTAL: Lazy<CounterVec> = Lazy::new(|| {
     try_create_counter_vec(
         "multichain_send_encrypted_total",
@@ -362,6 +410,24 @@ pub(crate) static SIGNATURE_GENERATOR_FAILURES: Lazy<CounterVec> = Lazy::new(||
     .unwrap()
 });
 
+pub(crate) static SIGNATURE_CACHE_HITS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_signature_cache_hits",
+        "total sign requests answered with a cached signature instead of a new one",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static INVALID_SIGNATURES: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_invalid_signatures",
+        "total signatures produced that did not verify and were not published",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
 pub(crate) static PRESIGNATURE_GENERATOR_FAILURES: Lazy<CounterVec> = Lazy::new(|| {
     try_create_counter_vec(
         "multichain_presignature_generator_failures",
@@ -371,6 +437,15 @@ pub(crate) static PRESIGNATURE_GENERATOR_FAILURES: Lazy<CounterVec> = Lazy::new(
     .unwrap()
 });
 
+pub(crate) static PRESIGNATURE_TRIPLES_WASTED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_presignature_triples_wasted",
+        "number of triples consumed by presignature generators that failed",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
 pub(crate) static SIGNATURE_FAILURES: Lazy<CounterVec> = Lazy::new(|| {
     try_create_counter_vec(
         "multichain_signature_failures",
@@ -389,6 +464,16 @@ pub(crate) static SIGNATURE_PUBLISH_FAILURES: Lazy<CounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub(crate) static SIGNATURE_PUBLISH_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "multichain_signature_publish_latency_sec",
+        "Latency of publishing a transaction with signature responses, including retries of the rpc client.",
+        &["node_account_id"],
+        Some(exponential_buckets(0.01, 2.0, 15).unwrap()),
+    )
+    .unwrap()
+});
+
 pub(crate) static SIGNATURE_PUBLISH_RESPONSE_ERRORS: Lazy<CounterVec> = Lazy::new(|| {
     try_create_counter_vec(
         "multichain_signature_publish_response_errors",
@@ -398,6 +483,544 @@ pub(crate) static SIGNATURE_PUBLISH_RESPONSE_ERRORS: Lazy<CounterVec> = Lazy::ne
     .unwrap()
 });
 
+pub(crate) static NUM_TRIPLES_DISCARDED_ON_EPOCH_CHANGE: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_triples_discarded_on_epoch_change",
+        "number of triples discarded because the epoch changed",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_TRIPLE_GENERATORS_CANCELLED_ON_EPOCH_CHANGE: Lazy<CounterVec> =
+    Lazy::new(|| {
+        try_create_counter_vec(
+            "multichain_triple_generators_cancelled_on_epoch_change",
+            "number of ongoing triple generators cancelled because the epoch changed",
+            &["node_account_id"],
+        )
+        .unwrap()
+    });
+
+pub(crate) static NUM_PRESIGNATURES_DISCARDED_ON_EPOCH_CHANGE: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_presignatures_discarded_on_epoch_change",
+        "number of presignatures discarded because the epoch changed",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURE_GENERATORS_CANCELLED_ON_EPOCH_CHANGE: Lazy<CounterVec> =
+    Lazy::new(|| {
+        try_create_counter_vec(
+            "multichain_presignature_generators_cancelled_on_epoch_change",
+            "number of ongoing presignature generators cancelled because the epoch changed",
+            &["node_account_id"],
+        )
+        .unwrap()
+    });
+
+pub(crate) static TRIPLE_GENERATOR_TIMEOUTS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_triple_generator_timeouts",
+        "number of triple generators that timed out",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static PRESIGNATURE_GENERATOR_TIMEOUTS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_presignature_generator_timeouts",
+        "number of presignature generators that timed out",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static SIGNATURE_GENERATOR_TIMEOUTS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_signature_generator_timeouts",
+        "number of signature generators that timed out",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_MESSAGES_SENT: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_messages_sent",
+        "number of protocol messages successfully sent to a participant",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_SIGNATURE_GENERATORS_TOTAL: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_num_signature_generators_total",
+        "number of ongoing signature generators",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_DUPLICATE_MESSAGES: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_duplicate_messages",
+        "number of duplicate protocol messages received and dropped",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PEER_MISBEHAVIORS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_peer_misbehaviors",
+        "number of misbehaviors attributed to other participants",
+        &["node_account_id", "kind"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURE_GENERATORS_ABORTED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_presignature_generators_aborted",
+        "number of presignature generators aborted, either by this node or by another participant",
+        &["node_account_id", "origin"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_GENERATORS_SWEPT: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_generators_swept",
+        "number of generators dropped by the sweeper after timing out without being poked",
+        &["node_account_id", "protocol"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_SIGN_REQUESTS_EXPIRED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_sign_requests_expired",
+        "number of sign requests assigned to this node that expired before a presignature was available",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_SIGN_REQUESTS_REJECTED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_sign_requests_rejected",
+        "number of sign requests assigned to this node that were refused by the request validators",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_SENDER_MISMATCHES: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_sender_mismatches",
+        "number of incoming messages rejected for claiming to be sent by another participant",
+        &["message_type"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_UNREACHABLE_PARTICIPANTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_num_unreachable_participants",
+        "number of participants that did not respond to the latest ping",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static MESSAGE_OUTBOX_PENDING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_message_outbox_pending",
+        "number of messages waiting to be delivered to a participant",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_OUTBOX_MESSAGES_PRUNED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_outbox_messages_pruned",
+        "number of undelivered messages dropped because their protocol was done",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_GENERATORS_CANCELLED_ON_PEER_RESTART: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_generators_cancelled_on_peer_restart",
+        "number of ongoing generators cancelled because a participant restarted since they started",
+        &["node_account_id", "protocol"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_CATCH_UP_MESSAGES_DROPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_catch_up_messages_dropped",
+        "number of received messages dropped on startup because they were sent before the node started",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_STALE_EPOCH_MESSAGES_DROPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_stale_epoch_messages_dropped",
+        "number of received messages dropped because they belong to a past epoch",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_EARLY_MESSAGES_DROPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_early_messages_dropped",
+        "number of messages received before their protocol could be started that were dropped",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_FUTURE_EPOCH_MESSAGES_DROPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_future_epoch_messages_dropped",
+        "number of received messages of a future epoch dropped before the node caught up",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static TRIPLE_PACING_ACTIVE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_triple_pacing_active",
+        "whether starting triple protocols is currently held back by pacing",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_TRIPLE_GENERATORS_PACED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_num_triple_generators_paced",
+        "number of queued triple protocols that could have started but were held back by pacing",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURES_DISCARDED_BY_AGE: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_presignatures_discarded_by_age",
+        "number of unspent presignatures discarded for exceeding their max age",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURES_QUARANTINED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_presignatures_quarantined",
+        "number of unspent presignatures dropped for commitments of other participants disagreeing",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURES_RECONCILED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_presignatures_reconciled",
+        "number of unspent presignatures dropped for being held by too few participants",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURE_RESERVATIONS_EXPIRED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_presignature_reservations_expired",
+        "number of presignatures not spent since their reservation expired before",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static SIGNING_PAUSED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_signing_paused",
+        "whether signing is paused by the contract or by the operator of the node",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static STOCKPILE_PAUSED_FOR_LIVENESS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_stockpile_paused_for_liveness",
+        "whether the node holds off proposing triples and presignatures for lack of responsive participants",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_UNKNOWN_TRIPLE_NOTICES: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_unknown_triple_notices",
+        "number of notices to proposers of presignatures with triples the sender never had",
+        &["node_account_id", "direction"],
+    )
+    .unwrap()
+});
+
+pub(crate) static PROTOCOL_PARTICIPATION: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_protocol_participation",
+        "number of protocols a peer took part in, by how they ended",
+        &["node_account_id", "protocol", "peer", "outcome"],
+    )
+    .unwrap()
+});
+
+pub(crate) static MESSAGE_BYTES_SENT: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_message_bytes_sent",
+        "number of encrypted message bytes delivered to a participant",
+        &["participant", "message_type"],
+    )
+    .unwrap()
+});
+
+pub(crate) static MESSAGE_BYTES_RECEIVED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_message_bytes_received",
+        "number of encrypted message bytes received from a participant",
+        &["participant", "message_type"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_SEND_THROTTLED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_send_throttled",
+        "number of message batches held back by the outbound rate limit of a participant",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_OUTBOX_MESSAGES_DROPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_outbox_messages_dropped",
+        "number of messages dropped because the outbox of a participant was full",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_STALE_PRESIGNATURES_REJECTED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_stale_presignatures_rejected",
+        "number of presignatures rejected because they were generated for another key or epoch",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static LOAD_SHEDDING_ACTIVE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_load_shedding_active",
+        "whether the node sheds load because its memory or pools are over their ceiling",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static PROCESS_RSS_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_process_rss_bytes",
+        "resident memory of the node process",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_TRIPLES_EVICTED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_triples_evicted",
+        "number of unspent triples evicted while shedding load",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static SHARE_REFRESHES: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_share_refreshes",
+        "number of proactive refreshes of the key share, by outcome",
+        &["node_account_id", "outcome"],
+    )
+    .unwrap()
+});
+
+pub(crate) static SHARE_REFRESH_ACTIVE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_share_refresh_active",
+        "whether a proactive refresh of the key share is ongoing",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_PRESIGNATURES_DISCARDED_ON_SHARE_REFRESH: Lazy<CounterVec> =
+    Lazy::new(|| {
+        try_create_counter_vec(
+            "multichain_presignatures_discarded_on_share_refresh",
+            "number of presignatures discarded because the key share was refreshed",
+            &["node_account_id"],
+        )
+        .unwrap()
+    });
+
+pub(crate) static SIGN_LATENCY_P95: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_sign_latency_p95_ms",
+        "p95 latency of the signatures published within the SLO window, in milliseconds",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static SIGN_LATENCY_SLO_BREACHED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_sign_latency_slo_breached",
+        "whether the p95 latency of signatures is over its objective",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_SIGN_LATENCY_SLO_ALERTS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_sign_latency_slo_alerts",
+        "number of times the p95 latency of signatures went over its objective",
+        &["node_account_id"],
+    )
+    .unwrap()
+});
+
+pub(crate) static MANAGER_INVARIANT_VIOLATIONS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_manager_invariant_violations",
+        "number of times the bookkeeping of a manager was found inconsistent and repaired",
+        &["node_account_id", "manager"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_INCOMPATIBLE_PEER_HANDSHAKES: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_incompatible_peer_handshakes",
+        "number of handshakes with a participant sharing no message schema or curve with this node, or without a valid attestation",
+        &["participant"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_FOREIGN_PRESIGNATURE_GENERATORS_EVICTED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_foreign_presignature_generators_evicted",
+        "number of presignature generators evicted to make room for a newer one of the same proposer over its quota",
+        &["node_account_id", "proposer"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_FOREIGN_PRESIGNATURE_GENERATORS_REJECTED: Lazy<CounterVec> =
+    Lazy::new(|| {
+        try_create_counter_vec(
+        "multichain_num_foreign_presignature_generators_rejected",
+        "number of presignatures not joined because their proposer is over its quota of generators",
+        &["node_account_id", "proposer"],
+    )
+    .unwrap()
+    });
+
+pub(crate) static NUM_PRESIGNATURE_PROPOSALS_REJECTED_POOL_FULL: Lazy<CounterVec> = Lazy::new(
+    || {
+        try_create_counter_vec(
+            "multichain_num_presignature_proposals_rejected_pool_full",
+            "number of presignature proposals rejected because the pool of this node is at its caps",
+            &["node_account_id", "proposer"],
+        )
+        .unwrap()
+    },
+);
+
+pub(crate) static WORKER_INBOX_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "multichain_worker_inbox_size",
+        "number of messages waiting in the inbox of a protocol worker",
+        &["node_account_id", "worker"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_WORKER_MESSAGES_HELD: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_worker_messages_held",
+        "number of messages held back by the router because the inbox of their worker was full",
+        &["node_account_id", "worker"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_WORKER_CRANKS_SKIPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_worker_cranks_skipped",
+        "number of cranks skipped because the protocol worker was still busy with the previous one",
+        &["node_account_id", "worker"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_WORKER_MESSAGES_DROPPED: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_worker_messages_dropped",
+        "number of held messages dropped because too many were held back for busy protocol workers",
+        &["node_account_id", "worker"],
+    )
+    .unwrap()
+});
+
+pub(crate) static NUM_WORKER_RESPAWNS: Lazy<CounterVec> = Lazy::new(|| {
+    try_create_counter_vec(
+        "multichain_num_worker_respawns",
+        "number of protocol workers respawned after they stopped unexpectedly",
+        &["node_account_id", "worker"],
+    )
+    .unwrap()
+});
+
+pub(crate) static WORKER_CRANK_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "multichain_worker_crank_latency_sec",
+        "latency of a single crank of a protocol worker",
+        &["node_account_id", "worker"],
+        Some(exponential_buckets(0.001, 2.0, 20).unwrap()),
+    )
+    .unwrap()
+});
+
 pub fn try_create_int_gauge_vec(name: &str, help: &str, labels: &[&str]) -> Result<IntGaugeVec> {
     check_metric_multichain_prefix(name)?;
     let opts = Opts::new(name, help);
@@ -0,0 +1,58 @@
+//! Emergency switch halting signing during an incident. While paused, the node neither proposes
+//! signatures for the requests in its queue nor joins the signature protocols proposed by others,
+//! so no presignature gets consumed. Everything else keeps running: the mesh, the stockpiling of
+//! triples and presignatures, the indexing of requests and the signature protocols already in
+//! flight, such that signing resumes right away without losing any state.
+//!
+//! Signing is paused network-wide with the `signature.paused` entry of the protocol config of the
+//! contract, or on a single node by its operator through the admin server.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use mpc_contract::config::ProtocolConfig;
+
+static PAUSED_BY_OPERATOR: AtomicBool = AtomicBool::new(false);
+
+/// Pauses or resumes signing on this node, returning whether it was paused by the operator before.
+pub fn set_by_operator(paused: bool) -> bool {
+    PAUSED_BY_OPERATOR.swap(paused, Ordering::Relaxed)
+}
+
+/// Whether signing was paused by the operator of this node.
+pub fn by_operator() -> bool {
+    PAUSED_BY_OPERATOR.load(Ordering::Relaxed)
+}
+
+/// Whether signing is paused by the `signature.paused` entry of the protocol config.
+pub fn by_contract(cfg: &ProtocolConfig) -> bool {
+    cfg.signature
+        .other
+        .get("paused")
+        .and_then(|paused| serde_json::to_value(paused).ok())
+        .and_then(|paused| paused.as_bool())
+        .unwrap_or(false)
+}
+
+/// Whether signing is paused, either by the contract or by the operator.
+pub fn is_paused(cfg: &ProtocolConfig) -> bool {
+    by_operator() || by_contract(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paused_by_contract() {
+        let mut cfg = ProtocolConfig::default();
+        assert!(!by_contract(&cfg));
+        cfg.signature
+            .other
+            .insert("paused".to_string(), serde_json::json!(true).into());
+        assert!(by_contract(&cfg));
+        cfg.signature
+            .other
+            .insert("paused".to_string(), serde_json::json!("yes").into());
+        assert!(!by_contract(&cfg));
+    }
+}
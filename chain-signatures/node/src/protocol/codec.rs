@@ -0,0 +1,387 @@
+//! Wire encoding of protocol messages, selected by the message schema negotiated with each
+//! participant in the handshake.
+//!
+//! Schema 1 is the original JSON encoding. Schema 2 is a binary envelope: a header of the message
+//! type and the version of its fields, followed by the message encoded as CBOR. Fields are keyed
+//! by name, so decoders skip the fields they do not know. New fields must be `#[serde(default)]`
+//! so that messages of senders that do not know them yet still decode.
+
+use cait_sith::protocol::Participant;
+use near_crypto::Signature;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::message::{
+    AbortMessage, AnnounceMessage, CommitmentMessage, GeneratingMessage, MpcMessage,
+    PresignatureMessage, ReconcileMessage, RefreshMessage, ResharingMessage, SignatureMessage,
+    TripleMessage, UnknownTripleMessage,
+};
+
+/// The original JSON encoding, used with participants that predate the handshake.
+pub const SCHEMA_JSON: u32 = 1;
+/// Versioned binary envelope.
+pub const SCHEMA_BINARY: u32 = 2;
+
+/// Version of the fields of the messages. Bump it when fields are added to a message.
+const FIELDS_VERSION: u8 = 1;
+
+/// Length of the header of the binary envelope: message type then fields version.
+const HEADER_LEN: usize = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("unsupported message schema {0}")]
+    UnsupportedSchema(u32),
+    #[error("message envelope is truncated")]
+    Truncated,
+    #[error("unknown message type {0}")]
+    UnknownType(u8),
+    #[error("failed to encode message: {0}")]
+    Encode(String),
+    #[error("failed to decode message: {0}")]
+    Decode(String),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// The signed and encrypted part of a message, see `SignedMessage`.
+#[derive(Serialize, Deserialize)]
+pub struct Signed {
+    #[serde(with = "bytes")]
+    pub msg: Vec<u8>,
+    pub sig: Signature,
+    pub from: Participant,
+}
+
+/// Encodes a byte field as a CBOR byte string rather than an array of integers. Human readable
+/// formats keep the array of integers of schema 1.
+pub(crate) mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(bytes)
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            Vec::<u8>::deserialize(deserializer)
+        } else {
+            Ok(ciborium::value::Value::deserialize(deserializer)?
+                .into_bytes()
+                .map_err(|_| serde::de::Error::custom("expected a byte string"))?)
+        }
+    }
+}
+
+fn to_cbor<T: Serialize>(value: &T, out: &mut Vec<u8>) -> Result<(), CodecError> {
+    ciborium::ser::into_writer(value, out).map_err(|err| CodecError::Encode(err.to_string()))
+}
+
+fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+    ciborium::de::from_reader(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+}
+
+fn message_type(msg: &MpcMessage) -> u8 {
+    match msg {
+        MpcMessage::Generating(_) => 0,
+        MpcMessage::Resharing(_) => 1,
+        MpcMessage::Triple(_) => 2,
+        MpcMessage::Presignature(_) => 3,
+        MpcMessage::Signature(_) => 4,
+        MpcMessage::Abort(_) => 5,
+        MpcMessage::Reconcile(_) => 6,
+        MpcMessage::UnknownTriple(_) => 7,
+        MpcMessage::Commitment(_) => 8,
+        MpcMessage::Announce(_) => 9,
+        MpcMessage::Refresh(_) => 10,
+    }
+}
+
+/// Encodes a protocol message in the given schema.
+pub fn encode(msg: &MpcMessage, schema: u32) -> Result<Vec<u8>, CodecError> {
+    match schema {
+        SCHEMA_JSON => Ok(serde_json::to_vec(msg)?),
+        SCHEMA_BINARY => {
+            let mut out = vec![message_type(msg), FIELDS_VERSION];
+            match msg {
+                MpcMessage::Generating(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Resharing(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Triple(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Presignature(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Signature(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Abort(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Reconcile(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::UnknownTriple(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Commitment(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Announce(msg) => to_cbor(msg, &mut out)?,
+                MpcMessage::Refresh(msg) => to_cbor(msg, &mut out)?,
+            }
+            Ok(out)
+        }
+        schema => Err(CodecError::UnsupportedSchema(schema)),
+    }
+}
+
+/// Decodes a protocol message encoded in the given schema.
+pub fn decode(bytes: &[u8], schema: u32) -> Result<MpcMessage, CodecError> {
+    match schema {
+        SCHEMA_JSON => Ok(serde_json::from_slice(bytes)?),
+        SCHEMA_BINARY => {
+            if bytes.len() < HEADER_LEN {
+                return Err(CodecError::Truncated);
+            }
+            let (header, body) = bytes.split_at(HEADER_LEN);
+            if header[1] > FIELDS_VERSION {
+                tracing::trace!(
+                    version = header[1],
+                    "decoding message of a newer version, skipping unknown fields"
+                );
+            }
+            Ok(match header[0] {
+                0 => MpcMessage::Generating(from_cbor::<GeneratingMessage>(body)?),
+                1 => MpcMessage::Resharing(from_cbor::<ResharingMessage>(body)?),
+                2 => MpcMessage::Triple(from_cbor::<TripleMessage>(body)?),
+                3 => MpcMessage::Presignature(from_cbor::<PresignatureMessage>(body)?),
+                4 => MpcMessage::Signature(from_cbor::<SignatureMessage>(body)?),
+                5 => MpcMessage::Abort(from_cbor::<AbortMessage>(body)?),
+                6 => MpcMessage::Reconcile(from_cbor::<ReconcileMessage>(body)?),
+                7 => MpcMessage::UnknownTriple(from_cbor::<UnknownTripleMessage>(body)?),
+                8 => MpcMessage::Commitment(from_cbor::<CommitmentMessage>(body)?),
+                9 => MpcMessage::Announce(from_cbor::<AnnounceMessage>(body)?),
+                10 => MpcMessage::Refresh(from_cbor::<RefreshMessage>(body)?),
+                ty => return Err(CodecError::UnknownType(ty)),
+            })
+        }
+        schema => Err(CodecError::UnsupportedSchema(schema)),
+    }
+}
+
+/// Encodes the signed part of a message in the given schema.
+pub fn encode_signed(signed: &Signed, schema: u32) -> Result<Vec<u8>, CodecError> {
+    match schema {
+        SCHEMA_JSON => Ok(serde_json::to_vec(signed)?),
+        SCHEMA_BINARY => {
+            let mut out = Vec::new();
+            to_cbor(signed, &mut out)?;
+            Ok(out)
+        }
+        schema => Err(CodecError::UnsupportedSchema(schema)),
+    }
+}
+
+/// Decodes the signed part of a message encoded in the given schema.
+pub fn decode_signed(bytes: &[u8], schema: u32) -> Result<Signed, CodecError> {
+    match schema {
+        SCHEMA_JSON => Ok(serde_json::from_slice(bytes)?),
+        SCHEMA_BINARY => from_cbor(bytes),
+        schema => Err(CodecError::UnsupportedSchema(schema)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::ContractSignRequest;
+    use crate::keyspace::KeyspaceId;
+    use k256::elliptic_curve::Field;
+    use k256::Scalar;
+    use near_crypto::{KeyType, SecretKey};
+    use rand::{Rng, RngCore};
+
+    fn random_data(rng: &mut impl Rng) -> Vec<u8> {
+        let mut data = vec![0; rng.gen_range(0..2048)];
+        rng.fill_bytes(&mut data);
+        data
+    }
+
+    fn random_keyspace(rng: &mut impl Rng) -> KeyspaceId {
+        if rng.gen() {
+            KeyspaceId::root()
+        } else {
+            KeyspaceId::new(format!("keyspace-{}", rng.gen::<u8>()))
+        }
+    }
+
+    fn random_message(rng: &mut impl Rng) -> MpcMessage {
+        let from = Participant::from(rng.gen::<u32>());
+        match rng.gen_range(0..11) {
+            0 => MpcMessage::Generating(GeneratingMessage {
+                from,
+                data: random_data(rng),
+            }),
+            1 => MpcMessage::Resharing(ResharingMessage {
+                epoch: rng.gen(),
+                from,
+                data: random_data(rng),
+            }),
+            2 => MpcMessage::Triple(TripleMessage {
+                id: rng.gen(),
+                keyspace: random_keyspace(rng),
+                epoch: rng.gen(),
+                from,
+                data: random_data(rng),
+                timestamp: rng.gen(),
+            }),
+            3 => MpcMessage::Presignature(PresignatureMessage {
+                id: rng.gen(),
+                keyspace: random_keyspace(rng),
+                triple0: rng.gen(),
+                triple1: rng.gen(),
+                proposer: Participant::from(rng.gen::<u32>()),
+                epoch: rng.gen(),
+                from,
+                data: random_data(rng),
+                timestamp: rng.gen(),
+            }),
+            4 => MpcMessage::Signature(SignatureMessage {
+                receipt_id: near_primitives::hash::CryptoHash(rng.gen()),
+                keyspace: random_keyspace(rng),
+                proposer: Participant::from(rng.gen::<u32>()),
+                presignature_id: rng.gen(),
+                request: ContractSignRequest {
+                    payload: Scalar::random(&mut *rng),
+                    path: format!("path-{}", rng.gen::<u16>()),
+                    key_version: rng.gen(),
+                },
+                epsilon: Scalar::random(&mut *rng),
+                entropy: rng.gen(),
+                epoch: rng.gen(),
+                from,
+                data: random_data(rng),
+                timestamp: rng.gen(),
+            }),
+            5 => MpcMessage::Abort(AbortMessage {
+                id: rng.gen(),
+                keyspace: random_keyspace(rng),
+                epoch: rng.gen(),
+                from,
+                reason: format!("reason-{}", rng.gen::<u64>()),
+                timestamp: rng.gen(),
+            }),
+            6 => MpcMessage::Reconcile(ReconcileMessage {
+                keyspace: random_keyspace(rng),
+                epoch: rng.gen(),
+                from,
+                presignatures: (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect(),
+                timestamp: rng.gen(),
+            }),
+            7 => MpcMessage::UnknownTriple(UnknownTripleMessage {
+                id: rng.gen(),
+                keyspace: random_keyspace(rng),
+                epoch: rng.gen(),
+                from,
+                triple_id: rng.gen(),
+                timestamp: rng.gen(),
+            }),
+            8 => MpcMessage::Commitment(CommitmentMessage {
+                id: rng.gen(),
+                keyspace: random_keyspace(rng),
+                epoch: rng.gen(),
+                from,
+                commitment: rng.gen(),
+                timestamp: rng.gen(),
+            }),
+            9 => MpcMessage::Announce(AnnounceMessage {
+                epoch: rng.gen(),
+                from,
+                started_at: rng.gen(),
+                timestamp: rng.gen(),
+            }),
+            _ => MpcMessage::Refresh(RefreshMessage {
+                epoch: rng.gen(),
+                round: rng.gen(),
+                from,
+                completed: rng.gen(),
+                data: random_data(rng),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_codec_roundtrip_fuzz() {
+        let mut rng = rand::thread_rng();
+        let sign_sk = SecretKey::from_random(KeyType::ED25519);
+        for _ in 0..500 {
+            let msg = random_message(&mut rng);
+            for schema in [SCHEMA_JSON, SCHEMA_BINARY] {
+                let bytes = encode(&msg, schema).unwrap();
+                assert_eq!(decode(&bytes, schema).unwrap(), msg);
+
+                let signed = Signed {
+                    sig: sign_sk.sign(&bytes),
+                    msg: bytes,
+                    from: msg.sender(),
+                };
+                let decoded =
+                    decode_signed(&encode_signed(&signed, schema).unwrap(), schema).unwrap();
+                assert_eq!(decoded.msg, signed.msg);
+                assert_eq!(decoded.sig, signed.sig);
+                assert_eq!(decoded.from, signed.from);
+            }
+        }
+    }
+
+    #[test]
+    fn test_codec_garbage_fuzz() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..2000 {
+            let bytes = random_data(&mut rng);
+            // Garbage must be rejected without panicking.
+            let _ = decode(&bytes, SCHEMA_BINARY);
+            let _ = decode_signed(&bytes, SCHEMA_BINARY);
+        }
+
+        // Truncated messages are rejected too.
+        let msg = encode(&random_message(&mut rng), SCHEMA_BINARY).unwrap();
+        for len in 0..msg.len() {
+            assert!(decode(&msg[..len], SCHEMA_BINARY).is_err());
+        }
+    }
+
+    #[test]
+    fn test_codec_skips_unknown_fields() {
+        /// An abort message of a future version with a field this node does not know.
+        #[derive(Serialize)]
+        struct AbortMessageV2 {
+            id: u64,
+            epoch: u64,
+            from: Participant,
+            reason: String,
+            timestamp: u64,
+            retry_after: u64,
+        }
+
+        let mut bytes = vec![5, FIELDS_VERSION + 1];
+        to_cbor(
+            &AbortMessageV2 {
+                id: 7,
+                epoch: 3,
+                from: Participant::from(1u32),
+                reason: "timed out".to_string(),
+                timestamp: 42,
+                retry_after: 10,
+            },
+            &mut bytes,
+        )
+        .unwrap();
+        assert_eq!(
+            decode(&bytes, SCHEMA_BINARY).unwrap(),
+            MpcMessage::Abort(AbortMessage {
+                id: 7,
+                keyspace: KeyspaceId::root(),
+                epoch: 3,
+                from: Participant::from(1u32),
+                reason: "timed out".to_string(),
+                timestamp: 42,
+            })
+        );
+        assert!(matches!(
+            decode(&[9, FIELDS_VERSION], SCHEMA_BINARY),
+            Err(CodecError::UnknownType(9))
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 use super::contract::{ProtocolState, ResharingContractState};
+use super::message::MpcMessage;
 use super::state::{
     JoiningState, NodeState, PersistentNodeData, RunningState, StartedState,
     WaitingForConsensusState,
@@ -8,14 +9,19 @@ use crate::gcp::error::DatastoreStorageError;
 use crate::gcp::error::SecretStorageError;
 use crate::protocol::contract::primitives::Participants;
 use crate::protocol::monitor::StuckMonitor;
+use crate::protocol::policy;
 use crate::protocol::presignature::PresignatureManager;
+use crate::protocol::refresh::Refresher;
+use crate::protocol::registry::{self, SharedRegistry};
 use crate::protocol::signature::SignatureManager;
 use crate::protocol::state::{GeneratingState, ResharingState};
 use crate::protocol::triple::TripleManager;
+use crate::protocol::validation;
 use crate::rpc_client;
+use crate::storage::presignature_storage::{LockPresignatureNodeStorageBox, PresignatureData};
 use crate::storage::secret_storage::SecretNodeStorageBox;
 use crate::storage::triple_storage::LockTripleNodeStorageBox;
-use crate::storage::triple_storage::TripleData;
+use crate::storage::triple_storage::{SpentTripleData, TripleData};
 use crate::types::{KeygenProtocol, ReshareProtocol, SecretKeyShare};
 use crate::util::AffinePointExt;
 
@@ -41,6 +47,8 @@ pub trait ConsensusCtx {
     fn sign_queue(&self) -> Arc<RwLock<SignQueue>>;
     fn secret_storage(&self) -> &SecretNodeStorageBox;
     fn triple_storage(&self) -> LockTripleNodeStorageBox;
+    fn presignature_storage(&self) -> LockPresignatureNodeStorageBox;
+    fn registry(&self) -> SharedRegistry;
     fn cfg(&self) -> &Config;
 }
 
@@ -132,23 +140,47 @@ impl ConsensusProtocol for StartedState {
                                     tracing::info!(
                                         "started: contract state is running and we are already a participant"
                                     );
+                                    let registry = ctx.registry();
+                                    registry::write(&registry).set_share_commitment(
+                                        me,
+                                        registry::share_commitment(&private_share),
+                                    );
                                     let presignature_manager = PresignatureManager::new(
                                         me,
                                         contract_state.threshold,
                                         epoch,
+                                        &public_key,
+                                        self.presignature_data,
+                                        ctx.presignature_storage(),
                                         ctx.my_account_id(),
-                                    );
-                                    let triple_manager = Arc::new(RwLock::new(TripleManager::new(
+                                    )
+                                    .with_registry(registry.clone())
+                                    .with_caps(&ctx.cfg().local.scheduler);
+                                    let mut triple_manager = TripleManager::new(
                                         me,
                                         contract_state.threshold,
                                         epoch,
                                         self.triple_data,
                                         ctx.triple_storage(),
                                         ctx.my_account_id(),
-                                    )));
+                                    );
+                                    triple_manager.set_pacing(&ctx.cfg().local.scheduler);
+                                    triple_manager.set_policy(policy::from_options(
+                                        &ctx.cfg().local.scheduler,
+                                    ));
+                                    triple_manager.restore_spent(self.spent_triples).await;
+                                    let triple_manager = Arc::new(RwLock::new(triple_manager));
                                     let stuck_monitor = Arc::new(RwLock::new(
                                         StuckMonitor::new(&triple_manager).await,
                                     ));
+                                    let refresher = Refresher::new(
+                                        me,
+                                        epoch,
+                                        &contract_state.participants,
+                                        contract_state.threshold,
+                                        public_key,
+                                        ctx.my_account_id(),
+                                    );
 
                                     Ok(NodeState::Running(RunningState {
                                         epoch,
@@ -168,8 +200,14 @@ impl ConsensusProtocol for StartedState {
                                                 contract_state.public_key,
                                                 epoch,
                                                 ctx.my_account_id(),
-                                            ),
+                                            )
+                                            .with_registry(registry.clone())
+                                            .with_validators(validation::from_options(
+                                                &ctx.cfg().local.validation,
+                                            )),
                                         )),
+                                        refresher: Arc::new(RwLock::new(refresher)),
+                                        registry,
                                         messages: Default::default(),
                                     }))
                                 }
@@ -202,7 +240,7 @@ impl ConsensusProtocol for StartedState {
                             tracing::info!(
                                 "started(resharing): contract state is resharing with us, joining as a participant"
                             );
-                            start_resharing(Some(private_share), ctx, contract_state).await
+                            start_resharing(Some(&*private_share), ctx, contract_state).await
                         }
                     }
                 }
@@ -361,17 +399,46 @@ impl ConsensusProtocol for WaitingForConsensusState {
                     if let Err(err) = ctx.triple_storage().write().await.clear().await {
                         tracing::warn!(?err, "failed to clear triples from storage");
                     }
+                    // Triples of the previous epochs can no longer be used, neither can the
+                    // entries of the spent triple ledger referring to them.
+                    match ctx
+                        .triple_storage()
+                        .write()
+                        .await
+                        .prune_spent(self.epoch)
+                        .await
+                    {
+                        Ok(pruned) => tracing::info!(pruned, "pruned the spent triple ledger"),
+                        Err(err) => tracing::warn!(?err, "failed to prune the spent triple ledger"),
+                    }
+                    if let Err(err) = ctx.presignature_storage().write().await.clear().await {
+                        tracing::warn!(?err, "failed to clear presignatures from storage");
+                    }
 
-                    let triple_manager = Arc::new(RwLock::new(TripleManager::new(
+                    let mut triple_manager = TripleManager::new(
                         me,
                         self.threshold,
                         self.epoch,
                         vec![],
                         ctx.triple_storage(),
                         ctx.my_account_id(),
-                    )));
+                    );
+                    triple_manager.set_pacing(&ctx.cfg().local.scheduler);
+                    triple_manager.set_policy(policy::from_options(&ctx.cfg().local.scheduler));
+                    let triple_manager = Arc::new(RwLock::new(triple_manager));
                     let stuck_monitor =
                         Arc::new(RwLock::new(StuckMonitor::new(&triple_manager).await));
+                    let registry = ctx.registry();
+                    registry::write(&registry)
+                        .set_share_commitment(me, registry::share_commitment(&self.private_share));
+                    let refresher = Refresher::new(
+                        me,
+                        self.epoch,
+                        &self.participants,
+                        self.threshold,
+                        self.public_key,
+                        ctx.my_account_id(),
+                    );
 
                     Ok(NodeState::Running(RunningState {
                         epoch: self.epoch,
@@ -382,18 +449,31 @@ impl ConsensusProtocol for WaitingForConsensusState {
                         sign_queue: ctx.sign_queue(),
                         stuck_monitor,
                         triple_manager,
-                        presignature_manager: Arc::new(RwLock::new(PresignatureManager::new(
-                            me,
-                            self.threshold,
-                            self.epoch,
-                            ctx.my_account_id(),
-                        ))),
-                        signature_manager: Arc::new(RwLock::new(SignatureManager::new(
-                            me,
-                            self.public_key,
-                            self.epoch,
-                            ctx.my_account_id(),
-                        ))),
+                        presignature_manager: Arc::new(RwLock::new(
+                            PresignatureManager::new(
+                                me,
+                                self.threshold,
+                                self.epoch,
+                                &self.public_key,
+                                vec![],
+                                ctx.presignature_storage(),
+                                ctx.my_account_id(),
+                            )
+                            .with_registry(registry.clone())
+                            .with_caps(&ctx.cfg().local.scheduler),
+                        )),
+                        signature_manager: Arc::new(RwLock::new(
+                            SignatureManager::new(
+                                me,
+                                self.public_key,
+                                self.epoch,
+                                ctx.my_account_id(),
+                            )
+                            .with_registry(registry.clone())
+                            .with_validators(validation::from_options(&ctx.cfg().local.validation)),
+                        )),
+                        refresher: Arc::new(RwLock::new(refresher)),
+                        registry,
                         messages: self.messages,
                     }))
                 }
@@ -411,7 +491,7 @@ impl ConsensusProtocol for WaitingForConsensusState {
                         if contract_state.public_key != self.public_key {
                             return Err(ConsensusError::MismatchedPublicKey);
                         }
-                        start_resharing(Some(self.private_share), ctx, contract_state).await
+                        start_resharing(Some(&*self.private_share), ctx, contract_state).await
                     }
                     Ordering::Greater => {
                         tracing::warn!(
@@ -470,10 +550,78 @@ impl ConsensusProtocol for WaitingForConsensusState {
     }
 }
 
+impl RunningState {
+    /// Discards all triples and presignatures of the current epoch since they will no longer
+    /// be usable once the epoch advances.
+    async fn expire_epoch(&self, new_epoch: u64) {
+        self.triple_manager
+            .write()
+            .await
+            .on_epoch_change(new_epoch)
+            .await;
+        self.presignature_manager
+            .write()
+            .await
+            .on_epoch_change(new_epoch)
+            .await;
+    }
+
+    /// Applies a participant set that shrank within the current epoch, e.g. because a node got
+    /// kicked. Ongoing protocols involving the removed participants are cancelled across all the
+    /// managers, and nothing new gets started with them since they are no longer routable.
+    async fn update_participants(&mut self, participants: Participants) {
+        let removed: Vec<_> = self
+            .participants
+            .keys()
+            .filter(|p| !participants.contains_key(p))
+            .copied()
+            .collect();
+
+        let cancelled_triples = self
+            .triple_manager
+            .write()
+            .await
+            .update_participants(&participants);
+        let aborts = self
+            .presignature_manager
+            .write()
+            .await
+            .update_participants(&participants);
+        self.signature_manager
+            .write()
+            .await
+            .update_participants(&participants);
+        tracing::warn!(
+            ?removed,
+            cancelled_triples,
+            "running: participants were removed within the current epoch"
+        );
+
+        let mut messages = self.messages.write().await;
+        for (p, msg) in aborts {
+            if let Some(info) = participants.get(&p) {
+                messages.push(info.clone(), MpcMessage::Abort(msg));
+            }
+        }
+        drop(messages);
+
+        self.participants = participants;
+    }
+
+    /// Whether the given participant set only differs from ours by participants that were
+    /// removed, which can be applied without leaving the current epoch.
+    fn is_shrunk_participant_set(&self, participants: &Participants) -> bool {
+        participants.len() >= self.threshold
+            && participants
+                .iter()
+                .all(|(p, info)| self.participants.get(p) == Some(info))
+    }
+}
+
 #[async_trait]
 impl ConsensusProtocol for RunningState {
     async fn advance<C: ConsensusCtx + Send + Sync>(
-        self,
+        mut self,
         ctx: C,
         contract_state: ProtocolState,
     ) -> Result<NodeState, ConsensusError> {
@@ -486,6 +634,7 @@ impl ConsensusProtocol for RunningState {
                             self.epoch,
                             contract_state.epoch
                         );
+                    self.expire_epoch(contract_state.epoch).await;
 
                     Ok(NodeState::Joining(JoiningState {
                         participants: contract_state.participants,
@@ -495,15 +644,24 @@ impl ConsensusProtocol for RunningState {
                 Ordering::Less => Err(ConsensusError::EpochRollback),
                 Ordering::Equal => {
                     tracing::debug!("running(running): continuing to run as normal");
-                    if contract_state.participants != self.participants {
-                        return Err(ConsensusError::MismatchedParticipants);
-                    }
                     if contract_state.threshold != self.threshold {
                         return Err(ConsensusError::MismatchedThreshold);
                     }
                     if contract_state.public_key != self.public_key {
                         return Err(ConsensusError::MismatchedPublicKey);
                     }
+                    if contract_state.participants != self.participants {
+                        if !contract_state
+                            .participants
+                            .contains_account_id(ctx.my_account_id())
+                        {
+                            return Err(ConsensusError::HasBeenKicked);
+                        }
+                        if !self.is_shrunk_participant_set(&contract_state.participants) {
+                            return Err(ConsensusError::MismatchedParticipants);
+                        }
+                        self.update_participants(contract_state.participants).await;
+                    }
                     Ok(NodeState::Running(self))
                 }
             },
@@ -515,6 +673,7 @@ impl ConsensusProtocol for RunningState {
                             self.epoch,
                             contract_state.old_epoch
                         );
+                        self.expire_epoch(contract_state.old_epoch + 1).await;
 
                         Ok(NodeState::Joining(JoiningState {
                             participants: contract_state.old_participants,
@@ -536,7 +695,8 @@ impl ConsensusProtocol for RunningState {
                         if contract_state.public_key != self.public_key {
                             return Err(ConsensusError::MismatchedPublicKey);
                         }
-                        start_resharing(Some(self.private_share), ctx, contract_state).await
+                        self.expire_epoch(self.epoch + 1).await;
+                        start_resharing(Some(&*self.private_share), ctx, contract_state).await
                     }
                 }
             }
@@ -703,10 +863,17 @@ impl ConsensusProtocol for NodeState {
         match self {
             NodeState::Starting => {
                 let persistent_node_data = ctx.secret_storage().load().await?;
-                let triple_data = load_triples(ctx).await?;
+                if let Some(data) = &persistent_node_data {
+                    migrate_storage(&ctx, data.epoch).await;
+                }
+                let triple_data = load_triples(&ctx).await?;
+                let spent_triples = load_spent_triples(&ctx).await?;
+                let presignature_data = load_presignatures(&ctx).await?;
                 Ok(NodeState::Started(StartedState {
                     persistent_node_data,
                     triple_data,
+                    spent_triples,
+                    presignature_data,
                 }))
             }
             NodeState::Started(state) => state.advance(ctx, contract_state).await,
@@ -719,8 +886,24 @@ impl ConsensusProtocol for NodeState {
     }
 }
 
+/// Moves the triples and presignatures persisted before their storage keys were namespaced by
+/// epoch to their new keys. The ones failing to migrate stay under their legacy keys and are not
+/// loaded, the migration being retried on the next start.
+async fn migrate_storage<C: ConsensusCtx + Send + Sync>(ctx: &C, epoch: u64) {
+    match ctx.triple_storage().write().await.migrate(epoch).await {
+        Ok(0) => {}
+        Ok(migrated) => tracing::info!(migrated, epoch, "migrated triples to epoch keys"),
+        Err(err) => tracing::warn!(?err, "failed to migrate triples to epoch keys"),
+    }
+    match ctx.presignature_storage().write().await.migrate().await {
+        Ok(0) => {}
+        Ok(migrated) => tracing::info!(migrated, "migrated presignatures to epoch keys"),
+        Err(err) => tracing::warn!(?err, "failed to migrate presignatures to epoch keys"),
+    }
+}
+
 async fn load_triples<C: ConsensusCtx + Send + Sync>(
-    ctx: C,
+    ctx: &C,
 ) -> Result<Vec<TripleData>, ConsensusError> {
     let triple_storage = ctx.triple_storage();
     let mut retries = 3;
@@ -743,8 +926,56 @@ async fn load_triples<C: ConsensusCtx + Send + Sync>(
     Err(ConsensusError::DatastoreStorageError(error.unwrap()))
 }
 
+async fn load_spent_triples<C: ConsensusCtx + Send + Sync>(
+    ctx: &C,
+) -> Result<Vec<SpentTripleData>, ConsensusError> {
+    let triple_storage = ctx.triple_storage();
+    let mut retries = 3;
+    let mut error = None;
+    while retries > 0 {
+        match triple_storage.read().await.load_spent().await {
+            Err(DatastoreStorageError::FetchEntitiesError(_)) => {
+                tracing::info!("There are no spent triples persisted.");
+                return Ok(vec![]);
+            }
+            Err(e) => {
+                retries -= 1;
+                tracing::warn!(?e, "spent triple load failed.");
+                error = Some(e);
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            }
+            Ok(spent) => return Ok(spent),
+        }
+    }
+    Err(ConsensusError::DatastoreStorageError(error.unwrap()))
+}
+
+async fn load_presignatures<C: ConsensusCtx + Send + Sync>(
+    ctx: &C,
+) -> Result<Vec<PresignatureData>, ConsensusError> {
+    let presignature_storage = ctx.presignature_storage();
+    let mut retries = 3;
+    let mut error = None;
+    while retries > 0 {
+        match presignature_storage.read().await.load().await {
+            Err(DatastoreStorageError::FetchEntitiesError(_)) => {
+                tracing::info!("There are no presignatures persisted.");
+                return Ok(vec![]);
+            }
+            Err(e) => {
+                retries -= 1;
+                tracing::warn!(?e, "presignature load failed.");
+                error = Some(e);
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            }
+            Ok(loaded_presignatures) => return Ok(loaded_presignatures),
+        }
+    }
+    Err(ConsensusError::DatastoreStorageError(error.unwrap()))
+}
+
 async fn start_resharing<C: ConsensusCtx>(
-    private_share: Option<SecretKeyShare>,
+    private_share: Option<&SecretKeyShare>,
     ctx: C,
     contract_state: ResharingContractState,
 ) -> Result<NodeState, ConsensusError> {
@@ -0,0 +1,205 @@
+//! Commands issued by operators through the admin server, such as kick-starting the stockpiling
+//! of triples and presignatures after an incident or dropping a generator that is stuck. They are
+//! applied by the protocol loop, which owns the configuration and the view of the mesh needed to
+//! start new protocols.
+
+use cait_sith::protocol::InitializationError;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use super::state::RunningState;
+use super::{MpcMessage, NodeState};
+use crate::config::Config;
+use crate::mesh::Mesh;
+use crate::pause;
+
+/// Maximum number of protocols a single command can start.
+pub const MAX_GENERATE: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    GenerateTriples { n: usize },
+    GeneratePresignatures { n: usize },
+    DropGenerator { id: u64 },
+    PauseSigning,
+    ResumeSigning,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Number of generators that were started.
+    Started { count: usize },
+    /// Which kinds of generator with the requested id were dropped.
+    Dropped { triple: bool, presignature: bool },
+    /// Whether signing is paused by the operator and by the contract, see [`crate::pause`].
+    Signing {
+        by_operator: bool,
+        by_contract: bool,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    #[error("node is not running")]
+    NotRunning,
+    #[error("at most {MAX_GENERATE} protocols can be started at once")]
+    TooMany,
+    #[error("not enough active participants: {0} < {1}")]
+    NotEnoughParticipants(usize, usize),
+    #[error("no generator with id {0}")]
+    NotFound(u64),
+    #[error("failed to start generation: {0}")]
+    Generation(#[from] InitializationError),
+    #[error("protocol loop is not accepting commands")]
+    Unavailable,
+}
+
+pub type Request = (Command, oneshot::Sender<Result<Outcome, ControlError>>);
+
+/// Handle to send commands to the protocol loop.
+#[derive(Clone)]
+pub struct Controller {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Controller {
+    pub fn new() -> (Self, mpsc::Receiver<Request>) {
+        let (sender, receiver) = mpsc::channel(16);
+        (Self { sender }, receiver)
+    }
+
+    /// Sends the command to the protocol loop and waits for it to be applied.
+    pub async fn send(&self, command: Command) -> Result<Outcome, ControlError> {
+        let (reply, outcome) = oneshot::channel();
+        self.sender
+            .send((command, reply))
+            .await
+            .map_err(|_| ControlError::Unavailable)?;
+        outcome.await.map_err(|_| ControlError::Unavailable)?
+    }
+}
+
+/// Applies the command to the state of the node.
+pub(crate) async fn apply(
+    state: &NodeState,
+    cfg: &Config,
+    mesh: &Mesh,
+    command: Command,
+) -> Result<Outcome, ControlError> {
+    match command {
+        Command::GenerateTriples { n } => generate_triples(running(state)?, cfg, mesh, n).await,
+        Command::GeneratePresignatures { n } => {
+            generate_presignatures(running(state)?, cfg, mesh, n).await
+        }
+        Command::DropGenerator { id } => drop_generator(running(state)?, id).await,
+        // Signing can be paused in any state, such that it stays paused once the node is running.
+        Command::PauseSigning => Ok(set_signing_paused(cfg, true)),
+        Command::ResumeSigning => Ok(set_signing_paused(cfg, false)),
+    }
+}
+
+fn running(state: &NodeState) -> Result<&RunningState, ControlError> {
+    match state {
+        NodeState::Running(running) => Ok(running),
+        _ => Err(ControlError::NotRunning),
+    }
+}
+
+fn set_signing_paused(cfg: &Config, paused: bool) -> Outcome {
+    let was_paused = pause::set_by_operator(paused);
+    if was_paused != paused {
+        tracing::warn!(paused, "signing pause switched by operator");
+    }
+    Outcome::Signing {
+        by_operator: paused,
+        by_contract: pause::by_contract(&cfg.protocol),
+    }
+}
+
+fn check_active(running: &RunningState, mesh: &Mesh, n: usize) -> Result<(), ControlError> {
+    if n > MAX_GENERATE {
+        return Err(ControlError::TooMany);
+    }
+    let active = mesh.state().active.len();
+    if active < running.threshold {
+        return Err(ControlError::NotEnoughParticipants(
+            active,
+            running.threshold,
+        ));
+    }
+    Ok(())
+}
+
+async fn generate_triples(
+    running: &RunningState,
+    cfg: &Config,
+    mesh: &Mesh,
+    n: usize,
+) -> Result<Outcome, ControlError> {
+    check_active(running, mesh, n)?;
+    let mut triple_manager = running.triple_manager.write().await;
+    let before = triple_manager.generators.len();
+    for _ in 0..n {
+        triple_manager.generate(&mesh.state().active, cfg.protocol.triple.generation_timeout)?;
+    }
+    Ok(Outcome::Started {
+        count: triple_manager.generators.len() - before,
+    })
+}
+
+async fn generate_presignatures(
+    running: &RunningState,
+    cfg: &Config,
+    mesh: &Mesh,
+    n: usize,
+) -> Result<Outcome, ControlError> {
+    check_active(running, mesh, n)?;
+    let mut triple_manager = running.triple_manager.write().await;
+    let count = running
+        .presignature_manager
+        .write()
+        .await
+        .generate_batch(
+            n,
+            &mut triple_manager,
+            &mesh.state().active,
+            &running.public_key,
+            &running.private_share,
+            cfg.protocol.presignature.generation_timeout,
+        )
+        .await?;
+    Ok(Outcome::Started { count })
+}
+
+/// Drops the triple and presignature generators with the given id, as ids are drawn separately
+/// for both and may collide.
+async fn drop_generator(running: &RunningState, id: u64) -> Result<Outcome, ControlError> {
+    let triple = running
+        .triple_manager
+        .write()
+        .await
+        .cancel(id, "dropped by operator");
+    let aborts = running
+        .presignature_manager
+        .write()
+        .await
+        .cancel(id, "dropped by operator");
+    let presignature = !aborts.is_empty();
+    if !triple && !presignature {
+        return Err(ControlError::NotFound(id));
+    }
+    if presignature {
+        let mut messages = running.messages.write().await;
+        for (p, msg) in aborts {
+            if let Some(info) = running.participants.get(&p) {
+                messages.push(info.clone(), MpcMessage::Abort(msg));
+            }
+        }
+    }
+    Ok(Outcome::Dropped {
+        triple,
+        presignature,
+    })
+}
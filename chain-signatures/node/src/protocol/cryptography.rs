@@ -1,19 +1,29 @@
 use std::sync::PoisonError;
 
 use super::state::{GeneratingState, NodeState, ResharingState, RunningState};
+use super::worker::{Tick, WorkerOutbox, Workers};
 use super::Config;
 use crate::gcp::error::SecretStorageError;
 use crate::http_client::SendError;
+use crate::journal::{self, Event, ProtocolKind};
 use crate::mesh::Mesh;
+use crate::pause;
 use crate::protocol::message::{GeneratingMessage, ResharingMessage};
+use crate::protocol::refresh;
+use crate::protocol::registry;
+use crate::protocol::scheduler::{PokeFailure, PokeKind};
+use crate::protocol::signature::SignRequestError;
 use crate::protocol::state::{PersistentNodeData, WaitingForConsensusState};
 use crate::protocol::MpcMessage;
+use crate::shutdown;
 use crate::storage::secret_storage::SecretNodeStorageBox;
+use crate::storage::sign_request_storage::{self, LockSignRequestNodeStorageBox};
 use async_trait::async_trait;
 use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
 use k256::elliptic_curve::group::GroupEncoding;
 use near_account_id::AccountId;
 use near_crypto::InMemorySigner;
+use zeroize::Zeroizing;
 
 #[async_trait::async_trait]
 pub trait CryptographicCtx {
@@ -22,11 +32,16 @@ pub trait CryptographicCtx {
     fn rpc_client(&self) -> &near_fetch::Client;
     fn signer(&self) -> &InMemorySigner;
     fn mpc_contract_id(&self) -> &AccountId;
+    fn my_account_id(&self) -> &AccountId;
     fn secret_storage(&mut self) -> &mut SecretNodeStorageBox;
+    fn sign_request_storage(&self) -> &LockSignRequestNodeStorageBox;
     fn cfg(&self) -> &Config;
 
     /// Active participants is the active participants at the beginning of each protocol loop.
     fn mesh(&self) -> &Mesh;
+
+    /// Workers cranking the protocols of the managers of the running state.
+    fn workers(&mut self) -> &mut Workers;
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -45,12 +60,19 @@ pub enum CryptographicError {
     SyncError(String),
     #[error(transparent)]
     DataConversion(#[from] serde_json::Error),
+    #[error(transparent)]
+    Codec(#[from] super::codec::CodecError),
     #[error("encryption failed: {0}")]
     Encryption(String),
     #[error("more than one writing to state: {0}")]
     InvalidStateHandle(String),
     #[error("secret storage error: {0}")]
     SecretStorageError(#[from] SecretStorageError),
+    #[error("message claims to be sent by {claimed:?} but was sent by {authenticated:?}")]
+    SenderMismatch {
+        claimed: Participant,
+        authenticated: Participant,
+    },
 }
 
 impl<T> From<PoisonError<T>> for CryptographicError {
@@ -148,7 +170,7 @@ impl CryptographicProtocol for GeneratingState {
                     ctx.secret_storage()
                         .store(&PersistentNodeData {
                             epoch: 0,
-                            private_share: r.private_share,
+                            private_share: Zeroizing::new(r.private_share),
                             public_key: r.public_key,
                         })
                         .await?;
@@ -175,7 +197,7 @@ impl CryptographicProtocol for GeneratingState {
                         epoch: 0,
                         participants: self.participants,
                         threshold: self.threshold,
-                        private_share: r.private_share,
+                        private_share: Zeroizing::new(r.private_share),
                         public_key: r.public_key,
                         messages: self.messages,
                     }));
@@ -309,7 +331,7 @@ impl CryptographicProtocol for ResharingState {
                     ctx.secret_storage()
                         .store(&PersistentNodeData {
                             epoch: self.old_epoch + 1,
-                            private_share,
+                            private_share: Zeroizing::new(private_share),
                             public_key: self.public_key,
                         })
                         .await?;
@@ -340,7 +362,7 @@ impl CryptographicProtocol for ResharingState {
                         epoch: self.old_epoch + 1,
                         participants: self.new_participants,
                         threshold: self.threshold,
-                        private_share,
+                        private_share: Zeroizing::new(private_share),
                         public_key: self.public_key,
                         messages: self.messages,
                     }));
@@ -354,48 +376,198 @@ impl CryptographicProtocol for ResharingState {
 impl CryptographicProtocol for RunningState {
     async fn progress<C: CryptographicCtx + Send + Sync>(
         mut self,
-        ctx: C,
+        mut ctx: C,
     ) -> Result<NodeState, CryptographicError> {
+        self.refresh_share(&mut ctx).await?;
+
+        // The protocols of the managers are cranked by their workers, which leave the sending of
+        // their messages and the publishing of the signatures to the protocol loop.
+        let produced = ctx.workers().take_outbox();
+        let my_account_id = ctx.my_account_id().clone();
         let protocol_cfg = &ctx.cfg().protocol;
-        let active = ctx.mesh().active_participants();
+        let mesh_state = ctx.mesh().state();
+        let active = &mesh_state.active;
+
+        if !produced.is_empty() {
+            let mut messages = self.messages.write().await;
+            for (p, msg) in produced {
+                match self.fetch_participant(&p) {
+                    Ok(info) => messages.push(info.clone(), msg),
+                    Err(err) => tracing::warn!(?err, "running: dropping message of a worker"),
+                }
+            }
+        }
         if active.len() < self.threshold {
             tracing::warn!(
                 active = ?active.keys_vec(),
+                unreachable = ?mesh_state.unreachable,
                 "running: not enough participants to progress"
             );
             return Ok(NodeState::Running(self));
         }
 
+        // The workers lock the outgoing messages while holding their managers, so the managers
+        // are only locked here while the outgoing messages are not.
+        let mut sign_request_transitions = self.sign_queue.write().await.take_transitions();
+        let mut signature_manager = self.signature_manager.write().await;
+        signature_manager
+            .publish(ctx.rpc_client(), ctx.signer(), ctx.mpc_contract_id())
+            .await;
+        let signatures_in_flight =
+            signature_manager.generators().len() + signature_manager.to_publish_len();
+        sign_request_transitions.extend(signature_manager.take_transitions());
+        drop(signature_manager);
+        sign_request_storage::record(ctx.sign_request_storage(), sign_request_transitions).await;
+
         let mut messages = self.messages.write().await;
-        let mut triple_manager = self.triple_manager.write().await;
-        let my_account_id = triple_manager.my_account_id.clone();
+        messages.set_formats(&mesh_state.formats);
+        let network_cfg = &ctx.cfg().local.network;
+        messages.set_limits(
+            network_cfg.peer_send_rate_limit,
+            network_cfg.peer_outbox_limit,
+        );
+        crate::metrics::NUM_UNREACHABLE_PARTICIPANTS
+            .with_label_values(&[my_account_id.as_str()])
+            .set(mesh_state.unreachable.len() as i64);
         crate::metrics::MESSAGE_QUEUE_SIZE
             .with_label_values(&[my_account_id.as_str()])
             .set(messages.len() as i64);
-        if let Err(err) = triple_manager.stockpile(active, protocol_cfg) {
+        let draining = shutdown::is_draining();
+        let failures = messages
+            .send_encrypted(
+                ctx.me().await,
+                &ctx.cfg().local.network.sign_sk,
+                ctx.http_client(),
+                active,
+                protocol_cfg,
+            )
+            .await;
+        if !failures.is_empty() {
+            tracing::warn!(
+                active = ?active.keys_vec(),
+                "running: failed to send encrypted message; {failures:?}"
+            );
+        }
+        if draining {
+            tracing::info!(
+                signatures_in_flight,
+                outbox = messages.len(),
+                "running: draining before shutdown"
+            );
+            if signatures_in_flight == 0 && messages.is_empty() {
+                shutdown::drained();
+            }
+        }
+        drop(messages);
+
+        Ok(NodeState::Running(self))
+    }
+}
+
+impl RunningState {
+    /// Stockpiles and advances the triple protocols, on the triple worker.
+    pub(super) async fn crank_triples(&self, tick: &Tick, outbox: &WorkerOutbox) {
+        let protocol_cfg = &tick.cfg.protocol;
+        let stockpile = &tick.stockpile;
+        let active = &tick.mesh.active;
+        let my_account_id = tick.my_account_id.as_str();
+
+        let mut triple_manager = self.triple_manager.write().await;
+        triple_manager.set_subsets(&self.participants, protocol_cfg);
+        if stockpile.to_evict > 0 {
+            let evicted = triple_manager.evict_oldest(stockpile.to_evict).await;
+            tracing::warn!(
+                usage = ?stockpile.usage,
+                ?evicted,
+                "running: evicted triples to shed load"
+            );
+        }
+        if stockpile.draining {
+            tracing::info!("running: shutting down, not stockpiling triples");
+        } else if stockpile.shedding {
+            tracing::debug!(
+                usage = ?stockpile.usage,
+                "running: shedding load, not stockpiling triples"
+            );
+        } else if stockpile.lacks_liveness {
+            tracing::debug!(
+                active = active.len(),
+                required_live = stockpile.required_live,
+                "running: not enough responsive participants, not stockpiling triples"
+            );
+        } else if let Err(err) = triple_manager.stockpile(active, protocol_cfg) {
             tracing::warn!(?err, "running: failed to stockpile triples");
         }
-        for (p, msg) in triple_manager.poke(protocol_cfg).await {
-            let info = self.fetch_participant(&p)?;
-            messages.push(info.clone(), MpcMessage::Triple(msg));
+
+        let mut budget = tick.budget.clone();
+        let triple_outcome = triple_manager
+            .poke_with_budget(protocol_cfg, budget.budget(PokeKind::Triple))
+            .await;
+        budget.spend(PokeKind::Triple, triple_outcome.messages.len());
+        log_failures("triple", &triple_outcome.failures);
+        for (p, msg) in triple_outcome.messages {
+            journal::record(
+                msg.epoch,
+                ProtocolKind::Triple,
+                msg.id,
+                Event::Sent { to: p },
+            );
+            outbox.push(p, MpcMessage::Triple(msg));
         }
 
         crate::metrics::NUM_TRIPLES_MINE
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(triple_manager.mine.len() as i64);
         crate::metrics::NUM_TRIPLES_TOTAL
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(triple_manager.triples.len() as i64);
         crate::metrics::NUM_TRIPLE_GENERATORS_INTRODUCED
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(triple_manager.introduced.len() as i64);
         crate::metrics::NUM_TRIPLE_GENERATORS_TOTAL
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(triple_manager.ongoing.len() as i64);
+        let pruned = self.messages.write().await.prune(|msg| match msg {
+            MpcMessage::Triple(msg) => !triple_manager.gc.contains_key(&msg.id),
+            _ => true,
+        });
+        log_pruned(my_account_id, pruned);
+        drop(triple_manager);
+
+        self.stuck_monitor.write().await.check(protocol_cfg).await;
+    }
 
+    /// Stockpiles and advances the presignature protocols, on the presignature worker.
+    pub(super) async fn crank_presignatures(&self, tick: &Tick, outbox: &WorkerOutbox) {
+        let protocol_cfg = &tick.cfg.protocol;
+        let stockpile = &tick.stockpile;
+        let active = &tick.mesh.active;
+        let my_account_id = tick.my_account_id.as_str();
+
+        let mut triple_manager = self.triple_manager.write().await;
         let mut presignature_manager = self.presignature_manager.write().await;
-        if let Err(err) = presignature_manager
+        presignature_manager.on_unreachable(&tick.mesh.unreachable);
+        if let Some(max_age) = tick.cfg.local.presignature_max_age {
+            presignature_manager.discard_aged(max_age).await;
+        }
+        if stockpile.draining {
+            tracing::info!("running: shutting down, not stockpiling presignatures");
+        } else if stockpile.shedding {
+            tracing::debug!(
+                usage = ?stockpile.usage,
+                "running: shedding load, not stockpiling presignatures"
+            );
+        } else if stockpile.lacks_liveness {
+            tracing::debug!(
+                active = active.len(),
+                required_live = stockpile.required_live,
+                "running: not enough responsive participants, not stockpiling presignatures"
+            );
+        } else if refresh::is_refreshing() {
+            tracing::debug!("running: refreshing the key share, not stockpiling presignatures");
+        } else if let Err(err) = presignature_manager
             .stockpile(
+                &self.participants,
                 active,
                 &self.public_key,
                 &self.private_share,
@@ -406,35 +578,91 @@ impl CryptographicProtocol for RunningState {
         {
             tracing::warn!(?err, "running: failed to stockpile presignatures");
         }
+
+        // The presignature generators are poked without holding the locks of the managers, so that
+        // readers such as the `/state` endpoint are not blocked behind the CPU bound poke.
+        let checked_out = presignature_manager.checkout();
+        drop(presignature_manager);
         drop(triple_manager);
-        for (p, msg) in presignature_manager.poke() {
-            let info = self.fetch_participant(&p)?;
-            messages.push(info.clone(), MpcMessage::Presignature(msg));
+        let mut budget = tick.budget.clone();
+        let poked = checked_out
+            .poke(budget.budget(PokeKind::Presignature))
+            .await;
+        let mut presignature_manager = self.presignature_manager.write().await;
+        let presignature_outcome = presignature_manager.checkin(poked).await;
+        budget.spend(PokeKind::Presignature, presignature_outcome.messages.len());
+        log_failures("presignature", &presignature_outcome.failures);
+        for (p, msg) in presignature_outcome.messages {
+            journal::record(
+                msg.epoch,
+                ProtocolKind::Presignature,
+                msg.id,
+                Event::Sent { to: p },
+            );
+            outbox.push(p, MpcMessage::Presignature(msg));
+        }
+        // Let the other participants know about the failed generators instead of having them
+        // wait for the generators to time out.
+        for (p, msg) in presignature_manager.take_aborts() {
+            outbox.push(p, MpcMessage::Abort(msg));
         }
+        // Let the proposers retrying with triples we never had know, so they stop retrying.
+        for (p, msg) in presignature_manager.take_nacks() {
+            outbox.push(p, MpcMessage::UnknownTriple(msg));
+        }
+        // Let the other participants check that they completed the same presignatures.
+        for (p, msg) in presignature_manager.take_commitments() {
+            outbox.push(p, MpcMessage::Commitment(msg));
+        }
+        presignature_manager.report_missing_triples();
 
         crate::metrics::NUM_PRESIGNATURES_MINE
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(presignature_manager.my_len() as i64);
         crate::metrics::NUM_PRESIGNATURES_TOTAL
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(presignature_manager.len() as i64);
         crate::metrics::NUM_PRESIGNATURE_GENERATORS_TOTAL
-            .with_label_values(&[my_account_id.as_str()])
+            .with_label_values(&[my_account_id])
             .set(presignature_manager.potential_len() as i64 - presignature_manager.len() as i64);
+        let pruned = self.messages.write().await.prune(|msg| match msg {
+            MpcMessage::Presignature(msg) => !presignature_manager.is_garbage_collected(&msg.id),
+            _ => true,
+        });
+        log_pruned(my_account_id, pruned);
+    }
+
+    /// Organizes the sign queue, proposes the signatures of our requests and advances the
+    /// signature protocols, on the signature worker.
+    pub(super) async fn crank_signatures(&self, tick: &Tick, outbox: &WorkerOutbox) {
+        let protocol_cfg = &tick.cfg.protocol;
+        let my_account_id = &tick.my_account_id;
+        let me = tick.me;
 
         // NOTE: signatures should only use stable and not active participants. The difference here is that
         // stable participants utilizes more than the online status of a node, such as whether or not their
         // block height is up to date, such that they too can process signature requests. If they cannot
         // then they are considered unstable and should not be a part of signature generation this round.
-        let stable = ctx.mesh().stable_participants().await;
+        let stable = &tick.mesh.stable;
         tracing::debug!(?stable, "stable participants");
 
+        let mut presignature_manager = self.presignature_manager.write().await;
         let mut sign_queue = self.sign_queue.write().await;
         crate::metrics::SIGN_QUEUE_SIZE
             .with_label_values(&[my_account_id.as_str()])
             .set(sign_queue.len() as i64);
-        let me = ctx.me().await;
-        sign_queue.organize(self.threshold, &stable, me, &my_account_id);
+        sign_queue.organize(self.threshold, stable, me, my_account_id);
+        for err in sign_queue.expire() {
+            let SignRequestError::Expired { proposer, .. } = &err;
+            if *proposer == me {
+                tracing::warn!(%err, "running: sign request expired");
+                crate::metrics::NUM_SIGN_REQUESTS_EXPIRED
+                    .with_label_values(&[my_account_id.as_str()])
+                    .inc();
+            } else {
+                tracing::debug!(%err, "running: dropped expired sign request of another proposer");
+            }
+        }
 
         let my_requests = sign_queue.my_requests(me);
         crate::metrics::SIGN_QUEUE_MINE_SIZE
@@ -442,43 +670,132 @@ impl CryptographicProtocol for RunningState {
             .set(my_requests.len() as i64);
 
         let mut signature_manager = self.signature_manager.write().await;
-        signature_manager.handle_requests(
-            self.threshold,
-            &stable,
-            my_requests,
-            &mut presignature_manager,
-            protocol_cfg,
-        );
+        let paused = pause::is_paused(protocol_cfg);
+        crate::metrics::SIGNING_PAUSED
+            .with_label_values(&[my_account_id.as_str()])
+            .set(paused as i64);
+        // The requests left in the queue when shutting down are carried over by the snapshot, and
+        // the ones left while paused are proposed once signing resumes.
+        if paused {
+            tracing::info!(
+                by_operator = pause::by_operator(),
+                queued = my_requests.len(),
+                "running: signing is paused, not proposing signatures"
+            );
+        } else if !tick.stockpile.draining {
+            signature_manager
+                .handle_requests(
+                    self.threshold,
+                    stable,
+                    my_requests,
+                    &mut presignature_manager,
+                    protocol_cfg,
+                )
+                .await;
+        }
         drop(sign_queue);
         drop(presignature_manager);
 
-        for (p, msg) in signature_manager.poke() {
-            let info = self.fetch_participant(&p)?;
-            messages.push(info.clone(), MpcMessage::Signature(msg));
-        }
-        signature_manager
-            .publish(ctx.rpc_client(), ctx.signer(), ctx.mpc_contract_id())
-            .await;
-        drop(signature_manager);
-        let failures = messages
-            .send_encrypted(
-                ctx.me().await,
-                &ctx.cfg().local.network.sign_sk,
-                ctx.http_client(),
-                active,
-                protocol_cfg,
-            )
-            .await;
-        if !failures.is_empty() {
-            tracing::warn!(
-                active = ?active.keys_vec(),
-                "running: failed to send encrypted message; {failures:?}"
+        let mut budget = tick.budget.clone();
+        let signature_outcome =
+            signature_manager.poke_with_budget(budget.budget(PokeKind::Signature));
+        budget.spend(PokeKind::Signature, signature_outcome.messages.len());
+        log_failures("signature", &signature_outcome.failures);
+        for (p, msg) in signature_outcome.messages {
+            journal::record(
+                msg.epoch,
+                ProtocolKind::Signature,
+                msg.receipt_id,
+                Event::Sent { to: p },
             );
+            outbox.push(p, MpcMessage::Signature(msg));
         }
-        drop(messages);
+        crate::metrics::NUM_SIGNATURE_GENERATORS_TOTAL
+            .with_label_values(&[my_account_id.as_str()])
+            .set(signature_manager.generators().len() as i64);
+        let pruned = self.messages.write().await.prune(|msg| match msg {
+            MpcMessage::Signature(msg) => !signature_manager.is_completed(&msg.receipt_id),
+            _ => true,
+        });
+        log_pruned(my_account_id.as_str(), pruned);
+    }
 
-        self.stuck_monitor.write().await.check(protocol_cfg).await;
-        Ok(NodeState::Running(self))
+    /// Advances the proactive refresh of our key share, and puts the refreshed share in use once
+    /// every participant completed the refresh. The presignatures generated with the previous
+    /// share cannot be combined with the refreshed shares of the others, so they are discarded.
+    async fn refresh_share<C: CryptographicCtx + Send + Sync>(
+        &mut self,
+        ctx: &mut C,
+    ) -> Result<(), CryptographicError> {
+        let mut refresher = self.refresher.write().await;
+        let step = refresher.progress(
+            &self.private_share,
+            &ctx.mesh().state().active,
+            &ctx.cfg().protocol,
+        );
+        if !step.messages.is_empty() {
+            let mut messages = self.messages.write().await;
+            for (p, msg) in step.messages {
+                let info = self.fetch_participant(&p)?;
+                messages.push(info.clone(), MpcMessage::Refresh(msg));
+            }
+        }
+
+        let Some((round, private_share)) = step.ready else {
+            return Ok(());
+        };
+        ctx.secret_storage()
+            .store(&PersistentNodeData {
+                epoch: self.epoch,
+                private_share: Zeroizing::new(private_share),
+                public_key: self.public_key,
+            })
+            .await?;
+        self.private_share = Zeroizing::new(private_share);
+        let me = ctx.me().await;
+        registry::write(&self.registry)
+            .set_share_commitment(me, registry::share_commitment(&private_share));
+        self.presignature_manager
+            .write()
+            .await
+            .on_share_refresh(round)
+            .await;
+        refresher.commit();
+        Ok(())
+    }
+}
+
+/// Reports the undelivered messages pruned by a worker. Messages of protocols that are already done
+/// on our side are of no use to the other participants anymore, since they drop them as well.
+fn log_pruned(my_account_id: &str, pruned: usize) {
+    if pruned > 0 {
+        tracing::debug!(pruned, "running: pruned messages of finished protocols");
+        crate::metrics::NUM_OUTBOX_MESSAGES_PRUNED
+            .with_label_values(&[my_account_id])
+            .inc_by(pruned as f64);
+    }
+}
+
+/// Reports the protocols that failed while being poked. Timeouts are expected every now and then
+/// when participants are slow or restart, whereas a failing protocol points at a participant
+/// sending bad data.
+fn log_failures<Id: std::fmt::Debug>(kind: &str, failures: &[(Id, PokeFailure)]) {
+    let (timeouts, faults): (Vec<_>, Vec<_>) = failures
+        .iter()
+        .partition(|(_, failure)| failure.is_timeout());
+    if !timeouts.is_empty() {
+        tracing::info!(
+            kind,
+            timed_out = ?timeouts.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            "running: some generators timed out and were dropped"
+        );
+    }
+    if !faults.is_empty() {
+        tracing::warn!(
+            kind,
+            ?faults,
+            "running: some generators failed on data from other participants and were dropped"
+        );
     }
 }
 
@@ -0,0 +1,120 @@
+//! Poking of the triple and presignature generators. Poking is CPU bound, so the generators are
+//! poked in parallel on the rayon pool from a blocking task, leaving the tokio workers free to
+//! serve the other protocols and the web endpoints in the meantime.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::scheduler::PokeFailure;
+
+/// The state a protocol is left in after being poked until it could not progress any further.
+pub enum PokeStatus<T> {
+    /// Waiting on messages from the other participants.
+    Waiting,
+    Completed(T),
+    Failed(PokeFailure),
+}
+
+/// Orders the ids of the generators to be poked such that poking resumes from `cursor`, the first
+/// generator skipped for lack of budget the last time, wrapping around to the ones before it.
+/// Otherwise the generators skipped would be the same on every poke, and starve.
+pub fn rotate<I: Ord + Copy>(ids: &mut [I], cursor: Option<I>) {
+    ids.sort_unstable();
+    if let Some(cursor) = cursor {
+        let start = ids.partition_point(|id| *id < cursor);
+        ids.rotate_left(start);
+    }
+}
+
+/// Pokes the generators in parallel with `poke`, which returns the messages produced alongside
+/// the status the generator was left in. Generators are picked up in the order they were given,
+/// such that once `budget` messages have been produced, the generators skipped are the trailing
+/// ones. These are returned without a result, to be poked next time. The generators are returned
+/// in the order they were given.
+pub async fn poke_all<G, M, T, F>(
+    generators: Vec<G>,
+    budget: usize,
+    poke: F,
+) -> Vec<(G, Option<(Vec<M>, PokeStatus<T>)>)>
+where
+    G: Send + 'static,
+    M: Send + 'static,
+    T: Send + 'static,
+    F: Fn(&mut G) -> (Vec<M>, PokeStatus<T>) + Send + Sync + 'static,
+{
+    if generators.is_empty() {
+        return Vec::new();
+    }
+    tokio::task::spawn_blocking(move || {
+        let len = generators.len();
+        let slots = generators
+            .into_iter()
+            .map(|generator| Mutex::new((generator, None)))
+            .collect::<Vec<_>>();
+        let (next, produced) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        rayon::scope(|scope| {
+            for _ in 0..rayon::current_num_threads().min(len) {
+                scope.spawn(|_| {
+                    while produced.load(Ordering::Relaxed) < budget {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(slot) = slots.get(i) else {
+                            break;
+                        };
+                        let mut slot = slot.lock().unwrap();
+                        let (messages, status) = poke(&mut slot.0);
+                        produced.fetch_add(messages.len(), Ordering::Relaxed);
+                        slot.1 = Some((messages, status));
+                    }
+                });
+            }
+        });
+        slots
+            .into_iter()
+            .map(|slot| slot.into_inner().unwrap())
+            .collect()
+    })
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_poke_all_respects_budget() {
+        // Every generator produces two messages and completes with its own value.
+        let poke = |generator: &mut u32| (vec![*generator; 2], PokeStatus::Completed(*generator));
+        let poked = poke_all((0..64).collect(), usize::MAX, poke).await;
+        assert_eq!(
+            poked.iter().map(|(g, _)| *g).collect::<Vec<_>>(),
+            (0..64).collect::<Vec<_>>()
+        );
+        assert!(poked.iter().all(|(g, result)| matches!(
+            result,
+            Some((messages, PokeStatus::Completed(v))) if messages.len() == 2 && v == g
+        )));
+
+        // Only the trailing generators are skipped once the budget is spent.
+        let poked = poke_all((0..64).collect(), 1, poke).await;
+        assert!(poked[0].1.is_some());
+        assert!(poked
+            .iter()
+            .skip_while(|(_, result)| result.is_some())
+            .all(|(_, result)| result.is_none()));
+        assert_eq!(poke_all(Vec::new(), 0, poke).await.len(), 0);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut ids = vec![5, 1, 4, 2, 3];
+        rotate(&mut ids, None);
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        rotate(&mut ids, Some(3));
+        assert_eq!(ids, vec![3, 4, 5, 1, 2]);
+        // The generator skipped last time may be gone since, poking resumes after it.
+        let mut ids = vec![5, 1, 4, 2];
+        rotate(&mut ids, Some(3));
+        assert_eq!(ids, vec![4, 5, 1, 2]);
+    }
+}
@@ -1,22 +1,33 @@
+use super::codec;
 use super::cryptography::CryptographicError;
-use super::presignature::{GenerationError, PresignatureId};
+use super::presignature::{hash_as_id, GenerationError, PresignatureId};
+use super::reputation::{Misbehavior, Reputation};
+use super::router::MpcMessageQueue;
 use super::state::{GeneratingState, NodeState, ResharingState, RunningState};
 use super::triple::TripleId;
+use super::worker::Tick;
 use crate::gcp::error::SecretStorageError;
 use crate::http_client::SendError;
 use crate::indexer::ContractSignRequest;
+use crate::journal::{self, Event, ProtocolKind};
+use crate::keyspace::KeyspaceId;
 use crate::mesh::Mesh;
+use crate::pause;
+use crate::redact::{Secret, UserScalar};
 use crate::util;
 
 use async_trait::async_trait;
 use cait_sith::protocol::{InitializationError, MessageData, Participant, ProtocolError};
+use chrono::Utc;
 use k256::Scalar;
 use mpc_keys::hpke::{self, Ciphered};
 use near_crypto::Signature;
 use near_primitives::hash::CryptoHash;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 #[async_trait::async_trait]
@@ -24,46 +35,109 @@ pub trait MessageCtx {
     async fn me(&self) -> Participant;
     fn mesh(&self) -> &Mesh;
     fn cfg(&self) -> &crate::config::Config;
+    fn reputation(&self) -> &RwLock<Reputation>;
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct GeneratingMessage {
     pub from: Participant,
+    #[serde(with = "super::codec::bytes")]
     pub data: MessageData,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl fmt::Debug for GeneratingMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeneratingMessage")
+            .field("from", &self.from)
+            .field("data", &Secret(&self.data))
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct ResharingMessage {
     pub epoch: u64,
     pub from: Participant,
+    #[serde(with = "super::codec::bytes")]
     pub data: MessageData,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl fmt::Debug for ResharingMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResharingMessage")
+            .field("epoch", &self.epoch)
+            .field("from", &self.from)
+            .field("data", &Secret(&self.data))
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct TripleMessage {
     pub id: u64,
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
     pub epoch: u64,
     pub from: Participant,
+    #[serde(with = "super::codec::bytes")]
     pub data: MessageData,
     // UNIX timestamp as seconds since the epoch
     pub timestamp: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl fmt::Debug for TripleMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TripleMessage")
+            .field("id", &self.id)
+            .field("keyspace", &self.keyspace)
+            .field("epoch", &self.epoch)
+            .field("from", &self.from)
+            .field("data", &Secret(&self.data))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct PresignatureMessage {
     pub id: u64,
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
     pub triple0: TripleId,
     pub triple1: TripleId,
+    /// Required unlike the other fields added since schema 1: the id of the presignature is
+    /// derived from the proposer, so nodes predating it derive other ids for the same triples and
+    /// cannot generate presignatures with this node anyway. Their messages fail to decode.
+    pub proposer: Participant,
     pub epoch: u64,
     pub from: Participant,
+    #[serde(with = "super::codec::bytes")]
     pub data: MessageData,
     // UNIX timestamp as seconds since the epoch
     pub timestamp: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl fmt::Debug for PresignatureMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PresignatureMessage")
+            .field("id", &self.id)
+            .field("keyspace", &self.keyspace)
+            .field("triple0", &self.triple0)
+            .field("triple1", &self.triple1)
+            .field("proposer", &self.proposer)
+            .field("epoch", &self.epoch)
+            .field("from", &self.from)
+            .field("data", &Secret(&self.data))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct SignatureMessage {
     pub receipt_id: CryptoHash,
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
     pub proposer: Participant,
     pub presignature_id: PresignatureId,
     pub request: ContractSignRequest,
@@ -71,18 +145,137 @@ pub struct SignatureMessage {
     pub entropy: [u8; 32],
     pub epoch: u64,
     pub from: Participant,
+    #[serde(with = "super::codec::bytes")]
     pub data: MessageData,
     // UNIX timestamp as seconds since the epoch
     pub timestamp: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl fmt::Debug for SignatureMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignatureMessage")
+            .field("receipt_id", &self.receipt_id)
+            .field("keyspace", &self.keyspace)
+            .field("proposer", &self.proposer)
+            .field("presignature_id", &self.presignature_id)
+            .field("request", &self.request)
+            .field("epsilon", &UserScalar(&self.epsilon))
+            .field("epoch", &self.epoch)
+            .field("from", &self.from)
+            .field("data", &Secret(&self.data))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+/// Tells the other participants of a presignature generation protocol that the sender has
+/// abandoned it, so they can stop poking it instead of waiting for it to time out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AbortMessage {
+    pub id: PresignatureId,
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
+    pub epoch: u64,
+    pub from: Participant,
+    pub reason: String,
+    // UNIX timestamp as seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Lists the unspent presignatures the sender holds, so that the other participants can drop the
+/// presignatures that too few participants still hold to sign with, see
+/// [`super::reconciler`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReconcileMessage {
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
+    pub epoch: u64,
+    pub from: Participant,
+    pub presignatures: Vec<PresignatureId>,
+    // UNIX timestamp as seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Tells the proposer of a presignature that the sender never had one of its triples, so that it
+/// stops retrying the presignature instead of waiting for it to time out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTripleMessage {
+    pub id: PresignatureId,
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
+    pub epoch: u64,
+    pub from: Participant,
+    pub triple_id: TripleId,
+    // UNIX timestamp as seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Commitment to the `big_r` of a presignature the sender completed, for the other participants
+/// to check that they completed the same presignature.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommitmentMessage {
+    pub id: PresignatureId,
+    #[serde(default, skip_serializing_if = "KeyspaceId::is_root")]
+    pub keyspace: KeyspaceId,
+    pub epoch: u64,
+    pub from: Participant,
+    pub commitment: [u8; 32],
+    // UNIX timestamp as seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Tells the other participants that the sender (re)started at `started_at`, such that they drop
+/// the protocols they started with it before, which it lost track of, instead of waiting for them
+/// to time out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AnnounceMessage {
+    pub epoch: u64,
+    pub from: Participant,
+    /// UNIX timestamp as seconds since the epoch of when the sender started.
+    pub started_at: u64,
+    // UNIX timestamp as seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Message of the proactive refresh of the key shares of a round of the refresh schedule, see
+/// [`super::refresh`]. Once the sender completed the refresh, it lets the other participants know
+/// with a message without data.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RefreshMessage {
+    pub epoch: u64,
+    pub round: u64,
+    pub from: Participant,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(with = "super::codec::bytes")]
+    pub data: MessageData,
+}
+
+impl fmt::Debug for RefreshMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefreshMessage")
+            .field("epoch", &self.epoch)
+            .field("round", &self.round)
+            .field("from", &self.from)
+            .field("completed", &self.completed)
+            .field("data", &Secret(&self.data))
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum MpcMessage {
     Generating(GeneratingMessage),
     Resharing(ResharingMessage),
     Triple(TripleMessage),
     Presignature(PresignatureMessage),
     Signature(SignatureMessage),
+    Abort(AbortMessage),
+    Reconcile(ReconcileMessage),
+    UnknownTriple(UnknownTripleMessage),
+    Commitment(CommitmentMessage),
+    Announce(AnnounceMessage),
+    Refresh(RefreshMessage),
 }
 
 impl MpcMessage {
@@ -93,49 +286,28 @@ impl MpcMessage {
             MpcMessage::Triple(_) => "Triple",
             MpcMessage::Presignature(_) => "Presignature",
             MpcMessage::Signature(_) => "Signature",
+            MpcMessage::Abort(_) => "Abort",
+            MpcMessage::Reconcile(_) => "Reconcile",
+            MpcMessage::UnknownTriple(_) => "UnknownTriple",
+            MpcMessage::Commitment(_) => "Commitment",
+            MpcMessage::Announce(_) => "Announce",
+            MpcMessage::Refresh(_) => "Refresh",
         }
     }
-}
 
-#[derive(Default)]
-pub struct MpcMessageQueue {
-    generating: VecDeque<GeneratingMessage>,
-    resharing_bins: HashMap<u64, VecDeque<ResharingMessage>>,
-    triple_bins: HashMap<u64, HashMap<TripleId, VecDeque<TripleMessage>>>,
-    presignature_bins: HashMap<u64, HashMap<PresignatureId, VecDeque<PresignatureMessage>>>,
-    signature_bins: HashMap<u64, HashMap<CryptoHash, VecDeque<SignatureMessage>>>,
-}
-
-impl MpcMessageQueue {
-    pub fn push(&mut self, message: MpcMessage) {
-        match message {
-            MpcMessage::Generating(message) => self.generating.push_back(message),
-            MpcMessage::Resharing(message) => self
-                .resharing_bins
-                .entry(message.epoch)
-                .or_default()
-                .push_back(message),
-            MpcMessage::Triple(message) => self
-                .triple_bins
-                .entry(message.epoch)
-                .or_default()
-                .entry(message.id)
-                .or_default()
-                .push_back(message),
-            MpcMessage::Presignature(message) => self
-                .presignature_bins
-                .entry(message.epoch)
-                .or_default()
-                .entry(message.id)
-                .or_default()
-                .push_back(message),
-            MpcMessage::Signature(message) => self
-                .signature_bins
-                .entry(message.epoch)
-                .or_default()
-                .entry(message.receipt_id)
-                .or_default()
-                .push_back(message),
+    pub fn sender(&self) -> Participant {
+        match self {
+            MpcMessage::Generating(msg) => msg.from,
+            MpcMessage::Resharing(msg) => msg.from,
+            MpcMessage::Triple(msg) => msg.from,
+            MpcMessage::Presignature(msg) => msg.from,
+            MpcMessage::Signature(msg) => msg.from,
+            MpcMessage::Abort(msg) => msg.from,
+            MpcMessage::Reconcile(msg) => msg.from,
+            MpcMessage::UnknownTriple(msg) => msg.from,
+            MpcMessage::Commitment(msg) => msg.from,
+            MpcMessage::Announce(msg) => msg.from,
+            MpcMessage::Refresh(msg) => msg.from,
         }
     }
 }
@@ -162,6 +334,11 @@ pub enum MessageHandleError {
     RpcError(#[from] near_fetch::Error),
     #[error("secret storage error: {0}")]
     SecretStorageError(#[from] SecretStorageError),
+    #[error("message claims to be sent by {claimed:?} but was sent by {authenticated:?}")]
+    SenderMismatch {
+        claimed: Participant,
+        authenticated: Participant,
+    },
 }
 
 impl From<CryptographicError> for MessageHandleError {
@@ -179,6 +356,13 @@ impl From<CryptographicError> for MessageHandleError {
             CryptographicError::InvalidStateHandle(e) => Self::InvalidStateHandle(e),
             CryptographicError::RpcError(e) => Self::RpcError(e),
             CryptographicError::SecretStorageError(e) => Self::SecretStorageError(e),
+            CryptographicError::SenderMismatch {
+                claimed,
+                authenticated,
+            } => Self::SenderMismatch {
+                claimed,
+                authenticated,
+            },
         }
     }
 }
@@ -225,21 +409,83 @@ impl MessageHandler for ResharingState {
     }
 }
 
+/// Whether a message of the given keyspace belongs to the keyspace served by the manager it is
+/// routed to. Messages of other keyspaces are dropped, as this node holds no share of their key.
+fn is_served(keyspace: &KeyspaceId, served: &KeyspaceId, from: Participant) -> bool {
+    if keyspace != served {
+        tracing::debug!(%keyspace, ?from, "dropping message of a keyspace that is not served");
+        return false;
+    }
+    true
+}
+
 #[async_trait]
 impl MessageHandler for RunningState {
     async fn handle<C: MessageCtx + Send + Sync>(
         &mut self,
-        ctx: C,
+        _ctx: C,
         queue: &mut MpcMessageQueue,
     ) -> Result<(), MessageHandleError> {
-        let protocol_cfg = &ctx.cfg().protocol;
-        let participants = ctx.mesh().active_participants();
+        // The messages of the triple, presignature and signature protocols are handled by the
+        // workers of their managers, see `super::worker`.
+        let refresh_messages = queue.refresh_bins.entry(self.epoch).or_default();
+        if !refresh_messages.is_empty() {
+            let mut refresher = self.refresher.write().await;
+            while let Some(msg) = refresh_messages.pop_front() {
+                refresher.message(msg);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The instant a participant restarted at according to its announce, such that the protocols it
+/// started before then can be dropped as it lost them.
+fn restarted_at(announce: &AnnounceMessage) -> Option<Instant> {
+    let age = (Utc::now().timestamp() as u64).saturating_sub(announce.started_at);
+    Instant::now().checked_sub(Duration::from_secs(age))
+}
+
+/// Whether the queued messages of the presignature `id` show that it was proposed by `from`, as
+/// its id is derived from its proposer.
+fn is_proposed_by(
+    messages: &VecDeque<PresignatureMessage>,
+    id: PresignatureId,
+    from: Participant,
+) -> bool {
+    messages.iter().any(|msg| {
+        msg.proposer == from && hash_as_id(msg.epoch, msg.triple0, msg.triple1, msg.proposer) == id
+    })
+}
+
+impl RunningState {
+    /// Handles the messages of the triple protocols, on the triple worker.
+    pub(super) async fn handle_triple_messages(&self, tick: &Tick, queue: &mut MpcMessageQueue) {
+        let protocol_cfg = &tick.cfg.protocol;
+        let participants = &tick.active;
         let mut triple_manager = self.triple_manager.write().await;
 
+        // Drop the protocols of the participants that restarted first, such that their remaining
+        // messages are ignored below.
+        let announce_messages = queue.announce_bins.entry(self.epoch).or_default();
+        while let Some(announce) = announce_messages.pop_front() {
+            let Some(cutoff) = restarted_at(&announce) else {
+                continue;
+            };
+            let triples = triple_manager.cancel_started_before(announce.from, cutoff);
+            tracing::info!(
+                from = ?announce.from,
+                started_at = announce.started_at,
+                triples,
+                "participant restarted, dropped the triples it lost"
+            );
+        }
+
         // remove the triple_id that has already failed or taken from the triple_bins
         // and refresh the timestamp of failed and taken
         let triple_messages = queue.triple_bins.entry(self.epoch).or_default();
         triple_messages.retain(|id, queue| {
+            queue.retain(|msg| is_served(&msg.keyspace, &triple_manager.keyspace, msg.from));
             if queue.is_empty()
                 || queue.iter().any(|msg| {
                     util::is_elapsed_longer_than_timeout(
@@ -268,14 +514,149 @@ impl MessageHandler for RunningState {
 
             if let Some(protocol) = protocol {
                 while let Some(message) = queue.pop_front() {
+                    journal::record(
+                        message.epoch,
+                        ProtocolKind::Triple,
+                        id,
+                        Event::Received { from: message.from },
+                    );
                     protocol.message(message.from, message.data);
                 }
             }
         }
 
+        triple_manager.garbage_collect(protocol_cfg);
+    }
+
+    /// Handles the messages of the presignature protocols, on the presignature worker. The triple
+    /// manager is locked as well, since incoming presignatures take their triples from it.
+    pub(super) async fn handle_presignature_messages(
+        &self,
+        tick: &Tick,
+        reputation: &RwLock<Reputation>,
+        queue: &mut MpcMessageQueue,
+    ) {
+        let protocol_cfg = &tick.cfg.protocol;
+        let participants = &tick.active;
+        let mut triple_manager = self.triple_manager.write().await;
         let mut presignature_manager = self.presignature_manager.write().await;
+
+        let announce_messages = queue.announce_bins.entry(self.epoch).or_default();
+        while let Some(announce) = announce_messages.pop_front() {
+            let Some(cutoff) = restarted_at(&announce) else {
+                continue;
+            };
+            let presignatures = presignature_manager.cancel_started_before(announce.from, cutoff);
+            tracing::info!(
+                from = ?announce.from,
+                started_at = announce.started_at,
+                presignatures,
+                "participant restarted, dropped the presignatures it lost"
+            );
+        }
+
+        // Handle aborts first so that the remaining messages of an aborted presignature get
+        // dropped below, since the aborted presignature is moved into garbage collection.
+        let abort_messages = queue.abort_bins.entry(self.epoch).or_default();
+        while let Some(abort) = abort_messages.pop_front() {
+            if !is_served(&abort.keyspace, presignature_manager.keyspace(), abort.from) {
+                continue;
+            }
+            // Only a participant of the presignature may abort it. Until this node joins it, only
+            // its proposer is known, through the queued messages binding it to the id. Aborts that
+            // cannot be verified are dropped, as ids are predictable and any participant could
+            // otherwise have them garbage collected before this node joins them.
+            match presignature_manager.participants_of(abort.id) {
+                Some(participants) if !participants.contains(&abort.from) => {
+                    tracing::warn!(
+                        id = abort.id,
+                        from = ?abort.from,
+                        "received presignature abort from a non-participant"
+                    );
+                    reputation
+                        .write()
+                        .await
+                        .record(abort.from, Misbehavior::MalformedMessage);
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    let proposed = queue
+                        .presignature_bins
+                        .get(&self.epoch)
+                        .and_then(|bins| bins.get(&abort.id))
+                        .is_some_and(|messages| is_proposed_by(messages, abort.id, abort.from));
+                    if !proposed {
+                        tracing::debug!(
+                            id = abort.id,
+                            from = ?abort.from,
+                            "ignoring abort of a presignature that was not joined"
+                        );
+                        continue;
+                    }
+                }
+            }
+            presignature_manager.on_abort(abort.id, abort.from, &abort.reason);
+        }
+
+        let unknown_triple_messages = queue.unknown_triple_bins.entry(self.epoch).or_default();
+        while let Some(nack) = unknown_triple_messages.pop_front() {
+            if !is_served(&nack.keyspace, presignature_manager.keyspace(), nack.from) {
+                continue;
+            }
+            presignature_manager.on_unknown_triple(nack.id, nack.from, nack.triple_id);
+        }
+
+        let commitment_messages = queue.commitment_bins.entry(self.epoch).or_default();
+        while let Some(commitment) = commitment_messages.pop_front() {
+            if !is_served(
+                &commitment.keyspace,
+                presignature_manager.keyspace(),
+                commitment.from,
+            ) {
+                continue;
+            }
+            presignature_manager
+                .on_commitment(commitment.id, commitment.from, commitment.commitment)
+                .await;
+        }
+
+        // Only the latest report of every participant matters, the pruning itself is left to the
+        // reconciler.
+        let reconcile_messages = queue.reconcile_bins.entry(self.epoch).or_default();
+        while let Some(report) = reconcile_messages.pop_front() {
+            if !is_served(
+                &report.keyspace,
+                presignature_manager.keyspace(),
+                report.from,
+            ) {
+                continue;
+            }
+            presignature_manager.on_inventory(report.from, report.presignatures);
+        }
+
+        // Replay the early messages of the presignatures whose triples are now generated.
+        let (released, expired) = queue.pending_presignatures.release(
+            Duration::from_millis(protocol_cfg.presignature.generation_timeout),
+            |msg| {
+                triple_manager.triples.contains_key(&msg.triple0)
+                    && triple_manager.triples.contains_key(&msg.triple1)
+            },
+        );
+        if !released.is_empty() {
+            tracing::debug!(
+                len = released.len(),
+                "replaying early presignature messages"
+            );
+        }
+        for msg in released {
+            queue.enqueue(MpcMessage::Presignature(msg));
+        }
+        let mut early = Vec::new();
+
         let presignature_messages = queue.presignature_bins.entry(self.epoch).or_default();
         presignature_messages.retain(|id, queue| {
+            queue.retain(|msg| is_served(&msg.keyspace, presignature_manager.keyspace(), msg.from));
             // Skip message if it already timed out
             if queue.is_empty()
                 || queue.iter().any(|msg| {
@@ -292,17 +673,36 @@ impl MessageHandler for RunningState {
             // being GC'ed, where this particular presignature has previously failed or been utilized.
             !presignature_manager.refresh_gc(id)
         });
-        for (id, queue) in presignature_messages {
+        for (id, queue) in presignature_messages.iter_mut() {
+            // The presignature id is derived from the triples it uses and its proposer, so a message
+            // with an id inconsistent with them was crafted by its sender.
+            let mut reputation = reputation.write().await;
+            queue.retain(|msg| {
+                let consistent =
+                    hash_as_id(msg.epoch, msg.triple0, msg.triple1, msg.proposer) == msg.id;
+                if !consistent {
+                    tracing::warn!(id, from = ?msg.from, "presignature message id does not match its triples and proposer");
+                    reputation.record(msg.from, Misbehavior::MalformedMessage);
+                }
+                consistent
+            });
+            drop(reputation);
+            if queue.is_empty() {
+                continue;
+            }
+
             // SAFETY: this unwrap() is safe since we have already checked that the queue is not empty.
             let PresignatureMessage {
-                triple0, triple1, ..
+                triple0,
+                triple1,
+                proposer,
+                ..
             } = queue.front().unwrap();
 
-            if !queue
-                .iter()
-                .all(|msg| triple0 == &msg.triple0 && triple1 == &msg.triple1)
-            {
-                // Check that all messages in the queue have the same triple0 and triple1, otherwise this is an
+            if !queue.iter().all(|msg| {
+                triple0 == &msg.triple0 && triple1 == &msg.triple1 && proposer == &msg.proposer
+            }) {
+                // Check that all messages in the queue have the same triple0, triple1 and proposer, otherwise this is an
                 // invalid message, so we should just bin the whole entire protocol and its message for this presignature id.
                 queue.clear();
                 continue;
@@ -314,6 +714,7 @@ impl MessageHandler for RunningState {
                     *id,
                     *triple0,
                     *triple1,
+                    *proposer,
                     &mut triple_manager,
                     &self.public_key,
                     &self.private_share,
@@ -326,10 +727,66 @@ impl MessageHandler for RunningState {
                     // We will go back to this presignature bin later when the triple is generated.
                     continue;
                 }
+                Err(GenerationError::PresignatureIsCheckedOut(_)) => {
+                    // The generator is being poked, we will go back to this presignature bin once
+                    // it is checked back in.
+                    continue;
+                }
+                Err(GenerationError::TooManyForeignGenerators { .. }) => {
+                    // The proposer has to wait for its other presignatures to finish first, so we
+                    // will go back to this presignature bin later.
+                    continue;
+                }
+                Err(GenerationError::JoinDeclined(_)) => {
+                    // The proposal policy may accept it once this node is less busy, so we will
+                    // go back to this presignature bin later.
+                    continue;
+                }
+                Err(GenerationError::PoolFull(_)) => {
+                    // The participants were told that we dropped the presignature, so there is no
+                    // point in holding its messages.
+                    queue.clear();
+                    continue;
+                }
+                Err(
+                    err @ (GenerationError::UnknownProposer(_)
+                    | GenerationError::ProposerNotAssigned(_)),
+                ) => {
+                    // Honest participants only propose presignatures for their own slots, so every
+                    // sender taking part in this presignature is misbehaving.
+                    tracing::warn!(id, ?err, "presignature proposal is not allowed");
+                    let mut reputation = reputation.write().await;
+                    for msg in queue.iter() {
+                        reputation.record(msg.from, Misbehavior::MalformedMessage);
+                    }
+                    queue.clear();
+                    continue;
+                }
+                Err(GenerationError::TripleIsMissing(triple_id)) => {
+                    if presignature_manager.on_missing_triple(*id, *proposer, triple_id) {
+                        // The proposer keeps retrying with a triple we never had and was told
+                        // so, there is no point in holding its messages.
+                        queue.clear();
+                        continue;
+                    }
+                    // The triple is likely yet to be generated on our side, e.g. because its
+                    // messages are still on their way. Hold the messages until it is.
+                    tracing::debug!(
+                        id,
+                        triple_id,
+                        "holding presignature messages until the triple is generated"
+                    );
+                    early.push((*id, queue.drain(..).collect::<Vec<_>>()));
+                    continue;
+                }
                 Err(
                     err @ (GenerationError::AlreadyGenerated
+                    | GenerationError::AlreadySpent(_)
                     | GenerationError::TripleIsGarbageCollected(_)
-                    | GenerationError::TripleIsMissing(_)),
+                    | GenerationError::TripleIsReserved(..)
+                    | GenerationError::TripleSubsetMismatch(_)
+                    | GenerationError::TripleIsSpent(_)
+                    | GenerationError::TripleLedgerUnavailable(..)),
                 ) => {
                     // This triple has already been generated or removed from the triple manager, so we will have to bin
                     // the entirety of the messages we received for this presignature id, and have the other nodes timeout
@@ -337,6 +794,12 @@ impl MessageHandler for RunningState {
                     queue.clear();
                     continue;
                 }
+                Err(err @ GenerationError::IdMismatch { .. }) => {
+                    // The messages do not belong to the presignature they claim, so bin all of them.
+                    tracing::warn!(id, ?err, "presignature messages are inconsistent");
+                    queue.clear();
+                    continue;
+                }
                 Err(GenerationError::CaitSithInitializationError(error)) => {
                     // ignore these messages since the generation had bad parameters. Also have the other node who
                     // initiated the protocol resend the message or have it timeout on their side.
@@ -360,13 +823,46 @@ impl MessageHandler for RunningState {
             };
 
             while let Some(message) = queue.pop_front() {
+                journal::record(
+                    message.epoch,
+                    ProtocolKind::Presignature,
+                    id,
+                    Event::Received { from: message.from },
+                );
                 protocol.message(message.from, message.data);
             }
         }
 
+        let mut dropped = expired;
+        for (id, messages) in early {
+            dropped += queue.pending_presignatures.hold(id, messages);
+        }
+        if dropped > 0 {
+            tracing::warn!(dropped, "dropped early presignature messages");
+            crate::metrics::NUM_EARLY_MESSAGES_DROPPED
+                .with_label_values(&[triple_manager.my_account_id.as_str()])
+                .inc_by(dropped as f64);
+        }
+
+        presignature_manager.garbage_collect(protocol_cfg);
+    }
+
+    /// Handles the messages of the signature protocols, on the signature worker. The presignature
+    /// manager is locked as well, since incoming signatures take their presignatures from it.
+    pub(super) async fn handle_signature_messages(
+        &self,
+        tick: &Tick,
+        reputation: &RwLock<Reputation>,
+        queue: &mut MpcMessageQueue,
+    ) {
+        let protocol_cfg = &tick.cfg.protocol;
+        let participants = &tick.active;
+        let mut presignature_manager = self.presignature_manager.write().await;
         let mut signature_manager = self.signature_manager.write().await;
+        let paused = pause::is_paused(protocol_cfg);
         let signature_messages = queue.signature_bins.entry(self.epoch).or_default();
         signature_messages.retain(|receipt_id, queue| {
+            queue.retain(|msg| is_served(&msg.keyspace, signature_manager.keyspace(), msg.from));
             // Skip message if it already timed out
             if queue.is_empty()
                 || queue.iter().any(|msg| {
@@ -392,16 +888,24 @@ impl MessageHandler for RunningState {
                 ..
             } = queue.front().unwrap();
 
-            if !queue
-                .iter()
-                .all(|msg| presignature_id == &msg.presignature_id)
-            {
-                // Check that all messages in the queue have the same triple0 and triple1, otherwise this is an
-                // invalid message, so we should just bin the whole entire protocol and its message for this presignature id.
+            if !queue.iter().all(|msg| {
+                presignature_id == &msg.presignature_id
+                    && proposer == &msg.proposer
+                    && request == &msg.request
+                    && epsilon == &msg.epsilon
+            }) {
+                // Check that all messages in the queue are for the same presignature and request, otherwise this is an
+                // invalid message, so we should just bin the whole entire protocol and its message for this receipt id.
                 queue.clear();
                 continue;
             }
 
+            // While signing is paused, no new signature protocol is joined, such that no presignature
+            // is consumed. The messages stay queued until signing resumes or they time out.
+            if paused && !signature_manager.generators().contains_key(receipt_id) {
+                continue;
+            }
+
             // if !self
             //     .sign_queue
             //     .read()
@@ -412,24 +916,34 @@ impl MessageHandler for RunningState {
             //     continue;
             // };
             // TODO: Validate that the message matches our sign_queue
-            let protocol = match signature_manager.get_or_generate(
-                participants,
-                *receipt_id,
-                *proposer,
-                *presignature_id,
-                request,
-                *epsilon,
-                *entropy,
-                &mut presignature_manager,
-                protocol_cfg,
-            ) {
+            let protocol = match signature_manager
+                .get_or_generate(
+                    participants,
+                    *receipt_id,
+                    *proposer,
+                    *presignature_id,
+                    request,
+                    *epsilon,
+                    *entropy,
+                    &mut presignature_manager,
+                    protocol_cfg,
+                )
+                .await
+            {
                 Ok(protocol) => protocol,
                 Err(GenerationError::PresignatureIsGenerating(_)) => {
                     // We will revisit this this signature request later when the presignature has been generated.
                     continue;
                 }
+                Err(err @ GenerationError::PresignatureNotDeleted(..)) => {
+                    // The presignature was released back since it could not be removed from storage, so we will
+                    // revisit this signature request later.
+                    tracing::warn!(%receipt_id, ?err, "signature postponed");
+                    continue;
+                }
                 Err(
                     err @ (GenerationError::AlreadyGenerated
+                    | GenerationError::AlreadySpent(_)
                     | GenerationError::PresignatureIsGarbageCollected(_)
                     | GenerationError::PresignatureIsMissing(_)),
                 ) => {
@@ -437,10 +951,28 @@ impl MessageHandler for RunningState {
                     // and have the other nodes timeout in the following cases:
                     // - If a presignature is in GC, then it was used already or failed to be produced.
                     // - If a presignature is missing, that means our system cannot process this signature.
+                    // - If a presignature is spent, then it was already used in another signature.
                     tracing::warn!(%receipt_id, ?err, "signature cannot be generated");
                     queue.clear();
                     continue;
                 }
+                Err(err @ GenerationError::SignatureBindingMismatch { .. }) => {
+                    // The ongoing signature protocol is bound to another presignature or request, so these messages
+                    // cannot belong to it.
+                    tracing::warn!(%receipt_id, ?err, "signature messages do not match the ongoing protocol");
+                    let mut reputation = reputation.write().await;
+                    for msg in queue.iter() {
+                        reputation.record(msg.from, Misbehavior::MalformedMessage);
+                    }
+                    queue.clear();
+                    continue;
+                }
+                Err(GenerationError::RequestRejected(_)) => {
+                    // The request did not pass our validators, which already logged why, so we refuse to take
+                    // part in its signature and have the other nodes timeout.
+                    queue.clear();
+                    continue;
+                }
                 Err(GenerationError::CaitSithInitializationError(error)) => {
                     // ignore the whole of the messages since the generation had bad parameters. Also have the other node who
                     // initiated the protocol resend the message or have it timeout on their side.
@@ -465,13 +997,16 @@ impl MessageHandler for RunningState {
             };
 
             while let Some(message) = queue.pop_front() {
+                journal::record(
+                    message.epoch,
+                    ProtocolKind::Signature,
+                    receipt_id,
+                    Event::Received { from: message.from },
+                );
                 protocol.message(message.from, message.data);
             }
         }
-        triple_manager.garbage_collect(protocol_cfg);
-        presignature_manager.garbage_collect(protocol_cfg);
         signature_manager.garbage_collect(protocol_cfg);
-        Ok(())
     }
 }
 
@@ -510,46 +1045,53 @@ impl<T> SignedMessage<T> {
     pub const ASSOCIATED_DATA: &'static [u8] = b"";
 }
 
-impl<T> SignedMessage<T>
-where
-    T: Serialize,
-{
+impl SignedMessage<MpcMessage> {
+    /// Signs and encrypts a message, encoded in the schema negotiated with the receiver.
     pub fn encrypt(
-        msg: &T,
+        msg: &MpcMessage,
         from: Participant,
         sign_sk: &near_crypto::SecretKey,
         cipher_pk: &hpke::PublicKey,
+        schema: u32,
     ) -> Result<Ciphered, CryptographicError> {
-        let msg = serde_json::to_vec(msg)?;
+        let msg = codec::encode(msg, schema)?;
         let sig = sign_sk.sign(&msg);
-        let msg = SignedMessage { msg, sig, from };
-        let msg = serde_json::to_vec(&msg)?;
+        let msg = codec::encode_signed(&codec::Signed { msg, sig, from }, schema)?;
         let ciphered = cipher_pk
-            .encrypt(&msg, SignedMessage::<T>::ASSOCIATED_DATA)
+            .encrypt(&msg, Self::ASSOCIATED_DATA)
             .map_err(|e| {
                 tracing::error!(error = ?e, "failed to encrypt message");
                 CryptographicError::Encryption(e.to_string())
             })?;
         Ok(ciphered)
     }
-}
 
-impl<T> SignedMessage<T>
-where
-    T: for<'a> Deserialize<'a>,
-{
     pub async fn decrypt(
         cipher_sk: &hpke::SecretKey,
         protocol_state: &Arc<RwLock<NodeState>>,
         encrypted: Ciphered,
-    ) -> Result<T, CryptographicError> {
+        schema: u32,
+    ) -> Result<MpcMessage, CryptographicError> {
+        let (_, msg) =
+            Self::decrypt_with_sender(cipher_sk, protocol_state, encrypted, schema).await?;
+        Ok(msg)
+    }
+
+    /// Same as [`SignedMessage::decrypt`], but also returns the participant whose signature
+    /// was verified, i.e. the authenticated sender of the message.
+    pub async fn decrypt_with_sender(
+        cipher_sk: &hpke::SecretKey,
+        protocol_state: &Arc<RwLock<NodeState>>,
+        encrypted: Ciphered,
+        schema: u32,
+    ) -> Result<(Participant, MpcMessage), CryptographicError> {
         let message = cipher_sk
-            .decrypt(&encrypted, SignedMessage::<T>::ASSOCIATED_DATA)
+            .decrypt(&encrypted, Self::ASSOCIATED_DATA)
             .map_err(|err| {
                 tracing::error!(error = ?err, "failed to decrypt message");
                 CryptographicError::Encryption(err.to_string())
             })?;
-        let SignedMessage::<Vec<u8>> { msg, sig, from } = serde_json::from_slice(&message)?;
+        let codec::Signed { msg, sig, from } = codec::decode_signed(&message, schema)?;
         if !sig.verify(
             &msg,
             &protocol_state
@@ -565,6 +1107,40 @@ where
             ));
         }
 
-        Ok(serde_json::from_slice(&msg)?)
+        Ok((from, codec::decode(&msg, schema)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presignature_message(proposer: Participant, from: Participant) -> PresignatureMessage {
+        PresignatureMessage {
+            id: hash_as_id(0, 1, 2, proposer),
+            keyspace: KeyspaceId::root(),
+            triple0: 1,
+            triple1: 2,
+            proposer,
+            epoch: 0,
+            from,
+            data: Vec::new(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_is_proposed_by() {
+        let (proposer, other) = (Participant::from(0u32), Participant::from(1u32));
+        let id = hash_as_id(0, 1, 2, proposer);
+        let messages = VecDeque::from([presignature_message(proposer, other)]);
+        assert!(is_proposed_by(&messages, id, proposer));
+        assert!(!is_proposed_by(&messages, id, other));
+
+        // A message claiming another proposer than the one its id is derived from proves nothing.
+        let mut forged = presignature_message(proposer, other);
+        forged.proposer = other;
+        assert!(!is_proposed_by(&VecDeque::from([forged]), id, other));
+        assert!(!is_proposed_by(&VecDeque::new(), id, proposer));
     }
 }
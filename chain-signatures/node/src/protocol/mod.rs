@@ -1,13 +1,28 @@
 mod cryptography;
 
+pub mod codec;
 pub mod consensus;
 pub mod contract;
+pub mod control;
+pub mod generator;
 pub mod message;
 pub mod monitor;
+pub mod participation;
+pub mod policy;
 pub mod presignature;
+pub mod publisher;
+pub mod reconciler;
+pub mod refresh;
+pub mod registry;
+pub mod reputation;
+pub mod router;
+pub mod scheduler;
 pub mod signature;
 pub mod state;
+pub mod sweeper;
 pub mod triple;
+pub mod validation;
+pub mod worker;
 
 pub use consensus::ConsensusError;
 pub use contract::primitives::ParticipantInfo;
@@ -21,16 +36,28 @@ pub use state::NodeState;
 use self::consensus::ConsensusCtx;
 use self::cryptography::CryptographicCtx;
 use self::message::MessageCtx;
-use crate::config::Config;
+use crate::config::{Config, ConfigWatcher};
+use crate::indexer::Indexer;
 use crate::mesh::Mesh;
 use crate::protocol::consensus::ConsensusProtocol;
 use crate::protocol::cryptography::CryptographicProtocol;
-use crate::protocol::message::{MessageHandler, MpcMessageQueue};
+use crate::protocol::message::AnnounceMessage;
+use crate::protocol::registry::SharedRegistry;
+use crate::protocol::reputation::{Misbehavior, Reputation};
+use crate::protocol::router::{DroppedMessages, MessageRouter, Received};
+use crate::protocol::scheduler::PokeKind;
+use crate::protocol::state::RunningState;
+use crate::protocol::worker::Workers;
 use crate::rpc_client;
+use crate::shutdown;
+use crate::snapshot::Outbox;
+use crate::storage::presignature_storage::LockPresignatureNodeStorageBox;
 use crate::storage::secret_storage::SecretNodeStorageBox;
+use crate::storage::sign_request_storage::LockSignRequestNodeStorageBox;
 use crate::storage::triple_storage::LockTripleNodeStorageBox;
 
 use cait_sith::protocol::Participant;
+use chrono::Utc;
 use near_account_id::AccountId;
 use near_crypto::InMemorySigner;
 use reqwest::IntoUrl;
@@ -48,9 +75,14 @@ struct Ctx {
     rpc_client: near_fetch::Client,
     http_client: reqwest::Client,
     sign_queue: Arc<RwLock<SignQueue>>,
+    indexer: Indexer,
+    reputation: Arc<RwLock<Reputation>>,
     secret_storage: SecretNodeStorageBox,
     triple_storage: LockTripleNodeStorageBox,
+    presignature_storage: LockPresignatureNodeStorageBox,
+    sign_request_storage: LockSignRequestNodeStorageBox,
     cfg: Config,
+    config_watcher: Option<ConfigWatcher>,
     mesh: Mesh,
 }
 
@@ -94,6 +126,14 @@ impl ConsensusCtx for &mut MpcSignProtocol {
     fn triple_storage(&self) -> LockTripleNodeStorageBox {
         self.ctx.triple_storage.clone()
     }
+
+    fn presignature_storage(&self) -> LockPresignatureNodeStorageBox {
+        self.ctx.presignature_storage.clone()
+    }
+
+    fn registry(&self) -> SharedRegistry {
+        self.ctx.mesh.registry.clone()
+    }
 }
 
 #[async_trait::async_trait]
@@ -118,10 +158,18 @@ impl CryptographicCtx for &mut MpcSignProtocol {
         &self.ctx.mpc_contract_id
     }
 
+    fn my_account_id(&self) -> &AccountId {
+        &self.ctx.account_id
+    }
+
     fn secret_storage(&mut self) -> &mut SecretNodeStorageBox {
         &mut self.ctx.secret_storage
     }
 
+    fn sign_request_storage(&self) -> &LockSignRequestNodeStorageBox {
+        &self.ctx.sign_request_storage
+    }
+
     fn cfg(&self) -> &Config {
         &self.ctx.cfg
     }
@@ -129,6 +177,10 @@ impl CryptographicCtx for &mut MpcSignProtocol {
     fn mesh(&self) -> &Mesh {
         &self.ctx.mesh
     }
+
+    fn workers(&mut self) -> &mut Workers {
+        &mut self.workers
+    }
 }
 
 #[async_trait::async_trait]
@@ -144,12 +196,25 @@ impl MessageCtx for &MpcSignProtocol {
     fn cfg(&self) -> &Config {
         &self.ctx.cfg
     }
+
+    fn reputation(&self) -> &RwLock<Reputation> {
+        &self.ctx.reputation
+    }
 }
 
 pub struct MpcSignProtocol {
     ctx: Ctx,
     receiver: mpsc::Receiver<MpcMessage>,
     state: Arc<RwLock<NodeState>>,
+    /// Workers cranking the protocols of the managers of the running state.
+    workers: Workers,
+    /// Outbox restored from a snapshot, delivered once the node runs the protocol in its epoch.
+    restored_outbox: Option<(u64, Outbox)>,
+    /// Commands of the operators, applied once per iteration of the protocol loop.
+    control: Option<mpsc::Receiver<control::Request>>,
+    /// UNIX timestamp as seconds since the epoch of when the node started, until it caught up
+    /// with the protocols of the other participants, see [`MpcSignProtocol::catch_up`].
+    started_at: Option<u64>,
 }
 
 impl MpcSignProtocol {
@@ -162,9 +227,14 @@ impl MpcSignProtocol {
         signer: InMemorySigner,
         receiver: mpsc::Receiver<MpcMessage>,
         sign_queue: Arc<RwLock<SignQueue>>,
+        indexer: Indexer,
+        reputation: Arc<RwLock<Reputation>>,
         secret_storage: SecretNodeStorageBox,
         triple_storage: LockTripleNodeStorageBox,
+        presignature_storage: LockPresignatureNodeStorageBox,
+        sign_request_storage: LockSignRequestNodeStorageBox,
         cfg: Config,
+        config_watcher: Option<ConfigWatcher>,
     ) -> (Self, Arc<RwLock<NodeState>>) {
         let my_address = my_address.into_url().unwrap();
         let rpc_url = rpc_client.rpc_addr();
@@ -179,6 +249,7 @@ impl MpcSignProtocol {
             "initializing protocol with parameters"
         );
         let state = Arc::new(RwLock::new(NodeState::Starting));
+        let workers = Workers::new(&account_id, reputation.clone());
         let ctx = Ctx {
             my_address,
             account_id,
@@ -186,20 +257,78 @@ impl MpcSignProtocol {
             rpc_client,
             http_client: reqwest::Client::new(),
             sign_queue,
+            indexer,
+            reputation,
             signer,
             secret_storage,
             triple_storage,
+            presignature_storage,
+            sign_request_storage,
             cfg,
+            config_watcher,
             mesh: Mesh::default(),
         };
         let protocol = MpcSignProtocol {
             ctx,
             receiver,
             state: state.clone(),
+            workers,
+            restored_outbox: None,
+            control: None,
+            started_at: Some(Utc::now().timestamp() as u64),
         };
         (protocol, state)
     }
 
+    /// Queues the outbox of a snapshot taken in `epoch`, to be delivered once the node runs the
+    /// protocol in that epoch again.
+    pub fn restore_outbox(&mut self, epoch: u64, outbox: Outbox) {
+        self.restored_outbox = Some((epoch, outbox));
+    }
+
+    /// Creates the handle through which the admin server sends commands to the protocol loop.
+    pub fn controller(&mut self) -> control::Controller {
+        let (controller, receiver) = control::Controller::new();
+        self.control = Some(receiver);
+        controller
+    }
+
+    /// Catches up with the protocols of the other participants once the node runs the protocol
+    /// for the first time since it started. The messages buffered for the protocols that were
+    /// ongoing before the node started are dropped, since the node lost track of them, and the
+    /// other participants are told to drop these protocols as well. The protocols started from
+    /// then on are joined as usual.
+    async fn catch_up(&self, running: &RunningState, router: &mut MessageRouter, started_at: u64) {
+        let dropped = router.drop_sent_before(started_at);
+        crate::metrics::NUM_CATCH_UP_MESSAGES_DROPPED
+            .with_label_values(&[self.ctx.account_id.as_str()])
+            .inc_by(dropped as f64);
+        let Some(me) = running.participants.find_participant(&self.ctx.account_id) else {
+            return;
+        };
+        let mut messages = running.messages.write().await;
+        for (p, info) in running.participants.iter() {
+            if *p == me {
+                continue;
+            }
+            messages.push(
+                info.clone(),
+                MpcMessage::Announce(AnnounceMessage {
+                    epoch: running.epoch,
+                    from: me,
+                    started_at,
+                    timestamp: Utc::now().timestamp() as u64,
+                }),
+            );
+        }
+        tracing::info!(
+            epoch = running.epoch,
+            started_at,
+            dropped,
+            "caught up with the other participants"
+        );
+    }
+
     pub async fn run(mut self) -> anyhow::Result<()> {
         let my_account_id = self.ctx.account_id.to_string();
         let _span = tracing::info_span!("running", my_account_id);
@@ -209,9 +338,11 @@ impl MpcSignProtocol {
         crate::metrics::NODE_VERSION
             .with_label_values(&[my_account_id.as_str()])
             .set(node_version());
-        let mut queue = MpcMessageQueue::default();
+        let mut router = MessageRouter::new(self.ctx.cfg.local.network.message_dedup_window);
+        self.workers.spawn();
         let mut last_state_update = Instant::now();
         let mut last_config_update = Instant::now();
+        let mut last_config_file_check = Instant::now();
         let mut last_pinged = Instant::now();
 
         // Sets the latest configurations from the contract:
@@ -232,7 +363,19 @@ impl MpcSignProtocol {
                 match msg_result {
                     Ok(msg) => {
                         tracing::debug!("received a new message");
-                        queue.push(msg);
+                        let sender = msg.sender();
+                        let typename = msg.typename();
+                        if router.receive(msg) == Received::Duplicate {
+                            self.ctx
+                                .reputation
+                                .write()
+                                .await
+                                .record(sender, Misbehavior::DuplicateMessage);
+                            tracing::debug!(from = ?sender, typename, "dropping duplicate message");
+                            crate::metrics::NUM_DUPLICATE_MESSAGES
+                                .with_label_values(&[my_account_id.as_str()])
+                                .inc();
+                        }
                     }
                     Err(TryRecvError::Empty) => {
                         tracing::debug!("no new messages received");
@@ -245,7 +388,15 @@ impl MpcSignProtocol {
                 }
             }
 
-            let contract_state = if last_state_update.elapsed() > Duration::from_secs(1) {
+            // Refresh right away when the indexer saw the contract change, so that participant-set
+            // changes are picked up without waiting for the next poll.
+            let contract_state = if last_state_update.elapsed() > Duration::from_secs(1)
+                || self
+                    .ctx
+                    .indexer
+                    .contract_changed_since(last_state_update)
+                    .await
+            {
                 let contract_state = match rpc_client::fetch_mpc_contract_state(
                     &self.ctx.rpc_client,
                     &self.ctx.mpc_contract_id,
@@ -270,7 +421,42 @@ impl MpcSignProtocol {
                 None
             };
 
-            if last_config_update.elapsed() > Duration::from_secs(5 * 60) {
+            // Apply the changes to the config file. Changed overrides of the contract configuration
+            // are applied when the configuration is fetched again right below.
+            let mut config_file_changed = false;
+            if last_config_file_check.elapsed() > Duration::from_secs(5) {
+                if let Some(local) = self.ctx.config_watcher.as_mut().and_then(|w| w.poll()) {
+                    let pacing_changed = local.scheduler != self.ctx.cfg.local.scheduler;
+                    let validation_changed = local.validation != self.ctx.cfg.local.validation;
+                    self.ctx.cfg.local = local;
+                    let mut changed = Vec::new();
+                    if pacing_changed {
+                        changed.extend([PokeKind::Triple, PokeKind::Presignature]);
+                    }
+                    if validation_changed {
+                        changed.push(PokeKind::Signature);
+                    }
+                    if !changed.is_empty() {
+                        let state = self.state.read().await.clone();
+                        if let NodeState::Running(running) = &state {
+                            self.workers
+                                .reconfigure(&changed, running, &self.ctx.cfg)
+                                .await;
+                        }
+                    }
+                    config_file_changed = true;
+                }
+                last_config_file_check = Instant::now();
+            }
+
+            if config_file_changed
+                || last_config_update.elapsed() > Duration::from_secs(5 * 60)
+                || self
+                    .ctx
+                    .indexer
+                    .contract_changed_since(last_config_update)
+                    .await
+            {
                 // Sets the latest configurations from the contract:
                 if let Err(err) = self
                     .ctx
@@ -288,6 +474,15 @@ impl MpcSignProtocol {
                 last_pinged = Instant::now();
             }
 
+            if let Some(control) = self.control.as_mut() {
+                while let Ok((command, reply)) = control.try_recv() {
+                    let state = self.state.read().await;
+                    let outcome =
+                        control::apply(&state, &self.ctx.cfg, &self.ctx.mesh, command).await;
+                    let _ = reply.send(outcome);
+                }
+            }
+
             let state = {
                 let guard = self.state.read().await;
                 guard.clone()
@@ -329,13 +524,61 @@ impl MpcSignProtocol {
                 .observe(consensus_time.elapsed().as_secs_f64());
 
             let message_time = Instant::now();
-            if let Err(err) = state.handle(&self, &mut queue).await {
+            let dropped = router.route(state.epoch());
+            if dropped != DroppedMessages::default() {
+                tracing::info!(epoch = ?state.epoch(), ?dropped, "dropped messages of other epochs");
+                crate::metrics::NUM_STALE_EPOCH_MESSAGES_DROPPED
+                    .with_label_values(&[my_account_id.as_str()])
+                    .inc_by(dropped.stale as f64);
+                crate::metrics::NUM_FUTURE_EPOCH_MESSAGES_DROPPED
+                    .with_label_values(&[my_account_id.as_str()])
+                    .inc_by(dropped.future as f64);
+            }
+            if let NodeState::Running(running) = &state {
+                if let Some(started_at) = self.started_at.take() {
+                    self.catch_up(running, &mut router, started_at).await;
+                }
+            }
+            if let NodeState::Running(running) = &state {
+                let held = router.dispatch(running.epoch, &mut self.workers);
+                if held > 0 {
+                    tracing::debug!(held, "held messages of busy workers");
+                }
+            }
+            if let Err(err) = router.deliver(&mut state, &self).await {
                 tracing::warn!("protocol unable to handle messages: {err:?}");
             }
             crate::metrics::PROTOCOL_LATENCY_ITER_MESSAGE
                 .with_label_values(&[my_account_id.as_str()])
                 .observe(message_time.elapsed().as_secs_f64());
 
+            if let NodeState::Running(running) = &state {
+                if let Some((epoch, outbox)) = self.restored_outbox.take() {
+                    if epoch == running.epoch {
+                        tracing::info!(epoch, len = outbox.len(), "delivering restored outbox");
+                        let mut messages = running.messages.write().await;
+                        for (info, msg) in outbox {
+                            messages.push(info, msg);
+                        }
+                    } else {
+                        tracing::info!(epoch, "dropping restored outbox of another epoch");
+                    }
+                }
+            }
+
+            if let NodeState::Running(running) = &state {
+                if let Some(me) = running.participants.find_participant(&self.ctx.account_id) {
+                    self.workers
+                        .crank(running, me, &self.ctx.cfg, &self.ctx.mesh)
+                        .await;
+                }
+            }
+
+            // Only the signature protocols of the running state are worth draining before shutdown.
+            if shutdown::is_draining() && !matches!(state, NodeState::Running(_)) {
+                shutdown::drained();
+            }
+
             let sleep_ms = match state {
                 NodeState::Generating(_) => 500,
                 NodeState::Resharing(_) => 500,
@@ -0,0 +1,156 @@
+//! Accounting of the protocols every participant took part in, per epoch and per protocol, by
+//! how they ended. A protocol that times out or fails is counted against all of its
+//! participants, so a single flaky node stands out as the one whose share of timeouts is well
+//! above that of its peers.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use cait_sith::protocol::Participant;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::journal::ProtocolKind;
+
+/// Number of most recent epochs kept in the matrix.
+const MAX_EPOCHS: usize = 8;
+
+static MATRIX: Lazy<Mutex<Matrix>> = Lazy::new(Default::default);
+
+/// Participation of the peers per epoch, then per protocol.
+pub type Matrix = BTreeMap<u64, BTreeMap<ProtocolKind, BTreeMap<Participant, Participation>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Completed,
+    TimedOut,
+    Failed,
+}
+
+impl Outcome {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::TimedOut => "timed_out",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Number of protocols a participant took part in, by how they ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Participation {
+    pub completed: u64,
+    pub timed_out: u64,
+    pub failed: u64,
+}
+
+impl Participation {
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Completed => self.completed += 1,
+            Outcome::TimedOut => self.timed_out += 1,
+            Outcome::Failed => self.failed += 1,
+        }
+    }
+}
+
+/// Records the outcome of a protocol of the epoch against each of its participants.
+pub fn record(
+    my_account_id: &str,
+    epoch: u64,
+    protocol: ProtocolKind,
+    participants: &[Participant],
+    outcome: Outcome,
+) {
+    let mut matrix = MATRIX.lock().unwrap_or_else(|p| p.into_inner());
+    let peers = matrix
+        .entry(epoch)
+        .or_default()
+        .entry(protocol)
+        .or_default();
+    for participant in participants {
+        peers.entry(*participant).or_default().add(outcome);
+        crate::metrics::PROTOCOL_PARTICIPATION
+            .with_label_values(&[
+                my_account_id,
+                protocol_label(protocol),
+                &u32::from(*participant).to_string(),
+                outcome.as_str(),
+            ])
+            .inc();
+    }
+    while matrix.len() > MAX_EPOCHS {
+        matrix.pop_first();
+    }
+}
+
+/// Participation of the peers over the epochs still kept, or only the given one.
+pub fn matrix(epoch: Option<u64>) -> Matrix {
+    let matrix = MATRIX.lock().unwrap_or_else(|p| p.into_inner());
+    match epoch {
+        Some(epoch) => matrix
+            .get(&epoch)
+            .map(|protocols| BTreeMap::from([(epoch, protocols.clone())]))
+            .unwrap_or_default(),
+        None => matrix.clone(),
+    }
+}
+
+const fn protocol_label(protocol: ProtocolKind) -> &'static str {
+    match protocol {
+        ProtocolKind::Triple => "triple",
+        ProtocolKind::Presignature => "presignature",
+        ProtocolKind::Signature => "signature",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_participation_matrix() {
+        // The matrix is shared by the tests of the crate, so this one uses epochs of its own.
+        let base = u64::MAX - MAX_EPOCHS as u64 - 1;
+        let (a, b, c) = (
+            Participant::from(0),
+            Participant::from(1),
+            Participant::from(2),
+        );
+        record(
+            "me",
+            base,
+            ProtocolKind::Triple,
+            &[a, b, c],
+            Outcome::Completed,
+        );
+        record("me", base, ProtocolKind::Triple, &[a, c], Outcome::TimedOut);
+        record(
+            "me",
+            base,
+            ProtocolKind::Signature,
+            &[b, c],
+            Outcome::Failed,
+        );
+
+        let epochs = matrix(Some(base));
+        let triples = &epochs[&base][&ProtocolKind::Triple];
+        assert_eq!(
+            triples[&c],
+            Participation {
+                completed: 1,
+                timed_out: 1,
+                failed: 0
+            }
+        );
+        assert_eq!(triples[&b].timed_out, 0);
+        assert_eq!(epochs[&base][&ProtocolKind::Signature][&b].failed, 1);
+
+        for epoch in base + 1..=base + MAX_EPOCHS as u64 {
+            record("me", epoch, ProtocolKind::Triple, &[a], Outcome::Completed);
+        }
+        assert!(matrix(Some(base)).is_empty());
+    }
+}
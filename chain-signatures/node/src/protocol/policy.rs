@@ -0,0 +1,173 @@
+//! Policies deciding how much of the stockpiling work a node takes on: how many of the triples it
+//! proposes itself rather than joins from others, and whether it joins the presignatures proposed
+//! by others while it is short on triples or busy. The managers consult a [`ProposalPolicy`],
+//! which is the [`DefaultPolicy`] configured from the [`scheduler::Options`] unless replaced.
+
+use std::sync::Arc;
+
+use mpc_contract::config::ProtocolConfig;
+
+use super::scheduler;
+
+/// Triples held and being generated by this node, when deciding whether to propose a new one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TripleStockpile {
+    /// Unspent triples introduced by this node.
+    pub mine: usize,
+    /// Ongoing triple protocols introduced by this node.
+    pub introduced: usize,
+    /// Ongoing triple protocols, introduced by any node.
+    pub generators: usize,
+    /// Unspent triples plus the ones being generated, introduced by any node.
+    pub potential: usize,
+}
+
+/// Load of this node when asked to join a presignature proposed by another node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JoinLoad {
+    /// Unspent triples introduced by this node.
+    pub my_triples: usize,
+    /// Ongoing triple and presignature protocols, introduced by any node.
+    pub generators: usize,
+}
+
+pub trait ProposalPolicy: std::fmt::Debug + Send + Sync {
+    /// Whether this node proposes a new triple.
+    fn propose_triple(&self, stockpile: &TripleStockpile, cfg: &ProtocolConfig) -> bool;
+
+    /// Whether this node joins a presignature proposed by another node. Declined proposals are
+    /// held and asked again later, until their messages expire.
+    fn join_presignature(&self, load: &JoinLoad, cfg: &ProtocolConfig) -> bool;
+}
+
+/// Policy of the node unless configured otherwise. Triples are proposed until the node holds the
+/// minimum of the contract, and presignatures of others are always joined, unless limited by the
+/// [`scheduler::Options`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DefaultPolicy {
+    /// Maximum share of the triple stockpile introduced by this node.
+    pub triple_mine_ratio: Option<f64>,
+    /// Presignatures of others are declined while this node holds fewer triples of its own.
+    pub min_triples_to_join: Option<usize>,
+    /// Presignatures of others are declined while this many protocols are ongoing.
+    pub max_generators_to_join: Option<usize>,
+}
+
+impl DefaultPolicy {
+    pub fn new(opts: &scheduler::Options) -> Self {
+        Self {
+            triple_mine_ratio: opts.triple_mine_ratio,
+            min_triples_to_join: opts.min_triples_to_join,
+            max_generators_to_join: opts.max_generators_to_join,
+        }
+    }
+}
+
+impl ProposalPolicy for DefaultPolicy {
+    fn propose_triple(&self, stockpile: &TripleStockpile, cfg: &ProtocolConfig) -> bool {
+        // Stopgap to prevent too many triples in the system. This should be around min_triple*nodes*2
+        // for good measure so that we have enough triples to do presig generation while also maintain
+        // the minimum number of triples where a single node can't flood the system.
+        if stockpile.potential >= cfg.triple.max_triples as usize {
+            return false;
+        }
+        // The stockpile is empty when starting out, so the ratio only applies once there is one.
+        let within_ratio = self.triple_mine_ratio.map_or(true, |ratio| {
+            stockpile.potential == 0
+                || ((stockpile.mine + stockpile.introduced) as f64)
+                    < ratio * stockpile.potential as f64
+        });
+        // We will always try to generate a new triple if we have less than the minimum
+        stockpile.mine < cfg.triple.min_triples as usize
+            && stockpile.introduced < cfg.max_concurrent_introduction as usize
+            && stockpile.generators < cfg.max_concurrent_generation as usize
+            && within_ratio
+    }
+
+    fn join_presignature(&self, load: &JoinLoad, _cfg: &ProtocolConfig) -> bool {
+        self.min_triples_to_join
+            .map_or(true, |min| load.my_triples >= min)
+            && self
+                .max_generators_to_join
+                .map_or(true, |max| load.generators < max)
+    }
+}
+
+/// The policy configured by the options.
+pub fn from_options(opts: &scheduler::Options) -> Arc<dyn ProposalPolicy> {
+    Arc::new(DefaultPolicy::new(opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> ProtocolConfig {
+        let mut cfg = ProtocolConfig::default();
+        cfg.triple.min_triples = 10;
+        cfg.triple.max_triples = 100;
+        cfg.max_concurrent_introduction = 4;
+        cfg.max_concurrent_generation = 16;
+        cfg
+    }
+
+    #[test]
+    fn test_propose_triple() {
+        let cfg = cfg();
+        let stockpile = TripleStockpile {
+            mine: 4,
+            introduced: 2,
+            generators: 6,
+            potential: 20,
+        };
+        assert!(DefaultPolicy::default().propose_triple(&stockpile, &cfg));
+        assert!(!DefaultPolicy::default().propose_triple(
+            &TripleStockpile {
+                potential: 100,
+                ..stockpile
+            },
+            &cfg
+        ));
+
+        let ratio = DefaultPolicy {
+            triple_mine_ratio: Some(0.25),
+            ..Default::default()
+        };
+        // 6 of the 20 triples are ours, above a quarter of the stockpile.
+        assert!(!ratio.propose_triple(&stockpile, &cfg));
+        assert!(ratio.propose_triple(
+            &TripleStockpile {
+                potential: 40,
+                ..stockpile
+            },
+            &cfg
+        ));
+        assert!(ratio.propose_triple(&TripleStockpile::default(), &cfg));
+    }
+
+    #[test]
+    fn test_join_presignature() {
+        let cfg = cfg();
+        let load = JoinLoad {
+            my_triples: 3,
+            generators: 8,
+        };
+        assert!(DefaultPolicy::default().join_presignature(&load, &cfg));
+        let policy = DefaultPolicy {
+            min_triples_to_join: Some(4),
+            ..Default::default()
+        };
+        assert!(!policy.join_presignature(&load, &cfg));
+        let policy = DefaultPolicy {
+            max_generators_to_join: Some(8),
+            ..Default::default()
+        };
+        assert!(!policy.join_presignature(&load, &cfg));
+        let policy = DefaultPolicy {
+            min_triples_to_join: Some(3),
+            max_generators_to_join: Some(9),
+            ..Default::default()
+        };
+        assert!(policy.join_presignature(&load, &cfg));
+    }
+}
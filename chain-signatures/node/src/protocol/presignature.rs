@@ -1,81 +1,279 @@
-use super::message::PresignatureMessage;
-use super::triple::{Triple, TripleId, TripleManager};
+use super::generator::{self, PokeStatus};
+use super::message::{
+    AbortMessage, CommitmentMessage, PresignatureMessage, ReconcileMessage, UnknownTripleMessage,
+};
+use super::participation::{self, Outcome};
+use super::policy::JoinLoad;
+use super::scheduler::{self, GeneratorSummary, PokeFailure, PokeKind, PokeOutcome, RoundTimer};
+use super::triple::{self, Triple, TripleId, TripleManager};
+use super::validation::Rejection;
+use crate::clock::{self, SharedClock};
+use crate::events::{self, NodeEvent};
+use crate::gcp::error;
+use crate::journal::{self, Event, ProtocolKind};
+use crate::keyspace::KeyspaceId;
 use crate::protocol::contract::primitives::Participants;
+use crate::protocol::refresh;
+use crate::protocol::registry::{self, ParticipantRegistry, SharedRegistry};
+use crate::redact::Secret;
+use crate::shedding;
+use crate::storage::presignature_storage::{LockPresignatureNodeStorageBox, PresignatureData};
+use crate::telemetry;
 use crate::types::{PresignatureProtocol, SecretKeyShare};
 use crate::util::AffinePointExt;
 
-use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
+use cait_sith::protocol::{Action, InitializationError, MessageData, Participant};
 use cait_sith::{KeygenOutput, PresignArguments, PresignOutput};
 use chrono::Utc;
 use crypto_shared::PublicKey;
+use k256::elliptic_curve::group::GroupEncoding;
 use k256::Secp256k1;
+use lru::LruCache;
 use mpc_contract::config::ProtocolConfig;
+use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
+use std::cmp::Reverse;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::num::NonZeroUsize;
 use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use near_account_id::AccountId;
+use near_primitives::hash::CryptoHash;
 
 /// Unique number used to identify a specific ongoing presignature generation protocol.
 /// Without `PresignatureId` it would be unclear where to route incoming cait-sith presignature
 /// generation messages.
 pub type PresignatureId = u64;
 
+/// How long a presignature can stay reserved before the reservation expires and the
+/// presignature is made available again.
+const RESERVATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum number of spent presignature ids to remember. Requests referencing a
+/// presignature that is still in this cache are rejected with `AlreadySpent`.
+const SPENT_CACHE_CAPACITY: usize = 16_384;
+
+/// Extra time presignatures owned by other nodes are kept past the max age, so that their owner
+/// discards them before we do and never proposes a signature we can no longer join.
+const FOREIGN_AGE_GRACE: Duration = Duration::from_secs(60);
+
+/// Time after a presignature got completed on our side during which the reports of the other
+/// participants are not taken as evidence of them lacking it, since they may still be completing it.
+const RECONCILE_GRACE: Duration = Duration::from_secs(60);
+
+/// Number of times a proposer can fail us with the same triple we never had before we tell it so
+/// with an [`UnknownTripleMessage`].
+const MISSING_TRIPLE_NACK_THRESHOLD: usize = 3;
+
+/// Failures to join presignatures with triples we never had are summarized in one warning per
+/// interval, rather than logged once per message.
+const MISSING_TRIPLES_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the failures for a proposer and triple are remembered after the last one.
+const MISSING_TRIPLES_RETENTION: Duration = Duration::from_secs(10 * 60);
+
 /// A completed presignature.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Presignature {
     pub id: PresignatureId,
     pub output: PresignOutput<Secp256k1>,
     pub participants: Vec<Participant>,
+    /// UNIX timestamp as seconds since the epoch of when the presignature was generated.
+    pub created_at: u64,
+    /// Epoch of the key shares the presignature was generated with.
+    #[serde(default)]
+    pub epoch: u64,
+    /// Hash of the public key the presignature was generated for, see [`public_key_hash`].
+    #[serde(default)]
+    pub public_key_hash: [u8; 32],
+    /// Triples the presignature was generated from, kept for the [`crate::audit`] trail.
+    #[serde(default)]
+    pub triples: Option<(TripleId, TripleId)>,
+}
+
+impl fmt::Debug for Presignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Presignature")
+            .field("id", &self.id)
+            .field("big_r", &self.output.big_r)
+            .field("k", &Secret(&self.output.k))
+            .field("sigma", &Secret(&self.output.sigma))
+            .field("participants", &self.participants)
+            .field("created_at", &self.created_at)
+            .field("epoch", &self.epoch)
+            .field("triples", &self.triples)
+            .finish()
+    }
+}
+
+impl Zeroize for Presignature {
+    fn zeroize(&mut self) {
+        self.output.k.zeroize();
+        self.output.sigma.zeroize();
+    }
+}
+
+impl Drop for Presignature {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
 }
 
+impl ZeroizeOnDrop for Presignature {}
+
 /// An ongoing presignature generator.
 pub struct PresignatureGenerator {
     pub participants: Vec<Participant>,
     pub protocol: PresignatureProtocol,
     pub triple0: TripleId,
     pub triple1: TripleId,
+    /// The participant that introduced this presignature to the system.
+    pub proposer: Participant,
     pub mine: bool,
     pub timestamp: Instant,
+    /// Last time messages were handed to this generator.
+    pub last_active: Instant,
     pub timeout: Duration,
+    /// Time the timeout got extended by while participants were unreachable.
+    extension: Duration,
+    /// Since when a participant of this presignature has been unreachable.
+    stalled_since: Option<Instant>,
+    /// Time spent poking versus waiting between rounds.
+    pub rounds: RoundTimer,
+    /// Span the logs about this presignature are recorded in, see [`crate::telemetry`].
+    pub span: tracing::Span,
+    /// Clock the timeout is measured against, the one of the manager.
+    pub clock: SharedClock,
 }
 
 impl PresignatureGenerator {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        id: PresignatureId,
         protocol: PresignatureProtocol,
         participants: Vec<Participant>,
         triple0: TripleId,
         triple1: TripleId,
+        proposer: Participant,
         mine: bool,
         timeout: u64,
+        clock: SharedClock,
     ) -> Self {
+        let now = clock.now();
         Self {
             protocol,
             participants,
             triple0,
             triple1,
+            proposer,
             mine,
-            timestamp: Instant::now(),
+            timestamp: now,
+            last_active: now,
             timeout: Duration::from_millis(timeout),
+            extension: Duration::ZERO,
+            stalled_since: None,
+            rounds: RoundTimer::default(),
+            span: telemetry::presignature_span(id, triple0, triple1, proposer, mine),
+            clock,
         }
     }
 
-    pub fn poke(&mut self) -> Result<Action<PresignOutput<Secp256k1>>, ProtocolError> {
-        if self.timestamp.elapsed() > self.timeout {
-            let id = hash_as_id(self.triple0, self.triple1);
-            tracing::warn!(
-                presignature_id = id,
-                self.triple0,
-                self.triple1,
-                self.mine,
-                "presignature protocol timed out"
-            );
-            return Err(ProtocolError::Other(
-                anyhow::anyhow!("presignature protocol timed out").into(),
-            ));
+    pub fn is_timed_out(&self) -> bool {
+        self.clock.elapsed(self.timestamp) > self.timeout + self.extension()
+    }
+
+    /// The time this generator was stalled by unreachable participants is not counted against
+    /// its timeout, up to the length of the timeout itself.
+    fn extension(&self) -> Duration {
+        let stalled = self
+            .stalled_since
+            .map_or(Duration::ZERO, |since| self.clock.elapsed(since));
+        (self.extension + stalled).min(self.timeout)
+    }
+
+    /// Starts or stops extending the timeout depending on whether any participant of this
+    /// generator is currently unreachable.
+    fn set_stalled(&mut self, stalled: bool) {
+        match (stalled, self.stalled_since) {
+            (true, None) => self.stalled_since = Some(self.clock.now()),
+            (false, Some(since)) => {
+                self.extension += self.clock.elapsed(since);
+                self.stalled_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn poke(&mut self) -> Result<Action<PresignOutput<Secp256k1>>, PokeFailure> {
+        if self.is_timed_out() {
+            tracing::warn!("presignature protocol timed out");
+            return Err(PokeFailure::TimedOut(self.clock.elapsed(self.timestamp)));
         }
 
-        self.protocol.poke()
+        Ok(self.protocol.poke()?)
+    }
+
+    /// Pokes the protocol until it can no longer progress without messages from the other
+    /// participants, completes or fails. Returns the messages produced along the way.
+    pub fn poke_until_blocked(
+        &mut self,
+        id: PresignatureId,
+        keyspace: &KeyspaceId,
+        epoch: u64,
+        me: Participant,
+    ) -> (
+        Vec<(Participant, PresignatureMessage)>,
+        PokeStatus<PresignOutput<Secp256k1>>,
+    ) {
+        self.span.record("epoch", epoch);
+        let _span = self.span.clone().entered();
+        self.rounds.resume();
+        let mut messages = Vec::new();
+        let status = loop {
+            let action = match self.poke() {
+                Ok(action) => action,
+                Err(error) => break PokeStatus::Failed(error),
+            };
+            match action {
+                Action::Wait => break PokeStatus::Waiting,
+                Action::SendMany(data) => {
+                    for p in self.participants.iter() {
+                        messages.push((*p, self.message(id, keyspace, epoch, me, data.clone())));
+                    }
+                }
+                Action::SendPrivate(p, data) => {
+                    messages.push((p, self.message(id, keyspace, epoch, me, data)));
+                }
+                Action::Return(output) => break PokeStatus::Completed(output),
+            }
+        };
+        self.rounds.pause(matches!(status, PokeStatus::Waiting));
+        (messages, status)
+    }
+
+    fn message(
+        &self,
+        id: PresignatureId,
+        keyspace: &KeyspaceId,
+        epoch: u64,
+        me: Participant,
+        data: MessageData,
+    ) -> PresignatureMessage {
+        PresignatureMessage {
+            id,
+            keyspace: keyspace.clone(),
+            triple0: self.triple0,
+            triple1: self.triple1,
+            proposer: self.proposer,
+            epoch,
+            from: me,
+            data,
+            timestamp: Utc::now().timestamp() as u64,
+        }
     }
 }
 
@@ -91,12 +289,310 @@ pub enum GenerationError {
     TripleIsGenerating(TripleId),
     #[error("triple {0} is in garbage collection")]
     TripleIsGarbageCollected(TripleId),
+    #[error("triple {0} is reserved by a presignature of {1:?}")]
+    TripleIsReserved(TripleId, Participant),
+    #[error("triple {0} was not generated by the participants selected for it")]
+    TripleSubsetMismatch(TripleId),
+    #[error("triple {0} was already consumed by a presignature")]
+    TripleIsSpent(TripleId),
+    #[error("triple {0} could not be recorded as spent: {1}")]
+    TripleLedgerUnavailable(TripleId, String),
     #[error("presignature {0} is generating")]
     PresignatureIsGenerating(PresignatureId),
     #[error("presignature {0} is missing")]
     PresignatureIsMissing(PresignatureId),
+    #[error("sign request rejected: {0}")]
+    RequestRejected(Rejection),
     #[error("presignature {0} is in garbage collection")]
     PresignatureIsGarbageCollected(TripleId),
+    #[error("presignature {0} is reserved")]
+    PresignatureIsReserved(PresignatureId),
+    #[error("reservation of presignature {0} expired")]
+    ReservationExpired(PresignatureId),
+    #[error("presignature {0} is checked out to be poked")]
+    PresignatureIsCheckedOut(PresignatureId),
+    #[error("presignature {0} has already been spent")]
+    AlreadySpent(PresignatureId),
+    #[error("presignature {0} could not be removed from storage: {1}")]
+    PresignatureNotDeleted(PresignatureId, String),
+    #[error("presignature id {id} does not match its triples and proposer, expected {expected}")]
+    IdMismatch {
+        id: PresignatureId,
+        expected: PresignatureId,
+    },
+    #[error("signature {receipt_id} is already generating with presignature {presignature_id} for another request")]
+    SignatureBindingMismatch {
+        receipt_id: CryptoHash,
+        presignature_id: PresignatureId,
+    },
+    #[error("presignature proposer {0:?} is not a participant")]
+    UnknownProposer(Participant),
+    #[error("presignature proposer {0:?} is not assigned any presignature slots")]
+    ProposerNotAssigned(Participant),
+    #[error("presignature proposer {proposer:?} already has {limit} presignatures generating")]
+    TooManyForeignGenerators { proposer: Participant, limit: usize },
+    #[error("declined to join presignature of {0:?}: not enough triples or too busy")]
+    JoinDeclined(Participant),
+    #[error("presignature {id} was generated in epoch {epoch} or for another public key")]
+    StaleKeyBinding { id: PresignatureId, epoch: u64 },
+    #[error("presignature pool is full: {0}")]
+    PoolFull(PoolUtilization),
+}
+
+/// A presignature reserved for a signature generation protocol. While reserved, the
+/// presignature cannot be taken or reserved by anyone else. A slot must either be spent
+/// with [`PresignatureManager::spend`] once the signing protocol has started, or released
+/// with [`PresignatureManager::release`] if the signing protocol could not be started.
+#[derive(Debug)]
+pub struct PresignatureSlot {
+    presignature: Presignature,
+    /// Generation of the reservation, telling it apart from later reservations of the same
+    /// presignature once this one expired.
+    generation: u64,
+}
+
+impl PresignatureSlot {
+    pub fn id(&self) -> PresignatureId {
+        self.presignature.id
+    }
+
+    pub fn presignature(&self) -> &Presignature {
+        &self.presignature
+    }
+}
+
+struct Reservation {
+    presignature: Presignature,
+    mine: bool,
+    timestamp: Instant,
+    generation: u64,
+}
+
+/// Commitments to the `big_r` of a presignature received from the other participants, see
+/// [`PresignatureManager::on_commitment`].
+struct Commitments {
+    /// When the first commitment was received.
+    received_at: Instant,
+    by: HashMap<Participant, [u8; 32]>,
+}
+
+/// Unspent presignatures reported by another participant.
+struct Inventory {
+    /// UNIX timestamp as seconds since the epoch of when the report was received.
+    received_at: u64,
+    presignatures: HashSet<PresignatureId>,
+}
+
+/// Failures to join presignatures because of triples we never had, per proposer and triple.
+struct MissingTriples {
+    /// Number of failures per proposer and triple, with the time of the last one.
+    failures: HashMap<(Participant, TripleId), (usize, Instant)>,
+    /// Failures since the last report per proposer, with the triples involved.
+    unreported: BTreeMap<Participant, (usize, HashSet<TripleId>)>,
+    last_report: Instant,
+}
+
+impl MissingTriples {
+    fn new() -> Self {
+        Self {
+            failures: HashMap::new(),
+            unreported: BTreeMap::new(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Records a failure and returns how many times the proposer failed us with the triple.
+    fn record(&mut self, proposer: Participant, triple_id: TripleId) -> usize {
+        let (count, last) = self
+            .failures
+            .entry((proposer, triple_id))
+            .or_insert((0, Instant::now()));
+        *count += 1;
+        *last = Instant::now();
+        let (unreported, triples) = self.unreported.entry(proposer).or_default();
+        *unreported += 1;
+        triples.insert(triple_id);
+        *count
+    }
+
+    /// Emits one warning summarizing the failures since the last report, once the report interval
+    /// has passed.
+    fn report(&mut self) {
+        if self.last_report.elapsed() < MISSING_TRIPLES_REPORT_INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+        self.failures
+            .retain(|_, (_, last)| last.elapsed() < MISSING_TRIPLES_RETENTION);
+        if self.unreported.is_empty() {
+            return;
+        }
+        let summary = std::mem::take(&mut self.unreported)
+            .into_iter()
+            .map(|(proposer, (failures, triples))| {
+                format!(
+                    "{proposer:?}: {failures} failures over {} triples",
+                    triples.len()
+                )
+            })
+            .collect::<Vec<_>>();
+        tracing::warn!(
+            ?summary,
+            "could not join presignatures proposed with triples we never had"
+        );
+    }
+}
+
+/// What is kept of a generator while it is checked out to be poked.
+struct CheckedOutGenerator {
+    participants: Vec<Participant>,
+    proposer: Participant,
+    mine: bool,
+}
+
+type PokeResult = (
+    PresignatureId,
+    PresignatureGenerator,
+    Vec<(Participant, PresignatureMessage)>,
+    PokeStatus<PresignOutput<Secp256k1>>,
+);
+
+/// Generators checked out of the manager with [`PresignatureManager::checkout`], to be poked
+/// without holding the lock of the manager.
+pub struct CheckedOut {
+    keyspace: KeyspaceId,
+    epoch: u64,
+    me: Participant,
+    generators: Vec<(PresignatureId, PresignatureGenerator)>,
+}
+
+impl CheckedOut {
+    pub fn len(&self) -> usize {
+        self.generators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.generators.is_empty()
+    }
+
+    /// Pokes the generators in parallel off the tokio workers. The generators left over once
+    /// `budget` messages have been produced are checked back in unpoked, to be poked next time.
+    pub async fn poke(self, budget: usize) -> Poked {
+        let Self {
+            keyspace,
+            epoch,
+            me,
+            generators,
+        } = self;
+        let poked = generator::poke_all(generators, budget, move |(id, generator)| {
+            generator.poke_until_blocked(*id, &keyspace, epoch, me)
+        })
+        .await;
+        let skipped = poked
+            .iter()
+            .find(|(_, result)| result.is_none())
+            .map(|((id, _), _)| *id);
+        let results = poked
+            .into_iter()
+            .map(|((id, generator), result)| {
+                let (messages, status) = result.unwrap_or((Vec::new(), PokeStatus::Waiting));
+                (id, generator, messages, status)
+            })
+            .collect();
+        Poked {
+            epoch,
+            results,
+            skipped,
+        }
+    }
+}
+
+/// Generators poked outside of the manager, to be checked back in with
+/// [`PresignatureManager::checkin`].
+pub struct Poked {
+    epoch: u64,
+    results: Vec<PokeResult>,
+    /// First generator skipped for lack of budget, poked first by the next poke.
+    skipped: Option<PresignatureId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresignatureSummary {
+    pub id: PresignatureId,
+    pub mine: bool,
+    /// Seconds since the presignature was generated.
+    pub age_secs: u64,
+}
+
+/// Hard caps on the presignatures held and being generated by a [`PresignatureManager`], set
+/// on the node with the [`scheduler::Options`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolCaps {
+    pub max_generators: Option<usize>,
+    pub max_presignatures: Option<usize>,
+}
+
+impl PoolCaps {
+    pub fn new(opts: &scheduler::Options) -> Self {
+        Self {
+            max_generators: opts.max_presignature_generators,
+            max_presignatures: opts.max_presignatures_held,
+        }
+    }
+}
+
+/// Presignatures held and being generated by a [`PresignatureManager`], against its
+/// [`PoolCaps`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolUtilization {
+    /// Ongoing generators, including the ones checked out to be poked.
+    pub generators: usize,
+    pub max_generators: Option<usize>,
+    /// Unspent presignatures, including the reserved ones.
+    pub presignatures: usize,
+    pub max_presignatures: Option<usize>,
+}
+
+impl PoolUtilization {
+    pub fn is_full(&self) -> bool {
+        self.max_generators
+            .is_some_and(|max| self.generators >= max)
+            || self
+                .max_presignatures
+                .is_some_and(|max| self.presignatures >= max)
+    }
+}
+
+impl fmt::Display for PoolUtilization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cap = |max: Option<usize>| max.map_or("unbounded".to_string(), |max| max.to_string());
+        write!(
+            f,
+            "{}/{} generators, {}/{} presignatures",
+            self.generators,
+            cap(self.max_generators),
+            self.presignatures,
+            cap(self.max_presignatures),
+        )
+    }
+}
+
+/// Summary of the state of a [`PresignatureManager`], see [`PresignatureManager::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresignatureSnapshot {
+    pub epoch: u64,
+    /// Completed unspent presignatures, ordered by id.
+    pub presignatures: Vec<PresignatureSummary>,
+    /// Presignatures reserved for a signing protocol that has yet to start, ordered by id.
+    pub reserved: Vec<PresignatureId>,
+    /// Generators held by the manager, ordered by id.
+    pub generators: Vec<GeneratorSummary>,
+    /// Number of generators currently checked out to be poked.
+    pub checked_out: usize,
+    /// Number of generators introduced by this node.
+    pub introduced: usize,
+    /// Utilization of the pool against its caps.
+    pub pool: PoolUtilization,
 }
 
 /// Abstracts how triples are generated by providing a way to request a new triple that will be
@@ -106,6 +602,11 @@ pub struct PresignatureManager {
     presignatures: HashMap<PresignatureId, Presignature>,
     /// Ongoing presignature generation protocols.
     generators: HashMap<PresignatureId, PresignatureGenerator>,
+    /// Generators currently checked out to be poked. A generator that is cancelled or aborted
+    /// while checked out is removed from here, and dropped on checkin.
+    checked_out: HashMap<PresignatureId, CheckedOutGenerator>,
+    /// First generator skipped for lack of budget by the last poke, the next poke resuming from it.
+    poke_cursor: Option<PresignatureId>,
     /// List of presignature ids generation of which was initiated by the current node.
     mine: VecDeque<PresignatureId>,
     /// The set of presignatures that were introduced to the system by the current node.
@@ -114,27 +615,151 @@ pub struct PresignatureManager {
     /// will be maintained for at most presignature timeout period just so messages are
     /// cycled through the system.
     gc: HashMap<PresignatureId, Instant>,
+    /// Presignatures currently reserved for a signing protocol that has yet to start.
+    reserved: HashMap<PresignatureId, Reservation>,
+    /// Generation handed out to the next reservation.
+    next_generation: u64,
+    /// Bounded set of presignatures that have already been used in a signing protocol.
+    spent: LruCache<PresignatureId, ()>,
+    /// Abort messages for the generators that failed while being poked, to be taken with
+    /// [`PresignatureManager::take_aborts`].
+    aborts: Vec<(Participant, AbortMessage)>,
+    /// Latest unspent presignatures reported by the other participants, see
+    /// [`PresignatureManager::reconcile`].
+    inventories: HashMap<Participant, Inventory>,
+    /// Failures to join presignatures with triples we never had, see
+    /// [`PresignatureManager::on_missing_triple`].
+    missing_triples: MissingTriples,
+    /// Notices for the proposers that keep retrying with triples we never had, to be taken with
+    /// [`PresignatureManager::take_nacks`].
+    nacks: Vec<(Participant, UnknownTripleMessage)>,
+    /// Commitments of the other participants to the presignatures they completed, kept until
+    /// every one of them has been checked against ours.
+    commitments: HashMap<PresignatureId, Commitments>,
+    /// Commitments to the presignatures we completed, to be taken with
+    /// [`PresignatureManager::take_commitments`].
+    outgoing_commitments: Vec<(Participant, CommitmentMessage)>,
+    /// Keyspace the presignatures are generated for, see [`crate::keyspace`].
+    keyspace: KeyspaceId,
     me: Participant,
     threshold: usize,
     epoch: u64,
+    /// Hash of the public key the presignatures are generated for.
+    public_key_hash: [u8; 32],
+    presignature_storage: LockPresignatureNodeStorageBox,
     my_account_id: AccountId,
+    /// Clock the timeouts, the reservations and the garbage collection are measured against.
+    clock: SharedClock,
+    /// Participants of the contract, against which the proposers of protocols are validated.
+    registry: SharedRegistry,
+    /// Caps on the presignatures held and being generated, see [`PoolCaps`].
+    caps: PoolCaps,
+    /// Bumped whenever a presignature becomes available, waking up
+    /// [`PresignatureManager::wait_for`].
+    available: watch::Sender<u64>,
 }
 
 impl PresignatureManager {
-    pub fn new(me: Participant, threshold: usize, epoch: u64, my_account_id: &AccountId) -> Self {
+    pub fn new(
+        me: Participant,
+        threshold: usize,
+        epoch: u64,
+        public_key: &PublicKey,
+        presignature_data: Vec<PresignatureData>,
+        presignature_storage: LockPresignatureNodeStorageBox,
+        my_account_id: &AccountId,
+    ) -> Self {
+        let public_key_hash = public_key_hash(public_key);
+        let mut mine = VecDeque::new();
+        let mut presignatures = HashMap::new();
+        for entry in presignature_data {
+            if !is_bound_to(&entry.presignature, epoch, &public_key_hash) {
+                tracing::warn!(
+                    id = entry.presignature.id,
+                    epoch = entry.presignature.epoch,
+                    "discarding loaded presignature generated under another key or epoch"
+                );
+                continue;
+            }
+            tracing::debug!(
+                id = entry.presignature.id,
+                mine = entry.mine,
+                "loaded presignature"
+            );
+            if entry.mine {
+                mine.push_back(entry.presignature.id);
+            }
+            presignatures.insert(entry.presignature.id, entry.presignature);
+        }
         Self {
-            presignatures: HashMap::new(),
+            presignatures,
             generators: HashMap::new(),
-            mine: VecDeque::new(),
+            checked_out: HashMap::new(),
+            poke_cursor: None,
+            mine,
             introduced: HashSet::new(),
             gc: HashMap::new(),
+            reserved: HashMap::new(),
+            next_generation: 0,
+            spent: LruCache::new(NonZeroUsize::new(SPENT_CACHE_CAPACITY).unwrap()),
+            aborts: Vec::new(),
+            inventories: HashMap::new(),
+            missing_triples: MissingTriples::new(),
+            nacks: Vec::new(),
+            commitments: HashMap::new(),
+            outgoing_commitments: Vec::new(),
+            keyspace: KeyspaceId::root(),
             me,
             threshold,
             epoch,
+            public_key_hash,
+            presignature_storage,
             my_account_id: my_account_id.clone(),
+            clock: clock::monotonic(),
+            registry: ParticipantRegistry::shared(),
+            caps: PoolCaps::default(),
+            available: watch::channel(0).0,
         }
     }
 
+    /// Measures the timeouts of this manager and its generators against `clock` instead of the
+    /// monotonic clock of the operating system.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Validates the proposers of protocols against the shared registry of participants.
+    pub fn with_registry(mut self, registry: SharedRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Caps the presignatures held and being generated as configured by the options.
+    pub fn with_caps(mut self, opts: &scheduler::Options) -> Self {
+        self.set_caps(opts);
+        self
+    }
+
+    pub fn set_caps(&mut self, opts: &scheduler::Options) {
+        self.caps = PoolCaps::new(opts);
+    }
+
+    /// Presignatures held and being generated, against the caps of the manager.
+    pub fn utilization(&self) -> PoolUtilization {
+        PoolUtilization {
+            generators: self.generators.len() + self.checked_out.len(),
+            max_generators: self.caps.max_generators,
+            presignatures: self.presignatures.len() + self.reserved.len(),
+            max_presignatures: self.caps.max_presignatures,
+        }
+    }
+
+    /// Keyspace the presignatures are generated for.
+    pub fn keyspace(&self) -> &KeyspaceId {
+        &self.keyspace
+    }
+
     /// Returns the number of unspent presignatures available in the manager.
     pub fn len(&self) -> usize {
         self.presignatures.len()
@@ -145,10 +770,17 @@ impl PresignatureManager {
         self.mine.len()
     }
 
+    /// Unspent presignatures that are not reserved, with whether they are assigned to this node.
+    pub fn unspent(&self) -> impl Iterator<Item = (&Presignature, bool)> {
+        self.presignatures
+            .values()
+            .map(|presignature| (presignature, self.mine.contains(&presignature.id)))
+    }
+
     /// Returns the number of unspent presignatures we will have in the manager once
     /// all ongoing generation protocols complete.
     pub fn potential_len(&self) -> usize {
-        self.presignatures.len() + self.generators.len()
+        self.presignatures.len() + self.generators.len() + self.checked_out.len()
     }
 
     /// Returns if there are unspent presignatures available in the manager.
@@ -156,96 +788,855 @@ impl PresignatureManager {
         self.len() == 0
     }
 
+    /// Returns the ids of the unspent presignatures along with whether they are assigned to this node.
+    pub fn presignature_ids(&self) -> Vec<(PresignatureId, bool)> {
+        self.presignatures
+            .keys()
+            .map(|id| (*id, self.mine.contains(id)))
+            .collect()
+    }
+
+    /// Returns the ids of the presignatures currently reserved for a signing protocol.
+    pub fn reserved_ids(&self) -> Vec<PresignatureId> {
+        self.reserved.keys().copied().collect()
+    }
+
+    /// Returns the ongoing presignature generation protocols, except the ones checked out to be
+    /// poked at the moment.
+    pub fn generators(&self) -> &HashMap<PresignatureId, PresignatureGenerator> {
+        &self.generators
+    }
+
+    /// Participants of the presignature `id` this node is generating, including while its
+    /// generator is checked out to be poked. `None` if this node has not joined it.
+    pub fn participants_of(&self, id: PresignatureId) -> Option<&[Participant]> {
+        self.generators
+            .get(&id)
+            .map(|generator| generator.participants.as_slice())
+            .or_else(|| {
+                self.checked_out
+                    .get(&id)
+                    .map(|generator| generator.participants.as_slice())
+            })
+    }
+
+    /// Summarizes the presignatures and generators held by the manager. Generators checked out
+    /// to be poked are only counted.
+    pub fn snapshot(&self) -> PresignatureSnapshot {
+        let now = Utc::now().timestamp() as u64;
+        let mut presignatures = self
+            .presignatures
+            .values()
+            .map(|presignature| PresignatureSummary {
+                id: presignature.id,
+                mine: self.mine.contains(&presignature.id),
+                age_secs: now.saturating_sub(presignature.created_at),
+            })
+            .collect::<Vec<_>>();
+        presignatures.sort_by_key(|presignature| presignature.id);
+        let mut reserved = self.reserved_ids();
+        reserved.sort();
+        let mut generators = self
+            .generators
+            .iter()
+            .map(|(id, generator)| GeneratorSummary {
+                id: *id,
+                mine: generator.mine,
+                participants: generator.participants.clone(),
+                age_ms: Some(self.clock.elapsed(generator.timestamp).as_millis()),
+            })
+            .collect::<Vec<_>>();
+        generators.sort_by_key(|generator| generator.id);
+        PresignatureSnapshot {
+            epoch: self.epoch,
+            presignatures,
+            reserved,
+            generators,
+            checked_out: self.checked_out.len(),
+            introduced: self.introduced.len(),
+            pool: self.utilization(),
+        }
+    }
+
     pub fn garbage_collect(&mut self, cfg: &ProtocolConfig) {
         let before = self.gc.len();
-        self.gc
-            .retain(|_, instant| instant.elapsed() < Duration::from_millis(cfg.garbage_timeout));
+        self.gc.retain(|_, instant| {
+            self.clock.elapsed(*instant) < Duration::from_millis(cfg.garbage_timeout)
+        });
         let removed = before.saturating_sub(self.gc.len());
         if removed > 0 {
             tracing::debug!("garbage collected {} presignatures", removed);
         }
+        // Commitments that could never be checked, e.g. since the presignature failed on our side.
+        self.commitments.retain(|_, commitments| {
+            self.clock.elapsed(commitments.received_at) < Duration::from_millis(cfg.garbage_timeout)
+        });
+
+        let expired = self
+            .reserved
+            .iter()
+            .filter(|(_, reservation)| {
+                self.clock.elapsed(reservation.timestamp) > RESERVATION_TIMEOUT
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            if let Some(reservation) = self.reserved.remove(&id) {
+                tracing::warn!(
+                    id,
+                    "presignature reservation expired; making it available again"
+                );
+                self.unreserve(reservation);
+            }
+        }
+    }
+
+    /// Discards the unspent presignatures older than `max_age`, so that they get regenerated with
+    /// fresh triples. The fewer and younger the presignatures held, the less a leaked key share
+    /// exposes. Returns the number of discarded presignatures.
+    pub async fn discard_aged(&mut self, max_age: Duration) -> usize {
+        let now = Utc::now().timestamp() as u64;
+        let aged = self
+            .presignatures
+            .values()
+            .filter(|presignature| {
+                let max_age = if self.mine.contains(&presignature.id) {
+                    max_age
+                } else {
+                    max_age + FOREIGN_AGE_GRACE
+                };
+                now.saturating_sub(presignature.created_at) > max_age.as_secs()
+            })
+            .map(|presignature| presignature.id)
+            .collect::<Vec<_>>();
+        for id in &aged {
+            tracing::info!(id, "discarding presignature past its max age");
+            self.presignatures.remove(id);
+            self.mine.retain(|mine_id| mine_id != id);
+            self.gc.insert(*id, self.clock.now());
+            journal::record(
+                self.epoch,
+                ProtocolKind::Presignature,
+                id,
+                Event::Aborted {
+                    reason: "max age exceeded".to_string(),
+                },
+            );
+            if let Err(err) = self.delete_presignature_from_storage(*id).await {
+                tracing::warn!(
+                    id,
+                    ?err,
+                    "unable to delete aged presignature from datastore"
+                );
+            }
+        }
+        if !aged.is_empty() {
+            crate::metrics::NUM_PRESIGNATURES_DISCARDED_BY_AGE
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc_by(aged.len() as f64);
+        }
+        aged.len()
+    }
+
+    /// Extends the timeouts of the generators with participants that the mesh reports as
+    /// unreachable, so that a temporary network partition does not waste their triples.
+    pub fn on_unreachable(&mut self, unreachable: &[Participant]) {
+        for generator in self.generators.values_mut() {
+            let stalled = generator
+                .participants
+                .iter()
+                .any(|p| unreachable.contains(p));
+            generator.set_stalled(stalled);
+        }
+    }
+
+    /// Whether the presignature was used or failed and is now in garbage collection.
+    pub fn is_garbage_collected(&self, id: &PresignatureId) -> bool {
+        self.gc.contains_key(id)
     }
 
     pub fn refresh_gc(&mut self, id: &PresignatureId) -> bool {
-        let entry = self.gc.entry(*id).and_modify(|e| *e = Instant::now());
+        let entry = self.gc.entry(*id).and_modify(|e| *e = self.clock.now());
         matches!(entry, Entry::Occupied(_))
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn generate_internal(
-        participants: &Participants,
-        me: Participant,
-        threshold: usize,
-        triple0: Triple,
-        triple1: Triple,
-        public_key: &PublicKey,
-        private_share: &SecretKeyShare,
-        mine: bool,
-        timeout: u64,
-    ) -> Result<PresignatureGenerator, InitializationError> {
-        let participants: Vec<_> = participants.keys().cloned().collect();
-        let protocol = Box::new(cait_sith::presign(
-            &participants,
-            me,
-            // These paramaters appear to be to make it easier to use different indexing schemes for triples
-            // Introduced in this PR https://github.com/LIT-Protocol/cait-sith/pull/7
-            &participants,
-            me,
-            PresignArguments {
-                triple0: (triple0.share, triple0.public),
-                triple1: (triple1.share, triple1.public),
-                keygen_out: KeygenOutput {
-                    private_share: *private_share,
-                    public_key: *public_key,
-                },
-                threshold,
+    /// Drops all presignatures and cancels all ongoing generators since they were produced with
+    /// the key shares of the previous epoch. The dropped ids are moved into garbage collection
+    /// so that late messages for them are ignored.
+    pub async fn on_epoch_change(&mut self, new_epoch: u64) {
+        if new_epoch <= self.epoch {
+            return;
+        }
+
+        let (discarded, cancelled) = self.discard_all();
+        if let Err(err) = self.presignature_storage.write().await.clear().await {
+            tracing::warn!(
+                ?err,
+                "failed to clear presignatures from storage on epoch change"
+            );
+        }
+
+        crate::metrics::NUM_PRESIGNATURES_DISCARDED_ON_EPOCH_CHANGE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc_by(discarded as f64);
+        crate::metrics::NUM_PRESIGNATURE_GENERATORS_CANCELLED_ON_EPOCH_CHANGE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc_by(cancelled as f64);
+        tracing::info!(
+            old_epoch = self.epoch,
+            new_epoch,
+            discarded,
+            cancelled,
+            "discarded presignatures from previous epoch"
+        );
+        self.epoch = new_epoch;
+    }
+
+    /// Discards all presignatures and cancels their generation once our key share got refreshed,
+    /// since they were generated with the previous shares and cannot be used with the new ones.
+    pub async fn on_share_refresh(&mut self, round: u64) {
+        let (discarded, cancelled) = self.discard_all();
+        if let Err(err) = self.presignature_storage.write().await.clear().await {
+            tracing::warn!(
+                ?err,
+                "failed to clear presignatures from storage on share refresh"
+            );
+        }
+
+        crate::metrics::NUM_PRESIGNATURES_DISCARDED_ON_SHARE_REFRESH
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc_by(discarded as f64);
+        tracing::info!(
+            round,
+            discarded,
+            cancelled,
+            "discarded presignatures of the previous key share"
+        );
+    }
+
+    /// Moves all presignatures and generators to the garbage collection, returning how many
+    /// presignatures were discarded and how many generators were cancelled.
+    fn discard_all(&mut self) -> (usize, usize) {
+        let now = self.clock.now();
+        let discarded = self.presignatures.len() + self.reserved.len();
+        let cancelled = self.generators.len() + self.checked_out.len();
+        for id in self
+            .presignatures
+            .keys()
+            .chain(self.reserved.keys())
+            .chain(self.generators.keys())
+            .chain(self.checked_out.keys())
+        {
+            self.gc.insert(*id, now);
+        }
+        self.presignatures.clear();
+        self.reserved.clear();
+        self.generators.clear();
+        self.checked_out.clear();
+        self.introduced.clear();
+        self.mine.clear();
+        self.inventories.clear();
+        (discarded, cancelled)
+    }
+
+    /// Cancels the ongoing generation of the presignature with the given id. Returns the abort
+    /// messages to be sent to the other participants of the protocol, so that they can stop
+    /// generating it as well instead of waiting for it to time out.
+    pub fn cancel(&mut self, id: PresignatureId, reason: &str) -> Vec<(Participant, AbortMessage)> {
+        let participants = match self.generators.remove(&id) {
+            Some(generator) => generator.participants,
+            // Dropped once checked back in.
+            None => match self.checked_out.remove(&id) {
+                Some(generator) => generator.participants,
+                None => {
+                    tracing::warn!(id, "cannot cancel presignature that is not generating");
+                    return Vec::new();
+                }
             },
-        )?);
-        Ok(PresignatureGenerator::new(
-            protocol,
-            participants,
-            triple0.id,
-            triple1.id,
-            mine,
-            timeout,
-        ))
+        };
+        self.gc.insert(id, self.clock.now());
+        self.introduced.remove(&id);
+        crate::metrics::NUM_PRESIGNATURE_GENERATORS_ABORTED
+            .with_label_values(&[self.my_account_id.as_str(), "local"])
+            .inc();
+        tracing::info!(id, reason, "cancelled presignature generation");
+        journal::record(
+            self.epoch,
+            ProtocolKind::Presignature,
+            id,
+            Event::Aborted {
+                reason: reason.to_string(),
+            },
+        );
+        self.abort_messages(id, &participants, reason)
     }
 
-    /// Starts a new presignature generation protocol.
-    pub fn generate(
+    /// Cancels the ongoing generators that involve participants which are no longer part of the
+    /// participant set. Returns the abort messages for the remaining participants.
+    pub fn update_participants(
         &mut self,
         participants: &Participants,
-        triple0: Triple,
-        triple1: Triple,
-        public_key: &PublicKey,
-        private_share: &SecretKeyShare,
-        timeout: u64,
-    ) -> Result<(), InitializationError> {
-        let id = hash_as_id(triple0.id, triple1.id);
+    ) -> Vec<(Participant, AbortMessage)> {
+        let stale: Vec<PresignatureId> = self
+            .generators
+            .iter()
+            .map(|(id, generator)| (id, &generator.participants))
+            .chain(
+                self.checked_out
+                    .iter()
+                    .map(|(id, generator)| (id, &generator.participants)),
+            )
+            .filter(|(_, generator_participants)| {
+                generator_participants
+                    .iter()
+                    .any(|p| !participants.contains_key(p))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        stale
+            .into_iter()
+            .flat_map(|id| self.cancel(id, "participant removed"))
+            .filter(|(p, _)| participants.contains_key(p))
+            .collect()
+    }
 
-        // Check if the `id` is already in the system. Error out and have the next cycle try again.
-        if self.generators.contains_key(&id)
-            || self.presignatures.contains_key(&id)
-            || self.gc.contains_key(&id)
-        {
-            tracing::warn!(id, "presignature id collision");
-            return Err(InitializationError::BadParameters(format!(
-                "id collision: presignature_id={id}"
-            )));
+    /// Cancels the ongoing generators involving `participant` that were started before `cutoff`,
+    /// since the participant restarted in the meantime. The remaining participants are let know
+    /// through abort messages to be taken with [`PresignatureManager::take_aborts`]. Generators
+    /// checked out to be poked are left to time out. Returns the number of cancelled generators.
+    pub fn cancel_started_before(&mut self, participant: Participant, cutoff: Instant) -> usize {
+        let lost = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| {
+                generator.participants.contains(&participant) && generator.timestamp < cutoff
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &lost {
+            let aborts = self
+                .cancel(*id, "participant restarted")
+                .into_iter()
+                .filter(|(p, _)| *p != participant);
+            self.aborts.extend(aborts);
+        }
+        if !lost.is_empty() {
+            crate::metrics::NUM_GENERATORS_CANCELLED_ON_PEER_RESTART
+                .with_label_values(&[self.my_account_id.as_str(), "presignature"])
+                .inc_by(lost.len() as f64);
         }
+        lost.len()
+    }
 
-        tracing::info!(id, "starting protocol to generate a new presignature");
-        let generator = Self::generate_internal(
-            participants,
-            self.me,
+    /// Takes the abort messages for the generators that failed while being poked, so that the
+    /// other participants stop generating them as well.
+    pub fn take_aborts(&mut self) -> Vec<(Participant, AbortMessage)> {
+        std::mem::take(&mut self.aborts)
+    }
+
+    /// Drops the generators that are past their timeout, independently of them being poked. The
+    /// triples they consumed are wasted, and the other participants are let know through abort
+    /// messages to be taken with [`PresignatureManager::take_aborts`]. Generators checked out to
+    /// be poked are left to the poke. Returns the ids of the dropped generators.
+    pub fn sweep(&mut self) -> Vec<PresignatureId> {
+        let timed_out = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| generator.is_timed_out())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &timed_out {
+            let Some(generator) = self.generators.remove(id) else {
+                continue;
+            };
+            crate::metrics::PRESIGNATURE_GENERATOR_FAILURES
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            crate::metrics::PRESIGNATURE_GENERATOR_TIMEOUTS
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            crate::metrics::PRESIGNATURE_TRIPLES_WASTED
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc_by(2.0);
+            self.gc.insert(*id, self.clock.now());
+            self.introduced.remove(id);
+            journal::record(self.epoch, ProtocolKind::Presignature, id, Event::TimedOut);
+            participation::record(
+                self.my_account_id.as_str(),
+                self.epoch,
+                ProtocolKind::Presignature,
+                &generator.participants,
+                Outcome::TimedOut,
+            );
+            generator.span.in_scope(|| {
+                tracing::warn!("swept timed out presignature generator; its triples are wasted")
+            });
+            let aborts = self.abort_messages(*id, &generator.participants, "timed out");
+            self.aborts.extend(aborts);
+        }
+        timed_out
+    }
+
+    /// Builds the abort messages for the presignature with the given id, addressed to every
+    /// participant of its protocol other than this node.
+    pub fn abort_messages(
+        &self,
+        id: PresignatureId,
+        participants: &[Participant],
+        reason: &str,
+    ) -> Vec<(Participant, AbortMessage)> {
+        participants
+            .iter()
+            .filter(|p| **p != self.me)
+            .map(|p| {
+                (
+                    *p,
+                    AbortMessage {
+                        id,
+                        keyspace: self.keyspace.clone(),
+                        epoch: self.epoch,
+                        from: self.me,
+                        reason: reason.to_string(),
+                        timestamp: Utc::now().timestamp() as u64,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Handles an abort sent by another participant of the presignature protocol. The generator
+    /// is dropped and the presignature moved into garbage collection, such that any further
+    /// messages for it are ignored rather than starting the protocol anew.
+    pub fn on_abort(&mut self, id: PresignatureId, from: Participant, reason: &str) {
+        if self.presignatures.contains_key(&id) || self.reserved.contains_key(&id) {
+            // We have already completed the presignature, so there is nothing left to abort.
+            tracing::debug!(
+                id,
+                ?from,
+                reason,
+                "ignoring abort for completed presignature"
+            );
+            return;
+        }
+
+        self.gc.insert(id, self.clock.now());
+        if self.generators.remove(&id).is_some() || self.checked_out.remove(&id).is_some() {
+            self.introduced.remove(&id);
+            crate::metrics::NUM_PRESIGNATURE_GENERATORS_ABORTED
+                .with_label_values(&[self.my_account_id.as_str(), "remote"])
+                .inc();
+            tracing::info!(
+                id,
+                ?from,
+                reason,
+                "presignature generation aborted by participant"
+            );
+            journal::record(
+                self.epoch,
+                ProtocolKind::Presignature,
+                id,
+                Event::Aborted {
+                    reason: format!("aborted by {from:?}: {reason}"),
+                },
+            );
+        }
+    }
+
+    /// Records that the presignature `id` of `proposer` could not be joined since we never had
+    /// `triple_id`. Once the proposer failed us with that triple
+    /// [`MISSING_TRIPLE_NACK_THRESHOLD`] times, the presignature is moved into garbage collection
+    /// and the proposer is told through a notice to be taken with
+    /// [`PresignatureManager::take_nacks`]. Returns whether that happened, in which case the
+    /// messages of the presignature are not worth holding anymore.
+    pub fn on_missing_triple(
+        &mut self,
+        id: PresignatureId,
+        proposer: Participant,
+        triple_id: TripleId,
+    ) -> bool {
+        let failures = self.missing_triples.record(proposer, triple_id);
+        tracing::debug!(
+            id,
+            ?proposer,
+            triple_id,
+            failures,
+            "could not join presignature: triple is missing"
+        );
+        if failures < MISSING_TRIPLE_NACK_THRESHOLD {
+            return false;
+        }
+
+        self.gc.insert(id, self.clock.now());
+        self.nacks.push((
+            proposer,
+            UnknownTripleMessage {
+                id,
+                keyspace: self.keyspace.clone(),
+                epoch: self.epoch,
+                from: self.me,
+                triple_id,
+                timestamp: Utc::now().timestamp() as u64,
+            },
+        ));
+        crate::metrics::NUM_UNKNOWN_TRIPLE_NOTICES
+            .with_label_values(&[self.my_account_id.as_str(), "sent"])
+            .inc();
+        true
+    }
+
+    /// Takes the notices for the proposers that keep retrying with triples we never had.
+    pub fn take_nacks(&mut self) -> Vec<(Participant, UnknownTripleMessage)> {
+        std::mem::take(&mut self.nacks)
+    }
+
+    /// Takes the commitments to the presignatures we completed, so that the other participants can
+    /// check them against theirs.
+    pub fn take_commitments(&mut self) -> Vec<(Participant, CommitmentMessage)> {
+        std::mem::take(&mut self.outgoing_commitments)
+    }
+
+    /// Handles the commitment of another participant to the presignature it completed. Once we
+    /// completed the presignature as well, the commitment is checked against ours, see
+    /// [`PresignatureManager::check_commitments`].
+    pub async fn on_commitment(
+        &mut self,
+        id: PresignatureId,
+        from: Participant,
+        commitment: [u8; 32],
+    ) {
+        if self.gc.contains_key(&id) {
+            return;
+        }
+        let participants = self
+            .presignatures
+            .get(&id)
+            .map(|presignature| &presignature.participants)
+            .or_else(|| self.generators.get(&id).map(|g| &g.participants))
+            .or_else(|| self.checked_out.get(&id).map(|g| &g.participants));
+        if participants.is_some_and(|participants| !participants.contains(&from)) {
+            tracing::warn!(
+                id,
+                ?from,
+                "received presignature commitment from a non-participant"
+            );
+            return;
+        }
+        self.commitments
+            .entry(id)
+            .or_insert_with(|| Commitments {
+                received_at: self.clock.now(),
+                by: HashMap::new(),
+            })
+            .by
+            .insert(from, commitment);
+        self.check_commitments(id).await;
+    }
+
+    /// Checks the commitments received for the presignature `id` against our own. A presignature
+    /// for which another participant derived a different `big_r` can never produce a valid
+    /// signature, and may be the result of a participant tampering with the protocol, so it is
+    /// quarantined: dropped and moved into garbage collection. Presignatures already reserved for
+    /// a signature are left to it.
+    async fn check_commitments(&mut self, id: PresignatureId) {
+        let Some(presignature) = self.presignatures.get(&id) else {
+            if self.reserved.contains_key(&id) {
+                tracing::warn!(id, "cannot check commitments of reserved presignature");
+                self.commitments.remove(&id);
+            }
+            return;
+        };
+        let Some(received) = self.commitments.get(&id) else {
+            return;
+        };
+        let ours = commitment(presignature);
+        let disagreeing = received
+            .by
+            .iter()
+            .filter(|(_, theirs)| **theirs != ours)
+            .map(|(p, _)| *p)
+            .collect::<Vec<_>>();
+        if disagreeing.is_empty() {
+            let others = presignature.participants.len().saturating_sub(1);
+            if received.by.len() >= others {
+                self.commitments.remove(&id);
+            }
+            return;
+        }
+
+        tracing::warn!(
+            id,
+            ?disagreeing,
+            "quarantining presignature with disagreeing commitments"
+        );
+        self.commitments.remove(&id);
+        self.presignatures.remove(&id);
+        self.mine.retain(|mine_id| *mine_id != id);
+        self.gc.insert(id, self.clock.now());
+        journal::record(
+            self.epoch,
+            ProtocolKind::Presignature,
+            id,
+            Event::Aborted {
+                reason: format!("commitment mismatch with {disagreeing:?}"),
+            },
+        );
+        if let Err(err) = self.delete_presignature_from_storage(id).await {
+            tracing::warn!(
+                id,
+                ?err,
+                "unable to delete quarantined presignature from datastore"
+            );
+        }
+        crate::metrics::NUM_PRESIGNATURES_QUARANTINED
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc();
+    }
+
+    /// Emits the periodic summary of the presignatures that could not be joined for missing
+    /// triples.
+    pub fn report_missing_triples(&mut self) {
+        self.missing_triples.report();
+    }
+
+    /// Handles the notice of a participant that it never had the triple `triple_id` of our
+    /// presignature `id`. The presignature can never complete, so it is cancelled right away.
+    pub fn on_unknown_triple(
+        &mut self,
+        id: PresignatureId,
+        from: Participant,
+        triple_id: TripleId,
+    ) {
+        let (proposer, participants) = match (self.generators.get(&id), self.checked_out.get(&id)) {
+            (Some(generator), _) => (generator.proposer, &generator.participants),
+            (None, Some(generator)) => (generator.proposer, &generator.participants),
+            (None, None) => {
+                tracing::debug!(
+                    id,
+                    ?from,
+                    triple_id,
+                    "ignoring unknown triple notice for presignature that is not generating"
+                );
+                return;
+            }
+        };
+        if proposer != self.me || !participants.contains(&from) {
+            tracing::warn!(
+                id,
+                ?from,
+                triple_id,
+                "received unknown triple notice for presignature we did not propose to it"
+            );
+            return;
+        }
+
+        crate::metrics::NUM_UNKNOWN_TRIPLE_NOTICES
+            .with_label_values(&[self.my_account_id.as_str(), "received"])
+            .inc();
+        let aborts = self.cancel(id, &format!("{from:?} never had triple {triple_id}"));
+        self.aborts.extend(aborts);
+    }
+
+    /// Builds the report of the unspent presignatures held by this node, reserved ones included,
+    /// addressed to every participant other than this node.
+    pub fn inventory_messages(
+        &self,
+        participants: &Participants,
+    ) -> Vec<(Participant, ReconcileMessage)> {
+        let mut presignatures = self
+            .presignatures
+            .keys()
+            .chain(self.reserved.keys())
+            .copied()
+            .collect::<Vec<_>>();
+        presignatures.sort();
+        participants
+            .keys()
+            .filter(|p| **p != self.me)
+            .map(|p| {
+                (
+                    *p,
+                    ReconcileMessage {
+                        keyspace: self.keyspace.clone(),
+                        epoch: self.epoch,
+                        from: self.me,
+                        presignatures: presignatures.clone(),
+                        timestamp: Utc::now().timestamp() as u64,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Records the unspent presignatures `from` reported to hold, replacing its previous report.
+    pub fn on_inventory(&mut self, from: Participant, presignatures: Vec<PresignatureId>) {
+        tracing::debug!(
+            ?from,
+            len = presignatures.len(),
+            "received presignature inventory"
+        );
+        self.inventories.insert(
+            from,
+            Inventory {
+                received_at: Utc::now().timestamp() as u64,
+                presignatures: presignatures.into_iter().collect(),
+            },
+        );
+    }
+
+    /// Number of participants of the presignature that still hold it. A participant counts as a
+    /// holder unless it reported otherwise at least [`RECONCILE_GRACE`] after the presignature got
+    /// completed on our side, so that nothing is pruned on missing or early reports.
+    fn holders(&self, presignature: &Presignature) -> usize {
+        let since = presignature.created_at + RECONCILE_GRACE.as_secs();
+        presignature
+            .participants
+            .iter()
+            .filter(|p| {
+                **p == self.me
+                    || match self.inventories.get(p) {
+                        Some(inventory) if inventory.received_at >= since => {
+                            inventory.presignatures.contains(&presignature.id)
+                        }
+                        _ => true,
+                    }
+            })
+            .count()
+    }
+
+    /// Drops the unspent presignatures that fewer than `threshold` of their participants still
+    /// hold according to their reports, e.g. because the protocol timed out on their side while
+    /// it completed on ours. A signature can never be produced from them, so they would only
+    /// fail the requests they get picked for. Returns the ids of the dropped presignatures.
+    pub async fn reconcile(&mut self) -> Vec<PresignatureId> {
+        let orphaned = self
+            .presignatures
+            .values()
+            .filter(|presignature| self.holders(presignature) < self.threshold)
+            .map(|presignature| presignature.id)
+            .collect::<Vec<_>>();
+        for id in &orphaned {
+            tracing::warn!(id, "dropping presignature held by too few participants");
+            self.presignatures.remove(id);
+            self.mine.retain(|mine_id| mine_id != id);
+            self.gc.insert(*id, self.clock.now());
+            journal::record(
+                self.epoch,
+                ProtocolKind::Presignature,
+                id,
+                Event::Aborted {
+                    reason: "held by too few participants".to_string(),
+                },
+            );
+            if let Err(err) = self.delete_presignature_from_storage(*id).await {
+                tracing::warn!(
+                    id,
+                    ?err,
+                    "unable to delete orphaned presignature from datastore"
+                );
+            }
+        }
+        if !orphaned.is_empty() {
+            crate::metrics::NUM_PRESIGNATURES_RECONCILED
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc_by(orphaned.len() as f64);
+        }
+        orphaned
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn generate_internal(
+        id: PresignatureId,
+        participants: &Participants,
+        me: Participant,
+        threshold: usize,
+        triple0: Triple,
+        triple1: Triple,
+        public_key: &PublicKey,
+        private_share: &SecretKeyShare,
+        proposer: Participant,
+        timeout: u64,
+        clock: SharedClock,
+    ) -> Result<PresignatureGenerator, InitializationError> {
+        let participants: Vec<_> = participants.keys().cloned().collect();
+        let protocol = Box::new(cait_sith::presign(
+            &participants,
+            me,
+            // These paramaters appear to be to make it easier to use different indexing schemes for triples
+            // Introduced in this PR https://github.com/LIT-Protocol/cait-sith/pull/7
+            &participants,
+            me,
+            PresignArguments {
+                // The triples wipe their shares once dropped, so the protocol gets its own copy.
+                triple0: (triple0.share.clone(), triple0.public.clone()),
+                triple1: (triple1.share.clone(), triple1.public.clone()),
+                keygen_out: KeygenOutput {
+                    private_share: *private_share,
+                    public_key: *public_key,
+                },
+                threshold,
+            },
+        )?);
+        Ok(PresignatureGenerator::new(
+            id,
+            protocol,
+            participants,
+            triple0.id,
+            triple1.id,
+            proposer,
+            proposer == me,
+            timeout,
+            clock,
+        ))
+    }
+
+    /// Starts a new presignature generation protocol.
+    pub fn generate(
+        &mut self,
+        participants: &Participants,
+        triple0: Triple,
+        triple1: Triple,
+        public_key: &PublicKey,
+        private_share: &SecretKeyShare,
+        timeout: u64,
+    ) -> Result<(), InitializationError> {
+        let id = hash_as_id(self.epoch, triple0.id, triple1.id, self.me);
+
+        // Check if the `id` is already in the system. Error out and have the next cycle try again.
+        if self.generators.contains_key(&id)
+            || self.checked_out.contains_key(&id)
+            || self.presignatures.contains_key(&id)
+            || self.gc.contains_key(&id)
+        {
+            tracing::warn!(id, "presignature id collision");
+            return Err(InitializationError::BadParameters(format!(
+                "id collision: presignature_id={id}"
+            )));
+        }
+
+        let generator = Self::generate_internal(
+            id,
+            participants,
+            self.me,
             self.threshold,
             triple0,
             triple1,
             public_key,
             private_share,
-            true,
+            self.me,
             timeout,
+            self.clock.clone(),
         )?;
+        generator
+            .span
+            .in_scope(|| tracing::info!("starting protocol to generate a new presignature"));
+        journal::record(
+            self.epoch,
+            ProtocolKind::Presignature,
+            id,
+            Event::Started {
+                participants: generator.participants.clone(),
+            },
+        );
         self.generators.insert(id, generator);
         self.introduced.insert(id);
         crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS
@@ -257,69 +1648,279 @@ impl PresignatureManager {
         Ok(())
     }
 
+    /// Starts `n` presignature generation protocols at once. The `2n` triples are taken from the
+    /// triple manager in a single all-or-nothing call so that no other caller can race us for
+    /// them. Triples that end up unused, either because their participants do not reach the
+    /// threshold or because a generator fails to start, are returned to the triple manager.
+    ///
+    /// Returns the number of generators that were started.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn generate_batch(
+        &mut self,
+        n: usize,
+        triple_manager: &mut TripleManager,
+        active: &Participants,
+        pk: &PublicKey,
+        sk_share: &SecretKeyShare,
+        timeout: u64,
+    ) -> Result<usize, InitializationError> {
+        let Some(pairs) = triple_manager.take_two_mine_batch(n).await else {
+            tracing::warn!(
+                n,
+                "running: we don't have enough triples to generate presignatures"
+            );
+            return Ok(0);
+        };
+
+        let mut started = 0;
+        let mut pairs = pairs.into_iter();
+        while let Some((triple0, triple1)) = pairs.next() {
+            if triple_manager
+                .check_subset(&triple0)
+                .and_then(|_| triple_manager.check_subset(&triple1))
+                .is_err()
+            {
+                // Triples generated before the participants of triples were selected by subset
+                // cannot be used anymore, as the other participants reject them.
+                continue;
+            }
+            let presig_participants =
+                active.intersection(&[&triple0.public.participants, &triple1.public.participants]);
+            if presig_participants.len() < self.threshold {
+                tracing::warn!(
+                    participants = ?presig_participants.keys_vec(),
+                    "running: the intersection of participants is less than the threshold"
+                );
+
+                // Insert back the triples to be used later since this active set of
+                // participants were not able to make use of these triples.
+                triple_manager.insert_mine(triple0).await;
+                triple_manager.insert_mine(triple1).await;
+                continue;
+            }
+
+            if let Err(err) = self.generate(
+                &presig_participants,
+                triple0.clone(),
+                triple1.clone(),
+                pk,
+                sk_share,
+                timeout,
+            ) {
+                // The generator never started, so neither this pair nor the remaining ones have
+                // been shared with anyone yet.
+                for (triple0, triple1) in std::iter::once((triple0, triple1)).chain(pairs) {
+                    triple_manager.insert_mine(triple0).await;
+                    triple_manager.insert_mine(triple1).await;
+                }
+                return Err(err);
+            }
+            started += 1;
+        }
+
+        Ok(started)
+    }
+
+    /// Proposes a new presignature to the `active` participants if this node is short of them.
+    /// The share of the stockpile of this node is taken over the `participants` of the epoch, as
+    /// recorded in the contract, so that every node computes the same split regardless of which
+    /// peers it currently reaches.
     pub async fn stockpile(
         &mut self,
+        participants: &Participants,
         active: &Participants,
         pk: &PublicKey,
         sk_share: &SecretKeyShare,
         triple_manager: &mut TripleManager,
         cfg: &ProtocolConfig,
     ) -> Result<(), InitializationError> {
-        let not_enough_presignatures = {
+        let missing = {
             // Stopgap to prevent too many presignatures in the system. This should be around min_presig*nodes*2
             // for good measure so that we have enough presignatures to do sig generation while also maintain
             // the minimum number of presignature where a single node can't flood the system.
-            if self.potential_len() >= cfg.presignature.max_presignatures as usize {
-                false
+            if self.potential_len() >= cfg.presignature.max_presignatures as usize
+                || self.utilization().is_full()
+            {
+                0
             } else {
-                // We will always try to generate a new triple if we have less than the minimum
-                self.my_len() < cfg.presignature.min_presignatures as usize
-                    && self.introduced.len() < cfg.max_concurrent_introduction as usize
+                // We will always try to generate new presignatures if we have less than the
+                // minimum, as long as we stay within our assigned share of the stockpile.
+                let assigned = assigned_slots(
+                    self.epoch,
+                    self.me,
+                    &weights(cfg, participants),
+                    cfg.presignature.max_presignatures as usize,
+                );
+                (cfg.presignature.min_presignatures as usize)
+                    .saturating_sub(self.my_len())
+                    .min(assigned.saturating_sub(self.my_len() + self.introduced.len()))
+                    .min(
+                        (cfg.max_concurrent_introduction as usize)
+                            .saturating_sub(self.introduced.len()),
+                    )
             }
         };
 
-        if not_enough_presignatures {
-            tracing::debug!("not enough presignatures, generating");
+        // A batch takes all of its triples at once, so it is bounded by the pairs of triples of
+        // mine available as well.
+        let n = missing
+            .min(batch_size(cfg))
+            .min(triple_manager.my_len() / 2)
+            .max(missing.min(1));
+        if n > 0 {
+            tracing::debug!(n, "not enough presignatures, generating");
             // To ensure there is no contention between different nodes we are only using triples
             // that we proposed. This way in a non-BFT environment we are guaranteed to never try
             // to use the same triple as any other node.
-            if let Some((triple0, triple1)) = triple_manager.take_two_mine().await {
-                let presig_participants = active
-                    .intersection(&[&triple0.public.participants, &triple1.public.participants]);
-                if presig_participants.len() < self.threshold {
-                    tracing::warn!(
-                        participants = ?presig_participants.keys_vec(),
-                        "running: the intersection of participants is less than the threshold"
-                    );
-
-                    // Insert back the triples to be used later since this active set of
-                    // participants were not able to make use of these triples.
-                    triple_manager.insert_mine(triple0).await;
-                    triple_manager.insert_mine(triple1).await;
-                } else {
-                    self.generate(
-                        &presig_participants,
-                        triple0,
-                        triple1,
-                        pk,
-                        sk_share,
-                        cfg.presignature.generation_timeout,
-                    )?;
-                }
-            } else {
-                tracing::warn!("running: we don't have enough triples to generate a presignature");
-            }
+            self.generate_batch(
+                n,
+                triple_manager,
+                active,
+                pk,
+                sk_share,
+                cfg.presignature.generation_timeout,
+            )
+            .await?;
         }
 
         Ok(())
     }
 
+    /// Checks that a presignature proposed by another participant may be joined before any
+    /// triples are taken for it: the proposer has to be a participant with presignature slots
+    /// assigned in this epoch, and may only have a bounded number of presignatures generating.
+    /// A proposer at its quota is let through as long as room can be made for it with
+    /// [`PresignatureManager::make_room_for`].
+    fn validate_proposal(
+        &self,
+        participants: &Participants,
+        id: PresignatureId,
+        proposer: Participant,
+        cfg: &ProtocolConfig,
+    ) -> Result<(), GenerationError> {
+        if proposer == self.me || !participants.contains_key(&proposer) {
+            tracing::warn!(
+                id,
+                ?proposer,
+                "presignature proposed by an unknown participant"
+            );
+            return Err(GenerationError::UnknownProposer(proposer));
+        }
+        // The registry follows the contract, whereas the participants of the epoch only change
+        // once the node caught up with it.
+        if let Err(err) = registry::read(&self.registry).validate(&proposer) {
+            tracing::warn!(id, %err, "presignature proposed by a participant not in the contract");
+            return Err(GenerationError::UnknownProposer(proposer));
+        }
+        let slots = assigned_slots(
+            self.epoch,
+            proposer,
+            &weights(cfg, participants),
+            cfg.presignature.max_presignatures as usize,
+        );
+        if slots == 0 {
+            tracing::warn!(
+                id,
+                ?proposer,
+                "presignature proposed by a participant without slots"
+            );
+            return Err(GenerationError::ProposerNotAssigned(proposer));
+        }
+        let limit = cfg.max_concurrent_introduction as usize;
+        // Generators checked out to be poked cannot be evicted.
+        let checked_out = self
+            .checked_out
+            .values()
+            .filter(|generator| !generator.mine && generator.proposer == proposer)
+            .count();
+        if limit == 0 || checked_out >= limit {
+            tracing::warn!(
+                id,
+                ?proposer,
+                checked_out,
+                "presignature proposer has too many presignatures generating"
+            );
+            crate::metrics::NUM_FOREIGN_PRESIGNATURE_GENERATORS_REJECTED
+                .with_label_values(&[
+                    self.my_account_id.as_str(),
+                    &u32::from(proposer).to_string(),
+                ])
+                .inc();
+            return Err(GenerationError::TooManyForeignGenerators { proposer, limit });
+        }
+        Ok(())
+    }
+
+    /// Rejects the presignature `id` of `proposer` while the pool is at its caps. The presignature
+    /// cannot complete without this node, so its participants are told right away with abort
+    /// messages to be taken with [`PresignatureManager::take_aborts`], instead of waiting for it
+    /// to time out, and its messages are dropped from then on.
+    fn reject_pool_full(
+        &mut self,
+        participants: &Participants,
+        id: PresignatureId,
+        proposer: Participant,
+        pool: PoolUtilization,
+    ) {
+        tracing::warn!(id, ?proposer, %pool, "presignature pool is full, rejecting proposal");
+        self.gc.insert(id, self.clock.now());
+        let reason = format!("presignature pool is full: {pool}");
+        let aborts = self.abort_messages(id, &participants.keys_vec(), &reason);
+        self.aborts.extend(aborts);
+        crate::metrics::NUM_PRESIGNATURE_PROPOSALS_REJECTED_POOL_FULL
+            .with_label_values(&[
+                self.my_account_id.as_str(),
+                &u32::from(proposer).to_string(),
+            ])
+            .inc();
+    }
+
+    /// Makes room for a new generator proposed by `proposer` once it reaches its quota of
+    /// `limit` concurrent foreign generators, by cancelling its least recently active ones. An
+    /// honest proposer never goes over its quota, unless it dropped presignatures without this
+    /// node noticing yet, which are then the ones that stopped receiving messages. A proposer
+    /// spamming new presignatures only ever evicts its own.
+    fn make_room_for(&mut self, proposer: Participant, limit: usize) {
+        let mut foreign = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| !generator.mine && generator.proposer == proposer)
+            .map(|(id, generator)| (generator.last_active, *id))
+            .collect::<Vec<_>>();
+        let open = foreign.len()
+            + self
+                .checked_out
+                .values()
+                .filter(|generator| !generator.mine && generator.proposer == proposer)
+                .count();
+        if open < limit {
+            return;
+        }
+        foreign.sort_unstable();
+        for (last_active, id) in foreign.into_iter().take(open + 1 - limit) {
+            tracing::warn!(
+                id,
+                ?proposer,
+                idle = ?self.clock.elapsed(last_active),
+                "evicting least recently active foreign presignature generator"
+            );
+            let aborts = self.cancel(id, "evicted over the quota of its proposer");
+            self.aborts.extend(aborts);
+            crate::metrics::NUM_FOREIGN_PRESIGNATURE_GENERATORS_EVICTED
+                .with_label_values(&[
+                    self.my_account_id.as_str(),
+                    &u32::from(proposer).to_string(),
+                ])
+                .inc();
+        }
+    }
+
     /// Ensures that the presignature with the given id is either:
     /// 1) Already generated in which case returns `None`, or
     /// 2) Is currently being generated by `protocol` in which case returns `Some(protocol)`, or
     /// 3) Has never been seen by the manager in which case start a new protocol and returns `Some(protocol)`, or
-    /// 4) Depends on triples (`triple0`/`triple1`) that are unknown to the node
-    // TODO: What if the presignature completed generation and is already spent?
+    /// 4) Depends on triples (`triple0`/`triple1`) that are unknown to the node, or
+    /// 5) Has already been spent in a signing protocol in which case returns `AlreadySpent`
     #[allow(clippy::too_many_arguments)]
     pub async fn get_or_generate(
         &mut self,
@@ -327,98 +1928,312 @@ impl PresignatureManager {
         id: PresignatureId,
         triple0: TripleId,
         triple1: TripleId,
+        proposer: Participant,
         triple_manager: &mut TripleManager,
         public_key: &PublicKey,
         private_share: &SecretKeyShare,
         cfg: &ProtocolConfig,
     ) -> Result<&mut PresignatureProtocol, GenerationError> {
-        if self.presignatures.contains_key(&id) {
+        let expected = hash_as_id(self.epoch, triple0, triple1, proposer);
+        if id != expected {
+            tracing::warn!(
+                id,
+                expected,
+                triple0,
+                triple1,
+                ?proposer,
+                "presignature id does not match its triples and proposer"
+            );
+            Err(GenerationError::IdMismatch { id, expected })
+        } else if self.spent.contains(&id) {
+            tracing::warn!(id, "presignature already spent");
+            Err(GenerationError::AlreadySpent(id))
+        } else if self.presignatures.contains_key(&id) || self.reserved.contains_key(&id) {
             tracing::warn!(id, "presignature already generated");
             Err(GenerationError::AlreadyGenerated)
         } else if self.gc.contains_key(&id) {
             tracing::warn!(id, "presignature was garbage collected");
             Err(GenerationError::PresignatureIsGarbageCollected(id))
+        } else if self.checked_out.contains_key(&id) {
+            // The messages are handed to the generator once it is checked back in.
+            Err(GenerationError::PresignatureIsCheckedOut(id))
         } else {
-            match self.generators.entry(id) {
-                Entry::Vacant(entry) => {
-                    tracing::info!(id, "joining protocol to generate a new presignature");
-                    let (triple0, triple1) = match triple_manager.take_two(triple0, triple1).await {
-                        Ok(result) => result,
-                        Err(error) => match error {
-                            GenerationError::TripleIsGenerating(_) => {
-                                tracing::warn!(
+            if let Some(generator) = self.generators.get_mut(&id) {
+                generator.last_active = self.clock.now();
+                return Ok(&mut generator.protocol);
+            }
+            self.validate_proposal(participants, id, proposer, cfg)?;
+            let pool = self.utilization();
+            if pool.is_full() {
+                self.reject_pool_full(participants, id, proposer, pool);
+                return Err(GenerationError::PoolFull(pool));
+            }
+            let load = JoinLoad {
+                my_triples: triple_manager.my_len(),
+                generators: triple_manager.generators.len() + self.generators.len(),
+            };
+            if shedding::is_shedding() {
+                tracing::debug!(
+                    id,
+                    ?proposer,
+                    "shedding load, declined to join presignature"
+                );
+                return Err(GenerationError::JoinDeclined(proposer));
+            }
+            if refresh::is_refreshing() {
+                tracing::debug!(
+                    id,
+                    ?proposer,
+                    "refreshing the key share, declined to join presignature"
+                );
+                return Err(GenerationError::JoinDeclined(proposer));
+            }
+            if !triple_manager.policy.join_presignature(&load, cfg) {
+                tracing::debug!(id, ?proposer, ?load, "declined to join presignature");
+                return Err(GenerationError::JoinDeclined(proposer));
+            }
+            tracing::info!(id, "joining protocol to generate a new presignature");
+            let (triple0, triple1) = match triple_manager
+                .take_two_for(triple0, triple1, proposer)
+                .await
+            {
+                Ok(result) => result,
+                Err(error) => match error {
+                    GenerationError::TripleIsGenerating(_) => {
+                        tracing::warn!(
                                     ?error,
                                     id,
                                     triple0,
                                     triple1,
                                     "could not initiate non-introduced presignature: one triple is generating"
                                 );
-                                return Err(error);
-                            }
-                            GenerationError::TripleIsGarbageCollected(_) => {
-                                tracing::warn!(
+                        return Err(error);
+                    }
+                    GenerationError::TripleIsGarbageCollected(_) => {
+                        tracing::warn!(
                                     ?error,
                                     id,
                                     triple0,
                                     triple1,
                                     "could not initiate non-introduced presignature: one triple is in garbage collection"
                                 );
-                                return Err(error);
-                            }
-                            GenerationError::TripleIsMissing(_) => {
-                                tracing::warn!(
+                        return Err(error);
+                    }
+                    GenerationError::TripleIsMissing(_) => {
+                        // Summarized by the caller, see `PresignatureManager::on_missing_triple`.
+                        return Err(error);
+                    }
+                    GenerationError::TripleIsSpent(_) => {
+                        tracing::warn!(
+                            ?error,
+                            id,
+                            triple0,
+                            triple1,
+                            "could not initiate non-introduced presignature: one triple was already spent"
+                        );
+                        return Err(error);
+                    }
+                    GenerationError::TripleIsReserved(..) => {
+                        tracing::warn!(
                                     ?error,
                                     id,
                                     triple0,
                                     triple1,
-                                    "could not initiate non-introduced presignature: one triple is missing"
+                                    "could not initiate non-introduced presignature: one triple is reserved by another presignature"
                                 );
-                                return Err(error);
-                            }
-                            _ => {
-                                tracing::error!(?error, "Unexpected Generation Error");
-                                return Err(error);
-                            }
-                        },
-                    };
-                    let generator = Self::generate_internal(
-                        participants,
-                        self.me,
-                        self.threshold,
-                        triple0,
-                        triple1,
-                        public_key,
-                        private_share,
-                        false,
-                        cfg.presignature.generation_timeout,
-                    )?;
-                    let generator = entry.insert(generator);
-                    crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS
-                        .with_label_values(&[self.my_account_id.as_str()])
+                        return Err(error);
+                    }
+                    _ => {
+                        tracing::error!(?error, "Unexpected Generation Error");
+                        return Err(error);
+                    }
+                },
+            };
+            // The triples are not released on mismatch: the proposer does not hold a valid
+            // triple for them either, so they can never be used.
+            triple_manager
+                .check_subset(&triple0)
+                .and_then(|_| triple_manager.check_subset(&triple1))?;
+            // Only the participants holding shares of both triples take part, as the proposer
+            // selected them.
+            let participants = participants
+                .intersection(&[&triple0.public.participants, &triple1.public.participants]);
+            let generator = match Self::generate_internal(
+                id,
+                &participants,
+                self.me,
+                self.threshold,
+                triple0.clone(),
+                triple1.clone(),
+                public_key,
+                private_share,
+                proposer,
+                cfg.presignature.generation_timeout,
+                self.clock.clone(),
+            ) {
+                Ok(generator) => generator,
+                Err(error) => {
+                    // No message was exchanged for this presignature yet, so the
+                    // triples can still be used by another proposal.
+                    triple_manager.release(triple0, triple1).await;
+                    return Err(error.into());
+                }
+            };
+            self.make_room_for(proposer, cfg.max_concurrent_introduction as usize);
+            journal::record(
+                self.epoch,
+                ProtocolKind::Presignature,
+                id,
+                Event::Started {
+                    participants: generator.participants.clone(),
+                },
+            );
+            crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            Ok(&mut self.generators.entry(id).or_insert(generator).protocol)
+        }
+    }
+
+    /// Takes the next presignature of mine. Presignatures that are no longer bound to the
+    /// current key and epoch are discarded along the way, as they would produce invalid
+    /// signatures.
+    pub async fn take_mine(&mut self) -> Option<Presignature> {
+        loop {
+            let my_presignature_id = *self.mine.front()?;
+            tracing::info!(my_presignature_id, "take presignature of mine");
+            match self.take(my_presignature_id).await {
+                Ok(presignature) => return Some(presignature),
+                Err(err @ GenerationError::StaleKeyBinding { .. }) => {
+                    tracing::error!(?err, "discarded presignature of mine");
+                }
+                // Taking mine always succeeds otherwise, since it is only present when generation
+                // completes where the determination of ownership is made. Drop the id so that the
+                // divergence does not block the queue.
+                Err(err) => {
+                    tracing::error!(?err, "failed to take presignature of mine");
+                    self.mine.retain(|id| *id != my_presignature_id);
+                    crate::metrics::MANAGER_INVARIANT_VIOLATIONS
+                        .with_label_values(&[self.my_account_id.as_str(), "presignature"])
                         .inc();
-                    Ok(&mut generator.protocol)
                 }
-                Entry::Occupied(entry) => Ok(&mut entry.into_mut().protocol),
             }
         }
     }
 
-    pub fn take_mine(&mut self) -> Option<Presignature> {
-        let my_presignature_id = self.mine.pop_front()?;
-        tracing::info!(my_presignature_id, "take presignature of mine");
-        // SAFETY: This unwrap is safe because taking mine will always succeed since it is only
-        // present when generation completes where the determination of ownership is made.
-        Some(self.take(my_presignature_id).unwrap())
+    /// Waits until the presignature with the given id can be reserved. Instead of polling, the
+    /// caller is parked until a presignature completes while this one is generating or its
+    /// messages are yet to arrive. Fails right away if it cannot become available anymore, and
+    /// with the reason it is still unavailable once `timeout` elapsed.
+    pub async fn wait_for(
+        manager: &RwLock<Self>,
+        id: PresignatureId,
+        timeout: Duration,
+    ) -> Result<(), GenerationError> {
+        let outcome = triple::park_until(
+            manager,
+            |manager| manager.poll_available(id),
+            |manager| manager.available.subscribe(),
+            timeout,
+        )
+        .await;
+        match outcome {
+            Some(outcome) => outcome,
+            None => {
+                let manager = manager.read().await;
+                manager.poll_available(id).unwrap_or_else(|| {
+                    if manager.generators.contains_key(&id) || manager.checked_out.contains_key(&id)
+                    {
+                        Err(GenerationError::PresignatureIsGenerating(id))
+                    } else {
+                        Err(GenerationError::PresignatureIsMissing(id))
+                    }
+                })
+            }
+        }
     }
 
-    pub fn take(&mut self, id: PresignatureId) -> Result<Presignature, GenerationError> {
+    /// Whether the presignature is available, or `None` while it may still become available.
+    fn poll_available(&self, id: PresignatureId) -> Option<Result<(), GenerationError>> {
+        if self.presignatures.contains_key(&id) {
+            Some(Ok(()))
+        } else if self.spent.contains(&id) {
+            Some(Err(GenerationError::AlreadySpent(id)))
+        } else if self.reserved.contains_key(&id) {
+            Some(Err(GenerationError::PresignatureIsReserved(id)))
+        } else if self.gc.contains_key(&id) {
+            Some(Err(GenerationError::PresignatureIsGarbageCollected(id)))
+        } else {
+            None
+        }
+    }
+
+    fn notify_available(&self) {
+        self.available.send_modify(|version| *version += 1);
+    }
+
+    pub async fn take(&mut self, id: PresignatureId) -> Result<Presignature, GenerationError> {
+        let slot = self.reserve(id)?;
+        let presignature = self.spend(slot).await?;
+        tracing::info!(id, "took presignature");
+        Ok(presignature)
+    }
+
+    /// Reserves the presignature with the given id so that no one else can take or reserve it
+    /// until the reservation is either spent, released, or expires.
+    pub fn reserve(&mut self, id: PresignatureId) -> Result<PresignatureSlot, GenerationError> {
         if let Some(presignature) = self.presignatures.remove(&id) {
-            self.gc.insert(id, Instant::now());
-            tracing::info!(id, "took presignature");
-            return Ok(presignature);
+            let mine = if let Some(pos) = self.mine.iter().position(|mine_id| *mine_id == id) {
+                self.mine.remove(pos);
+                true
+            } else {
+                false
+            };
+            if !is_bound_to(&presignature, self.epoch, &self.public_key_hash) {
+                // Never hand out a presignature of an old key, it would produce an invalid
+                // signature. It stays in garbage collection, so that it is never taken again.
+                tracing::error!(
+                    id,
+                    epoch = presignature.epoch,
+                    current_epoch = self.epoch,
+                    "presignature is not bound to the current key and epoch"
+                );
+                self.gc.insert(id, self.clock.now());
+                crate::metrics::NUM_STALE_PRESIGNATURES_REJECTED
+                    .with_label_values(&[self.my_account_id.as_str()])
+                    .inc();
+                return Err(GenerationError::StaleKeyBinding {
+                    id,
+                    epoch: presignature.epoch,
+                });
+            }
+            let generation = self.next_generation;
+            self.next_generation += 1;
+            self.reserved.insert(
+                id,
+                Reservation {
+                    presignature: presignature.clone(),
+                    mine,
+                    timestamp: self.clock.now(),
+                    generation,
+                },
+            );
+            tracing::debug!(id, "reserved presignature");
+            return Ok(PresignatureSlot {
+                presignature,
+                generation,
+            });
         }
 
-        if self.generators.contains_key(&id) {
+        if self.spent.contains(&id) {
+            tracing::warn!(id, "presignature was already spent");
+            return Err(GenerationError::AlreadySpent(id));
+        }
+        if self.reserved.contains_key(&id) {
+            tracing::warn!(id, "presignature is reserved");
+            return Err(GenerationError::PresignatureIsReserved(id));
+        }
+        if self.generators.contains_key(&id) || self.checked_out.contains_key(&id) {
             tracing::warn!(id, "presignature is still generating");
             return Err(GenerationError::PresignatureIsGenerating(id));
         }
@@ -430,124 +2245,455 @@ impl PresignatureManager {
         Err(GenerationError::PresignatureIsMissing(id))
     }
 
-    pub fn insert_mine(&mut self, presig: Presignature) {
+    /// Marks the reserved presignature as spent, removing it from the manager and storage for good.
+    /// Fails if the reservation of the slot expired, in which case the presignature may have been
+    /// reserved by someone else since and must not be used. Also fails if the presignature could
+    /// not be removed from storage, in which case it is released back to the manager.
+    pub async fn spend(&mut self, slot: PresignatureSlot) -> Result<Presignature, GenerationError> {
+        let id = slot.id();
+        if !self.holds(&slot) {
+            tracing::warn!(id, "presignature reservation expired before it was spent");
+            crate::metrics::NUM_PRESIGNATURE_RESERVATIONS_EXPIRED
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            return Err(GenerationError::ReservationExpired(id));
+        }
+        // The presignature is removed from the datastore before it is handed out, as a restart
+        // would otherwise load it again and have it used for a second signature, leaking the key
+        // share through the reused nonce.
+        if let Err(err) = self.delete_presignature_from_storage(id).await {
+            tracing::error!(
+                id,
+                ?err,
+                "unable to delete presignature: refusing to spend it"
+            );
+            if let Some(reservation) = self.reserved.remove(&id) {
+                self.unreserve(reservation);
+            }
+            return Err(GenerationError::PresignatureNotDeleted(id, err.to_string()));
+        }
+        self.reserved.remove(&id);
+        self.spent.put(id, ());
+        self.gc.insert(id, self.clock.now());
+        journal::record(self.epoch, ProtocolKind::Presignature, id, Event::Taken);
+        events::emit(NodeEvent::PresignatureSpent {
+            epoch: self.epoch,
+            id,
+        });
+        Ok(slot.presignature)
+    }
+
+    /// Whether the reservation of the slot is still the one held for its presignature.
+    fn holds(&self, slot: &PresignatureSlot) -> bool {
+        self.reserved
+            .get(&slot.id())
+            .is_some_and(|reservation| reservation.generation == slot.generation)
+    }
+
+    /// Releases the reserved presignature back to the manager. Only to be used when the signing
+    /// protocol failed to start, since a presignature must never be used in more than one signature.
+    pub fn release(&mut self, slot: PresignatureSlot) {
+        let id = slot.id();
+        if !self.holds(&slot) {
+            tracing::warn!(id, "presignature reservation already expired");
+            return;
+        }
+        if let Some(reservation) = self.reserved.remove(&id) {
+            tracing::debug!(id, "released presignature reservation");
+            self.unreserve(reservation);
+        }
+    }
+
+    fn unreserve(&mut self, reservation: Reservation) {
+        let id = reservation.presignature.id;
+        if reservation.mine {
+            self.mine.push_back(id);
+        }
+        self.presignatures.insert(id, reservation.presignature);
+        self.notify_available();
+    }
+
+    pub async fn insert_mine(&mut self, presig: Presignature) {
         tracing::debug!(id = ?presig.id, "inserting presignature");
         // Remove from taken list if it was there
         self.gc.remove(&presig.id);
+        self.spent.pop(&presig.id);
         self.mine.push_back(presig.id);
-        self.presignatures.insert(presig.id, presig);
+        self.presignatures.insert(presig.id, presig.clone());
+        self.notify_available();
+        self.insert_presignatures_to_storage(vec![presig]).await;
     }
 
-    /// Pokes all of the ongoing generation protocols and returns a vector of
-    /// messages to be sent to the respective participant.
+    async fn delete_presignature_from_storage(
+        &mut self,
+        id: PresignatureId,
+    ) -> Result<(), error::DatastoreStorageError> {
+        let action = || async {
+            let mut presignature_storage = self.presignature_storage.write().await;
+            if let Err(err) = presignature_storage.delete(id, self.epoch).await {
+                tracing::warn!(?err, id, "presignature deletion failed.");
+                return Err(err);
+            }
+            Ok(())
+        };
+
+        // Retry the action 3x with 500ms delay between each retry
+        let retry_strategy = std::iter::repeat_with(|| Duration::from_millis(500)).take(3);
+        tokio_retry::Retry::spawn(retry_strategy, action).await
+    }
+
+    async fn insert_presignatures_to_storage(
+        &mut self,
+        presignatures_to_insert: Vec<Presignature>,
+    ) {
+        for presignature in presignatures_to_insert {
+            let mine = self.mine.contains(&presignature.id);
+            let action = || async {
+                let mut presignature_storage = self.presignature_storage.write().await;
+                if let Err(e) = presignature_storage
+                    .insert(presignature.clone(), mine)
+                    .await
+                {
+                    tracing::warn!(?e, id = presignature.id, "presignature insertion failed.");
+                    return Err(e);
+                }
+                Ok(())
+            };
+
+            // Retry the action 3x with 500ms delay between each retry
+            let retry_strategy = std::iter::repeat_with(|| Duration::from_millis(500)).take(3);
+            let _ = tokio_retry::Retry::spawn(retry_strategy, action).await;
+        }
+    }
+
+    /// Pokes all of the ongoing generation protocols, returning the messages to be sent to the
+    /// respective participants alongside the generators that failed and were dropped.
     ///
-    /// An empty vector means we cannot progress until we receive a new message.
-    pub fn poke(&mut self) -> Vec<(Participant, PresignatureMessage)> {
+    /// A failing generator never prevents the remaining generators from progressing. The triples
+    /// it consumed are wasted: their shares were already exchanged with the other participants,
+    /// so they can never be safely reused for another presignature.
+    pub async fn poke(&mut self) -> PokeOutcome<PresignatureId, PresignatureMessage> {
+        self.poke_with_budget(usize::MAX).await
+    }
+
+    /// Same as [`PresignatureManager::poke`], but stops poking further generators once
+    /// `budget` messages have been produced. The remaining generators get poked in a later call.
+    pub async fn poke_with_budget(
+        &mut self,
+        budget: usize,
+    ) -> PokeOutcome<PresignatureId, PresignatureMessage> {
+        let poked = self.checkout().poke(budget).await;
+        self.checkin(poked).await
+    }
+
+    /// Checks out all of the ongoing generators, so that they can be poked with
+    /// [`CheckedOut::poke`] after releasing the lock of the manager. Poking is CPU bound and
+    /// takes a while with many generators, during which readers of the manager would otherwise
+    /// be blocked. Messages for checked out generators are deferred until they are checked back
+    /// in with [`PresignatureManager::checkin`].
+    pub fn checkout(&mut self) -> CheckedOut {
+        let mut ids = self.generators.keys().copied().collect::<Vec<_>>();
+        generator::rotate(&mut ids, self.poke_cursor.take());
+        let generators = ids
+            .into_iter()
+            .filter_map(|id| self.generators.remove_entry(&id))
+            .collect::<Vec<_>>();
+        for (id, generator) in &generators {
+            self.checked_out.insert(
+                *id,
+                CheckedOutGenerator {
+                    participants: generator.participants.clone(),
+                    proposer: generator.proposer,
+                    mine: generator.mine,
+                },
+            );
+        }
+        CheckedOut {
+            keyspace: self.keyspace.clone(),
+            epoch: self.epoch,
+            me: self.me,
+            generators,
+        }
+    }
+
+    /// Checks the poked generators back in, returning the messages to be sent to the respective
+    /// participants alongside the generators that failed and were dropped. Generators that were
+    /// cancelled, aborted or belong to a previous epoch in the meantime are dropped.
+    pub async fn checkin(
+        &mut self,
+        poked: Poked,
+    ) -> PokeOutcome<PresignatureId, PresignatureMessage> {
         let mut messages = Vec::new();
-        let mut errors = Vec::new();
-        self.generators.retain(|id, generator| {
-            loop {
-                let action = match generator.poke() {
-                    Ok(action) => action,
-                    Err(e) => {
-                        crate::metrics::PRESIGNATURE_GENERATOR_FAILURES
+        let mut presignatures_to_insert = Vec::new();
+        let mut completed = Vec::new();
+        let mut failures = Vec::new();
+
+        if poked.epoch == self.epoch {
+            self.poke_cursor = poked.skipped;
+        }
+        for (id, mut generator, generator_messages, status) in poked.results {
+            if poked.epoch != self.epoch || self.checked_out.remove(&id).is_none() {
+                tracing::debug!(id, "dropping presignature generator cancelled while poked");
+                continue;
+            }
+            let _span = generator.span.clone().entered();
+            generator.rounds.observe(
+                PokeKind::Presignature,
+                self.my_account_id.as_str(),
+                matches!(status, PokeStatus::Completed(_)),
+            );
+            messages.extend(generator_messages);
+            match status {
+                PokeStatus::Waiting => {
+                    tracing::debug!("presignature: waiting");
+                    // Retain protocol until we are finished
+                    self.generators.insert(id, generator);
+                }
+                PokeStatus::Failed(failure) => {
+                    crate::metrics::PRESIGNATURE_GENERATOR_FAILURES
+                        .with_label_values(&[self.my_account_id.as_str()])
+                        .inc();
+                    crate::metrics::PRESIGNATURE_TRIPLES_WASTED
+                        .with_label_values(&[self.my_account_id.as_str()])
+                        .inc_by(2.0);
+                    if failure.is_timeout() {
+                        crate::metrics::PRESIGNATURE_GENERATOR_TIMEOUTS
                             .with_label_values(&[self.my_account_id.as_str()])
                             .inc();
-                        self.gc.insert(*id, Instant::now());
-                        self.introduced.remove(id);
-                        errors.push(e);
-                        break false;
-                    }
-                };
-                match action {
-                    Action::Wait => {
-                        tracing::debug!("presignature: waiting");
-                        // Retain protocol until we are finished
-                        return true;
                     }
-                    Action::SendMany(data) => {
-                        for p in generator.participants.iter() {
-                            messages.push((
-                                *p,
-                                PresignatureMessage {
-                                    id: *id,
-                                    triple0: generator.triple0,
-                                    triple1: generator.triple1,
-                                    epoch: self.epoch,
-                                    from: self.me,
-                                    data: data.clone(),
-                                    timestamp: Utc::now().timestamp() as u64
-                                },
-                            ))
+                    self.gc.insert(id, self.clock.now());
+                    self.introduced.remove(&id);
+                    let event = if failure.is_timeout() {
+                        Event::TimedOut
+                    } else {
+                        Event::Failed {
+                            reason: failure.to_string(),
                         }
-                    }
-                    Action::SendPrivate(p, data) => messages.push((
-                        p,
-                        PresignatureMessage {
-                            id: *id,
-                            triple0: generator.triple0,
-                            triple1: generator.triple1,
-                            epoch: self.epoch,
-                            from: self.me,
-                            data,
-                            timestamp: Utc::now().timestamp() as u64
+                    };
+                    journal::record(self.epoch, ProtocolKind::Presignature, id, event);
+                    participation::record(
+                        self.my_account_id.as_str(),
+                        self.epoch,
+                        ProtocolKind::Presignature,
+                        &generator.participants,
+                        if failure.is_timeout() {
+                            Outcome::TimedOut
+                        } else {
+                            Outcome::Failed
                         },
-                    )),
-                    Action::Return(output) => {
-                        tracing::info!(
-                            id,
-                            me = ?self.me,
-                            big_r = ?output.big_r.to_base58(),
-                            "completed presignature generation"
-                        );
-                        self.presignatures.insert(
-                            *id,
-                            Presignature {
-                                id: *id,
-                                output,
-                                participants: generator.participants.clone(),
-                            },
-                        );
-                        if generator.mine {
-                            tracing::info!(id, "assigning presignature to myself");
-                            self.mine.push_back(*id);
-                            crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS_MINE_SUCCESS
-                                .with_label_values(&[self.my_account_id.as_str()])
-                                .inc();
+                    );
+                    tracing::warn!(
+                        ?failure,
+                        "dropped failed presignature generator; its triples are wasted"
+                    );
+                    let aborts =
+                        self.abort_messages(id, &generator.participants, &failure.to_string());
+                    self.aborts.extend(aborts);
+                    failures.push((id, failure));
+                }
+                PokeStatus::Completed(output) => {
+                    tracing::info!(
+                        big_r = ?output.big_r.to_base58(),
+                        "completed presignature generation"
+                    );
+                    journal::record(self.epoch, ProtocolKind::Presignature, id, Event::Completed);
+                    participation::record(
+                        self.my_account_id.as_str(),
+                        self.epoch,
+                        ProtocolKind::Presignature,
+                        &generator.participants,
+                        Outcome::Completed,
+                    );
+                    let presignature = Presignature {
+                        id,
+                        output,
+                        participants: generator.participants.clone(),
+                        created_at: Utc::now().timestamp() as u64,
+                        epoch: self.epoch,
+                        public_key_hash: self.public_key_hash,
+                        triples: Some((generator.triple0, generator.triple1)),
+                    };
+                    events::emit(NodeEvent::PresignatureCompleted {
+                        epoch: self.epoch,
+                        id,
+                        mine: generator.mine,
+                    });
+                    let commitment = commitment(&presignature);
+                    for p in &presignature.participants {
+                        if *p == self.me {
+                            continue;
                         }
-                        self.introduced.remove(id);
-
-                        crate::metrics::PRESIGNATURE_LATENCY
-                            .with_label_values(&[self.my_account_id.as_str()])
-                            .observe(generator.timestamp.elapsed().as_secs_f64());
-                        crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS_SUCCESS
+                        self.outgoing_commitments.push((
+                            *p,
+                            CommitmentMessage {
+                                id,
+                                keyspace: self.keyspace.clone(),
+                                epoch: self.epoch,
+                                from: self.me,
+                                commitment,
+                                timestamp: Utc::now().timestamp() as u64,
+                            },
+                        ));
+                    }
+                    self.presignatures.insert(id, presignature.clone());
+                    self.notify_available();
+                    presignatures_to_insert.push(presignature);
+                    completed.push(id);
+                    if generator.mine {
+                        tracing::info!("assigning presignature to myself");
+                        self.mine.push_back(id);
+                        crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS_MINE_SUCCESS
                             .with_label_values(&[self.my_account_id.as_str()])
                             .inc();
-                        // Do not retain the protocol
-                        return false;
                     }
+                    self.introduced.remove(&id);
+
+                    crate::metrics::PRESIGNATURE_LATENCY
+                        .with_label_values(&[self.my_account_id.as_str()])
+                        .observe(self.clock.elapsed(generator.timestamp).as_secs_f64());
+                    crate::metrics::NUM_TOTAL_HISTORICAL_PRESIGNATURE_GENERATORS_SUCCESS
+                        .with_label_values(&[self.my_account_id.as_str()])
+                        .inc();
                 }
             }
-        });
-
-        if !errors.is_empty() {
-            tracing::warn!(?errors, "failed to generate some presignatures");
+        }
+        self.insert_presignatures_to_storage(presignatures_to_insert)
+            .await;
+        // The other participants may have completed the presignatures before us.
+        for id in completed {
+            self.check_commitments(id).await;
         }
 
-        messages
+        PokeOutcome { messages, failures }
     }
 }
 
-pub fn hash_as_id(triple0: TripleId, triple1: TripleId) -> PresignatureId {
+/// Commitment to the `big_r` of a presignature, the same for every participant that completed the
+/// presignature honestly.
+pub fn commitment(presignature: &Presignature) -> [u8; 32] {
     let mut hasher = Sha3_256::new();
+    hasher.update(b"presignature-commitment");
+    hasher.update(presignature.epoch.to_le_bytes());
+    hasher.update(presignature.id.to_le_bytes());
+    hasher.update(presignature.output.big_r.to_bytes());
+    hasher.finalize().into()
+}
+
+/// Hash identifying the public key a presignature is generated for.
+pub fn public_key_hash(public_key: &PublicKey) -> [u8; 32] {
+    Sha3_256::digest(public_key.to_bytes()).into()
+}
+
+fn is_bound_to(presignature: &Presignature, epoch: u64, public_key_hash: &[u8; 32]) -> bool {
+    presignature.epoch == epoch && presignature.public_key_hash == *public_key_hash
+}
+
+/// Derives the id of a presignature from the epoch it is generated in, the triples it consumes
+/// and the participant that proposed it. This allows every participant to verify that the id of
+/// an incoming presignature protocol is consistent with the triples it claims to use.
+pub fn hash_as_id(
+    epoch: u64,
+    triple0: TripleId,
+    triple1: TripleId,
+    proposer: Participant,
+) -> PresignatureId {
+    let proposer: u32 = proposer.into();
+    let mut hasher = Sha3_256::new();
+    hasher.update(epoch.to_le_bytes());
     hasher.update(triple0.to_le_bytes());
     hasher.update(triple1.to_le_bytes());
+    hasher.update(proposer.to_le_bytes());
     let id: [u8; 32] = hasher.finalize().into();
     let id = u64::from_le_bytes(first_8_bytes(id));
 
     PresignatureId::from(id)
 }
 
+/// Number of presignatures proposed at once when short of them, from the
+/// `presignature.batch_size` entry of the protocol config. One at a time if not set.
+pub fn batch_size(cfg: &ProtocolConfig) -> usize {
+    cfg.presignature
+        .other
+        .get("batch_size")
+        .and_then(|size| serde_json::to_value(size).ok())
+        .and_then(|size| size.as_u64())
+        .map_or(1, |size| size.max(1) as usize)
+}
+
+/// Weights of the participants for dealing out the presignature slots, from the
+/// `presignature.weights` entry of the protocol config: an object mapping account ids to relative
+/// weights, such as their stake or capacity. Participants without an entry weigh 1, so the slots
+/// are dealt out equally if not set.
+pub fn weights(cfg: &ProtocolConfig, participants: &Participants) -> Vec<(Participant, u64)> {
+    let configured: HashMap<AccountId, u64> = cfg
+        .presignature
+        .other
+        .get("weights")
+        .and_then(|weights| serde_json::to_value(weights).ok())
+        .and_then(|weights| serde_json::from_value(weights).ok())
+        .unwrap_or_default();
+    participants
+        .iter()
+        .map(|(p, info)| (*p, configured.get(&info.account_id).copied().unwrap_or(1)))
+        .collect()
+}
+
+/// Deals out the `total` presignature slots of the stockpile in proportion to the weights of the
+/// participants. Every participant gets the integral part of its share, and the slots left over
+/// go to the largest remainders. Ties are broken by a hash of the epoch and the participant id,
+/// so with equal weights the participants getting the extra slots rotate between epochs. Every
+/// participant computes the same assignment from the same epoch and weights.
+pub fn slot_assignment(
+    epoch: u64,
+    weights: &[(Participant, u64)],
+    total: usize,
+) -> BTreeMap<Participant, usize> {
+    let sum: u128 = weights.iter().map(|(_, weight)| *weight as u128).sum();
+    if sum == 0 {
+        return weights.iter().map(|(p, _)| (*p, 0)).collect();
+    }
+    let mut assignment = BTreeMap::new();
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (p, weight) in weights {
+        let share = total as u128 * *weight as u128;
+        assignment.insert(*p, (share / sum) as usize);
+        remainders.push((share % sum, *p));
+    }
+    // A participant with a zero weight has no remainder, and there are always more non-zero
+    // remainders than slots left over, so it never gets any slot.
+    let left = total - assignment.values().sum::<usize>();
+    remainders.sort_by_cached_key(|(remainder, p)| {
+        let p: u32 = (*p).into();
+        let mut hasher = Sha3_256::new();
+        hasher.update(epoch.to_le_bytes());
+        hasher.update(p.to_le_bytes());
+        let hash: [u8; 32] = hasher.finalize().into();
+        (
+            Reverse(*remainder),
+            u64::from_le_bytes(first_8_bytes(hash)),
+            p,
+        )
+    });
+    for (_, p) in remainders.into_iter().take(left) {
+        *assignment.entry(p).or_default() += 1;
+    }
+    assignment
+}
+
+/// Number of the `total` presignature slots of the stockpile that `me` is allowed to propose,
+/// see [`slot_assignment`].
+pub fn assigned_slots(
+    epoch: u64,
+    me: Participant,
+    weights: &[(Participant, u64)],
+    total: usize,
+) -> usize {
+    slot_assignment(epoch, weights, total)
+        .get(&me)
+        .copied()
+        .unwrap_or(0)
+}
+
 const fn first_8_bytes(input: [u8; 32]) -> [u8; 8] {
     let mut output = [0u8; 8];
     let mut i = 0;
@@ -557,3 +2703,747 @@ const fn first_8_bytes(input: [u8; 32]) -> [u8; 8] {
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::storage::presignature_storage;
+    use std::sync::Arc;
+
+    fn account_id() -> AccountId {
+        "p-0.testnet".parse().unwrap()
+    }
+
+    /// Manager of `me` with a threshold of 2 in epoch 0, keeping its presignatures in memory.
+    fn manager(me: Participant, clock: SharedClock) -> PresignatureManager {
+        let storage = presignature_storage::init(None, &account_id());
+        manager_with_storage(me, clock, Arc::new(RwLock::new(storage)))
+    }
+
+    fn manager_with_storage(
+        me: Participant,
+        clock: SharedClock,
+        storage: LockPresignatureNodeStorageBox,
+    ) -> PresignatureManager {
+        PresignatureManager::new(
+            me,
+            2,
+            0,
+            &k256::AffinePoint::GENERATOR,
+            Vec::new(),
+            storage,
+            &account_id(),
+        )
+        .with_clock(clock)
+    }
+
+    /// Presignature of participant 0 alone in `epoch`, bound to the generator as public key.
+    fn presignature(id: PresignatureId, epoch: u64) -> Presignature {
+        Presignature {
+            id,
+            output: PresignOutput {
+                big_r: k256::AffinePoint::GENERATOR,
+                k: k256::Scalar::ONE,
+                sigma: k256::Scalar::ONE,
+            },
+            participants: vec![Participant::from(0u32)],
+            created_at: 0,
+            epoch,
+            public_key_hash: public_key_hash(&k256::AffinePoint::GENERATOR),
+            triples: None,
+        }
+    }
+
+    #[test]
+    fn test_batch_size() {
+        let mut cfg = ProtocolConfig::default();
+        assert_eq!(batch_size(&cfg), 1);
+        cfg.presignature
+            .other
+            .insert("batch_size".to_string(), serde_json::json!(0).into());
+        assert_eq!(batch_size(&cfg), 1);
+        cfg.presignature
+            .other
+            .insert("batch_size".to_string(), serde_json::json!(4).into());
+        assert_eq!(batch_size(&cfg), 4);
+    }
+
+    #[test]
+    fn test_assigned_slots_cover_stockpile() {
+        let participants: Vec<(Participant, u64)> =
+            (0..5u32).map(|p| (Participant::from(p), 1)).collect();
+        for epoch in 0..10 {
+            let slots: Vec<_> = participants
+                .iter()
+                .map(|(p, _)| assigned_slots(epoch, *p, &participants, 12))
+                .collect();
+            assert_eq!(slots.iter().sum::<usize>(), 12);
+            assert!(slots.iter().all(|s| *s == 2 || *s == 3));
+        }
+        assert_eq!(
+            assigned_slots(0, Participant::from(7u32), &participants, 12),
+            0
+        );
+    }
+
+    #[test]
+    fn test_weighted_slot_assignment() {
+        let weights: Vec<(Participant, u64)> = [(0u32, 5), (1, 3), (2, 2), (3, 0)]
+            .into_iter()
+            .map(|(p, weight)| (Participant::from(p), weight))
+            .collect();
+        let assignment = slot_assignment(0, &weights, 20);
+        assert_eq!(
+            assignment.values().copied().collect::<Vec<_>>(),
+            vec![10, 6, 4, 0]
+        );
+        for epoch in 0..10 {
+            let assignment = slot_assignment(epoch, &weights, 7);
+            assert_eq!(assignment.values().sum::<usize>(), 7);
+            assert_eq!(assignment[&Participant::from(3u32)], 0);
+            // Each share is within one slot of the exact proportion.
+            assert!((3..=4).contains(&assignment[&Participant::from(0u32)]));
+        }
+    }
+
+    #[test]
+    fn test_presignature_zeroize() {
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<Presignature>();
+        assert_zeroize_on_drop::<Triple>();
+
+        let mut presignature = presignature(1, 0);
+        presignature.zeroize();
+        assert_eq!(presignature.output.k, k256::Scalar::ZERO);
+        assert_eq!(presignature.output.sigma, k256::Scalar::ZERO);
+        // The public part is left as is.
+        assert_eq!(presignature.output.big_r, k256::AffinePoint::GENERATOR);
+    }
+
+    #[test]
+    fn test_stale_key_binding() {
+        let public_key = k256::AffinePoint::GENERATOR;
+        let account_id = account_id();
+        let entry = |id, epoch, public_key_hash| {
+            let mut presignature = presignature(id, epoch);
+            presignature.created_at = Utc::now().timestamp() as u64;
+            presignature.public_key_hash = public_key_hash;
+            PresignatureData {
+                account_id: account_id.clone(),
+                presignature,
+                mine: true,
+            }
+        };
+        // Loading from storage is what is tested, hence the manager is set up by hand.
+        let mut manager = PresignatureManager::new(
+            Participant::from(0u32),
+            1,
+            1,
+            &public_key,
+            vec![
+                entry(1, 1, public_key_hash(&public_key)),
+                entry(2, 0, public_key_hash(&public_key)),
+                entry(3, 1, [0; 32]),
+            ],
+            Arc::new(RwLock::new(presignature_storage::init(None, &account_id))),
+            &account_id,
+        );
+        // Only the presignature bound to the current key and epoch is loaded.
+        assert_eq!(manager.mine.iter().copied().collect::<Vec<_>>(), vec![1]);
+
+        let stale = entry(4, 0, public_key_hash(&public_key));
+        manager.presignatures.insert(4, stale.presignature.clone());
+        manager.mine.push_front(4);
+        assert!(matches!(
+            manager.reserve(4),
+            Err(GenerationError::StaleKeyBinding { id: 4, epoch: 0 })
+        ));
+        assert!(!manager.mine.contains(&4));
+        assert!(manager.reserve(1).is_ok());
+    }
+
+    /// Protocol that never makes progress, for generators only meant to be managed.
+    struct Idle;
+    impl cait_sith::protocol::Protocol for Idle {
+        type Output = PresignOutput<Secp256k1>;
+        fn poke(&mut self) -> Result<Action<Self::Output>, cait_sith::protocol::ProtocolError> {
+            Ok(Action::Wait)
+        }
+        fn message(&mut self, _from: Participant, _data: MessageData) {}
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_presignature() {
+        let me = Participant::from(0u32);
+        let mut manager = manager(me, clock::monotonic());
+        manager.generators.insert(
+            1,
+            PresignatureGenerator::new(
+                1,
+                Box::new(Idle),
+                vec![me],
+                1,
+                2,
+                me,
+                true,
+                60_000,
+                clock::monotonic(),
+            ),
+        );
+        let manager = Arc::new(RwLock::new(manager));
+
+        // The waiter is parked while the presignature is generating, and woken up once it is.
+        let waiter = tokio::spawn({
+            let manager = manager.clone();
+            async move { PresignatureManager::wait_for(&manager, 1, Duration::from_secs(10)).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        {
+            let mut manager = manager.write().await;
+            manager.generators.remove(&1);
+            manager.insert_mine(presignature(1, 0)).await;
+        }
+        assert!(waiter.await.unwrap().is_ok());
+
+        // A presignature that never shows up times out, and a spent one fails right away.
+        assert!(matches!(
+            PresignatureManager::wait_for(&manager, 2, Duration::from_millis(10)).await,
+            Err(GenerationError::PresignatureIsMissing(2))
+        ));
+        let slot = manager.write().await.reserve(1).unwrap();
+        manager.write().await.spend(slot).await.unwrap();
+        assert!(matches!(
+            PresignatureManager::wait_for(&manager, 1, Duration::from_secs(10)).await,
+            Err(GenerationError::AlreadySpent(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expired_reservation_is_not_spent() {
+        let clock = MockClock::new();
+        let mut manager = manager(Participant::from(0u32), clock.shared());
+        for id in [1, 2, 3] {
+            manager.insert_mine(presignature(id, 0)).await;
+        }
+        let stale = [1, 2, 3].map(|id| manager.reserve(id).unwrap());
+        clock.advance(RESERVATION_TIMEOUT + Duration::from_secs(1));
+        manager.garbage_collect(&ProtocolConfig::default());
+        let [stale1, stale2, stale3] = stale;
+
+        // Once expired, the presignature can be reserved again, and the stale slot can neither
+        // spend nor release the new reservation.
+        let fresh1 = manager.reserve(1).unwrap();
+        let fresh2 = manager.reserve(2).unwrap();
+        assert!(matches!(
+            manager.spend(stale1).await,
+            Err(GenerationError::ReservationExpired(1))
+        ));
+        manager.release(stale2);
+        assert!(manager.reserved.contains_key(&1));
+        assert!(manager.reserved.contains_key(&2));
+        assert_eq!(manager.spend(fresh1).await.unwrap().id, 1);
+        assert!(manager.spent.contains(&1));
+        manager.release(fresh2);
+        assert!(manager.presignatures.contains_key(&2));
+
+        // A stale slot fails to spend even when nobody reserved the presignature since, which
+        // stays available.
+        assert!(matches!(
+            manager.spend(stale3).await,
+            Err(GenerationError::ReservationExpired(3))
+        ));
+        assert!(manager.presignatures.contains_key(&3));
+        assert!(!manager.spent.contains(&3));
+    }
+
+    /// Storage failing every deletion, such as a datastore that became unreachable.
+    struct UndeletableStorage(presignature_storage::PresignatureNodeStorageBox);
+
+    #[async_trait::async_trait]
+    impl presignature_storage::PresignatureNodeStorage for UndeletableStorage {
+        async fn insert(
+            &mut self,
+            presignature: Presignature,
+            mine: bool,
+        ) -> Result<(), error::DatastoreStorageError> {
+            self.0.insert(presignature, mine).await
+        }
+
+        async fn delete(
+            &mut self,
+            id: PresignatureId,
+            _epoch: u64,
+        ) -> Result<(), error::DatastoreStorageError> {
+            Err(error::DatastoreStorageError::FetchEntitiesError(format!(
+                "presignature {id} is unreachable"
+            )))
+        }
+
+        async fn clear(&mut self) -> Result<Vec<PresignatureData>, error::DatastoreStorageError> {
+            self.0.clear().await
+        }
+
+        async fn load(&self) -> Result<Vec<PresignatureData>, error::DatastoreStorageError> {
+            self.0.load().await
+        }
+
+        async fn migrate(&mut self) -> Result<usize, error::DatastoreStorageError> {
+            self.0.migrate().await
+        }
+
+        fn account_id(&self) -> &AccountId {
+            self.0.account_id()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spend_fails_closed() {
+        let storage: presignature_storage::PresignatureNodeStorageBox = Box::new(
+            UndeletableStorage(presignature_storage::init(None, &account_id())),
+        );
+        let storage = Arc::new(RwLock::new(storage));
+        let mut manager =
+            manager_with_storage(Participant::from(0u32), clock::monotonic(), storage.clone());
+        manager.insert_mine(presignature(1, 0)).await;
+
+        // The presignature would be loaded again after a restart, so it is not handed out but
+        // released back to the manager.
+        let slot = manager.reserve(1).unwrap();
+        assert!(matches!(
+            manager.spend(slot).await,
+            Err(GenerationError::PresignatureNotDeleted(1, _))
+        ));
+        assert!(!manager.spent.contains(&1));
+        assert!(!manager.reserved.contains_key(&1));
+        assert!(manager.presignatures.contains_key(&1));
+        assert!(manager.mine.contains(&1));
+        assert_eq!(storage.read().await.load().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_foreign_quota_eviction() {
+        let (me, proposer) = (Participant::from(0u32), Participant::from(1u32));
+        let mut manager = manager(me, clock::monotonic());
+        let now = Instant::now();
+        for (id, idle) in [(1, 30), (2, 10), (3, 20)] {
+            let mut generator = PresignatureGenerator::new(
+                id,
+                Box::new(Idle),
+                vec![me, proposer],
+                id,
+                id,
+                proposer,
+                false,
+                60_000,
+                clock::monotonic(),
+            );
+            generator.last_active = now - Duration::from_secs(idle);
+            manager.generators.insert(id, generator);
+        }
+
+        // Under quota, nothing is evicted.
+        manager.make_room_for(proposer, 4);
+        assert_eq!(manager.generators.len(), 3);
+
+        // At quota, the least recently active generators make room for one more.
+        manager.make_room_for(proposer, 2);
+        assert_eq!(manager.generators.keys().collect::<Vec<_>>(), vec![&2]);
+        assert!(manager.is_garbage_collected(&1) && manager.is_garbage_collected(&3));
+        let aborts = manager.take_aborts();
+        assert_eq!(aborts.len(), 2);
+        assert!(aborts.iter().all(|(p, _)| *p == proposer));
+    }
+
+    #[test]
+    fn test_pool_caps() {
+        use crate::protocol::contract::primitives::ParticipantInfo;
+
+        let (me, proposer) = (Participant::from(0u32), Participant::from(1u32));
+        let mut manager = manager(me, clock::monotonic()).with_caps(&scheduler::Options {
+            max_presignature_generators: Some(2),
+            ..Default::default()
+        });
+        let generator = |id| {
+            PresignatureGenerator::new(
+                id,
+                Box::new(Idle),
+                vec![me, proposer],
+                id,
+                id,
+                proposer,
+                false,
+                60_000,
+                clock::monotonic(),
+            )
+        };
+        manager.generators.insert(1, generator(1));
+        assert!(!manager.utilization().is_full());
+        manager.generators.insert(2, generator(2));
+        let pool = manager.utilization();
+        assert!(pool.is_full());
+        assert_eq!(
+            pool.to_string(),
+            "2/2 generators, 0/unbounded presignatures"
+        );
+        assert_eq!(manager.snapshot().pool, pool);
+
+        // The participants of a rejected presignature are told to drop it.
+        let mut participants = Participants::default();
+        for p in [me, proposer] {
+            participants.insert(&p, ParticipantInfo::new(u32::from(p)));
+        }
+        manager.reject_pool_full(&participants, 3, proposer, pool);
+        assert!(manager.is_garbage_collected(&3));
+        let aborts = manager.take_aborts();
+        assert_eq!(aborts.len(), 1);
+        assert_eq!(aborts[0].0, proposer);
+        assert!(aborts[0].1.reason.contains("pool is full"));
+    }
+
+    #[test]
+    fn test_sweep_timed_out() {
+        let (me, proposer) = (Participant::from(0u32), Participant::from(1u32));
+        let clock = MockClock::new();
+        let mut manager = manager(me, clock.shared());
+        for (id, then) in [(1, 110), (2, 10)] {
+            let generator = PresignatureGenerator::new(
+                id,
+                Box::new(Idle),
+                vec![me, proposer],
+                id,
+                id,
+                proposer,
+                false,
+                60_000,
+                clock.shared(),
+            );
+            manager.generators.insert(id, generator);
+            clock.advance(Duration::from_secs(then));
+        }
+
+        // Only the generator past its timeout is dropped, and its participants are let know.
+        assert_eq!(manager.sweep(), vec![1]);
+        assert_eq!(manager.generators.keys().collect::<Vec<_>>(), vec![&2]);
+        assert!(manager.is_garbage_collected(&1));
+        let aborts = manager.take_aborts();
+        assert_eq!(aborts.len(), 1);
+        assert_eq!(aborts[0].0, proposer);
+        assert!(manager.sweep().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_drops_orphaned() {
+        let participants = (0..3u32).map(Participant::from).collect::<Vec<_>>();
+        let mut manager = manager(participants[0], clock::monotonic());
+        let now = Utc::now().timestamp() as u64;
+        for (id, created_at) in [(1, now - 3600), (2, now - 3600), (3, now)] {
+            let mut presignature = presignature(id, 0);
+            presignature.participants = participants.clone();
+            presignature.created_at = created_at;
+            manager.presignatures.insert(id, presignature);
+            manager.mine.push_back(id);
+        }
+
+        // Without reports, every participant is assumed to still hold its presignatures.
+        assert!(manager.reconcile().await.is_empty());
+
+        manager.on_inventory(participants[1], vec![1]);
+        manager.on_inventory(participants[2], vec![]);
+        // Only this node still holds 2, while 3 was completed too recently to tell.
+        assert_eq!(manager.reconcile().await, vec![2]);
+        assert!(manager.is_garbage_collected(&2));
+        assert_eq!(manager.mine.iter().copied().collect::<Vec<_>>(), vec![1, 3]);
+
+        let mut all = Participants::default();
+        for p in &participants {
+            all.insert(p, crate::protocol::ParticipantInfo::new((*p).into()));
+        }
+        let reports = manager.inventory_messages(&all);
+        assert_eq!(reports.len(), 2);
+        assert!(reports
+            .iter()
+            .all(|(_, msg)| msg.presignatures == vec![1, 3]));
+    }
+
+    #[tokio::test]
+    async fn test_commitment_mismatch_quarantines() {
+        let participants = (0..3u32).map(Participant::from).collect::<Vec<_>>();
+        let mut manager = manager(participants[0], clock::monotonic());
+        for id in [1, 2] {
+            let mut presignature = presignature(id, 0);
+            presignature.participants = participants.clone();
+            presignature.created_at = Utc::now().timestamp() as u64;
+            manager.presignatures.insert(id, presignature);
+            manager.mine.push_back(id);
+        }
+
+        let agreeing = commitment(&manager.presignatures[&1]);
+        manager.on_commitment(1, participants[1], agreeing).await;
+        manager.on_commitment(1, participants[2], agreeing).await;
+        assert!(manager.presignatures.contains_key(&1));
+        assert!(!manager.commitments.contains_key(&1));
+
+        // A commitment from outside the presignature is not taken into account.
+        manager
+            .on_commitment(2, Participant::from(7u32), [1; 32])
+            .await;
+        assert!(manager.presignatures.contains_key(&2));
+
+        manager.on_commitment(2, participants[2], [1; 32]).await;
+        assert!(!manager.presignatures.contains_key(&2));
+        assert!(manager.is_garbage_collected(&2));
+        assert_eq!(manager.mine.iter().copied().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let (me, proposer) = (Participant::from(0u32), Participant::from(1u32));
+        let mut manager = manager(me, clock::monotonic());
+        for id in [3, 1] {
+            let generator = PresignatureGenerator::new(
+                id,
+                Box::new(Idle),
+                vec![me, proposer],
+                id,
+                id,
+                proposer,
+                false,
+                60_000,
+                clock::monotonic(),
+            );
+            manager.generators.insert(id, generator);
+        }
+        manager.checked_out.insert(
+            2,
+            CheckedOutGenerator {
+                participants: vec![me, proposer],
+                proposer: me,
+                mine: true,
+            },
+        );
+
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.epoch, 0);
+        assert!(snapshot.presignatures.is_empty());
+        let ids = snapshot.generators.iter().map(|g| g.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+        assert!(snapshot.generators.iter().all(|g| !g.mine));
+        assert_eq!(snapshot.checked_out, 1);
+
+        // Aborts are checked against the participants of checked out generators as well.
+        assert_eq!(manager.participants_of(3), Some(&[me, proposer][..]));
+        assert_eq!(manager.participants_of(2), Some(&[me, proposer][..]));
+        assert_eq!(manager.participants_of(4), None);
+
+        // The snapshot is detached from the manager and can be sent around as is.
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PresignatureSnapshot>(&json).unwrap(),
+            snapshot
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_checked_out() {
+        let (me, other) = (Participant::from(0u32), Participant::from(1u32));
+        let mut manager = manager(me, clock::monotonic());
+        let checked_out = manager.checkout();
+        assert!(checked_out.is_empty());
+
+        // A generator being poked still counts towards the stockpile and cannot be reserved.
+        manager.checked_out.insert(
+            7,
+            CheckedOutGenerator {
+                participants: vec![me, other],
+                proposer: me,
+                mine: true,
+            },
+        );
+        assert_eq!(manager.potential_len(), 1);
+        assert!(matches!(
+            manager.reserve(7),
+            Err(GenerationError::PresignatureIsGenerating(7))
+        ));
+
+        // Cancelling it while poked lets the other participants know right away, and drops it
+        // once checked back in.
+        let aborts = manager.cancel(7, "test");
+        assert_eq!(aborts.len(), 1);
+        assert_eq!(aborts[0].0, other);
+        assert!(manager.checked_out.is_empty());
+        assert!(manager.is_garbage_collected(&7));
+        let outcome = manager.checkin(checked_out.poke(usize::MAX)).await;
+        assert!(outcome.messages.is_empty());
+        assert_eq!(manager.potential_len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_triple_notice() {
+        let (me, other, third) = (
+            Participant::from(0u32),
+            Participant::from(1u32),
+            Participant::from(2u32),
+        );
+        let mut manager = manager(me, clock::monotonic());
+
+        // The proposer is only told once it kept failing us with the same triple.
+        for _ in 1..MISSING_TRIPLE_NACK_THRESHOLD {
+            assert!(!manager.on_missing_triple(7, other, 42));
+        }
+        assert!(!manager.on_missing_triple(7, other, 43));
+        assert!(manager.take_nacks().is_empty());
+        assert!(manager.on_missing_triple(7, other, 42));
+        let nacks = manager.take_nacks();
+        assert_eq!(nacks.len(), 1);
+        assert_eq!(nacks[0].0, other);
+        assert_eq!((nacks[0].1.id, nacks[0].1.triple_id), (7, 42));
+        assert!(manager.is_garbage_collected(&7));
+
+        // As the proposer, the presignature is cancelled on notice of one of its participants.
+        manager.checked_out.insert(
+            8,
+            CheckedOutGenerator {
+                participants: vec![me, other],
+                proposer: me,
+                mine: true,
+            },
+        );
+        manager.on_unknown_triple(8, third, 42);
+        assert_eq!(manager.checked_out.len(), 1);
+        manager.on_unknown_triple(8, other, 42);
+        assert!(manager.checked_out.is_empty());
+        assert!(manager.is_garbage_collected(&8));
+        assert_eq!(manager.take_aborts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_removed_participant_cancels_generators() {
+        use crate::protocol::contract::primitives::ParticipantInfo;
+        use crate::storage::triple_storage;
+
+        let account_id = account_id();
+        let me = Participant::from(0u32);
+        let mut participants = Participants::default();
+        for p in 0..3u32 {
+            participants.insert(&Participant::from(p), ParticipantInfo::new(p));
+        }
+        let mut remaining = participants.clone();
+        remaining.participants.remove(&Participant::from(2u32));
+
+        let mut triple_manager = TripleManager::new(
+            me,
+            2,
+            0,
+            Vec::new(),
+            Arc::new(RwLock::new(triple_storage::init(None, &account_id))),
+            &account_id,
+        );
+        triple_manager.generate(&participants, 60_000).unwrap();
+        triple_manager.generate(&remaining, 60_000).unwrap();
+        assert_eq!(triple_manager.update_participants(&remaining), 1);
+        assert_eq!(triple_manager.generators.len(), 1);
+        assert!(triple_manager
+            .generators
+            .values()
+            .all(|generator| generator.participants.len() == 2));
+
+        let public_key = k256::AffinePoint::GENERATOR;
+        let mut manager = manager(me, clock::monotonic());
+        let triple = |id, participants: &Participants| {
+            let (public, mut shares) = cait_sith::triples::deal::<Secp256k1>(
+                &mut rand::thread_rng(),
+                &participants.keys_vec(),
+                2,
+            );
+            Triple {
+                id,
+                share: shares.remove(0),
+                public,
+            }
+        };
+        for (id, participants) in [(1, &participants), (3, &remaining)] {
+            manager
+                .generate(
+                    participants,
+                    triple(id, participants),
+                    triple(id + 1, participants),
+                    &public_key,
+                    &k256::Scalar::ONE,
+                    60_000,
+                )
+                .unwrap();
+        }
+        let removed = hash_as_id(0, 1, 2, me);
+        let kept = hash_as_id(0, 3, 4, me);
+
+        // Only the remaining participant of the cancelled generator is let know.
+        let aborts = manager.update_participants(&remaining);
+        assert_eq!(
+            aborts.iter().map(|(p, _)| *p).collect::<Vec<_>>(),
+            vec![Participant::from(1u32)]
+        );
+        assert_eq!(aborts[0].1.id, removed);
+        assert_eq!(
+            manager.generators.keys().copied().collect::<Vec<_>>(),
+            vec![kept]
+        );
+        assert!(!manager.introduced.contains(&removed));
+        assert!(manager.is_garbage_collected(&removed));
+    }
+
+    #[tokio::test]
+    async fn test_id_mismatch() {
+        use crate::protocol::contract::primitives::ParticipantInfo;
+        use crate::storage::triple_storage;
+
+        let account_id = account_id();
+        let me = Participant::from(0u32);
+        let proposer = Participant::from(1u32);
+        let mut participants = Participants::default();
+        for p in 0..2u32 {
+            participants.insert(&Participant::from(p), ParticipantInfo::new(p));
+        }
+        let mut triple_manager = TripleManager::new(
+            me,
+            2,
+            0,
+            Vec::new(),
+            Arc::new(RwLock::new(triple_storage::init(None, &account_id))),
+            &account_id,
+        );
+        let public_key = k256::AffinePoint::GENERATOR;
+        let mut manager = manager(me, clock::monotonic());
+
+        // An id advertised for other triples, another proposer or another epoch is rejected
+        // before anything gets looked up or started.
+        let expected = hash_as_id(0, 1, 2, proposer);
+        for id in [
+            hash_as_id(0, 1, 3, proposer),
+            hash_as_id(0, 1, 2, me),
+            hash_as_id(1, 1, 2, proposer),
+        ] {
+            let result = manager
+                .get_or_generate(
+                    &participants,
+                    id,
+                    1,
+                    2,
+                    proposer,
+                    &mut triple_manager,
+                    &public_key,
+                    &k256::Scalar::ONE,
+                    &ProtocolConfig::default(),
+                )
+                .await;
+            assert!(matches!(
+                result,
+                Err(GenerationError::IdMismatch { id: got, expected: want })
+                    if got == id && want == expected
+            ));
+        }
+        assert!(manager.generators.is_empty());
+        assert!(!manager.is_garbage_collected(&expected));
+    }
+}
@@ -0,0 +1,365 @@
+//! Publishes the signatures generated by this node back on chain by calling `respond` on the
+//! contract. Responses are batched into a single transaction where possible, which also keeps the
+//! node from racing itself for the nonce of its access key.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use cait_sith::FullSignature;
+use chrono::Utc;
+use crypto_shared::PublicKey;
+use k256::Secp256k1;
+use mpc_contract::primitives::SignatureRequest;
+use near_account_id::AccountId;
+use near_fetch::ops::Function;
+use near_fetch::signer::SignerExt;
+use near_primitives::types::Gas;
+
+use crate::audit::{self, Lineage};
+use crate::events::{self, NodeEvent};
+use crate::kdf::{cached_key, into_eth_sig};
+use crate::storage::sign_request_storage::SignRequestState;
+use crate::util::AffinePointExt;
+
+use super::signature::ReceiptId;
+
+pub const MAX_RETRY: u8 = 10;
+
+/// Maximum number of responses published in a single transaction.
+const MAX_BATCH_SIZE: usize = 8;
+
+/// Gas attached to a transaction, split between the responses in it.
+const MAX_TRANSACTION_GAS: Gas = 300_000_000_000_000;
+
+/// Delay before publishing again after the network reported congestion. It doubles with every
+/// congested attempt, up to [`MAX_CONGESTION_BACKOFF`].
+const BASE_CONGESTION_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_CONGESTION_BACKOFF: Duration = Duration::from_secs(30);
+
+pub struct ToPublish {
+    receipt_id: ReceiptId,
+    request: SignatureRequest,
+    time_added: Instant,
+    signature: FullSignature<Secp256k1>,
+    retry_count: u8,
+    /// Publish this response in a transaction of its own, because a batch it was part of failed
+    /// on the contract side and we cannot tell which response caused it.
+    alone: bool,
+    /// Receipts of the requests for the same signature merged into this one. The contract holds
+    /// a single pending request per signature, so the one response answers all of them.
+    merged: Vec<ReceiptId>,
+    /// Recorded in the audit trail once published.
+    lineage: Option<Lineage>,
+}
+
+impl ToPublish {
+    pub fn new(
+        receipt_id: ReceiptId,
+        request: SignatureRequest,
+        time_added: Instant,
+        signature: FullSignature<Secp256k1>,
+    ) -> ToPublish {
+        ToPublish {
+            receipt_id,
+            request,
+            time_added,
+            signature,
+            retry_count: 0,
+            alone: false,
+            merged: Vec::new(),
+            lineage: None,
+        }
+    }
+
+    /// Also answers the receipts of the requests merged into this one.
+    pub fn with_merged(mut self, merged: Vec<ReceiptId>) -> Self {
+        self.merged = merged;
+        self
+    }
+
+    pub fn with_lineage(mut self, lineage: Lineage) -> Self {
+        self.lineage = Some(lineage);
+        self
+    }
+}
+
+fn record_lineage(to_publish: &ToPublish) {
+    let Some(lineage) = &to_publish.lineage else {
+        return;
+    };
+    audit::record(&audit::Record {
+        timestamp: Utc::now().timestamp_millis(),
+        receipt_id: to_publish.receipt_id.to_string(),
+        merged: to_publish.merged.iter().map(ToString::to_string).collect(),
+        request_hash: hex::encode(to_publish.request.payload_hash.scalar.to_bytes()),
+        big_r: to_publish.signature.big_r.to_base58(),
+        lineage: lineage.clone(),
+    });
+}
+
+fn settle(
+    settled: &mut Vec<(ReceiptId, SignRequestState)>,
+    to_publish: &ToPublish,
+    state: SignRequestState,
+) {
+    for receipt_id in std::iter::once(&to_publish.receipt_id).chain(&to_publish.merged) {
+        settled.push((*receipt_id, state.clone()));
+    }
+}
+
+/// Why a transaction could not be published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PublishFailure {
+    /// Another transaction of ours used the nonce first. The client refreshes the nonce of the
+    /// access key after such an error, so the transaction can be sent again right away.
+    NonceConflict,
+    /// The network or the RPC node is overloaded, retrying right away would make it worse.
+    Congestion,
+    Other,
+}
+
+impl PublishFailure {
+    fn classify(err: &near_fetch::Error) -> Self {
+        let err = err.to_string();
+        if err.contains("InvalidNonce") || err.contains("nonce") {
+            PublishFailure::NonceConflict
+        } else if ["ShardCongested", "ShardStuck", "TooManyRequests", "timeout"]
+            .iter()
+            .any(|pattern| err.contains(pattern))
+        {
+            PublishFailure::Congestion
+        } else {
+            PublishFailure::Other
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Publisher {
+    queue: VecDeque<ToPublish>,
+    /// Number of consecutive publish attempts that ran into congestion.
+    congested: u32,
+    /// Publishing is held back until then after congestion.
+    retry_at: Option<Instant>,
+    /// Requests that were answered or given up on, see [`Publisher::take_settled`].
+    settled: Vec<(ReceiptId, SignRequestState)>,
+}
+
+impl Publisher {
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn push(&mut self, to_publish: ToPublish) {
+        self.queue.push_back(to_publish);
+    }
+
+    /// Takes the final states of the requests settled since the last call.
+    pub fn take_settled(&mut self) -> Vec<(ReceiptId, SignRequestState)> {
+        std::mem::take(&mut self.settled)
+    }
+
+    fn settle(&mut self, to_publish: &ToPublish, state: SignRequestState) {
+        settle(&mut self.settled, to_publish, state);
+    }
+
+    /// Takes the responses that go into the next transaction.
+    fn next_batch(&mut self) -> Vec<ToPublish> {
+        let Some(first) = self.queue.pop_front() else {
+            return Vec::new();
+        };
+        if first.alone {
+            return vec![first];
+        }
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH_SIZE {
+            match self.queue.front() {
+                Some(next) if !next.alone => batch.extend(self.queue.pop_front()),
+                _ => break,
+            }
+        }
+        batch
+    }
+
+    /// Publishes all the queued responses. Responses that fail to be published are queued again,
+    /// unless they have been retried [`MAX_RETRY`] times already.
+    pub async fn publish<T: SignerExt>(
+        &mut self,
+        rpc_client: &near_fetch::Client,
+        signer: &T,
+        mpc_contract_id: &AccountId,
+        public_key: PublicKey,
+        my_account_id: &AccountId,
+    ) {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| retry_at > Instant::now())
+        {
+            return;
+        }
+        self.retry_at = None;
+
+        let mut to_retry = Vec::new();
+        while !self.queue.is_empty() {
+            let mut batch = self.next_batch();
+            let mut calls = Vec::with_capacity(batch.len());
+            let settled = &mut self.settled;
+            batch.retain(|to_publish| {
+                let ToPublish {
+                    receipt_id,
+                    request,
+                    signature,
+                    ..
+                } = to_publish;
+                let expected_public_key = cached_key(public_key, request.epsilon.scalar);
+                // We do this here, rather than on the client side, so we can use the ecrecover system function on NEAR to validate our signature
+                let Ok(signature) = into_eth_sig(
+                    &expected_public_key,
+                    &signature.big_r,
+                    &signature.s,
+                    request.payload_hash.scalar,
+                ) else {
+                    tracing::error!(%receipt_id, "Failed to generate a recovery ID");
+                    settle(
+                        settled,
+                        to_publish,
+                        SignRequestState::Failed {
+                            reason: "failed to generate a recovery id".to_string(),
+                        },
+                    );
+                    return false;
+                };
+                calls.push(serde_json::json!({
+                    "request": request,
+                    "response": signature,
+                }));
+                true
+            });
+            if batch.is_empty() {
+                continue;
+            }
+
+            let gas = MAX_TRANSACTION_GAS / batch.len() as Gas;
+            let mut tx = rpc_client.batch(signer, mpc_contract_id);
+            for args in calls {
+                tx = tx.call(Function::new("respond").args_json(args).gas(gas));
+            }
+            let start = Instant::now();
+            let outcome = tx.retry_exponential(10, 5).transact().await;
+            crate::metrics::SIGNATURE_PUBLISH_LATENCY
+                .with_label_values(&[my_account_id.as_str()])
+                .observe(start.elapsed().as_secs_f64());
+
+            let response = match outcome {
+                Ok(response) => response,
+                Err(err) => {
+                    let failure = PublishFailure::classify(&err);
+                    let receipt_ids = batch.iter().map(|p| p.receipt_id).collect::<Vec<_>>();
+                    tracing::error!(?receipt_ids, ?failure, error = ?err, "Failed to publish the signatures");
+                    crate::metrics::SIGNATURE_PUBLISH_FAILURES
+                        .with_label_values(&[my_account_id.as_str()])
+                        .inc();
+                    match failure {
+                        PublishFailure::NonceConflict => {
+                            // Not the fault of the responses, send them again with a fresh nonce.
+                            to_retry.extend(batch);
+                        }
+                        PublishFailure::Congestion => {
+                            self.congested += 1;
+                            let backoff = BASE_CONGESTION_BACKOFF
+                                .saturating_mul(2u32.saturating_pow(self.congested - 1))
+                                .min(MAX_CONGESTION_BACKOFF);
+                            self.retry_at = Some(Instant::now() + backoff);
+                            to_retry.extend(batch);
+                            break;
+                        }
+                        PublishFailure::Other => {
+                            // Push the response to the back of the queue if it hasn't been retried the max number of times
+                            for mut to_publish in batch {
+                                if to_publish.retry_count < MAX_RETRY {
+                                    to_publish.retry_count += 1;
+                                    to_retry.push(to_publish);
+                                } else {
+                                    self.settle(
+                                        &to_publish,
+                                        SignRequestState::Failed {
+                                            reason: format!("failed to publish: {err}"),
+                                        },
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+            self.congested = 0;
+
+            if let Err(err) = response.json::<()>() {
+                if batch.len() > 1 {
+                    // The whole batch gets reverted when one of the responses fails. Publish them
+                    // one by one to find out which one it was.
+                    tracing::warn!(batch = batch.len(), error = ?err, "smart contract rejected batched responses, retrying them one by one");
+                    to_retry.extend(batch.into_iter().map(|mut to_publish| {
+                        to_publish.alone = true;
+                        to_publish
+                    }));
+                } else {
+                    let receipt_ids = batch.iter().map(|p| p.receipt_id).collect::<Vec<_>>();
+                    tracing::error!(?receipt_ids, error = ?err, "smart contract threw error");
+                    crate::metrics::SIGNATURE_PUBLISH_RESPONSE_ERRORS
+                        .with_label_values(&[my_account_id.as_str()])
+                        .inc();
+                    for to_publish in &batch {
+                        self.settle(
+                            to_publish,
+                            SignRequestState::Failed {
+                                reason: format!("smart contract threw error: {err}"),
+                            },
+                        );
+                    }
+                }
+                continue;
+            }
+
+            for to_publish in &batch {
+                self.settle(to_publish, SignRequestState::Completed);
+                record_lineage(to_publish);
+            }
+            for ToPublish {
+                receipt_id,
+                time_added,
+                signature,
+                merged,
+                ..
+            } in batch
+            {
+                tracing::info!(%receipt_id, ?merged, bi_r = signature.big_r.to_base58(), s = ?signature.s, "published signature sucessfully");
+                for receipt_id in std::iter::once(receipt_id).chain(merged) {
+                    events::emit(NodeEvent::SignaturePublished {
+                        receipt_id,
+                        latency_ms: time_added.elapsed().as_millis() as u64,
+                    });
+                    crate::metrics::NUM_SIGN_SUCCESS
+                        .with_label_values(&[my_account_id.as_str()])
+                        .inc();
+                    crate::metrics::SIGN_LATENCY
+                        .with_label_values(&[my_account_id.as_str()])
+                        .observe(time_added.elapsed().as_secs_f64());
+                    crate::slo::record(time_added.elapsed());
+                    if time_added.elapsed().as_secs() <= 30 {
+                        crate::metrics::NUM_SIGN_SUCCESS_30S
+                            .with_label_values(&[my_account_id.as_str()])
+                            .inc();
+                    }
+                }
+            }
+        }
+        // Put the failed requests at the back of the queue
+        self.queue.extend(to_retry);
+    }
+}
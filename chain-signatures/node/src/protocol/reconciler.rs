@@ -0,0 +1,55 @@
+//! Periodically reconciles the presignature pools of the participants. Every node reports the
+//! unspent presignatures it holds to the others, and drops the presignatures that fewer than
+//! `threshold` of their participants still hold, e.g. because the protocol completed on our side
+//! but timed out on theirs. Signing with such a presignature is bound to fail.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+
+use super::cryptography::CryptographicError;
+use super::state::{NodeState, RunningState};
+use super::MpcMessage;
+
+/// How often the unspent presignatures are reported to the other participants.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Reconciles the presignatures of the running state every [`RECONCILE_INTERVAL`].
+pub async fn run(state: Arc<RwLock<NodeState>>) {
+    let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let running = match &*state.read().await {
+            NodeState::Running(running) => running.clone(),
+            _ => continue,
+        };
+        if let Err(err) = reconcile(&running).await {
+            tracing::warn!(?err, "reconciler: failed to report presignatures");
+        }
+    }
+}
+
+/// Drops the presignatures held by too few participants according to their latest reports, then
+/// queues the report of our own unspent presignatures for the other participants.
+pub async fn reconcile(state: &RunningState) -> Result<(), CryptographicError> {
+    let mut presignature_manager = state.presignature_manager.write().await;
+    let dropped = presignature_manager.reconcile().await;
+    let reports = presignature_manager.inventory_messages(&state.participants);
+    drop(presignature_manager);
+
+    if !dropped.is_empty() {
+        tracing::info!(
+            ?dropped,
+            "reconciler: dropped presignatures held by too few participants"
+        );
+    }
+
+    let mut messages = state.messages.write().await;
+    for (p, msg) in reports {
+        let info = state.fetch_participant(&p)?;
+        messages.push(info.clone(), MpcMessage::Reconcile(msg));
+    }
+    Ok(())
+}
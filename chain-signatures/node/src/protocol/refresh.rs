@@ -0,0 +1,395 @@
+//! Proactive refresh of the key shares. On the schedule set by the `refresh_interval_secs` entry
+//! of the protocol config, the participants of the epoch re-randomize their shares among
+//! themselves by running the resharing protocol from and to the same participants and threshold.
+//! The public key and the participant set stay the same, but shares from before a refresh cannot
+//! be combined with shares from after it, so a stolen share is only of use until the next refresh.
+//!
+//! The schedule is made of rounds of UNIX time, such that the participants start the refresh of a
+//! round at about the same time without having to agree on it first. A participant whose clock
+//! lags behind starts as soon as it receives a message of the next round. The refreshed share only
+//! replaces ours once every participant completed the refresh, otherwise it is dropped at the
+//! timeout and the shares stay as they are until the next round. While refreshing, no presignature
+//! is started or joined, and the presignatures of the previous shares are discarded once the
+//! refreshed share is in use.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use cait_sith::protocol::{Action, InitializationError, MessageData, Participant, Protocol};
+use crypto_shared::PublicKey;
+use k256::Secp256k1;
+use mpc_contract::config::ProtocolConfig;
+use near_account_id::AccountId;
+use zeroize::Zeroizing;
+
+use super::contract::primitives::Participants;
+use super::message::RefreshMessage;
+use crate::clock::{self, SharedClock};
+use crate::events::{self, NodeEvent};
+use crate::types::SecretKeyShare;
+
+/// Time given to all participants to complete the refresh of a round.
+pub const REFRESH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Messages of rounds not started yet that are kept around, the rest is dropped.
+const MAX_EARLY_MESSAGES: usize = 1024;
+
+static REFRESHING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the key share of this node is being refreshed.
+pub fn is_refreshing() -> bool {
+    REFRESHING.load(Ordering::Relaxed)
+}
+
+/// Seconds between two refreshes of the key shares, if they are refreshed at all.
+pub fn interval(cfg: &ProtocolConfig) -> Option<u64> {
+    let interval = cfg.other.get("refresh_interval_secs")?;
+    let interval = serde_json::to_value(interval).ok()?.as_u64()?;
+    (interval > 0).then_some(interval)
+}
+
+/// Round of the schedule the given UNIX time falls in.
+pub fn round(now_secs: u64, interval_secs: u64) -> u64 {
+    now_secs / interval_secs
+}
+
+type RefreshProtocol = Box<dyn Protocol<Output = SecretKeyShare> + Send + Sync>;
+
+struct Refresh {
+    round: u64,
+    protocol: RefreshProtocol,
+    started_at: Instant,
+    /// Our refreshed share, once the protocol returned on our side.
+    share: Option<Zeroizing<SecretKeyShare>>,
+    /// Participants that let us know they completed the refresh.
+    completed: HashSet<Participant>,
+}
+
+impl Refresh {
+    fn message(&mut self, msg: RefreshMessage) {
+        if msg.completed {
+            self.completed.insert(msg.from);
+        } else if self.share.is_none() {
+            self.protocol.message(msg.from, msg.data);
+        }
+    }
+}
+
+/// What came out of [`Refresher::progress`].
+#[derive(Default)]
+pub struct RefreshStep {
+    pub messages: Vec<(Participant, RefreshMessage)>,
+    /// Round and refreshed share, completed by every participant. It has to be persisted before
+    /// replacing ours, after which the refresh is finalized with [`Refresher::commit`].
+    pub ready: Option<(u64, SecretKeyShare)>,
+}
+
+/// Drives the refreshes of the key share of this node within an epoch.
+pub struct Refresher {
+    me: Participant,
+    epoch: u64,
+    participants: Vec<Participant>,
+    threshold: usize,
+    public_key: PublicKey,
+    my_account_id: AccountId,
+    /// Last round of the schedule that was refreshed or seen at startup.
+    last_round: Option<u64>,
+    ongoing: Option<Refresh>,
+    early: Vec<RefreshMessage>,
+    clock: SharedClock,
+}
+
+impl Refresher {
+    pub fn new(
+        me: Participant,
+        epoch: u64,
+        participants: &Participants,
+        threshold: usize,
+        public_key: PublicKey,
+        my_account_id: &AccountId,
+    ) -> Self {
+        // A refresh left ongoing by the previous epoch is dropped with it.
+        REFRESHING.store(false, Ordering::Relaxed);
+        Self {
+            me,
+            epoch,
+            participants: participants.keys_vec(),
+            threshold,
+            public_key,
+            my_account_id: my_account_id.clone(),
+            last_round: None,
+            ongoing: None,
+            early: Vec::new(),
+            clock: clock::monotonic(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn is_ongoing(&self) -> bool {
+        self.ongoing.is_some()
+    }
+
+    pub fn message(&mut self, msg: RefreshMessage) {
+        if msg.epoch != self.epoch || !self.participants.contains(&msg.from) {
+            return;
+        }
+        match &mut self.ongoing {
+            Some(refresh) if refresh.round == msg.round => refresh.message(msg),
+            _ if self.last_round.map_or(true, |last| msg.round > last) => {
+                if self.early.len() < MAX_EARLY_MESSAGES {
+                    self.early.push(msg);
+                } else {
+                    tracing::warn!(round = msg.round, from = ?msg.from, "too many early share refresh messages, dropping");
+                }
+            }
+            _ => {
+                tracing::debug!(round = msg.round, from = ?msg.from, "dropping message of a past share refresh");
+            }
+        }
+    }
+
+    /// Starts the refresh of the current round of the schedule when due, and advances the ongoing
+    /// one. A refresh only starts once every participant is active, since all of them are needed
+    /// to complete it.
+    pub fn progress(
+        &mut self,
+        private_share: &SecretKeyShare,
+        active: &Participants,
+        cfg: &ProtocolConfig,
+    ) -> RefreshStep {
+        if self.ongoing.is_none() {
+            let Some(interval) = interval(cfg) else {
+                self.last_round = None;
+                self.early.clear();
+                return RefreshStep::default();
+            };
+            let now = chrono::Utc::now().timestamp().max(0) as u64;
+            let current = round(now, interval);
+            let Some(last) = self.last_round else {
+                // Refreshing right away at startup would refresh on every restart, so the first
+                // refresh happens in the next round.
+                self.last_round = Some(current);
+                return RefreshStep::default();
+            };
+            // Messages of rounds further ahead than clock skew can explain are not trusted to
+            // start a refresh.
+            self.early
+                .retain(|msg| msg.round > last && msg.round <= current + 1);
+            let due = self
+                .early
+                .iter()
+                .map(|msg| msg.round)
+                .max()
+                .unwrap_or(current)
+                .max(current);
+            if due <= last {
+                return RefreshStep::default();
+            }
+            if let Some(missing) = self
+                .participants
+                .iter()
+                .find(|p| **p != self.me && !active.contains_key(p))
+            {
+                tracing::debug!(
+                    round = due,
+                    ?missing,
+                    "waiting for all participants to refresh the key share"
+                );
+                return RefreshStep::default();
+            }
+            if let Err(err) = self.start(due, private_share) {
+                self.last_round = Some(due);
+                self.failed(due, format!("failed to start: {err:?}"));
+                return RefreshStep::default();
+            }
+        }
+
+        let Some(refresh) = &mut self.ongoing else {
+            return RefreshStep::default();
+        };
+        let round = refresh.round;
+        if self.clock.elapsed(refresh.started_at) > REFRESH_TIMEOUT {
+            let missing: Vec<_> = self
+                .participants
+                .iter()
+                .filter(|p| !refresh.completed.contains(p))
+                .collect();
+            let reason = format!("timed out waiting for {missing:?}");
+            self.ongoing = None;
+            self.failed(round, reason);
+            return RefreshStep::default();
+        }
+
+        let (epoch, me) = (self.epoch, self.me);
+        let mut step = RefreshStep::default();
+        while refresh.share.is_none() {
+            let action = match refresh.protocol.poke() {
+                Ok(action) => action,
+                Err(err) => {
+                    self.ongoing = None;
+                    self.failed(round, format!("{err:?}"));
+                    return step;
+                }
+            };
+            match action {
+                Action::Wait => break,
+                Action::SendMany(data) => {
+                    for p in self.participants.iter().filter(|p| **p != me) {
+                        let msg = refresh_message(epoch, round, me, false, data.clone());
+                        step.messages.push((*p, msg));
+                    }
+                }
+                Action::SendPrivate(to, data) => {
+                    let msg = refresh_message(epoch, round, me, false, data);
+                    step.messages.push((to, msg));
+                }
+                Action::Return(share) => {
+                    tracing::info!(
+                        round,
+                        "refreshed the key share, waiting for the other participants"
+                    );
+                    refresh.share = Some(Zeroizing::new(share));
+                    refresh.completed.insert(me);
+                    for p in self.participants.iter().filter(|p| **p != me) {
+                        let msg = refresh_message(epoch, round, me, true, Vec::new());
+                        step.messages.push((*p, msg));
+                    }
+                }
+            }
+        }
+
+        if let Some(share) = &refresh.share {
+            if self
+                .participants
+                .iter()
+                .all(|p| refresh.completed.contains(p))
+            {
+                step.ready = Some((round, **share));
+            }
+        }
+        step
+    }
+
+    /// Finalizes the refresh whose share was returned by [`Refresher::progress`], once it is
+    /// persisted and in use.
+    pub fn commit(&mut self) {
+        let Some(refresh) = self.ongoing.take() else {
+            return;
+        };
+        REFRESHING.store(false, Ordering::Relaxed);
+        crate::metrics::SHARE_REFRESHES
+            .with_label_values(&[self.my_account_id.as_str(), "completed"])
+            .inc();
+        crate::metrics::SHARE_REFRESH_ACTIVE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .set(0);
+        tracing::info!(
+            epoch = self.epoch,
+            round = refresh.round,
+            "key share refreshed"
+        );
+        events::emit(NodeEvent::ShareRefreshed {
+            epoch: self.epoch,
+            round: refresh.round,
+        });
+    }
+
+    fn start(
+        &mut self,
+        round: u64,
+        private_share: &SecretKeyShare,
+    ) -> Result<(), InitializationError> {
+        let protocol = cait_sith::reshare::<Secp256k1>(
+            &self.participants,
+            self.threshold,
+            &self.participants,
+            self.threshold,
+            self.me,
+            Some(*private_share),
+            self.public_key,
+        )?;
+        let mut refresh = Refresh {
+            round,
+            protocol: Box::new(protocol),
+            started_at: self.clock.now(),
+            share: None,
+            completed: HashSet::new(),
+        };
+        let (current, later) = std::mem::take(&mut self.early)
+            .into_iter()
+            .partition::<Vec<_>, _>(|msg| msg.round == round);
+        self.early = later;
+        for msg in current {
+            refresh.message(msg);
+        }
+
+        tracing::info!(epoch = self.epoch, round, "refreshing the key share");
+        self.ongoing = Some(refresh);
+        self.last_round = Some(round);
+        REFRESHING.store(true, Ordering::Relaxed);
+        crate::metrics::SHARE_REFRESH_ACTIVE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .set(1);
+        Ok(())
+    }
+
+    fn failed(&self, round: u64, reason: String) {
+        REFRESHING.store(false, Ordering::Relaxed);
+        crate::metrics::SHARE_REFRESHES
+            .with_label_values(&[self.my_account_id.as_str(), "failed"])
+            .inc();
+        crate::metrics::SHARE_REFRESH_ACTIVE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .set(0);
+        tracing::warn!(epoch = self.epoch, round, %reason, "failed to refresh the key share, keeping the current one");
+        events::emit(NodeEvent::ShareRefreshFailed {
+            epoch: self.epoch,
+            round,
+            reason,
+        });
+    }
+}
+
+fn refresh_message(
+    epoch: u64,
+    round: u64,
+    from: Participant,
+    completed: bool,
+    data: MessageData,
+) -> RefreshMessage {
+    RefreshMessage {
+        epoch,
+        round,
+        from,
+        completed,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_interval() {
+        let mut cfg = ProtocolConfig::default();
+        assert_eq!(interval(&cfg), None);
+        cfg.other.insert(
+            "refresh_interval_secs".to_string(),
+            serde_json::json!(0).into(),
+        );
+        assert_eq!(interval(&cfg), None);
+        cfg.other.insert(
+            "refresh_interval_secs".to_string(),
+            serde_json::json!(3600).into(),
+        );
+        assert_eq!(interval(&cfg), Some(3600));
+        assert_eq!(round(7199, 3600), 1);
+        assert_eq!(round(7200, 3600), 2);
+    }
+}
@@ -0,0 +1,248 @@
+//! Registry of the participants known to this node, along with what is known about each of them:
+//! the contract entry (account id, URL and keys of the transport), the commitment to its key share
+//! and whether it is reachable. The registry is shared between the mesh, which keeps it up to date
+//! with the contract and the latest round of pings, and the managers, which consult it to validate
+//! the protocols proposed by other participants.
+//!
+//! Participants that leave the contract are kept around as [`ParticipantStatus::Removed`], such
+//! that their late proposals are told apart from the ones of participants never seen before.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use cait_sith::protocol::Participant;
+use crypto_shared::PublicKey;
+use k256::ProjectivePoint;
+use near_account_id::AccountId;
+use serde::{Deserialize, Serialize};
+
+use super::contract::primitives::{ParticipantInfo, Participants};
+use super::ProtocolState;
+use crate::types::SecretKeyShare;
+
+pub type SharedRegistry = Arc<RwLock<ParticipantRegistry>>;
+
+/// Reads the shared registry. Every update leaves the registry consistent, so it is still read
+/// after a writer panicked.
+pub fn read(registry: &SharedRegistry) -> RwLockReadGuard<'_, ParticipantRegistry> {
+    registry
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn write(registry: &SharedRegistry) -> RwLockWriteGuard<'_, ParticipantRegistry> {
+    registry
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Commitment to a key share, the public share of the participant holding it.
+pub fn share_commitment(share: &SecretKeyShare) -> PublicKey {
+    (ProjectivePoint::GENERATOR * share).to_affine()
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantStatus {
+    /// Responded to the latest ping.
+    Active,
+    /// Part of the contract but did not respond to the latest ping, or was not pinged yet.
+    Unreachable,
+    /// No longer part of the contract.
+    Removed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RegisteredParticipant {
+    /// Entry of the participant in the contract.
+    pub info: ParticipantInfo,
+    /// Commitment to the key share of the participant, see [`share_commitment`], once known.
+    pub share_commitment: Option<PublicKey>,
+    pub status: ParticipantStatus,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RegistryError {
+    #[error("participant {0:?} is unknown")]
+    Unknown(Participant),
+    #[error("participant {0:?} was removed from the contract")]
+    Removed(Participant),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParticipantRegistry {
+    participants: BTreeMap<Participant, RegisteredParticipant>,
+}
+
+impl ParticipantRegistry {
+    pub fn shared() -> SharedRegistry {
+        Arc::new(RwLock::new(Self::default()))
+    }
+
+    /// Registers the participants of the contract state. Participants that are no longer part of
+    /// it are marked as removed, and the ones whose entry changed lose their share commitment.
+    pub fn update(&mut self, contract_state: &ProtocolState) {
+        let participants = match contract_state {
+            ProtocolState::Initializing(contract_state) => {
+                Participants::from(contract_state.candidates.clone())
+            }
+            ProtocolState::Running(contract_state) => contract_state.participants.clone(),
+            ProtocolState::Resharing(contract_state) => contract_state
+                .old_participants
+                .and(&contract_state.new_participants),
+        };
+        self.set_participants(&participants);
+    }
+
+    pub fn set_participants(&mut self, participants: &Participants) {
+        for (participant, registered) in self.participants.iter_mut() {
+            if !participants.contains_key(participant) {
+                registered.status = ParticipantStatus::Removed;
+            }
+        }
+        for (participant, info) in participants.iter() {
+            match self.participants.get_mut(participant) {
+                Some(registered) if registered.info == *info => {
+                    if registered.status == ParticipantStatus::Removed {
+                        registered.status = ParticipantStatus::Unreachable;
+                    }
+                }
+                _ => {
+                    self.participants.insert(
+                        *participant,
+                        RegisteredParticipant {
+                            info: info.clone(),
+                            share_commitment: None,
+                            status: ParticipantStatus::Unreachable,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Applies the outcome of the latest round of pings to the participants of the contract.
+    pub fn set_active(&mut self, active: &Participants) {
+        for (participant, registered) in self.participants.iter_mut() {
+            if registered.status == ParticipantStatus::Removed {
+                continue;
+            }
+            registered.status = if active.contains_key(participant) {
+                ParticipantStatus::Active
+            } else {
+                ParticipantStatus::Unreachable
+            };
+        }
+    }
+
+    pub fn set_share_commitment(&mut self, participant: Participant, commitment: PublicKey) {
+        if let Some(registered) = self.participants.get_mut(&participant) {
+            registered.share_commitment = Some(commitment);
+        }
+    }
+
+    pub fn get(&self, participant: &Participant) -> Option<&RegisteredParticipant> {
+        self.participants.get(participant)
+    }
+
+    pub fn account_id(&self, participant: &Participant) -> Option<&AccountId> {
+        self.get(participant)
+            .map(|registered| &registered.info.account_id)
+    }
+
+    pub fn status(&self, participant: &Participant) -> Option<ParticipantStatus> {
+        self.get(participant).map(|registered| registered.status)
+    }
+
+    /// Checks that the participant is part of the contract, e.g. before taking part in a
+    /// protocol it proposed. Everyone passes while nothing is registered yet.
+    pub fn validate(&self, participant: &Participant) -> Result<(), RegistryError> {
+        if self.participants.is_empty() {
+            return Ok(());
+        }
+        match self.status(participant) {
+            None => Err(RegistryError::Unknown(*participant)),
+            Some(ParticipantStatus::Removed) => Err(RegistryError::Removed(*participant)),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Participants of the contract, whether reachable or not.
+    pub fn participants(&self) -> Participants {
+        let mut participants = Participants::default();
+        for (participant, registered) in self.iter() {
+            participants.insert(participant, registered.info.clone());
+        }
+        participants
+    }
+
+    /// Participants of the contract that responded to the latest ping.
+    pub fn active(&self) -> Vec<Participant> {
+        self.participants
+            .iter()
+            .filter(|(_, registered)| registered.status == ParticipantStatus::Active)
+            .map(|(participant, _)| *participant)
+            .collect()
+    }
+
+    /// Registered participants that are still part of the contract.
+    pub fn iter(&self) -> impl Iterator<Item = (&Participant, &RegisteredParticipant)> {
+        self.participants
+            .iter()
+            .filter(|(_, registered)| registered.status != ParticipantStatus::Removed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participants(ids: &[u32]) -> Participants {
+        let mut participants = Participants::default();
+        for id in ids {
+            participants.insert(&Participant::from(*id), ParticipantInfo::new(*id));
+        }
+        participants
+    }
+
+    #[test]
+    fn test_registry_tracks_contract_and_liveness() {
+        let mut registry = ParticipantRegistry::default();
+        registry.set_participants(&participants(&[0, 1, 2]));
+        assert_eq!(
+            registry.status(&Participant::from(1)),
+            Some(ParticipantStatus::Unreachable)
+        );
+
+        registry.set_active(&participants(&[0, 1]));
+        assert_eq!(
+            registry.active(),
+            vec![Participant::from(0), Participant::from(1)]
+        );
+
+        registry.set_share_commitment(Participant::from(2), share_commitment(&k256::Scalar::ONE));
+        registry.set_participants(&participants(&[0, 2]));
+        assert_eq!(
+            registry.validate(&Participant::from(1)),
+            Err(RegistryError::Removed(Participant::from(1)))
+        );
+        assert_eq!(
+            registry.validate(&Participant::from(3)),
+            Err(RegistryError::Unknown(Participant::from(3)))
+        );
+        assert!(registry.validate(&Participant::from(2)).is_ok());
+        assert!(registry
+            .get(&Participant::from(2))
+            .unwrap()
+            .share_commitment
+            .is_some());
+        assert_eq!(
+            registry.participants().keys_vec(),
+            participants(&[0, 2]).keys_vec()
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use cait_sith::protocol::Participant;
+use chrono::Utc;
+use near_account_id::AccountId;
+use near_crypto::{PublicKey, SecretKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Number of misbehaviors after which a participant is considered an offender and a
+/// misbehavior report can be produced against them.
+const OFFENDER_THRESHOLD: u64 = 16;
+
+/// Kinds of misbehavior that can be attributed to a specific participant.
+///
+/// NOTE: cait-sith does not tell which participant caused a protocol to fail or time out,
+/// so only misbehavior that can be traced back to the sender of a message is tracked.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Misbehavior {
+    /// Sent a message that is inconsistent with the protocol it is addressed to.
+    MalformedMessage,
+    /// Sent the exact same message more than once.
+    DuplicateMessage,
+}
+
+impl Misbehavior {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Misbehavior::MalformedMessage => "malformed_message",
+            Misbehavior::DuplicateMessage => "duplicate_message",
+        }
+    }
+}
+
+/// The claim of a misbehavior report. This is the part of the report that gets signed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MisbehaviorClaim {
+    pub reporter: AccountId,
+    pub offender: AccountId,
+    pub epoch: u64,
+    pub misbehaviors: BTreeMap<Misbehavior, u64>,
+    // UNIX timestamp as seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// A misbehavior report signed by the reporting node, to be used as justification for
+/// kicking the offender out of the participant set.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MisbehaviorReport {
+    pub claim: MisbehaviorClaim,
+    pub signature: Signature,
+}
+
+impl MisbehaviorReport {
+    pub fn verify(&self, reporter_pk: &PublicKey) -> bool {
+        match serde_json::to_vec(&self.claim) {
+            Ok(claim) => self.signature.verify(&claim, reporter_pk),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Tracks misbehavior of the other participants.
+pub struct Reputation {
+    my_account_id: AccountId,
+    records: HashMap<Participant, BTreeMap<Misbehavior, u64>>,
+}
+
+impl Reputation {
+    pub fn new(my_account_id: &AccountId) -> Self {
+        Self {
+            my_account_id: my_account_id.clone(),
+            records: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, participant: Participant, misbehavior: Misbehavior) {
+        let record = self.records.entry(participant).or_default();
+        *record.entry(misbehavior).or_default() += 1;
+        crate::metrics::NUM_PEER_MISBEHAVIORS
+            .with_label_values(&[self.my_account_id.as_str(), misbehavior.as_str()])
+            .inc();
+
+        if record.values().sum::<u64>() == OFFENDER_THRESHOLD {
+            tracing::warn!(?participant, ?record, "participant flagged as an offender");
+        }
+    }
+
+    /// Returns the total number of misbehaviors recorded for the participant.
+    pub fn score(&self, participant: &Participant) -> u64 {
+        self.records
+            .get(participant)
+            .map(|record| record.values().sum())
+            .unwrap_or(0)
+    }
+
+    /// Returns the participants that have misbehaved at least `OFFENDER_THRESHOLD` times.
+    pub fn offenders(&self) -> Vec<Participant> {
+        self.records
+            .keys()
+            .filter(|participant| self.score(participant) >= OFFENDER_THRESHOLD)
+            .copied()
+            .collect()
+    }
+
+    /// Produces a signed report of the misbehavior of an offender. Returns `None` if the
+    /// participant has not misbehaved enough to be considered an offender.
+    pub fn report(
+        &self,
+        offender: Participant,
+        offender_account_id: &AccountId,
+        epoch: u64,
+        sign_sk: &SecretKey,
+    ) -> Option<MisbehaviorReport> {
+        if self.score(&offender) < OFFENDER_THRESHOLD {
+            return None;
+        }
+
+        let claim = MisbehaviorClaim {
+            reporter: self.my_account_id.clone(),
+            offender: offender_account_id.clone(),
+            epoch,
+            misbehaviors: self.records.get(&offender)?.clone(),
+            timestamp: Utc::now().timestamp() as u64,
+        };
+        let signature = sign_sk.sign(&serde_json::to_vec(&claim).ok()?);
+        Some(MisbehaviorReport { claim, signature })
+    }
+}
@@ -0,0 +1,712 @@
+//! Routing of the received messages to the protocols they belong to. Every message goes through
+//! the [`MessageRouter`], which drops duplicates, sorts the messages into a queue per kind and
+//! epoch, and applies the epoch policies in one place before handing the queues of our epoch to
+//! the workers of the managers of the running state, see [`super::worker`], and the remaining ones
+//! to the [`MessageHandler`] of the node state.
+
+use super::message::{
+    AbortMessage, AnnounceMessage, CommitmentMessage, GeneratingMessage, MessageHandleError,
+    MessageHandler, MpcMessage, PresignatureMessage, ReconcileMessage, RefreshMessage,
+    ResharingMessage, SignatureMessage, TripleMessage, UnknownTripleMessage,
+};
+use super::presignature::PresignatureId;
+use super::scheduler::PokeKind;
+use super::triple::TripleId;
+use super::worker::Workers;
+
+use lru::LruCache;
+use near_primitives::hash::CryptoHash;
+use sha3::{Digest, Sha3_256};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+/// How often the dedup entries of the messages seen outside of the window are removed.
+const DEDUP_GC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Maximum number of messages remembered by [`MessageDedup`]. Past this, the messages seen the
+/// longest ago are forgotten before their window is over, rather than letting a flood of
+/// distinct messages grow the dedup without bound.
+const DEDUP_CAPACITY: usize = 1 << 18;
+
+/// Detects protocol messages that have already been received within a window of time. A
+/// duplicated or replayed message is dropped before it gets to any protocol.
+pub struct MessageDedup {
+    window: Duration,
+    /// When each message was last seen, ordered from the least recently seen one.
+    seen: LruCache<[u8; 32], Instant>,
+}
+
+impl MessageDedup {
+    pub fn new(window: Duration) -> Self {
+        Self::with_capacity(window, DEDUP_CAPACITY)
+    }
+
+    fn with_capacity(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            seen: LruCache::new(NonZeroUsize::new(capacity).unwrap()),
+        }
+    }
+
+    /// Messages are identified by (epoch, protocol id, sender, payload). The payload stands in
+    /// for the protocol round since every round of a protocol produces a different payload.
+    fn key(message: &MpcMessage) -> [u8; 32] {
+        let mut hasher = Sha3_256::new();
+        hasher.update(message.typename());
+        let from: u32 = message.sender().into();
+        hasher.update(from.to_le_bytes());
+        match message {
+            MpcMessage::Generating(msg) => {
+                hasher.update(&msg.data);
+            }
+            MpcMessage::Resharing(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(&msg.data);
+            }
+            MpcMessage::Triple(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.id.to_le_bytes());
+                hasher.update(&msg.data);
+            }
+            MpcMessage::Presignature(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.id.to_le_bytes());
+                hasher.update(&msg.data);
+            }
+            MpcMessage::Signature(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.receipt_id.0);
+                hasher.update(&msg.data);
+            }
+            MpcMessage::Abort(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.id.to_le_bytes());
+                hasher.update(&msg.reason);
+            }
+            MpcMessage::Reconcile(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.timestamp.to_le_bytes());
+                for id in &msg.presignatures {
+                    hasher.update(id.to_le_bytes());
+                }
+            }
+            MpcMessage::UnknownTriple(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.id.to_le_bytes());
+                hasher.update(msg.triple_id.to_le_bytes());
+            }
+            MpcMessage::Commitment(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.id.to_le_bytes());
+                hasher.update(msg.commitment);
+            }
+            MpcMessage::Announce(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.started_at.to_le_bytes());
+            }
+            MpcMessage::Refresh(msg) => {
+                hasher.update(msg.epoch.to_le_bytes());
+                hasher.update(msg.round.to_le_bytes());
+                hasher.update([msg.completed as u8]);
+                hasher.update(&msg.data);
+            }
+        }
+        hasher.finalize().into()
+    }
+
+    /// Records the message and returns whether it was already seen within the window.
+    pub fn is_duplicate(&mut self, message: &MpcMessage) -> bool {
+        let key = Self::key(message);
+        // Peeking leaves the order of the entries to be the order they were seen in.
+        match self.seen.peek(&key) {
+            Some(seen_at) if seen_at.elapsed() < self.window => true,
+            _ => {
+                self.seen.put(key, Instant::now());
+                false
+            }
+        }
+    }
+
+    /// Removes entries of messages that were seen outside of the window.
+    pub fn garbage_collect(&mut self) {
+        while self
+            .seen
+            .peek_lru()
+            .is_some_and(|(_, seen_at)| seen_at.elapsed() >= self.window)
+        {
+            self.seen.pop_lru();
+        }
+    }
+}
+
+/// Messages of an epoch ahead of ours are held for at most this long, waiting for our epoch to
+/// catch up, e.g. when the other participants finished resharing before us.
+const FUTURE_EPOCH_QUARANTINE: Duration = Duration::from_secs(5 * 60);
+
+/// Only messages of at most this many epochs ahead of ours are held.
+const MAX_FUTURE_EPOCHS: u64 = 1;
+
+/// Maximum number of protocols whose early messages are held, see [`PendingMessages`].
+const MAX_PENDING_PROTOCOLS: usize = 1024;
+
+/// Maximum number of early messages held for a single protocol.
+const MAX_PENDING_MESSAGES: usize = 64;
+
+/// Messages of protocols that cannot be started yet because this node still lacks some of their
+/// inputs, e.g. the triples of a presignature that are yet to be generated on our side. They are
+/// held and replayed once the protocol can be started, rather than dropped and leaving the sender
+/// to resend them or to time out. Bounded in the number of protocols, evicting the oldest, and in
+/// the number of messages held per protocol.
+pub struct PendingMessages<Id, Msg> {
+    held: HashMap<Id, (Instant, VecDeque<Msg>)>,
+}
+
+impl<Id, Msg> Default for PendingMessages<Id, Msg> {
+    fn default() -> Self {
+        Self {
+            held: HashMap::new(),
+        }
+    }
+}
+
+impl<Id: Copy + Eq + std::hash::Hash, Msg> PendingMessages<Id, Msg> {
+    /// Number of protocols with messages held.
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Holds the messages of a protocol that cannot be started yet. Returns the number of
+    /// messages dropped to stay within bounds.
+    pub fn hold(&mut self, id: Id, messages: impl IntoIterator<Item = Msg>) -> usize {
+        let mut dropped = 0;
+        if !self.held.contains_key(&id) && self.held.len() >= MAX_PENDING_PROTOCOLS {
+            let oldest = self
+                .held
+                .iter()
+                .min_by_key(|(_, (since, _))| *since)
+                .map(|(id, _)| *id);
+            if let Some((_, evicted)) = oldest.and_then(|oldest| self.held.remove(&oldest)) {
+                dropped += evicted.len();
+            }
+        }
+        let (_, held) = self
+            .held
+            .entry(id)
+            .or_insert_with(|| (Instant::now(), VecDeque::new()));
+        for message in messages {
+            if held.len() < MAX_PENDING_MESSAGES {
+                held.push_back(message);
+            } else {
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// Takes out the messages of the protocols that can be started now according to `ready`, and
+    /// drops the ones held for longer than `timeout`, as their protocol timed out on the sender's
+    /// side anyway. Returns the released messages and the number of messages dropped.
+    pub fn release(
+        &mut self,
+        timeout: Duration,
+        mut ready: impl FnMut(&Msg) -> bool,
+    ) -> (Vec<Msg>, usize) {
+        let mut released = Vec::new();
+        let mut dropped = 0;
+        self.held.retain(|_, (since, held)| {
+            if since.elapsed() > timeout {
+                dropped += held.len();
+                return false;
+            }
+            if held.front().is_some_and(&mut ready) {
+                released.extend(held.drain(..));
+                return false;
+            }
+            true
+        });
+        (released, dropped)
+    }
+}
+
+/// Number of messages dropped by [`MpcMessageQueue::route_epochs`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedMessages {
+    /// Messages of epochs before ours.
+    pub stale: usize,
+    /// Messages of epochs too far ahead of ours, or that were held for too long.
+    pub future: usize,
+}
+
+#[derive(Default)]
+pub struct MpcMessageQueue {
+    pub(super) generating: VecDeque<GeneratingMessage>,
+    pub(super) resharing_bins: HashMap<u64, VecDeque<ResharingMessage>>,
+    pub(super) triple_bins: HashMap<u64, HashMap<TripleId, VecDeque<TripleMessage>>>,
+    pub(super) presignature_bins:
+        HashMap<u64, HashMap<PresignatureId, VecDeque<PresignatureMessage>>>,
+    pub(super) signature_bins: HashMap<u64, HashMap<CryptoHash, VecDeque<SignatureMessage>>>,
+    pub(super) abort_bins: HashMap<u64, VecDeque<AbortMessage>>,
+    pub(super) reconcile_bins: HashMap<u64, VecDeque<ReconcileMessage>>,
+    pub(super) unknown_triple_bins: HashMap<u64, VecDeque<UnknownTripleMessage>>,
+    pub(super) commitment_bins: HashMap<u64, VecDeque<CommitmentMessage>>,
+    pub(super) announce_bins: HashMap<u64, VecDeque<AnnounceMessage>>,
+    pub(super) refresh_bins: HashMap<u64, VecDeque<RefreshMessage>>,
+    /// Presignature messages that arrived before the triples they use were generated on our side.
+    pub(super) pending_presignatures: PendingMessages<PresignatureId, PresignatureMessage>,
+    /// Epochs ahead of ours that have messages held, with the time they were first seen.
+    pub(super) quarantined: HashMap<u64, Instant>,
+    /// Messages held back by fault injection, with the time they get released.
+    #[cfg(feature = "fault-injection")]
+    pub(super) delayed: Vec<(Instant, MpcMessage)>,
+}
+
+impl MpcMessageQueue {
+    fn epochs(&self) -> BTreeSet<u64> {
+        self.resharing_bins
+            .keys()
+            .chain(self.triple_bins.keys())
+            .chain(self.presignature_bins.keys())
+            .chain(self.signature_bins.keys())
+            .chain(self.abort_bins.keys())
+            .chain(self.reconcile_bins.keys())
+            .chain(self.unknown_triple_bins.keys())
+            .chain(self.commitment_bins.keys())
+            .chain(self.announce_bins.keys())
+            .chain(self.refresh_bins.keys())
+            .copied()
+            .collect()
+    }
+
+    /// Removes all the messages of the epoch, returning how many were removed.
+    fn remove_epoch(&mut self, epoch: u64) -> usize {
+        fn count<K, V>(bins: Option<HashMap<K, VecDeque<V>>>) -> usize {
+            bins.map_or(0, |bins| bins.values().map(VecDeque::len).sum())
+        }
+
+        self.quarantined.remove(&epoch);
+        self.resharing_bins.remove(&epoch).map_or(0, |q| q.len())
+            + count(self.triple_bins.remove(&epoch))
+            + count(self.presignature_bins.remove(&epoch))
+            + count(self.signature_bins.remove(&epoch))
+            + self.abort_bins.remove(&epoch).map_or(0, |q| q.len())
+            + self.reconcile_bins.remove(&epoch).map_or(0, |q| q.len())
+            + self
+                .unknown_triple_bins
+                .remove(&epoch)
+                .map_or(0, |q| q.len())
+            + self.commitment_bins.remove(&epoch).map_or(0, |q| q.len())
+            + self.announce_bins.remove(&epoch).map_or(0, |q| q.len())
+            + self.refresh_bins.remove(&epoch).map_or(0, |q| q.len())
+    }
+
+    /// Routes the queued messages relative to our current epoch. Messages of past epochs can never
+    /// be handled anymore and are dropped. Messages of the next epoch are held until our epoch
+    /// catches up, at which point the handlers of that epoch pick them up, unless that takes longer
+    /// than [`FUTURE_EPOCH_QUARANTINE`]. Messages of epochs further ahead are dropped.
+    pub fn route_epochs(&mut self, current: u64) -> DroppedMessages {
+        #[cfg(feature = "fault-injection")]
+        self.release_delayed();
+
+        let mut dropped = DroppedMessages::default();
+        for epoch in self.epochs() {
+            if epoch < current {
+                dropped.stale += self.remove_epoch(epoch);
+            } else if epoch == current {
+                self.quarantined.remove(&epoch);
+            } else if epoch > current + MAX_FUTURE_EPOCHS {
+                dropped.future += self.remove_epoch(epoch);
+            } else {
+                let since = *self.quarantined.entry(epoch).or_insert_with(Instant::now);
+                if since.elapsed() > FUTURE_EPOCH_QUARANTINE {
+                    tracing::warn!(epoch, current, "dropping messages held for a future epoch");
+                    dropped.future += self.remove_epoch(epoch);
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Drops the protocol messages sent before `started_at`, which belong to protocols that were
+    /// ongoing before this node started and can no longer be joined. Returns the number of
+    /// dropped messages.
+    pub fn drop_sent_before(&mut self, started_at: u64) -> usize {
+        fn drop<K, V>(
+            bins: &mut HashMap<u64, HashMap<K, VecDeque<V>>>,
+            sent_at: impl Fn(&V) -> u64,
+            started_at: u64,
+        ) -> usize {
+            let mut dropped = 0;
+            for queue in bins.values_mut().flat_map(HashMap::values_mut) {
+                let before = queue.len();
+                queue.retain(|msg| sent_at(msg) >= started_at);
+                dropped += before - queue.len();
+            }
+            dropped
+        }
+
+        drop(&mut self.triple_bins, |msg| msg.timestamp, started_at)
+            + drop(&mut self.presignature_bins, |msg| msg.timestamp, started_at)
+            + drop(&mut self.signature_bins, |msg| msg.timestamp, started_at)
+    }
+
+    /// Takes the messages of the epoch that are handled by the workers, along with the kind of the
+    /// worker each of them is for. Announces are for both the triple and presignature workers, as
+    /// either drops the protocols a restarted participant lost.
+    pub(super) fn take_worker_messages(&mut self, epoch: u64) -> Vec<(PokeKind, MpcMessage)> {
+        fn protocols<K, V>(
+            bins: &mut HashMap<u64, HashMap<K, VecDeque<V>>>,
+            epoch: u64,
+        ) -> impl Iterator<Item = V> {
+            bins.remove(&epoch)
+                .into_iter()
+                .flat_map(HashMap::into_values)
+                .flatten()
+        }
+
+        fn bin<V>(bins: &mut HashMap<u64, VecDeque<V>>, epoch: u64) -> impl Iterator<Item = V> {
+            bins.remove(&epoch).into_iter().flatten()
+        }
+
+        let mut messages = Vec::new();
+        for announce in bin(&mut self.announce_bins, epoch) {
+            messages.push((PokeKind::Triple, MpcMessage::Announce(announce.clone())));
+            messages.push((PokeKind::Presignature, MpcMessage::Announce(announce)));
+        }
+        messages.extend(
+            protocols(&mut self.triple_bins, epoch)
+                .map(|msg| (PokeKind::Triple, MpcMessage::Triple(msg))),
+        );
+        messages.extend(
+            bin(&mut self.abort_bins, epoch)
+                .map(MpcMessage::Abort)
+                .chain(bin(&mut self.unknown_triple_bins, epoch).map(MpcMessage::UnknownTriple))
+                .chain(bin(&mut self.commitment_bins, epoch).map(MpcMessage::Commitment))
+                .chain(bin(&mut self.reconcile_bins, epoch).map(MpcMessage::Reconcile))
+                .chain(protocols(&mut self.presignature_bins, epoch).map(MpcMessage::Presignature))
+                .map(|msg| (PokeKind::Presignature, msg)),
+        );
+        messages.extend(
+            protocols(&mut self.signature_bins, epoch)
+                .map(|msg| (PokeKind::Signature, MpcMessage::Signature(msg))),
+        );
+        messages
+    }
+
+    #[cfg(feature = "fault-injection")]
+    fn release_delayed(&mut self) {
+        let now = Instant::now();
+        let (due, delayed) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(release_at, _)| *release_at <= now);
+        self.delayed = delayed;
+        for (_, message) in due {
+            self.enqueue(message);
+        }
+    }
+
+    pub fn push(&mut self, message: MpcMessage) {
+        #[cfg(feature = "fault-injection")]
+        let message = {
+            use crate::fault::{self, Direction, Fault};
+
+            let mut message = message;
+            match fault::inject(Direction::Receive, message.sender(), &message) {
+                Fault::Deliver => {}
+                Fault::Drop => return,
+                Fault::Delay(delay) => {
+                    self.delayed.push((Instant::now() + delay, message));
+                    return;
+                }
+                Fault::Duplicate => self.enqueue(message.clone()),
+                Fault::Corrupt => fault::corrupt(&mut message),
+            }
+            message
+        };
+        self.enqueue(message);
+    }
+
+    pub(super) fn enqueue(&mut self, message: MpcMessage) {
+        match message {
+            MpcMessage::Generating(message) => self.generating.push_back(message),
+            MpcMessage::Resharing(message) => self
+                .resharing_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Triple(message) => self
+                .triple_bins
+                .entry(message.epoch)
+                .or_default()
+                .entry(message.id)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Presignature(message) => self
+                .presignature_bins
+                .entry(message.epoch)
+                .or_default()
+                .entry(message.id)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Signature(message) => self
+                .signature_bins
+                .entry(message.epoch)
+                .or_default()
+                .entry(message.receipt_id)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Abort(message) => self
+                .abort_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Reconcile(message) => self
+                .reconcile_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+            MpcMessage::UnknownTriple(message) => self
+                .unknown_triple_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Commitment(message) => self
+                .commitment_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Announce(message) => self
+                .announce_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+            MpcMessage::Refresh(message) => self
+                .refresh_bins
+                .entry(message.epoch)
+                .or_default()
+                .push_back(message),
+        }
+    }
+}
+
+/// Outcome of [`MessageRouter::receive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received {
+    /// The message got queued for its protocol.
+    Queued,
+    /// The message was already received within the dedup window and got dropped.
+    Duplicate,
+}
+
+/// Owns the queues of the received messages, from their reception until they are handled.
+pub struct MessageRouter {
+    queue: MpcMessageQueue,
+    dedup: MessageDedup,
+    last_dedup_gc: Instant,
+}
+
+impl MessageRouter {
+    pub fn new(dedup_window: Duration) -> Self {
+        Self {
+            queue: MpcMessageQueue::default(),
+            dedup: MessageDedup::new(dedup_window),
+            last_dedup_gc: Instant::now(),
+        }
+    }
+
+    /// Queues a received message for its protocol, unless it is a duplicate.
+    pub fn receive(&mut self, message: MpcMessage) -> Received {
+        if self.dedup.is_duplicate(&message) {
+            return Received::Duplicate;
+        }
+        self.queue.push(message);
+        Received::Queued
+    }
+
+    /// Applies the epoch policies of [`MpcMessageQueue::route_epochs`] relative to our current
+    /// epoch, if we have one yet, and forgets the duplicates seen long enough ago.
+    pub fn route(&mut self, epoch: Option<u64>) -> DroppedMessages {
+        if self.last_dedup_gc.elapsed() > DEDUP_GC_INTERVAL {
+            self.dedup.garbage_collect();
+            self.last_dedup_gc = Instant::now();
+        }
+        epoch.map_or_else(DroppedMessages::default, |epoch| {
+            self.queue.route_epochs(epoch)
+        })
+    }
+
+    /// Drops the protocol messages sent before `started_at`, see
+    /// [`MpcMessageQueue::drop_sent_before`].
+    pub fn drop_sent_before(&mut self, started_at: u64) -> usize {
+        self.queue.drop_sent_before(started_at)
+    }
+
+    /// Hands the queued messages of the epoch to the workers of the managers, see
+    /// [`Workers::deliver`]. Returns how many messages are held for busy workers.
+    pub fn dispatch(&mut self, epoch: u64, workers: &mut Workers) -> usize {
+        workers.deliver(self.queue.take_worker_messages(epoch))
+    }
+
+    /// Hands the queued messages to `handler`, which takes those it can handle in its state.
+    pub async fn deliver<H, C>(&mut self, handler: &mut H, ctx: C) -> Result<(), MessageHandleError>
+    where
+        H: MessageHandler + Send,
+        C: super::message::MessageCtx + Send + Sync,
+    {
+        handler.handle(ctx, &mut self.queue).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyspace::KeyspaceId;
+    use cait_sith::protocol::Participant;
+
+    fn abort(epoch: u64) -> MpcMessage {
+        MpcMessage::Abort(AbortMessage {
+            id: 0,
+            keyspace: KeyspaceId::root(),
+            epoch,
+            from: Participant::from(0),
+            reason: String::new(),
+            timestamp: 0,
+        })
+    }
+
+    #[test]
+    fn test_route_epochs() {
+        let mut queue = MpcMessageQueue::default();
+        for epoch in [0, 1, 1, 2, 3] {
+            queue.push(abort(epoch));
+        }
+
+        let dropped = queue.route_epochs(1);
+        assert_eq!(
+            dropped,
+            DroppedMessages {
+                stale: 1,
+                future: 1
+            }
+        );
+        assert_eq!(queue.epochs(), BTreeSet::from([1, 2]));
+        assert!(queue.quarantined.contains_key(&2));
+
+        // Once we catch up, the held messages are no longer quarantined.
+        let dropped = queue.route_epochs(2);
+        assert_eq!(dropped.stale, 2);
+        assert_eq!(queue.abort_bins[&2].len(), 1);
+        assert!(queue.quarantined.is_empty());
+    }
+
+    #[test]
+    fn test_drop_sent_before() {
+        let mut queue = MpcMessageQueue::default();
+        for (id, timestamp) in [(0, 10), (0, 20), (1, 30)] {
+            queue.push(MpcMessage::Triple(TripleMessage {
+                id,
+                keyspace: KeyspaceId::root(),
+                epoch: 0,
+                from: Participant::from(1),
+                data: Vec::new(),
+                timestamp,
+            }));
+        }
+        queue.push(abort(0));
+
+        assert_eq!(queue.drop_sent_before(20), 1);
+        assert_eq!(queue.triple_bins[&0][&0].len(), 1);
+        assert_eq!(queue.triple_bins[&0][&1].len(), 1);
+        // Only the messages of protocols are dropped.
+        assert_eq!(queue.abort_bins[&0].len(), 1);
+    }
+
+    #[test]
+    fn test_take_worker_messages() {
+        let mut queue = MpcMessageQueue::default();
+        queue.push(abort(0));
+        queue.push(abort(1));
+        queue.push(MpcMessage::Announce(AnnounceMessage {
+            epoch: 0,
+            from: Participant::from(1),
+            started_at: 0,
+            timestamp: 0,
+        }));
+
+        let kinds: Vec<_> = queue
+            .take_worker_messages(0)
+            .into_iter()
+            .map(|(kind, msg)| (kind, msg.typename()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (PokeKind::Triple, "Announce"),
+                (PokeKind::Presignature, "Announce"),
+                (PokeKind::Presignature, "Abort"),
+            ]
+        );
+        // The messages of other epochs are left to the epoch policies.
+        assert_eq!(queue.epochs(), BTreeSet::from([1]));
+    }
+
+    #[test]
+    fn test_pending_messages() {
+        let mut pending = PendingMessages::<u64, u64>::default();
+        assert_eq!(pending.hold(0, 0..MAX_PENDING_MESSAGES as u64 + 3), 3);
+        assert_eq!(pending.hold(1, [7]), 0);
+
+        // Only the protocols ready to be started are released.
+        let (released, dropped) = pending.release(Duration::from_secs(60), |msg| *msg == 7);
+        assert_eq!((released, dropped), (vec![7], 0));
+        assert_eq!(pending.len(), 1);
+
+        // The oldest protocol is evicted once too many protocols are held.
+        for id in 2..MAX_PENDING_PROTOCOLS as u64 + 1 {
+            assert_eq!(pending.hold(id, [id]), 0);
+        }
+        assert_eq!(pending.hold(u64::MAX, [0]), MAX_PENDING_MESSAGES);
+        assert_eq!(pending.len(), MAX_PENDING_PROTOCOLS);
+
+        // Messages held for too long are dropped.
+        let (released, dropped) = pending.release(Duration::ZERO, |_| false);
+        assert!(released.is_empty());
+        assert_eq!(dropped, MAX_PENDING_PROTOCOLS);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_router_drops_duplicates() {
+        let mut router = MessageRouter::new(Duration::from_secs(60));
+        assert_eq!(router.receive(abort(1)), Received::Queued);
+        assert_eq!(router.receive(abort(1)), Received::Duplicate);
+        assert_eq!(router.receive(abort(2)), Received::Queued);
+
+        // Without an epoch yet, the messages are held as they are.
+        assert_eq!(router.route(None), DroppedMessages::default());
+        assert_eq!(router.route(Some(2)).stale, 1);
+        assert_eq!(router.queue.abort_bins[&2].len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_is_bounded() {
+        let mut dedup = MessageDedup::with_capacity(Duration::from_secs(60), 2);
+        assert!(!dedup.is_duplicate(&abort(1)));
+        assert!(!dedup.is_duplicate(&abort(2)));
+        // A duplicate does not keep the message from being forgotten first.
+        assert!(dedup.is_duplicate(&abort(1)));
+        assert!(!dedup.is_duplicate(&abort(3)));
+        assert_eq!(dedup.seen.len(), 2);
+        assert!(!dedup.is_duplicate(&abort(1)));
+        assert!(dedup.is_duplicate(&abort(3)));
+
+        let mut dedup = MessageDedup::with_capacity(Duration::ZERO, 2);
+        assert!(!dedup.is_duplicate(&abort(1)));
+        dedup.garbage_collect();
+        assert!(dedup.seen.is_empty());
+    }
+}
@@ -0,0 +1,500 @@
+//! Scheduling of the poke loop. Every tick of the loop gets a budget of messages that can be
+//! produced by poking the ongoing protocols. The budget is split across the different kinds of
+//! protocols by weight, such that background stockpiling of triples and presignatures cannot
+//! starve the user facing signature protocols.
+
+use std::time::{Duration, Instant};
+
+use cait_sith::protocol::{Participant, ProtocolError};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_POKE_MESSAGE_BUDGET: usize = 4096;
+const DEFAULT_POKE_WEIGHT_SIGNATURE: usize = 4;
+const DEFAULT_POKE_WEIGHT_PRESIGNATURE: usize = 2;
+const DEFAULT_POKE_WEIGHT_TRIPLE: usize = 1;
+const DEFAULT_TRIPLE_START_RATE: f64 = 4.0;
+const DEFAULT_TRIPLE_START_BURST: usize = 8;
+
+/// Configures how the poke loop schedules the ongoing protocols.
+#[derive(Debug, Clone, PartialEq, clap::Parser)]
+#[group(id = "scheduler_options")]
+pub struct Options {
+    /// Maximum number of messages that can be produced by poking protocols in a single tick.
+    #[arg(long, env("MPC_POKE_MESSAGE_BUDGET"), default_value_t = DEFAULT_POKE_MESSAGE_BUDGET)]
+    pub poke_message_budget: usize,
+    /// Share of the message budget given to signature protocols.
+    #[arg(long, env("MPC_POKE_WEIGHT_SIGNATURE"), default_value_t = DEFAULT_POKE_WEIGHT_SIGNATURE)]
+    pub poke_weight_signature: usize,
+    /// Share of the message budget given to presignature protocols.
+    #[arg(long, env("MPC_POKE_WEIGHT_PRESIGNATURE"), default_value_t = DEFAULT_POKE_WEIGHT_PRESIGNATURE)]
+    pub poke_weight_presignature: usize,
+    /// Share of the message budget given to triple protocols.
+    #[arg(long, env("MPC_POKE_WEIGHT_TRIPLE"), default_value_t = DEFAULT_POKE_WEIGHT_TRIPLE)]
+    pub poke_weight_triple: usize,
+    /// Number of triple protocols that can be started per second. Zero disables pacing.
+    #[arg(long, env("MPC_TRIPLE_START_RATE"), default_value_t = DEFAULT_TRIPLE_START_RATE)]
+    pub triple_start_rate: f64,
+    /// Number of triple protocols that can be started at once after a quiet period.
+    #[arg(long, env("MPC_TRIPLE_START_BURST"), default_value_t = DEFAULT_TRIPLE_START_BURST)]
+    pub triple_start_burst: usize,
+    /// Maximum number of triple protocols in flight on this node, on top of the limit set by the
+    /// contract.
+    #[arg(long, env("MPC_MAX_TRIPLES_IN_FLIGHT"))]
+    pub max_triples_in_flight: Option<usize>,
+    /// Number of responsive participants required on top of the threshold for this node to
+    /// propose new triples and presignatures. Capped at the size of the participant set.
+    #[arg(long, env("MPC_LIVENESS_MARGIN"), default_value_t = 0)]
+    pub liveness_margin: usize,
+    /// Maximum share of the triple stockpile, including ongoing protocols, introduced by this
+    /// node. Above it, the node only joins the triples proposed by others.
+    #[arg(long, env("MPC_TRIPLE_MINE_RATIO"))]
+    pub triple_mine_ratio: Option<f64>,
+    /// Minimum number of triples of its own this node holds to join the presignatures proposed
+    /// by others.
+    #[arg(long, env("MPC_MIN_TRIPLES_TO_JOIN"))]
+    pub min_triples_to_join: Option<usize>,
+    /// Number of ongoing triple and presignature protocols from which this node stops joining the
+    /// presignatures proposed by others.
+    #[arg(long, env("MPC_MAX_GENERATORS_TO_JOIN"))]
+    pub max_generators_to_join: Option<usize>,
+    /// Resident memory of the node in MiB above which it sheds load, see [`crate::shedding`].
+    #[arg(long, env("MPC_SHED_MAX_RSS_MB"))]
+    pub shed_max_rss_mb: Option<u64>,
+    /// Number of triples and presignatures, held or being generated, above which the node sheds
+    /// load, see [`crate::shedding`].
+    #[arg(long, env("MPC_SHED_MAX_POOL_SIZE"))]
+    pub shed_max_pool_size: Option<usize>,
+    /// Hard cap on the presignature protocols ongoing on this node, proposed by any node. Above
+    /// it, proposals are rejected and the proposers told so.
+    #[arg(long, env("MPC_MAX_PRESIGNATURE_GENERATORS"))]
+    pub max_presignature_generators: Option<usize>,
+    /// Hard cap on the unspent presignatures held by this node. Above it, no presignature is
+    /// started or joined until some get spent.
+    #[arg(long, env("MPC_MAX_PRESIGNATURES_HELD"))]
+    pub max_presignatures_held: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            poke_message_budget: DEFAULT_POKE_MESSAGE_BUDGET,
+            poke_weight_signature: DEFAULT_POKE_WEIGHT_SIGNATURE,
+            poke_weight_presignature: DEFAULT_POKE_WEIGHT_PRESIGNATURE,
+            poke_weight_triple: DEFAULT_POKE_WEIGHT_TRIPLE,
+            triple_start_rate: DEFAULT_TRIPLE_START_RATE,
+            triple_start_burst: DEFAULT_TRIPLE_START_BURST,
+            max_triples_in_flight: None,
+            liveness_margin: 0,
+            triple_mine_ratio: None,
+            min_triples_to_join: None,
+            max_generators_to_join: None,
+            shed_max_rss_mb: None,
+            shed_max_pool_size: None,
+            max_presignature_generators: None,
+            max_presignatures_held: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut opts = vec![
+            "--poke-message-budget".to_string(),
+            self.poke_message_budget.to_string(),
+            "--poke-weight-signature".to_string(),
+            self.poke_weight_signature.to_string(),
+            "--poke-weight-presignature".to_string(),
+            self.poke_weight_presignature.to_string(),
+            "--poke-weight-triple".to_string(),
+            self.poke_weight_triple.to_string(),
+            "--triple-start-rate".to_string(),
+            self.triple_start_rate.to_string(),
+            "--triple-start-burst".to_string(),
+            self.triple_start_burst.to_string(),
+            "--liveness-margin".to_string(),
+            self.liveness_margin.to_string(),
+        ];
+        if let Some(max_triples_in_flight) = self.max_triples_in_flight {
+            opts.extend(vec![
+                "--max-triples-in-flight".to_string(),
+                max_triples_in_flight.to_string(),
+            ]);
+        }
+        if let Some(triple_mine_ratio) = self.triple_mine_ratio {
+            opts.extend(vec![
+                "--triple-mine-ratio".to_string(),
+                triple_mine_ratio.to_string(),
+            ]);
+        }
+        if let Some(min_triples_to_join) = self.min_triples_to_join {
+            opts.extend(vec![
+                "--min-triples-to-join".to_string(),
+                min_triples_to_join.to_string(),
+            ]);
+        }
+        if let Some(max_generators_to_join) = self.max_generators_to_join {
+            opts.extend(vec![
+                "--max-generators-to-join".to_string(),
+                max_generators_to_join.to_string(),
+            ]);
+        }
+        if let Some(shed_max_rss_mb) = self.shed_max_rss_mb {
+            opts.extend(vec![
+                "--shed-max-rss-mb".to_string(),
+                shed_max_rss_mb.to_string(),
+            ]);
+        }
+        if let Some(shed_max_pool_size) = self.shed_max_pool_size {
+            opts.extend(vec![
+                "--shed-max-pool-size".to_string(),
+                shed_max_pool_size.to_string(),
+            ]);
+        }
+        if let Some(max_presignature_generators) = self.max_presignature_generators {
+            opts.extend(vec![
+                "--max-presignature-generators".to_string(),
+                max_presignature_generators.to_string(),
+            ]);
+        }
+        if let Some(max_presignatures_held) = self.max_presignatures_held {
+            opts.extend(vec![
+                "--max-presignatures-held".to_string(),
+                max_presignatures_held.to_string(),
+            ]);
+        }
+        opts
+    }
+}
+
+/// Token bucket pacing how many protocols get started. The bucket holds up to `burst` tokens and
+/// refills at `rate` tokens per second, every started protocol takes one token. Also used to pace
+/// the bytes sent to participants, with one token per byte.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    /// `None` when pacing is disabled.
+    limit: Option<(f64, f64)>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: usize) -> Self {
+        let limit = (rate > 0.0).then_some((rate, burst.max(1) as f64));
+        Self {
+            limit,
+            tokens: limit.map_or(0.0, |(_, burst)| burst),
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0.0, 0)
+    }
+
+    /// Number of protocols that can be started at `now`.
+    pub fn available(&mut self, now: Instant) -> usize {
+        let Some((rate, burst)) = self.limit else {
+            return usize::MAX;
+        };
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(burst);
+        self.last_refill = now;
+        self.tokens as usize
+    }
+
+    /// Records that `n` protocols were started.
+    pub fn take(&mut self, n: usize) {
+        if self.limit.is_some() {
+            self.tokens = (self.tokens - n as f64).max(0.0);
+        }
+    }
+}
+
+impl Default for TokenBucket {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// Why a protocol failed while being poked.
+#[derive(Debug, thiserror::Error)]
+pub enum PokeFailure {
+    /// The protocol did not complete in time, usually because a participant stopped responding.
+    #[error("protocol timed out after {0:?}")]
+    TimedOut(Duration),
+    /// The cryptographic protocol itself failed. Our own inputs are validated when the protocol
+    /// is initialized, so this is caused by the data received from the other participants, e.g.
+    /// a malformed message or a share that does not verify.
+    #[error("protocol failed: {0}")]
+    Protocol(#[from] ProtocolError),
+    /// The signing protocol completed, but the signature does not verify against the derived
+    /// public key and the payload. Such a signature is never published.
+    #[error("produced an invalid signature: {0}")]
+    InvalidSignature(String),
+}
+
+impl PokeFailure {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, PokeFailure::TimedOut(_))
+    }
+}
+
+/// Splits the wall-clock time of a protocol into the time spent poking it, which is CPU bound,
+/// and the time spent waiting between rounds for the messages of the other participants, which is
+/// bound by the network and how fast the other participants poke.
+#[derive(Debug, Default)]
+pub struct RoundTimer {
+    /// When the protocol got poked for the first time.
+    started: Option<Instant>,
+    poked_at: Option<Instant>,
+    waiting_since: Option<Instant>,
+    poking: Duration,
+    /// Waits between rounds that are yet to be exported.
+    waits: Vec<Duration>,
+}
+
+impl RoundTimer {
+    /// Marks the start of a poke. The time since the protocol got blocked is a wait between rounds.
+    pub fn resume(&mut self) {
+        let now = Instant::now();
+        self.started.get_or_insert(now);
+        if let Some(since) = self.waiting_since.take() {
+            self.waits.push(now - since);
+        }
+        self.poked_at = Some(now);
+    }
+
+    /// Marks the end of a poke, after which the protocol is either `blocked` on messages of the
+    /// other participants, or done.
+    pub fn pause(&mut self, blocked: bool) {
+        let now = Instant::now();
+        if let Some(poked_at) = self.poked_at.take() {
+            self.poking += now - poked_at;
+        }
+        if blocked {
+            self.waiting_since = Some(now);
+        }
+    }
+
+    /// Exports the waits between rounds recorded since the last call and, once the protocol is
+    /// `completed`, the total time spent poking it alongside its wall-clock time.
+    pub fn observe(&mut self, kind: PokeKind, node_account_id: &str, completed: bool) {
+        let labels = [node_account_id, kind.as_str()];
+        for wait in self.waits.drain(..) {
+            crate::metrics::PROTOCOL_ROUND_WAIT_LATENCY
+                .with_label_values(&labels)
+                .observe(wait.as_secs_f64());
+        }
+        if !completed {
+            return;
+        }
+        crate::metrics::PROTOCOL_POKE_LATENCY
+            .with_label_values(&labels)
+            .observe(self.poking.as_secs_f64());
+        if let Some(started) = self.started {
+            crate::metrics::PROTOCOL_TOTAL_LATENCY
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+        }
+    }
+
+    /// Total time spent poking the protocol so far.
+    pub fn poking(&self) -> Duration {
+        self.poking
+    }
+}
+
+/// Summary of an ongoing generator, as part of the snapshot of its manager.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeneratorSummary {
+    pub id: u64,
+    pub mine: bool,
+    pub participants: Vec<Participant>,
+    /// Milliseconds since the generator started. `None` if it has yet to be poked.
+    pub age_ms: Option<u128>,
+}
+
+/// What poking the protocols of a manager produced.
+pub struct PokeOutcome<Id, Msg> {
+    /// Messages to be sent to the respective participants. Empty if no protocol can progress
+    /// until new messages are received.
+    pub messages: Vec<(Participant, Msg)>,
+    /// Protocols that failed and were dropped from the manager.
+    pub failures: Vec<(Id, PokeFailure)>,
+}
+
+impl<Id, Msg> Default for PokeOutcome<Id, Msg> {
+    fn default() -> Self {
+        Self {
+            messages: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+/// The kinds of protocols that get poked, in the order they should be poked in a tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PokeKind {
+    Signature,
+    Presignature,
+    Triple,
+}
+
+impl PokeKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            PokeKind::Signature => "signature",
+            PokeKind::Presignature => "presignature",
+            PokeKind::Triple => "triple",
+        }
+    }
+}
+
+/// The message budget of a single tick of the poke loop.
+///
+/// Budget left unused by one kind of protocol is handed down to the next one in priority order.
+/// Messages still waiting in the outgoing queue apply back-pressure, but only to the background
+/// protocols: signature protocols always get their full share.
+#[derive(Clone, Debug)]
+pub struct PokeTick {
+    signature: usize,
+    presignature: usize,
+    triple: usize,
+    /// Budget left unused by the previously poked kind of protocol.
+    carry: usize,
+    /// Number of messages the background protocols can still produce before the outgoing
+    /// queue is considered to be backed up.
+    headroom: usize,
+}
+
+impl PokeTick {
+    pub fn new(opts: &Options, pending_messages: usize) -> Self {
+        let budget = opts.poke_message_budget;
+        let (ws, wp, wt) = match (
+            opts.poke_weight_signature,
+            opts.poke_weight_presignature,
+            opts.poke_weight_triple,
+        ) {
+            (0, 0, 0) => (1, 1, 1),
+            weights => weights,
+        };
+        let total = ws + wp + wt;
+        let signature = budget * ws / total;
+        let presignature = budget * wp / total;
+        let triple = budget - signature - presignature;
+
+        Self {
+            signature,
+            presignature,
+            triple,
+            carry: 0,
+            headroom: budget.saturating_sub(pending_messages),
+        }
+    }
+
+    /// The number of messages the given kind of protocol can produce in this tick.
+    pub fn budget(&self, kind: PokeKind) -> usize {
+        match kind {
+            PokeKind::Signature => self.signature + self.carry,
+            PokeKind::Presignature => (self.presignature + self.carry).min(self.headroom),
+            PokeKind::Triple => (self.triple + self.carry).min(self.headroom),
+        }
+    }
+
+    /// Records the number of messages the given kind of protocol produced in this tick.
+    pub fn spend(&mut self, kind: PokeKind, sent: usize) {
+        let budget = self.budget(kind);
+        if sent >= budget {
+            tracing::debug!(kind = kind.as_str(), budget, "poke budget exhausted");
+        }
+        self.carry = budget.saturating_sub(sent);
+        if kind != PokeKind::Signature {
+            self.headroom = self.headroom.saturating_sub(sent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_budget_is_split_by_weight() {
+        let opts = Options {
+            poke_message_budget: 700,
+            poke_weight_signature: 4,
+            poke_weight_presignature: 2,
+            poke_weight_triple: 1,
+            ..Default::default()
+        };
+        let mut tick = PokeTick::new(&opts, 0);
+        assert_eq!(tick.budget(PokeKind::Signature), 400);
+        tick.spend(PokeKind::Signature, 400);
+        assert_eq!(tick.budget(PokeKind::Presignature), 200);
+        tick.spend(PokeKind::Presignature, 200);
+        assert_eq!(tick.budget(PokeKind::Triple), 100);
+    }
+
+    #[test]
+    fn test_unused_budget_is_carried_over() {
+        let opts = Options {
+            poke_message_budget: 700,
+            poke_weight_signature: 4,
+            poke_weight_presignature: 2,
+            poke_weight_triple: 1,
+            ..Default::default()
+        };
+        let mut tick = PokeTick::new(&opts, 0);
+        tick.spend(PokeKind::Signature, 0);
+        assert_eq!(tick.budget(PokeKind::Presignature), 600);
+        tick.spend(PokeKind::Presignature, 100);
+        assert_eq!(tick.budget(PokeKind::Triple), 600);
+    }
+
+    #[test]
+    fn test_back_pressure_only_throttles_background() {
+        let opts = Options {
+            poke_message_budget: 700,
+            poke_weight_signature: 4,
+            poke_weight_presignature: 2,
+            poke_weight_triple: 1,
+            ..Default::default()
+        };
+        let mut tick = PokeTick::new(&opts, 650);
+        assert_eq!(tick.budget(PokeKind::Signature), 400);
+        tick.spend(PokeKind::Signature, 400);
+        assert_eq!(tick.budget(PokeKind::Presignature), 50);
+        tick.spend(PokeKind::Presignature, 50);
+        assert_eq!(tick.budget(PokeKind::Triple), 0);
+    }
+
+    #[test]
+    fn test_token_bucket_paces_starts() {
+        let mut bucket = TokenBucket::new(2.0, 4);
+        let start = bucket.last_refill;
+        assert_eq!(bucket.available(start), 4);
+        bucket.take(4);
+        assert_eq!(bucket.available(start), 0);
+        assert_eq!(bucket.available(start + Duration::from_millis(500)), 1);
+        assert_eq!(bucket.available(start + Duration::from_secs(10)), 4);
+        assert_eq!(TokenBucket::unlimited().available(start), usize::MAX);
+    }
+
+    #[test]
+    fn test_round_timer_splits_poking_from_waiting() {
+        let mut rounds = RoundTimer::default();
+        rounds.resume();
+        std::thread::sleep(Duration::from_millis(5));
+        rounds.pause(true);
+        std::thread::sleep(Duration::from_millis(20));
+        rounds.resume();
+        rounds.pause(false);
+
+        assert_eq!(rounds.waits.len(), 1);
+        assert!(rounds.waits[0] >= Duration::from_millis(20));
+        assert!(rounds.poking() >= Duration::from_millis(5));
+        rounds.observe(PokeKind::Triple, "p-0.testnet", true);
+        assert!(rounds.waits.is_empty());
+    }
+}
@@ -1,25 +1,43 @@
 use super::contract::primitives::Participants;
+use super::generator;
 use super::message::SignatureMessage;
+use super::participation::{self, Outcome};
 use super::presignature::{GenerationError, Presignature, PresignatureId, PresignatureManager};
+use super::publisher::{Publisher, ToPublish};
+use super::registry::{self, ParticipantRegistry, SharedRegistry};
+use super::scheduler::{PokeFailure, PokeKind, PokeOutcome, RoundTimer};
+use super::validation::{PendingRequest, Validators};
+use crate::audit::{Lineage, Precomputation};
+use crate::clock::{self, SharedClock};
+use crate::events::{self, NodeEvent};
 use crate::indexer::ContractSignRequest;
-use crate::kdf::{derive_delta, into_eth_sig};
+use crate::journal::{self, Event, ProtocolKind};
+use crate::kdf::{cached_key, derive_delta, into_eth_sig};
+use crate::keyspace::KeyspaceId;
+use crate::redact::UserScalar;
+use crate::storage::sign_request_storage::SignRequestState;
+use crate::telemetry;
 use crate::types::SignatureProtocol;
 use crate::util::AffinePointExt;
 use near_primitives::hash::CryptoHash;
+use near_sdk::NearToken;
 
-use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
+use cait_sith::protocol::{Action, InitializationError, Participant};
 use cait_sith::{FullSignature, PresignOutput};
 use chrono::Utc;
+use crypto_shared::PublicKey;
 use crypto_shared::SerializableScalar;
-use crypto_shared::{derive_key, PublicKey};
-use k256::{Scalar, Secp256k1};
+use k256::{AffinePoint, Scalar, Secp256k1};
 use mpc_contract::config::ProtocolConfig;
 use mpc_contract::primitives::SignatureRequest;
 use rand::rngs::StdRng;
 use rand::seq::{IteratorRandom, SliceRandom};
 use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::cmp::Reverse;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::time::{Duration, Instant};
 
 use near_account_id::AccountId;
@@ -27,25 +45,114 @@ use near_fetch::signer::SignerExt;
 
 pub type ReceiptId = near_primitives::hash::CryptoHash;
 
+/// How long a sign request can wait to be matched with a presignature before it expires. This
+/// matches the default total timeout of a signature generation.
+pub const SIGN_REQUEST_DEADLINE: Duration = Duration::from_secs(200);
+
+/// Priority of a sign request. Higher priority requests are matched with presignatures first.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum SignPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Deposit attached to a sign request from which it is matched with a presignature first. The
+/// contract refunds whatever is attached over its required deposit once the request is answered,
+/// such that the deposit is only held for the duration of the request.
+pub const HIGH_PRIORITY_DEPOSIT: NearToken = NearToken::from_near(1);
+
+impl SignPriority {
+    /// The priority of a sign request to which `deposit` was attached.
+    pub fn from_deposit(deposit: NearToken) -> Self {
+        if deposit >= HIGH_PRIORITY_DEPOSIT {
+            Self::High
+        } else {
+            Self::Normal
+        }
+    }
+}
+
 pub struct SignRequest {
     pub receipt_id: ReceiptId,
     pub request: ContractSignRequest,
     pub epsilon: Scalar,
     pub entropy: [u8; 32],
     pub time_added: Instant,
+    pub priority: SignPriority,
+    /// The request expires if it has not been matched with a presignature by this time.
+    pub deadline: Instant,
+    /// Receipts of other requests for the same payload and epsilon merged into this one, which
+    /// get answered with the same signature, see [`SignQueue::add`].
+    pub merged: Vec<ReceiptId>,
+}
+
+impl SignRequest {
+    /// Whether `other` asks for the same signature, regardless of its receipt.
+    fn is_same_signature(&self, other: &SignRequest) -> bool {
+        self.request.payload == other.request.payload && self.epsilon == other.epsilon
+    }
+
+    /// Whether this request answers the given receipt, itself or through a merged request.
+    fn answers(&self, receipt_id: &ReceiptId) -> bool {
+        self.receipt_id == *receipt_id || self.merged.contains(receipt_id)
+    }
+
+    /// The receipts answered by this request, its own and the merged ones.
+    fn receipts(&self) -> impl Iterator<Item = ReceiptId> + '_ {
+        std::iter::once(self.receipt_id).chain(self.merged.iter().copied())
+    }
+
+    /// Merges two requests for the same signature. The request with the lowest receipt id leads,
+    /// such that every node ends up with the same proposer regardless of the order the requests
+    /// were indexed in. The merged request keeps the highest priority and the latest deadline.
+    fn merge(self, other: SignRequest) -> SignRequest {
+        let (mut lead, other) = if other.receipt_id < self.receipt_id {
+            (other, self)
+        } else {
+            (self, other)
+        };
+        lead.merged.push(other.receipt_id);
+        lead.merged.extend(other.merged);
+        lead.merged.sort();
+        lead.merged.dedup();
+        lead.time_added = lead.time_added.min(other.time_added);
+        lead.priority = lead.priority.max(other.priority);
+        lead.deadline = lead.deadline.max(other.deadline);
+        lead
+    }
 }
 
-/// Type that preserves the insertion order of requests.
+#[derive(Debug, thiserror::Error)]
+pub enum SignRequestError {
+    #[error("sign request {receipt_id} expired after waiting {waited:?} for a presignature")]
+    Expired {
+        receipt_id: ReceiptId,
+        proposer: Participant,
+        priority: SignPriority,
+        waited: Duration,
+    },
+}
+
+/// Type that orders requests by priority, then by deadline, then by insertion order.
 #[derive(Default)]
 pub struct ParticipantRequests {
     requests: HashMap<ReceiptId, SignRequest>,
-    order: VecDeque<ReceiptId>,
+    order: BTreeMap<(Reverse<SignPriority>, Instant, u64), ReceiptId>,
+    inserted: u64,
 }
 
 impl ParticipantRequests {
     fn insert(&mut self, receipt_id: ReceiptId, request: SignRequest) {
+        self.order.insert(
+            (Reverse(request.priority), request.deadline, self.inserted),
+            receipt_id,
+        );
+        self.inserted += 1;
         self.requests.insert(receipt_id, request);
-        self.order.push_back(receipt_id);
     }
 
     fn contains_key(&self, receipt_id: &ReceiptId) -> bool {
@@ -60,11 +167,56 @@ impl ParticipantRequests {
         self.len() == 0
     }
 
+    /// Pops the request with the highest priority, and the earliest deadline among those.
     pub fn pop_front(&mut self) -> Option<(ReceiptId, SignRequest)> {
-        let receipt_id = self.order.pop_front()?;
-        self.requests
-            .remove(&receipt_id)
-            .map(|req| (receipt_id, req))
+        while let Some((_, receipt_id)) = self.order.pop_first() {
+            // The request could have been replaced by a later insert with the same receipt id.
+            if let Some(request) = self.requests.remove(&receipt_id) {
+                return Some((receipt_id, request));
+            }
+        }
+        None
+    }
+
+    /// Removes and returns the requests matching the predicate.
+    fn take_matching(
+        &mut self,
+        mut predicate: impl FnMut(&SignRequest) -> bool,
+    ) -> Vec<(ReceiptId, SignRequest)> {
+        let matching = self
+            .requests
+            .iter()
+            .filter(|(_, request)| predicate(request))
+            .map(|(receipt_id, _)| *receipt_id)
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            return Vec::new();
+        }
+        self.order
+            .retain(|_, receipt_id| !matching.contains(receipt_id));
+        matching
+            .into_iter()
+            .filter_map(|receipt_id| Some((receipt_id, self.requests.remove(&receipt_id)?)))
+            .collect()
+    }
+
+    /// Removes and returns the requests whose deadline has passed.
+    fn expire(&mut self, now: Instant) -> Vec<SignRequest> {
+        let expired = self
+            .requests
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(receipt_id, _)| *receipt_id)
+            .collect::<Vec<_>>();
+        if expired.is_empty() {
+            return Vec::new();
+        }
+        self.order
+            .retain(|_, receipt_id| !expired.contains(receipt_id));
+        expired
+            .iter()
+            .filter_map(|receipt_id| self.requests.remove(receipt_id))
+            .collect()
     }
 }
 
@@ -72,6 +224,8 @@ impl ParticipantRequests {
 pub struct SignQueue {
     unorganized_requests: Vec<SignRequest>,
     requests: HashMap<Participant, ParticipantRequests>,
+    /// Transitions of the requests to be persisted, see [`SignQueue::take_transitions`].
+    transitions: Vec<(ReceiptId, SignRequestState)>,
 }
 
 impl SignQueue {
@@ -87,13 +241,63 @@ impl SignQueue {
         self.len() == 0
     }
 
+    /// All the requests waiting for a presignature, organized or not.
+    pub fn requests(&self) -> impl Iterator<Item = &SignRequest> {
+        self.unorganized_requests.iter().chain(
+            self.requests
+                .values()
+                .flat_map(|requests| requests.requests.values()),
+        )
+    }
+
+    /// Queues the request. A request that is already queued, e.g. delivered again by another
+    /// indexer or a replay, is dropped. A request with another receipt for the same payload and
+    /// epsilon is merged with the queued one, such that a single signature answers both.
     pub fn add(&mut self, request: SignRequest) {
+        if self
+            .requests()
+            .any(|queued| queued.answers(&request.receipt_id))
+        {
+            tracing::debug!(
+                receipt_id = %request.receipt_id,
+                "dropping sign request that is already queued"
+            );
+            return;
+        }
         tracing::info!(
             receipt_id = %request.receipt_id,
             payload = hex::encode(request.request.payload.to_bytes()),
             entropy = hex::encode(request.entropy),
+            priority = ?request.priority,
             "new sign request"
         );
+
+        // The merged request is organized again, since its lead and thus its proposer may change.
+        let queued = match self
+            .unorganized_requests
+            .iter()
+            .position(|queued| queued.is_same_signature(&request))
+        {
+            Some(pos) => Some(self.unorganized_requests.remove(pos)),
+            None => self.requests.values_mut().find_map(|requests| {
+                requests
+                    .take_matching(|queued| queued.is_same_signature(&request))
+                    .pop()
+                    .map(|(_, queued)| queued)
+            }),
+        };
+        let request = match queued {
+            Some(queued) => {
+                let request = queued.merge(request);
+                tracing::info!(
+                    receipt_id = %request.receipt_id,
+                    merged = ?request.merged,
+                    "merged sign requests for the same signature"
+                );
+                request
+            }
+            None => request,
+        };
         self.unorganized_requests.push(request);
     }
 
@@ -116,7 +320,13 @@ impl SignQueue {
         for request in self.unorganized_requests.drain(..) {
             let mut rng = StdRng::from_seed(request.entropy);
             let subset = stable.keys().choose_multiple(&mut rng, threshold);
-            let proposer = **subset.choose(&mut rng).unwrap();
+            let Some(&&proposer) = subset.choose(&mut rng) else {
+                tracing::error!(
+                    threshold,
+                    "unable to choose a proposer out of an empty subset"
+                );
+                continue;
+            };
             if subset.contains(&&me) {
                 let is_mine = proposer == me;
                 tracing::info!(
@@ -141,6 +351,10 @@ impl SignQueue {
                     ?proposer,
                     "skipping sign request: node is NOT in the signer subset"
                 );
+                for receipt_id in request.receipts() {
+                    self.transitions
+                        .push((receipt_id, SignRequestState::NotSelected));
+                }
             }
         }
     }
@@ -155,6 +369,37 @@ impl SignQueue {
     pub fn my_requests(&mut self, me: Participant) -> &mut ParticipantRequests {
         self.requests.entry(me).or_default()
     }
+
+    /// Removes the organized requests that were not matched with a presignature before their
+    /// deadline, returning an error for each of them.
+    pub fn expire(&mut self) -> Vec<SignRequestError> {
+        let now = Instant::now();
+        let mut errors = Vec::new();
+        for (proposer, requests) in self.requests.iter_mut() {
+            for request in requests.expire(now) {
+                for receipt_id in request.receipts() {
+                    self.transitions.push((
+                        receipt_id,
+                        SignRequestState::Failed {
+                            reason: "expired".to_string(),
+                        },
+                    ));
+                }
+                errors.push(SignRequestError::Expired {
+                    receipt_id: request.receipt_id,
+                    proposer: *proposer,
+                    priority: request.priority,
+                    waited: request.time_added.elapsed(),
+                });
+            }
+        }
+        errors
+    }
+
+    /// Takes the transitions of the requests since the last call, to be persisted.
+    pub fn take_transitions(&mut self) -> Vec<(ReceiptId, SignRequestState)> {
+        std::mem::take(&mut self.transitions)
+    }
 }
 
 /// An ongoing signature generator.
@@ -171,9 +416,32 @@ pub struct SignatureGenerator {
     pub generator_timestamp: Instant,
     pub timeout: Duration,
     pub timeout_total: Duration,
+    /// Time spent poking versus waiting between rounds.
+    pub rounds: RoundTimer,
+    /// Span the logs about this signature are recorded in, see [`crate::telemetry`].
+    pub span: tracing::Span,
+    /// The presignature consumed, recorded in the [`crate::audit`] trail once published.
+    pub precomputation: Precomputation,
+    /// Clock the timeouts are measured against, the one of the manager.
+    pub clock: SharedClock,
 }
 
 impl SignatureGenerator {
+    /// Whether this generator signs `request` with the given presignature. A signing protocol is
+    /// bound to a single presignature and request for its whole lifetime.
+    pub fn is_bound_to(
+        &self,
+        proposer: Participant,
+        presignature_id: PresignatureId,
+        request: &ContractSignRequest,
+        epsilon: Scalar,
+    ) -> bool {
+        self.proposer == proposer
+            && self.presignature_id == presignature_id
+            && &self.request == request
+            && self.epsilon == epsilon
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         protocol: SignatureProtocol,
@@ -185,7 +453,9 @@ impl SignatureGenerator {
         receipt_id: CryptoHash,
         entropy: [u8; 32],
         sign_request_timestamp: Instant,
+        precomputation: Precomputation,
         cfg: &ProtocolConfig,
+        clock: SharedClock,
     ) -> Self {
         Self {
             protocol,
@@ -197,27 +467,91 @@ impl SignatureGenerator {
             receipt_id,
             entropy,
             sign_request_timestamp,
-            generator_timestamp: Instant::now(),
+            generator_timestamp: clock.now(),
             timeout: Duration::from_millis(cfg.signature.generation_timeout),
             timeout_total: Duration::from_millis(cfg.signature.generation_timeout_total),
+            rounds: RoundTimer::default(),
+            span: telemetry::signature_span(&receipt_id, presignature_id, proposer),
+            precomputation,
+            clock,
         }
     }
 
-    pub fn poke(&mut self) -> Result<Action<FullSignature<Secp256k1>>, ProtocolError> {
-        if self.sign_request_timestamp.elapsed() > self.timeout_total {
-            let msg = "signature protocol timed out completely";
-            tracing::warn!(msg);
-            return Err(ProtocolError::Other(anyhow::anyhow!(msg).into()));
+    pub fn lineage(&self, epoch: u64) -> Lineage {
+        Lineage {
+            epoch,
+            proposer: self.proposer,
+            participants: self.participants.clone(),
+            presignature: self.precomputation.clone(),
         }
+    }
 
-        if self.generator_timestamp.elapsed() > self.timeout {
-            tracing::warn!(self.presignature_id, "signature protocol timed out");
-            return Err(ProtocolError::Other(
-                anyhow::anyhow!("signature protocol timeout").into(),
-            ));
+    pub fn is_timed_out(&self) -> bool {
+        self.clock.elapsed(self.sign_request_timestamp) > self.timeout_total
+            || self.clock.elapsed(self.generator_timestamp) > self.timeout
+    }
+
+    pub fn poke(&mut self) -> Result<Action<FullSignature<Secp256k1>>, PokeFailure> {
+        let elapsed = self.clock.elapsed(self.sign_request_timestamp);
+        if elapsed > self.timeout_total {
+            tracing::warn!("signature protocol timed out completely");
+            return Err(PokeFailure::TimedOut(elapsed));
+        }
+
+        let elapsed = self.clock.elapsed(self.generator_timestamp);
+        if elapsed > self.timeout {
+            tracing::warn!("signature protocol timed out");
+            return Err(PokeFailure::TimedOut(elapsed));
+        }
+
+        Ok(self.protocol.poke()?)
+    }
+
+    /// Verifies the signature produced by the protocol against the key derived for the request
+    /// and its payload, such that an invalid signature never makes it on chain.
+    fn verify(
+        &self,
+        epoch: u64,
+        public_key: PublicKey,
+        output: &FullSignature<Secp256k1>,
+    ) -> Result<(), PokeFailure> {
+        let expected_public_key = cached_key(public_key, self.epsilon);
+        if let Err(err) = into_eth_sig(
+            &expected_public_key,
+            &output.big_r,
+            &output.s,
+            self.request.payload,
+        ) {
+            tracing::error!(
+                receipt_id = ?self.receipt_id,
+                epoch,
+                proposer = ?self.proposer,
+                presignature_id = self.presignature_id,
+                participants = ?self.participants,
+                request = ?self.request,
+                epsilon = ?UserScalar(&self.epsilon),
+                entropy = ?self.entropy,
+                expected_public_key = ?expected_public_key.to_base58(),
+                big_r = ?output.big_r.to_base58(),
+                s = ?output.s,
+                ?err,
+                "signature protocol produced an invalid signature"
+            );
+            return Err(PokeFailure::InvalidSignature(err.to_string()));
         }
+        Ok(())
+    }
 
-        self.protocol.poke()
+    /// The request this generator was started for, such that it can be retried later on.
+    fn generation_request(&self) -> GenerationRequest {
+        GenerationRequest {
+            proposer: self.proposer,
+            request: self.request.clone(),
+            epsilon: self.epsilon,
+            receipt_id: self.receipt_id,
+            entropy: self.entropy,
+            sign_request_timestamp: self.sign_request_timestamp,
+        }
     }
 }
 
@@ -232,44 +566,140 @@ pub struct GenerationRequest {
     pub sign_request_timestamp: Instant,
 }
 
+/// Key of a signature in the cache of completed signatures: the hash of the payload, of the
+/// derivation of the path of the requester (epsilon), and of the epoch the signature was produced
+/// in. The same request submitted again has the same key regardless of its receipt.
+type SignatureCacheKey = [u8; 32];
+
+fn signature_cache_key(payload: &Scalar, epsilon: &Scalar, epoch: u64) -> SignatureCacheKey {
+    Sha3_256::new()
+        .chain_update(payload.to_bytes())
+        .chain_update(epsilon.to_bytes())
+        .chain_update(epoch.to_le_bytes())
+        .finalize()
+        .into()
+}
+
+/// A signature completed by this node, kept to answer requests submitted again.
+struct CachedSignature {
+    big_r: AffinePoint,
+    s: Scalar,
+    at: Instant,
+    lineage: Lineage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureSummary {
+    pub receipt_id: String,
+    pub presignature_id: PresignatureId,
+    pub proposer: Participant,
+    pub mine: bool,
+    pub participants: Vec<Participant>,
+    /// Milliseconds since the sign request was first seen by this node.
+    pub request_age_ms: u128,
+    /// Milliseconds since the current generator started.
+    pub generator_age_ms: u128,
+}
+
+/// Summary of the state of a [`SignatureManager`], see [`SignatureManager::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureSnapshot {
+    pub epoch: u64,
+    /// Ongoing signature generators, ordered by receipt id.
+    pub generators: Vec<SignatureSummary>,
+    /// Number of requests of this node waiting to be retried.
+    pub failed: usize,
+    /// Number of completed signatures still tracked to drop their late messages.
+    pub completed: usize,
+    /// Number of signatures waiting to be published on chain.
+    pub to_publish: usize,
+    /// Number of signatures cached to answer repeated requests.
+    pub cached: usize,
+}
+
 pub struct SignatureManager {
     /// Ongoing signature generation protocols.
     generators: HashMap<ReceiptId, SignatureGenerator>,
+    /// First generator skipped for lack of budget by the last poke, the next poke resuming from it.
+    poke_cursor: Option<ReceiptId>,
     /// Failed signatures awaiting to be retried.
     failed: VecDeque<(ReceiptId, GenerationRequest)>,
     /// Set of completed signatures
     completed: HashMap<ReceiptId, Instant>,
+    /// Completed signatures by request content, such that requests submitted again, by an
+    /// indexer replay or a client retry, do not consume another presignature.
+    cache: HashMap<SignatureCacheKey, CachedSignature>,
     /// Generated signatures assigned to the current node that are yet to be published.
-    /// Vec<(receipt_id, msg_hash, timestamp, output)>
-    signatures: Vec<ToPublish>,
+    publisher: Publisher,
+    /// Receipts of the requests merged into the requests being signed by this node, keyed by the
+    /// receipt of the request they were merged into, see [`SignQueue::add`].
+    merged: HashMap<ReceiptId, Vec<ReceiptId>>,
+    /// Transitions of the requests to be persisted, see [`SignatureManager::take_transitions`].
+    transitions: Vec<(ReceiptId, SignRequestState)>,
+    /// Keyspace of the root key the signatures are produced with, see [`crate::keyspace`].
+    keyspace: KeyspaceId,
     me: Participant,
     public_key: PublicKey,
     epoch: u64,
     my_account_id: AccountId,
+    /// Clock the timeouts and the garbage collection are measured against.
+    clock: SharedClock,
+    /// Participants of the contract, against which the proposers of protocols are validated.
+    registry: SharedRegistry,
+    /// Validators the requests go through before consuming a presignature.
+    validators: Validators,
 }
 
-pub const MAX_RETRY: u8 = 10;
-pub struct ToPublish {
-    receipt_id: ReceiptId,
-    request: SignatureRequest,
-    time_added: Instant,
-    signature: FullSignature<Secp256k1>,
-    retry_count: u8,
-}
+impl SignatureManager {
+    /// Keyspace of the root key the signatures are produced with.
+    pub fn keyspace(&self) -> &KeyspaceId {
+        &self.keyspace
+    }
 
-impl ToPublish {
-    pub fn new(
-        receipt_id: ReceiptId,
-        request: SignatureRequest,
-        time_added: Instant,
-        signature: FullSignature<Secp256k1>,
-    ) -> ToPublish {
-        ToPublish {
-            receipt_id,
-            request,
-            time_added,
-            signature,
-            retry_count: 0,
+    /// Returns the ongoing signature generation protocols.
+    pub fn generators(&self) -> &HashMap<ReceiptId, SignatureGenerator> {
+        &self.generators
+    }
+
+    /// Returns the number of completed signatures still tracked by the manager.
+    pub fn completed_len(&self) -> usize {
+        self.completed.len()
+    }
+
+    /// Returns the number of generated signatures that are yet to be published.
+    pub fn to_publish_len(&self) -> usize {
+        self.publisher.len()
+    }
+
+    /// Summarizes the generators and requests held by the manager.
+    pub fn snapshot(&self) -> SignatureSnapshot {
+        let mut generators = self
+            .generators
+            .iter()
+            .map(|(receipt_id, generator)| SignatureSummary {
+                receipt_id: receipt_id.to_string(),
+                presignature_id: generator.presignature_id,
+                proposer: generator.proposer,
+                mine: generator.proposer == self.me,
+                participants: generator.participants.clone(),
+                request_age_ms: self
+                    .clock
+                    .elapsed(generator.sign_request_timestamp)
+                    .as_millis(),
+                generator_age_ms: self
+                    .clock
+                    .elapsed(generator.generator_timestamp)
+                    .as_millis(),
+            })
+            .collect::<Vec<_>>();
+        generators.sort_by(|a, b| a.receipt_id.cmp(&b.receipt_id));
+        SignatureSnapshot {
+            epoch: self.epoch,
+            generators,
+            failed: self.failed.len(),
+            completed: self.completed.len(),
+            to_publish: self.publisher.len(),
+            cached: self.cache.len(),
         }
     }
 }
@@ -283,16 +713,47 @@ impl SignatureManager {
     ) -> Self {
         Self {
             generators: HashMap::new(),
+            poke_cursor: None,
             failed: VecDeque::new(),
             completed: HashMap::new(),
-            signatures: Vec::new(),
+            cache: HashMap::new(),
+            publisher: Publisher::default(),
+            merged: HashMap::new(),
+            transitions: Vec::new(),
+            keyspace: KeyspaceId::root(),
             me,
             public_key,
             epoch,
             my_account_id: my_account_id.clone(),
+            clock: clock::monotonic(),
+            registry: ParticipantRegistry::shared(),
+            validators: Validators::default(),
         }
     }
 
+    /// Measures the timeouts of this manager and its generators against `clock` instead of the
+    /// monotonic clock of the operating system.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Validates the proposers of protocols against the shared registry of participants.
+    pub fn with_registry(mut self, registry: SharedRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Runs the requests through `validators` instead of the built-in ones.
+    pub fn with_validators(mut self, validators: Validators) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    pub fn set_validators(&mut self, validators: Validators) {
+        self.validators = validators;
+    }
+
     pub fn failed_len(&self) -> usize {
         self.failed.len()
     }
@@ -301,6 +762,43 @@ impl SignatureManager {
         self.me
     }
 
+    /// Drops the ongoing generators that involve participants which are no longer part of the
+    /// participant set. Requests proposed by this node are pushed back into the failed queue so
+    /// that they get retried with the remaining participants.
+    pub fn update_participants(&mut self, participants: &Participants) {
+        let me = self.me;
+        let failed = &mut self.failed;
+        let transitions = &mut self.transitions;
+        let merged = &self.merged;
+        self.generators.retain(|receipt_id, generator| {
+            if generator
+                .participants
+                .iter()
+                .all(|p| participants.contains_key(p))
+            {
+                return true;
+            }
+            tracing::info!(
+                %receipt_id,
+                proposer = ?generator.proposer,
+                "dropping signature generator involving removed participants"
+            );
+            if generator.proposer == me {
+                failed.push_back((*receipt_id, generator.generation_request()));
+            }
+            transition(transitions, merged, *receipt_id, SignRequestState::Indexed);
+            false
+        });
+    }
+
+    /// Takes the transitions of the requests since the last call, including the ones of the
+    /// signatures published or given up on, to be persisted.
+    pub fn take_transitions(&mut self) -> Vec<(ReceiptId, SignRequestState)> {
+        let mut transitions = std::mem::take(&mut self.transitions);
+        transitions.extend(self.publisher.take_settled());
+        transitions
+    }
+
     #[allow(clippy::too_many_arguments)]
     #[allow(clippy::result_large_err)]
     fn generate_internal(
@@ -310,6 +808,7 @@ impl SignatureManager {
         presignature: Presignature,
         req: GenerationRequest,
         cfg: &ProtocolConfig,
+        clock: SharedClock,
     ) -> Result<SignatureGenerator, (Presignature, InitializationError)> {
         let participants = participants.keys_vec();
         let GenerationRequest {
@@ -320,20 +819,27 @@ impl SignatureManager {
             entropy,
             sign_request_timestamp,
         } = req;
-        let PresignOutput { big_r, k, sigma } = presignature.output;
+        let PresignOutput { big_r, k, sigma } = presignature.output.clone();
         let delta = derive_delta(receipt_id, entropy, big_r);
         // TODO: Check whether it is okay to use invert_vartime instead
+        let Some(delta_inv) = Option::<Scalar>::from(delta.invert()) else {
+            return Err((
+                presignature,
+                InitializationError::BadParameters("delta is not invertible".to_string()),
+            ));
+        };
         let output: PresignOutput<Secp256k1> = PresignOutput {
             big_r: (big_r * delta).to_affine(),
-            k: k * delta.invert().unwrap(),
-            sigma: (sigma + epsilon * k) * delta.invert().unwrap(),
+            k: k * delta_inv,
+            sigma: (sigma + epsilon * k) * delta_inv,
         };
         let presignature_id = presignature.id;
+        let precomputation = Precomputation::of(&presignature);
         let protocol = Box::new(
             cait_sith::sign(
                 &participants,
                 me,
-                derive_key(public_key, epsilon),
+                cached_key(public_key, epsilon),
                 output,
                 request.payload,
             )
@@ -349,7 +855,9 @@ impl SignatureManager {
             receipt_id,
             entropy,
             sign_request_timestamp,
+            precomputation,
             cfg,
+            clock,
         ))
     }
 
@@ -370,10 +878,27 @@ impl SignatureManager {
             presignature,
             req,
             cfg,
+            self.clock.clone(),
         )?;
         crate::metrics::NUM_TOTAL_HISTORICAL_SIGNATURE_GENERATORS
             .with_label_values(&[self.my_account_id.as_str()])
             .inc();
+        journal::record(
+            self.epoch,
+            ProtocolKind::Signature,
+            receipt_id,
+            Event::Started {
+                participants: generator.participants.clone(),
+            },
+        );
+        transition(
+            &mut self.transitions,
+            &self.merged,
+            receipt_id,
+            SignRequestState::Signing {
+                presignature_id: generator.presignature_id,
+            },
+        );
         self.generators.insert(receipt_id, generator);
         Ok(())
     }
@@ -413,10 +938,27 @@ impl SignatureManager {
                 sign_request_timestamp,
             },
             cfg,
+            self.clock.clone(),
         )?;
         crate::metrics::NUM_TOTAL_HISTORICAL_SIGNATURE_GENERATORS
             .with_label_values(&[self.my_account_id.as_str()])
             .inc();
+        journal::record(
+            self.epoch,
+            ProtocolKind::Signature,
+            receipt_id,
+            Event::Started {
+                participants: generator.participants.clone(),
+            },
+        );
+        transition(
+            &mut self.transitions,
+            &self.merged,
+            receipt_id,
+            SignRequestState::Signing {
+                presignature_id: generator.presignature_id,
+            },
+        );
         self.generators.insert(receipt_id, generator);
         Ok(())
     }
@@ -425,10 +967,10 @@ impl SignatureManager {
     /// 1) Already generated in which case returns `None`, or
     /// 2) Is currently being generated by `protocol` in which case returns `Some(protocol)`, or
     /// 3) Has never been seen by the manager in which case start a new protocol and returns `Some(protocol)`, or
-    /// 4) Depends on triples (`triple0`/`triple1`) that are unknown to the node
-    // TODO: What if the presignature completed generation and is already spent?
+    /// 4) Depends on triples (`triple0`/`triple1`) that are unknown to the node, or
+    /// 5) Depends on a presignature that has already been spent in which case returns `AlreadySpent`
     #[allow(clippy::too_many_arguments)]
-    pub fn get_or_generate(
+    pub async fn get_or_generate(
         &mut self,
         participants: &Participants,
         receipt_id: ReceiptId,
@@ -446,9 +988,22 @@ impl SignatureManager {
         }
         match self.generators.entry(receipt_id) {
             Entry::Vacant(entry) => {
+                if let Err(err) = registry::read(&self.registry).validate(&proposer) {
+                    tracing::warn!(%receipt_id, %err, "signature proposed by a participant not in the contract");
+                    return Err(GenerationError::UnknownProposer(proposer));
+                }
+                if let Err(rejection) = self.validators.validate(&PendingRequest {
+                    receipt_id,
+                    request,
+                    epsilon,
+                    public_key: &self.public_key,
+                }) {
+                    tracing::warn!(%receipt_id, ?proposer, %rejection, "refusing to join signature of a rejected request");
+                    return Err(GenerationError::RequestRejected(rejection));
+                }
                 tracing::info!(%receipt_id, me = ?self.me, presignature_id, "joining protocol to generate a new signature");
-                let presignature = match presignature_manager.take(presignature_id) {
-                    Ok(presignature) => presignature,
+                let slot = match presignature_manager.reserve(presignature_id) {
+                    Ok(slot) => slot,
                     Err(err @ GenerationError::PresignatureIsGenerating(_)) => {
                         tracing::warn!(me = ?self.me, presignature_id, "presignature is generating, can't join signature generation protocol");
                         return Err(err);
@@ -461,6 +1016,10 @@ impl SignatureManager {
                         tracing::warn!(me = ?self.me, presignature_id, "presignature is garbage collected, can't join signature generation protocol");
                         return Err(err);
                     }
+                    Err(err @ GenerationError::AlreadySpent(_)) => {
+                        tracing::warn!(me = ?self.me, presignature_id, "presignature is already spent, can't join signature generation protocol");
+                        return Err(err);
+                    }
                     Err(err) => return Err(err),
                 };
                 tracing::info!(me = ?self.me, presignature_id, "found presignature: ready to start signature generation");
@@ -468,143 +1027,323 @@ impl SignatureManager {
                     participants,
                     self.me,
                     self.public_key,
-                    presignature,
+                    slot.presignature().clone(),
                     GenerationRequest {
                         proposer,
                         request: request.clone(),
                         epsilon,
                         entropy,
                         receipt_id,
-                        sign_request_timestamp: Instant::now(),
+                        sign_request_timestamp: self.clock.now(),
                     },
                     cfg,
+                    self.clock.clone(),
                 ) {
-                    Ok(generator) => generator,
-                    Err((presignature, err @ InitializationError::BadParameters(_))) => {
-                        presignature_manager.insert_mine(presignature);
+                    Ok(generator) => {
+                        if let Err(err) = presignature_manager.spend(slot).await {
+                            tracing::warn!(%receipt_id, presignature_id, ?err, "presignature was lost before signature generation started");
+                            return Err(err);
+                        }
+                        generator
+                    }
+                    Err((_, err @ InitializationError::BadParameters(_))) => {
+                        presignature_manager.release(slot);
                         tracing::warn!(%receipt_id, presignature_id, ?err, "failed to start signature generation");
                         return Err(GenerationError::CaitSithInitializationError(err));
                     }
                 };
+                journal::record(
+                    self.epoch,
+                    ProtocolKind::Signature,
+                    receipt_id,
+                    Event::Started {
+                        participants: generator.participants.clone(),
+                    },
+                );
+                transition(
+                    &mut self.transitions,
+                    &self.merged,
+                    receipt_id,
+                    SignRequestState::Signing { presignature_id },
+                );
                 let generator = entry.insert(generator);
                 crate::metrics::NUM_TOTAL_HISTORICAL_SIGNATURE_GENERATORS
                     .with_label_values(&[self.my_account_id.as_str()])
                     .inc();
                 Ok(&mut generator.protocol)
             }
-            Entry::Occupied(entry) => Ok(&mut entry.into_mut().protocol),
+            Entry::Occupied(entry) => {
+                let generator = entry.into_mut();
+                if !generator.is_bound_to(proposer, presignature_id, request, epsilon) {
+                    tracing::warn!(
+                        %receipt_id,
+                        presignature_id,
+                        bound_presignature_id = generator.presignature_id,
+                        "signature messages do not match the presignature and request of the ongoing protocol"
+                    );
+                    return Err(GenerationError::SignatureBindingMismatch {
+                        receipt_id,
+                        presignature_id: generator.presignature_id,
+                    });
+                }
+                Ok(&mut generator.protocol)
+            }
         }
     }
 
-    /// Pokes all of the ongoing generation protocols and returns a vector of
-    /// messages to be sent to the respective participant.
-    ///
-    /// An empty vector means we cannot progress until we receive a new message.
-    pub fn poke(&mut self) -> Vec<(Participant, SignatureMessage)> {
+    /// Pokes all of the ongoing generation protocols, returning the messages to be sent to the
+    /// respective participants alongside the protocols that failed and were dropped. Failed
+    /// protocols proposed by this node are retried until the request times out.
+    pub fn poke(&mut self) -> PokeOutcome<ReceiptId, SignatureMessage> {
+        self.poke_with_budget(usize::MAX)
+    }
+
+    /// Same as [`SignatureManager::poke`], but stops poking further generators once `budget`
+    /// messages have been produced. The remaining generators get poked in a later call.
+    pub fn poke_with_budget(&mut self, budget: usize) -> PokeOutcome<ReceiptId, SignatureMessage> {
         let mut messages = Vec::new();
-        self.generators.retain(|receipt_id, generator| {
-            loop {
-                let action = match generator.poke() {
-                    Ok(action) => action,
-                    Err(err) => {
-                        if generator.proposer == self.me {
-                            if generator.sign_request_timestamp.elapsed() < generator.timeout_total {
-                                tracing::warn!(?err, "signature failed to be produced; pushing request back into failed queue");
-                                crate::metrics::SIGNATURE_GENERATOR_FAILURES
+        let mut failures = Vec::new();
+        let mut ids = self.generators.keys().copied().collect::<Vec<_>>();
+        generator::rotate(&mut ids, self.poke_cursor.take());
+        for receipt_id in ids {
+            if messages.len() >= budget {
+                // Out of budget for this poke, so it is retained to be poked first next time.
+                self.poke_cursor = Some(receipt_id);
+                break;
+            }
+            let Some(mut generator) = self.generators.remove(&receipt_id) else {
+                continue;
+            };
+            let retain = {
+                let (receipt_id, generator) = (&receipt_id, &mut generator);
+                generator.span.record("epoch", self.epoch);
+                let _span = generator.span.clone().entered();
+                generator.rounds.resume();
+                loop {
+                    let action = match generator.poke().and_then(|action| match action {
+                        Action::Return(output) => generator
+                            .verify(self.epoch, self.public_key, &output)
+                            .map(|_| Action::Return(output)),
+                        action => Ok(action),
+                    }) {
+                        Ok(action) => action,
+                        Err(err) => {
+                            generator.rounds.pause(false);
+                            generator.rounds.observe(
+                                PokeKind::Signature,
+                                self.my_account_id.as_str(),
+                                false,
+                            );
+                            let event = if err.is_timeout() {
+                                Event::TimedOut
+                            } else {
+                                Event::Failed {
+                                    reason: err.to_string(),
+                                }
+                            };
+                            journal::record(self.epoch, ProtocolKind::Signature, receipt_id, event);
+                            participation::record(
+                                self.my_account_id.as_str(),
+                                self.epoch,
+                                ProtocolKind::Signature,
+                                &generator.participants,
+                                if err.is_timeout() {
+                                    Outcome::TimedOut
+                                } else {
+                                    Outcome::Failed
+                                },
+                            );
+                            if err.is_timeout() {
+                                crate::metrics::SIGNATURE_GENERATOR_TIMEOUTS
+                                    .with_label_values(&[self.my_account_id.as_str()])
+                                    .inc();
+                            }
+                            if matches!(err, PokeFailure::InvalidSignature(_)) {
+                                crate::metrics::INVALID_SIGNATURES
                                     .with_label_values(&[self.my_account_id.as_str()])
                                     .inc();
-                                // only retry the signature generation if it was initially proposed by us. We do not
-                                // want any nodes to be proposing the same signature multiple times.
-                                self.failed.push_back((
+                            }
+                            if generator.proposer == self.me {
+                                if self.clock.elapsed(generator.sign_request_timestamp)
+                                    < generator.timeout_total
+                                {
+                                    tracing::warn!(?err, "signature failed to be produced; pushing request back into failed queue");
+                                    crate::metrics::SIGNATURE_GENERATOR_FAILURES
+                                        .with_label_values(&[self.my_account_id.as_str()])
+                                        .inc();
+                                    // only retry the signature generation if it was initially proposed by us. We do not
+                                    // want any nodes to be proposing the same signature multiple times.
+                                    self.failed
+                                        .push_back((*receipt_id, generator.generation_request()));
+                                    transition(
+                                        &mut self.transitions,
+                                        &self.merged,
+                                        *receipt_id,
+                                        SignRequestState::Indexed,
+                                    );
+                                } else {
+                                    self.completed.insert(*receipt_id, self.clock.now());
+                                    transition(
+                                        &mut self.transitions,
+                                        &self.merged,
+                                        *receipt_id,
+                                        SignRequestState::Failed {
+                                            reason: err.to_string(),
+                                        },
+                                    );
+                                    self.merged.remove(receipt_id);
+                                    crate::metrics::SIGNATURE_FAILURES
+                                        .with_label_values(&[self.my_account_id.as_str()])
+                                        .inc();
+                                    tracing::warn!(
+                                        ?err,
+                                        "signature failed to be produced; trashing request"
+                                    );
+                                }
+                            } else {
+                                transition(
+                                    &mut self.transitions,
+                                    &self.merged,
                                     *receipt_id,
-                                    GenerationRequest {
+                                    SignRequestState::Indexed,
+                                );
+                            }
+                            failures.push((*receipt_id, err));
+                            break false;
+                        }
+                    };
+                    match action {
+                        Action::Wait => {
+                            tracing::debug!("signature: waiting");
+                            generator.rounds.pause(true);
+                            generator.rounds.observe(
+                                PokeKind::Signature,
+                                self.my_account_id.as_str(),
+                                false,
+                            );
+                            // Retain protocol until we are finished
+                            break true;
+                        }
+                        Action::SendMany(data) => {
+                            for p in generator.participants.iter() {
+                                messages.push((
+                                    *p,
+                                    SignatureMessage {
+                                        receipt_id: *receipt_id,
+                                        keyspace: self.keyspace.clone(),
                                         proposer: generator.proposer,
+                                        presignature_id: generator.presignature_id,
                                         request: generator.request.clone(),
                                         epsilon: generator.epsilon,
-                                        receipt_id: generator.receipt_id,
                                         entropy: generator.entropy,
-                                        sign_request_timestamp: generator.sign_request_timestamp
+                                        epoch: self.epoch,
+                                        from: self.me,
+                                        data: data.clone(),
+                                        timestamp: Utc::now().timestamp() as u64,
                                     },
-                                ));
-                            } else {
-                                self.completed.insert(*receipt_id, Instant::now());
-                                crate::metrics::SIGNATURE_FAILURES
-                                    .with_label_values(&[self.my_account_id.as_str()])
-                                    .inc();
-                                tracing::warn!(?err, "signature failed to be produced; trashing request");
+                                ))
                             }
                         }
-                        break false;
-                    }
-                };
-                match action {
-                    Action::Wait => {
-                        tracing::debug!("signature: waiting");
-                        // Retain protocol until we are finished
-                        return true;
-                    }
-                    Action::SendMany(data) => {
-                        for p in generator.participants.iter() {
-                            messages.push((
-                                *p,
-                                SignatureMessage {
-                                    receipt_id: *receipt_id,
-                                    proposer: generator.proposer,
-                                    presignature_id: generator.presignature_id,
-                                    request: generator.request.clone(),
-                                    epsilon: generator.epsilon,
-                                    entropy: generator.entropy,
-                                    epoch: self.epoch,
-                                    from: self.me,
-                                    data: data.clone(),
-                                    timestamp: Utc::now().timestamp() as u64
+                        Action::SendPrivate(p, data) => messages.push((
+                            p,
+                            SignatureMessage {
+                                receipt_id: *receipt_id,
+                                keyspace: self.keyspace.clone(),
+                                proposer: generator.proposer,
+                                presignature_id: generator.presignature_id,
+                                request: generator.request.clone(),
+                                epsilon: generator.epsilon,
+                                entropy: generator.entropy,
+                                epoch: self.epoch,
+                                from: self.me,
+                                data,
+                                timestamp: Utc::now().timestamp() as u64,
+                            },
+                        )),
+                        Action::Return(output) => {
+                            generator.rounds.pause(false);
+                            generator.rounds.observe(
+                                PokeKind::Signature,
+                                self.my_account_id.as_str(),
+                                true,
+                            );
+                            tracing::info!(
+                                big_r = ?output.big_r.to_base58(),
+                                s = ?output.s,
+                                "completed signature generation"
+                            );
+                            journal::record(
+                                self.epoch,
+                                ProtocolKind::Signature,
+                                receipt_id,
+                                Event::Completed,
+                            );
+                            participation::record(
+                                self.my_account_id.as_str(),
+                                self.epoch,
+                                ProtocolKind::Signature,
+                                &generator.participants,
+                                Outcome::Completed,
+                            );
+                            self.completed.insert(*receipt_id, self.clock.now());
+                            self.cache.insert(
+                                signature_cache_key(
+                                    &generator.request.payload,
+                                    &generator.epsilon,
+                                    self.epoch,
+                                ),
+                                CachedSignature {
+                                    big_r: output.big_r,
+                                    s: output.s,
+                                    at: self.clock.now(),
+                                    lineage: generator.lineage(self.epoch),
                                 },
-                            ))
-                        }
-                    }
-                    Action::SendPrivate(p, data) => messages.push((
-                        p,
-                        SignatureMessage {
-                            receipt_id: *receipt_id,
-                            proposer: generator.proposer,
-                            presignature_id: generator.presignature_id,
-                            request: generator.request.clone(),
-                            epsilon: generator.epsilon,
-                            entropy: generator.entropy,
-                            epoch: self.epoch,
-                            from: self.me,
-                            data,
-                            timestamp: Utc::now().timestamp() as u64
-                        },
-                    )),
-                    Action::Return(output) => {
-                        tracing::info!(
-                            ?receipt_id,
-                            me = ?self.me,
-                            presignature_id = generator.presignature_id,
-                            big_r = ?output.big_r.to_base58(),
-                            s = ?output.s,
-                            "completed signature generation"
-                        );
-                        self.completed.insert(*receipt_id, Instant::now());
-                        let request = SignatureRequest {
-                            epsilon: SerializableScalar {scalar: generator.epsilon},
-                            payload_hash: generator.request.payload.into(),
-                        };
-                        if generator.proposer == self.me {
-                            self.signatures
-                                .push(ToPublish::new(*receipt_id, request, generator.sign_request_timestamp, output));
+                            );
+                            let request = SignatureRequest {
+                                epsilon: SerializableScalar {
+                                    scalar: generator.epsilon,
+                                },
+                                payload_hash: generator.request.payload.into(),
+                            };
+                            // The requests proposed by this node are completed once their signature
+                            // is published, see [`Publisher::take_settled`].
+                            if generator.proposer == self.me {
+                                let merged = self.merged.remove(receipt_id).unwrap_or_default();
+                                for merged_id in &merged {
+                                    self.completed.insert(*merged_id, self.clock.now());
+                                }
+                                self.publisher.push(
+                                    ToPublish::new(
+                                        *receipt_id,
+                                        request,
+                                        generator.sign_request_timestamp,
+                                        output,
+                                    )
+                                    .with_merged(merged)
+                                    .with_lineage(generator.lineage(self.epoch)),
+                                );
+                            } else {
+                                transition(
+                                    &mut self.transitions,
+                                    &self.merged,
+                                    *receipt_id,
+                                    SignRequestState::Completed,
+                                );
+                            }
+                            // Do not retain the protocol
+                            break false;
                         }
-                        // Do not retain the protocol
-                        return false;
                     }
                 }
+            };
+            if retain {
+                self.generators.insert(receipt_id, generator);
             }
-        });
-        messages
+        }
+        PokeOutcome { messages, failures }
     }
 
-    pub fn handle_requests(
+    pub async fn handle_requests(
         &mut self,
         threshold: usize,
         stable: &Participants,
@@ -612,6 +1351,7 @@ impl SignatureManager {
         presignature_manager: &mut PresignatureManager,
         cfg: &ProtocolConfig,
     ) {
+        self.publish_cached(my_requests);
         if stable.len() < threshold {
             tracing::warn!(
                 "Require at least {} stable participants to handle_requests, got {}: {:?}",
@@ -626,7 +1366,7 @@ impl SignatureManager {
             if self.failed.is_empty() && my_requests.is_empty() {
                 None
             } else {
-                presignature_manager.take_mine()
+                presignature_manager.take_mine().await
             }
         } {
             let sig_participants = stable.intersection(&[&presignature.participants]);
@@ -655,21 +1395,36 @@ impl SignatureManager {
                     )
                 {
                     tracing::warn!(%receipt_id, presig_id, ?err, "failed to retry signature generation: trashing presignature");
+                    self.transition(
+                        receipt_id,
+                        SignRequestState::Failed {
+                            reason: err.to_string(),
+                        },
+                    );
                     failed_presigs.push(presignature);
                     continue;
                 }
 
-                if let Some(another_presignature) = presignature_manager.take_mine() {
+                if let Some(another_presignature) = presignature_manager.take_mine().await {
                     presignature = another_presignature;
                 } else {
                     break;
                 }
             }
 
-            let Some((receipt_id, my_request)) = my_requests.pop_front() else {
+            let Some((receipt_id, my_request)) = self.pop_valid(my_requests) else {
                 failed_presigs.push(presignature);
                 continue;
             };
+            if !my_request.merged.is_empty() {
+                self.merged.insert(receipt_id, my_request.merged);
+            }
+            self.transition(
+                receipt_id,
+                SignRequestState::Assigned {
+                    presignature_id: presig_id,
+                },
+            );
             if let Err((presignature, InitializationError::BadParameters(err))) = self.generate(
                 &sig_participants,
                 receipt_id,
@@ -681,6 +1436,13 @@ impl SignatureManager {
                 cfg,
             ) {
                 failed_presigs.push(presignature);
+                self.transition(
+                    receipt_id,
+                    SignRequestState::Failed {
+                        reason: err.to_string(),
+                    },
+                );
+                self.merged.remove(&receipt_id);
                 tracing::warn!(%receipt_id, presig_id, ?err, "failed to start signature generation: trashing presignature");
                 continue;
             }
@@ -689,97 +1451,120 @@ impl SignatureManager {
         // add back the failed presignatures that were incompatible to be made into
         // signatures due to failures or lack of participants.
         for presignature in failed_presigs {
-            presignature_manager.insert_mine(presignature);
+            presignature_manager.insert_mine(presignature).await;
         }
     }
 
-    pub async fn publish<T: SignerExt>(
+    /// Pops the next request that passes the validators. The rejected ones are given up on along
+    /// with the requests merged into them.
+    fn pop_valid(
         &mut self,
-        rpc_client: &near_fetch::Client,
-        signer: &T,
-        mpc_contract_id: &AccountId,
-    ) {
-        let mut to_retry: Vec<ToPublish> = Vec::new();
-
-        for mut to_publish in self.signatures.drain(..) {
-            let ToPublish {
+        my_requests: &mut ParticipantRequests,
+    ) -> Option<(ReceiptId, SignRequest)> {
+        while let Some((receipt_id, my_request)) = my_requests.pop_front() {
+            let Err(rejection) = self.validators.validate(&PendingRequest {
                 receipt_id,
-                request,
-                time_added,
-                signature,
-                ..
-            } = &to_publish;
-            let expected_public_key = derive_key(self.public_key, request.epsilon.scalar);
-            // We do this here, rather than on the client side, so we can use the ecrecover system function on NEAR to validate our signature
-            let Ok(signature) = into_eth_sig(
-                &expected_public_key,
-                &signature.big_r,
-                &signature.s,
-                request.payload_hash.scalar,
-            ) else {
-                tracing::error!(%receipt_id, "Failed to generate a recovery ID");
-                continue;
-            };
-            let response = match rpc_client
-                .call(signer, mpc_contract_id, "respond")
-                .args_json(serde_json::json!({
-                    "request": request,
-                    "response": signature,
-                }))
-                .max_gas()
-                .retry_exponential(10, 5)
-                .transact()
-                .await
-            {
-                Ok(response) => response,
-                Err(err) => {
-                    tracing::error!(%receipt_id, error = ?err, "Failed to publish the signature");
-                    crate::metrics::SIGNATURE_PUBLISH_FAILURES
-                        .with_label_values(&[self.my_account_id.as_str()])
-                        .inc();
-                    // Push the response to the back of the queue if it hasn't been retried the max number of times
-                    if to_publish.retry_count < MAX_RETRY {
-                        to_publish.retry_count += 1;
-                        to_retry.push(to_publish);
-                    }
-                    continue;
-                }
+                request: &my_request.request,
+                epsilon: my_request.epsilon,
+                public_key: &self.public_key,
+            }) else {
+                return Some((receipt_id, my_request));
             };
+            tracing::warn!(%receipt_id, %rejection, "rejecting sign request");
+            crate::metrics::NUM_SIGN_REQUESTS_REJECTED
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            for receipt_id in my_request.receipts() {
+                self.transitions.push((
+                    receipt_id,
+                    SignRequestState::Rejected {
+                        rejection: rejection.clone(),
+                    },
+                ));
+                events::emit(NodeEvent::SignRequestRejected {
+                    receipt_id,
+                    rejection: rejection.clone(),
+                });
+            }
+        }
+        None
+    }
 
-            match response.json() {
-                Ok(()) => {
-                    tracing::info!(%receipt_id, bi_r = signature.big_r.affine_point.to_base58(), s = ?signature.s, "published signature sucessfully")
-                }
-                Err(err) => {
-                    tracing::error!(%receipt_id, bi_r = signature.big_r.affine_point.to_base58(), s = ?signature.s, error = ?err, "smart contract threw error");
-                    crate::metrics::SIGNATURE_PUBLISH_RESPONSE_ERRORS
-                        .with_label_values(&[self.my_account_id.as_str()])
-                        .inc();
-                    continue;
-                }
+    /// Publishes the cached signatures of the requests that were already signed in this epoch
+    /// instead of generating them again.
+    fn publish_cached(&mut self, my_requests: &mut ParticipantRequests) {
+        if self.cache.is_empty() {
+            return;
+        }
+        let epoch = self.epoch;
+        let cache = &self.cache;
+        let cached = my_requests.take_matching(|request| {
+            cache.contains_key(&signature_cache_key(
+                &request.request.payload,
+                &request.epsilon,
+                epoch,
+            ))
+        });
+        for (receipt_id, my_request) in cached {
+            let key = signature_cache_key(&my_request.request.payload, &my_request.epsilon, epoch);
+            let Some(cached) = self.cache.get(&key) else {
+                continue;
             };
-
-            crate::metrics::NUM_SIGN_SUCCESS
+            tracing::info!(
+                %receipt_id,
+                big_r = ?cached.big_r.to_base58(),
+                "request was already signed: publishing cached signature"
+            );
+            crate::metrics::SIGNATURE_CACHE_HITS
                 .with_label_values(&[self.my_account_id.as_str()])
                 .inc();
-            crate::metrics::SIGN_LATENCY
-                .with_label_values(&[self.my_account_id.as_str()])
-                .observe(time_added.elapsed().as_secs_f64());
-            if time_added.elapsed().as_secs() <= 30 {
-                crate::metrics::NUM_SIGN_SUCCESS_30S
-                    .with_label_values(&[self.my_account_id.as_str()])
-                    .inc();
+            let request = SignatureRequest {
+                epsilon: SerializableScalar {
+                    scalar: my_request.epsilon,
+                },
+                payload_hash: my_request.request.payload.into(),
+            };
+            let signature = FullSignature {
+                big_r: cached.big_r,
+                s: cached.s,
+            };
+            self.completed.insert(receipt_id, self.clock.now());
+            for merged_id in &my_request.merged {
+                self.completed.insert(*merged_id, self.clock.now());
             }
+            self.publisher.push(
+                ToPublish::new(receipt_id, request, my_request.time_added, signature)
+                    .with_merged(my_request.merged)
+                    .with_lineage(cached.lineage.clone()),
+            );
         }
-        // Put the failed requests at the back of the queue
-        self.signatures.extend(to_retry);
+    }
+
+    pub async fn publish<T: SignerExt>(
+        &mut self,
+        rpc_client: &near_fetch::Client,
+        signer: &T,
+        mpc_contract_id: &AccountId,
+    ) {
+        self.publisher
+            .publish(
+                rpc_client,
+                signer,
+                mpc_contract_id,
+                self.public_key,
+                &self.my_account_id,
+            )
+            .await;
     }
 
     /// Garbage collect all the completed signatures.
     pub fn garbage_collect(&mut self, cfg: &ProtocolConfig) {
         let before = self.completed.len();
         self.completed.retain(|_, timestamp| {
-            timestamp.elapsed() < Duration::from_millis(cfg.signature.garbage_timeout)
+            self.clock.elapsed(*timestamp) < Duration::from_millis(cfg.signature.garbage_timeout)
+        });
+        self.cache.retain(|_, cached| {
+            self.clock.elapsed(cached.at) < Duration::from_millis(cfg.signature.garbage_timeout)
         });
         let garbage_collected = before.saturating_sub(self.completed.len());
         if garbage_collected > 0 {
@@ -790,11 +1575,272 @@ impl SignatureManager {
         }
     }
 
+    /// Drops the generators that are past their timeout, independently of them being poked. The
+    /// requests proposed by this node are retried while within their total timeout, the same as
+    /// when the timeout is hit on poke. Returns the ids of the dropped generators.
+    pub fn sweep(&mut self) -> Vec<ReceiptId> {
+        let timed_out = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| generator.is_timed_out())
+            .map(|(receipt_id, _)| *receipt_id)
+            .collect::<Vec<_>>();
+        for receipt_id in &timed_out {
+            let Some(generator) = self.generators.remove(receipt_id) else {
+                continue;
+            };
+            journal::record(
+                self.epoch,
+                ProtocolKind::Signature,
+                receipt_id,
+                Event::TimedOut,
+            );
+            participation::record(
+                self.my_account_id.as_str(),
+                self.epoch,
+                ProtocolKind::Signature,
+                &generator.participants,
+                Outcome::TimedOut,
+            );
+            crate::metrics::SIGNATURE_GENERATOR_TIMEOUTS
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            let _span = generator.span.clone().entered();
+            if generator.proposer != self.me {
+                tracing::debug!("swept timed out signature generator");
+                self.transition(*receipt_id, SignRequestState::Indexed);
+            } else if self.clock.elapsed(generator.sign_request_timestamp) < generator.timeout_total
+            {
+                tracing::warn!(
+                    "swept timed out signature generator; pushing request back into failed queue"
+                );
+                crate::metrics::SIGNATURE_GENERATOR_FAILURES
+                    .with_label_values(&[self.my_account_id.as_str()])
+                    .inc();
+                self.failed
+                    .push_back((*receipt_id, generator.generation_request()));
+                self.transition(*receipt_id, SignRequestState::Indexed);
+            } else {
+                tracing::warn!("swept timed out signature generator; trashing request");
+                self.completed.insert(*receipt_id, self.clock.now());
+                self.transition(
+                    *receipt_id,
+                    SignRequestState::Failed {
+                        reason: "timed out".to_string(),
+                    },
+                );
+                self.merged.remove(receipt_id);
+                crate::metrics::SIGNATURE_FAILURES
+                    .with_label_values(&[self.my_account_id.as_str()])
+                    .inc();
+            }
+        }
+        timed_out
+    }
+
+    fn transition(&mut self, receipt_id: ReceiptId, state: SignRequestState) {
+        transition(&mut self.transitions, &self.merged, receipt_id, state);
+    }
+
+    /// Whether the signature was completed and has not been garbage collected yet.
+    pub fn is_completed(&self, id: &ReceiptId) -> bool {
+        self.completed.contains_key(id)
+    }
+
+    /// The signature completed in this epoch for `payload` signed with the key derived by
+    /// `epsilon`, as `(big_r, s)`, if still cached.
+    pub fn cached_signature(
+        &self,
+        payload: &Scalar,
+        epsilon: &Scalar,
+    ) -> Option<(AffinePoint, Scalar)> {
+        self.cache
+            .get(&signature_cache_key(payload, epsilon, self.epoch))
+            .map(|cached| (cached.big_r, cached.s))
+    }
+
     pub fn refresh_gc(&mut self, id: &ReceiptId) -> bool {
         let entry = self
             .completed
             .entry(*id)
-            .and_modify(|e| *e = Instant::now());
+            .and_modify(|e| *e = self.clock.now());
         matches!(entry, Entry::Occupied(_))
     }
 }
+
+/// Records the transition of a request, along with the requests merged into it.
+fn transition(
+    transitions: &mut Vec<(ReceiptId, SignRequestState)>,
+    merged: &HashMap<ReceiptId, Vec<ReceiptId>>,
+    receipt_id: ReceiptId,
+    state: SignRequestState,
+) {
+    for merged_id in merged.get(&receipt_id).into_iter().flatten() {
+        transitions.push((*merged_id, state.clone()));
+    }
+    transitions.push((receipt_id, state));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_shared::kdf::check_ec_signature;
+    use crypto_shared::x_coordinate;
+    use k256::ProjectivePoint;
+
+    fn request(receipt_id: u8, payload: u64, priority: SignPriority) -> SignRequest {
+        let now = Instant::now();
+        SignRequest {
+            receipt_id: CryptoHash([receipt_id; 32]),
+            request: ContractSignRequest {
+                payload: Scalar::from(payload),
+                path: "test".to_string(),
+                key_version: 0,
+            },
+            epsilon: Scalar::ONE,
+            entropy: [receipt_id; 32],
+            time_added: now,
+            priority,
+            deadline: now + SIGN_REQUEST_DEADLINE,
+            merged: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_sign_queue_merges_same_signature() {
+        let mut queue = SignQueue::new();
+        queue.add(request(2, 7, SignPriority::Low));
+        // The same receipt delivered again is dropped.
+        queue.add(request(2, 7, SignPriority::Low));
+        // Another receipt for the same signature is merged, led by the lowest receipt id.
+        queue.add(request(1, 7, SignPriority::High));
+        queue.add(request(3, 8, SignPriority::Normal));
+        assert_eq!(queue.requests().count(), 2);
+
+        let merged = queue
+            .requests()
+            .find(|request| request.request.payload == Scalar::from(7u64))
+            .unwrap();
+        assert_eq!(merged.receipt_id, CryptoHash([1; 32]));
+        assert_eq!(merged.entropy, [1; 32]);
+        assert_eq!(merged.merged, vec![CryptoHash([2; 32])]);
+        assert_eq!(merged.priority, SignPriority::High);
+
+        // A merged receipt delivered again is dropped as well.
+        queue.add(request(2, 7, SignPriority::Low));
+        assert_eq!(queue.requests().count(), 2);
+    }
+
+    #[test]
+    fn test_priority_from_deposit() {
+        let priority = |yocto| SignPriority::from_deposit(NearToken::from_yoctonear(yocto));
+        assert_eq!(priority(1), SignPriority::Normal);
+        assert_eq!(
+            priority(HIGH_PRIORITY_DEPOSIT.as_yoctonear() - 1),
+            SignPriority::Normal
+        );
+        assert_eq!(
+            priority(HIGH_PRIORITY_DEPOSIT.as_yoctonear()),
+            SignPriority::High
+        );
+    }
+
+    /// Signs `payload` with the root secret key `x` tweaked by `epsilon`, using the nonce `k`.
+    fn sign(x: Scalar, epsilon: Scalar, k: Scalar, payload: Scalar) -> FullSignature<Secp256k1> {
+        let big_r = (ProjectivePoint::GENERATOR * k).to_affine();
+        let s = k.invert().unwrap() * (payload + x_coordinate(&big_r) * (x + epsilon));
+        FullSignature { big_r, s }
+    }
+
+    fn generator(request: ContractSignRequest, epsilon: Scalar) -> SignatureGenerator {
+        let participants = vec![Participant::from(0u32), Participant::from(1u32)];
+        let output = PresignOutput {
+            big_r: AffinePoint::GENERATOR,
+            k: Scalar::ONE,
+            sigma: Scalar::ONE,
+        };
+        let protocol = Box::new(
+            cait_sith::sign(
+                &participants,
+                participants[0],
+                AffinePoint::GENERATOR,
+                output,
+                request.payload,
+            )
+            .unwrap(),
+        );
+        SignatureGenerator::new(
+            protocol,
+            participants.clone(),
+            participants[0],
+            1,
+            request,
+            epsilon,
+            CryptoHash([1; 32]),
+            [1; 32],
+            Instant::now(),
+            Precomputation {
+                id: 1,
+                epoch: 0,
+                participants,
+                triples: None,
+            },
+            &ProtocolConfig::default(),
+            clock::monotonic(),
+        )
+    }
+
+    #[test]
+    fn test_tampered_signature_is_rejected() {
+        let x = Scalar::from(7u64);
+        let public_key = (ProjectivePoint::GENERATOR * x).to_affine();
+        let req = request(1, 42, SignPriority::Normal);
+        let payload = req.request.payload;
+        let generator = generator(req.request.clone(), req.epsilon);
+
+        let signature = sign(x, req.epsilon, Scalar::from(11u64), payload);
+        assert!(generator.verify(0, public_key, &signature).is_ok());
+
+        // A tampered `s` fails verification, such that the signature is never published.
+        let tampered = FullSignature {
+            big_r: signature.big_r,
+            s: signature.s + Scalar::ONE,
+        };
+        assert!(matches!(
+            generator.verify(0, public_key, &tampered),
+            Err(PokeFailure::InvalidSignature(_))
+        ));
+        // As does a signature for another key or another payload.
+        let other_key = sign(x + Scalar::ONE, req.epsilon, Scalar::from(11u64), payload);
+        assert!(generator.verify(0, public_key, &other_key).is_err());
+        let other_payload = sign(x, req.epsilon, Scalar::from(11u64), payload + Scalar::ONE);
+        assert!(generator.verify(0, public_key, &other_payload).is_err());
+
+        // The recovery id is derived from the derived key rather than taken from the protocol:
+        // the published one recovers the key and a flipped one does not.
+        let expected_public_key = cached_key(public_key, req.epsilon);
+        let response = into_eth_sig(
+            &expected_public_key,
+            &signature.big_r,
+            &signature.s,
+            payload,
+        )
+        .unwrap();
+        check_ec_signature(
+            &expected_public_key,
+            &response.big_r.affine_point,
+            &response.s.scalar,
+            payload,
+            response.recovery_id,
+        )
+        .unwrap();
+        assert!(check_ec_signature(
+            &expected_public_key,
+            &response.big_r.affine_point,
+            &response.s.scalar,
+            payload,
+            response.recovery_id ^ 1,
+        )
+        .is_err());
+    }
+}
@@ -2,11 +2,14 @@ use super::contract::primitives::{ParticipantInfo, Participants};
 use super::cryptography::CryptographicError;
 use super::monitor::StuckMonitor;
 use super::presignature::PresignatureManager;
+use super::refresh::Refresher;
+use super::registry::SharedRegistry;
 use super::signature::SignatureManager;
 use super::triple::TripleManager;
 use super::SignQueue;
 use crate::http_client::MessageQueue;
-use crate::storage::triple_storage::TripleData;
+use crate::storage::presignature_storage::PresignatureData;
+use crate::storage::triple_storage::{SpentTripleData, TripleData};
 use crate::types::{KeygenProtocol, ReshareProtocol, SecretKeyShare};
 
 use cait_sith::protocol::Participant;
@@ -17,11 +20,12 @@ use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use zeroize::Zeroizing;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PersistentNodeData {
     pub epoch: u64,
-    pub private_share: SecretKeyShare,
+    pub private_share: Zeroizing<SecretKeyShare>,
     pub public_key: PublicKey,
 }
 
@@ -34,12 +38,18 @@ impl fmt::Debug for PersistentNodeData {
     }
 }
 
+/// The node waits for the contract to tell it what to do. If the contract is being initialized
+/// with this node among its candidates, the node starts generating the key with them.
 #[derive(Debug, Clone)]
 pub struct StartedState {
     pub persistent_node_data: Option<PersistentNodeData>,
     pub triple_data: Vec<TripleData>,
+    pub spent_triples: Vec<SpentTripleData>,
+    pub presignature_data: Vec<PresignatureData>,
 }
 
+/// The initial key generation runs among the candidates of the contract. The share of this node
+/// is persisted once it completes.
 #[derive(Clone)]
 pub struct GeneratingState {
     pub participants: Participants,
@@ -57,12 +67,13 @@ impl GeneratingState {
     }
 }
 
+/// The key is generated or reshared, and this node votes for it until the contract agrees on it.
 #[derive(Clone)]
 pub struct WaitingForConsensusState {
     pub epoch: u64,
     pub participants: Participants,
     pub threshold: usize,
-    pub private_share: SecretKeyShare,
+    pub private_share: Zeroizing<SecretKeyShare>,
     pub public_key: PublicKey,
     pub messages: Arc<RwLock<MessageQueue>>,
 }
@@ -92,13 +103,15 @@ pub struct RunningState {
     pub epoch: u64,
     pub participants: Participants,
     pub threshold: usize,
-    pub private_share: SecretKeyShare,
+    pub private_share: Zeroizing<SecretKeyShare>,
     pub public_key: PublicKey,
     pub sign_queue: Arc<RwLock<SignQueue>>,
     pub stuck_monitor: Arc<RwLock<StuckMonitor>>,
     pub triple_manager: Arc<RwLock<TripleManager>>,
     pub presignature_manager: Arc<RwLock<PresignatureManager>>,
     pub signature_manager: Arc<RwLock<SignatureManager>>,
+    pub refresher: Arc<RwLock<Refresher>>,
+    pub registry: SharedRegistry,
     pub messages: Arc<RwLock<MessageQueue>>,
 }
 
@@ -175,6 +188,16 @@ impl Display for NodeState {
 }
 
 impl NodeState {
+    /// The epoch whose messages this state handles, if it has one.
+    pub fn epoch(&self) -> Option<u64> {
+        match self {
+            NodeState::Running(state) => Some(state.epoch),
+            NodeState::WaitingForConsensus(state) => Some(state.epoch),
+            NodeState::Resharing(state) => Some(state.old_epoch),
+            _ => None,
+        }
+    }
+
     pub fn fetch_participant(
         &self,
         p: &Participant,
@@ -0,0 +1,77 @@
+//! Periodically drops the generators that are past their timeout. Timeouts are otherwise only
+//! detected while poking, so a node that stops receiving messages would hold on to its stale
+//! generators for as long as they are not poked.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+
+use super::cryptography::CryptographicError;
+use super::state::{NodeState, RunningState};
+use super::MpcMessage;
+
+/// How often the generators are checked for timeouts, regardless of the poke cadence.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sweeps the managers of the running state every [`SWEEP_INTERVAL`].
+pub async fn run(state: Arc<RwLock<NodeState>>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let running = match &*state.read().await {
+            NodeState::Running(running) => running.clone(),
+            _ => continue,
+        };
+        if let Err(err) = sweep(&running).await {
+            tracing::warn!(
+                ?err,
+                "sweeper: failed to notify participants of swept generators"
+            );
+        }
+    }
+}
+
+/// Drops the timed out generators of every manager and queues the abort messages of the dropped
+/// presignatures for the other participants. The managers are locked one at a time.
+pub async fn sweep(state: &RunningState) -> Result<(), CryptographicError> {
+    let mut triple_manager = state.triple_manager.write().await;
+    let my_account_id = triple_manager.my_account_id.clone();
+    let triples = triple_manager.sweep();
+    drop(triple_manager);
+
+    let mut presignature_manager = state.presignature_manager.write().await;
+    let presignatures = presignature_manager.sweep();
+    let aborts = presignature_manager.take_aborts();
+    drop(presignature_manager);
+
+    let signatures = state.signature_manager.write().await.sweep();
+
+    for (protocol, swept) in [
+        ("triple", triples.len()),
+        ("presignature", presignatures.len()),
+        ("signature", signatures.len()),
+    ] {
+        crate::metrics::NUM_GENERATORS_SWEPT
+            .with_label_values(&[my_account_id.as_str(), protocol])
+            .inc_by(swept as f64);
+    }
+    if !triples.is_empty() || !presignatures.is_empty() || !signatures.is_empty() {
+        tracing::info!(
+            ?triples,
+            ?presignatures,
+            ?signatures,
+            "sweeper: dropped timed out generators"
+        );
+    }
+
+    if !aborts.is_empty() {
+        let mut messages = state.messages.write().await;
+        for (p, msg) in aborts {
+            let info = state.fetch_participant(&p)?;
+            messages.push(info.clone(), MpcMessage::Abort(msg));
+        }
+    }
+    Ok(())
+}
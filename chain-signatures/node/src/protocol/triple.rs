@@ -1,24 +1,42 @@
 use super::contract::primitives::Participants;
 use super::cryptography::CryptographicError;
+use super::generator::{self, PokeStatus};
 use super::message::TripleMessage;
+use super::policy::{DefaultPolicy, ProposalPolicy, TripleStockpile};
 use super::presignature::GenerationError;
+use super::scheduler::{
+    self, GeneratorSummary, PokeFailure, PokeKind, PokeOutcome, RoundTimer, TokenBucket,
+};
+use crate::clock::{self, SharedClock};
+use crate::events::{self, NodeEvent};
 use crate::gcp::error;
-use crate::storage::triple_storage::{LockTripleNodeStorageBox, TripleData};
+use crate::journal::{self, Event, ProtocolKind};
+use crate::keyspace::KeyspaceId;
+use crate::redact::Secret;
+use crate::rng::{self, BoxedRng};
+use crate::shedding;
+use crate::storage::triple_storage::{LockTripleNodeStorageBox, SpentTripleData, TripleData};
+use crate::telemetry;
 use crate::types::TripleProtocol;
 use crate::util::AffinePointExt;
 
-use cait_sith::protocol::{Action, InitializationError, Participant, ProtocolError};
+use cait_sith::protocol::{Action, InitializationError, MessageData, Participant};
 use cait_sith::triples::{TripleGenerationOutput, TriplePub, TripleShare};
 use chrono::Utc;
 use highway::{HighwayHash, HighwayHasher};
 use k256::elliptic_curve::group::GroupEncoding;
 use k256::Secp256k1;
 use mpc_contract::config::ProtocolConfig;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use near_account_id::AccountId;
 
@@ -27,20 +45,52 @@ use near_account_id::AccountId;
 /// messages.
 pub type TripleId = u64;
 
-/// A completed triple.
-#[derive(Clone, Serialize, Deserialize, Debug)]
+/// A completed triple. The secret share is wiped from memory when the triple is dropped.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Triple {
     pub id: TripleId,
     pub share: TripleShare<Secp256k1>,
     pub public: TriplePub<Secp256k1>,
 }
 
+impl fmt::Debug for Triple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Triple")
+            .field("id", &self.id)
+            .field("share", &Secret(&self.share))
+            .field("public", &self.public)
+            .finish()
+    }
+}
+
+impl Zeroize for Triple {
+    fn zeroize(&mut self) {
+        self.share.a.zeroize();
+        self.share.b.zeroize();
+        self.share.c.zeroize();
+    }
+}
+
+impl Drop for Triple {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for Triple {}
+
 pub struct TripleGenerator {
     pub id: TripleId,
     pub participants: Vec<Participant>,
     pub protocol: TripleProtocol,
     pub timestamp: Option<Instant>,
     pub timeout: Duration,
+    /// Time spent poking versus waiting between rounds.
+    pub rounds: RoundTimer,
+    /// Span the logs about this triple are recorded in, see [`crate::telemetry`].
+    pub span: tracing::Span,
+    /// Clock the timeout is measured against, the one of the manager.
+    pub clock: SharedClock,
 }
 
 impl TripleGenerator {
@@ -49,6 +99,7 @@ impl TripleGenerator {
         participants: Vec<Participant>,
         protocol: TripleProtocol,
         timeout: u64,
+        clock: SharedClock,
     ) -> Self {
         Self {
             id,
@@ -56,24 +107,104 @@ impl TripleGenerator {
             protocol,
             timestamp: None,
             timeout: Duration::from_millis(timeout),
+            rounds: RoundTimer::default(),
+            span: telemetry::triple_span(id),
+            clock,
         }
     }
 
-    pub fn poke(&mut self) -> Result<Action<TripleGenerationOutput<Secp256k1>>, ProtocolError> {
-        let timestamp = self.timestamp.get_or_insert_with(Instant::now);
-        if timestamp.elapsed() > self.timeout {
-            tracing::warn!(
-                id = self.id,
-                elapsed = ?timestamp.elapsed(),
-                "triple protocol timed out"
-            );
-            return Err(ProtocolError::Other(
-                anyhow::anyhow!("triple protocol timed out").into(),
-            ));
+    pub fn is_timed_out(&self) -> bool {
+        self.timestamp.map_or(false, |timestamp| {
+            self.clock.elapsed(timestamp) > self.timeout
+        })
+    }
+
+    pub fn poke(&mut self) -> Result<Action<TripleGenerationOutput<Secp256k1>>, PokeFailure> {
+        let timestamp = *self.timestamp.get_or_insert_with(|| self.clock.now());
+        let elapsed = self.clock.elapsed(timestamp);
+        if elapsed > self.timeout {
+            tracing::warn!(?elapsed, "triple protocol timed out");
+            return Err(PokeFailure::TimedOut(elapsed));
         }
 
-        self.protocol.poke()
+        Ok(self.protocol.poke()?)
     }
+
+    /// Pokes the protocol until it can no longer progress without messages from the other
+    /// participants, completes or fails. Returns the messages produced along the way.
+    pub fn poke_until_blocked(
+        &mut self,
+        keyspace: &KeyspaceId,
+        epoch: u64,
+        me: Participant,
+    ) -> (
+        Vec<(Participant, TripleMessage)>,
+        PokeStatus<TripleGenerationOutput<Secp256k1>>,
+    ) {
+        self.span.record("epoch", epoch);
+        let _span = self.span.clone().entered();
+        self.rounds.resume();
+        let mut messages = Vec::new();
+        let status = loop {
+            let action = match self.poke() {
+                Ok(action) => action,
+                Err(error) => break PokeStatus::Failed(error),
+            };
+            match action {
+                Action::Wait => break PokeStatus::Waiting,
+                Action::SendMany(data) => {
+                    for p in &self.participants {
+                        messages.push((*p, self.message(keyspace, epoch, me, data.clone())));
+                    }
+                }
+                Action::SendPrivate(p, data) => {
+                    messages.push((p, self.message(keyspace, epoch, me, data)))
+                }
+                Action::Return(output) => break PokeStatus::Completed(output),
+            }
+        };
+        self.rounds.pause(matches!(status, PokeStatus::Waiting));
+        (messages, status)
+    }
+
+    fn message(
+        &self,
+        keyspace: &KeyspaceId,
+        epoch: u64,
+        me: Participant,
+        data: MessageData,
+    ) -> TripleMessage {
+        TripleMessage {
+            id: self.id,
+            keyspace: keyspace.clone(),
+            epoch,
+            from: me,
+            data,
+            timestamp: Utc::now().timestamp() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TripleSummary {
+    pub id: TripleId,
+    pub mine: bool,
+}
+
+/// Summary of the state of a [`TripleManager`], see [`TripleManager::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TripleSnapshot {
+    pub epoch: u64,
+    /// Completed unspent triples, ordered by id.
+    pub triples: Vec<TripleSummary>,
+    /// Generators either queued or ongoing, ordered by id.
+    pub generators: Vec<GeneratorSummary>,
+    /// Number of generators waiting to be started.
+    pub queued: usize,
+    /// Number of generators started and not yet completed.
+    pub ongoing: usize,
+    /// Number of triples reserved for a presignature.
+    pub reserved: usize,
 }
 
 /// Abstracts how triples are generated by providing a way to request a new triple that will be
@@ -99,10 +230,52 @@ pub struct TripleManager {
     /// List of triple ids generation of which was initiated by the current node.
     pub mine: VecDeque<TripleId>,
 
+    /// Since when the triples are held, so that the oldest get evicted first when shedding load,
+    /// see [`crate::shedding`].
+    pub held_since: HashMap<TripleId, Instant>,
+
     /// The set of triple ids that were already taken or failed. This will be maintained for at most
     /// triple timeout period just so messages are cycled through the system.
     pub gc: HashMap<TripleId, Instant>,
 
+    /// Ledger of the triples taken for a presignature, with the proposer of that presignature.
+    /// A triple can only be held by one presignature, so concurrent proposals for it are rejected
+    /// until the reservation is released or garbage collected with the triple.
+    pub reserved: HashMap<TripleId, Participant>,
+
+    /// Triples consumed by a presignature in this epoch. The ledger is persisted before a triple
+    /// is handed out, so that no triple is consumed twice even across restarts or replayed
+    /// messages.
+    pub spent: HashSet<TripleId>,
+
+    /// Paces how fast queued protocols are started, so that a burst of new triples does not
+    /// saturate the CPU and bandwidth of the node.
+    pub pacer: TokenBucket,
+
+    /// Maximum number of ongoing protocols set locally, on top of the limit of the contract.
+    pub max_in_flight: Option<usize>,
+
+    /// First generator skipped for lack of budget by the last poke, the next poke resuming from it.
+    poke_cursor: Option<TripleId>,
+
+    /// Set when triples are generated among subsets of the participants rather than all of them.
+    pub subsets: Option<Subsets>,
+
+    /// Decides which triples this node proposes, and which presignatures of others it joins.
+    pub policy: Arc<dyn ProposalPolicy>,
+
+    /// Keyspace the triples are generated for, see [`crate::keyspace`].
+    pub keyspace: KeyspaceId,
+
+    /// Rng the ids of the triples introduced by this node are drawn from.
+    pub rng: BoxedRng,
+
+    /// Clock the timeouts, the garbage collection and the pacing are measured against.
+    pub clock: SharedClock,
+
+    /// Bumped whenever a triple becomes available, waking up [`TripleManager::wait_for`].
+    available: watch::Sender<u64>,
+
     pub me: Participant,
     pub threshold: usize,
     pub epoch: u64,
@@ -110,6 +283,42 @@ pub struct TripleManager {
     pub my_account_id: AccountId,
 }
 
+/// Number of random ids tried when looking for one whose subset of participants includes this
+/// node and only reachable participants.
+const SUBSET_ATTEMPTS: usize = 1024;
+
+/// Triples generated among subsets of `size` participants of the epoch, see [`subset`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subsets {
+    pub size: usize,
+    /// All participants of the epoch, from which the subsets are selected.
+    pub participants: Vec<Participant>,
+}
+
+/// Number of participants generating each triple, from the `triple.subset_size` entry of the
+/// protocol config. Every participant generates every triple if not set.
+pub fn subset_size(cfg: &ProtocolConfig) -> Option<usize> {
+    let size = cfg.triple.other.get("subset_size")?;
+    let size = serde_json::to_value(size).ok()?.as_u64()?;
+    Some(size as usize)
+}
+
+/// Selects the `size` participants generating the triple with the given id: the participants
+/// ranked by the hash of the id along with their own id. Every participant computes the same
+/// subset for an id from the same participant set, so the subset is never sent around.
+pub fn subset(id: TripleId, participants: &[Participant], size: usize) -> Vec<Participant> {
+    let mut ranked = participants.to_vec();
+    ranked.sort_by_cached_key(|participant| {
+        Sha3_256::new()
+            .chain_update(id.to_le_bytes())
+            .chain_update(u32::from(*participant).to_le_bytes())
+            .finalize()
+    });
+    ranked.truncate(size);
+    ranked.sort();
+    ranked
+}
+
 impl fmt::Debug for TripleManager {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TripleManager")
@@ -119,7 +328,10 @@ impl fmt::Debug for TripleManager {
             .field("ongoing", &self.ongoing)
             .field("introduced", &self.introduced)
             .field("gc", &self.gc.keys().collect::<Vec<_>>())
+            .field("reserved", &self.reserved)
+            .field("spent", &self.spent.len())
             .field("mine", &self.mine)
+            .field("policy", &self.policy)
             .field("me", &self.me)
             .field("threshold", &self.threshold)
             .field("epoch", &self.epoch)
@@ -139,12 +351,23 @@ impl TripleManager {
     ) -> Self {
         let mut mine: VecDeque<TripleId> = VecDeque::new();
         let mut all_triples = HashMap::new();
+        let mut held_since = HashMap::new();
+        let clock = clock::monotonic();
         for entry in triple_data {
             tracing::debug!("the triple data loaded is {:?}", entry);
+            if entry.epoch != epoch {
+                tracing::debug!(
+                    id = entry.triple.id,
+                    entry.epoch,
+                    "skipping triple of another epoch"
+                );
+                continue;
+            }
             if entry.mine {
                 tracing::debug!("pushed tripleId = {} into mine.", entry.triple.id);
                 mine.push_back(entry.triple.id);
             }
+            held_since.insert(entry.triple.id, clock.now());
             all_triples.insert(entry.triple.id, entry.triple);
         }
         Self {
@@ -154,7 +377,19 @@ impl TripleManager {
             ongoing: HashSet::new(),
             introduced: HashSet::new(),
             gc: HashMap::new(),
+            reserved: HashMap::new(),
+            spent: HashSet::new(),
+            pacer: TokenBucket::unlimited(),
+            max_in_flight: None,
+            poke_cursor: None,
+            subsets: None,
+            policy: Arc::new(DefaultPolicy::default()),
+            keyspace: KeyspaceId::root(),
+            rng: rng::node(my_account_id),
+            clock,
+            available: watch::channel(0).0,
             mine,
+            held_since,
             me,
             threshold,
             epoch,
@@ -163,6 +398,101 @@ impl TripleManager {
         }
     }
 
+    /// Restores the ledger of spent triples persisted before a restart. Entries of previous epochs
+    /// are pruned, and the stored triples found in the ledger, e.g. because their deletion failed
+    /// before the restart, are dropped rather than consumed again.
+    pub async fn restore_spent(&mut self, spent: Vec<SpentTripleData>) {
+        self.spent = spent
+            .into_iter()
+            .filter(|spent| spent.epoch == self.epoch)
+            .map(|spent| spent.triple_id)
+            .collect();
+        match self
+            .triple_storage
+            .write()
+            .await
+            .prune_spent(self.epoch)
+            .await
+        {
+            Ok(pruned) if pruned > 0 => {
+                tracing::info!(pruned, "pruned spent triples of previous epochs")
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!(?err, "failed to prune spent triples"),
+        }
+        let lingering = self
+            .triples
+            .keys()
+            .filter(|id| self.spent.contains(*id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in lingering {
+            tracing::warn!(id, "dropping stored triple that was already spent");
+            self.triples.remove(&id);
+            self.mine.retain(|mine| *mine != id);
+            if let Err(err) = self.delete_triple_from_storage(id).await {
+                tracing::warn!(id, ?err, "unable to delete spent triple from storage");
+            }
+        }
+    }
+
+    /// Sets how fast protocols are started and how many can be ongoing at once.
+    pub fn set_pacing(&mut self, opts: &scheduler::Options) {
+        self.pacer = TokenBucket::new(opts.triple_start_rate, opts.triple_start_burst);
+        self.max_in_flight = opts.max_triples_in_flight;
+    }
+
+    /// Sets the policy deciding which triples this node proposes, and which presignatures of
+    /// others it joins.
+    pub fn set_policy(&mut self, policy: Arc<dyn ProposalPolicy>) {
+        self.policy = policy;
+    }
+
+    /// Sets whether triples are generated among subsets of the participants of the epoch. The
+    /// subsets are never smaller than the threshold, and are only used when smaller than the
+    /// participant set.
+    pub fn set_subsets(&mut self, participants: &Participants, cfg: &ProtocolConfig) {
+        self.subsets = subset_size(cfg)
+            .map(|size| size.max(self.threshold))
+            .filter(|size| *size < participants.len())
+            .map(|size| Subsets {
+                size,
+                participants: participants.keys_vec(),
+            });
+    }
+
+    /// Checks that the triple was generated by the subset of participants selected for its id,
+    /// when triples are generated among subsets.
+    pub fn check_subset(&self, triple: &Triple) -> Result<(), GenerationError> {
+        let Some(subsets) = &self.subsets else {
+            return Ok(());
+        };
+        let mut participants = triple.public.participants.clone();
+        participants.sort();
+        if participants != subset(triple.id, &subsets.participants, subsets.size) {
+            tracing::warn!(
+                id = triple.id,
+                ?participants,
+                "triple was not generated by the participants selected for it"
+            );
+            return Err(GenerationError::TripleSubsetMismatch(triple.id));
+        }
+        Ok(())
+    }
+
+    /// Picks a random id whose subset of participants includes this node and only participants
+    /// of the given active set, such that the triple can be generated while others are down.
+    fn pick_subset(&mut self, active: &Participants) -> Option<(TripleId, Vec<Participant>)> {
+        let subsets = self.subsets.as_ref()?;
+        let rng = &mut self.rng;
+        (0..SUBSET_ATTEMPTS).find_map(|_| {
+            let id = rng.next_u64();
+            let participants = subset(id, &subsets.participants, subsets.size);
+            let reachable = participants.iter().all(|p| active.contains_key(p));
+            (reachable && participants.contains(&self.me)).then_some((id, participants))
+        })
+    }
+
     /// Returns the number of unspent triples available in the manager.
     pub fn len(&self) -> usize {
         self.triples.len()
@@ -188,32 +518,283 @@ impl TripleManager {
         self.my_len() >= cfg.triple.min_triples as usize
     }
 
+    /// Summarizes the triples and generators held by the manager.
+    pub fn snapshot(&self) -> TripleSnapshot {
+        let mut triples = self
+            .triples
+            .keys()
+            .map(|id| TripleSummary {
+                id: *id,
+                mine: self.mine.contains(id),
+            })
+            .collect::<Vec<_>>();
+        triples.sort_by_key(|triple| triple.id);
+        let mut generators = self
+            .generators
+            .iter()
+            .map(|(id, generator)| GeneratorSummary {
+                id: *id,
+                mine: self.introduced.contains(id),
+                participants: generator.participants.clone(),
+                age_ms: generator
+                    .timestamp
+                    .map(|timestamp| self.clock.elapsed(timestamp).as_millis()),
+            })
+            .collect::<Vec<_>>();
+        generators.sort_by_key(|generator| generator.id);
+        TripleSnapshot {
+            epoch: self.epoch,
+            triples,
+            generators,
+            queued: self.queued.len(),
+            ongoing: self.ongoing.len(),
+            reserved: self.reserved.len(),
+        }
+    }
+
     /// Clears an entry from failed triples if that triple protocol was created more than 2 hrs ago
     pub fn garbage_collect(&mut self, cfg: &ProtocolConfig) {
         let before = self.gc.len();
         self.gc.retain(|_, timestamp| {
-            timestamp.elapsed() < Duration::from_millis(cfg.garbage_timeout)
+            self.clock.elapsed(*timestamp) < Duration::from_millis(cfg.garbage_timeout)
         });
         let garbage_collected = before.saturating_sub(self.gc.len());
         if garbage_collected > 0 {
             tracing::debug!("garbage collected {} triples", garbage_collected);
         }
+        let gc = &self.gc;
+        self.reserved.retain(|id, _| gc.contains_key(id));
+        let triples = &self.triples;
+        self.held_since.retain(|id, _| triples.contains_key(id));
+    }
+
+    /// Evicts up to `n` unspent triples, oldest first, to relieve memory pressure. Triples taken
+    /// for a presignature are no longer held, so they are never evicted. Returns the ids of the
+    /// evicted triples.
+    pub async fn evict_oldest(&mut self, n: usize) -> Vec<TripleId> {
+        let mut held = self
+            .triples
+            .keys()
+            .map(|id| (self.held_since.get(id).copied(), *id))
+            .collect::<Vec<_>>();
+        // Triples of unknown age are the oldest.
+        held.sort_unstable();
+        let evicted = held
+            .into_iter()
+            .take(n)
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        for id in &evicted {
+            tracing::warn!(id, "evicting triple to shed load");
+            self.triples.remove(id);
+            self.held_since.remove(id);
+            self.mine.retain(|mine| mine != id);
+            self.gc.insert(*id, self.clock.now());
+            journal::record(
+                self.epoch,
+                ProtocolKind::Triple,
+                id,
+                Event::Aborted {
+                    reason: "evicted to shed load".to_string(),
+                },
+            );
+            events::emit(NodeEvent::TripleEvicted {
+                epoch: self.epoch,
+                id: *id,
+            });
+            if let Err(err) = self.delete_triple_from_storage(*id).await {
+                tracing::warn!(id, ?err, "unable to delete evicted triple from storage");
+            }
+        }
+        crate::metrics::NUM_TRIPLES_EVICTED
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc_by(evicted.len() as f64);
+        evicted
+    }
+
+    /// Drops the ongoing generators that are past their timeout, independently of them being
+    /// poked. Returns the ids of the dropped generators.
+    pub fn sweep(&mut self) -> Vec<TripleId> {
+        let timed_out = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| generator.is_timed_out())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &timed_out {
+            let Some(generator) = self.generators.remove(id) else {
+                continue;
+            };
+            self.gc.insert(*id, self.clock.now());
+            self.ongoing.remove(id);
+            self.introduced.remove(id);
+            journal::record(self.epoch, ProtocolKind::Triple, id, Event::TimedOut);
+            participation::record(
+                self.my_account_id.as_str(),
+                self.epoch,
+                ProtocolKind::Triple,
+                &generator.participants,
+                Outcome::TimedOut,
+            );
+            crate::metrics::TRIPLE_GENERATOR_FAILURES
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+            crate::metrics::TRIPLE_GENERATOR_TIMEOUTS
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+        }
+        timed_out
     }
 
     /// Refresh item in the garbage collection. If it is present, return true and update internally
     /// the timestamp for gabage collection.
     pub fn refresh_gc(&mut self, id: &TripleId) -> bool {
-        let entry = self.gc.entry(*id).and_modify(|e| *e = Instant::now());
+        let entry = self.gc.entry(*id).and_modify(|e| *e = self.clock.now());
         matches!(entry, Entry::Occupied(_))
     }
 
+    /// Drops all triples and cancels all ongoing generators since they were produced with the
+    /// participant set of the previous epoch. The dropped ids are moved into garbage collection
+    /// so that late messages for them are ignored.
+    pub async fn on_epoch_change(&mut self, new_epoch: u64) {
+        if new_epoch <= self.epoch {
+            return;
+        }
+
+        let now = self.clock.now();
+        let discarded = self.triples.len();
+        let cancelled = self.generators.len();
+        for id in self.triples.keys().chain(self.generators.keys()) {
+            self.gc.insert(*id, now);
+        }
+        self.triples.clear();
+        self.generators.clear();
+        self.queued.clear();
+        self.ongoing.clear();
+        self.introduced.clear();
+        self.mine.clear();
+        self.held_since.clear();
+
+        if let Err(err) = self.triple_storage.write().await.clear().await {
+            tracing::warn!(?err, "failed to clear triples from storage on epoch change");
+        }
+
+        crate::metrics::NUM_TRIPLES_DISCARDED_ON_EPOCH_CHANGE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc_by(discarded as f64);
+        crate::metrics::NUM_TRIPLE_GENERATORS_CANCELLED_ON_EPOCH_CHANGE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc_by(cancelled as f64);
+        tracing::info!(
+            old_epoch = self.epoch,
+            new_epoch,
+            discarded,
+            cancelled,
+            "discarded triples from previous epoch"
+        );
+        self.epoch = new_epoch;
+    }
+
+    /// Cancels the ongoing generators that involve participants which are no longer part of the
+    /// participant set, since they can never complete. Returns the number of cancelled generators.
+    pub fn update_participants(&mut self, participants: &Participants) -> usize {
+        let now = self.clock.now();
+        let stale: Vec<TripleId> = self
+            .generators
+            .iter()
+            .filter(|(_, generator)| {
+                generator
+                    .participants
+                    .iter()
+                    .any(|p| !participants.contains_key(p))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &stale {
+            self.generators.remove(id);
+            self.ongoing.remove(id);
+            self.introduced.remove(id);
+            self.gc.insert(*id, now);
+        }
+        self.queued.retain(|id| !stale.contains(id));
+
+        if !stale.is_empty() {
+            tracing::info!(
+                cancelled = ?stale,
+                "cancelled triple generators involving removed participants"
+            );
+        }
+        stale.len()
+    }
+
+    /// Cancels the ongoing generation of the triple with the given id. Returns whether such a
+    /// generator existed. The other participants let their own generator time out.
+    pub fn cancel(&mut self, id: TripleId, reason: &str) -> bool {
+        if self.generators.remove(&id).is_none() {
+            return false;
+        }
+        self.ongoing.remove(&id);
+        self.introduced.remove(&id);
+        self.queued.retain(|queued| *queued != id);
+        self.gc.insert(id, self.clock.now());
+        crate::metrics::TRIPLE_GENERATOR_FAILURES
+            .with_label_values(&[self.my_account_id.as_str()])
+            .inc();
+        tracing::info!(id, reason, "cancelled triple generation");
+        journal::record(
+            self.epoch,
+            ProtocolKind::Triple,
+            id,
+            Event::Aborted {
+                reason: reason.to_string(),
+            },
+        );
+        true
+    }
+
+    /// Cancels the ongoing generators involving `participant` that were started before `cutoff`,
+    /// since the participant restarted in the meantime. Returns the number of cancelled generators.
+    pub fn cancel_started_before(&mut self, participant: Participant, cutoff: Instant) -> usize {
+        let lost = self
+            .generators
+            .values()
+            .filter(|generator| {
+                generator.participants.contains(&participant)
+                    && generator
+                        .timestamp
+                        .is_some_and(|timestamp| timestamp < cutoff)
+            })
+            .map(|generator| generator.id)
+            .collect::<Vec<_>>();
+        for id in &lost {
+            self.cancel(*id, "participant restarted");
+        }
+        if !lost.is_empty() {
+            crate::metrics::NUM_GENERATORS_CANCELLED_ON_PEER_RESTART
+                .with_label_values(&[self.my_account_id.as_str(), "triple"])
+                .inc_by(lost.len() as f64);
+        }
+        lost.len()
+    }
+
     /// Starts a new Beaver triple generation protocol.
     pub fn generate(
         &mut self,
         participants: &Participants,
         timeout: u64,
     ) -> Result<(), InitializationError> {
-        let id = rand::random();
+        let (id, participants) = if self.subsets.is_some() {
+            let Some(picked) = self.pick_subset(participants) else {
+                tracing::warn!(
+                    active = ?participants.keys_vec(),
+                    "no subset of reachable participants found to generate a triple"
+                );
+                return Ok(());
+            };
+            picked
+        } else {
+            (self.rng.next_u64(), participants.keys_vec())
+        };
 
         // Check if the `id` is already in the system. Error out and have the next cycle try again.
         if self.generators.contains_key(&id)
@@ -227,7 +808,14 @@ impl TripleManager {
         }
 
         tracing::info!(id, "starting protocol to generate a new triple");
-        let participants: Vec<_> = participants.keys().cloned().collect();
+        journal::record(
+            self.epoch,
+            ProtocolKind::Triple,
+            id,
+            Event::Started {
+                participants: participants.clone(),
+            },
+        );
         let protocol: TripleProtocol = Box::new(cait_sith::triples::generate_triple::<Secp256k1>(
             &participants,
             self.me,
@@ -235,7 +823,7 @@ impl TripleManager {
         )?);
         self.generators.insert(
             id,
-            TripleGenerator::new(id, participants, protocol, timeout),
+            TripleGenerator::new(id, participants, protocol, timeout, self.clock.clone()),
         );
         self.queued.push_back(id);
         self.introduced.insert(id);
@@ -245,28 +833,30 @@ impl TripleManager {
         Ok(())
     }
 
-    /// Stockpile triples if the amount of unspent triples is below the minimum
-    /// and the maximum number of all ongoing generation protocols is below the maximum.
+    /// Stockpile triples while the proposal policy asks for more, by default as long as the
+    /// amount of unspent triples is below the minimum and the maximum number of all ongoing
+    /// generation protocols is below the maximum, see [`ProposalPolicy::propose_triple`].
     pub fn stockpile(
         &mut self,
         participants: &Participants,
         cfg: &ProtocolConfig,
     ) -> Result<(), InitializationError> {
-        let not_enough_triples = {
-            // Stopgap to prevent too many triples in the system. This should be around min_triple*nodes*2
-            // for good measure so that we have enough triples to do presig generation while also maintain
-            // the minimum number of triples where a single node can't flood the system.
-            if self.potential_len() >= cfg.triple.max_triples as usize {
-                false
-            } else {
-                // We will always try to generate a new triple if we have less than the minimum
-                self.my_len() < cfg.triple.min_triples as usize
-                    && self.introduced.len() < cfg.max_concurrent_introduction as usize
-                    && self.generators.len() < cfg.max_concurrent_generation as usize
-            }
+        let stockpile = TripleStockpile {
+            mine: self.my_len(),
+            introduced: self.introduced.len(),
+            generators: self.generators.len(),
+            potential: self.potential_len(),
         };
+        let not_enough_triples = self.policy.propose_triple(&stockpile, cfg);
 
         if not_enough_triples {
+            if participants.len() < self.threshold {
+                tracing::warn!(
+                    participants = ?participants.keys_vec(),
+                    "not enough live participants to generate triples"
+                );
+                return Ok(());
+            }
             tracing::debug!("not enough triples, generating");
             self.generate(participants, cfg.triple.generation_timeout)?;
         }
@@ -282,29 +872,26 @@ impl TripleManager {
         id0: TripleId,
         id1: TripleId,
     ) -> Result<(Triple, Triple), GenerationError> {
-        if !self.triples.contains_key(&id0) {
-            if self.generators.contains_key(&id0) {
-                tracing::warn!(id0, "triple is generating");
-                Err(GenerationError::TripleIsGenerating(id0))
-            } else if self.gc.contains_key(&id0) {
-                tracing::warn!(id0, "triple is garbage collected");
-                Err(GenerationError::TripleIsGarbageCollected(id0))
-            } else {
-                tracing::warn!(id0, "triple is missing");
-                Err(GenerationError::TripleIsMissing(id0))
-            }
-        } else if !self.triples.contains_key(&id1) {
-            if self.generators.contains_key(&id1) {
-                tracing::warn!(id1, "triple is generating");
-                Err(GenerationError::TripleIsGenerating(id1))
-            } else if self.gc.contains_key(&id1) {
-                tracing::warn!(id1, "triple is garbage collected");
-                Err(GenerationError::TripleIsGarbageCollected(id1))
-            } else {
-                tracing::warn!(id1, "triple is missing");
-                Err(GenerationError::TripleIsMissing(id1))
-            }
+        self.take_two_for(id0, id1, self.me).await
+    }
+
+    /// Takes the two triples on behalf of a presignature proposed by `holder`, reserving them
+    /// such that no other proposal can take them. Either takes both or none.
+    pub async fn take_two_for(
+        &mut self,
+        id0: TripleId,
+        id1: TripleId,
+        holder: Participant,
+    ) -> Result<(Triple, Triple), GenerationError> {
+        if let Err(err) = self
+            .check_available(id0)
+            .and_then(|_| self.check_available(id1))
+        {
+            Err(err)
         } else {
+            // The triples are recorded as spent before they are handed out, such that they are
+            // never consumed again should the node crash from here on.
+            self.record_spent([id0, id1]).await?;
             // Ensure that the triples have been removed from the datastore if they're going to be used in a signing protocol.
             // We expect them to be there so warn if they're not.
             // They may already be in memory, so even if this fails still try to pull them out.
@@ -315,8 +902,18 @@ impl TripleManager {
                 tracing::warn!(triple_id = id1, ?err, "unable to delete triple: potentially missing from datastore; deleting from memory only");
             }
 
-            self.gc.insert(id0, Instant::now());
-            self.gc.insert(id1, Instant::now());
+            self.gc.insert(id0, self.clock.now());
+            self.gc.insert(id1, self.clock.now());
+            self.reserved.insert(id0, holder);
+            self.reserved.insert(id1, holder);
+            journal::record(self.epoch, ProtocolKind::Triple, id0, Event::Taken);
+            journal::record(self.epoch, ProtocolKind::Triple, id1, Event::Taken);
+            for id in [id0, id1] {
+                events::emit(NodeEvent::TripleSpent {
+                    epoch: self.epoch,
+                    id,
+                });
+            }
 
             let triple_0 = self
                 .triples
@@ -330,13 +927,114 @@ impl TripleManager {
         }
     }
 
+    /// Waits until the triple with the given id is completed and unspent, such that it can be
+    /// taken. Instead of polling, the caller is parked until a triple completes while this one is
+    /// generating or its messages are yet to arrive. Fails right away if it cannot become
+    /// available anymore, and with the reason it is still unavailable once `timeout` elapsed.
+    pub async fn wait_for(
+        manager: &RwLock<Self>,
+        id: TripleId,
+        timeout: Duration,
+    ) -> Result<(), GenerationError> {
+        let outcome = park_until(
+            manager,
+            |manager| manager.poll_available(id),
+            |manager| manager.available.subscribe(),
+            timeout,
+        )
+        .await;
+        match outcome {
+            Some(outcome) => outcome,
+            None => manager.read().await.check_available(id),
+        }
+    }
+
+    /// Whether the triple is available, or `None` while it may still become available.
+    fn poll_available(&self, id: TripleId) -> Option<Result<(), GenerationError>> {
+        if self.triples.contains_key(&id) {
+            Some(Ok(()))
+        } else if self.generators.contains_key(&id)
+            || !(self.reserved.contains_key(&id)
+                || self.spent.contains(&id)
+                || self.gc.contains_key(&id))
+        {
+            None
+        } else {
+            Some(self.check_available(id))
+        }
+    }
+
+    fn notify_available(&self) {
+        self.available.send_modify(|version| *version += 1);
+    }
+
+    /// Checks that the triple with the given id is completed and unspent, returning the reason
+    /// it cannot be taken otherwise.
+    pub fn check_available(&self, id: TripleId) -> Result<(), GenerationError> {
+        if self.triples.contains_key(&id) {
+            Ok(())
+        } else if self.generators.contains_key(&id) {
+            tracing::warn!(id, "triple is generating");
+            Err(GenerationError::TripleIsGenerating(id))
+        } else if let Some(holder) = self.reserved.get(&id) {
+            tracing::warn!(id, ?holder, "triple is reserved");
+            Err(GenerationError::TripleIsReserved(id, *holder))
+        } else if self.spent.contains(&id) {
+            tracing::warn!(id, "triple is spent");
+            Err(GenerationError::TripleIsSpent(id))
+        } else if self.gc.contains_key(&id) {
+            tracing::warn!(id, "triple is garbage collected");
+            Err(GenerationError::TripleIsGarbageCollected(id))
+        } else {
+            tracing::warn!(id, "triple is missing");
+            Err(GenerationError::TripleIsMissing(id))
+        }
+    }
+
+    async fn record_spent(&mut self, ids: [TripleId; 2]) -> Result<(), GenerationError> {
+        let mut triple_storage = self.triple_storage.write().await;
+        for (i, id) in ids.iter().enumerate() {
+            if let Err(err) = triple_storage.insert_spent(*id, self.epoch).await {
+                tracing::error!(id, ?err, "unable to record spent triple");
+                // The triple is not taken, so the entries recorded so far are undone.
+                for recorded in &ids[..i] {
+                    let _ = triple_storage.delete_spent(*recorded, self.epoch).await;
+                }
+                return Err(GenerationError::TripleLedgerUnavailable(
+                    *id,
+                    err.to_string(),
+                ));
+            }
+        }
+        drop(triple_storage);
+        self.spent.extend(ids);
+        Ok(())
+    }
+
+    /// Removes the triple from the ledger of spent triples, for triples given back before their
+    /// presignature exchanged any message.
+    async fn unspend(&mut self, id: TripleId) {
+        if self.spent.remove(&id) {
+            if let Err(err) = self
+                .triple_storage
+                .write()
+                .await
+                .delete_spent(id, self.epoch)
+                .await
+            {
+                // The triple is then dropped on restart, which is the safe way to fail.
+                tracing::warn!(id, ?err, "unable to remove triple from the spent ledger");
+            }
+        }
+    }
+
     async fn delete_triple_from_storage(
         &mut self,
         id: TripleId,
     ) -> Result<(), error::DatastoreStorageError> {
         let action = || async {
             let mut triple_storage = self.triple_storage.write().await;
-            if let Err(err) = triple_storage.delete(id).await {
+            if let Err(err) = triple_storage.delete(id, self.epoch).await {
                 tracing::warn!(?err, id, "triple deletion failed.");
                 return Err(err);
             }
@@ -373,6 +1071,14 @@ impl TripleManager {
                     ?error,
                     "unable to take two triples: one or both of the triples are missing/not-generated",
                 );
+                self.requeue_mine(&[id0, id1]);
+                None
+            }
+            Err(error @ GenerationError::TripleLedgerUnavailable(..)) => {
+                tracing::warn!(
+                    ?error,
+                    "unable to take two triples: they cannot be recorded as spent"
+                );
                 self.mine.push_front(id1);
                 self.mine.push_front(id0);
                 None
@@ -390,26 +1096,149 @@ impl TripleManager {
         }
     }
 
+    /// Take `2n` unspent triples generated by this node as `n` pairs. Either takes all of them or
+    /// none. If any of the triples cannot be taken, the ones that are still usable are returned
+    /// to the front of the queue in their original order.
+    /// It is very important to NOT reuse the same triple twice for two different
+    /// protocols.
+    pub async fn take_two_mine_batch(&mut self, n: usize) -> Option<Vec<(Triple, Triple)>> {
+        if n == 0 || self.mine.len() < 2 * n {
+            return None;
+        }
+        let ids = if self.subsets.is_some() {
+            self.pair_by_subset(n)?
+        } else {
+            self.mine.drain(..2 * n).collect::<Vec<_>>()
+        };
+        tracing::info!(?ids, me = ?self.me, "trying to take a batch of mine triples");
+
+        if let Some(missing) = ids.iter().find(|id| !self.triples.contains_key(*id)) {
+            tracing::warn!(
+                triple_id = *missing,
+                "unable to take batch of triples: one of the triples is not available"
+            );
+            self.requeue_mine(&ids);
+            return None;
+        }
+
+        let mut pairs = Vec::with_capacity(n);
+        for pair in ids.chunks(2) {
+            let (id0, id1) = (pair[0], pair[1]);
+            match self.take_two(id0, id1).await {
+                Ok(triples) => pairs.push(triples),
+                Err(error) => {
+                    // All triples were checked to be present, but they may fail to be recorded
+                    // as spent. Return what was already taken so the batch stays all-or-nothing,
+                    // and put the whole batch back to the front of the queue in its order.
+                    tracing::warn!(
+                        triple_id0 = id0,
+                        triple_id1 = id1,
+                        ?error,
+                        "unable to take batch of triples"
+                    );
+                    for (triple0, triple1) in pairs {
+                        self.release(triple0, triple1).await;
+                    }
+                    self.requeue_mine(&ids);
+                    return None;
+                }
+            }
+        }
+        Some(pairs)
+    }
+
+    /// Takes the ids of `n` pairs of triples of mine generated by the same subset of participants
+    /// out of the queue, oldest first. A presignature needs both of its triples to be shared by
+    /// at least a threshold of participants, which a pair of different subsets may not be.
+    fn pair_by_subset(&mut self, n: usize) -> Option<Vec<TripleId>> {
+        let mut unpaired: HashMap<&[Participant], TripleId> = HashMap::new();
+        let mut ids = Vec::with_capacity(2 * n);
+        for id in &self.mine {
+            let Some(triple) = self.triples.get(id) else {
+                continue;
+            };
+            match unpaired.remove(triple.public.participants.as_slice()) {
+                Some(other) => {
+                    ids.extend([other, *id]);
+                    if ids.len() == 2 * n {
+                        break;
+                    }
+                }
+                None => {
+                    unpaired.insert(&triple.public.participants, *id);
+                }
+            }
+        }
+        if ids.len() < 2 * n {
+            tracing::debug!(
+                n,
+                "not enough pairs of triples generated by the same subset"
+            );
+            return None;
+        }
+        self.mine.retain(|id| !ids.contains(id));
+        Some(ids)
+    }
+
+    /// Returns the ids of triples of mine that could not be taken to the front of the queue, in
+    /// their original order. Ids of triples that are no longer held are dropped instead of being
+    /// requeued: they can never be taken, and would block the queue for good.
+    fn requeue_mine(&mut self, ids: &[TripleId]) {
+        for id in ids.iter().rev() {
+            if self.triples.contains_key(id) || self.generators.contains_key(id) {
+                self.mine.push_front(*id);
+            } else {
+                tracing::error!(id, "triple of mine is missing, dropping it from the queue");
+                crate::metrics::MANAGER_INVARIANT_VIOLATIONS
+                    .with_label_values(&[self.my_account_id.as_str(), "triple"])
+                    .inc();
+            }
+        }
+    }
+
     pub async fn insert_mine(&mut self, triple: Triple) {
         tracing::debug!(id = triple.id, "inserting mine triple");
         self.mine.push_back(triple.id);
+        self.held_since.insert(triple.id, self.clock.now());
         self.triples.insert(triple.id, triple.clone());
         self.gc.remove(&triple.id);
+        self.reserved.remove(&triple.id);
+        self.unspend(triple.id).await;
+        self.notify_available();
         self.insert_triples_to_storage(vec![triple]).await;
     }
 
+    /// Returns two triples taken with [`TripleManager::take_two_for`] whose presignature could
+    /// not be started, such that they can be used by another proposal. Triples must never be
+    /// released once their presignature has exchanged any messages.
+    pub async fn release(&mut self, triple0: Triple, triple1: Triple) {
+        tracing::info!(
+            id0 = triple0.id,
+            id1 = triple1.id,
+            "releasing reserved triples"
+        );
+        for triple in [&triple0, &triple1] {
+            self.held_since.insert(triple.id, self.clock.now());
+            self.triples.insert(triple.id, triple.clone());
+            self.gc.remove(&triple.id);
+            self.reserved.remove(&triple.id);
+            self.unspend(triple.id).await;
+        }
+        self.notify_available();
+        self.insert_triples_to_storage(vec![triple0, triple1]).await;
+    }
+
     /// Ensures that the triple with the given id is either:
     /// 1) Already generated in which case returns `None`, or
     /// 2) Is currently being generated by `protocol` in which case returns `Some(protocol)`, or
     /// 3) Has never been seen by the manager in which case start a new protocol and returns `Some(protocol)`
-    // TODO: What if the triple completed generation and is already spent?
     pub fn get_or_generate(
         &mut self,
         id: TripleId,
         participants: &Participants,
         cfg: &ProtocolConfig,
     ) -> Result<Option<&mut TripleProtocol>, CryptographicError> {
-        if self.triples.contains_key(&id) || self.gc.contains_key(&id) {
+        if self.triples.contains_key(&id) || self.gc.contains_key(&id) || self.spent.contains(&id) {
             Ok(None)
         } else {
             let potential_len = self.potential_len();
@@ -420,9 +1249,34 @@ impl TripleManager {
                         // sends more triple generation requests, reject them and have them tiemout.
                         return Ok(None);
                     }
+                    if shedding::is_shedding() {
+                        // Held back until the node stops shedding load or the messages expire.
+                        tracing::debug!(id, "shedding load, not joining triple");
+                        return Ok(None);
+                    }
 
+                    let participants = match &self.subsets {
+                        Some(subsets) => {
+                            let participants = subset(id, &subsets.participants, subsets.size);
+                            if !participants.contains(&self.me) {
+                                // Only a misbehaving proposer sends messages for a triple this
+                                // node was not selected for.
+                                tracing::warn!(id, "not selected to generate triple");
+                                return Ok(None);
+                            }
+                            participants
+                        }
+                        None => participants.keys_vec(),
+                    };
                     tracing::info!(id, "joining protocol to generate a new triple");
-                    let participants = participants.keys_vec();
+                    journal::record(
+                        self.epoch,
+                        ProtocolKind::Triple,
+                        id,
+                        Event::Started {
+                            participants: participants.clone(),
+                        },
+                    );
                     let protocol = Box::new(cait_sith::triples::generate_triple::<Secp256k1>(
                         &participants,
                         self.me,
@@ -433,6 +1287,7 @@ impl TripleManager {
                         participants,
                         protocol,
                         cfg.triple.generation_timeout,
+                        self.clock.clone(),
                     ));
                     self.queued.push_back(id);
                     crate::metrics::NUM_TOTAL_HISTORICAL_TRIPLE_GENERATORS
@@ -445,150 +1300,202 @@ impl TripleManager {
         }
     }
 
-    /// Pokes all of the ongoing generation protocols and returns a vector of
-    /// messages to be sent to the respective participant.
-    ///
-    /// An empty vector means we cannot progress until we receive a new message.
-    pub async fn poke(&mut self, cfg: &ProtocolConfig) -> Vec<(Participant, TripleMessage)> {
-        // Add more protocols to the ongoing pool if there is space.
-        let to_generate_len = cfg.max_concurrent_generation as usize - self.ongoing.len();
-        if !self.queued.is_empty() && to_generate_len > 0 {
-            for _ in 0..to_generate_len {
-                self.queued.pop_front().map(|id| self.ongoing.insert(id));
-            }
+    /// Pokes all of the ongoing generation protocols, returning the messages to be sent to the
+    /// respective participants alongside the protocols that failed and were dropped.
+    pub async fn poke(&mut self, cfg: &ProtocolConfig) -> PokeOutcome<TripleId, TripleMessage> {
+        self.poke_with_budget(cfg, usize::MAX).await
+    }
+
+    /// Same as [`TripleManager::poke`], but stops poking further protocols once `budget`
+    /// messages have been produced. The remaining protocols get poked in a later call.
+    pub async fn poke_with_budget(
+        &mut self,
+        cfg: &ProtocolConfig,
+        budget: usize,
+    ) -> PokeOutcome<TripleId, TripleMessage> {
+        // Add more protocols to the ongoing pool if there is space, as fast as pacing allows.
+        let max_in_flight = self
+            .max_in_flight
+            .map_or(cfg.max_concurrent_generation as usize, |max| {
+                max.min(cfg.max_concurrent_generation as usize)
+            });
+        let to_generate_len = max_in_flight
+            .saturating_sub(self.ongoing.len())
+            .min(self.queued.len());
+        let allowed = to_generate_len.min(self.pacer.available(self.clock.now()));
+        for id in self.queued.drain(..allowed) {
+            self.ongoing.insert(id);
         }
+        self.pacer.take(allowed);
+        let paced = to_generate_len - allowed;
+        crate::metrics::TRIPLE_PACING_ACTIVE
+            .with_label_values(&[self.my_account_id.as_str()])
+            .set((paced > 0) as i64);
+        crate::metrics::NUM_TRIPLE_GENERATORS_PACED
+            .with_label_values(&[self.my_account_id.as_str()])
+            .set(paced as i64);
 
         let mut messages = Vec::new();
         let mut triples_to_insert = Vec::new();
-        let mut errors = Vec::new();
-        self.generators.retain(|id, generator| {
-            if !self.ongoing.contains(id) {
-                // If the protocol is not ongoing, we should retain it for the next time
-                // it is in the ongoing pool.
-                return true;
-            }
+        let mut failures = Vec::new();
 
-            loop {
-                let action = match generator.poke() {
-                    Ok(action) => action,
-                    Err(e) => {
-                        errors.push(e);
-                        crate::metrics::TRIPLE_GENERATOR_FAILURES
+        // Generators are poked in parallel off the tokio workers. Protocols that are not ongoing
+        // are retained for the next time they are in the ongoing pool.
+        let mut ids = self
+            .generators
+            .keys()
+            .filter(|id| self.ongoing.contains(*id))
+            .copied()
+            .collect::<Vec<_>>();
+        generator::rotate(&mut ids, self.poke_cursor.take());
+        let poking = ids
+            .iter()
+            .filter_map(|id| self.generators.remove_entry(id))
+            .collect::<Vec<_>>();
+        let (keyspace, epoch, me) = (self.keyspace.clone(), self.epoch, self.me);
+        let poked = generator::poke_all(poking, budget, move |(_, generator)| {
+            generator.poke_until_blocked(&keyspace, epoch, me)
+        })
+        .await;
+
+        for ((id, mut generator), result) in poked {
+            let Some((generator_messages, status)) = result else {
+                // Out of budget for this poke, so it is retained to be poked first next time.
+                self.poke_cursor.get_or_insert(id);
+                self.generators.insert(id, generator);
+                continue;
+            };
+            let _span = generator.span.clone().entered();
+            generator.rounds.observe(
+                PokeKind::Triple,
+                self.my_account_id.as_str(),
+                matches!(status, PokeStatus::Completed(_)),
+            );
+            messages.extend(generator_messages);
+            match status {
+                PokeStatus::Waiting => {
+                    tracing::debug!("triple: waiting");
+                    // Retain protocol until we are finished
+                    self.generators.insert(id, generator);
+                }
+                PokeStatus::Failed(failure) => {
+                    let event = if failure.is_timeout() {
+                        Event::TimedOut
+                    } else {
+                        Event::Failed {
+                            reason: failure.to_string(),
+                        }
+                    };
+                    journal::record(self.epoch, ProtocolKind::Triple, id, event);
+                    participation::record(
+                        self.my_account_id.as_str(),
+                        self.epoch,
+                        ProtocolKind::Triple,
+                        &generator.participants,
+                        if failure.is_timeout() {
+                            Outcome::TimedOut
+                        } else {
+                            Outcome::Failed
+                        },
+                    );
+                    crate::metrics::TRIPLE_GENERATOR_FAILURES
+                        .with_label_values(&[self.my_account_id.as_str()])
+                        .inc();
+                    if failure.is_timeout() {
+                        crate::metrics::TRIPLE_GENERATOR_TIMEOUTS
                             .with_label_values(&[self.my_account_id.as_str()])
                             .inc();
-                        self.gc.insert(*id, Instant::now());
-                        self.ongoing.remove(id);
-                        self.introduced.remove(id);
-                        tracing::warn!(
-                            elapsed = ?generator.timestamp.unwrap().elapsed(),
-                            "added {id} to failed triples"
-                        );
-                        break false;
                     }
-                };
+                    self.gc.insert(id, self.clock.now());
+                    self.ongoing.remove(&id);
+                    self.introduced.remove(&id);
+                    tracing::warn!(
+                        elapsed = ?generator.timestamp.map(|t| self.clock.elapsed(t)),
+                        "added to failed triples"
+                    );
+                    failures.push((id, failure));
+                }
+                PokeStatus::Completed(output) => {
+                    tracing::info!(
+                        elapsed = ?generator.timestamp.map(|t| self.clock.elapsed(t)),
+                        big_a = ?output.1.big_a.to_base58(),
+                        big_b = ?output.1.big_b.to_base58(),
+                        big_c = ?output.1.big_c.to_base58(),
+                        "completed triple generation"
+                    );
+                    journal::record(self.epoch, ProtocolKind::Triple, id, Event::Completed);
+                    participation::record(
+                        self.my_account_id.as_str(),
+                        self.epoch,
+                        ProtocolKind::Triple,
+                        &generator.participants,
+                        Outcome::Completed,
+                    );
 
-                match action {
-                    Action::Wait => {
-                        tracing::debug!("triple: waiting");
-                        // Retain protocol until we are finished
-                        break true;
-                    }
-                    Action::SendMany(data) => {
-                        for p in &generator.participants {
-                            messages.push((
-                                *p,
-                                TripleMessage {
-                                    id: *id,
-                                    epoch: self.epoch,
-                                    from: self.me,
-                                    data: data.clone(),
-                                    timestamp: Utc::now().timestamp() as u64,
-                                },
-                            ))
-                        }
+                    if let Some(start_time) = generator.timestamp {
+                        crate::metrics::TRIPLE_LATENCY
+                            .with_label_values(&[self.my_account_id.as_str()])
+                            .observe(self.clock.elapsed(start_time).as_secs_f64());
                     }
-                    Action::SendPrivate(p, data) => messages.push((
-                        p,
-                        TripleMessage {
-                            id: *id,
-                            epoch: self.epoch,
-                            from: self.me,
-                            data,
-                            timestamp: Utc::now().timestamp() as u64,
-                        },
-                    )),
-                    Action::Return(output) => {
-                        tracing::info!(
-                            id,
-                            me = ?self.me,
-                            elapsed = ?generator.timestamp.unwrap().elapsed(),
-                            big_a = ?output.1.big_a.to_base58(),
-                            big_b = ?output.1.big_b.to_base58(),
-                            big_c = ?output.1.big_c.to_base58(),
-                            "completed triple generation"
-                        );
-
-                        if let Some(start_time) = generator.timestamp {
-                            crate::metrics::TRIPLE_LATENCY
-                                .with_label_values(&[self.my_account_id.as_str()])
-                                .observe(start_time.elapsed().as_secs_f64());
-                        }
 
-                        crate::metrics::NUM_TOTAL_HISTORICAL_TRIPLE_GENERATORS_SUCCESS
-                            .with_label_values(&[self.my_account_id.as_str()])
-                            .inc();
+                    crate::metrics::NUM_TOTAL_HISTORICAL_TRIPLE_GENERATORS_SUCCESS
+                        .with_label_values(&[self.my_account_id.as_str()])
+                        .inc();
 
-                        let triple = Triple {
-                            id: *id,
-                            share: output.0,
-                            public: output.1,
-                        };
-
-                        // After creation the triple is assigned to a random node, which is NOT necessarily the one that initiated it's creation
-                        let triple_is_mine = {
-                            // This is an entirely unpredictable value to all participants because it's a combination of big_c_i
-                            // It is the same value across all participants
-                            let big_c = triple.public.big_c;
-
-                            // We turn this into a u64 in a way not biased to the structure of the byte serialisation so we hash it
-                            // We use Highway Hash because the DefaultHasher doesn't guarantee a consistent output across versions
-                            let entropy =
-                                HighwayHasher::default().hash64(&big_c.to_bytes()) as usize;
-
-                            let num_participants = generator.participants.len();
-                            // This has a *tiny* bias towards lower indexed participants, they're up to (1 + num_participants / u64::MAX)^2 times more likely to be selected
-                            // This is acceptably small that it will likely never result in a biased selection happening
-                            let triple_owner = generator.participants[entropy % num_participants];
-
-                            triple_owner == self.me
-                        };
-
-                        if triple_is_mine {
-                            self.mine.push_back(*id);
-                            crate::metrics::NUM_TOTAL_HISTORICAL_TRIPLE_GENERATIONS_MINE_SUCCESS
-                                .with_label_values(&[self.my_account_id.as_str()])
-                                .inc();
-                        }
+                    let triple = Triple {
+                        id,
+                        share: output.0,
+                        public: output.1,
+                    };
+
+                    // After creation the triple is assigned to a random node, which is NOT necessarily the one that initiated it's creation
+                    let triple_is_mine = {
+                        // This is an entirely unpredictable value to all participants because it's a combination of big_c_i
+                        // It is the same value across all participants
+                        let big_c = triple.public.big_c;
+
+                        // We turn this into a u64 in a way not biased to the structure of the byte serialisation so we hash it
+                        // We use Highway Hash because the DefaultHasher doesn't guarantee a consistent output across versions
+                        let entropy = HighwayHasher::default().hash64(&big_c.to_bytes()) as usize;
+
+                        let num_participants = generator.participants.len();
+                        // This has a *tiny* bias towards lower indexed participants, they're up to (1 + num_participants / u64::MAX)^2 times more likely to be selected
+                        // This is acceptably small that it will likely never result in a biased selection happening
+                        let triple_owner = generator.participants[entropy % num_participants];
 
-                        self.triples.insert(*id, triple.clone());
-                        triples_to_insert.push(triple);
+                        triple_owner == self.me
+                    };
 
-                        // Protocol done, remove it from the ongoing pool.
-                        self.ongoing.remove(id);
-                        self.introduced.remove(id);
-                        // Do not retain the protocol
-                        break false;
+                    if triple_is_mine {
+                        self.mine.push_back(id);
+                        crate::metrics::NUM_TOTAL_HISTORICAL_TRIPLE_GENERATIONS_MINE_SUCCESS
+                            .with_label_values(&[self.my_account_id.as_str()])
+                            .inc();
                     }
+
+                    events::emit(NodeEvent::TripleCompleted {
+                        epoch: self.epoch,
+                        id,
+                        mine: triple_is_mine,
+                    });
+                    self.held_since.insert(id, self.clock.now());
+                    self.triples.insert(id, triple.clone());
+                    self.notify_available();
+                    triples_to_insert.push(triple);
+
+                    // Protocol done, remove it from the ongoing pool.
+                    self.ongoing.remove(&id);
+                    self.introduced.remove(&id);
                 }
             }
-        });
+        }
         self.insert_triples_to_storage(triples_to_insert).await;
 
-        if !errors.is_empty() {
-            tracing::warn!(?errors, "faled to generate some triples");
+        if !failures.is_empty() {
+            tracing::warn!(?failures, "failed to generate some triples");
         }
 
-        messages
+        PokeOutcome { messages, failures }
     }
 
     async fn insert_triples_to_storage(&mut self, triples_to_insert: Vec<Triple>) {
@@ -596,7 +1503,10 @@ impl TripleManager {
             let mine = self.mine.contains(&triple.id);
             let action = || async {
                 let mut triple_storage = self.triple_storage.write().await;
-                if let Err(e) = triple_storage.insert(triple.clone(), mine).await {
+                if let Err(e) = triple_storage
+                    .insert(triple.clone(), self.epoch, mine)
+                    .await
+                {
                     tracing::warn!(?e, id = triple.id, "triple insertion failed.");
                     return Err(e);
                 }
@@ -610,6 +1520,38 @@ impl TripleManager {
     }
 }
 
+/// Parks until `poll` gives an outcome, polling again whenever the channel returned by
+/// `subscribe` signals a change. Returns `None` if `timeout` elapsed first.
+pub(crate) async fn park_until<M, T>(
+    manager: &RwLock<M>,
+    poll: impl Fn(&M) -> Option<T>,
+    subscribe: impl Fn(&M) -> watch::Receiver<u64>,
+    timeout: Duration,
+) -> Option<T> {
+    let mut changes = {
+        let manager = manager.read().await;
+        if let Some(outcome) = poll(&manager) {
+            return Some(outcome);
+        }
+        // Subscribing under the same lock as the poll, so that no change is missed in between.
+        subscribe(&manager)
+    };
+    let park = async {
+        loop {
+            let changed = changes.changed().await;
+            let manager = manager.read().await;
+            if let Some(outcome) = poll(&manager) {
+                return outcome;
+            }
+            if changed.is_err() {
+                // The manager was replaced, e.g. on a new epoch, so listen to the new one.
+                changes = subscribe(&manager);
+            }
+        }
+    };
+    tokio::time::timeout(timeout, park).await.ok()
+}
+
 #[cfg(test)]
 mod test {
     // TODO: This test currently takes 22 seconds on my machine, which is much slower than it should be
@@ -623,4 +1565,44 @@ mod test {
     async fn test_triple_deletion_locally() {
         crate::test_utils::test_triple_deletion(None).await
     }
+
+    #[test]
+    fn test_subset_is_deterministic() {
+        use cait_sith::protocol::Participant;
+
+        let participants: Vec<_> = (0..7u32).map(Participant::from).collect();
+        let mut shuffled = participants.clone();
+        shuffled.reverse();
+        for id in 0..100 {
+            let subset = super::subset(id, &participants, 4);
+            assert_eq!(subset.len(), 4);
+            assert!(subset.windows(2).all(|w| w[0] < w[1]));
+            // The order the participants are known in does not matter.
+            assert_eq!(subset, super::subset(id, &shuffled, 4));
+        }
+        // Different ids select different subsets, spreading the load among participants.
+        let selected: std::collections::HashSet<_> = (0..100)
+            .flat_map(|id| super::subset(id, &participants, 4))
+            .collect();
+        assert_eq!(selected.len(), participants.len());
+    }
+
+    #[tokio::test]
+    async fn test_take_two_mine_drops_missing_triples() {
+        use cait_sith::protocol::Participant;
+        use std::sync::Arc;
+        use tokio::sync::RwLock;
+
+        let account_id: near_account_id::AccountId = "node0.test".parse().unwrap();
+        let storage = Arc::new(RwLock::new(crate::storage::triple_storage::init(
+            None,
+            &account_id,
+        )));
+        let mut manager =
+            super::TripleManager::new(Participant::from(0u32), 2, 0, vec![], storage, &account_id);
+        // Ids of mine without a triple behind them must not block the queue forever.
+        manager.mine.extend([1, 2]);
+        assert!(manager.take_two_mine().await.is_none());
+        assert!(manager.mine.is_empty());
+    }
 }
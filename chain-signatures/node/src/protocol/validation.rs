@@ -0,0 +1,229 @@
+//! Validation of sign requests before they consume a presignature. The [`SignatureManager`] runs
+//! the [`Validators`] on the requests it proposes, before matching them with a presignature, and
+//! on the ones it is asked to join, before reserving its presignature. A rejected request is given
+//! up on with the [`Rejection`] recorded as its state, which the requester can look up on
+//! `/sign_requests/:receipt_id`.
+//!
+//! The built-in validators check that the payload is a digest that can be signed and that the key
+//! derived for the request is valid. Deployments add their own policies by implementing
+//! [`RequestValidator`], such as the [`PathDenyList`] configured with `--deny-derivation-path`.
+//!
+//! [`SignatureManager`]: super::signature::SignatureManager
+
+use std::sync::Arc;
+
+use crypto_shared::PublicKey;
+use k256::{AffinePoint, Scalar};
+use serde::{Deserialize, Serialize};
+
+use super::signature::ReceiptId;
+use crate::indexer::ContractSignRequest;
+use crate::kdf;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, clap::Parser)]
+#[group(id = "validation_options")]
+pub struct Options {
+    /// Derivation paths that are refused signatures. A request is refused if its path starts with
+    /// any of these prefixes.
+    #[arg(long, env("MPC_DENY_DERIVATION_PATHS"), value_delimiter(','))]
+    pub deny_derivation_path: Vec<String>,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        self.deny_derivation_path
+            .into_iter()
+            .flat_map(|prefix| ["--deny-derivation-path".to_string(), prefix])
+            .collect()
+    }
+}
+
+/// Why a sign request was refused.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Rejection {
+    /// The payload is not a digest that can be signed.
+    #[error("payload is not a valid digest: {reason}")]
+    InvalidDigest { reason: String },
+    /// The key derived for the request is not a valid point of the curve.
+    #[error("derived key is not a valid point")]
+    InvalidDerivedKey,
+    /// The derivation path is denied by the operator of the node.
+    #[error("derivation path is denied by `{prefix}`")]
+    DeniedPath { prefix: String },
+    /// The request was refused by a policy of the deployment.
+    #[error("refused by {validator}: {reason}")]
+    Policy { validator: String, reason: String },
+}
+
+/// A sign request about to consume a presignature.
+#[derive(Clone, Copy, Debug)]
+pub struct PendingRequest<'a> {
+    pub receipt_id: ReceiptId,
+    pub request: &'a ContractSignRequest,
+    pub epsilon: Scalar,
+    /// Root public key the request is signed under.
+    pub public_key: &'a PublicKey,
+}
+
+pub trait RequestValidator: std::fmt::Debug + Send + Sync {
+    /// Whether the request may consume a presignature. Validators must be deterministic, such
+    /// that the participants of a signature agree on whether to sign it.
+    fn validate(&self, pending: &PendingRequest<'_>) -> Result<(), Rejection>;
+}
+
+/// Refuses payloads that cannot be signed. The payload is already a canonical scalar of 32 bytes
+/// when indexed, but a zero digest would let anyone forge the signature.
+#[derive(Clone, Debug, Default)]
+pub struct DigestValidator;
+
+impl RequestValidator for DigestValidator {
+    fn validate(&self, pending: &PendingRequest<'_>) -> Result<(), Rejection> {
+        if pending.request.payload == Scalar::ZERO {
+            return Err(Rejection::InvalidDigest {
+                reason: "payload is zero".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Refuses requests whose derived key is the identity, for which no signature verifies.
+#[derive(Clone, Debug, Default)]
+pub struct DerivedKeyValidator;
+
+impl RequestValidator for DerivedKeyValidator {
+    fn validate(&self, pending: &PendingRequest<'_>) -> Result<(), Rejection> {
+        if kdf::cached_key(*pending.public_key, pending.epsilon) == AffinePoint::IDENTITY {
+            return Err(Rejection::InvalidDerivedKey);
+        }
+        Ok(())
+    }
+}
+
+/// Refuses the derivation paths starting with any of the prefixes.
+#[derive(Clone, Debug, Default)]
+pub struct PathDenyList {
+    pub prefixes: Vec<String>,
+}
+
+impl RequestValidator for PathDenyList {
+    fn validate(&self, pending: &PendingRequest<'_>) -> Result<(), Rejection> {
+        match self
+            .prefixes
+            .iter()
+            .find(|prefix| pending.request.path.starts_with(prefix.as_str()))
+        {
+            Some(prefix) => Err(Rejection::DeniedPath {
+                prefix: prefix.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Validators run in order on every request, the first rejection winning.
+#[derive(Clone, Debug)]
+pub struct Validators {
+    validators: Vec<Arc<dyn RequestValidator>>,
+}
+
+impl Default for Validators {
+    /// The built-in validators.
+    fn default() -> Self {
+        Self {
+            validators: vec![Arc::new(DigestValidator), Arc::new(DerivedKeyValidator)],
+        }
+    }
+}
+
+impl Validators {
+    /// Adds a validator, run after the ones already there.
+    pub fn with(mut self, validator: Arc<dyn RequestValidator>) -> Self {
+        self.validators.push(validator);
+        self
+    }
+
+    pub fn validate(&self, pending: &PendingRequest<'_>) -> Result<(), Rejection> {
+        self.validators
+            .iter()
+            .try_for_each(|validator| validator.validate(pending))
+    }
+}
+
+/// The built-in validators along with the ones configured by the options.
+pub fn from_options(opts: &Options) -> Validators {
+    let validators = Validators::default();
+    if opts.deny_derivation_path.is_empty() {
+        return validators;
+    }
+    validators.with(Arc::new(PathDenyList {
+        prefixes: opts.deny_derivation_path.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ProjectivePoint;
+
+    use super::*;
+
+    #[test]
+    fn test_validators() {
+        let public_key = (ProjectivePoint::GENERATOR * Scalar::from(7u64)).to_affine();
+        let request = ContractSignRequest {
+            payload: Scalar::from(42u64),
+            path: "vault/cold".to_string(),
+            key_version: 0,
+        };
+        let pending = PendingRequest {
+            receipt_id: ReceiptId::default(),
+            request: &request,
+            epsilon: Scalar::ONE,
+            public_key: &public_key,
+        };
+        assert_eq!(Validators::default().validate(&pending), Ok(()));
+
+        let zero = ContractSignRequest {
+            payload: Scalar::ZERO,
+            ..request.clone()
+        };
+        assert!(matches!(
+            Validators::default().validate(&PendingRequest {
+                request: &zero,
+                ..pending
+            }),
+            Err(Rejection::InvalidDigest { .. })
+        ));
+
+        // The derived key is the identity if the tweak cancels out the root key.
+        assert_eq!(
+            Validators::default().validate(&PendingRequest {
+                epsilon: -Scalar::from(7u64),
+                ..pending
+            }),
+            Err(Rejection::InvalidDerivedKey)
+        );
+
+        let validators = from_options(&Options {
+            deny_derivation_path: vec!["hot/".to_string(), "vault/".to_string()],
+        });
+        assert_eq!(
+            validators.validate(&pending),
+            Err(Rejection::DeniedPath {
+                prefix: "vault/".to_string()
+            })
+        );
+        let allowed = ContractSignRequest {
+            path: "wallet/vault/".to_string(),
+            ..request.clone()
+        };
+        assert_eq!(
+            validators.validate(&PendingRequest {
+                request: &allowed,
+                ..pending
+            }),
+            Ok(())
+        );
+    }
+}
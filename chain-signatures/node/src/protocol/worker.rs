@@ -0,0 +1,724 @@
+//! The protocols of the running state are cranked by a task per manager, rather than all at once
+//! by the protocol loop while it holds the node state. The protocol loop acts as the consensus
+//! layer: it routes the messages of our epoch into the inbox of the worker of their manager, and
+//! commands every worker to crank with a [`Tick`], its view of the node for that iteration. The
+//! messages produced by the workers go through a shared outbox, which the protocol loop drains
+//! into the outgoing message queue before sending it out.
+//!
+//! Inboxes and command channels are bounded, such that a worker falling behind does not hold up
+//! the others. Its messages are held back, up to a bound past which the oldest are dropped, and
+//! its cranks are skipped until it catches up, all of which are reported by the metrics of the
+//! worker. A worker that stopped, e.g. since it panicked, is respawned as soon as it is found out.
+//!
+//! Workers lock the managers they need in the same order as the rest of the node: triples, then
+//! presignatures, then signatures. The protocol loop does not wait on the locks of the triple and
+//! presignature managers, it samples the usage of the node from the pool sizes last published by
+//! their workers instead.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use cait_sith::protocol::Participant;
+use near_account_id::AccountId;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::RwLock;
+
+use super::contract::primitives::Participants;
+use super::message::MpcMessage;
+use super::reputation::Reputation;
+use super::router::MpcMessageQueue;
+use super::scheduler::{PokeKind, PokeTick};
+use super::state::RunningState;
+use super::{policy, validation};
+use crate::config::Config;
+use crate::mesh::{Mesh, MeshState};
+use crate::shedding::{self, Ceiling, Usage};
+use crate::shutdown;
+
+/// Number of messages an inbox holds before the messages of its worker are held back.
+const INBOX_CAPACITY: usize = 4096;
+
+/// Number of messages held back for busy workers, past which the oldest are dropped. Their
+/// protocols time out like they would on a lost message.
+const MAX_HELD_MESSAGES: usize = 4 * INBOX_CAPACITY;
+
+/// Number of cranks in a row a worker skips before it is reported as stuck.
+const STUCK_AFTER_SKIPPED_CRANKS: usize = 100;
+
+/// View of the node taken by the protocol loop, with which the workers crank their protocols.
+pub struct Tick {
+    pub running: RunningState,
+    pub me: Participant,
+    pub my_account_id: AccountId,
+    pub cfg: Config,
+    /// Health of the mesh, with which new protocols are started.
+    pub mesh: MeshState,
+    /// Active participants, with which the protocols started by others are joined.
+    pub active: Participants,
+    pub stockpile: Stockpile,
+    /// Message budget of the tick. Each worker spends its share from a copy of its own, such that
+    /// the budget left unused by one is not handed down to the others as they crank concurrently.
+    pub budget: PokeTick,
+}
+
+/// Whether new triples and presignatures are stockpiled in this tick.
+#[derive(Clone, Copy, Debug)]
+pub struct Stockpile {
+    /// No new protocol is started while shutting down, only the ones in flight are completed.
+    pub draining: bool,
+    pub usage: Usage,
+    pub shedding: bool,
+    /// Number of triples to evict to shed load.
+    pub to_evict: usize,
+    /// Work proposed with barely enough responsive participants is doomed as soon as one of them
+    /// stops responding, so nothing new is proposed until there is some margin.
+    pub lacks_liveness: bool,
+    pub required_live: usize,
+}
+
+/// Number of triples and presignatures held or being generated, as of the last crank of their
+/// worker.
+#[derive(Default)]
+struct Pools {
+    triples: AtomicUsize,
+    presignatures: AtomicUsize,
+}
+
+impl Tick {
+    /// Takes the view of the node for the workers, unless too few participants are active for the
+    /// protocols to make any progress.
+    async fn new(
+        running: &RunningState,
+        me: Participant,
+        my_account_id: &AccountId,
+        cfg: &Config,
+        mesh: &Mesh,
+        pools: &Pools,
+    ) -> Option<Self> {
+        let mesh_state = mesh.state();
+        let active = &mesh_state.active;
+        if active.len() < running.threshold {
+            return None;
+        }
+
+        let usage = Usage::sample(
+            pools.triples.load(Ordering::Relaxed) + pools.presignatures.load(Ordering::Relaxed),
+        );
+        let ceiling = Ceiling::new(&cfg.local.scheduler);
+        let shedding = shedding::update(&usage, &ceiling, my_account_id);
+        let required_live = (running.threshold + cfg.local.scheduler.liveness_margin)
+            .min(running.participants.len());
+        let lacks_liveness = active.len() < required_live;
+        crate::metrics::STOCKPILE_PAUSED_FOR_LIVENESS
+            .with_label_values(&[my_account_id.as_str()])
+            .set(lacks_liveness as i64);
+        let pending_messages = running.messages.read().await.len();
+
+        Some(Self {
+            running: running.clone(),
+            me,
+            my_account_id: my_account_id.clone(),
+            cfg: cfg.clone(),
+            mesh: mesh_state.clone(),
+            active: mesh.active_participants().clone(),
+            stockpile: Stockpile {
+                draining: shutdown::is_draining(),
+                usage,
+                shedding,
+                to_evict: if shedding {
+                    ceiling.to_evict(&usage)
+                } else {
+                    0
+                },
+                lacks_liveness,
+                required_live,
+            },
+            budget: PokeTick::new(&cfg.local.scheduler, pending_messages),
+        })
+    }
+}
+
+/// Commands of the protocol loop to a worker.
+enum Command {
+    /// Handles the messages received so far and advances the protocols of the manager.
+    Crank(Arc<Tick>),
+    /// Applies the options of the local config that changed to the manager.
+    Reconfigure { running: RunningState, cfg: Config },
+}
+
+/// Sending half of the outbox shared by the workers.
+#[derive(Clone)]
+pub struct WorkerOutbox(mpsc::UnboundedSender<(Participant, MpcMessage)>);
+
+impl WorkerOutbox {
+    pub fn push(&self, to: Participant, message: MpcMessage) {
+        // The outbox is only closed once the protocol loop stopped, along with the workers.
+        let _ = self.0.send((to, message));
+    }
+}
+
+struct Handle {
+    inbox: mpsc::Sender<MpcMessage>,
+    commands: mpsc::Sender<Command>,
+    /// Number of cranks skipped in a row since the worker was last cranked.
+    skipped_cranks: usize,
+}
+
+/// Handles of the protocol loop to the workers.
+pub struct Workers {
+    triple: Handle,
+    presignature: Handle,
+    signature: Handle,
+    outbox: mpsc::UnboundedReceiver<(Participant, MpcMessage)>,
+    /// Sending half of the outbox, for the workers respawned later on.
+    outbox_tx: WorkerOutbox,
+    /// Messages of the workers whose inbox was full, oldest first, see [`Workers::deliver`].
+    held: VecDeque<(PokeKind, MpcMessage)>,
+    pools: Arc<Pools>,
+    reputation: Arc<RwLock<Reputation>>,
+    /// Workers yet to be spawned, see [`Workers::spawn`].
+    pending: Vec<Worker>,
+    my_account_id: AccountId,
+}
+
+impl Workers {
+    pub fn new(my_account_id: &AccountId, reputation: Arc<RwLock<Reputation>>) -> Self {
+        let (outbox_tx, outbox) = mpsc::unbounded_channel();
+        let outbox_tx = WorkerOutbox(outbox_tx);
+        let pools = Arc::new(Pools::default());
+        let mut pending = Vec::new();
+        let mut worker = |kind| {
+            let (handle, worker) = Worker::new(
+                kind,
+                outbox_tx.clone(),
+                pools.clone(),
+                reputation.clone(),
+                my_account_id,
+            );
+            pending.push(worker);
+            handle
+        };
+        let triple = worker(PokeKind::Triple);
+        let presignature = worker(PokeKind::Presignature);
+        let signature = worker(PokeKind::Signature);
+        Self {
+            triple,
+            presignature,
+            signature,
+            outbox,
+            outbox_tx,
+            held: VecDeque::new(),
+            pools,
+            reputation,
+            pending,
+            my_account_id: my_account_id.clone(),
+        }
+    }
+
+    /// Spawns the tasks of the workers, once the protocol loop runs.
+    pub fn spawn(&mut self) {
+        for worker in self.pending.drain(..) {
+            tokio::spawn(worker.run());
+        }
+    }
+
+    fn handle(&self, kind: PokeKind) -> &Handle {
+        match kind {
+            PokeKind::Triple => &self.triple,
+            PokeKind::Presignature => &self.presignature,
+            PokeKind::Signature => &self.signature,
+        }
+    }
+
+    fn handle_mut(&mut self, kind: PokeKind) -> &mut Handle {
+        match kind {
+            PokeKind::Triple => &mut self.triple,
+            PokeKind::Presignature => &mut self.presignature,
+            PokeKind::Signature => &mut self.signature,
+        }
+    }
+
+    /// Replaces the worker of the given kind, which stopped while the protocol loop still runs.
+    /// The managers are shared with the new worker, only the messages left in the inbox and the
+    /// queue of the old one are lost, and their protocols time out.
+    fn respawn(&mut self, kind: PokeKind) {
+        tracing::error!(
+            worker = kind.as_str(),
+            "worker stopped unexpectedly, respawning it"
+        );
+        crate::metrics::NUM_WORKER_RESPAWNS
+            .with_label_values(&[self.my_account_id.as_str(), kind.as_str()])
+            .inc();
+        let (handle, worker) = Worker::new(
+            kind,
+            self.outbox_tx.clone(),
+            self.pools.clone(),
+            self.reputation.clone(),
+            &self.my_account_id,
+        );
+        *self.handle_mut(kind) = handle;
+        tokio::spawn(worker.run());
+    }
+
+    /// Puts the messages into the inboxes of their workers. The messages of a worker whose inbox
+    /// is full are held, and handed to it ahead of the newer ones once it catches up. Past
+    /// [`MAX_HELD_MESSAGES`], the oldest held messages are dropped. Returns how many are held.
+    pub fn deliver(&mut self, messages: impl IntoIterator<Item = (PokeKind, MpcMessage)>) -> usize {
+        let held = std::mem::take(&mut self.held);
+        // Once the inbox of a worker is full, its later messages are held too, so that they are
+        // not handed to it ahead of the held ones.
+        let mut full = Vec::new();
+        for (kind, message) in held.into_iter().chain(messages) {
+            let message = if full.contains(&kind) {
+                message
+            } else {
+                match self.try_deliver(kind, message) {
+                    Ok(()) => continue,
+                    Err(message) => {
+                        full.push(kind);
+                        message
+                    }
+                }
+            };
+            crate::metrics::NUM_WORKER_MESSAGES_HELD
+                .with_label_values(&[self.my_account_id.as_str(), kind.as_str()])
+                .inc();
+            self.held.push_back((kind, message));
+        }
+
+        let excess = self.held.len().saturating_sub(MAX_HELD_MESSAGES);
+        if excess > 0 {
+            tracing::warn!(
+                dropped = excess,
+                "too many messages held for busy workers, dropping the oldest"
+            );
+            for (kind, _) in self.held.drain(..excess) {
+                crate::metrics::NUM_WORKER_MESSAGES_DROPPED
+                    .with_label_values(&[self.my_account_id.as_str(), kind.as_str()])
+                    .inc();
+            }
+        }
+        self.held.len()
+    }
+
+    /// Puts the message into the inbox of the worker of the given kind, respawning the worker if
+    /// it stopped, or gives it back if the inbox is full.
+    fn try_deliver(&mut self, kind: PokeKind, message: MpcMessage) -> Result<(), MpcMessage> {
+        match self.handle(kind).inbox.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(message)) => Err(message),
+            Err(TrySendError::Closed(message)) => {
+                self.respawn(kind);
+                self.handle(kind)
+                    .inbox
+                    .try_send(message)
+                    .map_err(TrySendError::into_inner)
+            }
+        }
+    }
+
+    /// Commands every worker to crank, skipping the workers still busy with their previous crank.
+    /// Nothing is cranked while too few participants are active for the protocols to progress.
+    pub async fn crank(
+        &mut self,
+        running: &RunningState,
+        me: Participant,
+        cfg: &Config,
+        mesh: &Mesh,
+    ) {
+        let Some(tick) = Tick::new(running, me, &self.my_account_id, cfg, mesh, &self.pools).await
+        else {
+            return;
+        };
+        self.send_crank(Arc::new(tick));
+    }
+
+    fn send_crank(&mut self, tick: Arc<Tick>) {
+        for kind in [
+            PokeKind::Signature,
+            PokeKind::Presignature,
+            PokeKind::Triple,
+        ] {
+            let my_account_id = self.my_account_id.clone();
+            let labels = [my_account_id.as_str(), kind.as_str()];
+            let inbox = &self.handle(kind).inbox;
+            crate::metrics::WORKER_INBOX_SIZE
+                .with_label_values(&labels)
+                .set((inbox.max_capacity() - inbox.capacity()) as i64);
+            let sent = match self
+                .handle(kind)
+                .commands
+                .try_send(Command::Crank(tick.clone()))
+            {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => false,
+                Err(TrySendError::Closed(command)) => {
+                    self.respawn(kind);
+                    self.handle(kind).commands.try_send(command).is_ok()
+                }
+            };
+
+            let handle = self.handle_mut(kind);
+            if sent {
+                handle.skipped_cranks = 0;
+                continue;
+            }
+            handle.skipped_cranks += 1;
+            crate::metrics::NUM_WORKER_CRANKS_SKIPPED
+                .with_label_values(&labels)
+                .inc();
+            if handle.skipped_cranks % STUCK_AFTER_SKIPPED_CRANKS == 0 {
+                tracing::warn!(
+                    worker = kind.as_str(),
+                    skipped = handle.skipped_cranks,
+                    "worker is still busy with a crank, skipped the ones since"
+                );
+            } else {
+                tracing::debug!(worker = kind.as_str(), "worker is busy, skipping crank");
+            }
+        }
+    }
+
+    /// Commands the workers of the given kinds to apply the local config, waiting for them to be
+    /// done with their current crank.
+    pub async fn reconfigure(&mut self, kinds: &[PokeKind], running: &RunningState, cfg: &Config) {
+        for kind in kinds {
+            let command = Command::Reconfigure {
+                running: running.clone(),
+                cfg: cfg.clone(),
+            };
+            if let Err(err) = self.handle(*kind).commands.send(command).await {
+                self.respawn(*kind);
+                if self.handle(*kind).commands.send(err.0).await.is_err() {
+                    tracing::warn!(
+                        worker = kind.as_str(),
+                        "worker stopped, cannot reconfigure it"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Takes the messages the workers produced since the last time.
+    pub fn take_outbox(&mut self) -> Vec<(Participant, MpcMessage)> {
+        let mut messages = Vec::new();
+        while let Ok(message) = self.outbox.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+}
+
+struct Worker {
+    kind: PokeKind,
+    inbox: mpsc::Receiver<MpcMessage>,
+    commands: mpsc::Receiver<Command>,
+    outbox: WorkerOutbox,
+    /// Messages received but not handled yet, e.g. of presignatures waiting on their triples.
+    queue: MpcMessageQueue,
+    pools: Arc<Pools>,
+    reputation: Arc<RwLock<Reputation>>,
+    my_account_id: AccountId,
+}
+
+impl Worker {
+    fn new(
+        kind: PokeKind,
+        outbox: WorkerOutbox,
+        pools: Arc<Pools>,
+        reputation: Arc<RwLock<Reputation>>,
+        my_account_id: &AccountId,
+    ) -> (Handle, Self) {
+        let (inbox_tx, inbox) = mpsc::channel(INBOX_CAPACITY);
+        // A single crank waits for the worker, the others are skipped while it is busy.
+        let (commands_tx, commands) = mpsc::channel(1);
+        let handle = Handle {
+            inbox: inbox_tx,
+            commands: commands_tx,
+            skipped_cranks: 0,
+        };
+        let worker = Self {
+            kind,
+            inbox,
+            commands,
+            outbox,
+            queue: MpcMessageQueue::default(),
+            pools,
+            reputation,
+            my_account_id: my_account_id.clone(),
+        };
+        (handle, worker)
+    }
+
+    async fn run(mut self) {
+        while let Some(command) = self.commands.recv().await {
+            match command {
+                Command::Crank(tick) => self.crank(&tick).await,
+                Command::Reconfigure { running, cfg } => self.reconfigure(&running, &cfg).await,
+            }
+        }
+        tracing::debug!(worker = self.kind.as_str(), "worker stopped");
+    }
+
+    async fn crank(&mut self, tick: &Tick) {
+        let started_at = Instant::now();
+        while let Ok(message) = self.inbox.try_recv() {
+            self.queue.enqueue(message);
+        }
+        // Messages of the previous epochs are left over once the running state moved on.
+        self.queue.route_epochs(tick.running.epoch);
+
+        let running = &tick.running;
+        match self.kind {
+            PokeKind::Triple => {
+                running.handle_triple_messages(tick, &mut self.queue).await;
+                running.crank_triples(tick, &self.outbox).await;
+                let len = running.triple_manager.read().await.potential_len();
+                self.pools.triples.store(len, Ordering::Relaxed);
+            }
+            PokeKind::Presignature => {
+                running
+                    .handle_presignature_messages(tick, &self.reputation, &mut self.queue)
+                    .await;
+                running.crank_presignatures(tick, &self.outbox).await;
+                let len = running.presignature_manager.read().await.potential_len();
+                self.pools.presignatures.store(len, Ordering::Relaxed);
+            }
+            PokeKind::Signature => {
+                running
+                    .handle_signature_messages(tick, &self.reputation, &mut self.queue)
+                    .await;
+                running.crank_signatures(tick, &self.outbox).await;
+            }
+        }
+
+        crate::metrics::WORKER_CRANK_LATENCY
+            .with_label_values(&[self.my_account_id.as_str(), self.kind.as_str()])
+            .observe(started_at.elapsed().as_secs_f64());
+    }
+
+    async fn reconfigure(&self, running: &RunningState, cfg: &Config) {
+        let scheduler = &cfg.local.scheduler;
+        match self.kind {
+            PokeKind::Triple => {
+                let mut triple_manager = running.triple_manager.write().await;
+                triple_manager.set_pacing(scheduler);
+                triple_manager.set_policy(policy::from_options(scheduler));
+            }
+            PokeKind::Presignature => {
+                running
+                    .presignature_manager
+                    .write()
+                    .await
+                    .set_caps(scheduler);
+            }
+            PokeKind::Signature => {
+                running
+                    .signature_manager
+                    .write()
+                    .await
+                    .set_validators(validation::from_options(&cfg.local.validation));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MessageQueue;
+    use crate::keyspace::KeyspaceId;
+    use crate::protocol::message::AbortMessage;
+    use crate::protocol::monitor::StuckMonitor;
+    use crate::protocol::presignature::PresignatureManager;
+    use crate::protocol::refresh::Refresher;
+    use crate::protocol::registry::ParticipantRegistry;
+    use crate::protocol::signature::SignatureManager;
+    use crate::protocol::triple::TripleManager;
+    use crate::protocol::SignQueue;
+    use crate::storage::{presignature_storage, triple_storage};
+    use zeroize::Zeroizing;
+
+    fn account_id() -> AccountId {
+        "p-0.testnet".parse().unwrap()
+    }
+
+    fn abort(id: u64) -> MpcMessage {
+        MpcMessage::Abort(AbortMessage {
+            id,
+            keyspace: KeyspaceId::root(),
+            epoch: 0,
+            from: Participant::from(1u32),
+            reason: String::new(),
+            timestamp: 0,
+        })
+    }
+
+    fn workers() -> Workers {
+        let account_id = account_id();
+        Workers::new(
+            &account_id,
+            Arc::new(RwLock::new(Reputation::new(&account_id))),
+        )
+    }
+
+    /// Takes the worker of the given kind out of the workers yet to be spawned.
+    fn take_worker(workers: &mut Workers, kind: PokeKind) -> Worker {
+        let position = workers.pending.iter().position(|w| w.kind == kind).unwrap();
+        workers.pending.remove(position)
+    }
+
+    async fn tick() -> Arc<Tick> {
+        let account_id = account_id();
+        let me = Participant::from(0u32);
+        let public_key = k256::AffinePoint::GENERATOR;
+        let triple_manager = Arc::new(RwLock::new(TripleManager::new(
+            me,
+            1,
+            0,
+            Vec::new(),
+            Arc::new(RwLock::new(triple_storage::init(None, &account_id))),
+            &account_id,
+        )));
+        let running = RunningState {
+            epoch: 0,
+            participants: Participants::default(),
+            threshold: 1,
+            private_share: Zeroizing::new(k256::Scalar::ONE),
+            public_key,
+            sign_queue: Arc::new(RwLock::new(SignQueue::new())),
+            stuck_monitor: Arc::new(RwLock::new(StuckMonitor::new(&triple_manager).await)),
+            triple_manager,
+            presignature_manager: Arc::new(RwLock::new(PresignatureManager::new(
+                me,
+                1,
+                0,
+                &public_key,
+                Vec::new(),
+                Arc::new(RwLock::new(presignature_storage::init(None, &account_id))),
+                &account_id,
+            ))),
+            signature_manager: Arc::new(RwLock::new(SignatureManager::new(
+                me,
+                public_key,
+                0,
+                &account_id,
+            ))),
+            refresher: Arc::new(RwLock::new(Refresher::new(
+                me,
+                0,
+                &Participants::default(),
+                1,
+                public_key,
+                &account_id,
+            ))),
+            registry: ParticipantRegistry::shared(),
+            messages: Arc::new(RwLock::new(MessageQueue::default())),
+        };
+        let cfg = Config::default();
+        Arc::new(Tick {
+            running,
+            me,
+            my_account_id: account_id,
+            budget: PokeTick::new(&cfg.local.scheduler, 0),
+            cfg,
+            mesh: MeshState::default(),
+            active: Participants::default(),
+            stockpile: Stockpile {
+                draining: false,
+                usage: Usage::sample(0),
+                shedding: false,
+                to_evict: 0,
+                lacks_liveness: false,
+                required_live: 1,
+            },
+        })
+    }
+
+    fn id(message: &MpcMessage) -> u64 {
+        match message {
+            MpcMessage::Abort(abort) => abort.id,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_inbox_backpressure() {
+        let mut workers = workers();
+        let mut worker = take_worker(&mut workers, PokeKind::Presignature);
+
+        // Once the inbox is full, the messages are held in order, without holding up the
+        // messages of the other workers.
+        let messages = (0..INBOX_CAPACITY as u64 + 2).map(|id| (PokeKind::Presignature, abort(id)));
+        assert_eq!(workers.deliver(messages), 2);
+        assert_eq!(workers.deliver([(PokeKind::Triple, abort(0))]), 2);
+        let mut received = Vec::new();
+        while let Ok(message) = worker.inbox.try_recv() {
+            received.push(id(&message));
+        }
+        assert_eq!(received, (0..INBOX_CAPACITY as u64).collect::<Vec<_>>());
+
+        // The held messages are handed over ahead of the newer ones once the worker caught up.
+        let next = INBOX_CAPACITY as u64 + 2;
+        assert_eq!(workers.deliver([(PokeKind::Presignature, abort(next))]), 0);
+        let mut received = Vec::new();
+        while let Ok(message) = worker.inbox.try_recv() {
+            received.push(id(&message));
+        }
+        assert_eq!(received, vec![next - 2, next - 1, next]);
+
+        // Past the bound, the oldest held messages are dropped.
+        let total = INBOX_CAPACITY + MAX_HELD_MESSAGES + 5;
+        let messages = (0..total as u64).map(|id| (PokeKind::Presignature, abort(id)));
+        assert_eq!(workers.deliver(messages), MAX_HELD_MESSAGES);
+        let (_, oldest) = workers.held.front().unwrap();
+        assert_eq!(id(oldest), (INBOX_CAPACITY + 5) as u64);
+    }
+
+    #[tokio::test]
+    async fn test_dead_worker_is_respawned() {
+        let mut workers = workers();
+        drop(take_worker(&mut workers, PokeKind::Signature));
+
+        // The messages of a worker that stopped are not held forever, they go to its replacement.
+        assert_eq!(workers.deliver([(PokeKind::Signature, abort(1))]), 0);
+        let inbox = &workers.handle(PokeKind::Signature).inbox;
+        assert!(!inbox.is_closed());
+        assert_eq!(inbox.max_capacity() - inbox.capacity(), 1);
+
+        // So do the commands sent to it.
+        drop(take_worker(&mut workers, PokeKind::Triple));
+        workers.send_crank(tick().await);
+        assert_eq!(workers.handle(PokeKind::Triple).skipped_cranks, 0);
+        assert!(!workers.handle(PokeKind::Triple).commands.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_cranks_reach_every_worker() {
+        let mut workers = workers();
+        let tick = tick().await;
+        workers.send_crank(tick.clone());
+        for worker in &mut workers.pending {
+            assert!(matches!(worker.commands.try_recv(), Ok(Command::Crank(_))));
+        }
+
+        // A worker busy with its previous crank skips the next ones.
+        workers.send_crank(tick.clone());
+        workers.send_crank(tick.clone());
+        for kind in [
+            PokeKind::Triple,
+            PokeKind::Presignature,
+            PokeKind::Signature,
+        ] {
+            assert_eq!(workers.handle(kind).skipped_cranks, 1);
+        }
+        for worker in &mut workers.pending {
+            assert!(matches!(worker.commands.try_recv(), Ok(Command::Crank(_))));
+            assert!(worker.commands.try_recv().is_err());
+        }
+        workers.send_crank(tick);
+        assert_eq!(workers.handle(PokeKind::Signature).skipped_cranks, 0);
+    }
+}
@@ -0,0 +1,112 @@
+//! Redaction of sensitive values in the logs. Secrets, such as the key share and the shares of the
+//! triples and presignatures, as well as the data of the protocol messages carrying them, are never
+//! written out, whatever the log level. The payloads and derivation paths of the users are hidden
+//! as well, unless the node runs with `--log-user-data fingerprint`, in which case they are
+//! replaced by a short hash, enough to correlate the logs of the same request across nodes.
+//!
+//! The types holding such values implement `Debug` through the wrappers of this module instead of
+//! deriving it, and values logged directly go through them too.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use k256::Scalar;
+use sha2::{Digest, Sha256};
+
+static FINGERPRINT_USER_DATA: AtomicBool = AtomicBool::new(false);
+
+/// How the payloads and derivation paths of the users show up in the logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum UserDataMode {
+    /// Not at all.
+    #[default]
+    Hidden,
+    /// As the first bytes of their hash.
+    Fingerprint,
+}
+
+#[derive(Debug, Clone, Default, clap::Parser)]
+#[group(id = "redact_options")]
+pub struct Options {
+    /// How the payloads and derivation paths of sign requests show up in the logs. Secrets are
+    /// never logged.
+    #[arg(long, env("MPC_LOG_USER_DATA"), value_enum, default_value_t = UserDataMode::Hidden)]
+    pub log_user_data: UserDataMode,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mode = clap::ValueEnum::to_possible_value(&self.log_user_data).unwrap();
+        vec!["--log-user-data".to_string(), mode.get_name().to_string()]
+    }
+}
+
+pub fn init(options: &Options) {
+    FINGERPRINT_USER_DATA.store(
+        options.log_user_data == UserDataMode::Fingerprint,
+        Ordering::Relaxed,
+    );
+}
+
+/// A secret, never shown.
+pub struct Secret<'a, T: ?Sized>(pub &'a T);
+
+impl<T: ?Sized> fmt::Debug for Secret<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl<T: ?Sized> fmt::Display for Secret<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Data of a user, shown as a fingerprint if enabled with `--log-user-data`.
+pub struct UserData<'a>(&'a [u8]);
+
+impl<'a> UserData<'a> {
+    pub fn str(data: &'a str) -> Self {
+        Self(data.as_bytes())
+    }
+
+    pub fn bytes(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+}
+
+/// A payload or derivation of a user, shown as a fingerprint if enabled with `--log-user-data`.
+pub struct UserScalar<'a>(pub &'a Scalar);
+
+impl fmt::Debug for UserData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_user_data(self.0, f)
+    }
+}
+
+impl fmt::Display for UserData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_user_data(self.0, f)
+    }
+}
+
+impl fmt::Debug for UserScalar<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_user_data(&self.0.to_bytes(), f)
+    }
+}
+
+impl fmt::Display for UserScalar<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_user_data(&self.0.to_bytes(), f)
+    }
+}
+
+fn fmt_user_data(data: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if !FINGERPRINT_USER_DATA.load(Ordering::Relaxed) {
+        return f.write_str("<redacted>");
+    }
+    let digest = Sha256::digest(data);
+    write!(f, "<{}>", hex::encode(&digest[..4]))
+}
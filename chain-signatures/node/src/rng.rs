@@ -0,0 +1,79 @@
+//! Source of the randomness the node draws its protocol ids from. Production nodes always use the
+//! OS rng. With the `test-utils` feature, a seed can be installed for the whole process, from which
+//! every node derives its own rng, so that tests produce the same ids on every run.
+
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+
+/// A cryptographically secure rng that can be shared between tasks.
+pub trait NodeRng: RngCore + CryptoRng + Send + Sync {}
+
+impl<T: RngCore + CryptoRng + Send + Sync> NodeRng for T {}
+
+pub type BoxedRng = Box<dyn NodeRng>;
+
+/// The rng of the node with the given account, seeded from the installed seed if any.
+#[cfg_attr(not(any(test, feature = "test-utils")), allow(unused_variables))]
+pub fn node(account_id: &near_account_id::AccountId) -> BoxedRng {
+    #[cfg(any(test, feature = "test-utils"))]
+    if let Some(seed) = deterministic::SEED.get() {
+        return deterministic::seeded_for(*seed, account_id.as_str().as_bytes());
+    }
+    os()
+}
+
+/// The rng of the operating system.
+pub fn os() -> BoxedRng {
+    Box::new(OsRng)
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+pub use deterministic::{install_seed, seeded, seeded_for};
+
+#[cfg(any(test, feature = "test-utils"))]
+mod deterministic {
+    use once_cell::sync::OnceCell;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use sha3::{Digest, Sha3_256};
+
+    use super::BoxedRng;
+
+    pub(super) static SEED: OnceCell<u64> = OnceCell::new();
+
+    /// Makes every node rng created from now on deterministic, derived from `seed`. Only one seed
+    /// can be installed for the lifetime of the process.
+    pub fn install_seed(seed: u64) {
+        if SEED.set(seed).is_err() {
+            tracing::warn!("rng seed is already installed");
+        }
+    }
+
+    /// A deterministic rng producing the same values for the same seed.
+    pub fn seeded(seed: u64) -> BoxedRng {
+        Box::new(StdRng::seed_from_u64(seed))
+    }
+
+    /// A deterministic rng for the node identified by `id`, such that nodes sharing a seed still
+    /// draw different values.
+    pub fn seeded_for(seed: u64, id: &[u8]) -> BoxedRng {
+        let mut hasher = Sha3_256::new();
+        hasher.update(seed.to_le_bytes());
+        hasher.update(id);
+        Box::new(StdRng::from_seed(hasher.finalize().into()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_seeded_is_reproducible() {
+            let draw = |mut rng: BoxedRng| (0..4).map(|_| rng.next_u64()).collect::<Vec<_>>();
+            assert_eq!(draw(seeded(7)), draw(seeded(7)));
+            assert_ne!(draw(seeded(7)), draw(seeded(8)));
+            assert_eq!(draw(seeded_for(7, b"a")), draw(seeded_for(7, b"a")));
+            assert_ne!(draw(seeded_for(7, b"a")), draw(seeded_for(7, b"b")));
+        }
+    }
+}
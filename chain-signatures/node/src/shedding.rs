@@ -0,0 +1,177 @@
+//! Load shedding under memory pressure. Every running tick, the resident memory of the process and
+//! the size of the pools of triples and presignatures, including the ones being generated, are
+//! compared against the ceilings set in the [`scheduler::Options`]. While over a ceiling, the node
+//! sheds load:
+//! - it no longer joins the triples and presignatures proposed by others,
+//! - it no longer stockpiles triples and presignatures of its own,
+//! - it evicts its oldest unspent triples until the pool is back under the ceiling.
+//!
+//! Signing is not affected. Shedding stops once the usage is back under a margin below the
+//! ceilings, so that the node does not flap in and out of it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use near_account_id::AccountId;
+
+use crate::events::{self, NodeEvent};
+use crate::protocol::scheduler;
+
+static SHEDDING: AtomicBool = AtomicBool::new(false);
+
+/// Share of a ceiling the usage has to get back under for shedding to stop.
+const RECOVERY_RATIO: f64 = 0.9;
+
+/// Number of triples evicted per tick while the resident memory is over its ceiling.
+const RSS_EVICTION_BATCH: usize = 16;
+
+/// Whether the node is shedding load, in which case no new triple or presignature is to be
+/// started or joined.
+pub fn is_shedding() -> bool {
+    SHEDDING.load(Ordering::Relaxed)
+}
+
+/// Resources used by the node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Resident memory of the process. Unknown on platforms without procfs.
+    pub rss_bytes: Option<u64>,
+    /// Triples and presignatures held or being generated.
+    pub pool_size: usize,
+}
+
+impl Usage {
+    pub fn sample(pool_size: usize) -> Self {
+        Self {
+            rss_bytes: rss_bytes(),
+            pool_size,
+        }
+    }
+}
+
+/// Reads the resident memory of the process from procfs.
+pub fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+/// Ceilings above which the node sheds load. Nothing is shed without any.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Ceiling {
+    pub max_rss_bytes: Option<u64>,
+    pub max_pool_size: Option<usize>,
+}
+
+impl Ceiling {
+    pub fn new(opts: &scheduler::Options) -> Self {
+        Self {
+            max_rss_bytes: opts.shed_max_rss_mb.map(|mb| mb * 1024 * 1024),
+            max_pool_size: opts.shed_max_pool_size,
+        }
+    }
+
+    fn exceeded(&self, usage: &Usage, ratio: f64) -> bool {
+        let rss = match (usage.rss_bytes, self.max_rss_bytes) {
+            (Some(rss), Some(max)) => rss as f64 > max as f64 * ratio,
+            _ => false,
+        };
+        let pool = self
+            .max_pool_size
+            .is_some_and(|max| usage.pool_size as f64 > max as f64 * ratio);
+        rss || pool
+    }
+
+    /// Whether the node sheds load with the given usage, given whether it was shedding before.
+    pub fn sheds(&self, usage: &Usage, shedding: bool) -> bool {
+        if shedding {
+            self.exceeded(usage, RECOVERY_RATIO)
+        } else {
+            self.exceeded(usage, 1.0)
+        }
+    }
+
+    /// Number of triples to evict to get back under the ceilings.
+    pub fn to_evict(&self, usage: &Usage) -> usize {
+        let pool = self
+            .max_pool_size
+            .map_or(0, |max| usage.pool_size.saturating_sub(max));
+        let rss = match (usage.rss_bytes, self.max_rss_bytes) {
+            (Some(rss), Some(max)) if rss > max => RSS_EVICTION_BATCH,
+            _ => 0,
+        };
+        pool.max(rss)
+    }
+}
+
+/// Starts or stops shedding load given the current usage, letting operators know through the
+/// node events. Returns whether the node sheds load.
+pub fn update(usage: &Usage, ceiling: &Ceiling, my_account_id: &AccountId) -> bool {
+    let was_shedding = is_shedding();
+    let shedding = ceiling.sheds(usage, was_shedding);
+    if shedding != was_shedding {
+        SHEDDING.store(shedding, Ordering::Relaxed);
+        if shedding {
+            tracing::warn!(?usage, ?ceiling, "resources over ceiling, shedding load");
+            events::emit(NodeEvent::SheddingStarted {
+                rss_bytes: usage.rss_bytes,
+                pool_size: usage.pool_size,
+            });
+        } else {
+            tracing::info!(
+                ?usage,
+                ?ceiling,
+                "resources back under ceiling, stopped shedding load"
+            );
+            events::emit(NodeEvent::SheddingStopped {
+                rss_bytes: usage.rss_bytes,
+                pool_size: usage.pool_size,
+            });
+        }
+    }
+    if let Some(rss_bytes) = usage.rss_bytes {
+        crate::metrics::PROCESS_RSS_BYTES
+            .with_label_values(&[my_account_id.as_str()])
+            .set(rss_bytes as i64);
+    }
+    crate::metrics::LOAD_SHEDDING_ACTIVE
+        .with_label_values(&[my_account_id.as_str()])
+        .set(shedding as i64);
+    shedding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ceiling() {
+        let ceiling = Ceiling {
+            max_rss_bytes: Some(1000),
+            max_pool_size: Some(100),
+        };
+        let usage = |rss_bytes, pool_size| Usage {
+            rss_bytes: Some(rss_bytes),
+            pool_size,
+        };
+        assert!(!ceiling.sheds(&usage(1000, 100), false));
+        assert!(ceiling.sheds(&usage(1001, 100), false));
+        assert!(ceiling.sheds(&usage(500, 101), false));
+        // Shedding only stops once back under the recovery margin.
+        assert!(ceiling.sheds(&usage(950, 50), true));
+        assert!(ceiling.sheds(&usage(500, 95), true));
+        assert!(!ceiling.sheds(&usage(900, 90), true));
+
+        assert_eq!(ceiling.to_evict(&usage(500, 130)), 30);
+        assert_eq!(ceiling.to_evict(&usage(1001, 90)), RSS_EVICTION_BATCH);
+        assert_eq!(ceiling.to_evict(&usage(950, 90)), 0);
+
+        // Nothing is shed without ceilings, nor for an unknown resident memory.
+        assert!(!Ceiling::default().sheds(&usage(u64::MAX, usize::MAX), false));
+        let unknown = Usage {
+            rss_bytes: None,
+            pool_size: 0,
+        };
+        assert!(!ceiling.sheds(&unknown, false));
+    }
+}
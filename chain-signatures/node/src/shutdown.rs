@@ -0,0 +1,83 @@
+//! Graceful shutdown. On SIGTERM the node stops starting new protocols, but keeps running the
+//! signature protocols already in flight for a grace period, so that its peers do not have to wait
+//! for them to time out. Once they are done and the outbox and the signatures to publish are
+//! flushed, or once the grace period is over, the snapshot of the node is written and it exits.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mpc_keys::hpke;
+use near_account_id::AccountId;
+use once_cell::sync::Lazy;
+use tokio::sync::{Notify, RwLock};
+
+use crate::protocol::{NodeState, SignQueue};
+use crate::snapshot;
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+static DRAINED: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Where and how to write the snapshot of the node once drained.
+pub struct SnapshotTask {
+    pub path: PathBuf,
+    pub cipher_pk: hpke::PublicKey,
+    pub account_id: AccountId,
+    pub sign_queue: Arc<RwLock<SignQueue>>,
+}
+
+/// Whether the node is shutting down, in which case no new protocol is to be started.
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// Reports that nothing is in flight anymore, such that the node can exit before the end of the
+/// grace period.
+pub fn drained() {
+    if is_draining() {
+        DRAINED.notify_one();
+    }
+}
+
+/// Waits for SIGTERM, then drains the node for at most `grace` before writing its snapshot and
+/// exiting.
+pub async fn on_terminate(
+    grace: Duration,
+    state: Arc<RwLock<NodeState>>,
+    snapshot: Option<SnapshotTask>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::terminate())?.recv().await;
+    tracing::info!(
+        ?grace,
+        "received SIGTERM, draining in-flight signature protocols"
+    );
+    DRAINING.store(true, Ordering::Relaxed);
+    if tokio::time::timeout(grace, DRAINED.notified())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            ?grace,
+            "grace period is over with protocols still in flight"
+        );
+    } else {
+        tracing::info!("drained in-flight signature protocols");
+    }
+
+    if let Some(task) = snapshot {
+        snapshot::write_now(
+            &task.path,
+            task.cipher_pk,
+            task.account_id,
+            state,
+            task.sign_queue,
+        )
+        .await;
+    }
+    // Flushing blocks until the exporter is done, which needs the runtime to make progress.
+    let _ = tokio::task::spawn_blocking(crate::telemetry::flush).await;
+    std::process::exit(0);
+}
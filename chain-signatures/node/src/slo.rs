@@ -0,0 +1,325 @@
+//! Tracking of the latency of signatures against a service level objective. The latency of a
+//! request runs from its indexing to the publication of its response on chain. The percentiles
+//! are computed over the requests answered within a rolling window, and once the p95 goes over
+//! the objective the alert hooks fire, and again once it is back under it. This lets operators
+//! notice a degradation before the users of the contract do.
+//!
+//! The objective is set with `--sign-latency-slo-ms`. The alerts are always logged, exported as
+//! metrics and emitted as [`NodeEvent`]s, and posted to `--slo-alert-webhook` if set.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::events::{self, NodeEvent};
+
+/// Number of latencies within the window before alerts may fire, so that a handful of slow
+/// requests on a quiet node does not page anyone.
+const MIN_SAMPLES: usize = 20;
+
+/// Upper bound of the latencies kept within the window.
+const MAX_SAMPLES: usize = 10_000;
+
+/// Account id of this node, labelling the metrics, and its tracker.
+static TRACKER: OnceCell<(String, Mutex<Tracker>)> = OnceCell::new();
+
+#[derive(Debug, Clone, Default, clap::Parser)]
+#[group(id = "slo_options")]
+pub struct Options {
+    /// Objective for the p95 latency of signatures, from the indexing of a request to the
+    /// publication of its response, in milliseconds. Latencies are not tracked if not set.
+    #[arg(long, env("MPC_SIGN_LATENCY_SLO_MS"))]
+    pub sign_latency_slo_ms: Option<u64>,
+    /// Seconds of latencies the percentiles are computed over.
+    #[arg(long, env("MPC_SLO_WINDOW_SECS"), default_value("600"))]
+    pub slo_window_secs: u64,
+    /// URL the alerts are posted to as JSON, in addition to being logged.
+    #[arg(long, env("MPC_SLO_ALERT_WEBHOOK"))]
+    pub slo_alert_webhook: Option<String>,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut opts = vec![
+            "--slo-window-secs".to_string(),
+            self.slo_window_secs.to_string(),
+        ];
+        if let Some(slo) = self.sign_latency_slo_ms {
+            opts.extend(["--sign-latency-slo-ms".to_string(), slo.to_string()]);
+        }
+        if let Some(webhook) = self.slo_alert_webhook {
+            opts.extend(["--slo-alert-webhook".to_string(), webhook]);
+        }
+        opts
+    }
+}
+
+/// Change of the latency with respect to the objective.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    Breached {
+        p95_ms: u64,
+        slo_ms: u64,
+        samples: usize,
+    },
+    Recovered {
+        p95_ms: u64,
+        slo_ms: u64,
+        samples: usize,
+    },
+}
+
+/// Somewhere the alerts go.
+pub trait AlertHook: Send + Sync {
+    fn fire(&self, alert: &Alert);
+}
+
+/// Logs the alerts.
+pub struct LogHook;
+
+impl AlertHook for LogHook {
+    fn fire(&self, alert: &Alert) {
+        match alert {
+            Alert::Breached { .. } => {
+                tracing::warn!(?alert, "signature latency is over its objective")
+            }
+            Alert::Recovered { .. } => {
+                tracing::info!(?alert, "signature latency is back under its objective")
+            }
+        }
+    }
+}
+
+/// Exports the alerts as metrics and node events.
+pub struct MetricHook {
+    pub my_account_id: String,
+}
+
+impl AlertHook for MetricHook {
+    fn fire(&self, alert: &Alert) {
+        let (breached, event) = match *alert {
+            Alert::Breached { p95_ms, slo_ms, .. } => {
+                (true, NodeEvent::LatencySloBreached { p95_ms, slo_ms })
+            }
+            Alert::Recovered { p95_ms, slo_ms, .. } => {
+                (false, NodeEvent::LatencySloRecovered { p95_ms, slo_ms })
+            }
+        };
+        crate::metrics::SIGN_LATENCY_SLO_BREACHED
+            .with_label_values(&[self.my_account_id.as_str()])
+            .set(breached as i64);
+        if breached {
+            crate::metrics::NUM_SIGN_LATENCY_SLO_ALERTS
+                .with_label_values(&[self.my_account_id.as_str()])
+                .inc();
+        }
+        events::emit(event);
+    }
+}
+
+/// Posts the alerts as JSON to a URL. Posting happens in the background on the tokio runtime and
+/// failures are only logged.
+pub struct WebhookHook {
+    pub url: String,
+    pub client: reqwest::Client,
+}
+
+impl AlertHook for WebhookHook {
+    fn fire(&self, alert: &Alert) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(url = %self.url, "no runtime to post the latency alert on");
+            return;
+        };
+        let request = self.client.post(&self.url).json(alert);
+        let url = self.url.clone();
+        runtime.spawn(async move {
+            match request
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+            {
+                Ok(_) => tracing::debug!(%url, "posted latency alert"),
+                Err(err) => tracing::warn!(%url, ?err, "failed to post latency alert"),
+            }
+        });
+    }
+}
+
+/// Rolling window of the latencies of signatures, firing the hooks when its p95 crosses the
+/// objective.
+pub struct Tracker {
+    slo: Duration,
+    window: Duration,
+    latencies: VecDeque<(Instant, Duration)>,
+    breached: bool,
+    hooks: Vec<Box<dyn AlertHook>>,
+}
+
+impl Tracker {
+    pub fn new(slo: Duration, window: Duration, hooks: Vec<Box<dyn AlertHook>>) -> Self {
+        Self {
+            slo,
+            window,
+            latencies: VecDeque::new(),
+            breached: false,
+            hooks,
+        }
+    }
+
+    /// Records the latency of a signature published at `now`, and fires the hooks if the p95
+    /// crossed the objective.
+    pub fn record(&mut self, now: Instant, latency: Duration) -> Option<Alert> {
+        self.latencies.push_back((now, latency));
+        while self.latencies.len() > MAX_SAMPLES
+            || self
+                .latencies
+                .front()
+                .is_some_and(|(at, _)| now.saturating_duration_since(*at) > self.window)
+        {
+            self.latencies.pop_front();
+        }
+
+        let samples = self.latencies.len();
+        if samples < MIN_SAMPLES {
+            return None;
+        }
+        let p95 = self.percentile(0.95)?;
+        let (p95_ms, slo_ms) = (p95.as_millis() as u64, self.slo.as_millis() as u64);
+        let alert = match (self.breached, p95 > self.slo) {
+            (false, true) => Alert::Breached {
+                p95_ms,
+                slo_ms,
+                samples,
+            },
+            (true, false) => Alert::Recovered {
+                p95_ms,
+                slo_ms,
+                samples,
+            },
+            _ => return None,
+        };
+        self.breached = !self.breached;
+        for hook in &self.hooks {
+            hook.fire(&alert);
+        }
+        Some(alert)
+    }
+
+    /// Latency under which the given fraction of the latencies within the window fall.
+    pub fn percentile(&self, q: f64) -> Option<Duration> {
+        let mut latencies: Vec<_> = self.latencies.iter().map(|(_, latency)| *latency).collect();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        let rank = ((latencies.len() as f64 * q).ceil() as usize).clamp(1, latencies.len());
+        Some(latencies[rank - 1])
+    }
+}
+
+/// Starts tracking the latency of signatures if an objective is set.
+pub fn init(options: &Options, my_account_id: &str, client: reqwest::Client) {
+    let Some(slo_ms) = options.sign_latency_slo_ms else {
+        return;
+    };
+    let mut hooks: Vec<Box<dyn AlertHook>> = vec![
+        Box::new(LogHook),
+        Box::new(MetricHook {
+            my_account_id: my_account_id.to_string(),
+        }),
+    ];
+    if let Some(url) = &options.slo_alert_webhook {
+        hooks.push(Box::new(WebhookHook {
+            url: url.clone(),
+            client,
+        }));
+    }
+    let tracker = Tracker::new(
+        Duration::from_millis(slo_ms),
+        Duration::from_secs(options.slo_window_secs),
+        hooks,
+    );
+    if TRACKER
+        .set((my_account_id.to_string(), Mutex::new(tracker)))
+        .is_err()
+    {
+        tracing::warn!("signature latency is already tracked");
+        return;
+    }
+    tracing::info!(slo_ms, "tracking signature latency");
+}
+
+/// Records the latency of a signature that just got published.
+pub fn record(latency: Duration) {
+    let Some((my_account_id, tracker)) = TRACKER.get() else {
+        return;
+    };
+    let mut tracker = tracker
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    tracker.record(Instant::now(), latency);
+    if let Some(p95) = tracker.percentile(0.95) {
+        crate::metrics::SIGN_LATENCY_P95
+            .with_label_values(&[my_account_id.as_str()])
+            .set(p95.as_millis() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct Recorder(Arc<Mutex<Vec<Alert>>>);
+
+    impl AlertHook for Recorder {
+        fn fire(&self, alert: &Alert) {
+            self.0.lock().unwrap().push(alert.clone());
+        }
+    }
+
+    #[test]
+    fn test_alerts_on_p95_crossing_the_slo() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut tracker = Tracker::new(
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+            vec![Box::new(Recorder(fired.clone()))],
+        );
+        let start = Instant::now();
+        for i in 0..MIN_SAMPLES as u64 {
+            let alert = tracker.record(start + Duration::from_secs(i), Duration::from_secs(1));
+            assert_eq!(alert, None);
+        }
+        assert_eq!(tracker.percentile(0.95), Some(Duration::from_secs(1)));
+
+        // A few slow requests push the p95 over the objective, and only alert once.
+        let mut now = start + Duration::from_secs(MIN_SAMPLES as u64);
+        for _ in 0..3 {
+            tracker.record(now, Duration::from_secs(30));
+            now += Duration::from_secs(1);
+        }
+        assert!(matches!(
+            fired.lock().unwrap().as_slice(),
+            [Alert::Breached { slo_ms: 10_000, .. }]
+        ));
+
+        // Once the slow requests fall out of the window, the latency recovers.
+        let later = now + Duration::from_secs(61);
+        for i in 0..MIN_SAMPLES as u64 {
+            tracker.record(later + Duration::from_secs(i), Duration::from_secs(1));
+        }
+        assert!(matches!(
+            fired.lock().unwrap().as_slice(),
+            [
+                Alert::Breached { .. },
+                Alert::Recovered { p95_ms: 1_000, .. }
+            ]
+        ));
+    }
+}
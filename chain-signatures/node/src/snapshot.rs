@@ -0,0 +1,339 @@
+//! Snapshot of the state a node can carry over a restart. The cait-sith protocols of in-flight
+//! generators cannot be serialized, but the pools of completed triples and presignatures, the
+//! messages waiting to be delivered and the queued sign requests can. The snapshot is written on
+//! SIGTERM once the node is drained, see [`crate::shutdown`], and restored on startup, so that a rolling upgrade loses at most the in-flight
+//! generators and not the whole stockpile and the queued requests.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use k256::Scalar;
+use mpc_keys::hpke::{self, Ciphered};
+use near_account_id::AccountId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use zeroize::Zeroizing;
+
+use crate::indexer::ContractSignRequest;
+use crate::protocol::contract::primitives::ParticipantInfo;
+use crate::protocol::presignature::Presignature;
+use crate::protocol::signature::{ReceiptId, SignPriority};
+use crate::protocol::triple::Triple;
+use crate::protocol::{MpcMessage, NodeState, SignQueue, SignRequest};
+use crate::storage::presignature_storage::LockPresignatureNodeStorageBox;
+use crate::storage::secret_storage::SecretNodeStorageBox;
+use crate::storage::triple_storage::LockTripleNodeStorageBox;
+
+/// Associated data used when encrypting the snapshot written on disk.
+const SNAPSHOT_ASSOCIATED_DATA: &[u8] = b"mpc-node-snapshot";
+
+/// Messages waiting to be delivered, with the participant they are addressed to.
+pub type Outbox = Vec<(ParticipantInfo, MpcMessage)>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("encryption error: {0}")]
+    Encryption(String),
+    #[error("snapshot was taken by {0}")]
+    AccountMismatch(AccountId),
+}
+
+/// An item of a pool, with whether it was introduced by this node.
+#[derive(Serialize, Deserialize)]
+pub struct Pooled<T> {
+    pub item: T,
+    pub mine: bool,
+}
+
+/// A sign request waiting for a presignature. Instants do not survive a restart, so the age and
+/// the time left before the deadline are recorded instead.
+#[derive(Serialize, Deserialize)]
+pub struct QueuedSignRequest {
+    pub receipt_id: ReceiptId,
+    pub request: ContractSignRequest,
+    pub epsilon: Scalar,
+    pub entropy: [u8; 32],
+    pub priority: SignPriority,
+    pub age: Duration,
+    pub remaining: Duration,
+    #[serde(default)]
+    pub merged: Vec<ReceiptId>,
+}
+
+impl QueuedSignRequest {
+    fn new(request: &SignRequest, now: Instant) -> Self {
+        Self {
+            receipt_id: request.receipt_id,
+            request: request.request.clone(),
+            epsilon: request.epsilon,
+            entropy: request.entropy,
+            priority: request.priority,
+            age: now.saturating_duration_since(request.time_added),
+            remaining: request.deadline.saturating_duration_since(now),
+            merged: request.merged.clone(),
+        }
+    }
+
+    /// The sign request to queue again, unless it expired in the meantime.
+    fn into_request(self, downtime: Duration, now: Instant) -> Option<SignRequest> {
+        let remaining = self.remaining.checked_sub(downtime)?;
+        Some(SignRequest {
+            receipt_id: self.receipt_id,
+            request: self.request,
+            epsilon: self.epsilon,
+            entropy: self.entropy,
+            time_added: now.checked_sub(self.age + downtime).unwrap_or(now),
+            priority: self.priority,
+            deadline: now + remaining,
+            merged: self.merged,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub account_id: AccountId,
+    /// Epoch of the pools and the outbox. Not set if the node was not running the protocol.
+    pub epoch: Option<u64>,
+    /// UNIX timestamp in milliseconds of when the snapshot was taken.
+    pub taken_at: i64,
+    pub triples: Vec<Pooled<Triple>>,
+    pub presignatures: Vec<Pooled<Presignature>>,
+    pub outbox: Outbox,
+    pub sign_requests: Vec<QueuedSignRequest>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the node. Triples and presignatures that are reserved or consumed by
+    /// an in-flight generator are left out, as they may already be spent by the other nodes.
+    pub async fn take(account_id: &AccountId, state: &NodeState, sign_queue: &SignQueue) -> Self {
+        let now = Instant::now();
+        let mut snapshot = Snapshot {
+            account_id: account_id.clone(),
+            epoch: None,
+            taken_at: Utc::now().timestamp_millis(),
+            triples: Vec::new(),
+            presignatures: Vec::new(),
+            outbox: Vec::new(),
+            sign_requests: sign_queue
+                .requests()
+                .map(|request| QueuedSignRequest::new(request, now))
+                .collect(),
+        };
+        if let NodeState::Running(running) = state {
+            snapshot.epoch = Some(running.epoch);
+            let triple_manager = running.triple_manager.read().await;
+            snapshot.triples = triple_manager
+                .triples
+                .values()
+                .map(|triple| Pooled {
+                    item: triple.clone(),
+                    mine: triple_manager.mine.contains(&triple.id),
+                })
+                .collect();
+            drop(triple_manager);
+            snapshot.presignatures = running
+                .presignature_manager
+                .read()
+                .await
+                .unspent()
+                .map(|(presignature, mine)| Pooled {
+                    item: presignature.clone(),
+                    mine,
+                })
+                .collect();
+            snapshot.outbox = running
+                .messages
+                .read()
+                .await
+                .outgoing()
+                .map(|(info, msg)| (info.clone(), msg.clone()))
+                .collect();
+        }
+        snapshot
+    }
+
+    /// Writes the snapshot to `path`, encrypted to the node's own cipher key.
+    pub fn write(&self, path: &Path, cipher_pk: &hpke::PublicKey) -> Result<(), SnapshotError> {
+        let ciphered = cipher_pk
+            .encrypt(
+                &Zeroizing::new(serde_json::to_vec(self)?),
+                SNAPSHOT_ASSOCIATED_DATA,
+            )
+            .map_err(|err| SnapshotError::Encryption(err.to_string()))?;
+
+        // Write to a temporary file first and move it over the existing one, such that a node
+        // killed midway through never leaves a truncated snapshot behind.
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&ciphered)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the snapshot at `path` and removes it, so that it is never restored twice: the
+    /// pools are spent from after the restore and restoring them again would reuse spent items.
+    pub fn read(
+        path: &Path,
+        cipher_sk: &hpke::SecretKey,
+        account_id: &AccountId,
+    ) -> Result<Option<Self>, SnapshotError> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => Zeroizing::new(contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        std::fs::remove_file(path)?;
+        let ciphered: Ciphered = serde_json::from_slice(&contents)?;
+        let contents = cipher_sk
+            .decrypt(&ciphered, SNAPSHOT_ASSOCIATED_DATA)
+            .map(Zeroizing::new)
+            .map_err(|err| SnapshotError::Encryption(err.to_string()))?;
+        let snapshot: Snapshot = serde_json::from_slice(&contents)?;
+        if &snapshot.account_id != account_id {
+            return Err(SnapshotError::AccountMismatch(snapshot.account_id));
+        }
+        Ok(Some(snapshot))
+    }
+
+    /// Restores the pools into storage, from where they are loaded when the node starts, and
+    /// queues the sign requests that have not expired in the meantime. The pools are only
+    /// restored if they belong to the epoch of the stored key share. Returns the epoch and the
+    /// outbox, which can only be delivered once the node runs the protocol again.
+    pub async fn restore(
+        self,
+        secret_storage: &SecretNodeStorageBox,
+        triple_storage: &LockTripleNodeStorageBox,
+        presignature_storage: &LockPresignatureNodeStorageBox,
+        sign_queue: &Arc<RwLock<SignQueue>>,
+    ) -> Result<Option<(u64, Outbox)>, SnapshotError> {
+        let downtime =
+            Duration::from_millis((Utc::now().timestamp_millis() - self.taken_at).max(0) as u64);
+        let now = Instant::now();
+        let mut queue = sign_queue.write().await;
+        let mut requests = 0;
+        for request in self.sign_requests {
+            if let Some(request) = request.into_request(downtime, now) {
+                queue.add(request);
+                requests += 1;
+            }
+        }
+        drop(queue);
+
+        let key_epoch = match secret_storage.load().await {
+            Ok(data) => data.map(|data| data.epoch),
+            Err(err) => {
+                tracing::warn!(?err, "snapshot: could not load the key share");
+                None
+            }
+        };
+        let Some(epoch) = self.epoch.filter(|epoch| Some(*epoch) == key_epoch) else {
+            tracing::info!(
+                requests,
+                epoch = self.epoch,
+                ?key_epoch,
+                "restored sign requests from snapshot, pools belong to another epoch"
+            );
+            return Ok(None);
+        };
+
+        let (triples, presignatures) = (self.triples.len(), self.presignatures.len());
+        let mut storage = triple_storage.write().await;
+        for Pooled { item, mine } in self.triples {
+            if let Err(err) = storage.insert(item, epoch, mine).await {
+                tracing::warn!(?err, "snapshot: failed to restore triple");
+            }
+        }
+        drop(storage);
+        let mut storage = presignature_storage.write().await;
+        for Pooled { item, mine } in self.presignatures {
+            if let Err(err) = storage.insert(item, mine).await {
+                tracing::warn!(?err, "snapshot: failed to restore presignature");
+            }
+        }
+        drop(storage);
+
+        tracing::info!(
+            epoch,
+            requests,
+            triples,
+            presignatures,
+            outbox = self.outbox.len(),
+            "restored snapshot"
+        );
+        Ok(Some((epoch, self.outbox)))
+    }
+}
+
+/// Writes a snapshot of the node to `path`, once the node is shutting down.
+pub async fn write_now(
+    path: &Path,
+    cipher_pk: hpke::PublicKey,
+    account_id: AccountId,
+    state: Arc<RwLock<NodeState>>,
+    sign_queue: Arc<RwLock<SignQueue>>,
+) {
+    tracing::info!(?path, "writing snapshot");
+    let snapshot = {
+        let state = state.read().await;
+        let sign_queue = sign_queue.read().await;
+        Snapshot::take(&account_id, &state, &sign_queue).await
+    };
+    match snapshot.write(path, &cipher_pk) {
+        Ok(()) => tracing::info!(
+            epoch = snapshot.epoch,
+            triples = snapshot.triples.len(),
+            presignatures = snapshot.presignatures.len(),
+            outbox = snapshot.outbox.len(),
+            requests = snapshot.sign_requests.len(),
+            "snapshot written"
+        ),
+        Err(err) => tracing::error!(?err, "failed to write snapshot"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use k256::Scalar;
+
+    use super::QueuedSignRequest;
+    use crate::indexer::ContractSignRequest;
+    use crate::protocol::signature::SignPriority;
+    use crate::protocol::SignRequest;
+
+    #[test]
+    fn test_sign_request_downtime() {
+        let now = Instant::now();
+        let request = SignRequest {
+            receipt_id: Default::default(),
+            request: ContractSignRequest {
+                payload: Scalar::ONE,
+                path: "test".to_string(),
+                key_version: 0,
+            },
+            epsilon: Scalar::ONE,
+            entropy: [1; 32],
+            time_added: now,
+            priority: SignPriority::High,
+            deadline: now + Duration::from_secs(100),
+            merged: Vec::new(),
+        };
+        let queued = QueuedSignRequest::new(&request, now);
+        let restored = queued
+            .into_request(Duration::from_secs(30), now)
+            .expect("request has not expired yet");
+        assert_eq!(restored.deadline, now + Duration::from_secs(70));
+        assert_eq!(restored.priority, SignPriority::High);
+
+        let queued = QueuedSignRequest::new(&request, now);
+        assert!(queued.into_request(Duration::from_secs(101), now).is_none());
+    }
+}
@@ -1,6 +1,115 @@
+pub mod presignature_storage;
 pub mod secret_storage;
+pub mod sign_request_storage;
 pub mod triple_storage;
 
+use std::marker::PhantomData;
+
+use google_datastore1::api::{
+    Filter, Key, PathElement, PropertyFilter, PropertyReference, Value as DatastoreValue,
+};
+use near_account_id::AccountId;
+
+use crate::gcp::error::DatastoreStorageError;
+use crate::gcp::value::{FromValue, IntoValue, Value};
+use crate::gcp::{DatastoreResult, DatastoreService, KeyKind, Keyable};
+
+/// Name of the datastore entity of the triple or presignature `id` generated in `epoch`. Ids are
+/// random, so they are namespaced by their epoch to never collide with those of another epoch.
+pub(crate) fn entity_name(account_id: &str, epoch: u64, id: u64) -> String {
+    format!("{account_id}/{epoch}/{id}")
+}
+
+/// Whether the entity is still stored under the name it had before names were namespaced by
+/// epoch, i.e. `{account_id}/{id}`. Account ids never contain a `/`.
+pub(crate) fn is_legacy(entity: &Value) -> bool {
+    let Value::EntityValue { key, .. } = entity else {
+        return false;
+    };
+    key.path
+        .as_ref()
+        .and_then(|path| path.first())
+        .and_then(|element| element.name.as_ref())
+        .is_some_and(|name| name.matches('/').count() == 1)
+}
+
+/// Key of an entity of kind `T` stored under its legacy name, deleted once the entity has been
+/// migrated to the name namespaced by epoch.
+pub(crate) struct LegacyKey<'a, T> {
+    pub account_id: &'a str,
+    pub id: u64,
+    kind: PhantomData<T>,
+}
+
+impl<'a, T> LegacyKey<'a, T> {
+    pub fn new(account_id: &'a str, id: u64) -> Self {
+        Self {
+            account_id,
+            id,
+            kind: PhantomData,
+        }
+    }
+}
+
+impl<T: KeyKind> KeyKind for LegacyKey<'_, T> {
+    fn kind() -> String {
+        T::kind()
+    }
+}
+
+impl<T: KeyKind> Keyable for LegacyKey<'_, T> {
+    fn key(&self) -> Key {
+        Key {
+            path: Some(vec![PathElement {
+                kind: None,
+                name: Some(format!("{}/{}", self.account_id, self.id)),
+                id: None,
+            }]),
+            partition_id: None,
+        }
+    }
+}
+
+/// Entities of kind `T` stored by the node, in both the current and the legacy layout. The
+/// emulator does not support filters, so entities of other nodes are returned along with them
+/// there.
+pub(crate) async fn fetch_entities<T: KeyKind>(
+    datastore: &DatastoreService,
+    account_id: &AccountId,
+) -> DatastoreResult<Vec<Value>> {
+    let filter = if datastore.is_emulator() {
+        None
+    } else {
+        Some(Filter {
+            composite_filter: None,
+            property_filter: Some(PropertyFilter {
+                op: Some("Equal".to_string()),
+                property: Some(PropertyReference {
+                    name: Some("account_id".to_string()),
+                }),
+                value: Some(DatastoreValue::from_value(
+                    account_id.as_str().into_value(),
+                )?),
+            }),
+        })
+    };
+    datastore
+        .fetch_entities::<T>(filter)
+        .await?
+        .into_iter()
+        .map(|entity_result| {
+            entity_result
+                .entity
+                .map(|entity| entity.into_value())
+                .ok_or_else(|| {
+                    DatastoreStorageError::FetchEntitiesError(
+                        "entity was not able to unwrapped".to_string(),
+                    )
+                })
+        })
+        .collect()
+}
+
 /// Configures storage.
 #[derive(Debug, Clone, clap::Parser)]
 #[group(id = "storage_options")]
@@ -20,6 +129,18 @@ pub struct Options {
     pub gcp_datastore_url: Option<String>,
     #[arg(long, env("MPC_SK_SHARE_LOCAL_PATH"))]
     pub sk_share_local_path: Option<String>,
+    /// AWS Secrets Manager secret that will be used to load/store the node's secret key share.
+    #[arg(long, env("MPC_SK_SHARE_AWS_SECRET_ID"))]
+    pub sk_share_aws_secret_id: Option<String>,
+    /// HashiCorp Vault address that will be used to load/store the node's secret key share.
+    #[arg(long, env("MPC_SK_SHARE_VAULT_ADDRESS"), requires_all=["sk_share_vault_path", "sk_share_vault_token"])]
+    pub sk_share_vault_address: Option<String>,
+    /// Path of the key share secret in a KV v2 engine, e.g. `secret/data/mpc-node`.
+    #[arg(long, env("MPC_SK_SHARE_VAULT_PATH"))]
+    pub sk_share_vault_path: Option<String>,
+    /// Token used to authenticate against Vault.
+    #[arg(long, env("MPC_SK_SHARE_VAULT_TOKEN"))]
+    pub sk_share_vault_token: Option<String>,
 }
 
 impl Options {
@@ -42,7 +163,62 @@ impl Options {
                 sk_share_local_path,
             ]);
         }
+        if let Some(sk_share_aws_secret_id) = self.sk_share_aws_secret_id {
+            opts.extend(vec![
+                "--sk-share-aws-secret-id".to_string(),
+                sk_share_aws_secret_id,
+            ]);
+        }
+        if let Some(sk_share_vault_address) = self.sk_share_vault_address {
+            opts.extend(vec![
+                "--sk-share-vault-address".to_string(),
+                sk_share_vault_address,
+            ]);
+        }
+        if let Some(sk_share_vault_path) = self.sk_share_vault_path {
+            opts.extend(vec![
+                "--sk-share-vault-path".to_string(),
+                sk_share_vault_path,
+            ]);
+        }
+        if let Some(sk_share_vault_token) = self.sk_share_vault_token {
+            opts.extend(vec![
+                "--sk-share-vault-token".to_string(),
+                sk_share_vault_token,
+            ]);
+        }
 
         opts
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::triple_storage::{TripleData, TripleKey};
+    use std::collections::HashMap;
+
+    fn entity(key: Key) -> Value {
+        Value::EntityValue {
+            key,
+            properties: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_is_legacy() {
+        let legacy = LegacyKey::<TripleData>::new("p-0.testnet", 7);
+        assert!(is_legacy(&entity(legacy.key())));
+        let key = TripleKey {
+            account_id: "p-0.testnet",
+            epoch: 3,
+            triple_id: 7,
+        };
+        assert!(!is_legacy(&entity(key.key())));
+        assert!(!is_legacy(&entity(Key {
+            path: None,
+            partition_id: None,
+        })));
+        assert!(!is_legacy(&Value::IntegerValue(7)));
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::gcp::{error, Keyable};
+use crate::gcp::{
+    error::ConvertError,
+    value::{FromValue, IntoValue, Value},
+    KeyKind,
+};
+use crate::gcp::{DatastoreService, GcpService};
+use crate::protocol::presignature::{Presignature, PresignatureId};
+use crate::storage::{entity_name, fetch_entities, is_legacy, LegacyKey};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use google_datastore1::api::{Key, PathElement};
+use tokio::sync::RwLock;
+
+use near_account_id::AccountId;
+use zeroize::Zeroizing;
+
+pub struct PresignatureKey<'a> {
+    pub account_id: &'a str,
+    pub epoch: u64,
+    pub presignature_id: PresignatureId,
+}
+
+impl KeyKind for PresignatureKey<'_> {
+    fn kind() -> String {
+        "presignatures".to_string()
+    }
+}
+
+impl Keyable for PresignatureKey<'_> {
+    fn key(&self) -> Key {
+        Key {
+            path: Some(vec![PathElement {
+                kind: None,
+                name: Some(entity_name(
+                    self.account_id,
+                    self.epoch,
+                    self.presignature_id,
+                )),
+                id: None,
+            }]),
+            partition_id: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PresignatureData {
+    pub account_id: AccountId,
+    pub presignature: Presignature,
+    pub mine: bool,
+}
+
+impl KeyKind for PresignatureData {
+    fn kind() -> String {
+        "presignatures".to_string()
+    }
+}
+
+impl Keyable for PresignatureData {
+    fn key(&self) -> Key {
+        Key {
+            path: Some(vec![PathElement {
+                kind: None,
+                name: Some(entity_name(
+                    self.account_id.as_str(),
+                    self.presignature.epoch,
+                    self.presignature.id,
+                )),
+                id: None,
+            }]),
+            partition_id: None,
+        }
+    }
+}
+
+impl IntoValue for PresignatureData {
+    fn into_value(self) -> Value {
+        let presignature_key = PresignatureKey {
+            account_id: self.account_id.as_str(),
+            epoch: self.presignature.epoch,
+            presignature_id: self.presignature.id,
+        };
+        let mut properties = HashMap::new();
+        properties.insert(
+            "account_id".to_string(),
+            Value::StringValue(self.account_id.to_string()),
+        );
+        properties.insert(
+            "presignature_id".to_string(),
+            Value::IntegerValue(self.presignature.id as i64),
+        );
+        properties.insert(
+            "presignature_output".to_string(),
+            Value::StringValue(serde_json::to_string(&self.presignature.output).unwrap()),
+        );
+        properties.insert(
+            "presignature_participants".to_string(),
+            Value::StringValue(serde_json::to_string(&self.presignature.participants).unwrap()),
+        );
+        properties.insert(
+            "created_at".to_string(),
+            Value::IntegerValue(self.presignature.created_at as i64),
+        );
+        properties.insert(
+            "epoch".to_string(),
+            Value::IntegerValue(self.presignature.epoch as i64),
+        );
+        properties.insert(
+            "public_key_hash".to_string(),
+            Value::StringValue(hex::encode(self.presignature.public_key_hash)),
+        );
+        if let Some(triples) = self.presignature.triples {
+            properties.insert(
+                "presignature_triples".to_string(),
+                Value::StringValue(serde_json::to_string(&triples).unwrap()),
+            );
+        }
+        properties.insert("mine".to_string(), Value::BooleanValue(self.mine));
+        Value::EntityValue {
+            key: presignature_key.key(),
+            properties,
+        }
+    }
+}
+
+impl FromValue for PresignatureData {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::EntityValue { mut properties, .. } => {
+                let (_, presignature_id) = properties
+                    .remove_entry("presignature_id")
+                    .ok_or_else(|| ConvertError::MissingProperty("presignature_id".to_string()))?;
+                let presignature_id = i64::from_value(presignature_id)?;
+
+                let (_, account_id) = properties
+                    .remove_entry("account_id")
+                    .ok_or_else(|| ConvertError::MissingProperty("account_id".to_string()))?;
+                let account_id = String::from_value(account_id)?.parse().map_err(|err| {
+                    ConvertError::MalformedProperty(format!(
+                        "PresignatureData failed to parse account_id: {err:?}"
+                    ))
+                })?;
+
+                let (_, output) =
+                    properties
+                        .remove_entry("presignature_output")
+                        .ok_or_else(|| {
+                            ConvertError::MissingProperty("presignature_output".to_string())
+                        })?;
+                let output = Zeroizing::new(String::from_value(output)?);
+                let output = serde_json::from_str(&output).map_err(|_| {
+                    ConvertError::MalformedProperty("presignature_output".to_string())
+                })?;
+
+                let (_, participants) = properties
+                    .remove_entry("presignature_participants")
+                    .ok_or_else(|| {
+                        ConvertError::MissingProperty("presignature_participants".to_string())
+                    })?;
+                let participants = String::from_value(participants)?;
+                let participants = serde_json::from_str(&participants).map_err(|_| {
+                    ConvertError::MalformedProperty("presignature_participants".to_string())
+                })?;
+
+                let (_, mine) = properties
+                    .remove_entry("mine")
+                    .ok_or_else(|| ConvertError::MissingProperty("mine".to_string()))?;
+                let mine = bool::from_value(mine)?;
+
+                // Presignatures stored before their creation time was tracked start aging now.
+                let created_at = match properties.remove("created_at") {
+                    Some(created_at) => i64::from_value(created_at)? as u64,
+                    None => Utc::now().timestamp() as u64,
+                };
+
+                // Presignatures stored before they were bound to a key are never bound to the
+                // current one, so that they get discarded on load.
+                let epoch = match properties.remove("epoch") {
+                    Some(epoch) => i64::from_value(epoch)? as u64,
+                    None => 0,
+                };
+                let public_key_hash = match properties.remove("public_key_hash") {
+                    Some(hash) => {
+                        let hash = String::from_value(hash)?;
+                        hex::decode(&hash)
+                            .ok()
+                            .and_then(|hash| hash.try_into().ok())
+                            .ok_or_else(|| {
+                                ConvertError::MalformedProperty("public_key_hash".to_string())
+                            })?
+                    }
+                    None => [0; 32],
+                };
+                // Presignatures stored before their triples were recorded have no lineage.
+                let triples = match properties.remove("presignature_triples") {
+                    Some(triples) => {
+                        let triples = String::from_value(triples)?;
+                        Some(serde_json::from_str(&triples).map_err(|_| {
+                            ConvertError::MalformedProperty("presignature_triples".to_string())
+                        })?)
+                    }
+                    None => None,
+                };
+
+                Ok(Self {
+                    account_id,
+                    presignature: Presignature {
+                        id: presignature_id as u64,
+                        output,
+                        participants,
+                        created_at,
+                        epoch,
+                        public_key_hash,
+                        triples,
+                    },
+                    mine,
+                })
+            }
+            value => Err(ConvertError::UnexpectedPropertyType {
+                expected: "entity".to_string(),
+                got: format!("{:?}", value),
+            }),
+        }
+    }
+}
+
+type PresignatureResult<T> = std::result::Result<T, error::DatastoreStorageError>;
+
+#[async_trait]
+pub trait PresignatureNodeStorage {
+    /// Stores the presignature under the epoch it was generated in.
+    async fn insert(&mut self, presignature: Presignature, mine: bool) -> PresignatureResult<()>;
+    async fn delete(&mut self, id: PresignatureId, epoch: u64) -> PresignatureResult<()>;
+    async fn clear(&mut self) -> PresignatureResult<Vec<PresignatureData>>;
+    async fn load(&self) -> PresignatureResult<Vec<PresignatureData>>;
+    /// Moves the presignatures still stored under the keys of the layout before keys were
+    /// namespaced by epoch, which are not loaded until then. Returns the number of presignatures
+    /// migrated.
+    async fn migrate(&mut self) -> PresignatureResult<usize>;
+    fn account_id(&self) -> &AccountId;
+}
+
+#[derive(Clone)]
+struct MemoryPresignatureNodeStorage {
+    presignatures: HashMap<(u64, PresignatureId), Presignature>,
+    mine: HashSet<(u64, PresignatureId)>,
+    /// Presignatures stored under the layout before keys were namespaced by epoch, see
+    /// [`PresignatureNodeStorage::migrate`].
+    legacy: HashMap<PresignatureId, (Presignature, bool)>,
+    account_id: AccountId,
+}
+
+#[async_trait]
+impl PresignatureNodeStorage for MemoryPresignatureNodeStorage {
+    async fn insert(&mut self, presignature: Presignature, mine: bool) -> PresignatureResult<()> {
+        let key = (presignature.epoch, presignature.id);
+        if mine {
+            self.mine.insert(key);
+        }
+        self.presignatures.insert(key, presignature);
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: PresignatureId, epoch: u64) -> PresignatureResult<()> {
+        self.presignatures.remove(&(epoch, id));
+        self.mine.remove(&(epoch, id));
+        Ok(())
+    }
+
+    async fn clear(&mut self) -> PresignatureResult<Vec<PresignatureData>> {
+        let res = self.load().await?;
+        self.presignatures.clear();
+        self.mine.clear();
+        Ok(res)
+    }
+
+    async fn load(&self) -> PresignatureResult<Vec<PresignatureData>> {
+        let mut res: Vec<PresignatureData> = vec![];
+        for (key, presignature) in self.presignatures.clone() {
+            let mine = self.mine.contains(&key);
+            res.push(PresignatureData {
+                account_id: self.account_id().clone(),
+                presignature,
+                mine,
+            });
+        }
+        Ok(res)
+    }
+
+    async fn migrate(&mut self) -> PresignatureResult<usize> {
+        let mut migrated = 0;
+        for (_, (presignature, mine)) in self.legacy.drain() {
+            let key = (presignature.epoch, presignature.id);
+            if mine {
+                self.mine.insert(key);
+            }
+            self.presignatures.insert(key, presignature);
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+}
+
+#[derive(Clone)]
+struct DataStorePresignatureNodeStorage {
+    datastore: DatastoreService,
+    account_id: AccountId,
+}
+
+impl DataStorePresignatureNodeStorage {
+    fn new(datastore: DatastoreService, account_id: &AccountId) -> Self {
+        Self {
+            datastore,
+            account_id: account_id.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl PresignatureNodeStorage for DataStorePresignatureNodeStorage {
+    async fn insert(&mut self, presignature: Presignature, mine: bool) -> PresignatureResult<()> {
+        tracing::debug!(
+            id = presignature.id,
+            epoch = presignature.epoch,
+            "inserting presignature using datastore"
+        );
+        self.datastore
+            .upsert(PresignatureData {
+                account_id: self.account_id().clone(),
+                presignature,
+                mine,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: PresignatureId, epoch: u64) -> PresignatureResult<()> {
+        tracing::debug!(id, epoch, "deleting presignature using datastore");
+        self.datastore
+            .delete(PresignatureKey {
+                account_id: self.account_id.as_str(),
+                epoch,
+                presignature_id: id,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn clear(&mut self) -> PresignatureResult<Vec<PresignatureData>> {
+        let presignatures = self.load().await?;
+        self.datastore.delete_many(&presignatures).await?;
+        Ok(presignatures)
+    }
+
+    async fn load(&self) -> PresignatureResult<Vec<PresignatureData>> {
+        tracing::debug!("loading presignatures using datastore");
+        let entities =
+            fetch_entities::<PresignatureData>(&self.datastore, &self.account_id).await?;
+        let mut res: Vec<PresignatureData> = vec![];
+        for entity in entities.into_iter().filter(|entity| !is_legacy(entity)) {
+            let presignature_data = PresignatureData::from_value(entity)?;
+            if &presignature_data.account_id == self.account_id() {
+                res.push(presignature_data);
+            }
+        }
+        tracing::debug!(count = res.len(), "loading presignatures success");
+        Ok(res)
+    }
+
+    async fn migrate(&mut self) -> PresignatureResult<usize> {
+        let mut migrated = 0;
+        // Presignatures recorded their epoch before their keys were namespaced by it. The ones
+        // stored before that are migrated under epoch 0, never bound to the current key.
+        let entities =
+            fetch_entities::<PresignatureData>(&self.datastore, &self.account_id).await?;
+        for entity in entities.into_iter().filter(is_legacy) {
+            let presignature_data = PresignatureData::from_value(entity)?;
+            if &presignature_data.account_id != self.account_id() {
+                continue;
+            }
+            let legacy = LegacyKey::<PresignatureData>::new(
+                self.account_id.as_str(),
+                presignature_data.presignature.id,
+            );
+            // Written under the new key before the legacy one is deleted, such that an
+            // interrupted migration is resumed on the next start rather than losing entities.
+            self.datastore.upsert(presignature_data).await?;
+            self.datastore.delete(legacy).await?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+}
+
+pub type PresignatureNodeStorageBox = Box<dyn PresignatureNodeStorage + Send + Sync>;
+
+pub type LockPresignatureNodeStorageBox = Arc<RwLock<PresignatureNodeStorageBox>>;
+
+pub fn init(
+    gcp_service: Option<&GcpService>,
+    account_id: &AccountId,
+) -> PresignatureNodeStorageBox {
+    match gcp_service {
+        Some(gcp) => {
+            tracing::info!("using DataStorePresignatureNodeStorage");
+            Box::new(DataStorePresignatureNodeStorage::new(
+                gcp.datastore.clone(),
+                account_id,
+            )) as PresignatureNodeStorageBox
+        }
+        _ => {
+            tracing::info!("using MemoryPresignatureNodeStorage");
+            Box::new(MemoryPresignatureNodeStorage {
+                presignatures: HashMap::new(),
+                mine: HashSet::new(),
+                legacy: HashMap::new(),
+                account_id: account_id.clone(),
+            }) as PresignatureNodeStorageBox
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::presignature::{public_key_hash, PresignatureManager};
+    use cait_sith::protocol::Participant;
+    use cait_sith::PresignOutput;
+    use k256::elliptic_curve::Field;
+
+    fn presignature(id: PresignatureId, epoch: u64, public_key_hash: [u8; 32]) -> Presignature {
+        Presignature {
+            id,
+            output: PresignOutput {
+                big_r: k256::AffinePoint::GENERATOR,
+                k: k256::Scalar::random(&mut rand::thread_rng()),
+                sigma: k256::Scalar::random(&mut rand::thread_rng()),
+            },
+            participants: vec![Participant::from(0u32)],
+            created_at: Utc::now().timestamp() as u64,
+            epoch,
+            public_key_hash,
+            triples: None,
+        }
+    }
+
+    fn keys(presignatures: &[PresignatureData]) -> Vec<(u64, PresignatureId, bool)> {
+        let mut keys = presignatures
+            .iter()
+            .map(|data| (data.presignature.epoch, data.presignature.id, data.mine))
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let account_id: AccountId = "p-0.testnet".parse().unwrap();
+        let public_key = k256::AffinePoint::GENERATOR;
+        let hash = public_key_hash(&public_key);
+        let mut storage = MemoryPresignatureNodeStorage {
+            presignatures: HashMap::new(),
+            mine: HashSet::new(),
+            legacy: HashMap::from([
+                (1, (presignature(1, 1, hash), true)),
+                (2, (presignature(2, 0, hash), false)),
+            ]),
+            account_id: account_id.clone(),
+        };
+        storage
+            .insert(presignature(3, 1, hash), false)
+            .await
+            .unwrap();
+        // Presignatures under their legacy keys are not loaded until they are migrated.
+        assert_eq!(keys(&storage.load().await.unwrap()), vec![(1, 3, false)]);
+
+        // They are moved under the epoch they recorded.
+        assert_eq!(storage.migrate().await.unwrap(), 2);
+        let loaded = storage.load().await.unwrap();
+        assert_eq!(
+            keys(&loaded),
+            vec![(0, 2, false), (1, 1, true), (1, 3, false)]
+        );
+        // Nothing is left to migrate on the next start.
+        assert_eq!(storage.migrate().await.unwrap(), 0);
+
+        // The presignature of the previous epoch is not loaded by the manager.
+        let manager = PresignatureManager::new(
+            Participant::from(0u32),
+            1,
+            1,
+            &public_key,
+            loaded,
+            Arc::new(RwLock::new(init(None, &account_id))),
+            &account_id,
+        );
+        assert_eq!(manager.len(), 2);
+        assert_eq!(manager.my_len(), 1);
+    }
+}
@@ -2,12 +2,19 @@ use std::path::PathBuf;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::gcp::error::SecretStorageError;
 use crate::gcp::{GcpService, SecretResult};
 use crate::storage::Options;
 use crate::{gcp::SecretManagerService, protocol::state::PersistentNodeData};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
+use mpc_keys::hpke::{self, Ciphered};
 use near_account_id::AccountId;
+use zeroize::Zeroizing;
+
+/// Associated data used when encrypting the key share stored on disk.
+const DISK_KEY_SHARE_ASSOCIATED_DATA: &[u8] = b"mpc-node-key-share";
 
 #[async_trait]
 pub trait SecretNodeStorage {
@@ -53,7 +60,10 @@ impl SecretNodeStorage for SecretManagerNodeStorage {
     async fn store(&mut self, data: &PersistentNodeData) -> SecretResult<()> {
         tracing::info!("storing PersistentNodeData using SecretManagerNodeStorage");
         self.secret_manager
-            .store_secret(&serde_json::to_vec(data)?, &self.sk_share_secret_id)
+            .store_secret(
+                &Zeroizing::new(serde_json::to_vec(data)?),
+                &self.sk_share_secret_id,
+            )
             .await?;
         Ok(())
     }
@@ -63,7 +73,8 @@ impl SecretNodeStorage for SecretManagerNodeStorage {
         let raw_data = self
             .secret_manager
             .load_secret(&self.sk_share_secret_id)
-            .await?;
+            .await?
+            .map(Zeroizing::new);
         match raw_data {
             Some(data) if data.len() > 1 => match serde_json::from_slice(&data) {
                 Ok(persistent_node_data) => Ok(Some(persistent_node_data)),
@@ -80,14 +91,148 @@ impl SecretNodeStorage for SecretManagerNodeStorage {
     }
 }
 
+struct AwsSecretsManagerNodeStorage {
+    client: aws_sdk_secretsmanager::Client,
+    secret_id: String,
+}
+
+impl AwsSecretsManagerNodeStorage {
+    async fn new(secret_id: String) -> Self {
+        let aws_config = aws_config::from_env().load().await;
+        Self {
+            client: aws_sdk_secretsmanager::Client::new(&aws_config),
+            secret_id,
+        }
+    }
+}
+
+#[async_trait]
+impl SecretNodeStorage for AwsSecretsManagerNodeStorage {
+    async fn store(&mut self, data: &PersistentNodeData) -> SecretResult<()> {
+        tracing::info!("storing PersistentNodeData using AwsSecretsManagerNodeStorage");
+        self.client
+            .put_secret_value()
+            .secret_id(&self.secret_id)
+            .secret_string(serde_json::to_string(data)?)
+            .send()
+            .await
+            .map_err(|err| {
+                tracing::error!(?err, "failed to store secret");
+                SecretStorageError::AwsError(err.to_string())
+            })?;
+        Ok(())
+    }
+
+    async fn load(&self) -> SecretResult<Option<PersistentNodeData>> {
+        tracing::info!("loading PersistentNodeData using AwsSecretsManagerNodeStorage");
+        let output = match self
+            .client
+            .get_secret_value()
+            .secret_id(&self.secret_id)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(err) => {
+                let err = err.into_service_error();
+                if err.is_resource_not_found_exception() {
+                    tracing::error!("failed to load existing key share, presuming it is missing");
+                    return Ok(None);
+                }
+                return Err(SecretStorageError::AwsError(err.to_string()));
+            }
+        };
+        match output.secret_string() {
+            Some(data) => Ok(Some(serde_json::from_str(data)?)),
+            None => {
+                tracing::error!("failed to load existing key share, presuming it is missing");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Stores the key share in a HashiCorp Vault KV v2 secrets engine.
+struct VaultNodeStorage {
+    http: reqwest::Client,
+    /// Full url of the secret, i.e. `<vault address>/v1/<mount>/data/<path>`.
+    url: String,
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultData<T> {
+    data: T,
+}
+
+impl VaultNodeStorage {
+    fn new(address: &str, path: &str, token: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: format!(
+                "{}/v1/{}",
+                address.trim_end_matches('/'),
+                path.trim_start_matches('/')
+            ),
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl SecretNodeStorage for VaultNodeStorage {
+    async fn store(&mut self, data: &PersistentNodeData) -> SecretResult<()> {
+        tracing::info!("storing PersistentNodeData using VaultNodeStorage");
+        self.http
+            .post(&self.url)
+            .header("X-Vault-Token", &self.token)
+            .json(&VaultData { data })
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|err| {
+                tracing::error!(%err, "failed to store secret");
+                SecretStorageError::VaultError(err.to_string())
+            })?;
+        Ok(())
+    }
+
+    async fn load(&self) -> SecretResult<Option<PersistentNodeData>> {
+        tracing::info!("loading PersistentNodeData using VaultNodeStorage");
+        let resp = self
+            .http
+            .get(&self.url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .map_err(|err| SecretStorageError::VaultError(err.to_string()))?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            tracing::error!("failed to load existing key share, presuming it is missing");
+            return Ok(None);
+        }
+        let VaultData {
+            data: VaultData { data },
+        } = resp
+            .error_for_status()
+            .map_err(|err| SecretStorageError::VaultError(err.to_string()))?
+            .json::<VaultData<VaultData<PersistentNodeData>>>()
+            .await
+            .map_err(|err| SecretStorageError::VaultError(err.to_string()))?;
+        Ok(Some(data))
+    }
+}
+
+/// Stores the key share on disk, encrypted to the node's own cipher key.
 struct DiskNodeStorage {
     path: PathBuf,
+    cipher_sk: hpke::SecretKey,
 }
 
 impl DiskNodeStorage {
-    pub fn new(path: &str) -> Self {
+    pub fn new(path: &str, cipher_sk: &hpke::SecretKey) -> Self {
         Self {
             path: PathBuf::from(path),
+            cipher_sk: cipher_sk.clone(),
         }
     }
 }
@@ -96,11 +241,26 @@ impl DiskNodeStorage {
 impl SecretNodeStorage for DiskNodeStorage {
     async fn store(&mut self, data: &PersistentNodeData) -> SecretResult<()> {
         tracing::info!("storing PersistentNodeData using DiskNodeStorage");
-        let mut file = File::create(self.path.as_os_str()).await?;
-        // Serialize the person object to JSON and convert directly to bytes
-        let json_bytes = serde_json::to_vec(data)?;
-        // Write the serialized JSON bytes to the file
+        // Serialize the person object to JSON and encrypt it before it touches the disk
+        let ciphered = self
+            .cipher_sk
+            .public_key()
+            .encrypt(
+                &Zeroizing::new(serde_json::to_vec(data)?),
+                DISK_KEY_SHARE_ASSOCIATED_DATA,
+            )
+            .map_err(|err| SecretStorageError::Encryption(err.to_string()))?;
+        let json_bytes = serde_json::to_vec(&ciphered)?;
+
+        // Write to a temporary file first and move it over the existing one, such that a crash
+        // midway through never leaves us with a truncated key share, e.g. after a reshare.
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path).await?;
         file.write_all(&json_bytes).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&tmp_path, &self.path).await?;
 
         Ok(())
     }
@@ -112,12 +272,26 @@ impl SecretNodeStorage for DiskNodeStorage {
 
         match file_res {
             Ok(mut file) => {
-                let mut contents = Vec::new();
+                let mut contents = Zeroizing::new(Vec::new());
                 // Read the contents of the file into the vector
                 tracing::info!("loading PersistentNodeData using DiskNodeStorage: reading");
                 file.read_to_end(&mut contents).await?;
 
                 tracing::info!("loading PersistentNodeData using DiskNodeStorage: read done");
+                let Ok(ciphered) = serde_json::from_slice::<Ciphered>(&contents) else {
+                    // Key shares written before encryption was introduced are stored in plain
+                    // text. They get encrypted the next time the share is stored.
+                    tracing::warn!(
+                        "loading PersistentNodeData using DiskNodeStorage: share is not encrypted"
+                    );
+                    let data: PersistentNodeData = serde_json::from_slice(&contents)?;
+                    return Ok(Some(data));
+                };
+                let contents = self
+                    .cipher_sk
+                    .decrypt(&ciphered, DISK_KEY_SHARE_ASSOCIATED_DATA)
+                    .map(Zeroizing::new)
+                    .map_err(|err| SecretStorageError::Encryption(err.to_string()))?;
                 // Deserialize the JSON content to a PersistentNodeData object
                 let data: PersistentNodeData = serde_json::from_slice(&contents)?;
 
@@ -130,10 +304,11 @@ impl SecretNodeStorage for DiskNodeStorage {
 
 pub type SecretNodeStorageBox = Box<dyn SecretNodeStorage + Send + Sync>;
 
-pub fn init(
+pub async fn init(
     gcp_service: Option<&GcpService>,
     opts: &Options,
     account_id: &AccountId,
+    cipher_sk: &hpke::SecretKey,
 ) -> SecretNodeStorageBox {
     match gcp_service {
         Some(gcp) if opts.sk_share_secret_id.is_some() => {
@@ -143,11 +318,26 @@ pub fn init(
                 opts.clone().sk_share_secret_id.unwrap().clone(),
             )) as SecretNodeStorageBox
         }
+        _ if opts.sk_share_aws_secret_id.is_some() => {
+            tracing::info!("using AwsSecretsManagerNodeStorage");
+            Box::new(
+                AwsSecretsManagerNodeStorage::new(opts.sk_share_aws_secret_id.clone().unwrap())
+                    .await,
+            ) as SecretNodeStorageBox
+        }
+        _ if opts.sk_share_vault_address.is_some() => {
+            tracing::info!("using VaultNodeStorage");
+            Box::new(VaultNodeStorage::new(
+                opts.sk_share_vault_address.as_deref().unwrap(),
+                opts.sk_share_vault_path.as_deref().unwrap_or_default(),
+                opts.sk_share_vault_token.clone().unwrap_or_default(),
+            )) as SecretNodeStorageBox
+        }
         _ => {
             if let Some(sk_share_local_path) = &opts.sk_share_local_path {
                 let path = format!("{sk_share_local_path}-{account_id}");
                 tracing::info!("using DiskNodeStorage with path: {}", path);
-                Box::new(DiskNodeStorage::new(&path)) as SecretNodeStorageBox
+                Box::new(DiskNodeStorage::new(&path, cipher_sk)) as SecretNodeStorageBox
             } else {
                 tracing::info!("using MemoryNodeStorage");
                 Box::<MemoryNodeStorage>::default() as SecretNodeStorageBox
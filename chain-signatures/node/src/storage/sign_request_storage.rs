@@ -0,0 +1,596 @@
+//! Persisted state of the sign requests indexed by the node, such that a request survives a crash
+//! anywhere between being indexed and being answered. Every request goes through
+//! [`SignRequestState`]: it is recorded as indexed before the indexer moves past its block, and
+//! advanced by the protocol loop as it gets signed. On startup, the requests that did not reach a
+//! final state are queued again, see [`recover`], while the ones that did are never answered again.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::gcp::{error, Keyable};
+use crate::gcp::{
+    error::ConvertError,
+    value::{FromValue, IntoValue, Value},
+    KeyKind,
+};
+use crate::gcp::{DatastoreService, GcpService};
+use crate::indexer::ContractSignRequest;
+use crate::protocol::presignature::PresignatureId;
+use crate::protocol::signature::{ReceiptId, SignPriority, SignQueue, SignRequest};
+use crate::protocol::validation::Rejection;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use google_datastore1::api::{
+    Filter, Key, PathElement, PropertyFilter, PropertyReference, Value as DatastoreValue,
+};
+use k256::Scalar;
+use near_account_id::AccountId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+/// How long the requests that reached a final state are kept around, so that they are not
+/// answered again when indexed again.
+pub const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the records past their retention are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Where a sign request is at on this node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SignRequestState {
+    /// Waiting in the queue for a presignature.
+    Indexed,
+    /// Matched with a presignature of this node, which proposes its signature.
+    Assigned { presignature_id: PresignatureId },
+    /// The signature protocol with the given presignature is running.
+    Signing { presignature_id: PresignatureId },
+    /// The signature was generated, and published if this node proposed it.
+    Completed,
+    /// The request was given up on.
+    Failed { reason: String },
+    /// The request was refused by the validators, see [`crate::protocol::validation`].
+    Rejected { rejection: Rejection },
+    /// This node is not part of the participants selected to sign the request.
+    NotSelected,
+}
+
+impl SignRequestState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Indexed => "indexed",
+            Self::Assigned { .. } => "assigned",
+            Self::Signing { .. } => "signing",
+            Self::Completed => "completed",
+            Self::Failed { .. } => "failed",
+            Self::Rejected { .. } => "rejected",
+            Self::NotSelected => "not_selected",
+        }
+    }
+
+    /// Whether nothing is left to do for the request.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            Self::Completed | Self::Failed { .. } | Self::Rejected { .. } | Self::NotSelected
+        )
+    }
+
+    /// Whether the request can go from this state to `next`. Final states are never left, and a
+    /// request can go back to waiting for a presignature when its signature protocol failed.
+    pub fn can_advance_to(&self, next: &SignRequestState) -> bool {
+        match (self, next) {
+            (current, _) if current.is_final() => false,
+            (_, Self::Completed | Self::Failed { .. } | Self::Rejected { .. }) => true,
+            (Self::Indexed, Self::Assigned { .. } | Self::Signing { .. } | Self::NotSelected) => {
+                true
+            }
+            (Self::Assigned { .. }, Self::Signing { .. } | Self::Indexed) => true,
+            (Self::Signing { .. }, Self::Signing { .. } | Self::Indexed) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A sign request along with its state. Instants do not survive a restart, so the times are
+/// recorded as UNIX timestamps in milliseconds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignRequestRecord {
+    pub receipt_id: ReceiptId,
+    pub request: ContractSignRequest,
+    pub epsilon: Scalar,
+    pub entropy: [u8; 32],
+    pub priority: SignPriority,
+    pub indexed_at: i64,
+    pub deadline: i64,
+    pub state: SignRequestState,
+    pub updated_at: i64,
+}
+
+impl SignRequestRecord {
+    /// The record of a request that was just indexed.
+    pub fn indexed(request: &SignRequest) -> Self {
+        let now = Instant::now();
+        let now_ms = Utc::now().timestamp_millis();
+        let age = now
+            .saturating_duration_since(request.time_added)
+            .as_millis() as i64;
+        let remaining = request.deadline.saturating_duration_since(now).as_millis() as i64;
+        Self {
+            receipt_id: request.receipt_id,
+            request: request.request.clone(),
+            epsilon: request.epsilon,
+            entropy: request.entropy,
+            priority: request.priority,
+            indexed_at: now_ms - age,
+            deadline: now_ms + remaining,
+            state: SignRequestState::Indexed,
+            updated_at: now_ms,
+        }
+    }
+
+    /// The sign request to queue again, unless it expired in the meantime.
+    fn into_request(self, now_ms: i64) -> Option<SignRequest> {
+        let remaining = u64::try_from(self.deadline - now_ms).ok()?;
+        let age = u64::try_from(now_ms - self.indexed_at).unwrap_or_default();
+        let now = Instant::now();
+        Some(SignRequest {
+            receipt_id: self.receipt_id,
+            request: self.request,
+            epsilon: self.epsilon,
+            entropy: self.entropy,
+            time_added: now.checked_sub(Duration::from_millis(age)).unwrap_or(now),
+            priority: self.priority,
+            deadline: now + Duration::from_millis(remaining),
+            merged: Vec::new(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SignRequestData {
+    pub account_id: AccountId,
+    pub record: SignRequestRecord,
+}
+
+impl KeyKind for SignRequestData {
+    fn kind() -> String {
+        "sign_requests".to_string()
+    }
+}
+
+impl Keyable for SignRequestData {
+    fn key(&self) -> Key {
+        Key {
+            path: Some(vec![PathElement {
+                kind: None,
+                name: Some(name(&self.account_id, &self.record.receipt_id)),
+                id: None,
+            }]),
+            partition_id: None,
+        }
+    }
+}
+
+fn name(account_id: &AccountId, receipt_id: &ReceiptId) -> String {
+    format!("{account_id}/{receipt_id}")
+}
+
+impl IntoValue for SignRequestData {
+    fn into_value(self) -> Value {
+        let key = self.key();
+        let mut properties = HashMap::new();
+        properties.insert(
+            "account_id".to_string(),
+            Value::StringValue(self.account_id.to_string()),
+        );
+        properties.insert(
+            "receipt_id".to_string(),
+            Value::StringValue(self.record.receipt_id.to_string()),
+        );
+        properties.insert(
+            "state".to_string(),
+            Value::StringValue(self.record.state.name().to_string()),
+        );
+        properties.insert(
+            "updated_at".to_string(),
+            Value::IntegerValue(self.record.updated_at),
+        );
+        properties.insert(
+            "record".to_string(),
+            Value::StringValue(serde_json::to_string(&self.record).unwrap()),
+        );
+        Value::EntityValue { key, properties }
+    }
+}
+
+impl FromValue for SignRequestData {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::EntityValue { mut properties, .. } => {
+                let (_, account_id) = properties
+                    .remove_entry("account_id")
+                    .ok_or_else(|| ConvertError::MissingProperty("account_id".to_string()))?;
+                let account_id = String::from_value(account_id)?.parse().map_err(|err| {
+                    ConvertError::MalformedProperty(format!(
+                        "SignRequestData failed to parse account_id: {err:?}"
+                    ))
+                })?;
+                let (_, record) = properties
+                    .remove_entry("record")
+                    .ok_or_else(|| ConvertError::MissingProperty("record".to_string()))?;
+                let record = serde_json::from_str(&String::from_value(record)?)
+                    .map_err(|_| ConvertError::MalformedProperty("record".to_string()))?;
+                Ok(Self { account_id, record })
+            }
+            value => Err(ConvertError::UnexpectedPropertyType {
+                expected: "entity".to_string(),
+                got: format!("{:?}", value),
+            }),
+        }
+    }
+}
+
+type SignRequestResult<T> = std::result::Result<T, error::DatastoreStorageError>;
+
+#[async_trait]
+pub trait SignRequestNodeStorage {
+    async fn get(&self, receipt_id: &ReceiptId) -> SignRequestResult<Option<SignRequestRecord>>;
+    async fn upsert(&mut self, record: SignRequestRecord) -> SignRequestResult<()>;
+    async fn delete(&mut self, receipt_id: &ReceiptId) -> SignRequestResult<()>;
+    async fn load(&self) -> SignRequestResult<Vec<SignRequestRecord>>;
+    fn account_id(&self) -> &AccountId;
+
+    /// Records a newly indexed request. Returns false, leaving the record untouched, if the
+    /// request is already known, e.g. because its block got indexed again.
+    async fn insert(&mut self, record: SignRequestRecord) -> SignRequestResult<bool> {
+        if self.get(&record.receipt_id).await?.is_some() {
+            return Ok(false);
+        }
+        self.upsert(record).await?;
+        Ok(true)
+    }
+
+    /// Moves the request to the `next` state. Transitions that the state machine does not allow,
+    /// e.g. out of a final state, are ignored.
+    async fn advance(
+        &mut self,
+        receipt_id: &ReceiptId,
+        next: SignRequestState,
+    ) -> SignRequestResult<()> {
+        let Some(mut record) = self.get(receipt_id).await? else {
+            tracing::debug!(%receipt_id, state = next.name(), "no record of sign request to advance");
+            return Ok(());
+        };
+        if record.state == next {
+            return Ok(());
+        }
+        if !record.state.can_advance_to(&next) {
+            tracing::debug!(
+                %receipt_id,
+                from = record.state.name(),
+                to = next.name(),
+                "ignoring invalid sign request transition"
+            );
+            return Ok(());
+        }
+        record.state = next;
+        record.updated_at = Utc::now().timestamp_millis();
+        self.upsert(record).await
+    }
+}
+
+#[derive(Clone)]
+struct MemorySignRequestNodeStorage {
+    records: HashMap<ReceiptId, SignRequestRecord>,
+    account_id: AccountId,
+}
+
+#[async_trait]
+impl SignRequestNodeStorage for MemorySignRequestNodeStorage {
+    async fn get(&self, receipt_id: &ReceiptId) -> SignRequestResult<Option<SignRequestRecord>> {
+        Ok(self.records.get(receipt_id).cloned())
+    }
+
+    async fn upsert(&mut self, record: SignRequestRecord) -> SignRequestResult<()> {
+        self.records.insert(record.receipt_id, record);
+        Ok(())
+    }
+
+    async fn delete(&mut self, receipt_id: &ReceiptId) -> SignRequestResult<()> {
+        self.records.remove(receipt_id);
+        Ok(())
+    }
+
+    async fn load(&self) -> SignRequestResult<Vec<SignRequestRecord>> {
+        Ok(self.records.values().cloned().collect())
+    }
+
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+}
+
+#[derive(Clone)]
+struct DataStoreSignRequestNodeStorage {
+    datastore: DatastoreService,
+    account_id: AccountId,
+}
+
+#[async_trait]
+impl SignRequestNodeStorage for DataStoreSignRequestNodeStorage {
+    async fn get(&self, receipt_id: &ReceiptId) -> SignRequestResult<Option<SignRequestRecord>> {
+        match self
+            .datastore
+            .get::<_, SignRequestData>(name(&self.account_id, receipt_id))
+            .await
+        {
+            Ok(data) => Ok(Some(data.record)),
+            Err(error::DatastoreStorageError::EntityNotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn upsert(&mut self, record: SignRequestRecord) -> SignRequestResult<()> {
+        tracing::debug!(receipt_id = %record.receipt_id, state = record.state.name(), "upserting sign request using datastore");
+        self.datastore
+            .upsert(SignRequestData {
+                account_id: self.account_id.clone(),
+                record,
+            })
+            .await
+    }
+
+    async fn delete(&mut self, receipt_id: &ReceiptId) -> SignRequestResult<()> {
+        let Some(record) = self.get(receipt_id).await? else {
+            return Ok(());
+        };
+        self.datastore
+            .delete(SignRequestData {
+                account_id: self.account_id.clone(),
+                record,
+            })
+            .await
+    }
+
+    async fn load(&self) -> SignRequestResult<Vec<SignRequestRecord>> {
+        tracing::debug!("loading sign requests using datastore");
+        let filter = if self.datastore.is_emulator() {
+            None
+        } else {
+            Some(Filter {
+                composite_filter: None,
+                property_filter: Some(PropertyFilter {
+                    op: Some("Equal".to_string()),
+                    property: Some(PropertyReference {
+                        name: Some("account_id".to_string()),
+                    }),
+                    value: Some(DatastoreValue::from_value(
+                        self.account_id.as_str().into_value(),
+                    )?),
+                }),
+            })
+        };
+        let response = self
+            .datastore
+            .fetch_entities::<SignRequestData>(filter)
+            .await?;
+        let mut res = Vec::new();
+        for entity_result in response {
+            let entity = entity_result.entity.ok_or_else(|| {
+                error::DatastoreStorageError::FetchEntitiesError(
+                    "entity was not able to unwrapped".to_string(),
+                )
+            })?;
+            let data = SignRequestData::from_value(entity.into_value())?;
+            if data.account_id == self.account_id {
+                res.push(data.record);
+            }
+        }
+        tracing::debug!(count = res.len(), "loading sign requests success");
+        Ok(res)
+    }
+
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+}
+
+pub type SignRequestNodeStorageBox = Box<dyn SignRequestNodeStorage + Send + Sync>;
+
+pub type LockSignRequestNodeStorageBox = Arc<RwLock<SignRequestNodeStorageBox>>;
+
+pub fn init(gcp_service: Option<&GcpService>, account_id: &AccountId) -> SignRequestNodeStorageBox {
+    match gcp_service {
+        Some(gcp) => {
+            tracing::info!("using DataStoreSignRequestNodeStorage");
+            Box::new(DataStoreSignRequestNodeStorage {
+                datastore: gcp.datastore.clone(),
+                account_id: account_id.clone(),
+            }) as SignRequestNodeStorageBox
+        }
+        _ => {
+            tracing::info!("using MemorySignRequestNodeStorage");
+            Box::new(MemorySignRequestNodeStorage {
+                records: HashMap::new(),
+                account_id: account_id.clone(),
+            }) as SignRequestNodeStorageBox
+        }
+    }
+}
+
+/// Records the transitions of the sign requests made by the protocol loop.
+pub async fn record(
+    storage: &LockSignRequestNodeStorageBox,
+    transitions: Vec<(ReceiptId, SignRequestState)>,
+) {
+    if transitions.is_empty() {
+        return;
+    }
+    let mut storage = storage.write().await;
+    for (receipt_id, state) in transitions {
+        let name = state.name();
+        if let Err(err) = storage.advance(&receipt_id, state).await {
+            tracing::warn!(%receipt_id, state = name, ?err, "failed to record sign request state");
+        }
+    }
+}
+
+/// Queues again the requests that did not reach a final state before the node stopped. Requests
+/// that were being signed start over from the queue, since their presignature and protocol did
+/// not survive, and the ones past their deadline are given up on. Returns the number of requests
+/// queued again.
+pub async fn recover(
+    storage: &LockSignRequestNodeStorageBox,
+    queue: &Arc<RwLock<SignQueue>>,
+) -> SignRequestResult<usize> {
+    let mut storage = storage.write().await;
+    let now_ms = Utc::now().timestamp_millis();
+    let mut queue = queue.write().await;
+    let mut recovered = 0;
+    for record in storage.load().await? {
+        if record.state.is_final() {
+            continue;
+        }
+        let receipt_id = record.receipt_id;
+        let state = record.state.clone();
+        match record.into_request(now_ms) {
+            Some(request) => {
+                tracing::info!(%receipt_id, state = state.name(), "recovered sign request");
+                queue.add(request);
+                storage
+                    .advance(&receipt_id, SignRequestState::Indexed)
+                    .await?;
+                recovered += 1;
+            }
+            None => {
+                tracing::warn!(%receipt_id, state = state.name(), "sign request expired while the node was down");
+                storage
+                    .advance(
+                        &receipt_id,
+                        SignRequestState::Failed {
+                            reason: "expired while the node was down".to_string(),
+                        },
+                    )
+                    .await?;
+            }
+        }
+    }
+    Ok(recovered)
+}
+
+/// Deletes the records of the requests that reached a final state longer than [`RETENTION`] ago,
+/// returning how many were deleted.
+pub async fn prune(storage: &LockSignRequestNodeStorageBox) -> SignRequestResult<usize> {
+    let cutoff = Utc::now().timestamp_millis() - RETENTION.as_millis() as i64;
+    let mut storage = storage.write().await;
+    let mut pruned = 0;
+    for record in storage.load().await? {
+        if record.state.is_final() && record.updated_at < cutoff {
+            storage.delete(&record.receipt_id).await?;
+            pruned += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+/// Prunes the records past their retention every [`PRUNE_INTERVAL`].
+pub async fn run_pruner(storage: LockSignRequestNodeStorageBox) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match prune(&storage).await {
+            Ok(0) => {}
+            Ok(pruned) => tracing::info!(pruned, "pruned records of settled sign requests"),
+            Err(err) => tracing::warn!(?err, "failed to prune records of sign requests"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::signature::SIGN_REQUEST_DEADLINE;
+    use near_primitives::hash::CryptoHash;
+
+    fn request(receipt_id: ReceiptId) -> SignRequest {
+        let now = Instant::now();
+        SignRequest {
+            receipt_id,
+            request: ContractSignRequest {
+                payload: Scalar::ONE,
+                path: "test".to_string(),
+                key_version: 0,
+            },
+            epsilon: Scalar::ONE,
+            entropy: [0; 32],
+            time_added: now,
+            priority: SignPriority::default(),
+            deadline: now + SIGN_REQUEST_DEADLINE,
+            merged: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_state_machine() {
+        let signing = SignRequestState::Signing { presignature_id: 1 };
+        assert!(SignRequestState::Indexed
+            .can_advance_to(&SignRequestState::Assigned { presignature_id: 1 }));
+        assert!(signing.can_advance_to(&SignRequestState::Indexed));
+        assert!(signing.can_advance_to(&SignRequestState::Completed));
+        assert!(!signing.can_advance_to(&SignRequestState::NotSelected));
+        assert!(!SignRequestState::Completed.can_advance_to(&SignRequestState::Indexed));
+        assert!(
+            !SignRequestState::Completed.can_advance_to(&SignRequestState::Failed {
+                reason: "late".to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recover_requests() {
+        let account_id: AccountId = "node.test".parse().unwrap();
+        let storage: LockSignRequestNodeStorageBox = Arc::new(RwLock::new(init(None, &account_id)));
+        let (signing, completed) = (CryptoHash([1; 32]), CryptoHash([2; 32]));
+        {
+            let mut storage = storage.write().await;
+            for receipt_id in [signing, completed] {
+                let record = SignRequestRecord::indexed(&request(receipt_id));
+                assert!(storage.insert(record.clone()).await.unwrap());
+                // Indexing the same request again keeps its record.
+                assert!(!storage.insert(record).await.unwrap());
+            }
+        }
+        record(
+            &storage,
+            vec![
+                (signing, SignRequestState::Signing { presignature_id: 7 }),
+                (completed, SignRequestState::Signing { presignature_id: 8 }),
+                (completed, SignRequestState::Completed),
+                (completed, SignRequestState::Indexed),
+            ],
+        )
+        .await;
+
+        let queue = Arc::new(RwLock::new(SignQueue::new()));
+        assert_eq!(recover(&storage, &queue).await.unwrap(), 1);
+        let queued = queue
+            .read()
+            .await
+            .requests()
+            .map(|request| request.receipt_id)
+            .collect::<Vec<_>>();
+        assert_eq!(queued, vec![signing]);
+        let storage = storage.read().await;
+        assert_eq!(
+            storage.get(&signing).await.unwrap().unwrap().state,
+            SignRequestState::Indexed
+        );
+        assert_eq!(
+            storage.get(&completed).await.unwrap().unwrap().state,
+            SignRequestState::Completed
+        );
+    }
+}
@@ -9,17 +9,18 @@ use crate::gcp::{
 };
 use crate::gcp::{DatastoreService, GcpService};
 use crate::protocol::triple::{Triple, TripleId};
+use crate::storage::{entity_name, fetch_entities, is_legacy, LegacyKey};
 
 use async_trait::async_trait;
-use google_datastore1::api::{
-    Filter, Key, PathElement, PropertyFilter, PropertyReference, Value as DatastoreValue,
-};
+use google_datastore1::api::{Key, PathElement};
 use tokio::sync::RwLock;
 
 use near_account_id::AccountId;
+use zeroize::Zeroizing;
 
 pub struct TripleKey<'a> {
     pub account_id: &'a str,
+    pub epoch: u64,
     pub triple_id: TripleId,
 }
 
@@ -34,7 +35,7 @@ impl Keyable for TripleKey<'_> {
         Key {
             path: Some(vec![PathElement {
                 kind: None,
-                name: Some(format!("{}/{}", self.account_id, self.triple_id)),
+                name: Some(entity_name(self.account_id, self.epoch, self.triple_id)),
                 id: None,
             }]),
             partition_id: None,
@@ -45,6 +46,8 @@ impl Keyable for TripleKey<'_> {
 #[derive(Clone, Debug)]
 pub struct TripleData {
     pub account_id: AccountId,
+    /// Epoch the triple was generated in.
+    pub epoch: u64,
     pub triple: Triple,
     pub mine: bool,
 }
@@ -60,7 +63,11 @@ impl Keyable for TripleData {
         Key {
             path: Some(vec![PathElement {
                 kind: None,
-                name: Some(format!("{}/{}", self.account_id, self.triple.id)),
+                name: Some(entity_name(
+                    self.account_id.as_str(),
+                    self.epoch,
+                    self.triple.id,
+                )),
                 id: None,
             }]),
             partition_id: None,
@@ -72,6 +79,7 @@ impl IntoValue for TripleData {
     fn into_value(self) -> Value {
         let triple_key = TripleKey {
             account_id: self.account_id.as_str(),
+            epoch: self.epoch,
             triple_id: self.triple.id,
         };
         let mut properties = HashMap::new();
@@ -83,6 +91,7 @@ impl IntoValue for TripleData {
             "triple_id".to_string(),
             Value::IntegerValue(self.triple.id as i64),
         );
+        properties.insert("epoch".to_string(), Value::IntegerValue(self.epoch as i64));
         properties.insert(
             "triple_share".to_string(),
             Value::StringValue(serde_json::to_string(&self.triple.share).unwrap()),
@@ -108,6 +117,10 @@ impl FromValue for TripleData {
                     .ok_or_else(|| ConvertError::MissingProperty("triple_id".to_string()))?;
 
                 let triple_id = i64::from_value(triple_id)?;
+                let (_, epoch) = properties
+                    .remove_entry("epoch")
+                    .ok_or_else(|| ConvertError::MissingProperty("epoch".to_string()))?;
+                let epoch = i64::from_value(epoch)?;
                 let (_, account_id) = properties
                     .remove_entry("account_id")
                     .ok_or_else(|| ConvertError::MissingProperty("account_id".to_string()))?;
@@ -120,7 +133,7 @@ impl FromValue for TripleData {
                 let (_, triple_share) = properties
                     .remove_entry("triple_share")
                     .ok_or_else(|| ConvertError::MissingProperty("triple_share".to_string()))?;
-                let triple_share = String::from_value(triple_share)?;
+                let triple_share = Zeroizing::new(String::from_value(triple_share)?);
                 let triple_share = serde_json::from_str(&triple_share)
                     .map_err(|_| ConvertError::MalformedProperty("triple_share".to_string()))?;
 
@@ -138,6 +151,7 @@ impl FromValue for TripleData {
 
                 Ok(Self {
                     account_id,
+                    epoch: epoch as u64,
                     triple: Triple {
                         id: triple_id as u64,
                         share: triple_share,
@@ -154,37 +168,145 @@ impl FromValue for TripleData {
     }
 }
 
+/// Entry of the ledger of the triples consumed by a presignature, see
+/// [`TripleNodeStorage::insert_spent`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpentTripleData {
+    pub account_id: AccountId,
+    pub triple_id: TripleId,
+    pub epoch: u64,
+}
+
+impl KeyKind for SpentTripleData {
+    fn kind() -> String {
+        "spent_triples".to_string()
+    }
+}
+
+impl Keyable for SpentTripleData {
+    fn key(&self) -> Key {
+        Key {
+            path: Some(vec![PathElement {
+                kind: None,
+                name: Some(entity_name(
+                    self.account_id.as_str(),
+                    self.epoch,
+                    self.triple_id,
+                )),
+                id: None,
+            }]),
+            partition_id: None,
+        }
+    }
+}
+
+impl IntoValue for SpentTripleData {
+    fn into_value(self) -> Value {
+        let mut properties = HashMap::new();
+        properties.insert(
+            "account_id".to_string(),
+            Value::StringValue(self.account_id.to_string()),
+        );
+        properties.insert(
+            "triple_id".to_string(),
+            Value::IntegerValue(self.triple_id as i64),
+        );
+        properties.insert("epoch".to_string(), Value::IntegerValue(self.epoch as i64));
+        Value::EntityValue {
+            key: self.key(),
+            properties,
+        }
+    }
+}
+
+impl FromValue for SpentTripleData {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        match value {
+            Value::EntityValue { mut properties, .. } => {
+                let (_, account_id) = properties
+                    .remove_entry("account_id")
+                    .ok_or_else(|| ConvertError::MissingProperty("account_id".to_string()))?;
+                let account_id = String::from_value(account_id)?.parse().map_err(|err| {
+                    ConvertError::MalformedProperty(format!(
+                        "SpentTripleData failed to parse account_id: {err:?}"
+                    ))
+                })?;
+                let (_, triple_id) = properties
+                    .remove_entry("triple_id")
+                    .ok_or_else(|| ConvertError::MissingProperty("triple_id".to_string()))?;
+                let triple_id = i64::from_value(triple_id)?;
+                let (_, epoch) = properties
+                    .remove_entry("epoch")
+                    .ok_or_else(|| ConvertError::MissingProperty("epoch".to_string()))?;
+                let epoch = i64::from_value(epoch)?;
+                Ok(Self {
+                    account_id,
+                    triple_id: triple_id as u64,
+                    epoch: epoch as u64,
+                })
+            }
+            value => Err(ConvertError::UnexpectedPropertyType {
+                expected: "entity".to_string(),
+                got: format!("{:?}", value),
+            }),
+        }
+    }
+}
+
 type TripleResult<T> = std::result::Result<T, error::DatastoreStorageError>;
 
 #[async_trait]
 pub trait TripleNodeStorage {
-    async fn insert(&mut self, triple: Triple, mine: bool) -> TripleResult<()>;
-    async fn delete(&mut self, id: TripleId) -> TripleResult<()>;
+    async fn insert(&mut self, triple: Triple, epoch: u64, mine: bool) -> TripleResult<()>;
+    async fn delete(&mut self, id: TripleId, epoch: u64) -> TripleResult<()>;
     async fn clear(&mut self) -> TripleResult<Vec<TripleData>>;
+    /// Loads the triples of all epochs, it is up to the caller to discard the ones of other
+    /// epochs than its own.
     async fn load(&self) -> TripleResult<Vec<TripleData>>;
+    /// Records that the triple was consumed by a presignature in `epoch`, such that it is never
+    /// consumed again, even after a restart.
+    async fn insert_spent(&mut self, id: TripleId, epoch: u64) -> TripleResult<()>;
+    /// Removes the triple from the ledger, for triples released before any message was sent.
+    async fn delete_spent(&mut self, id: TripleId, epoch: u64) -> TripleResult<()>;
+    async fn load_spent(&self) -> TripleResult<Vec<SpentTripleData>>;
+    /// Removes the entries of the epochs before `epoch` from the ledger, as their triples can
+    /// no longer be used by any presignature.
+    async fn prune_spent(&mut self, epoch: u64) -> TripleResult<usize>;
+    /// Moves the triples and the ledger entries still stored under the keys of the layout before
+    /// keys were namespaced by epoch, which are not loaded until then. Triples of that layout did
+    /// not record their epoch and are assumed to be of `epoch`, the epoch of the stored key share,
+    /// as triples are cleared from storage whenever the epoch changes. Returns the number of
+    /// entities migrated.
+    async fn migrate(&mut self, epoch: u64) -> TripleResult<usize>;
     fn account_id(&self) -> &AccountId;
 }
 
 #[derive(Clone)]
 struct MemoryTripleNodeStorage {
-    triples: HashMap<TripleId, Triple>,
-    mine: HashSet<TripleId>,
+    triples: HashMap<(u64, TripleId), Triple>,
+    mine: HashSet<(u64, TripleId)>,
+    spent: HashSet<(u64, TripleId)>,
+    /// Triples stored under the layout before keys were namespaced by epoch, which did not record
+    /// their epoch, see [`TripleNodeStorage::migrate`].
+    legacy: HashMap<TripleId, (Triple, bool)>,
+    /// Spent triples stored under the layout before keys were namespaced by epoch.
+    legacy_spent: HashSet<(u64, TripleId)>,
     account_id: AccountId,
 }
 
 #[async_trait]
 impl TripleNodeStorage for MemoryTripleNodeStorage {
-    async fn insert(&mut self, triple: Triple, mine: bool) -> TripleResult<()> {
+    async fn insert(&mut self, triple: Triple, epoch: u64, mine: bool) -> TripleResult<()> {
         if mine {
-            self.mine.insert(triple.id);
+            self.mine.insert((epoch, triple.id));
         }
-        self.triples.insert(triple.id, triple);
+        self.triples.insert((epoch, triple.id), triple);
         Ok(())
     }
 
-    async fn delete(&mut self, id: TripleId) -> TripleResult<()> {
-        self.triples.remove(&id);
-        self.mine.remove(&id);
+    async fn delete(&mut self, id: TripleId, epoch: u64) -> TripleResult<()> {
+        self.triples.remove(&(epoch, id));
+        self.mine.remove(&(epoch, id));
         Ok(())
     }
 
@@ -197,10 +319,11 @@ impl TripleNodeStorage for MemoryTripleNodeStorage {
 
     async fn load(&self) -> TripleResult<Vec<TripleData>> {
         let mut res: Vec<TripleData> = vec![];
-        for (triple_id, triple) in self.triples.clone() {
-            let mine = self.mine.contains(&triple_id);
+        for ((epoch, triple_id), triple) in self.triples.clone() {
+            let mine = self.mine.contains(&(epoch, triple_id));
             res.push(TripleData {
                 account_id: self.account_id().clone(),
+                epoch,
                 triple,
                 mine,
             });
@@ -208,6 +331,50 @@ impl TripleNodeStorage for MemoryTripleNodeStorage {
         Ok(res)
     }
 
+    async fn insert_spent(&mut self, id: TripleId, epoch: u64) -> TripleResult<()> {
+        self.spent.insert((epoch, id));
+        Ok(())
+    }
+
+    async fn delete_spent(&mut self, id: TripleId, epoch: u64) -> TripleResult<()> {
+        self.spent.remove(&(epoch, id));
+        Ok(())
+    }
+
+    async fn load_spent(&self) -> TripleResult<Vec<SpentTripleData>> {
+        Ok(self
+            .spent
+            .iter()
+            .map(|(epoch, triple_id)| SpentTripleData {
+                account_id: self.account_id.clone(),
+                triple_id: *triple_id,
+                epoch: *epoch,
+            })
+            .collect())
+    }
+
+    async fn prune_spent(&mut self, epoch: u64) -> TripleResult<usize> {
+        let before = self.spent.len();
+        self.spent.retain(|(spent_epoch, _)| *spent_epoch >= epoch);
+        Ok(before - self.spent.len())
+    }
+
+    async fn migrate(&mut self, epoch: u64) -> TripleResult<usize> {
+        let mut migrated = 0;
+        for (id, (triple, mine)) in self.legacy.drain() {
+            if mine {
+                self.mine.insert((epoch, id));
+            }
+            self.triples.insert((epoch, id), triple);
+            migrated += 1;
+        }
+        for spent in self.legacy_spent.drain() {
+            self.spent.insert(spent);
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
     fn account_id(&self) -> &AccountId {
         &self.account_id
     }
@@ -230,11 +397,12 @@ impl DataStoreTripleNodeStorage {
 
 #[async_trait]
 impl TripleNodeStorage for DataStoreTripleNodeStorage {
-    async fn insert(&mut self, triple: Triple, mine: bool) -> TripleResult<()> {
-        tracing::debug!(id = triple.id, "inserting triples using datastore");
+    async fn insert(&mut self, triple: Triple, epoch: u64, mine: bool) -> TripleResult<()> {
+        tracing::debug!(id = triple.id, epoch, "inserting triples using datastore");
         self.datastore
             .upsert(TripleData {
                 account_id: self.account_id().clone(),
+                epoch,
                 triple,
                 mine,
             })
@@ -242,11 +410,12 @@ impl TripleNodeStorage for DataStoreTripleNodeStorage {
         Ok(())
     }
 
-    async fn delete(&mut self, id: TripleId) -> TripleResult<()> {
-        tracing::debug!(id, "deleting triples using datastore");
+    async fn delete(&mut self, id: TripleId, epoch: u64) -> TripleResult<()> {
+        tracing::debug!(id, epoch, "deleting triples using datastore");
         self.datastore
             .delete(TripleKey {
                 account_id: self.account_id.as_str(),
+                epoch,
                 triple_id: id,
             })
             .await?;
@@ -261,31 +430,10 @@ impl TripleNodeStorage for DataStoreTripleNodeStorage {
 
     async fn load(&self) -> TripleResult<Vec<TripleData>> {
         tracing::debug!("loading triples using datastore");
-        let filter = if self.datastore.is_emulator() {
-            None
-        } else {
-            Some(Filter {
-                composite_filter: None,
-                property_filter: Some(PropertyFilter {
-                    op: Some("Equal".to_string()),
-                    property: Some(PropertyReference {
-                        name: Some("account_id".to_string()),
-                    }),
-                    value: Some(DatastoreValue::from_value(
-                        self.account_id.as_str().into_value(),
-                    )?),
-                }),
-            })
-        };
-        let response = self.datastore.fetch_entities::<TripleData>(filter).await?;
+        let entities = fetch_entities::<TripleData>(&self.datastore, &self.account_id).await?;
         let mut res: Vec<TripleData> = vec![];
-        for entity_result in response {
-            let entity = entity_result.entity.ok_or_else(|| {
-                error::DatastoreStorageError::FetchEntitiesError(
-                    "entity was not able to unwrapped".to_string(),
-                )
-            })?;
-            let triple_data = TripleData::from_value(entity.into_value())?;
+        for entity in entities.into_iter().filter(|entity| !is_legacy(entity)) {
+            let triple_data = TripleData::from_value(entity)?;
             if &triple_data.account_id == self.account_id() {
                 res.push(triple_data);
             }
@@ -294,11 +442,104 @@ impl TripleNodeStorage for DataStoreTripleNodeStorage {
         Ok(res)
     }
 
+    async fn insert_spent(&mut self, id: TripleId, epoch: u64) -> TripleResult<()> {
+        tracing::debug!(id, epoch, "recording spent triple using datastore");
+        self.datastore
+            .upsert(SpentTripleData {
+                account_id: self.account_id.clone(),
+                triple_id: id,
+                epoch,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_spent(&mut self, id: TripleId, epoch: u64) -> TripleResult<()> {
+        tracing::debug!(id, epoch, "deleting spent triple using datastore");
+        self.datastore
+            .delete(SpentTripleData {
+                account_id: self.account_id.clone(),
+                triple_id: id,
+                epoch,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn load_spent(&self) -> TripleResult<Vec<SpentTripleData>> {
+        tracing::debug!("loading spent triples using datastore");
+        let entities = fetch_entities::<SpentTripleData>(&self.datastore, &self.account_id).await?;
+        let mut res = Vec::new();
+        for entity in entities.into_iter().filter(|entity| !is_legacy(entity)) {
+            let spent = SpentTripleData::from_value(entity)?;
+            if &spent.account_id == self.account_id() {
+                res.push(spent);
+            }
+        }
+        Ok(res)
+    }
+
+    async fn prune_spent(&mut self, epoch: u64) -> TripleResult<usize> {
+        let stale = self
+            .load_spent()
+            .await?
+            .into_iter()
+            .filter(|spent| spent.epoch < epoch)
+            .collect::<Vec<_>>();
+        if !stale.is_empty() {
+            self.datastore.delete_many(&stale).await?;
+        }
+        Ok(stale.len())
+    }
+
+    async fn migrate(&mut self, epoch: u64) -> TripleResult<usize> {
+        let mut migrated = 0;
+        // The entity is written under its new key before the legacy one is deleted, so that an
+        // interrupted migration is resumed on the next start rather than losing entities.
+        let entities = fetch_entities::<TripleData>(&self.datastore, &self.account_id).await?;
+        for entity in entities.into_iter().filter(is_legacy) {
+            let triple_data = migrated(entity, epoch)?;
+            if &triple_data.account_id != self.account_id() {
+                continue;
+            }
+            let legacy =
+                LegacyKey::<TripleData>::new(self.account_id.as_str(), triple_data.triple.id);
+            self.datastore.upsert(triple_data).await?;
+            self.datastore.delete(legacy).await?;
+            migrated += 1;
+        }
+
+        let entities = fetch_entities::<SpentTripleData>(&self.datastore, &self.account_id).await?;
+        for entity in entities.into_iter().filter(is_legacy) {
+            let spent = SpentTripleData::from_value(entity)?;
+            if &spent.account_id != self.account_id() {
+                continue;
+            }
+            let legacy =
+                LegacyKey::<SpentTripleData>::new(self.account_id.as_str(), spent.triple_id);
+            self.datastore.upsert(spent).await?;
+            self.datastore.delete(legacy).await?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+
     fn account_id(&self) -> &AccountId {
         &self.account_id
     }
 }
 
+/// Reads a triple stored under its legacy key, to be stored under its key namespaced by epoch.
+/// Triples of that layout did not record their epoch, they are assigned `epoch`.
+fn migrated(mut entity: Value, epoch: u64) -> Result<TripleData, ConvertError> {
+    if let Value::EntityValue { properties, .. } = &mut entity {
+        properties
+            .entry("epoch".to_string())
+            .or_insert(Value::IntegerValue(epoch as i64));
+    }
+    TripleData::from_value(entity)
+}
+
 pub type TripleNodeStorageBox = Box<dyn TripleNodeStorage + Send + Sync>;
 
 pub struct TripleStorage {
@@ -321,8 +562,115 @@ pub fn init(gcp_service: Option<&GcpService>, account_id: &AccountId) -> TripleN
             Box::new(MemoryTripleNodeStorage {
                 triples: HashMap::new(),
                 mine: HashSet::new(),
+                spent: HashSet::new(),
+                legacy: HashMap::new(),
+                legacy_spent: HashSet::new(),
                 account_id: account_id.clone(),
             }) as TripleNodeStorageBox
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::triple::TripleManager;
+    use cait_sith::protocol::Participant;
+    use k256::Secp256k1;
+
+    fn triple(id: TripleId) -> Triple {
+        let (public, mut shares) = cait_sith::triples::deal::<Secp256k1>(
+            &mut rand::thread_rng(),
+            &[Participant::from(0u32)],
+            1,
+        );
+        Triple {
+            id,
+            share: shares.remove(0),
+            public,
+        }
+    }
+
+    fn keys(triples: &[TripleData]) -> Vec<(u64, TripleId, bool)> {
+        let mut keys = triples
+            .iter()
+            .map(|data| (data.epoch, data.triple.id, data.mine))
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let account_id: AccountId = "p-0.testnet".parse().unwrap();
+        let mut storage = MemoryTripleNodeStorage {
+            triples: HashMap::new(),
+            mine: HashSet::new(),
+            spent: HashSet::new(),
+            legacy: HashMap::from([(1, (triple(1), true)), (2, (triple(2), false))]),
+            legacy_spent: HashSet::from([(1, 3)]),
+            account_id: account_id.clone(),
+        };
+        storage.insert(triple(4), 0, true).await.unwrap();
+        // Triples under their legacy keys are not loaded until they are migrated.
+        assert_eq!(keys(&storage.load().await.unwrap()), vec![(0, 4, true)]);
+
+        assert_eq!(storage.migrate(1).await.unwrap(), 3);
+        let loaded = storage.load().await.unwrap();
+        assert_eq!(
+            keys(&loaded),
+            vec![(0, 4, true), (1, 1, true), (1, 2, false)]
+        );
+        let spent = storage.load_spent().await.unwrap();
+        assert_eq!(
+            spent
+                .iter()
+                .map(|spent| (spent.epoch, spent.triple_id))
+                .collect::<Vec<_>>(),
+            vec![(1, 3)]
+        );
+        // Nothing is left to migrate on the next start.
+        assert_eq!(storage.migrate(1).await.unwrap(), 0);
+
+        // The triple of the previous epoch is not loaded by the manager.
+        let manager = TripleManager::new(
+            Participant::from(0u32),
+            1,
+            1,
+            loaded,
+            Arc::new(RwLock::new(init(None, &account_id))),
+            &account_id,
+        );
+        assert_eq!(manager.len(), 2);
+        assert_eq!(manager.my_len(), 1);
+    }
+
+    #[test]
+    fn test_migrated_entity() {
+        let account_id: AccountId = "p-0.testnet".parse().unwrap();
+        let data = TripleData {
+            account_id: account_id.clone(),
+            epoch: 0,
+            triple: triple(7),
+            mine: true,
+        };
+        // Triples of the legacy layout are stored under their id only, without their epoch.
+        let Value::EntityValue { mut properties, .. } = data.into_value() else {
+            unreachable!()
+        };
+        properties.remove("epoch");
+        let entity = Value::EntityValue {
+            key: LegacyKey::<TripleData>::new(account_id.as_str(), 7).key(),
+            properties,
+        };
+        assert!(is_legacy(&entity));
+
+        let data = migrated(entity, 3).unwrap();
+        assert_eq!((data.epoch, data.triple.id, data.mine), (3, 7, true));
+        let Value::EntityValue { key, .. } = data.into_value() else {
+            unreachable!()
+        };
+        let name = key.path.unwrap().remove(0).name;
+        assert_eq!(name, Some(entity_name(account_id.as_str(), 3, 7)));
+    }
+}
@@ -0,0 +1,181 @@
+//! Spans of the protocol instances and their export through OpenTelemetry. Every triple,
+//! presignature and signature protocol gets a span carrying its identifiers, so that the logs of
+//! all the modules about one protocol can be correlated. The trace id of such a span is derived
+//! from the protocol kind and id alone, such that the traces exported by every participant of the
+//! same protocol are stitched together into one trace.
+
+use cait_sith::protocol::Participant;
+use near_primitives::hash::CryptoHash;
+use once_cell::sync::OnceCell;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::{trace::Tracer, Resource};
+use sha3::{Digest, Sha3_256};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::layer::Layered;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::journal::ProtocolKind;
+
+#[derive(Debug, Clone, Default, clap::Parser)]
+#[group(id = "telemetry_options")]
+pub struct Options {
+    /// Address of the OpenTelemetry collector the protocol spans are exported to over OTLP/gRPC,
+    /// e.g. `http://localhost:4317`. Spans are not exported if not set.
+    #[arg(long, env("MPC_OTLP_ENDPOINT"))]
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut opts = Vec::new();
+        if let Some(otlp_endpoint) = self.otlp_endpoint {
+            opts.extend(vec!["--otlp-endpoint".to_string(), otlp_endpoint]);
+        }
+        opts
+    }
+}
+
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// The layer exporting the spans, empty until [`init`] is called within the tokio runtime.
+pub type Layer = reload::Layer<Option<OpenTelemetryLayer<Filtered, Tracer>>, Filtered>;
+
+static LAYER: OnceCell<reload::Handle<Option<OpenTelemetryLayer<Filtered, Tracer>>, Filtered>> =
+    OnceCell::new();
+
+/// Creates the layer exporting the spans, to be installed on top of the log filter at startup.
+pub fn layer() -> Layer {
+    let (layer, handle) = reload::Layer::new(None);
+    if LAYER.set(handle).is_err() {
+        tracing::warn!("telemetry layer is already installed");
+    }
+    layer
+}
+
+/// Starts exporting the spans to the collector of `options`, if any. The exporter runs on the
+/// current tokio runtime.
+pub fn init(options: &Options, node_account_id: &str) -> anyhow::Result<()> {
+    let Some(endpoint) = &options.otlp_endpoint else {
+        return Ok(());
+    };
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            opentelemetry_sdk::trace::config().with_resource(Resource::new(vec![
+                KeyValue::new("service.name", "mpc-node"),
+                KeyValue::new("node_account_id", node_account_id.to_string()),
+            ])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    if let Some(handle) = LAYER.get() {
+        handle.reload(Some(tracing_opentelemetry::layer().with_tracer(tracer)))?;
+    }
+    tracing::info!(endpoint, "exporting protocol spans");
+    Ok(())
+}
+
+/// Exports the spans that are still buffered. Called before the node exits.
+pub fn flush() {
+    if LAYER.get().is_some() {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// The trace and parent span ids shared by every participant of a protocol.
+fn ids(kind: ProtocolKind, id: &[u8]) -> (TraceId, SpanId) {
+    let mut hasher = Sha3_256::new();
+    hasher.update([kind as u8]);
+    hasher.update(id);
+    let hash: [u8; 32] = hasher.finalize().into();
+    let trace_id = TraceId::from_bytes(hash[..16].try_into().unwrap());
+    let span_id = SpanId::from_bytes(hash[16..24].try_into().unwrap());
+    (trace_id, span_id)
+}
+
+/// Makes `span` part of the trace of the protocol `id`, which is the same on every node.
+pub fn correlate(span: &tracing::Span, kind: ProtocolKind, id: &[u8]) {
+    let (trace_id, span_id) = ids(kind, id);
+    let parent = SpanContext::new(
+        trace_id,
+        span_id,
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    span.set_parent(Context::new().with_remote_span_context(parent));
+}
+
+/// Span of the triple protocol `id`.
+pub fn triple_span(id: u64) -> tracing::Span {
+    let span = tracing::info_span!(
+        "triple",
+        epoch = tracing::field::Empty,
+        protocol_type = "triple",
+        id,
+    );
+    correlate(&span, ProtocolKind::Triple, &id.to_le_bytes());
+    span
+}
+
+/// Span of the presignature protocol `id` consuming the triples `triple0` and `triple1`.
+pub fn presignature_span(
+    id: u64,
+    triple0: u64,
+    triple1: u64,
+    proposer: Participant,
+    mine: bool,
+) -> tracing::Span {
+    let span = tracing::info_span!(
+        "presignature",
+        epoch = tracing::field::Empty,
+        protocol_type = "presignature",
+        id,
+        triple0,
+        triple1,
+        ?proposer,
+        mine,
+    );
+    correlate(&span, ProtocolKind::Presignature, &id.to_le_bytes());
+    span
+}
+
+/// Span of the signature protocol answering the request of `receipt_id`.
+pub fn signature_span(
+    receipt_id: &CryptoHash,
+    presignature_id: u64,
+    proposer: Participant,
+) -> tracing::Span {
+    let span = tracing::info_span!(
+        "signature",
+        epoch = tracing::field::Empty,
+        protocol_type = "signature",
+        id = %receipt_id,
+        presignature_id,
+        ?proposer,
+    );
+    correlate(&span, ProtocolKind::Signature, receipt_id.as_ref());
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_shared_per_protocol() {
+        assert_eq!(
+            ids(ProtocolKind::Presignature, &7u64.to_le_bytes()),
+            ids(ProtocolKind::Presignature, &7u64.to_le_bytes())
+        );
+        assert_ne!(
+            ids(ProtocolKind::Presignature, &7u64.to_le_bytes()).0,
+            ids(ProtocolKind::Triple, &7u64.to_le_bytes()).0
+        );
+    }
+}
@@ -41,6 +41,10 @@ impl TestTripleManagers {
                     gcp_datastore_url: Some(url.clone()),
                     env: "triple-test".to_string(),
                     sk_share_local_path: None,
+                    sk_share_aws_secret_id: None,
+                    sk_share_vault_address: None,
+                    sk_share_vault_path: None,
+                    sk_share_vault_token: None,
                 };
                 Some(
                     GcpService::init(&account_id, &storage_options)
@@ -85,7 +89,10 @@ impl TestTripleManagers {
 
     async fn poke(&mut self, index: usize) -> Result<bool, ProtocolError> {
         let mut quiet = true;
-        let messages = self.managers[index].poke(&self.config.protocol).await;
+        let messages = self.managers[index]
+            .poke(&self.config.protocol)
+            .await
+            .messages;
         for (
             participant,
             ref tm @ TripleMessage {
@@ -334,11 +341,31 @@ pub async fn test_triple_deletion(datastore_url: Option<String>) {
             );
         }
 
+        // The spent triples are recorded in the ledger and can never be taken again.
+        {
+            let triple_storage = triple_storage.read().await;
+            let mut spent = triple_storage
+                .load_spent()
+                .await
+                .expect("expected spent triples to load successfully")
+                .into_iter()
+                .map(|spent| spent.triple_id)
+                .collect::<Vec<_>>();
+            spent.sort();
+            let mut expected = vec![id0, id1];
+            expected.sort();
+            assert_eq!(spent, expected, "both taken triples should be spent");
+        }
+        assert!(
+            tm.take_two(i, id0, id1).await.is_err(),
+            "spent triples should not be taken twice"
+        );
+
         //verify that if in take_two, one of the triples were accidentally deleted, double deletion will not cause issue
         {
             let mut triple_storage = triple_storage.write().await;
-            let del_res_mine_false = triple_storage.delete(triple0.id).await;
-            let del_res_mine_true = triple_storage.delete(triple0.id).await;
+            let del_res_mine_false = triple_storage.delete(triple0.id, STARTING_EPOCH).await;
+            let del_res_mine_true = triple_storage.delete(triple0.id, STARTING_EPOCH).await;
             assert!(
                 del_res_mine_false.is_ok() && del_res_mine_true.is_ok(),
                 "repeatedly deleting a triple won't err out"
@@ -362,11 +389,33 @@ pub async fn test_triple_deletion(datastore_url: Option<String>) {
         {
             let mut triple_storage = triple_storage.write().await;
             triple_storage
-                .insert(triple0.clone(), true)
+                .insert(triple0.clone(), STARTING_EPOCH, true)
                 .await
                 .expect("expected insert to succeed");
             triple_storage
-                .delete(triple0.id)
+                .delete(triple0.id, STARTING_EPOCH)
+                .await
+                .expect("expected delete to succeed");
+        }
+
+        // The same id in another epoch is stored under a key of its own.
+        {
+            let mut triple_storage = triple_storage.write().await;
+            triple_storage
+                .insert(triple0.clone(), STARTING_EPOCH + 1, false)
+                .await
+                .expect("expected insert to succeed");
+            let loaded = triple_storage
+                .load()
+                .await
+                .expect("expected to be able to load the triples");
+            assert_eq!(
+                loaded.len(),
+                2,
+                "the triple of the next epoch should be added"
+            );
+            triple_storage
+                .delete(triple0.id, STARTING_EPOCH + 1)
                 .await
                 .expect("expected delete to succeed");
         }
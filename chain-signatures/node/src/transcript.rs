@@ -0,0 +1,189 @@
+//! Transcripts of the messages exchanged by the nodes of the [`Harness`], recorded into a corpus
+//! with one transcript per release. Every version of the node replays the transcripts of the
+//! previous releases, so that a change to the wire format or to how the managers handle what
+//! older nodes send gets noticed before nodes of different versions share a network.
+//!
+//! The transcript of the current version is recorded into `corpus/<version>.json` with
+//! `cargo test -p mpc-node --features testing record_corpus -- --ignored`, once per release.
+//! Transcripts are never edited afterwards.
+
+use std::path::Path;
+
+use cait_sith::protocol::Participant;
+use crypto_shared::PublicKey;
+use k256::Scalar;
+use serde::{Deserialize, Serialize};
+
+use crate::harness::{Harness, NetworkOptions};
+use crate::indexer::ContractSignRequest;
+use crate::protocol::codec::{self, CodecError};
+use crate::protocol::MpcMessage;
+use crate::types::SecretKeyShare;
+
+/// Directory of the transcripts recorded by past releases.
+pub const CORPUS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus");
+
+/// A message sent by a node of the harness.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    /// Tick of the harness the message was sent on.
+    pub tick: u64,
+    pub from: Participant,
+    pub to: Participant,
+    /// The message as it went on the wire, hex encoded.
+    pub message: String,
+}
+
+impl Recorded {
+    pub fn new(
+        tick: u64,
+        from: Participant,
+        to: Participant,
+        message: &MpcMessage,
+        schema: u32,
+    ) -> Self {
+        let bytes = codec::encode(message, schema).expect("harness messages are encodable");
+        Self {
+            tick,
+            from,
+            to,
+            message: hex::encode(bytes),
+        }
+    }
+
+    pub fn decode(&self, schema: u32) -> Result<MpcMessage, CodecError> {
+        let bytes =
+            hex::decode(&self.message).map_err(|err| CodecError::Decode(err.to_string()))?;
+        codec::decode(&bytes, schema)
+    }
+}
+
+/// The messages recorded over a run of the harness along with the keys of its nodes, so that
+/// they can be handed to nodes holding the same shares.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Version of the node that recorded the transcript.
+    pub version: String,
+    /// Schema the messages are encoded with.
+    pub schema: u32,
+    pub threshold: usize,
+    pub public_key: PublicKey,
+    /// Share of the key held by each node, the `i`th node being participant `i`.
+    pub shares: Vec<SecretKeyShare>,
+    /// Seed of the network the transcript was recorded over.
+    pub seed: u64,
+    pub messages: Vec<Recorded>,
+}
+
+impl Transcript {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// Loads the transcripts of the corpus, ordered by file name. The corpus may not exist yet.
+pub fn load_corpus() -> anyhow::Result<Vec<Transcript>> {
+    let dir = Path::new(CORPUS_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+    paths.iter().map(|path| Transcript::load(path)).collect()
+}
+
+/// Runs the scenario the corpus is made of: 3 nodes with a threshold of 2 generate triples, a
+/// presignature out of them, and sign a request with it.
+pub async fn record_scenario(seed: u64) -> Transcript {
+    let mut harness = Harness::new(
+        3,
+        2,
+        NetworkOptions {
+            seed,
+            ..Default::default()
+        },
+    );
+    harness.record();
+    harness.generate_triples(0, 2);
+    harness.run_until_quiet(10_000).await;
+    assert_eq!(harness.generate_presignatures(0, 1).await, 1);
+    harness.run_until_quiet(10_000).await;
+
+    let request = ContractSignRequest {
+        payload: Scalar::from(42u64),
+        path: "corpus".to_string(),
+        key_version: 0,
+    };
+    let receipt_id = harness.sign(0, request, Scalar::ONE).await.unwrap();
+    harness.run_until_quiet(10_000).await;
+    for node in &harness.nodes {
+        assert!(node.signature_manager.is_completed(&receipt_id));
+    }
+    harness.transcript()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Delivery;
+
+    /// Replays the transcript on fresh nodes. The protocols themselves cannot complete, since
+    /// the nodes draw their own randomness, but every message must decode and none may be
+    /// refused by the managers.
+    async fn assert_replays(transcript: &Transcript) {
+        let mut harness = Harness::from_transcript(transcript);
+        let deliveries = harness.replay(transcript).await;
+        assert_eq!(deliveries.len(), transcript.messages.len());
+        for (recorded, delivery) in transcript.messages.iter().zip(deliveries) {
+            match delivery {
+                Ok(Delivery::Rejected(reason)) => panic!(
+                    "{} refused a message of version {} sent by {:?}: {reason}",
+                    env!("CARGO_PKG_VERSION"),
+                    transcript.version,
+                    recorded.from,
+                ),
+                Ok(_) => {}
+                Err(err) => panic!(
+                    "failed to decode a message of version {}: {err}",
+                    transcript.version
+                ),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_current_transcript() {
+        let transcript = record_scenario(7).await;
+        assert!(!transcript.messages.is_empty());
+        let json = serde_json::to_string(&transcript).unwrap();
+        let transcript: Transcript = serde_json::from_str(&json).unwrap();
+        assert_replays(&transcript).await;
+    }
+
+    #[tokio::test]
+    async fn test_replay_corpus() {
+        for transcript in load_corpus().unwrap() {
+            assert_replays(&transcript).await;
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "records the corpus of a release"]
+    async fn record_corpus() {
+        let transcript = record_scenario(0).await;
+        std::fs::create_dir_all(CORPUS_DIR).unwrap();
+        let path = Path::new(CORPUS_DIR).join(format!("{}.json", transcript.version));
+        assert!(!path.exists(), "{} is already recorded", path.display());
+        transcript.save(&path).unwrap();
+    }
+}
@@ -7,6 +7,7 @@ use cait_sith::{FullSignature, PresignOutput};
 use crypto_shared::PublicKey;
 use k256::{elliptic_curve::CurveArithmetic, Secp256k1};
 use tokio::sync::{RwLock, RwLockWriteGuard};
+use zeroize::Zeroizing;
 
 use crate::gcp::error::ConvertError;
 use crate::gcp::value::{FromValue, IntoValue, Value};
@@ -70,14 +71,14 @@ pub struct ReshareProtocol {
     new_participants: Vec<Participant>,
     me: Participant,
     threshold: usize,
-    private_share: Option<SecretKeyShare>,
+    private_share: Option<Zeroizing<SecretKeyShare>>,
     protocol: Arc<RwLock<Box<dyn Protocol<Output = SecretKeyShare> + Send + Sync>>>,
     root_pk: PublicKey,
 }
 
 impl ReshareProtocol {
     pub fn new(
-        private_share: Option<SecretKeyShare>,
+        private_share: Option<&SecretKeyShare>,
         me: Participant,
         contract_state: &ResharingContractState,
     ) -> Result<Self, InitializationError> {
@@ -96,10 +97,10 @@ impl ReshareProtocol {
                 &new_participants,
                 contract_state.threshold,
                 me,
-                private_share,
+                private_share.copied(),
                 contract_state.public_key,
             )?))),
-            private_share,
+            private_share: private_share.map(|share| Zeroizing::new(*share)),
             me,
             threshold: contract_state.threshold,
             old_participants,
@@ -121,7 +122,8 @@ impl ReshareProtocol {
             &self.new_participants,
             self.threshold,
             self.me,
-            self.private_share,
+            // cait-sith takes the share by value, this copy is moved into the protocol.
+            self.private_share.as_deref().copied(),
             self.root_pk,
         )?);
         Ok(())
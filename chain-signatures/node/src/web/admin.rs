@@ -0,0 +1,497 @@
+//! Admin server exposing the internal state of the protocol managers. This is only meant to be
+//! reachable by operators, so it runs on its own port separate from the node-to-node server, and
+//! listens on localhost unless configured otherwise.
+//! Besides the read-only views, including the audit trail of the published signatures, it exposes
+//! control endpoints that require the bearer token of an operator, and every invocation of them is
+//! logged with the `audit` target.
+
+use crate::audit;
+use crate::events::{self, NodeEvent};
+use crate::mesh::bandwidth::{self, Traffic};
+use crate::protocol::control::{Command, ControlError, Controller, Outcome};
+use crate::protocol::participation;
+use crate::protocol::presignature::{PoolUtilization, PresignatureId, PresignatureSummary};
+use crate::protocol::reputation::{MisbehaviorReport, Reputation};
+use crate::protocol::scheduler::GeneratorSummary;
+use crate::protocol::signature::SignatureSummary;
+use crate::protocol::triple::TripleSummary;
+use crate::protocol::NodeState;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, Router};
+use cait_sith::protocol::Participant;
+use near_account_id::AccountId;
+use near_crypto::SecretKey;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+
+type Result<T> = std::result::Result<Json<T>, (StatusCode, String)>;
+
+struct AdminState {
+    protocol_state: Arc<RwLock<NodeState>>,
+    reputation: Arc<RwLock<Reputation>>,
+    sign_sk: SecretKey,
+    controller: Controller,
+    operators: Operators,
+}
+
+/// The operators allowed to use the control endpoints, identified by their bearer token.
+#[derive(Clone, Debug, Default)]
+pub struct Operators {
+    tokens: Vec<(String, String)>,
+}
+
+impl Operators {
+    /// Parses comma-separated `operator:token` pairs. No operator is allowed if not set.
+    pub fn parse(tokens: Option<&str>) -> anyhow::Result<Self> {
+        let mut operators = Self::default();
+        for entry in tokens.into_iter().flat_map(|tokens| tokens.split(',')) {
+            let Some((operator, token)) = entry.trim().split_once(':') else {
+                anyhow::bail!("admin token entry is not of the form `operator:token`");
+            };
+            if operator.is_empty() || token.is_empty() {
+                anyhow::bail!("admin token entry has an empty operator or token");
+            }
+            operators
+                .tokens
+                .push((operator.to_string(), token.to_string()));
+        }
+        Ok(operators)
+    }
+
+    /// Returns the operator whose token is presented in the `Authorization` header.
+    fn authenticate(&self, headers: &HeaderMap) -> std::result::Result<&str, (StatusCode, String)> {
+        if self.tokens.is_empty() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "control endpoints are disabled".to_string(),
+            ));
+        }
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        self.tokens
+            .iter()
+            .find(|(_, token)| constant_time_eq(token.as_bytes(), presented.as_bytes()))
+            .map(|(operator, _)| operator.as_str())
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "invalid admin token".to_string()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn run(
+    addr: SocketAddr,
+    protocol_state: Arc<RwLock<NodeState>>,
+    reputation: Arc<RwLock<Reputation>>,
+    sign_sk: SecretKey,
+    controller: Controller,
+    operators: Operators,
+) -> anyhow::Result<()> {
+    let admin_state = AdminState {
+        protocol_state,
+        reputation,
+        sign_sk,
+        controller,
+        operators,
+    };
+
+    let app = Router::new()
+        .route("/state/triples", get(triples))
+        .route("/state/presignatures", get(presignatures))
+        .route("/state/generators", get(generators))
+        .route("/state/signatures", get(signatures))
+        .route("/state/peers", get(peers))
+        .route("/state/participation", get(participation))
+        .route("/reputation/reports", get(reputation_reports))
+        .route("/audit/signatures", get(audit_signatures))
+        .route("/events", get(events_stream))
+        .route("/control/generate-triples", post(generate_triples))
+        .route(
+            "/control/generate-presignatures",
+            post(generate_presignatures),
+        )
+        .route("/control/generators/:id", delete(drop_generator))
+        .route("/control/pause-signing", post(pause_signing))
+        .route("/control/resume-signing", post(resume_signing))
+        .layer(Extension(Arc::new(admin_state)));
+
+    tracing::info!(?addr, "starting admin http server");
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+fn not_running() -> (StatusCode, String) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "node is not running".to_string(),
+    )
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TriplesView {
+    pub count: usize,
+    pub mine_count: usize,
+    pub triples: Vec<TripleSummary>,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn triples(Extension(state): Extension<Arc<AdminState>>) -> Result<TriplesView> {
+    let protocol_state = state.protocol_state.read().await;
+    let NodeState::Running(running) = &*protocol_state else {
+        return Err(not_running());
+    };
+
+    let snapshot = running.triple_manager.read().await.snapshot();
+    Ok(Json(TriplesView {
+        count: snapshot.triples.len(),
+        mine_count: snapshot.triples.iter().filter(|triple| triple.mine).count(),
+        triples: snapshot.triples,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresignaturesView {
+    pub count: usize,
+    pub mine_count: usize,
+    pub presignatures: Vec<PresignatureSummary>,
+    pub reserved: Vec<PresignatureId>,
+    pub pool: PoolUtilization,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn presignatures(Extension(state): Extension<Arc<AdminState>>) -> Result<PresignaturesView> {
+    let protocol_state = state.protocol_state.read().await;
+    let NodeState::Running(running) = &*protocol_state else {
+        return Err(not_running());
+    };
+
+    let snapshot = running.presignature_manager.read().await.snapshot();
+    Ok(Json(PresignaturesView {
+        count: snapshot.presignatures.len(),
+        mine_count: snapshot
+            .presignatures
+            .iter()
+            .filter(|presignature| presignature.mine)
+            .count(),
+        presignatures: snapshot.presignatures,
+        reserved: snapshot.reserved,
+        pool: snapshot.pool,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeneratorsView {
+    pub triples: Vec<GeneratorSummary>,
+    pub triples_queued: usize,
+    pub presignatures: Vec<GeneratorSummary>,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn generators(Extension(state): Extension<Arc<AdminState>>) -> Result<GeneratorsView> {
+    let protocol_state = state.protocol_state.read().await;
+    let NodeState::Running(running) = &*protocol_state else {
+        return Err(not_running());
+    };
+
+    let triples = running.triple_manager.read().await.snapshot();
+    let presignatures = running.presignature_manager.read().await.snapshot();
+    Ok(Json(GeneratorsView {
+        triples: triples.generators,
+        triples_queued: triples.queued,
+        presignatures: presignatures.generators,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignaturesView {
+    pub sign_queue_count: usize,
+    pub generators: Vec<SignatureSummary>,
+    pub failed_count: usize,
+    pub completed_count: usize,
+    pub to_publish_count: usize,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn signatures(Extension(state): Extension<Arc<AdminState>>) -> Result<SignaturesView> {
+    let protocol_state = state.protocol_state.read().await;
+    let NodeState::Running(running) = &*protocol_state else {
+        return Err(not_running());
+    };
+
+    let sign_queue_count = running.sign_queue.read().await.len();
+    let snapshot = running.signature_manager.read().await.snapshot();
+    Ok(Json(SignaturesView {
+        sign_queue_count,
+        generators: snapshot.generators,
+        failed_count: snapshot.failed,
+        completed_count: snapshot.completed,
+        to_publish_count: snapshot.to_publish,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerEntry {
+    pub participant: Participant,
+    pub account_id: AccountId,
+    pub url: String,
+    /// Number of our messages waiting to be delivered to the peer.
+    pub pending_messages: usize,
+    /// Milliseconds since the peer last accepted our messages. `None` if it never did.
+    pub last_ack_ms: Option<u128>,
+    /// Number of misbehaviors recorded for the peer.
+    pub misbehaviors: u64,
+    /// Traffic exchanged with the peer since the node started, per type of message.
+    #[serde(default)]
+    pub traffic: BTreeMap<String, Traffic>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeersView {
+    pub epoch: u64,
+    pub threshold: usize,
+    pub peers: Vec<PeerEntry>,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn peers(Extension(state): Extension<Arc<AdminState>>) -> Result<PeersView> {
+    let protocol_state = state.protocol_state.read().await;
+    let NodeState::Running(running) = &*protocol_state else {
+        return Err(not_running());
+    };
+
+    let messages = running.messages.read().await;
+    let reputation = state.reputation.read().await;
+    let peers = running
+        .participants
+        .iter()
+        .map(|(participant, info)| PeerEntry {
+            participant: *participant,
+            account_id: info.account_id.clone(),
+            url: info.url.clone(),
+            pending_messages: messages.pending(participant),
+            last_ack_ms: messages
+                .last_ack(participant)
+                .map(|ack| ack.elapsed().as_millis()),
+            misbehaviors: reputation.score(participant),
+            traffic: bandwidth::traffic(participant),
+        })
+        .collect();
+
+    Ok(Json(PeersView {
+        epoch: running.epoch,
+        threshold: running.threshold,
+        peers,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ParticipationParams {
+    epoch: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParticipationView {
+    /// Number of protocols every peer took part in per epoch, then per protocol, by how they
+    /// ended. Only the most recent epochs are kept.
+    pub epochs: participation::Matrix,
+}
+
+/// Tells apart the peer that keeps timing out protocols from the ones that complete them.
+#[tracing::instrument(level = "debug", skip_all)]
+async fn participation(Query(params): Query<ParticipationParams>) -> Result<ParticipationView> {
+    Ok(Json(ParticipationView {
+        epochs: participation::matrix(params.epoch),
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReputationView {
+    pub reports: Vec<MisbehaviorReport>,
+    /// Offenders that are no longer part of the participant set and cannot be reported.
+    pub unknown_offenders: Vec<Participant>,
+}
+
+/// Produces signed misbehavior reports for every participant that is considered an offender.
+#[tracing::instrument(level = "debug", skip_all)]
+async fn reputation_reports(
+    Extension(state): Extension<Arc<AdminState>>,
+) -> Result<ReputationView> {
+    let protocol_state = state.protocol_state.read().await;
+    let NodeState::Running(running) = &*protocol_state else {
+        return Err(not_running());
+    };
+
+    let reputation = state.reputation.read().await;
+    let mut reports = Vec::new();
+    let mut unknown_offenders = Vec::new();
+    for offender in reputation.offenders() {
+        let Some(info) = running.participants.get(&offender) else {
+            unknown_offenders.push(offender);
+            continue;
+        };
+        if let Some(report) =
+            reputation.report(offender, &info.account_id, running.epoch, &state.sign_sk)
+        {
+            reports.push(report);
+        }
+    }
+
+    Ok(Json(ReputationView {
+        reports,
+        unknown_offenders,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditView {
+    pub records: Vec<audit::Record>,
+}
+
+/// Traces the signatures published by this node back to the presignature and the triples they
+/// were generated from.
+#[tracing::instrument(level = "debug", skip_all)]
+async fn audit_signatures(Query(filter): Query<audit::Filter>) -> Result<AuditView> {
+    let Some(records) = tokio::task::spawn_blocking(move || audit::query(&filter))
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+    else {
+        return Err((StatusCode::NOT_FOUND, "audit trail is disabled".to_string()));
+    };
+    let records = records.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(Json(AuditView { records }))
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateParams {
+    n: usize,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn generate_triples(
+    Extension(state): Extension<Arc<AdminState>>,
+    headers: HeaderMap,
+    Query(params): Query<GenerateParams>,
+) -> Result<Outcome> {
+    control(&state, &headers, Command::GenerateTriples { n: params.n }).await
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn generate_presignatures(
+    Extension(state): Extension<Arc<AdminState>>,
+    headers: HeaderMap,
+    Query(params): Query<GenerateParams>,
+) -> Result<Outcome> {
+    control(
+        &state,
+        &headers,
+        Command::GeneratePresignatures { n: params.n },
+    )
+    .await
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn drop_generator(
+    Extension(state): Extension<Arc<AdminState>>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Outcome> {
+    control(&state, &headers, Command::DropGenerator { id }).await
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn pause_signing(
+    Extension(state): Extension<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Outcome> {
+    control(&state, &headers, Command::PauseSigning).await
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn resume_signing(
+    Extension(state): Extension<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Outcome> {
+    control(&state, &headers, Command::ResumeSigning).await
+}
+
+/// Authenticates the operator, then has the protocol loop apply the command. Both the attempt
+/// and its outcome end up in the audit log.
+async fn control(state: &AdminState, headers: &HeaderMap, command: Command) -> Result<Outcome> {
+    let operator = match state.operators.authenticate(headers) {
+        Ok(operator) => operator,
+        Err(err) => {
+            tracing::warn!(target: "audit", ?command, "rejected unauthenticated control command");
+            return Err(err);
+        }
+    };
+    tracing::info!(target: "audit", operator, ?command, "control command invoked");
+    match state.controller.send(command.clone()).await {
+        Ok(outcome) => {
+            tracing::info!(target: "audit", operator, ?command, ?outcome, "control command applied");
+            Ok(Json(outcome))
+        }
+        Err(err) => {
+            tracing::warn!(target: "audit", operator, ?command, %err, "control command failed");
+            let status = match err {
+                ControlError::TooMany => StatusCode::BAD_REQUEST,
+                ControlError::NotFound(_) => StatusCode::NOT_FOUND,
+                ControlError::Generation(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                ControlError::NotRunning
+                | ControlError::NotEnoughParticipants(..)
+                | ControlError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            Err((status, err.to_string()))
+        }
+    }
+}
+
+/// Streams the node events as JSON text messages until the client disconnects.
+async fn events_stream(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(stream_events)
+}
+
+async fn stream_events(mut socket: WebSocket) {
+    let mut subscription = events::subscribe();
+    loop {
+        tokio::select! {
+            entry = subscription.recv() => {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "events subscriber lagged behind");
+                        events::Entry::new(NodeEvent::Lagged { skipped })
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let text = match serde_json::to_string(&entry) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        tracing::warn!(?err, "failed to serialize node event");
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
@@ -2,6 +2,7 @@ use axum::extract::rejection::JsonRejection;
 use reqwest::StatusCode;
 use tokio::sync::mpsc::error::SendError;
 
+use crate::protocol::signature::ReceiptId;
 use crate::protocol::{ConsensusError, CryptographicError, MpcMessage};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -20,6 +21,14 @@ pub enum Error {
     Message(#[from] SendError<MpcMessage>),
     #[error(transparent)]
     Rpc(#[from] near_fetch::Error),
+    #[error("malformed request body: {0}")]
+    MalformedBody(String),
+    #[error("unsupported message schema: {0}")]
+    UnsupportedSchema(String),
+    #[error("unknown sign request {0}")]
+    UnknownSignRequest(ReceiptId),
+    #[error("storage error: {0}")]
+    Storage(String),
 }
 
 impl Error {
@@ -30,6 +39,10 @@ impl Error {
             Error::Cryptography(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Message(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Rpc(_) => StatusCode::BAD_REQUEST,
+            Error::MalformedBody(_) => StatusCode::BAD_REQUEST,
+            Error::UnsupportedSchema(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::UnknownSignRequest(_) => StatusCode::NOT_FOUND,
+            Error::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
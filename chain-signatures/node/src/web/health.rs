@@ -0,0 +1,315 @@
+//! Probes for orchestrators. `/healthz` answers as long as the process serves requests, while
+//! `/readyz` only answers with success once the node can take part in signatures: it is running
+//! the protocol with enough reachable participants, holds a key share registered for the current
+//! epoch, and has stockpiled enough triples and presignatures. Kubernetes deployments use the
+//! latter as readiness probe so that no signature traffic is routed to a node that cannot serve
+//! it yet.
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use super::AxumState;
+use crate::protocol::{registry, NodeState};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, clap::Parser)]
+#[group(id = "readiness_options")]
+pub struct Options {
+    /// Number of triples the node must hold to be ready.
+    #[arg(long, env("MPC_READY_MIN_TRIPLES"), default_value("0"))]
+    pub ready_min_triples: usize,
+    /// Number of presignatures the node must hold to be ready.
+    #[arg(long, env("MPC_READY_MIN_PRESIGNATURES"), default_value("0"))]
+    pub ready_min_presignatures: usize,
+    /// Number of participants that must have responded to the latest ping for the node to be
+    /// ready. The threshold of the epoch if not set.
+    #[arg(long, env("MPC_READY_MIN_ACTIVE_PARTICIPANTS"))]
+    pub ready_min_active_participants: Option<usize>,
+}
+
+impl Options {
+    pub fn into_str_args(self) -> Vec<String> {
+        let mut opts = vec![
+            "--ready-min-triples".to_string(),
+            self.ready_min_triples.to_string(),
+            "--ready-min-presignatures".to_string(),
+            self.ready_min_presignatures.to_string(),
+        ];
+        if let Some(min) = self.ready_min_active_participants {
+            opts.extend([
+                "--ready-min-active-participants".to_string(),
+                min.to_string(),
+            ]);
+        }
+        opts
+    }
+}
+
+/// Why the node is not ready.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Unready {
+    /// The node is not running the protocol, e.g. still joining or resharing.
+    NotRunning {
+        state: String,
+    },
+    /// Too few participants responded to the latest ping.
+    NotMeshed {
+        active: usize,
+        required: usize,
+    },
+    /// The key share of the node is not registered for any participant of the epoch.
+    NoKeyShare {
+        epoch: u64,
+    },
+    NotEnoughTriples {
+        count: usize,
+        required: usize,
+    },
+    NotEnoughPresignatures {
+        count: usize,
+        required: usize,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadinessView {
+    pub ready: bool,
+    pub epoch: Option<u64>,
+    /// Every criterion the node does not meet, empty once ready.
+    pub unready: Vec<Unready>,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub(super) async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Succeeds only once the node meets all of the readiness criteria, listing the ones it does not
+/// meet otherwise.
+#[tracing::instrument(level = "debug", skip_all)]
+pub(super) async fn readyz(
+    Extension(state): Extension<Arc<AxumState>>,
+) -> (StatusCode, Json<ReadinessView>) {
+    let protocol_state = state.protocol_state.read().await;
+    probe(&protocol_state, &state.readiness).await
+}
+
+async fn probe(protocol_state: &NodeState, options: &Options) -> (StatusCode, Json<ReadinessView>) {
+    let view = readiness(protocol_state, options).await;
+    let status = if view.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(view))
+}
+
+async fn readiness(protocol_state: &NodeState, options: &Options) -> ReadinessView {
+    let NodeState::Running(running) = protocol_state else {
+        return ReadinessView {
+            ready: false,
+            epoch: protocol_state.epoch(),
+            unready: vec![Unready::NotRunning {
+                state: protocol_state.to_string(),
+            }],
+        };
+    };
+
+    let mut unready = Vec::new();
+    {
+        let registry = registry::read(&running.registry);
+        let active = registry.active().len();
+        let required = options
+            .ready_min_active_participants
+            .unwrap_or(running.threshold);
+        if active < required {
+            unready.push(Unready::NotMeshed { active, required });
+        }
+
+        let commitment = registry::share_commitment(&running.private_share);
+        let registered = running.participants.keys().any(|participant| {
+            registry
+                .get(participant)
+                .is_some_and(|registered| registered.share_commitment == Some(commitment))
+        });
+        if !registered {
+            unready.push(Unready::NoKeyShare {
+                epoch: running.epoch,
+            });
+        }
+    }
+
+    let count = running.triple_manager.read().await.len();
+    if count < options.ready_min_triples {
+        unready.push(Unready::NotEnoughTriples {
+            count,
+            required: options.ready_min_triples,
+        });
+    }
+    let count = running.presignature_manager.read().await.len();
+    if count < options.ready_min_presignatures {
+        unready.push(Unready::NotEnoughPresignatures {
+            count,
+            required: options.ready_min_presignatures,
+        });
+    }
+
+    ReadinessView {
+        ready: unready.is_empty(),
+        epoch: Some(running.epoch),
+        unready,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MessageQueue;
+    use crate::protocol::contract::primitives::Participants;
+    use crate::protocol::monitor::StuckMonitor;
+    use crate::protocol::presignature::PresignatureManager;
+    use crate::protocol::refresh::Refresher;
+    use crate::protocol::registry::ParticipantRegistry;
+    use crate::protocol::signature::SignatureManager;
+    use crate::protocol::state::RunningState;
+    use crate::protocol::triple::TripleManager;
+    use crate::protocol::{ParticipantInfo, SignQueue};
+    use crate::storage::{presignature_storage, triple_storage};
+    use cait_sith::protocol::Participant;
+    use near_account_id::AccountId;
+    use tokio::sync::RwLock;
+    use zeroize::Zeroizing;
+
+    fn participants(ids: &[u32]) -> Participants {
+        let mut participants = Participants::default();
+        for id in ids {
+            participants.insert(&Participant::from(*id), ParticipantInfo::new(*id));
+        }
+        participants
+    }
+
+    /// A node running with participants `0` and `1` and the given threshold, of which the
+    /// participants in `active` responded to the latest ping.
+    async fn running(threshold: usize, active: &[u32]) -> NodeState {
+        let account_id: AccountId = "p-0.testnet".parse().unwrap();
+        let me = Participant::from(0u32);
+        let public_key = k256::AffinePoint::GENERATOR;
+        let private_share = Zeroizing::new(k256::Scalar::ONE);
+        let participants = participants(&[0, 1]);
+
+        let registry = ParticipantRegistry::shared();
+        {
+            let mut registry = registry::write(&registry);
+            registry.set_participants(&participants);
+            registry.set_active(&self::participants(active));
+            registry.set_share_commitment(me, registry::share_commitment(&private_share));
+        }
+
+        let triple_manager = Arc::new(RwLock::new(TripleManager::new(
+            me,
+            threshold,
+            0,
+            Vec::new(),
+            Arc::new(RwLock::new(triple_storage::init(None, &account_id))),
+            &account_id,
+        )));
+        NodeState::Running(RunningState {
+            epoch: 0,
+            threshold,
+            private_share,
+            public_key,
+            sign_queue: Arc::new(RwLock::new(SignQueue::new())),
+            stuck_monitor: Arc::new(RwLock::new(StuckMonitor::new(&triple_manager).await)),
+            triple_manager,
+            presignature_manager: Arc::new(RwLock::new(PresignatureManager::new(
+                me,
+                threshold,
+                0,
+                &public_key,
+                Vec::new(),
+                Arc::new(RwLock::new(presignature_storage::init(None, &account_id))),
+                &account_id,
+            ))),
+            signature_manager: Arc::new(RwLock::new(SignatureManager::new(
+                me,
+                public_key,
+                0,
+                &account_id,
+            ))),
+            refresher: Arc::new(RwLock::new(Refresher::new(
+                me,
+                0,
+                &participants,
+                threshold,
+                public_key,
+                &account_id,
+            ))),
+            participants,
+            registry,
+            messages: Arc::new(RwLock::new(MessageQueue::default())),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_readyz_not_running() {
+        let (status, Json(view)) = probe(&NodeState::Starting, &Options::default()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!view.ready);
+        assert_eq!(
+            view.unready,
+            vec![Unready::NotRunning {
+                state: "Starting".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readyz_below_threshold() {
+        let state = running(2, &[0]).await;
+        let (status, Json(view)) = probe(&state, &Options::default()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            view.unready,
+            vec![Unready::NotMeshed {
+                active: 1,
+                required: 2,
+            }]
+        );
+
+        // An explicit minimum takes precedence over the threshold.
+        let options = Options {
+            ready_min_active_participants: Some(1),
+            ..Default::default()
+        };
+        let (status, _) = probe(&state, &options).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_running() {
+        let state = running(2, &[0, 1]).await;
+        let (status, Json(view)) = probe(&state, &Options::default()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(view.ready);
+        assert_eq!(view.epoch, Some(0));
+        assert!(view.unready.is_empty());
+
+        // Stockpile requirements are not met by an empty node.
+        let options = Options {
+            ready_min_triples: 1,
+            ..Default::default()
+        };
+        let (status, Json(view)) = probe(&state, &options).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            view.unready,
+            vec![Unready::NotEnoughTriples {
+                count: 0,
+                required: 1,
+            }]
+        );
+    }
+}
@@ -1,17 +1,24 @@
+pub mod admin;
 mod error;
+pub mod health;
 
 use self::error::Error;
+use crate::attestation;
 use crate::indexer::Indexer;
-use crate::protocol::message::SignedMessage;
-use crate::protocol::{MpcMessage, NodeState};
+use crate::mesh::{bandwidth, transport};
+use crate::protocol::signature::ReceiptId;
+use crate::protocol::{codec, registry, MpcMessage, NodeState};
+use crate::storage::sign_request_storage::{LockSignRequestNodeStorageBox, SignRequestState};
 use crate::web::error::Result;
 use anyhow::Context;
-use axum::http::StatusCode;
+use axum::body::Bytes;
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
-use axum_extra::extract::WithRejection;
 use cait_sith::protocol::Participant;
-use mpc_keys::hpke::{self, Ciphered};
+use mpc_keys::hpke;
 use near_primitives::types::BlockHeight;
 use prometheus::{Encoder, TextEncoder};
 use serde::{Deserialize, Serialize};
@@ -23,6 +30,8 @@ struct AxumState {
     protocol_state: Arc<RwLock<NodeState>>,
     cipher_sk: hpke::SecretKey,
     indexer: Indexer,
+    sign_request_storage: LockSignRequestNodeStorageBox,
+    readiness: health::Options,
 }
 
 pub async fn run(
@@ -31,6 +40,8 @@ pub async fn run(
     cipher_sk: hpke::SecretKey,
     protocol_state: Arc<RwLock<NodeState>>,
     indexer: Indexer,
+    sign_request_storage: LockSignRequestNodeStorageBox,
+    readiness: health::Options,
 ) -> anyhow::Result<()> {
     tracing::info!("running a node");
     let axum_state = AxumState {
@@ -38,6 +49,8 @@ pub async fn run(
         protocol_state,
         cipher_sk,
         indexer,
+        sign_request_storage,
+        readiness,
     };
 
     let app = Router::new()
@@ -49,8 +62,12 @@ pub async fn run(
                 StatusCode::OK
             }),
         )
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
         .route("/msg", post(msg))
         .route("/state", get(state))
+        .route("/handshake", get(handshake))
+        .route("/sign_requests/:receipt_id", get(sign_request))
         .route("/metrics", get(metrics))
         .layer(Extension(Arc::new(axum_state)));
 
@@ -73,23 +90,42 @@ pub struct MsgRequest {
 #[tracing::instrument(level = "debug", skip_all)]
 async fn msg(
     Extension(state): Extension<Arc<AxumState>>,
-    WithRejection(Json(encrypted), _): WithRejection<Json<Vec<Ciphered>>, Error>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Result<()> {
+    // Senders that predate the handshake do not set the schema header.
+    let schema = match headers.get(transport::SCHEMA_HEADER) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|schema| schema.parse::<u32>().ok())
+            .filter(|schema| transport::MESSAGE_SCHEMAS.contains(schema))
+            .ok_or_else(|| {
+                Error::UnsupportedSchema(format!(
+                    "{value:?}, supported: {:?}",
+                    transport::MESSAGE_SCHEMAS
+                ))
+            })?,
+        None => codec::SCHEMA_JSON,
+    };
+    let compressed = headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding == transport::ZSTD_ENCODING);
+    let encrypted = transport::decode_batch(&body, compressed).map_err(Error::MalformedBody)?;
     for encrypted in encrypted.into_iter() {
-        let message = match SignedMessage::decrypt(
-            &state.cipher_sk,
-            &state.protocol_state,
-            encrypted,
-        )
-        .await
-        {
-            Ok(msg) => msg,
-            Err(err) => {
-                tracing::error!(?err, "failed to decrypt or verify an encrypted message");
-                return Err(err.into());
-            }
-        };
+        let bytes = encrypted.text.len();
+        let message =
+            match transport::receive(&state.cipher_sk, &state.protocol_state, encrypted, schema)
+                .await
+            {
+                Ok(msg) => msg,
+                Err(err) => {
+                    tracing::error!(?err, "failed to decrypt or verify an encrypted message");
+                    return Err(err.into());
+                }
+            };
 
+        bandwidth::record_received(message.sender(), message.typename(), bytes);
         if let Err(err) = state.sender.send(message).await {
             tracing::error!(?err, "failed to forward an encrypted protocol message");
             return Err(err.into());
@@ -127,8 +163,63 @@ pub enum StateView {
     NotRunning,
 }
 
+/// Serves the state of the node. Participants ping this endpoint, so the response also advertises
+/// the content encodings accepted for messages sent to this node.
+#[tracing::instrument(level = "debug", skip_all)]
+async fn state(Extension(state): Extension<Arc<AxumState>>) -> Result<impl IntoResponse> {
+    let view = state_view(&state).await?;
+    Ok(([(header::ACCEPT_ENCODING, transport::ZSTD_ENCODING)], view))
+}
+
+/// Serves the capabilities of this node, from which participants negotiate the format of the
+/// messages they send to it.
+#[tracing::instrument(level = "debug", skip_all)]
+async fn handshake(Extension(state): Extension<Arc<AxumState>>) -> Json<transport::Handshake> {
+    let mut handshake = transport::Handshake::ours();
+    let protocol_state = state.protocol_state.read().await;
+    let share = match &*protocol_state {
+        NodeState::Running(running) => Some(&*running.private_share),
+        _ => None,
+    };
+    handshake.share_commitment = share.map(registry::share_commitment);
+    if attestation::enabled() {
+        handshake.attestation = attestation::attest(share);
+    }
+    Json(handshake)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignRequestView {
+    pub receipt_id: ReceiptId,
+    #[serde(flatten)]
+    pub state: SignRequestState,
+}
+
+/// Serves where a sign request is at on this node, such that requesters can learn why their
+/// request was rejected, see [`crate::protocol::validation`].
 #[tracing::instrument(level = "debug", skip_all)]
-async fn state(Extension(state): Extension<Arc<AxumState>>) -> Result<Json<StateView>> {
+async fn sign_request(
+    Extension(state): Extension<Arc<AxumState>>,
+    Path(receipt_id): Path<String>,
+) -> Result<Json<SignRequestView>> {
+    let receipt_id: ReceiptId = receipt_id
+        .parse()
+        .map_err(|_| Error::MalformedBody(format!("invalid receipt id: {receipt_id}")))?;
+    let record = state
+        .sign_request_storage
+        .read()
+        .await
+        .get(&receipt_id)
+        .await
+        .map_err(|err| Error::Storage(err.to_string()))?
+        .ok_or(Error::UnknownSignRequest(receipt_id))?;
+    Ok(Json(SignRequestView {
+        receipt_id,
+        state: record.state,
+    }))
+}
+
+async fn state_view(state: &AxumState) -> Result<Json<StateView>> {
     tracing::debug!("fetching state");
     let latest_block_height = state.indexer.latest_block_height().await;
     let is_stable = state.indexer.is_on_track().await;
@@ -238,6 +238,10 @@ pub async fn setup(docker_client: &DockerClient) -> anyhow::Result<Context<'_>>
         sk_share_secret_id: None,
         gcp_datastore_url: Some(datastore.local_address.clone()),
         sk_share_local_path: Some(sk_share_local_path),
+        sk_share_aws_secret_id: None,
+        sk_share_vault_address: None,
+        sk_share_vault_path: None,
+        sk_share_vault_token: None,
     };
     Ok(Context {
         docker_client,
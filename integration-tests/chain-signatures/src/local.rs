@@ -87,16 +87,37 @@ impl Node {
             account_id: config.account.id().clone(),
             account_sk: config.account.secret_key().to_string().parse()?,
             web_port,
+            admin_host: std::net::Ipv4Addr::LOCALHOST.into(),
+            admin_port: None,
+            admin_tokens: None,
             cipher_pk: hex::encode(config.cipher_pk.to_bytes()),
             cipher_sk: hex::encode(config.cipher_sk.to_bytes()),
             sign_sk: Some(config.sign_sk.clone()),
+            message_dedup_window_ms: 300_000,
+            peer_send_rate_limit: None,
+            peer_outbox_limit: None,
+            presignature_max_age_secs: 86_400,
             indexer_options,
             my_address: None,
             storage_options: ctx.storage_options.clone(),
+            scheduler_options: Default::default(),
+            attestation_options: Default::default(),
+            telemetry_options: Default::default(),
+            redact_options: Default::default(),
+            slo_options: Default::default(),
+            validation_options: Default::default(),
+            readiness_options: Default::default(),
+            transport_options: Default::default(),
             override_config: Some(OverrideConfig::new(serde_json::to_value(
                 config.cfg.protocol.clone(),
             )?)),
+            override_config_file: None,
+            config_file: None,
             client_header_referer: None,
+            journal_path: None,
+            audit_path: None,
+            snapshot_path: None,
+            shutdown_grace_period_secs: 30,
         };
 
         let mpc_node_id = format!("multichain/{}", config.account.id());